@@ -6,7 +6,7 @@ use std::fs::File;
 use std::str::FromStr;
 use std::time;
 
-use distributary::{DataType, Mutator, Token};
+use distributary::prelude::{DataType, Mutator, Token};
 use super::Backend;
 
 const NANOS_PER_SEC: u64 = 1_000_000_000;
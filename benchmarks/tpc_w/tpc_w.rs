@@ -13,7 +13,7 @@ use std::{thread, time};
 use std::collections::HashMap;
 use slog::DrainExt;
 
-use distributary::{Blender, DataType, Recipe};
+use distributary::prelude::{Blender, DataType, Recipe};
 
 pub struct Backend {
     r: Recipe,
@@ -1,9 +1,6 @@
 use distributary::srv;
 use distributary::{Blender, Base, Aggregation, JoinBuilder, DataType};
-use tarpc;
-use tarpc::util::FirstSocketAddr;
-use tarpc::future::client::{ClientExt, Options};
-use tokio_core::reactor;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 use targets::Backend;
 use targets::Putter;
@@ -13,7 +10,7 @@ pub struct SoupTarget {
     vote: usize,
     article: usize,
     end: usize,
-    addr: ::std::net::SocketAddr,
+    addr: SocketAddr,
     _srv: srv::ServerHandle,
 }
 
@@ -48,52 +45,35 @@ pub fn make(addr: &str, _: usize) -> SoupTarget {
     };
 
     // start processing
-    let srv = srv::run(g, addr.first_socket_addr(), 8);
+    let addr = addr.to_socket_addrs().unwrap().next().unwrap();
+    let srv = srv::run(g, addr, 8);
 
     SoupTarget {
         vote: vote.into(),
         article: article.into(),
         end: end.into(),
-        addr: addr.first_socket_addr(),
+        addr: addr,
         _srv: srv,
     }
 }
 
-pub struct C(srv::ext::FutureClient, reactor::Core);
-use std::ops::{Deref, DerefMut};
-impl Deref for C {
-    type Target = srv::ext::FutureClient;
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-impl DerefMut for C {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
+pub struct C(srv::Client);
 impl C {
     pub fn insert(&mut self, view: usize, data: Vec<DataType>) {
-        self.1.run(self.0.insert(view, data)).unwrap();
+        self.0.insert(view, data).unwrap();
     }
-    pub fn query(&mut self,
-                 view: usize,
-                 key: DataType)
-                 -> Result<Vec<Vec<DataType>>, tarpc::Error<()>> {
-        self.1.run(self.0.query(view, key))
+    pub fn query(&mut self, view: usize, key: DataType) -> Result<Vec<Vec<DataType>>, srv::Error> {
+        self.0.query(view, key)
     }
 }
 unsafe impl Send for C {}
 
 impl SoupTarget {
     fn mkc(&self) -> C {
-        use self::srv::ext::FutureClient;
-        let mut core = reactor::Core::new().unwrap();
         for _ in 0..3 {
-            let c = FutureClient::connect(self.addr, Options::default().handle(core.handle()));
-            match core.run(c) {
+            match srv::Client::connect(self.addr) {
                 Ok(client) => {
-                    return C(client, core);
+                    return C(client);
                 }
                 Err(_) => {
                     use std::thread;
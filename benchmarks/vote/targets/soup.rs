@@ -25,11 +25,20 @@ pub struct SoupTarget {
     _g: Blender,
 }
 
-pub fn make(_: &str, _: usize) -> SoupTarget {
+pub fn make(dbn: &str, _: usize) -> SoupTarget {
     // set up graph
     let mut g = Blender::new();
     g.log_with(slog::Logger::root(slog_term::streamer().full().build().fuse(), None));
 
+    // soup://pin=0,1,2 pins domain threads to the listed cores
+    if dbn.starts_with("pin=") {
+        let cores = dbn[4..]
+            .split(',')
+            .map(|c| c.parse().expect("cores to pin to must be numbers"))
+            .collect();
+        g.pin_domains_to(cores);
+    }
+
     let article;
     let vote;
     let vc;
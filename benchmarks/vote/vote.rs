@@ -1,5 +1,4 @@
-#![cfg_attr(feature="b_netsoup", feature(conservative_impl_trait, plugin))]
-#![cfg_attr(feature="b_netsoup", plugin(tarpc_plugins))]
+#![cfg_attr(feature="b_netsoup", feature(conservative_impl_trait))]
 
 #[macro_use]
 extern crate clap;
@@ -9,9 +8,9 @@ extern crate slog_term;
 
 extern crate rand;
 
-#[cfg(any(feature="b_mssql", feature="b_netsoup"))]
+#[cfg(feature="b_mssql")]
 extern crate futures;
-#[cfg(any(feature="b_mssql", feature="b_netsoup"))]
+#[cfg(feature="b_mssql")]
 extern crate tokio_core;
 
 #[cfg(feature="b_mssql")]
@@ -37,9 +36,6 @@ extern crate r2d2_postgres;
 
 extern crate distributary;
 
-#[cfg(feature="b_netsoup")]
-extern crate tarpc;
-
 #[cfg(any(feature="b_memcached", feature="b_hybrid"))]
 extern crate memcached;
 
@@ -166,7 +162,7 @@ fn main() {
     println!("Attempting to connect to database using {}", dbn);
     let mut dbn = dbn.splitn(2, "://");
     let (put_stats, get_stats) = match dbn.next().unwrap() {
-        // soup://
+        // soup:// or soup://pin=0,2,4,6 (to pin domains to cores)
         "soup" => exercise::launch(targets::soup::make(dbn.next().unwrap(), ngetters), config),
         // mssql://server=tcp:127.0.0.1,1433;user=user;pwd=password/bench_mssql
         #[cfg(feature="b_mssql")]
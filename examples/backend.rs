@@ -1,8 +1,5 @@
 use std::collections::HashMap;
-use distributary::{Blender, DataType, Mutator, Recipe};
-
-type Datas = Vec<Vec<DataType>>;
-type Getter = Box<Fn(&DataType) -> Result<Datas, ()> + Send + Sync>;
+use distributary::prelude::{Blender, DataType, Datas, Getter, Mutator, Recipe};
 
 pub struct Backend {
     getters: HashMap<String, Getter>,
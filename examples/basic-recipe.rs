@@ -3,7 +3,7 @@ extern crate distributary;
 mod backend;
 
 use std::{thread, time};
-use distributary::{Blender, Recipe};
+use distributary::prelude::{Blender, Recipe};
 use backend::Backend;
 
 fn load_recipe() -> Result<Backend, String> {
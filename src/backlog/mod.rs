@@ -1,90 +1,1218 @@
 use ops::Record;
 use flow::data::DataType;
-use fnv::FnvBuildHasher;
+use fnv::{FnvBuildHasher, FnvHashMap, FnvHashSet};
 use evmap;
 
-use std::sync::Arc;
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::Deref;
+use std::time;
+
+/// A single row stored in a backlog, along with the timestamp the store was at when the row was
+/// added.
+///
+/// Rows compare and hash by their contents only, so the timestamp does not need to be known to
+/// remove a row again.
+#[derive(Clone, Debug)]
+pub struct Row {
+    data: Arc<Vec<DataType>>,
+    ts: i64,
+}
+
+impl Row {
+    /// The timestamp of the write that added this row, or -1 if it was added before any
+    /// timestamped write reached the store.
+    pub fn ts(&self) -> i64 {
+        self.ts
+    }
+}
+
+impl Deref for Row {
+    type Target = Vec<DataType>;
+    fn deref(&self) -> &Self::Target {
+        &*self.data
+    }
+}
+
+impl PartialEq for Row {
+    fn eq(&self, other: &Row) -> bool {
+        self.data == other.data
+    }
+}
+
+impl Eq for Row {}
+
+impl Hash for Row {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.hash(state)
+    }
+}
+
+/// How often, and how recently, a key in a store has been read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyReads {
+    /// The number of times the key has been read.
+    pub reads: usize,
+    /// The number of reads of the whole store at the time the key was last read, or 0 if it has
+    /// never been read.
+    pub last_read: usize,
+}
+
+/// Which keys a store evicts first when it grows beyond its budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eviction {
+    /// Evict the keys that were read least recently.
+    LeastRecentlyUsed,
+    /// Evict the keys that were read least often.
+    LeastFrequentlyUsed,
+}
+
+/// How large a store may grow before it starts evicting keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Budget {
+    /// At most this many rows.
+    Rows(usize),
+    /// At most approximately this many bytes of rows.
+    Bytes(usize),
+}
+
+/// An `EvictionPolicy` bounds the size of a store by evicting keys once it grows too large.
+///
+/// Evicted keys are no longer answered from the store: reads of them fail, rather than returning
+/// no rows, so that callers can fetch their rows elsewhere. Writes to evicted keys are dropped
+/// until the key is filled in again (see `WriteHandle::fill`), and the keys that reads have missed
+/// on are reported to the writer so that it can do so (see `WriteHandle::fill_misses`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvictionPolicy {
+    /// The size the store is kept within.
+    pub budget: Budget,
+    /// The order in which keys are evicted.
+    pub evict: Eviction,
+}
+
+/// The approximate number of bytes used by a stored row.
+fn row_bytes(r: &[DataType]) -> usize {
+    mem::size_of::<Row>() + mem::size_of::<Vec<DataType>>() +
+    r.iter()
+        .map(|d| match *d {
+            DataType::Text(ref t) => mem::size_of::<DataType>() + t.to_bytes().len(),
+            _ => mem::size_of::<DataType>(),
+        })
+        .sum::<usize>()
+}
+
+/// The number of rows and bytes held for each key of a store with an eviction policy.
+struct Bounded {
+    policy: EvictionPolicy,
+    keys: FnvHashMap<DataType, (usize, usize)>,
+    rows: usize,
+    bytes: usize,
+    // evicted keys that have been filled in since the last swap. readers still see them as
+    // evicted until the swap, but writes to them are no longer dropped.
+    filled: FnvHashSet<DataType>,
+}
+
+impl Bounded {
+    fn over_budget(&self) -> bool {
+        match self.policy.budget {
+            Budget::Rows(n) => self.rows > n,
+            Budget::Bytes(n) => self.bytes > n,
+        }
+    }
+}
+
+/// Read statistics shared between a store's readers and its writer, along with the keys that
+/// have been evicted from the store.
+#[derive(Default)]
+struct Accesses {
+    enabled: AtomicBool,
+    clock: AtomicUsize,
+    reads: Mutex<FnvHashMap<DataType, KeyReads>>,
+
+    // keys that readers must not answer from the store, and whether there are any, so that
+    // stores that never evict don't need to take the lock on every read
+    evicted: RwLock<FnvHashSet<DataType>>,
+    evicting: AtomicBool,
+    // evicted keys that readers have tried to read since the writer last filled in misses
+    missed: Mutex<FnvHashSet<DataType>>,
+
+    // when the writer last swapped, and how many times it has swapped, regardless of whether
+    // tracking is enabled
+    swapped: Mutex<Option<time::Instant>>,
+    swaps: AtomicUsize,
+}
+
+impl Accesses {
+    fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, key: &DataType) {
+        if !self.enabled() {
+            return;
+        }
+
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut reads = self.reads.lock().unwrap();
+        let e = reads.entry(key.clone()).or_insert_with(KeyReads::default);
+        e.reads += 1;
+        e.last_read = now;
+    }
+
+    /// Record a read of `key`, which found `rows`, unless it found none. Keys without rows are
+    /// not recorded, so that reads of keys that do not exist do not take up any space.
+    fn record_if_found(&self, key: &DataType, rows: &[Row]) {
+        if !rows.is_empty() {
+            self.record(key);
+        }
+    }
+
+    /// Note a read of an evicted key, which the writer should fill in again.
+    fn miss(&self, key: &DataType) {
+        self.missed.lock().unwrap().insert(key.clone());
+    }
+}
+
+/// The keys of a store in order, for answering range lookups.
+struct Ordered {
+    // the keys that readers can currently see
+    keys: Arc<RwLock<BTreeSet<DataType>>>,
+    // the number of rows with each key, including rows that have not yet been swapped in
+    rows: FnvHashMap<DataType, usize>,
+    // keys whose number of rows has changed since the last swap
+    dirty: Vec<DataType>,
+}
+
+/// The rows of a sorted store, which are kept here rather than in the store's map.
+///
+/// evmap cannot insert a value at a given position, so sorted rows would have to be rewritten
+/// for every key that changes. Instead, readers share a single sorted copy of each key's rows.
+/// When it swaps, the writer builds new rows for the keys that have changed, and then only swaps
+/// them in while readers are locked out.
+struct Sorted {
+    // the column the rows are sorted by
+    col: usize,
+    // the rows that readers can currently see
+    rows: Arc<RwLock<SortedRows>>,
+    // rows added (true) or removed (false) since the last swap
+    pending: Vec<(DataType, Row, bool)>,
+}
+
+type SortedRows = FnvHashMap<DataType, Arc<Vec<Row>>>;
+
+impl Sorted {
+    /// Build the new rows of each key that rows have been added to or removed from since the last
+    /// swap, or `None` for keys that no longer have any rows.
+    fn prepare(&mut self) -> Vec<(DataType, Option<Arc<Vec<Row>>>)> {
+        use std::cmp::Ordering;
+
+        let col = self.col;
+        let mut changed: FnvHashMap<DataType, Vec<Row>> = FnvHashMap::default();
+        {
+            // we are the only writer, so nothing can change what readers see while we look
+            let current = self.rows.read().unwrap();
+            for (key, row, positive) in self.pending.drain(..) {
+                if !changed.contains_key(&key) {
+                    let rows = current.get(&key).map(|rs| (**rs).clone()).unwrap_or_default();
+                    changed.insert(key.clone(), rows);
+                }
+                let rows = changed.get_mut(&key).unwrap();
+
+                // rows that sort equal to `row` start at `at`. when adding, `row` goes after them
+                // instead, so that ties keep insertion order.
+                let at = match rows.binary_search_by(|e| match e[col].cmp(&row[col]) {
+                    Ordering::Equal if positive => Ordering::Less,
+                    Ordering::Equal => Ordering::Greater,
+                    o => o,
+                }) {
+                    Ok(i) | Err(i) => i,
+                };
+                if positive {
+                    rows.insert(at, row);
+                } else {
+                    let found = rows[at..]
+                        .iter()
+                        .take_while(|e| e[col] == row[col])
+                        .position(|e| *e == row);
+                    if let Some(i) = found {
+                        rows.remove(at + i);
+                    }
+                }
+            }
+        }
+
+        changed.into_iter()
+            .map(|(key, rows)| (key, if rows.is_empty() { None } else { Some(Arc::new(rows)) }))
+            .collect()
+    }
+}
+
+/// Rows recently removed from a store, so that reads can see the store as it was at an earlier
+/// timestamp (see `ReadHandle::find_at_and`).
+///
+/// A store and all its secondary indexes share the same history.
+struct History {
+    // removed rows, oldest first, along with the timestamp of the write that removed them
+    removed: VecDeque<(i64, Row)>,
+    // reads as of a timestamp before this one cannot be answered, since rows removed after it
+    // have been forgotten
+    horizon: i64,
+}
 
 /// Allocate a new buffered `Store`.
 pub fn new(cols: usize, key: usize) -> (ReadHandle, WriteHandle) {
-    let (r, w) = evmap::Options::default()
+    new_inner(cols, key, None, false)
+}
+
+/// Allocate a new buffered `Store` that also keeps its keys in order, so that all rows with keys
+/// in a given range can be found without looking at every key (see `ReadHandle::find_range_and`).
+pub fn new_ordered(cols: usize, key: usize) -> (ReadHandle, WriteHandle) {
+    let (mut r, mut w) = new_inner(cols, key, None, false);
+    let keys = Arc::new(RwLock::new(BTreeSet::new()));
+    r.ordered = Some(keys.clone());
+    w.ordered = Some(Ordered {
+        keys: keys,
+        rows: FnvHashMap::default(),
+        dirty: Vec::new(),
+    });
+    (r, w)
+}
+
+/// Allocate a new buffered `Store` that keeps the rows for each key sorted by column `sort`.
+///
+/// Rows with equal values in the sort column are kept in the order they were added.
+pub fn new_sorted(cols: usize, key: usize, sort: usize) -> (ReadHandle, WriteHandle) {
+    assert!(sort < cols,
+            "cannot sort by column {} of a store with {} columns",
+            sort,
+            cols);
+    new_inner(cols, key, Some(sort), false)
+}
+
+/// Allocate a new buffered `Store` that only keeps the number of rows with each key.
+///
+/// Instead of the rows themselves, each key maps to a single one-column row holding the number of
+/// rows with that key. Keys with no rows are not stored at all.
+pub fn new_counting(cols: usize, key: usize) -> (ReadHandle, WriteHandle) {
+    new_inner(cols, key, None, true)
+}
+
+fn new_inner(cols: usize,
+             key: usize,
+             sort: Option<usize>,
+             counting: bool)
+             -> (ReadHandle, WriteHandle) {
+    let (evr, evw) = evmap::Options::default()
         .with_meta(-1)
         .with_hasher(FnvBuildHasher::default())
         .construct();
-    let r = ReadHandle {
-        handle: r,
+    let accesses = Arc::new(Accesses::default());
+    let history = Arc::new(RwLock::new(History {
+        removed: VecDeque::new(),
+        horizon: -1,
+    }));
+    let mut r = ReadHandle {
+        handle: evr.clone(),
         key: key,
+        counting: counting,
+        accesses: accesses.clone(),
+        ordered: None,
+        sorted: None,
+        history: history.clone(),
     };
-    let w = WriteHandle {
-        handle: w,
+    let mut w = WriteHandle {
+        handle: evw,
         key: key,
         cols: cols,
+        ts: -1,
+        sorted: None,
+        counts: if counting {
+            Some(FnvHashMap::default())
+        } else {
+            None
+        },
+        accesses: accesses,
+        writes: FnvHashMap::default(),
+        shrunk: Vec::new(),
+        indexes: Vec::new(),
+        written: false,
+        ordered: None,
+        bounded: None,
+        history: history,
+        swapped: evr,
+        versions: 0,
+        removed: Vec::new(),
+        forgotten: -1,
     };
+    if let Some(col) = sort {
+        let rows = Arc::new(RwLock::new(SortedRows::default()));
+        r.sorted = Some(rows.clone());
+        w.sorted = Some(Sorted {
+            col: col,
+            rows: rows,
+            pending: Vec::new(),
+        });
+    }
     (r, w)
 }
 
 pub struct WriteHandle {
-    handle: evmap::WriteHandle<DataType, Arc<Vec<DataType>>, i64, FnvBuildHasher>,
+    handle: evmap::WriteHandle<DataType, Row, i64, FnvBuildHasher>,
     cols: usize,
     key: usize,
+    ts: i64,
+
+    // if set, the column to keep each key's rows sorted by, along with the rows themselves
+    sorted: Option<Sorted>,
+
+    // if set, the number of rows with each key, which is all that is exposed to readers
+    counts: Option<FnvHashMap<DataType, usize>>,
+
+    // reads of each key, as recorded by our readers, and the number of writes to each key. both
+    // are only kept while access tracking is enabled, and only for keys that have rows, so keys
+    // that rows were removed from since the last swap are checked for being empty when swapping.
+    accesses: Arc<Accesses>,
+    writes: FnvHashMap<DataType, usize>,
+    shrunk: Vec<DataType>,
+
+    // secondary indexes over the same rows, each keyed by a different column and with read
+    // statistics of its own
+    indexes: Vec<(usize, evmap::WriteHandle<DataType, Row, i64, FnvBuildHasher>, Arc<Accesses>)>,
+    // whether any records have been added yet
+    written: bool,
+
+    // if set, the store's keys in order
+    ordered: Option<Ordered>,
+
+    // if set, the size of the store, which is kept within the given budget
+    bounded: Option<Bounded>,
+
+    // rows removed by the most recent `versions` timestamps that removed any are kept in the
+    // history. rows removed since the last swap are held in `removed` until they are published,
+    // and `forgotten` is the latest timestamp at which rows were removed without being kept.
+    // `swapped` is used to find the timestamp each removed row was added at.
+    history: Arc<RwLock<History>>,
+    swapped: evmap::ReadHandle<DataType, Row, i64, FnvBuildHasher>,
+    versions: usize,
+    removed: Vec<(i64, Row)>,
+    forgotten: i64,
 }
 
 impl WriteHandle {
     pub fn swap(&mut self) {
+        // range lookups must see the same keys in the ordered index as in the store itself, so
+        // both are updated while no range lookups are running
+        let keys = self.ordered.as_ref().map(|o| o.keys.clone());
+        let mut keys = keys.as_ref().map(|keys| keys.write().unwrap());
+        if let Some(ref mut keys) = keys {
+            let o = self.ordered.as_mut().unwrap();
+            for key in o.dirty.drain(..) {
+                if o.rows.get(&key).map(|&n| n > 0).unwrap_or(false) {
+                    keys.insert(key);
+                } else {
+                    o.rows.remove(&key);
+                    keys.remove(&key);
+                }
+            }
+        }
+
+        if self.bounded.as_ref().map(|b| b.over_budget()).unwrap_or(false) {
+            self.evict();
+        }
+        let filled = self.bounded
+            .as_mut()
+            .map(|b| mem::replace(&mut b.filled, FnvHashSet::default()));
+        let changed = self.sorted.as_mut().map(|s| s.prepare());
+
+        // reads as of earlier timestamps must see rows either in the store or in its history, and
+        // reads of many keys must see them all from the same swap, so the store is only refreshed
+        // while no such reads are running
+        let lock = self.history.clone();
+        let mut history = lock.write().unwrap();
+
+        // sorted rows are kept outside of the map, and must change along with its timestamp. their
+        // new rows were built above, so readers are only locked out while they are swapped in.
+        let sorted = self.sorted.as_ref().map(|s| s.rows.clone());
+        let mut sorted = sorted.as_ref().map(|rows| rows.write().unwrap());
+        if let (Some(rows), Some(changed)) = (sorted.as_mut(), changed) {
+            for (key, rs) in changed {
+                match rs {
+                    Some(rs) => rows.insert(key, rs),
+                    None => rows.remove(&key),
+                };
+            }
+        }
+
         self.handle.refresh();
+        for &mut (_, ref mut handle, _) in &mut self.indexes {
+            handle.refresh();
+        }
+        self.publish_removed(&mut history);
+        drop(sorted);
+        drop(history);
+
+        self.forget_empty();
+
+        // filled keys can only be answered by readers once their rows have been swapped in
+        if let Some(filled) = filled {
+            if !filled.is_empty() {
+                let mut evicted = self.accesses.evicted.write().unwrap();
+                let mut missed = self.accesses.missed.lock().unwrap();
+                for key in filled {
+                    // reads that missed before the swap need not fill the key in again
+                    missed.remove(&key);
+                    evicted.remove(&key);
+                }
+                self.accesses.evicting.store(!evicted.is_empty(), Ordering::Release);
+            }
+        }
+
+        let now = time::Instant::now();
+        for accesses in Some(&self.accesses).into_iter().chain(self.indexes.iter().map(|i| &i.2)) {
+            *accesses.swapped.lock().unwrap() = Some(now);
+            accesses.swaps.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    /// Keep this store within the budget given by `policy`, by evicting keys whenever it has
+    /// grown too large as new writes are swapped in.
+    ///
+    /// Reads of this store are tracked from now on (see `ReadHandle::track_accesses`), since the
+    /// keys to evict are chosen based on them. Eviction is only supported for regular stores
+    /// without secondary indexes or ordered keys.
+    pub fn set_eviction(&mut self, policy: EvictionPolicy) {
+        assert!(self.sorted.is_none() && self.counts.is_none() && self.ordered.is_none() &&
+                self.indexes.is_empty(),
+                "eviction is only supported for regular stores");
+        assert!(!self.written,
+                "an eviction policy must be set before any records are added");
+        self.accesses.enabled.store(true, Ordering::Relaxed);
+        self.bounded = Some(Bounded {
+            policy: policy,
+            keys: FnvHashMap::default(),
+            rows: 0,
+            bytes: 0,
+            filled: FnvHashSet::default(),
+        });
+    }
+
+    /// Replace the rows of the evicted key `key` with `rows`, so that readers can read it again
+    /// once the store is next swapped.
+    ///
+    /// `rows` must be all the rows for the key that the store would hold had it never been
+    /// evicted, including those of any writes to it that have been dropped. Writes to the key
+    /// that are added after it has been filled in are kept like for any other key.
+    pub fn fill(&mut self, key: DataType, rows: Vec<Arc<Vec<DataType>>>) {
+        {
+            let bounded = self.bounded.as_mut().expect("only bounded stores can be filled");
+            if let Some((n, bytes)) = bounded.keys.remove(&key) {
+                bounded.rows -= n;
+                bounded.bytes -= bytes;
+            }
+            bounded.filled.insert(key.clone());
+        }
+        self.handle.clear(key);
+        self.add_records(rows.into_iter().map(Record::Positive));
+    }
+
+    /// Fill in the evicted keys that readers have tried to read since this was last called, with
+    /// the rows that `rows` gives for each of them (see `fill`).
+    ///
+    /// Keys for which `rows` returns `None` stay evicted, and are not reported again until they
+    /// are read again.
+    pub fn fill_misses<F>(&mut self, mut rows: F)
+        where F: FnMut(&DataType) -> Option<Vec<Arc<Vec<DataType>>>>
+    {
+        let missed = mem::replace(&mut *self.accesses.missed.lock().unwrap(),
+                                  FnvHashSet::default());
+        for key in missed {
+            if let Some(rs) = rows(&key) {
+                // the key was not counted as read when it was missed, since it had no rows
+                self.accesses.record(&key);
+                self.fill(key, rs);
+            }
+        }
+    }
+
+    /// Keep the rows removed by the last `versions` timestamps that removed any rows, so that
+    /// reads as of those timestamps can still be answered (see `ReadHandle::find_at_and`).
+    ///
+    /// Only the rows removed from now on are kept. Keeping versions is only supported for regular
+    /// stores, since sorted and counting stores do not keep track of the rows they remove.
+    pub fn keep_versions(&mut self, versions: usize) {
+        assert!(self.sorted.is_none() && self.counts.is_none(),
+                "versions can only be kept for regular stores");
+        self.versions = versions;
+    }
+
+    /// Keep `row`, which is being removed, in the history if the store keeps versions.
+    fn keep_removed(&mut self, key: &DataType, row: &Row) {
+        let added = if self.versions == 0 {
+            None
+        } else {
+            self.swapped
+                .meta_get_and(key, |rs| rs.iter().find(|r| *r == row).map(|r| r.ts))
+                .and_then(|(ts, _)| ts)
+        };
+        match added {
+            Some(ts) => {
+                self.removed.push((self.ts,
+                                   Row {
+                                       data: row.data.clone(),
+                                       ts: ts,
+                                   }))
+            }
+            // either no versions are kept, or the row was added since the last swap, and then we
+            // do not know when
+            None => self.forgotten = self.ts,
+        }
+    }
+
+    /// Move the rows removed since the last swap into `history`, and forget the oldest ones if it
+    /// holds more than the store's number of versions.
+    fn publish_removed(&mut self, history: &mut History) {
+        use std::cmp;
+
+        history.removed.extend(self.removed.drain(..));
+        history.horizon = cmp::max(history.horizon, self.forgotten);
+
+        let mut versions = 0;
+        let mut last = None;
+        let keep = history.removed
+            .iter()
+            .rev()
+            .take_while(|&&(ts, _)| {
+                if last != Some(ts) {
+                    versions += 1;
+                    last = Some(ts);
+                }
+                versions <= self.versions
+            })
+            .count();
+        let forget = history.removed.len() - keep;
+        for (ts, _) in history.removed.drain(..forget) {
+            history.horizon = cmp::max(history.horizon, ts);
+        }
+    }
+
+    /// Forget the reads of, and writes to, keys that rows were removed from since the last swap,
+    /// and that now have no rows left.
+    fn forget_empty(&mut self) {
+        if self.shrunk.is_empty() {
+            return;
+        }
+
+        let mut reads = self.accesses.reads.lock().unwrap();
+        for key in self.shrunk.drain(..) {
+            let empty = if let Some(ref counts) = self.counts {
+                !counts.contains_key(&key)
+            } else if let Some(ref sorted) = self.sorted {
+                sorted.rows.read().unwrap().get(&key).map(|rs| rs.is_empty()).unwrap_or(true)
+            } else {
+                self.swapped.meta_get_and(&key, |rs| rs.is_empty()).map(|(e, _)| e).unwrap_or(true)
+            };
+            if empty {
+                reads.remove(&key);
+                self.writes.remove(&key);
+            }
+        }
+    }
+
+    /// Evict keys until the store is within its budget again.
+    fn evict(&mut self) {
+        let bounded = self.bounded.as_mut().unwrap();
+        let mut keys: Vec<_> = {
+            let reads = self.accesses.reads.lock().unwrap();
+            bounded.keys
+                .keys()
+                .map(|k| (reads.get(k).cloned().unwrap_or_default(), k.clone()))
+                .collect()
+        };
+        match bounded.policy.evict {
+            Eviction::LeastRecentlyUsed => keys.sort_by_key(|&(r, _)| (r.last_read, r.reads)),
+            Eviction::LeastFrequentlyUsed => keys.sort_by_key(|&(r, _)| (r.reads, r.last_read)),
+        }
+
+        let mut evicted = self.accesses.evicted.write().unwrap();
+        let mut reads = self.accesses.reads.lock().unwrap();
+        for (_, key) in keys {
+            if !bounded.over_budget() {
+                break;
+            }
+            let (rows, bytes) = bounded.keys.remove(&key).unwrap();
+            bounded.rows -= rows;
+            bounded.bytes -= bytes;
+            self.handle.clear(key.clone());
+            bounded.filled.remove(&key);
+            // evicted keys have no rows to read, so their accesses need not be remembered
+            reads.remove(&key);
+            self.writes.remove(&key);
+            evicted.insert(key);
+        }
+        self.accesses.evicting.store(true, Ordering::Release);
+    }
+
+    /// Also index the rows of this store by column `col`, and return a handle for reading them
+    /// by that column.
+    ///
+    /// The secondary index shares its rows with the rest of the store, and is updated and swapped
+    /// along with it. Indexes can only be added to regular stores (not sorted or counting ones),
+    /// and only before any records have been added.
+    pub fn add_index(&mut self, col: usize) -> ReadHandle {
+        assert!(col < self.cols,
+                "cannot index column {} of a store with {} columns",
+                col,
+                self.cols);
+        assert!(self.sorted.is_none() && self.counts.is_none(),
+                "secondary indexes are only supported for regular stores");
+        assert!(!self.written,
+                "secondary indexes must be added before any records");
+
+        let (r, mut w) = evmap::Options::default()
+            .with_meta(-1)
+            .with_hasher(FnvBuildHasher::default())
+            .construct();
+        w.set_meta(self.ts);
+        let accesses = Arc::new(Accesses::default());
+        self.indexes.push((col, w, accesses.clone()));
+        ReadHandle {
+            handle: r,
+            key: col,
+            counting: false,
+            accesses: accesses,
+            ordered: None,
+            sorted: None,
+            history: self.history.clone(),
+        }
     }
 
     /// Add a new set of records to the backlog.
     ///
-    /// These will be made visible to readers after the next call to `swap()`.
+    /// These will be made visible to readers after the next call to `swap()`. Added rows are
+    /// tagged with the timestamp last given to `update_ts()`.
     pub fn add<I>(&mut self, rs: I)
         where I: IntoIterator<Item = Record>
     {
+        if self.accesses.enabled() || self.ordered.is_some() {
+            let rs: Vec<_> = rs.into_iter().collect();
+            if self.accesses.enabled() {
+                for r in &rs {
+                    *self.writes.entry(r[self.key].clone()).or_insert(0) += 1;
+                    if !r.is_positive() {
+                        self.shrunk.push(r[self.key].clone());
+                    }
+                }
+            }
+            if let Some(ref mut o) = self.ordered {
+                for r in &rs {
+                    let key = &r[self.key];
+                    let n = o.rows.entry(key.clone()).or_insert(0);
+                    if r.is_positive() {
+                        *n += 1;
+                    } else {
+                        debug_assert!(*n > 0, "negative for a key with no rows");
+                        *n -= 1;
+                    }
+                    o.dirty.push(key.clone());
+                }
+            }
+            return self.add_records(rs);
+        }
+        self.add_records(rs)
+    }
+
+    /// Pick up to `n` keys that are the best candidates for eviction.
+    ///
+    /// Keys that are written, but never read, come first, with the most frequently written keys
+    /// first among those. They are followed by keys that have been read, least recently read
+    /// first. Only accesses made while tracking was enabled (see `ReadHandle::track_accesses`) are
+    /// taken into account.
+    pub fn eviction_candidates(&self, n: usize) -> Vec<DataType> {
+        let reads = self.accesses.reads.lock().unwrap();
+        let mut keys: Vec<_> = self.writes
+            .iter()
+            .map(|(k, &w)| (reads.get(k).cloned().unwrap_or_default(), w, k))
+            .collect();
+        // note that the write counts are swapped, so that more writes sort first
+        keys.sort_by(|&(ra, wa, _), &(rb, wb, _)| {
+            (ra.reads > 0, ra.last_read, wb).cmp(&(rb.reads > 0, rb.last_read, wa))
+        });
+        keys.into_iter().take(n).map(|(_, _, k)| k.clone()).collect()
+    }
+
+    fn add_records<I>(&mut self, rs: I)
+        where I: IntoIterator<Item = Record>
+    {
+        self.written = true;
+        if self.bounded.is_some() {
+            return self.add_bounded(rs);
+        }
+        if self.sorted.is_some() {
+            return self.add_sorted(rs);
+        }
+        if self.counts.is_some() {
+            return self.add_counted(rs);
+        }
+
         for r in rs {
             debug_assert_eq!(r.len(), self.cols);
             let key = r[self.key].clone();
-            match r {
-                Record::Positive(r) => {
-                    self.handle.insert(key, r);
+            let (r, positive) = r.extract();
+            let row = Row {
+                data: r,
+                ts: self.ts,
+            };
+            for &mut (col, ref mut handle, _) in &mut self.indexes {
+                let key = row[col].clone();
+                if positive {
+                    handle.insert(key, row.clone());
+                } else {
+                    handle.remove(key, row.clone());
                 }
-                Record::Negative(r) => {
-                    self.handle.remove(key, r);
+            }
+            if positive {
+                self.handle.insert(key, row);
+            } else {
+                self.keep_removed(&key, &row);
+                // the timestamp is ignored when comparing rows
+                self.handle.remove(key, row);
+            }
+        }
+    }
+
+    fn add_bounded<I>(&mut self, rs: I)
+        where I: IntoIterator<Item = Record>
+    {
+        let mut kept = Vec::new();
+        {
+            let evicted = self.accesses.evicted.read().unwrap();
+            let bounded = self.bounded.as_mut().unwrap();
+            for r in rs {
+                {
+                    let key = &r[self.key];
+                    if evicted.contains(key) && !bounded.filled.contains(key) {
+                        continue;
+                    }
+
+                    let bytes = row_bytes(&r[..]);
+                    if r.is_positive() {
+                        let e = bounded.keys.entry(key.clone()).or_insert((0, 0));
+                        e.0 += 1;
+                        e.1 += bytes;
+                        bounded.rows += 1;
+                        bounded.bytes += bytes;
+                    } else {
+                        let empty = {
+                            let e = bounded.keys
+                                .get_mut(key)
+                                .expect("negative for a key with no rows");
+                            e.0 -= 1;
+                            e.1 -= bytes;
+                            e.0 == 0
+                        };
+                        if empty {
+                            bounded.keys.remove(key);
+                        }
+                        bounded.rows -= 1;
+                        bounded.bytes -= bytes;
+                    }
                 }
-                Record::DeleteRequest(..) => unreachable!(),
+                kept.push(r);
+            }
+        }
+
+        // the rows themselves are stored like in any regular store
+        let bounded = self.bounded.take();
+        self.add_records(kept);
+        self.bounded = bounded;
+    }
+
+    fn add_sorted<I>(&mut self, rs: I)
+        where I: IntoIterator<Item = Record>
+    {
+        let sorted = self.sorted.as_mut().unwrap();
+        for r in rs {
+            debug_assert_eq!(r.len(), self.cols);
+            let key = r[self.key].clone();
+            let (r, positive) = r.extract();
+            if !positive {
+                self.forgotten = self.ts;
+            }
+            sorted.pending.push((key,
+                                 Row {
+                                     data: r,
+                                     ts: self.ts,
+                                 },
+                                 positive));
+        }
+    }
+
+    fn add_counted<I>(&mut self, rs: I)
+        where I: IntoIterator<Item = Record>
+    {
+        use std::collections::HashSet;
+
+        let counts = self.counts.as_mut().unwrap();
+        let mut dirty = HashSet::new();
+        for r in rs {
+            debug_assert_eq!(r.len(), self.cols);
+            let key = r[self.key].clone();
+            {
+                let count = counts.entry(key.clone()).or_insert(0);
+                if r.is_positive() {
+                    *count += 1;
+                } else {
+                    debug_assert!(*count > 0, "negative for a key with no rows");
+                    *count -= 1;
+                }
+            }
+            dirty.insert(key);
+        }
+
+        // the old counts are replaced, so they cannot be seen by reads as of earlier timestamps
+        if !dirty.is_empty() {
+            self.forgotten = self.ts;
+        }
+        for key in dirty {
+            self.handle.clear(key.clone());
+            let count = counts[&key];
+            if count == 0 {
+                counts.remove(&key);
+            } else {
+                self.handle.insert(key,
+                                   Row {
+                                       data: Arc::new(vec![(count as i64).into()]),
+                                       ts: self.ts,
+                                   });
             }
         }
     }
 
     pub fn update_ts(&mut self, ts: i64) {
+        self.ts = ts;
         self.handle.set_meta(ts);
+        for &mut (_, ref mut handle, _) in &mut self.indexes {
+            handle.set_meta(ts);
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct ReadHandle {
-    handle: evmap::ReadHandle<DataType, Arc<Vec<DataType>>, i64, FnvBuildHasher>,
+    handle: evmap::ReadHandle<DataType, Row, i64, FnvBuildHasher>,
     key: usize,
+    counting: bool,
+    accesses: Arc<Accesses>,
+    ordered: Option<Arc<RwLock<BTreeSet<DataType>>>>,
+    sorted: Option<Arc<RwLock<SortedRows>>>,
+    history: Arc<RwLock<History>>,
 }
 
 impl ReadHandle {
     /// Find all entries that matched the given conditions.
     ///
-    /// Returned records are passed to `then` before being returned.
+    /// Returned records are passed to `then` before being returned. The returned timestamp is
+    /// that of the store as a whole; each `Row` also carries the timestamp it was written at.
     ///
     /// Note that not all writes will be included with this read -- only those that have been
     /// swapped in by the writer.
+    ///
+    /// Readers are always fully materialized, so a key with no rows is answered directly from the
+    /// store: `then` is given an empty slice, and the returned timestamp says as of when the key
+    /// is known to be empty. Such lookups never need to consult anything beyond the store itself,
+    /// so there is no separate record of missing keys.
     pub fn find_and<F, T>(&self, key: &DataType, then: F) -> Result<(T, i64), ()>
-        where F: FnOnce(&[Arc<Vec<DataType>>]) -> T
+        where F: FnOnce(&[Row]) -> T
+    {
+        if self.is_evicted(key) {
+            self.accesses.miss(key);
+            return Err(());
+        }
+        self.lookup_and(key, |rs| {
+            self.accesses.record_if_found(key, rs);
+            then(rs)
+        })
+    }
+
+    /// Find the rows with the given key, without checking whether it has been evicted or
+    /// recording the read.
+    fn lookup_and<F, T>(&self, key: &DataType, then: F) -> Result<(T, i64), ()>
+        where F: FnOnce(&[Row]) -> T
     {
+        if let Some(ref sorted) = self.sorted {
+            // the map of a sorted store holds no rows, only its timestamp
+            let (rows, ts) = {
+                let sorted = sorted.read().unwrap();
+                let (_, ts) = self.handle.meta_get_and(key, |_| ()).ok_or(())?;
+                (sorted.get(key).cloned(), ts)
+            };
+            let rows = rows.as_ref().map(|rs| &rs[..]).unwrap_or(&[]);
+            return Ok((then(rows), ts));
+        }
         self.handle.meta_get_and(key, then).ok_or(())
     }
 
+    /// Find all entries that matched the given conditions, like `find_and`, but only if the store
+    /// reflects all writes up to and including timestamp `ts`.
+    ///
+    /// Returns `Ok(None)` if the store has yet to swap in all of those writes, in which case the
+    /// read can be retried later.
+    pub fn find_after_and<F, T>(&self, key: &DataType, ts: i64, then: F) -> Result<Option<T>, ()>
+        where F: FnOnce(&[Row]) -> T
+    {
+        let (res, at) = self.find_and(key, then)?;
+        if at >= ts { Ok(Some(res)) } else { Ok(None) }
+    }
+
+    /// Find all entries that matched the given conditions as of timestamp `ts`, that is, the rows
+    /// that were in the store right after the write with that timestamp was applied.
+    ///
+    /// Returns `Ok(None)` if the store has yet to swap in all writes up to `ts`, like
+    /// `find_after_and`, and `Err(())` if the key has been evicted, or if rows removed after `ts`
+    /// have since been forgotten. Stores only remember removed rows for as many timestamps as
+    /// `WriteHandle::keep_versions` asks them to, so without that, only reads as of the latest
+    /// timestamp that removed rows, or any later one, can be answered. Rows added by
+    /// non-transactional writes count as added at the timestamp of the transaction before them.
+    pub fn find_at_and<F, T>(&self, key: &DataType, ts: i64, then: F) -> Result<Option<T>, ()>
+        where F: FnOnce(&[Row]) -> T
+    {
+        let history = self.history.read().unwrap();
+        if ts < history.horizon {
+            return Err(());
+        }
+
+        let (mut rows, at) = self.find_and(key, |rs| {
+                rs.iter().filter(|r| r.ts <= ts).cloned().collect::<Vec<_>>()
+            })?;
+        if at < ts {
+            return Ok(None);
+        }
+
+        // rows that have been removed since, but that were there at the time
+        rows.extend(history.removed
+            .iter()
+            .filter(|&&(removed, ref r)| removed > ts && r.ts <= ts && r[self.key] == *key)
+            .map(|&(_, ref r)| r.clone()));
+        Ok(Some(then(&rows[..])))
+    }
+
+    /// Find all entries whose values in `columns` are those in `key`.
+    ///
+    /// The store's own key column must be one of `columns`. The rows with the matching value in
+    /// that column are looked up as with `find_and`, and those that do not match the remaining
+    /// columns are filtered out before being passed to `then`. Compound lookups are therefore
+    /// cheapest when the store is keyed on the most selective of the columns.
+    pub fn find_compound_and<F, T>(&self,
+                                   columns: &[usize],
+                                   key: &[DataType],
+                                   then: F)
+                                   -> Result<(T, i64), ()>
+        where F: FnOnce(&[&Row]) -> T
+    {
+        assert_eq!(columns.len(),
+                   key.len(),
+                   "compound lookups need one value per key column");
+        assert!(!self.counting, "compound lookups require a store that keeps its rows");
+        let i = columns.iter()
+            .position(|&c| c == self.key)
+            .expect("compound lookups must include the store's key column");
+
+        self.find_and(&key[i], |rs| {
+            let rs: Vec<_> = rs.iter()
+                .filter(|r| columns.iter().zip(key).all(|(&c, k)| r[c] == *k))
+                .collect();
+            then(&rs[..])
+        })
+    }
+
+    /// Find the number of rows with the given key.
+    ///
+    /// This works for all stores, but is much cheaper for stores made with `new_counting`, which
+    /// keep only the counts.
+    pub fn count(&self, key: &DataType) -> Result<usize, ()> {
+        let counting = self.counting;
+        self.find_and(key, |rs| if counting {
+                rs.get(0)
+                    .map(|r| {
+                        let n: i64 = r[0].clone().into();
+                        n as usize
+                    })
+                    .unwrap_or(0)
+            } else {
+                rs.len()
+            })
+            .map(|r| r.0)
+    }
+
+    /// Whether there are any rows with the given key.
+    pub fn contains(&self, key: &DataType) -> Result<bool, ()> {
+        self.count(key).map(|n| n > 0)
+    }
+
+    /// Find all entries with keys between `lo` and `hi` (inclusive).
+    ///
+    /// The rows for each key are passed to `then`, and the results are returned along with their
+    /// key, in ascending key order. Keys are ordered as `DataType`s are, so all keys in a range
+    /// should be of the same type. Only stores made with `new_ordered` support range lookups.
+    pub fn find_range_and<F, T>(&self,
+                                lo: &DataType,
+                                hi: &DataType,
+                                mut then: F)
+                                -> Result<(Vec<(DataType, T)>, i64), ()>
+        where F: FnMut(&[Row]) -> T
+    {
+        let keys = self.ordered.as_ref().expect("range lookups require an ordered store");
+        let keys = keys.read().unwrap();
+        let ts = self.handle.meta_get_and(&DataType::None, |_| ()).ok_or(())?.1;
+
+        let mut results = Vec::new();
+        for key in keys.range(lo.clone()..).take_while(|k| *k <= hi) {
+            self.accesses.record(key);
+            if let Some((res, _)) = self.handle.meta_get_and(key, &mut then) {
+                results.push((key.clone(), res));
+            }
+        }
+        Ok((results, ts))
+    }
+
+    /// Whether this store keeps its keys in order, and thus supports range lookups.
+    pub fn is_ordered(&self) -> bool {
+        self.ordered.is_some()
+    }
+
+    /// Whether the given key has been evicted from the store (see `EvictionPolicy`).
+    ///
+    /// This distinguishes reads that fail because the key was evicted from reads that fail
+    /// because the store is not yet ready.
+    pub fn is_evicted(&self, key: &DataType) -> bool {
+        self.accesses.evicting.load(Ordering::Acquire) &&
+        self.accesses.evicted.read().unwrap().contains(key)
+    }
+
+    /// Whether this store only keeps the number of rows with each key.
+    pub fn is_counting(&self) -> bool {
+        self.counting
+    }
+
+    /// Start or stop recording how often, and how recently, each key of this store is read.
+    ///
+    /// This affects all readers of the store, and the recorded reads are made available to the
+    /// store's `WriteHandle` for picking eviction candidates.
+    pub fn track_accesses(&self, on: bool) {
+        self.accesses.enabled.store(on, Ordering::Relaxed);
+    }
+
+    /// How often, and how recently, the given key has been read while tracking was enabled.
+    ///
+    /// Only reads that found rows are counted, and the reads of keys that have since been evicted,
+    /// or have had all their rows removed, are forgotten.
+    pub fn reads(&self, key: &DataType) -> KeyReads {
+        self.accesses.reads.lock().unwrap().get(key).cloned().unwrap_or_default()
+    }
+
+    /// When the writer last swapped in new writes, if it has ever done so.
+    pub fn last_swap(&self) -> Option<time::Instant> {
+        *self.accesses.swapped.lock().unwrap()
+    }
+
+    /// The number of times the writer has swapped in new writes.
+    ///
+    /// The count is only incremented once a swap has completed, so a read made after this returns
+    /// `n` observes at least the writes exposed by the first `n` swaps.
+    pub fn swaps(&self) -> usize {
+        self.accesses.swaps.load(Ordering::Acquire)
+    }
+
+    /// Find all entries for each of the given keys.
+    ///
+    /// This behaves like calling `find_and` once per key, except that all keys are read from the
+    /// same swap of the store, and so at the same timestamp, which is returned alongside the
+    /// results (one per key, in the order the keys were given). `then` is called exactly once per
+    /// key, and only if none of the keys have been evicted.
+    pub fn find_many_and<F, T>(&self, keys: &[DataType], mut then: F) -> Result<(Vec<T>, i64), ()>
+        where F: FnMut(&[Row]) -> T
+    {
+        // the writer only swaps while holding the history lock, so holding it here keeps every
+        // key read from the same swap
+        let _history = self.history.read().unwrap();
+        let mut evicted = keys.iter().filter(|key| self.is_evicted(key)).peekable();
+        if evicted.peek().is_some() {
+            for key in evicted {
+                self.accesses.miss(key);
+            }
+            return Err(());
+        }
+        let (_, ts) = self.lookup_and(&DataType::None, |_| ())?;
+
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            let res = self.lookup_and(key, |rs| {
+                    self.accesses.record_if_found(key, rs);
+                    then(rs)
+                })?;
+            results.push(res.0);
+        }
+        Ok((results, ts))
+    }
+
+    /// All rows in the store, regardless of their key.
+    ///
+    /// Returns `Err(())` if the store has not yet been swapped for the first time.
+    pub fn all_rows(&self) -> Result<Vec<Arc<Vec<DataType>>>, ()> {
+        if self.handle.meta_get_and(&DataType::None, |_| ()).is_none() {
+            return Err(());
+        }
+
+        let mut rows = Vec::new();
+        self.for_each(|_, rs| rows.extend(rs.iter().map(|r| r.data.clone())));
+        Ok(rows)
+    }
+
+    /// Call `f` with the rows of every key in the store.
+    fn for_each<F>(&self, mut f: F)
+        where F: FnMut(&DataType, &[Row])
+    {
+        match self.sorted {
+            Some(ref sorted) => {
+                for (key, rs) in sorted.read().unwrap().iter() {
+                    f(key, &rs[..]);
+                }
+            }
+            None => self.handle.for_each(|key, rs| f(key, rs)),
+        }
+    }
+
     pub fn key(&self) -> usize {
         self.key
     }
 
     pub fn len(&self) -> usize {
-        self.handle.len()
+        match self.sorted {
+            Some(ref sorted) => sorted.read().unwrap().len(),
+            None => self.handle.len(),
+        }
+    }
+
+    /// The number of rows readers can currently see in the store, and the approximate number of
+    /// bytes they use.
+    ///
+    /// If `shared` is set, the rows are assumed to be held by another store as well (as they are
+    /// for secondary indexes), and only the cost of referring to them is counted.
+    pub fn memory(&self, shared: bool) -> (usize, usize) {
+        let (mut rows, mut bytes) = (0, 0);
+        self.for_each(|_, rs| {
+            rows += rs.len();
+            bytes += mem::size_of::<DataType>() + mem::size_of::<Vec<Row>>();
+            bytes += if shared {
+                rs.len() * mem::size_of::<Row>()
+            } else {
+                rs.iter().map(|r| row_bytes(&r[..])).sum::<usize>()
+            };
+        });
+        (rows, bytes)
     }
 }
 
@@ -118,6 +1246,377 @@ mod tests {
         assert!(r.find_and(&a[0], |rs| rs.iter().any(|r| r[0] == a[0] && r[1] == a[1])).unwrap().0);
     }
 
+    #[test]
+    fn compound_lookups() {
+        let a = Arc::new(vec![1.into(), "a".into(), 1.into()]);
+        let b = Arc::new(vec![1.into(), "b".into(), 2.into()]);
+        let c = Arc::new(vec![2.into(), "a".into(), 3.into()]);
+
+        let (r, mut w) = new(3, 0);
+        w.add(vec![Record::Positive(a.clone()),
+                   Record::Positive(b.clone()),
+                   Record::Positive(c.clone())]);
+        w.swap();
+
+        let find = |k: &[DataType]| {
+            r.find_compound_and(&[0, 1],
+                                   k,
+                                   |rs| rs.iter().map(|r| r[2].clone()).collect::<Vec<_>>())
+                .unwrap()
+                .0
+        };
+        assert_eq!(find(&[1.into(), "a".into()]), vec![1.into()]);
+        assert_eq!(find(&[1.into(), "b".into()]), vec![2.into()]);
+        assert_eq!(find(&[2.into(), "a".into()]), vec![3.into()]);
+        assert!(find(&[2.into(), "b".into()]).is_empty());
+
+        // the key column may come at any position
+        let rs = r.find_compound_and(&[1, 0], &["b".into(), 1.into()], |rs| rs.len());
+        assert_eq!(rs.unwrap().0, 1);
+    }
+
+    #[test]
+    fn missing_key_is_known_empty() {
+        let a = Arc::new(vec![1.into(), "a".into()]);
+
+        let (r, mut w) = new(2, 0);
+        w.add(vec![Record::Positive(a.clone())]);
+        w.update_ts(3);
+        w.swap();
+
+        // a key that was never written is empty as of the store's current timestamp
+        assert_eq!(r.find_and(&2.into(), |rs| rs.len()), Ok((0, 3)));
+
+        // as is a key whose rows have all been removed
+        w.update_ts(4);
+        w.add(vec![Record::Negative(a.clone())]);
+        w.swap();
+        assert_eq!(r.find_and(&a[0], |rs| rs.len()), Ok((0, 4)));
+    }
+
+    #[test]
+    fn rows_carry_ts() {
+        let a = Arc::new(vec![1.into(), "a".into()]);
+        let b = Arc::new(vec![1.into(), "b".into()]);
+
+        let (r, mut w) = new(2, 0);
+        w.add(vec![Record::Positive(a.clone())]);
+        w.update_ts(1);
+        w.add(vec![Record::Positive(b.clone())]);
+        w.swap();
+
+        let mut rows = r.find_and(&a[0], |rs| {
+                rs.iter().map(|r| (r[1].clone(), r.ts())).collect::<Vec<_>>()
+            })
+            .unwrap()
+            .0;
+        rows.sort_by_key(|&(_, ts)| ts);
+        assert_eq!(rows, vec![("a".into(), -1), ("b".into(), 1)]);
+
+        // removing a row doesn't require knowing its timestamp
+        w.update_ts(2);
+        w.add(vec![Record::Negative(a.clone())]);
+        w.swap();
+        assert_eq!(r.find_and(&a[0], |rs| rs.len()), Ok((1, 2)));
+    }
+
+    #[test]
+    fn find_many() {
+        let a = Arc::new(vec![1.into(), "a".into()]);
+        let b = Arc::new(vec![1.into(), "b".into()]);
+        let c = Arc::new(vec![2.into(), "c".into()]);
+
+        let (r, mut w) = new(2, 0);
+        assert_eq!(r.find_many_and(&[1.into()], |rs| rs.len()), Err(()));
+
+        w.add(vec![Record::Positive(a.clone()),
+                   Record::Positive(b.clone()),
+                   Record::Positive(c.clone())]);
+        w.update_ts(1);
+        w.swap();
+
+        let keys = [2.into(), 3.into(), 1.into()];
+        assert_eq!(r.find_many_and(&keys[..], |rs| rs.len()), Ok((vec![1, 0, 2], 1)));
+        assert_eq!(r.find_many_and(&[], |rs| rs.len()), Ok((vec![], 1)));
+
+        // each key is looked at, and counted as read, exactly once
+        r.track_accesses(true);
+        let mut calls = 0;
+        assert_eq!(r.find_many_and(&keys[..], |_| calls += 1), Ok((vec![(), (), ()], 1)));
+        assert_eq!(calls, 3);
+        assert_eq!(r.reads(&1.into()).reads, 1);
+        assert_eq!(r.reads(&DataType::None).reads, 0);
+
+        // and keys without rows are not counted at all
+        assert_eq!(r.reads(&3.into()).reads, 0);
+    }
+
+    #[test]
+    fn sorted() {
+        let a = Arc::new(vec![1.into(), 3.into()]);
+        let b = Arc::new(vec![1.into(), 1.into()]);
+        let c = Arc::new(vec![1.into(), 2.into()]);
+
+        let (r, mut w) = new_sorted(2, 0, 1);
+        w.add(vec![Record::Positive(a.clone()), Record::Positive(b.clone())]);
+        w.swap();
+        w.add(vec![Record::Positive(c.clone())]);
+        w.swap();
+
+        let order = |r: &ReadHandle| {
+            r.find_and(&1.into(), |rs| rs.iter().map(|r| r[1].clone()).collect::<Vec<_>>())
+                .unwrap()
+                .0
+        };
+        assert_eq!(order(&r), vec![1.into(), 2.into(), 3.into()]);
+
+        w.add(vec![Record::Negative(c.clone())]);
+        w.swap();
+        assert_eq!(order(&r), vec![1.into(), 3.into()]);
+
+        // rows added and removed again before a swap never become visible
+        w.add(vec![Record::Positive(c.clone())]);
+        w.add(vec![Record::Negative(c.clone()), Record::Negative(b.clone())]);
+        assert_eq!(order(&r), vec![1.into(), 3.into()]);
+        w.swap();
+        assert_eq!(order(&r), vec![3.into()]);
+        assert_eq!(r.len(), 1);
+
+        // keys whose rows have all been removed are gone
+        w.add(vec![Record::Negative(a.clone())]);
+        w.swap();
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()).unwrap().0, 0);
+        assert_eq!(r.len(), 0);
+        assert!(r.all_rows().unwrap().is_empty());
+    }
+
+    #[test]
+    fn sorted_ties() {
+        let row = |n: i32, name: &str| Arc::new(vec![1.into(), n.into(), name.into()]);
+
+        let (r, mut w) = new_sorted(3, 0, 1);
+        w.add(vec![Record::Positive(row(2, "x")),
+                   Record::Positive(row(1, "a")),
+                   Record::Positive(row(1, "b")),
+                   Record::Positive(row(1, "c"))]);
+        w.swap();
+
+        let names = |r: &ReadHandle| {
+            r.find_and(&1.into(), |rs| rs.iter().map(|r| r[2].clone()).collect::<Vec<_>>())
+                .unwrap()
+                .0
+        };
+        assert_eq!(names(&r), vec!["a".into(), "b".into(), "c".into(), "x".into()]);
+
+        // rows that sort equal keep the order they were added in, whichever of them is removed
+        w.add(vec![Record::Negative(row(1, "b")), Record::Positive(row(1, "d"))]);
+        w.swap();
+        assert_eq!(names(&r), vec!["a".into(), "c".into(), "d".into(), "x".into()]);
+        w.add(vec![Record::Negative(row(1, "d")), Record::Negative(row(1, "a"))]);
+        w.swap();
+        assert_eq!(names(&r), vec!["c".into(), "x".into()]);
+    }
+
+    #[test]
+    fn secondary_indexes() {
+        let a = Arc::new(vec![1.into(), "x".into()]);
+        let b = Arc::new(vec![2.into(), "x".into()]);
+        let c = Arc::new(vec![3.into(), "y".into()]);
+
+        let (r, mut w) = new(2, 0);
+        let by_author = w.add_index(1);
+        assert_eq!(by_author.key(), 1);
+        assert_eq!(by_author.find_and(&"x".into(), |rs| rs.len()), Err(()));
+
+        w.add(vec![Record::Positive(a.clone()),
+                   Record::Positive(b.clone()),
+                   Record::Positive(c.clone())]);
+        w.update_ts(1);
+        w.swap();
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()), Ok((1, 1)));
+        assert_eq!(by_author.find_and(&"x".into(), |rs| rs.len()), Ok((2, 1)));
+        assert_eq!(by_author.swaps(), 1);
+
+        // both indexes share the same rows
+        let shared = by_author.find_and(&"y".into(), |rs| rs[0].data.clone()).unwrap().0;
+        assert!(Arc::ptr_eq(&shared, &c));
+
+        w.add(vec![Record::Negative(a.clone())]);
+        w.swap();
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()).unwrap().0, 0);
+        assert_eq!(by_author.find_and(&"x".into(), |rs| rs.len()).unwrap().0, 1);
+    }
+
+    #[test]
+    fn ranges() {
+        let row = |k: i32, v: &str| Record::Positive(Arc::new(vec![k.into(), v.into()]));
+        let neg = |k: i32, v: &str| Record::Negative(Arc::new(vec![k.into(), v.into()]));
+
+        let (r, mut w) = new_ordered(2, 0);
+        assert!(r.is_ordered());
+        assert_eq!(r.find_range_and(&1.into(), &5.into(), |rs| rs.len()), Err(()));
+
+        w.add(vec![row(5, "e"), row(1, "a"), row(3, "c"), row(3, "cc"), row(7, "g")]);
+        w.swap();
+
+        // keys in the range come back in order, with all their rows
+        assert_eq!(r.find_range_and(&2.into(), &5.into(), |rs| rs.len()),
+                   Ok((vec![(3.into(), 2), (5.into(), 1)], -1)));
+        assert_eq!(r.find_range_and(&8.into(), &10.into(), |rs| rs.len()),
+                   Ok((vec![], -1)));
+
+        // writes are only seen by range lookups once swapped in
+        w.update_ts(1);
+        w.add(vec![row(4, "d"), neg(3, "c"), neg(3, "cc")]);
+        assert_eq!(r.find_range_and(&2.into(), &5.into(), |rs| rs.len()).unwrap().0.len(),
+                   2);
+        w.swap();
+        assert_eq!(r.find_range_and(&2.into(), &5.into(), |rs| rs.len()),
+                   Ok((vec![(4.into(), 1), (5.into(), 1)], 1)));
+    }
+
+    #[test]
+    fn eviction() {
+        let row = |k: i32| Record::Positive(Arc::new(vec![k.into(), "x".into()]));
+
+        let (r, mut w) = new(2, 0);
+        w.set_eviction(EvictionPolicy {
+            budget: Budget::Rows(4),
+            evict: Eviction::LeastRecentlyUsed,
+        });
+        w.add(vec![row(1), row(2), row(3), row(4)]);
+        w.swap();
+
+        // read 1 and then 3, so that 2 is the least recently used
+        r.find_and(&1.into(), |_| ()).unwrap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        r.find_and(&2.into(), |_| ()).unwrap();
+        r.find_and(&1.into(), |_| ()).unwrap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        r.find_and(&4.into(), |_| ()).unwrap();
+
+        // going over budget evicts keys that were never read first
+        w.add(vec![row(5)]);
+        w.swap();
+        assert!(r.is_evicted(&5.into()));
+        assert!(!r.is_evicted(&1.into()));
+
+        // and then the least recently read key
+        w.add(vec![row(3)]);
+        w.swap();
+        assert!(r.is_evicted(&2.into()));
+        assert!(!r.is_evicted(&4.into()));
+
+        // evicted keys fail to read, while keys that never had rows are still known to be empty
+        assert_eq!(r.find_and(&2.into(), |rs| rs.len()), Err(()));
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()).unwrap().0, 1);
+        assert_eq!(r.find_and(&7.into(), |rs| rs.len()).unwrap().0, 0);
+
+        // writes to evicted keys are dropped
+        w.add(vec![row(2)]);
+        w.swap();
+        assert_eq!(r.find_and(&2.into(), |rs| rs.len()), Err(()));
+    }
+
+    #[test]
+    fn eviction_fill() {
+        let row = |k: i32| Record::Positive(Arc::new(vec![k.into(), "x".into()]));
+
+        let (r, mut w) = new(2, 0);
+        w.set_eviction(EvictionPolicy {
+            budget: Budget::Rows(3),
+            evict: Eviction::LeastRecentlyUsed,
+        });
+        w.add(vec![row(1), row(2), row(3)]);
+        w.swap();
+        r.find_and(&2.into(), |_| ()).unwrap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        w.add(vec![row(2)]);
+        w.swap();
+        assert!(r.is_evicted(&1.into()));
+
+        // reads of evicted keys are reported to the writer, so that it can fill them in again
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()), Err(()));
+        w.add(vec![row(1)]);
+        let mut asked = Vec::new();
+        w.fill_misses(|key| {
+            asked.push(key.clone());
+            Some(vec![Arc::new(vec![1.into(), "y".into()])])
+        });
+        assert_eq!(asked, vec![1.into()]);
+
+        // readers see the filled key after the next swap, and writes to it are no longer dropped
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()), Err(()));
+        w.add(vec![row(1)]);
+        w.swap();
+        assert!(!r.is_evicted(&1.into()));
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()).unwrap().0, 2);
+
+        // the key was just read, so less recently read keys are evicted to make room for it
+        assert!(r.is_evicted(&2.into()));
+        assert!(r.is_evicted(&3.into()));
+
+        // keys that have been filled in are not reported again
+        w.fill_misses(|_| unreachable!());
+    }
+
+    #[test]
+    fn eviction_by_frequency() {
+        let row = |k: i32| Record::Positive(Arc::new(vec![k.into(), "x".into()]));
+
+        let (r, mut w) = new(2, 0);
+        w.set_eviction(EvictionPolicy {
+            budget: Budget::Rows(3),
+            evict: Eviction::LeastFrequentlyUsed,
+        });
+        w.add(vec![row(1), row(2), row(3)]);
+        w.swap();
+        r.find_and(&1.into(), |_| ()).unwrap();
+        r.find_and(&1.into(), |_| ()).unwrap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        r.find_and(&2.into(), |_| ()).unwrap();
+
+        // 2 was read least often, so it is evicted first, even though it was read most recently
+        w.add(vec![row(1)]);
+        w.swap();
+        assert!(r.is_evicted(&2.into()));
+        assert!(!r.is_evicted(&1.into()));
+        assert!(!r.is_evicted(&3.into()));
+    }
+
+    #[test]
+    fn counting() {
+        let a = Arc::new(vec![1.into(), "a".into()]);
+        let b = Arc::new(vec![1.into(), "b".into()]);
+        let c = Arc::new(vec![2.into(), "c".into()]);
+
+        let (r, mut w) = new_counting(2, 0);
+        assert!(r.is_counting());
+        w.add(vec![Record::Positive(a.clone()),
+                   Record::Positive(b.clone()),
+                   Record::Positive(c.clone())]);
+        w.swap();
+        assert_eq!(r.count(&1.into()), Ok(2));
+        assert_eq!(r.count(&2.into()), Ok(1));
+        assert_eq!(r.contains(&3.into()), Ok(false));
+
+        // only the count is stored
+        assert_eq!(r.find_and(&1.into(), |rs| rs.len()).unwrap().0, 1);
+
+        w.add(vec![Record::Negative(a.clone()), Record::Negative(c.clone())]);
+        w.swap();
+        assert_eq!(r.count(&1.into()), Ok(1));
+        assert_eq!(r.contains(&2.into()), Ok(false));
+
+        // counting also works for regular stores
+        let (r, mut w) = new(2, 0);
+        w.add(vec![Record::Positive(a.clone()), Record::Positive(b.clone())]);
+        w.swap();
+        assert_eq!(r.count(&1.into()), Ok(2));
+        assert_eq!(r.contains(&1.into()), Ok(true));
+    }
+
     #[test]
     fn busybusybusy() {
         use std::thread;
@@ -226,4 +1725,94 @@ mod tests {
         assert_eq!(r.find_and(&a[0], |rs| rs.len()).unwrap().0, 1);
         assert!(r.find_and(&a[0], |rs| rs.iter().any(|r| r[0] == b[0] && r[1] == b[1])).unwrap().0);
     }
+
+    #[test]
+    fn it_picks_unread_keys_for_eviction() {
+        let (r, mut w) = new(2, 0);
+        r.track_accesses(true);
+
+        // 1 and 2 are written, but never read, and 2 more often than 1
+        w.add(vec![Record::Positive(Arc::new(vec![1.into(), "a".into()]))]);
+        for _ in 0..2 {
+            w.add(vec![Record::Positive(Arc::new(vec![2.into(), "b".into()]))]);
+        }
+        // 3 and 4 are both read, but 3 less recently than 4
+        w.add(vec![Record::Positive(Arc::new(vec![3.into(), "c".into()])),
+                   Record::Positive(Arc::new(vec![4.into(), "d".into()]))]);
+        w.swap();
+        r.find_and(&3.into(), |_| ()).unwrap();
+        r.find_and(&4.into(), |_| ()).unwrap();
+        r.find_and(&4.into(), |_| ()).unwrap();
+
+        assert_eq!(r.reads(&4.into()),
+                   KeyReads {
+                       reads: 2,
+                       last_read: 3,
+                   });
+        assert_eq!(w.eviction_candidates(10),
+                   vec![2.into(), 1.into(), 3.into(), 4.into()]);
+        assert_eq!(w.eviction_candidates(1), vec![2.into()]);
+
+        // keys whose rows have all been removed are forgotten
+        w.add(vec![Record::Negative(Arc::new(vec![3.into(), "c".into()]))]);
+        w.swap();
+        assert_eq!(r.reads(&3.into()), KeyReads::default());
+        assert_eq!(w.eviction_candidates(10), vec![2.into(), 1.into(), 4.into()]);
+    }
+
+    #[test]
+    fn it_reads_earlier_versions() {
+        let a = Arc::new(vec![1.into(), "a".into()]);
+        let b = Arc::new(vec![1.into(), "b".into()]);
+        let c = Arc::new(vec![1.into(), "c".into()]);
+        let names = |rs: &[Row]| {
+            let mut names: Vec<String> = rs.iter().map(|r| r[1].clone().into()).collect();
+            names.sort();
+            names
+        };
+
+        let (r, mut w) = new(2, 0);
+        w.keep_versions(2);
+        w.update_ts(1);
+        w.add(vec![Record::Positive(a.clone())]);
+        w.swap();
+        w.update_ts(2);
+        w.add(vec![Record::Negative(a.clone()), Record::Positive(b.clone())]);
+        w.swap();
+        w.update_ts(3);
+        w.add(vec![Record::Positive(c.clone())]);
+        w.swap();
+
+        assert_eq!(r.find_at_and(&a[0], 1, &names), Ok(Some(vec!["a".to_owned()])));
+        assert_eq!(r.find_at_and(&a[0], 2, &names), Ok(Some(vec!["b".to_owned()])));
+        assert_eq!(r.find_at_and(&a[0], 3, &names),
+                   Ok(Some(vec!["b".to_owned(), "c".to_owned()])));
+        assert_eq!(r.find_at_and(&a[0], 4, &names), Ok(None));
+
+        // removals by two more timestamps push the first one out of the history
+        w.update_ts(4);
+        w.add(vec![Record::Negative(b.clone())]);
+        w.swap();
+        w.update_ts(5);
+        w.add(vec![Record::Negative(c.clone())]);
+        w.swap();
+        assert_eq!(r.find_at_and(&a[0], 1, &names), Err(()));
+        assert_eq!(r.find_at_and(&a[0], 2, &names),
+                   Ok(Some(vec!["b".to_owned()])));
+        assert_eq!(r.find_at_and(&a[0], 5, &names), Ok(Some(vec![])));
+
+        // without versions, only reads as of the latest removal can be answered
+        let (r, mut w) = new(2, 0);
+        w.update_ts(1);
+        w.add(vec![Record::Positive(a.clone()), Record::Positive(b.clone())]);
+        w.swap();
+        w.update_ts(2);
+        w.add(vec![Record::Negative(a.clone())]);
+        w.swap();
+        w.update_ts(3);
+        w.add(vec![Record::Positive(c.clone())]);
+        w.swap();
+        assert_eq!(r.find_at_and(&a[0], 1, &names), Err(()));
+        assert_eq!(r.find_at_and(&a[0], 2, &names), Ok(Some(vec!["b".to_owned()])));
+    }
 }
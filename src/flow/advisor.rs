@@ -0,0 +1,342 @@
+//! Recommendations for reconfiguring a running graph, based on how busy its domains have been.
+
+use petgraph;
+
+use std::collections::{HashMap, VecDeque};
+use std::time;
+
+use flow::prelude::*;
+use flow::domain;
+use flow::node::Type;
+use flow::statistics::GraphStats;
+
+/// A concrete change to a running graph that the `Advisor` recommends.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recommendation {
+    /// Split the given domain in two, moving the given nodes (the ones that account for the bulk
+    /// of the domain's processing time) into a new domain.
+    SplitDomain {
+        /// The overloaded domain.
+        domain: domain::Index,
+        /// The nodes to move out of it.
+        nodes: Vec<NodeAddress>,
+    },
+    /// Shard the given node by the given column, since it alone keeps its domain busy.
+    ShardNode {
+        /// The node to shard.
+        node: NodeAddress,
+        /// The column to shard it by.
+        column: usize,
+    },
+    /// Add a replica of the reader for the given node in a domain of its own, so that reads of it
+    /// no longer compete with the writes to its overloaded domain.
+    AddReaderReplica {
+        /// The node whose reader should be replicated.
+        node: NodeAddress,
+    },
+}
+
+/// How busy a domain has been over the `Advisor`'s window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DomainLoad {
+    /// The fraction of wall-clock time the domain spent using the CPU.
+    pub cpu: f64,
+    /// The fraction of wall-clock time the domain did not spend waiting for input.
+    ///
+    /// A domain that never waits always has packets queued up, so this is a measure of how deep
+    /// its input queue tends to be: close to 1 means the queue never drains.
+    pub busy: f64,
+}
+
+/// An `AdvisorPolicy` determines when the `Advisor` considers a domain overloaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdvisorPolicy {
+    /// The number of most recent observations to base recommendations on.
+    pub window: usize,
+    /// The CPU utilization above which a domain is considered overloaded.
+    pub cpu: f64,
+    /// The fraction of busy time above which a domain is considered overloaded.
+    pub busy: f64,
+}
+
+impl Default for AdvisorPolicy {
+    fn default() -> Self {
+        AdvisorPolicy {
+            window: 10,
+            cpu: 0.8,
+            busy: 0.9,
+        }
+    }
+}
+
+struct Sample {
+    at: time::Instant,
+    // for each domain, its CPU time, its waiting time, and the CPU time of each of its nodes
+    domains: HashMap<domain::Index, (u64, u64, HashMap<NodeAddress, u64>)>,
+}
+
+/// An `Advisor` collects statistics about a running graph over time, and recommends changes to
+/// the graph that would relieve its most overloaded domains.
+///
+/// Statistics are collected by passing the result of every call to `Blender::get_statistics` to
+/// `observe`, and recommendations for the graph are given by `Blender::recommendations`. Nothing
+/// is changed automatically.
+pub struct Advisor {
+    policy: AdvisorPolicy,
+    samples: VecDeque<Sample>,
+}
+
+impl Default for Advisor {
+    fn default() -> Self {
+        Advisor::new(AdvisorPolicy::default())
+    }
+}
+
+impl Advisor {
+    /// Create a new advisor that follows the given policy.
+    pub fn new(policy: AdvisorPolicy) -> Self {
+        assert!(policy.window >= 2, "the advisor needs at least two observations to compare");
+        Advisor {
+            policy: policy,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record a new set of statistics about the graph.
+    pub fn observe(&mut self, stats: &GraphStats) {
+        self.observe_at(stats, time::Instant::now())
+    }
+
+    fn observe_at(&mut self, stats: &GraphStats, at: time::Instant) {
+        let domains = stats.domains
+            .iter()
+            .map(|(&d, &(ref ds, ref ns))| {
+                let nodes = ns.iter().map(|(&n, s)| (n, s.process_ptime)).collect();
+                (d, (ds.total_ptime, ds.wait_time, nodes))
+            })
+            .collect();
+        self.samples.push_back(Sample {
+            at: at,
+            domains: domains,
+        });
+        while self.samples.len() > self.policy.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// How busy each domain has been between the oldest and the newest observation.
+    ///
+    /// Domains that were not part of both observations are left out.
+    pub fn load(&self) -> HashMap<domain::Index, DomainLoad> {
+        let (first, last) = match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) if last.at > first.at => (first, last),
+            _ => return HashMap::new(),
+        };
+        let wall = last.at.duration_since(first.at);
+        let wall = (wall.as_secs() * 1_000_000_000 + wall.subsec_nanos() as u64) as f64;
+
+        last.domains
+            .iter()
+            .filter_map(|(d, &(ptime, wait, _))| {
+                first.domains.get(d).map(|&(ptime0, wait0, _)| {
+                    let load = DomainLoad {
+                        cpu: ptime.saturating_sub(ptime0) as f64 / wall,
+                        busy: 1.0 - (wait.saturating_sub(wait0) as f64 / wall).min(1.0),
+                    };
+                    (*d, load)
+                })
+            })
+            .collect()
+    }
+
+    /// The CPU time spent by each node of the given domain between the oldest and the newest
+    /// observation, busiest first.
+    fn node_times(&self, d: domain::Index) -> Vec<(NodeAddress, u64)> {
+        let first = &self.samples.front().unwrap().domains[&d].2;
+        let last = &self.samples.back().unwrap().domains[&d].2;
+        let mut times: Vec<_> = last.iter()
+            .map(|(n, &t)| (*n, t.saturating_sub(first.get(n).cloned().unwrap_or(0))))
+            .filter(|&(_, t)| t > 0)
+            .collect();
+        times.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.as_global().cmp(&b.0.as_global())));
+        times
+    }
+
+    /// Recommend changes to `graph` that would relieve its overloaded domains.
+    pub fn recommend(&self, graph: &Graph) -> Vec<Recommendation> {
+        let mut load: Vec<_> = self.load()
+            .into_iter()
+            .filter(|&(_, l)| l.cpu >= self.policy.cpu || l.busy >= self.policy.busy)
+            .collect();
+        load.sort_by_key(|&(d, _)| d);
+
+        let mut recommendations = Vec::new();
+        for (d, _) in load {
+            let times = self.node_times(d);
+            let total: u64 = times.iter().map(|&(_, t)| t).sum();
+
+            match times.first() {
+                Some(&(busiest, t)) if t * 2 >= total => {
+                    // a single node does most of the work, so splitting the domain won't help
+                    // much, but sharding that node might
+                    let n = &graph[*busiest.as_global()];
+                    let key = if n.is_internal() {
+                        n.suggest_indexes(busiest)
+                            .remove(&busiest)
+                            .and_then(|cols| cols.into_iter().next())
+                    } else {
+                        None
+                    };
+                    if let Some(column) = key {
+                        recommendations.push(Recommendation::ShardNode {
+                            node: busiest,
+                            column: column,
+                        });
+                    }
+                }
+                Some(_) => {
+                    // move the busiest nodes, up to half of the work, into a domain of their own
+                    let mut moved = 0;
+                    let nodes = times.iter()
+                        .take_while(|&&(_, t)| {
+                            moved += t;
+                            moved * 2 <= total
+                        })
+                        .map(|&(n, _)| n)
+                        .collect();
+                    recommendations.push(Recommendation::SplitDomain {
+                        domain: d,
+                        nodes: nodes,
+                    });
+                }
+                None => {}
+            }
+
+            // readers in an overloaded domain are slow to expose new writes
+            for ni in graph.node_indices() {
+                if graph[ni].assigned_domain() != Some(d) {
+                    continue;
+                }
+                if let Type::Reader(..) = *graph[ni] {
+                    // readers of removed nodes have been disconnected from the graph
+                    let mut parents =
+                        graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming);
+                    if let Some(parent) = parents.next() {
+                        recommendations.push(Recommendation::AddReaderReplica {
+                            node: NodeAddress::make_global(parent),
+                        });
+                    }
+                }
+            }
+        }
+        recommendations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time;
+
+    use flow::domain;
+    use flow::node::Type;
+    use flow::prelude::*;
+    use flow::statistics::{DomainStats, GraphStats, NodeStats};
+    use ops::base::Base;
+    use ops::identity::Identity;
+
+    // a base, two identities below it, and a reader for the first identity, all in one domain
+    fn graph() -> (Graph, Vec<NodeAddress>) {
+        let mut g = Graph::new();
+        let add = |g: &mut Graph, n: Node, parent: Option<NodeAddress>| {
+            let ni = g.add_node(n);
+            g[ni].add_to(0.into());
+            if let Some(p) = parent {
+                g.add_edge(*p.as_global(), ni, false);
+            }
+            NodeAddress::make_global(ni)
+        };
+
+        let b = add(&mut g, Node::new("b", &["x"], Base::new(vec![0]).into()), None);
+        let i1 = add(&mut g, Node::new("i1", &["x"], Identity::new(b).into()), Some(b));
+        let i2 = add(&mut g, Node::new("i2", &["x"], Identity::new(b).into()), Some(b));
+        add(&mut g,
+            Node::new("r", &["x"], Type::Reader(None, Default::default())),
+            Some(i1));
+        (g, vec![b, i1, i2])
+    }
+
+    fn stats(ptime: u64, wait: u64, nodes: &[(NodeAddress, u64)]) -> GraphStats {
+        let nodes = nodes.iter()
+            .map(|&(n, t)| {
+                (n,
+                 NodeStats {
+                     process_time: t,
+                     process_ptime: t,
+                 })
+            })
+            .collect();
+        let ds = DomainStats {
+            total_time: ptime + wait,
+            total_ptime: ptime,
+            wait_time: wait,
+        };
+        GraphStats {
+            domains: Some((domain::Index::from(0), (ds, nodes))).into_iter().collect(),
+            replays: vec![],
+        }
+    }
+
+    fn advise(nodes: &[NodeAddress], ptime: u64, wait: u64, times: &[u64]) -> Advisor {
+        let before: Vec<_> = nodes.iter().map(|&n| (n, 0)).collect();
+        let after: Vec<_> = nodes.iter().cloned().zip(times.iter().cloned()).collect();
+
+        let mut a = Advisor::default();
+        let start = time::Instant::now();
+        a.observe_at(&stats(0, 0, &before[..]), start);
+        a.observe_at(&stats(ptime, wait, &after[..]),
+                     start + time::Duration::from_secs(1));
+        a
+    }
+
+    #[test]
+    fn it_measures_load() {
+        let (_, nodes) = graph();
+        let a = advise(&nodes[..], 900_000_000, 50_000_000, &[0, 0, 0]);
+        let load = a.load()[&domain::Index::from(0)];
+        assert!((load.cpu - 0.9).abs() < 1e-9);
+        assert!((load.busy - 0.95).abs() < 1e-9);
+    }
+
+    #[test]
+    fn it_recommends_sharding() {
+        let (g, nodes) = graph();
+        let a = advise(&nodes[..], 900_000_000, 0, &[800, 100, 100]);
+        assert_eq!(a.recommend(&g),
+                   vec![Recommendation::ShardNode {
+                            node: nodes[0],
+                            column: 0,
+                        },
+                        Recommendation::AddReaderReplica { node: nodes[1] }]);
+    }
+
+    #[test]
+    fn it_recommends_splitting() {
+        let (g, nodes) = graph();
+        let a = advise(&nodes[..], 900_000_000, 0, &[300, 300, 400]);
+        assert_eq!(a.recommend(&g),
+                   vec![Recommendation::SplitDomain {
+                            domain: 0.into(),
+                            nodes: vec![nodes[2]],
+                        },
+                        Recommendation::AddReaderReplica { node: nodes[1] }]);
+    }
+
+    #[test]
+    fn it_leaves_idle_domains_alone() {
+        let (g, nodes) = graph();
+        let a = advise(&nodes[..], 100_000_000, 800_000_000, &[800, 100, 100]);
+        assert!(a.recommend(&g).is_empty());
+    }
+}
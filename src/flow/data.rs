@@ -1,15 +1,28 @@
 #[cfg(feature="web")]
 use rustc_serialize::json::{ToJson, Json};
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Div, Mul, Sub};
+use std::sync::Arc;
 
 use arccstr::ArcCStr;
+use chrono::{DateTime, TimeZone};
+use chrono::naive::date::NaiveDate;
+use chrono::naive::datetime::NaiveDateTime;
 
 /// The main type used for user data throughout the codebase.
 ///
 /// Having this be an enum allows for our code to be agnostic about the types of user data except
 /// when type information is specifically necessary.
-#[derive(Eq, PartialOrd, Ord, Hash, Debug, Clone)]
-#[cfg_attr(feature="b_netsoup", derive(Serialize, Deserialize))]
+///
+/// `Int` and `BigInt` are two representations of the same integers, and compare and hash the same.
+/// Values of all other variants are distinct from each other; in particular, `None` is only equal
+/// to itself, and booleans are not integers. Across types, values are ordered as `None`, booleans,
+/// numbers, timestamps, strings, and then byte strings. Numbers are ordered by value, with integers
+/// before reals of the same value, and strings by their contents, however they are represented.
+#[derive(Eq, Debug, Clone)]
+#[cfg_attr(feature="netsoup", derive(Serialize, Deserialize))]
 pub enum DataType {
     /// An empty value.
     None,
@@ -18,24 +31,39 @@ pub enum DataType {
     /// A 64-bit numeric value.
     BigInt(i64),
     /// A fixed point real value.
-    Real((i32, i16)),
+    ///
+    /// The first field is the integral part, and the second the fractional part in billionths.
+    /// Both parts have the same sign, and the fractional part is always less than one billion in
+    /// magnitude.
+    Real(i64, i32),
     /// A reference-counted string-like value.
     Text(ArcCStr),
     /// A tiny string that fits in a pointer
     TinyText([u8; 8]),
+    /// A boolean value.
+    Bool(bool),
+    /// A date and time, without a time zone.
+    Timestamp(NaiveDateTime),
+    /// A reference-counted string of arbitrary bytes.
+    ///
+    /// Operators pass bytes through without interpreting them, but they can be compared, and so
+    /// can be used as keys.
+    Bytes(Arc<Vec<u8>>),
 }
 
 #[cfg(feature="web")]
 impl ToJson for DataType {
     fn to_json(&self) -> Json {
-        use std::str::FromStr;
         match *self {
             DataType::None => Json::Null,
             DataType::Int(n) => Json::I64(n as i64),
             DataType::BigInt(n) => Json::I64(n),
-            DataType::Real((i, f)) => Json::F64(f64::from_str(&format!("{}.{}", i, f)).unwrap()),
+            DataType::Real(..) => Json::F64(self.into()),
             DataType::Text(..) |
             DataType::TinyText(..) => Json::String(self.into()),
+            DataType::Bool(b) => Json::Boolean(b),
+            DataType::Timestamp(ref t) => Json::String(t.to_string()),
+            DataType::Bytes(ref b) => Json::String(hex(&b[..])),
         }
     }
 }
@@ -49,15 +77,114 @@ impl PartialEq for DataType {
             (&DataType::Int(ref a), &DataType::BigInt(ref b)) => *a as i64 == *b,
             (&DataType::BigInt(ref a), &DataType::Int(ref b)) => *a == *b as i64,
             (&DataType::BigInt(ref a), &DataType::BigInt(ref b)) => a == b,
-            (&DataType::Real((ref ai, ref af)), &DataType::Real((ref bi, ref bf))) => {
+            (&DataType::Real(ref ai, ref af), &DataType::Real(ref bi, ref bf)) => {
                 ai == bi && af == bf
             }
+            (&DataType::Bool(ref a), &DataType::Bool(ref b)) => a == b,
+            (&DataType::Timestamp(ref a), &DataType::Timestamp(ref b)) => a == b,
+            (&DataType::Bytes(ref a), &DataType::Bytes(ref b)) => a == b,
             (&DataType::None, &DataType::None) => true,
             _ => false,
         }
     }
 }
 
+impl Hash for DataType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // values that are equal must hash the same, so integers are always hashed as i64
+        self.rank().hash(state);
+        match *self {
+            DataType::None => (),
+            DataType::Bool(b) => b.hash(state),
+            DataType::Int(n) => (n as i64).hash(state),
+            DataType::BigInt(n) => n.hash(state),
+            DataType::Real(i, frac) => (i, frac).hash(state),
+            DataType::Timestamp(ref t) => t.hash(state),
+            DataType::Bytes(ref b) => b.hash(state),
+            DataType::Text(ref t) => t.hash(state),
+            DataType::TinyText(ref t) => t.hash(state),
+        }
+    }
+}
+
+impl Ord for DataType {
+    fn cmp(&self, other: &DataType) -> Ordering {
+        match (self, other) {
+            (&DataType::Bool(ref a), &DataType::Bool(ref b)) => a.cmp(b),
+            (&DataType::Real(ref ai, ref af), &DataType::Real(ref bi, ref bf)) => {
+                (ai, af).cmp(&(bi, bf))
+            }
+            (&DataType::Timestamp(ref a), &DataType::Timestamp(ref b)) => a.cmp(b),
+            (&DataType::Bytes(ref a), &DataType::Bytes(ref b)) => a.cmp(b),
+            (&DataType::Text(ref a), &DataType::Text(ref b)) => a.cmp(b),
+            (&DataType::TinyText(ref a), &DataType::TinyText(ref b)) => a.cmp(b),
+            (&DataType::Text(..), &DataType::TinyText(..)) |
+            (&DataType::TinyText(..), &DataType::Text(..)) => {
+                let (a, b): (Cow<str>, Cow<str>) = (self.into(), other.into());
+                a.cmp(&b)
+            }
+            _ if self.is_number() && other.is_number() => {
+                // integers and reals are never equal, so break ties between them by type
+                self.parts().cmp(&other.parts()).then(self.rank().cmp(&other.rank()))
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for DataType {
+    fn partial_cmp(&self, other: &DataType) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl From<bool> for DataType {
+    fn from(b: bool) -> Self {
+        DataType::Bool(b)
+    }
+}
+
+impl From<Vec<u8>> for DataType {
+    fn from(b: Vec<u8>) -> Self {
+        DataType::Bytes(Arc::new(b))
+    }
+}
+
+impl<'a> From<&'a [u8]> for DataType {
+    fn from(b: &'a [u8]) -> Self {
+        DataType::from(b.to_vec())
+    }
+}
+
+impl From<NaiveDateTime> for DataType {
+    fn from(t: NaiveDateTime) -> Self {
+        DataType::Timestamp(t)
+    }
+}
+
+/// A date is the timestamp of its midnight.
+impl From<NaiveDate> for DataType {
+    fn from(d: NaiveDate) -> Self {
+        DataType::Timestamp(d.and_hms(0, 0, 0))
+    }
+}
+
+/// Times with a time zone are stored as the corresponding time in UTC.
+impl<Tz: TimeZone> From<DateTime<Tz>> for DataType {
+    fn from(t: DateTime<Tz>) -> Self {
+        DataType::Timestamp(t.naive_utc())
+    }
+}
+
+impl<'a> Into<NaiveDateTime> for &'a DataType {
+    fn into(self) -> NaiveDateTime {
+        match *self {
+            DataType::Timestamp(t) => t,
+            _ => unreachable!("cannot convert {} to a timestamp", self),
+        }
+    }
+}
+
 impl From<i64> for DataType {
     fn from(s: i64) -> Self {
         DataType::BigInt(s)
@@ -70,13 +197,153 @@ impl From<i32> for DataType {
     }
 }
 
+const FRACTION: i64 = 1_000_000_000;
+
 impl From<f64> for DataType {
     fn from(f: f64) -> Self {
-        if f.is_nan() {
-            panic!();
+        assert!(f.is_finite(), "cannot represent {} as a real value", f);
+        let i = f.trunc();
+        let frac = ((f - i) * FRACTION as f64).round() as i64;
+        // rounding the fraction may carry over into the integral part
+        DataType::real(i as i64, frac)
+    }
+}
+
+impl<'a> Into<f64> for &'a DataType {
+    fn into(self) -> f64 {
+        match *self {
+            DataType::Int(n) => n as f64,
+            DataType::BigInt(n) => n as f64,
+            DataType::Real(i, frac) => i as f64 + frac as f64 / FRACTION as f64,
+            DataType::Bool(b) => if b { 1.0 } else { 0.0 },
+            _ => unreachable!("cannot convert non-numeric value to a real value"),
+        }
+    }
+}
+
+impl Into<f64> for DataType {
+    fn into(self) -> f64 {
+        (&self).into()
+    }
+}
+
+impl DataType {
+    /// Construct a normalized real value from an integral part and a fractional part given in
+    /// billionths. The fractional part may be of any magnitude and sign.
+    fn real(i: i64, frac: i64) -> DataType {
+        let mut i = i + frac / FRACTION;
+        let mut frac = frac % FRACTION;
+        if i > 0 && frac < 0 {
+            i -= 1;
+            frac += FRACTION;
+        } else if i < 0 && frac > 0 {
+            i += 1;
+            frac -= FRACTION;
+        }
+        DataType::Real(i, frac as i32)
+    }
+
+    /// The integral and fractional parts of a numeric value, as used by `DataType::Real`.
+    ///
+    /// Booleans count as 0 or 1, so that, for example, summing a boolean column counts the rows
+    /// for which it is true.
+    fn parts(&self) -> (i64, i64) {
+        match *self {
+            DataType::Bool(b) => (b as i64, 0),
+            DataType::Int(n) => (n as i64, 0),
+            DataType::BigInt(n) => (n, 0),
+            DataType::Real(i, frac) => (i, frac as i64),
+            _ => unreachable!("cannot do arithmetic on non-numeric value {}", self),
+        }
+    }
+
+    /// True for integers and reals.
+    fn is_number(&self) -> bool {
+        match *self {
+            DataType::Int(..) |
+            DataType::BigInt(..) |
+            DataType::Real(..) => true,
+            _ => false,
+        }
+    }
+
+    /// The position of the value's type in the order of values of different types.
+    fn rank(&self) -> u8 {
+        match *self {
+            DataType::None => 0,
+            DataType::Bool(..) => 1,
+            DataType::Int(..) |
+            DataType::BigInt(..) => 2,
+            DataType::Real(..) => 3,
+            DataType::Timestamp(..) => 4,
+            DataType::Text(..) => 5,
+            DataType::TinyText(..) => 6,
+            DataType::Bytes(..) => 7,
+        }
+    }
+}
+
+/// Numeric values can be added. Adding two integers produces a `BigInt`, and adding a real value
+/// to anything produces a `Real`. Addition of real values is exact.
+impl<'a, 'b> Add<&'b DataType> for &'a DataType {
+    type Output = DataType;
+
+    fn add(self, other: &'b DataType) -> DataType {
+        match (self, other) {
+            (&DataType::Real(..), _) |
+            (_, &DataType::Real(..)) => {
+                let ((ai, af), (bi, bf)) = (self.parts(), other.parts());
+                DataType::real(ai + bi, af + bf)
+            }
+            _ => DataType::BigInt(self.parts().0 + other.parts().0),
+        }
+    }
+}
+
+/// Numeric values can be subtracted, following the same rules as for addition.
+impl<'a, 'b> Sub<&'b DataType> for &'a DataType {
+    type Output = DataType;
+
+    fn sub(self, other: &'b DataType) -> DataType {
+        match (self, other) {
+            (&DataType::Real(..), _) |
+            (_, &DataType::Real(..)) => {
+                let ((ai, af), (bi, bf)) = (self.parts(), other.parts());
+                DataType::real(ai - bi, af - bf)
+            }
+            _ => DataType::BigInt(self.parts().0 - other.parts().0),
+        }
+    }
+}
+
+/// Numeric values can be multiplied. Multiplying two integers produces a `BigInt`; if either value
+/// is real, the product is computed in floating point, and is a `Real`.
+impl<'a, 'b> Mul<&'b DataType> for &'a DataType {
+    type Output = DataType;
+
+    fn mul(self, other: &'b DataType) -> DataType {
+        match (self, other) {
+            (&DataType::Real(..), _) |
+            (_, &DataType::Real(..)) => {
+                let (a, b): (f64, f64) = (self.into(), other.into());
+                DataType::from(a * b)
+            }
+            _ => DataType::BigInt(self.parts().0 * other.parts().0),
+        }
+    }
+}
+
+/// Numeric values can be divided. The quotient is computed in floating point, and is always a
+/// `Real`, even for two integers. Dividing by zero produces `DataType::None`.
+impl<'a, 'b> Div<&'b DataType> for &'a DataType {
+    type Output = DataType;
+
+    fn div(self, other: &'b DataType) -> DataType {
+        let (a, b): (f64, f64) = (self.into(), other.into());
+        if b == 0.0 {
+            DataType::None
         } else {
-            let (i, f, s) = f.integer_decode();
-            DataType::Real(((s as i64 * i as i64) as i32, f))
+            DataType::from(a / b)
         }
     }
 }
@@ -157,9 +424,192 @@ impl fmt::Display for DataType {
                 let text: Cow<str> = self.into();
                 write!(f, "\"{}\"", text)
             }
+            DataType::Bool(b) => write!(f, "{}", b),
+            DataType::Timestamp(ref t) => write!(f, "{}", t),
+            DataType::Bytes(ref b) => write!(f, "x'{}'", hex(&b[..])),
             DataType::Int(n) => write!(f, "{}", n),
             DataType::BigInt(n) => write!(f, "{}", n),
-            DataType::Real((i, frac)) => write!(f, "{}", format!("{}.{}", i, frac)),
+            DataType::Real(i, frac) => {
+                let sign = if i < 0 || frac < 0 { "-" } else { "" };
+                write!(f, "{}{}.{:09}", sign, i.abs(), frac.abs())
+            }
+        }
+    }
+}
+
+/// The bytes in `b` as lowercase hexadecimal digits.
+pub fn hex(b: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut s = String::with_capacity(2 * b.len());
+    for byte in b {
+        write!(s, "{:02x}", byte).unwrap();
+    }
+    s
+}
+
+/// The type of the values in a column.
+///
+/// A column of any type may also hold `DataType::None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnType {
+    /// Integers, held as `DataType::Int` or `DataType::BigInt`.
+    Int,
+    /// Real numbers, held as `DataType::Real`.
+    Real,
+    /// Strings, held as `DataType::Text` or `DataType::TinyText`.
+    Text,
+    /// Booleans, held as `DataType::Bool`.
+    Bool,
+    /// Dates and times, held as `DataType::Timestamp`.
+    Timestamp,
+    /// Binary data, held as `DataType::Bytes`.
+    Bytes,
+}
+
+impl ColumnType {
+    /// The type of the given value, or `None` for `DataType::None`.
+    pub fn of(v: &DataType) -> Option<ColumnType> {
+        match *v {
+            DataType::None => None,
+            DataType::Int(..) |
+            DataType::BigInt(..) => Some(ColumnType::Int),
+            DataType::Real(..) => Some(ColumnType::Real),
+            DataType::Text(..) |
+            DataType::TinyText(..) => Some(ColumnType::Text),
+            DataType::Bool(..) => Some(ColumnType::Bool),
+            DataType::Timestamp(..) => Some(ColumnType::Timestamp),
+            DataType::Bytes(..) => Some(ColumnType::Bytes),
+        }
+    }
+
+    /// The type that values of both this type and `other` can be coerced to, if any.
+    pub fn unify(self, other: ColumnType) -> Option<ColumnType> {
+        match (self, other) {
+            (a, b) if a == b => Some(a),
+            (ColumnType::Int, ColumnType::Real) |
+            (ColumnType::Real, ColumnType::Int) => Some(ColumnType::Real),
+            _ => None,
+        }
+    }
+
+    /// Convert `v` to a value of this type, if that can be done without losing information.
+    pub fn coerce(self, v: &DataType) -> Option<DataType> {
+        match (self, v) {
+            (_, &DataType::None) => Some(DataType::None),
+            (ColumnType::Real, &DataType::Int(n)) => Some(DataType::Real(n as i64, 0)),
+            (ColumnType::Real, &DataType::BigInt(n)) => Some(DataType::Real(n, 0)),
+            _ if ColumnType::of(v) == Some(self) => Some(v.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColumnType::Int => write!(f, "INT"),
+            ColumnType::Real => write!(f, "REAL"),
+            ColumnType::Text => write!(f, "TEXT"),
+            ColumnType::Bool => write!(f, "BOOL"),
+            ColumnType::Timestamp => write!(f, "TIMESTAMP"),
+            ColumnType::Bytes => write!(f, "BLOB"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::naive::date::NaiveDate;
+    use std::cmp::Ordering;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(d: &DataType) -> u64 {
+        let mut h = DefaultHasher::new();
+        d.hash(&mut h);
+        h.finish()
+    }
+
+    #[test]
+    fn it_hashes_equal_values_the_same() {
+        let (a, b) = (DataType::Int(3), DataType::BigInt(3));
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(a.cmp(&b), Ordering::Equal);
+    }
+
+    #[test]
+    fn it_keeps_types_distinct() {
+        let one: DataType = 1.into();
+        assert!(DataType::Bool(true) != one);
+        assert!(DataType::Bool(false) != DataType::None);
+        assert!(DataType::Int(0) != DataType::None);
+        assert!(DataType::Real(1, 0) != one);
+    }
+
+    #[test]
+    fn it_compares_bytes() {
+        let a: DataType = vec![0u8, 0xff].into();
+        let b: DataType = (&[0u8, 0xff][..]).into();
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        assert!(a < DataType::from(vec![1u8]));
+        // bytes are never equal to strings, even with the same contents
+        assert!(DataType::from(&b"ab"[..]) != DataType::from("ab"));
+        assert_eq!(a.to_string(), "x'00ff'");
+    }
+
+    #[test]
+    fn it_orders() {
+        let mut vs: Vec<DataType> = vec!["a".into(),
+                                         NaiveDate::from_ymd(2017, 1, 1).into(),
+                                         2.5f64.into(),
+                                         DataType::BigInt(2),
+                                         true.into(),
+                                         DataType::Int(-1),
+                                         false.into(),
+                                         DataType::None];
+        vs.sort();
+        let expected: Vec<DataType> = vec![DataType::None,
+                                           false.into(),
+                                           true.into(),
+                                           DataType::Int(-1),
+                                           DataType::BigInt(2),
+                                           2.5f64.into(),
+                                           NaiveDate::from_ymd(2017, 1, 1).into(),
+                                           "a".into()];
+        assert_eq!(vs, expected);
+    }
+
+    #[test]
+    fn it_orders_numbers_by_value() {
+        assert!(DataType::Int(100) > DataType::from(0.5f64));
+        assert!(DataType::BigInt(-1) < DataType::from(-0.5f64));
+        assert!(DataType::from(2.5f64) < DataType::Int(3));
+        assert!(DataType::from(-1.5f64) < DataType::from(-1.2f64));
+        // integers and reals with the same value are not equal, but order next to each other
+        let (one, real_one) = (DataType::Int(1), DataType::Real(1, 0));
+        assert_eq!(one.cmp(&real_one), Ordering::Less);
+        assert!(real_one < DataType::from(1.5f64));
+    }
+
+    #[test]
+    fn it_orders_strings_by_contents() {
+        let (long, short): (DataType, DataType) = ("zzzzzzzzzz".into(), "a".into());
+        match (&long, &short) {
+            (&DataType::Text(..), &DataType::TinyText(..)) => {}
+            _ => unreachable!("expected one string of each representation"),
         }
+        assert!(long > short);
+        assert!(DataType::from("aaaaaaaaaa") < DataType::from("b"));
+        assert!(DataType::from("abcdefgh") < DataType::from("abcdefghi"));
+
+        let mut vs: Vec<DataType> = vec!["zzzzzzzzzz".into(), "b".into(), "aaaaaaaaaa".into()];
+        vs.sort();
+        let expected: Vec<DataType> = vec!["aaaaaaaaaa".into(), "b".into(), "zzzzzzzzzz".into()];
+        assert_eq!(vs, expected);
     }
 }
@@ -0,0 +1,153 @@
+//! Structural summaries of the data flow graph, and differences between them.
+//!
+//! A `GraphSummary` captures the shape of the graph at some point in time: which nodes exist,
+//! what they compute, which domains they are assigned to, how they are connected, and which
+//! indices their materialized state has. Comparing
+//! the summary taken before a migration with one taken from the `Migration` itself (before it is
+//! committed) yields a `GraphDiff` describing what that migration would change, which is useful
+//! for reviewing changes to an application's queries before deploying them.
+
+use petgraph::graph::NodeIndex;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use flow::domain;
+use flow::node;
+use flow::prelude::*;
+use flow::NodeAddress;
+
+/// A description of a single node in the graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeSummary {
+    /// The node's name.
+    pub name: String,
+    /// What the node computes (e.g., `"internal ⋈ node"` or `"reader node"`).
+    pub kind: String,
+    /// The names of the node's output columns.
+    pub fields: Vec<String>,
+    /// The domain the node is assigned to, if it has been assigned one yet.
+    pub domain: Option<usize>,
+    /// For reader nodes, the column the reader is keyed on.
+    pub key: Option<usize>,
+}
+
+/// A description of the structure of an entire graph.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphSummary {
+    /// All nodes in the graph.
+    pub nodes: BTreeMap<NodeAddress, NodeSummary>,
+    /// All edges in the graph, as `(parent, child)` pairs.
+    pub edges: BTreeSet<(NodeAddress, NodeAddress)>,
+    /// All indices on materialized state, as the node holding the state along with the columns
+    /// the index is keyed on.
+    pub indexes: BTreeSet<(NodeAddress, Vec<usize>)>,
+}
+
+/// The structural difference between two `GraphSummary`s.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphDiff {
+    /// Nodes that only exist in the newer graph.
+    pub added_nodes: Vec<(NodeAddress, NodeSummary)>,
+    /// Nodes that only exist in the older graph.
+    pub removed_nodes: Vec<(NodeAddress, NodeSummary)>,
+    /// Nodes that exist in both graphs, but whose description differs, as `(old, new)`.
+    pub changed_nodes: Vec<(NodeAddress, NodeSummary, NodeSummary)>,
+    /// Edges that only exist in the newer graph.
+    pub added_edges: Vec<(NodeAddress, NodeAddress)>,
+    /// Edges that only exist in the older graph.
+    pub removed_edges: Vec<(NodeAddress, NodeAddress)>,
+    /// Indices that only exist in the newer graph.
+    pub added_indexes: Vec<(NodeAddress, Vec<usize>)>,
+    /// Indices that only exist in the older graph.
+    pub removed_indexes: Vec<(NodeAddress, Vec<usize>)>,
+}
+
+impl GraphDiff {
+    /// Returns true if the two graphs were structurally identical.
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty() && self.removed_nodes.is_empty() &&
+        self.changed_nodes.is_empty() && self.added_edges.is_empty() &&
+        self.removed_edges.is_empty() && self.added_indexes.is_empty() &&
+        self.removed_indexes.is_empty()
+    }
+}
+
+impl GraphSummary {
+    /// Compute the changes needed to go from this graph to `after`.
+    pub fn diff(&self, after: &GraphSummary) -> GraphDiff {
+        let mut diff = GraphDiff::default();
+        for (ni, n) in &after.nodes {
+            match self.nodes.get(ni) {
+                None => diff.added_nodes.push((*ni, n.clone())),
+                Some(old) if old != n => diff.changed_nodes.push((*ni, old.clone(), n.clone())),
+                Some(_) => (),
+            }
+        }
+        diff.removed_nodes = self.nodes
+            .iter()
+            .filter(|&(ni, _)| !after.nodes.contains_key(ni))
+            .map(|(ni, n)| (*ni, n.clone()))
+            .collect();
+        diff.added_edges = after.edges.difference(&self.edges).cloned().collect();
+        diff.removed_edges = self.edges.difference(&after.edges).cloned().collect();
+        diff.added_indexes = after.indexes.difference(&self.indexes).cloned().collect();
+        diff.removed_indexes = self.indexes.difference(&after.indexes).cloned().collect();
+        diff
+    }
+}
+
+/// Summarize the given graph.
+///
+/// `pending` holds the domain assignments of nodes that have been added by an ongoing migration,
+/// but whose domains have not yet been set on the nodes themselves. Nodes that have not been
+/// assigned a domain at all are summarized with `domain: None`. `indexes` holds the indices of
+/// every node with materialized state. Nodes in `removed`, and the edges and indices that touch
+/// them, are left out of the summary.
+pub fn summarize(graph: &Graph,
+                 source: NodeIndex,
+                 pending: &HashMap<NodeIndex, Option<domain::Index>>,
+                 indexes: &HashMap<NodeIndex, Vec<Vec<usize>>>,
+                 removed: &HashSet<NodeIndex>)
+                 -> GraphSummary {
+    let addr = |ni: NodeIndex| NodeAddress::make_global(ni);
+
+    let nodes = graph.node_indices()
+        .filter(|&ni| ni != source)
+        .filter(|ni| !removed.contains(ni))
+        .map(|ni| {
+            let n = &graph[ni];
+            let domain = pending.get(&ni).and_then(|&d| d).or(n.assigned_domain());
+            let key = if let node::Type::Reader(_, ref r) = **n {
+                r.key().ok()
+            } else {
+                None
+            };
+            (addr(ni),
+             NodeSummary {
+                 name: n.name().to_owned(),
+                 kind: format!("{:?}", **n),
+                 fields: n.fields().to_vec(),
+                 domain: domain.map(|d| d.index()),
+                 key: key,
+             })
+        })
+        .collect();
+
+    let edges = graph.raw_edges()
+        .iter()
+        .filter(|e| e.source() != source)
+        .filter(|e| !removed.contains(&e.source()) && !removed.contains(&e.target()))
+        .map(|e| (addr(e.source()), addr(e.target())))
+        .collect();
+
+    let indexes = indexes.iter()
+        .filter(|&(ni, _)| !removed.contains(ni))
+        .flat_map(|(&ni, idxs)| idxs.iter().map(move |idx| (addr(ni), idx.clone())))
+        .collect();
+
+    GraphSummary {
+        nodes: nodes,
+        edges: edges,
+        indexes: indexes,
+    }
+}
@@ -0,0 +1,44 @@
+//! Pinning of domain threads to particular CPU cores.
+//!
+//! Keeping a domain on a single core (and thus a single socket) means that the state it builds up
+//! stays in that core's caches and local memory, rather than bouncing between sockets as the
+//! scheduler moves the thread around.
+
+/// Pin the calling thread to the given core.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> Result<(), String> {
+    // mirrors glibc's cpu_set_t, which has room for 1024 cores
+    const CPU_SETSIZE: usize = 1024;
+    #[repr(C)]
+    struct CpuSet([u64; CPU_SETSIZE / 64]);
+
+    extern "C" {
+        fn sched_setaffinity(pid: i32, cpusetsize: usize, mask: *const CpuSet) -> i32;
+    }
+
+    if core >= CPU_SETSIZE {
+        return Err(format!("cannot pin to core {}; only {} cores are supported",
+                           core,
+                           CPU_SETSIZE));
+    }
+
+    let mut set = CpuSet([0; CPU_SETSIZE / 64]);
+    set.0[core / 64] |= 1 << (core % 64);
+
+    // a pid of 0 refers to the calling thread
+    let r = unsafe { sched_setaffinity(0, ::std::mem::size_of::<CpuSet>(), &set) };
+    if r == 0 {
+        Ok(())
+    } else {
+        Err(format!("failed to pin thread to core {}: {}",
+                    core,
+                    ::std::io::Error::last_os_error()))
+    }
+}
+
+/// Pin the calling thread to the given core.
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(core: usize) -> Result<(), String> {
+    Err(format!("cannot pin thread to core {}; pinning is only supported on Linux",
+                core))
+}
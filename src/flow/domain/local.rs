@@ -94,7 +94,8 @@ impl<T> FromIterator<(LocalNodeIndex, T)> for Map<T> {
     }
 }
 
-use std::collections::hash_map;
+use std::collections::BTreeMap;
+use std::iter;
 use fnv::FnvHashMap;
 use std::hash::Hash;
 use std::sync::Arc;
@@ -107,12 +108,28 @@ pub enum KeyType<'a, T: 'a> {
     Quad((T, T, T, T)),
 }
 
+impl<'a, T: Clone> KeyType<'a, T> {
+    /// The values of the key, in column order.
+    pub fn to_vec(&self) -> Vec<T> {
+        match *self {
+            KeyType::Single(k) => vec![k.clone()],
+            KeyType::Double((ref a, ref b)) => vec![a.clone(), b.clone()],
+            KeyType::Tri((ref a, ref b, ref c)) => vec![a.clone(), b.clone(), c.clone()],
+            KeyType::Quad((ref a, ref b, ref c, ref d)) => {
+                vec![a.clone(), b.clone(), c.clone(), d.clone()]
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 enum KeyedState<T: Eq + Hash> {
     Single(FnvHashMap<T, Vec<Arc<Vec<T>>>>),
     Double(FnvHashMap<(T, T), Vec<Arc<Vec<T>>>>),
     Tri(FnvHashMap<(T, T, T), Vec<Arc<Vec<T>>>>),
     Quad(FnvHashMap<(T, T, T, T), Vec<Arc<Vec<T>>>>),
+    // an ordered index on any number of columns, which also supports range lookups
+    Ordered(BTreeMap<Vec<T>, Vec<Arc<Vec<T>>>>),
 }
 
 impl<'a, T: 'static + Eq + Hash + Clone> From<&'a [T]> for KeyType<'a, T> {
@@ -151,13 +168,14 @@ impl<'a, T: 'static + Eq + Hash + Clone> From<&'a [&'a T]> for KeyType<'a, T> {
     }
 }
 
-impl<T: Eq + Hash> KeyedState<T> {
+impl<T: Eq + Hash + Ord + Clone> KeyedState<T> {
     pub fn is_empty(&self) -> bool {
         match *self {
             KeyedState::Single(ref m) => m.is_empty(),
             KeyedState::Double(ref m) => m.is_empty(),
             KeyedState::Tri(ref m) => m.is_empty(),
             KeyedState::Quad(ref m) => m.is_empty(),
+            KeyedState::Ordered(ref m) => m.is_empty(),
         }
     }
 
@@ -167,6 +185,27 @@ impl<T: Eq + Hash> KeyedState<T> {
             KeyedState::Double(ref m) => m.len(),
             KeyedState::Tri(ref m) => m.len(),
             KeyedState::Quad(ref m) => m.len(),
+            KeyedState::Ordered(ref m) => m.len(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        match *self {
+            KeyedState::Single(ref m) => m.values().map(Vec::len).sum(),
+            KeyedState::Double(ref m) => m.values().map(Vec::len).sum(),
+            KeyedState::Tri(ref m) => m.values().map(Vec::len).sum(),
+            KeyedState::Quad(ref m) => m.values().map(Vec::len).sum(),
+            KeyedState::Ordered(ref m) => m.values().map(Vec::len).sum(),
+        }
+    }
+
+    pub fn values<'a>(&'a self) -> Box<Iterator<Item = &'a Vec<Arc<Vec<T>>>> + 'a> {
+        match *self {
+            KeyedState::Single(ref m) => Box::new(m.values()),
+            KeyedState::Double(ref m) => Box::new(m.values()),
+            KeyedState::Tri(ref m) => Box::new(m.values()),
+            KeyedState::Quad(ref m) => Box::new(m.values()),
+            KeyedState::Ordered(ref m) => Box::new(m.values()),
         }
     }
 
@@ -176,6 +215,7 @@ impl<T: Eq + Hash> KeyedState<T> {
             (&KeyedState::Double(ref m), &KeyType::Double(ref k)) => m.get(k),
             (&KeyedState::Tri(ref m), &KeyType::Tri(ref k)) => m.get(k),
             (&KeyedState::Quad(ref m), &KeyType::Quad(ref k)) => m.get(k),
+            (&KeyedState::Ordered(ref m), k) => m.get(&k.to_vec()),
             _ => unreachable!(),
         }
     }
@@ -205,7 +245,7 @@ impl<T: Hash + Eq + Clone> Default for State<T> {
     }
 }
 
-impl<T: Hash + Eq + Clone> State<T> {
+impl<T: Hash + Eq + Ord + Clone> State<T> {
     /// Construct base materializations differently (potentially)
     pub fn base() -> Self {
         Self::default()
@@ -221,12 +261,44 @@ impl<T: Hash + Eq + Clone> State<T> {
             return;
         }
 
-        if !self.state.is_empty() && !self.state[0].1.is_empty() {
-            // we'd need to *construct* the index!
-            unimplemented!();
+        // the rows we already hold must be found through the new index too
+        let mut state: (Vec<usize>, KeyedState<T>) = (Vec::from(columns), columns.into());
+        for r in self.all_rows() {
+            Self::insert_into(&mut state, r);
         }
+        self.state.push(state);
+    }
+
+    /// Index the state by the given columns with an index that keeps its keys in order, so that
+    /// the rows with keys in a range can be found with `lookup_range`.
+    ///
+    /// The ordered index also answers regular lookups on the same columns, so it replaces any
+    /// hash index on them.
+    pub fn add_ordered_key(&mut self, columns: &[usize]) {
+        let existing = self.state_for(columns);
+        if let Some(i) = existing {
+            if let KeyedState::Ordered(_) = self.state[i].1 {
+                // already keyed
+                return;
+            }
+        }
+
+        let mut state = (Vec::from(columns), KeyedState::Ordered(BTreeMap::new()));
+        for r in self.all_rows() {
+            Self::insert_into(&mut state, r);
+        }
+        match existing {
+            Some(i) => self.state[i] = state,
+            None => self.state.push(state),
+        }
+    }
 
-        self.state.push((Vec::from(columns), columns.into()));
+    /// Whether the state has an ordered index on the given columns (see `add_ordered_key`).
+    pub fn is_ordered(&self, columns: &[usize]) -> bool {
+        match self.state_for(columns).map(|i| &self.state[i].1) {
+            Some(&KeyedState::Ordered(_)) => true,
+            _ => false,
+        }
     }
 
     pub fn keys(&self) -> Vec<Vec<usize>> {
@@ -243,38 +315,45 @@ impl<T: Hash + Eq + Clone> State<T> {
         rclones.push(r);
 
         for s in &mut self.state {
-            let r = rclones.swap_remove(0);
-            match s.1 {
-                KeyedState::Single(ref mut map) => {
-                    // treat this specially to avoid the extra Vec
-                    debug_assert_eq!(s.0.len(), 1);
-                    // i *wish* we could use the entry API here, but it would mean an extra clone
-                    // in the common case of an entry already existing for the given key...
-                    if let Some(ref mut rs) = map.get_mut(&r[s.0[0]]) {
-                        rs.push(r);
-                        return;
-                    }
-                    map.insert(r[s.0[0]].clone(), vec![r]);
+            Self::insert_into(s, rclones.swap_remove(0));
+        }
+    }
+
+    fn insert_into(s: &mut (Vec<usize>, KeyedState<T>), r: Arc<Vec<T>>) {
+        match s.1 {
+            KeyedState::Single(ref mut map) => {
+                // treat this specially to avoid the extra Vec
+                debug_assert_eq!(s.0.len(), 1);
+                // i *wish* we could use the entry API here, but it would mean an extra clone
+                // in the common case of an entry already existing for the given key...
+                if let Some(ref mut rs) = map.get_mut(&r[s.0[0]]) {
+                    rs.push(r);
+                    return;
                 }
-                _ => {
-                    match s.1 {
-                        KeyedState::Double(ref mut map) => {
-                            let key = (r[s.0[0]].clone(), r[s.0[1]].clone());
-                            map.entry(key).or_insert_with(Vec::new).push(r)
-                        }
-                        KeyedState::Tri(ref mut map) => {
-                            let key = (r[s.0[0]].clone(), r[s.0[1]].clone(), r[s.0[2]].clone());
-                            map.entry(key).or_insert_with(Vec::new).push(r)
-                        }
-                        KeyedState::Quad(ref mut map) => {
-                            let key = (r[s.0[0]].clone(),
-                                       r[s.0[1]].clone(),
-                                       r[s.0[2]].clone(),
-                                       r[s.0[3]].clone());
-                            map.entry(key).or_insert_with(Vec::new).push(r)
-                        }
-                        KeyedState::Single(..) => unreachable!(),
+                map.insert(r[s.0[0]].clone(), vec![r]);
+            }
+            _ => {
+                match s.1 {
+                    KeyedState::Double(ref mut map) => {
+                        let key = (r[s.0[0]].clone(), r[s.0[1]].clone());
+                        map.entry(key).or_insert_with(Vec::new).push(r)
+                    }
+                    KeyedState::Tri(ref mut map) => {
+                        let key = (r[s.0[0]].clone(), r[s.0[1]].clone(), r[s.0[2]].clone());
+                        map.entry(key).or_insert_with(Vec::new).push(r)
                     }
+                    KeyedState::Quad(ref mut map) => {
+                        let key = (r[s.0[0]].clone(),
+                                   r[s.0[1]].clone(),
+                                   r[s.0[2]].clone(),
+                                   r[s.0[3]].clone());
+                        map.entry(key).or_insert_with(Vec::new).push(r)
+                    }
+                    KeyedState::Ordered(ref mut map) => {
+                        let key = s.0.iter().map(|&c| r[c].clone()).collect();
+                        map.entry(key).or_insert_with(Vec::new).push(r)
+                    }
+                    KeyedState::Single(..) => unreachable!(),
                 }
             }
         }
@@ -312,6 +391,12 @@ impl<T: Hash + Eq + Clone> State<T> {
                                 rs.retain(|rsr| &rsr[..] != r);
                             }
                         }
+                        KeyedState::Ordered(ref mut map) => {
+                            let key: Vec<_> = s.0.iter().map(|&c| r[c].clone()).collect();
+                            if let Some(ref mut rs) = map.get_mut(&key) {
+                                rs.retain(|rsr| &rsr[..] != r);
+                            }
+                        }
                         KeyedState::Single(..) => unreachable!(),
                     }
                 }
@@ -319,14 +404,12 @@ impl<T: Hash + Eq + Clone> State<T> {
         }
     }
 
-    pub fn iter(&self) -> hash_map::Values<T, Vec<Arc<Vec<T>>>> {
-        for &(_, ref state) in &self.state {
-            if let KeyedState::Single(ref map) = *state {
-                return map.values();
-            }
+    /// The rows held in this state, grouped by their key in one of the state's indices.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Vec<Arc<Vec<T>>>> + 'a> {
+        match self.state.first() {
+            Some(&(_, ref state)) => state.values(),
+            None => Box::new(iter::empty()),
         }
-        // TODO: allow iter without single key (breaks return type)
-        unimplemented!();
     }
 
     pub fn is_empty(&self) -> bool {
@@ -341,6 +424,23 @@ impl<T: Hash + Eq + Clone> State<T> {
         }
     }
 
+    /// The total number of rows held in this state (as opposed to `len`, which counts keys).
+    pub fn rows(&self) -> usize {
+        if self.state.is_empty() {
+            0
+        } else {
+            self.state[0].1.rows()
+        }
+    }
+
+    /// All rows held in this state, regardless of how it is keyed.
+    pub fn all_rows(&self) -> Vec<Arc<Vec<T>>> {
+        if self.state.is_empty() {
+            return Vec::new();
+        }
+        self.state[0].1.values().flat_map(|rs| rs.iter().cloned()).collect()
+    }
+
     pub fn lookup(&self, columns: &[usize], key: &KeyType<T>) -> &[Arc<Vec<T>>] {
         debug_assert!(!self.state.is_empty(), "lookup on uninitialized index");
         let state = &self.state[self.state_for(columns).expect("lookup on non-indexed column set")];
@@ -350,18 +450,124 @@ impl<T: Hash + Eq + Clone> State<T> {
             &[]
         }
     }
+
+    /// All rows whose values in `columns` lie between `lo` and `hi` (inclusive), in key order.
+    ///
+    /// The state must have an ordered index on the columns (see `add_ordered_key`). Keys on
+    /// several columns are compared column by column, in the order the columns are given.
+    pub fn lookup_range<'a>(&'a self,
+                            columns: &[usize],
+                            lo: &[T],
+                            hi: &[T])
+                            -> Box<Iterator<Item = &'a Arc<Vec<T>>> + 'a> {
+        let state = &self.state[self.state_for(columns).expect("lookup on non-indexed column set")];
+        match state.1 {
+            KeyedState::Ordered(ref m) => {
+                let hi = hi.to_vec();
+                Box::new(m.range(lo.to_vec()..)
+                    .take_while(move |&(k, _)| *k <= hi)
+                    .flat_map(|(_, rs)| rs.iter()))
+            }
+            _ => panic!("range lookups require an ordered index"),
+        }
+    }
 }
 
-impl<T: Hash + Eq + Clone> IntoIterator for State<T> {
-    type Item = <FnvHashMap<T, Vec<Arc<Vec<T>>>> as IntoIterator>::Item;
-    type IntoIter = <FnvHashMap<T, Vec<Arc<Vec<T>>>> as IntoIterator>::IntoIter;
+/// The approximate number of bytes used by a row shared between indices.
+pub(crate) fn row_bytes(r: &[DataType]) -> usize {
+    use std::mem;
+    // the row, plus the reference counts of the Arc that holds it
+    mem::size_of::<Vec<DataType>>() + 2 * mem::size_of::<usize>() +
+    r.iter()
+        .map(|d| match *d {
+            DataType::Text(ref t) => mem::size_of::<DataType>() + t.to_bytes().len(),
+            DataType::Bytes(ref b) => mem::size_of::<DataType>() + b.len(),
+            _ => mem::size_of::<DataType>(),
+        })
+        .sum::<usize>()
+}
+
+impl State<DataType> {
+    /// The approximate number of bytes used by the rows held in this state and its indices.
+    ///
+    /// Every row is stored once, and referenced from each index, which also holds a copy of the
+    /// row's key.
+    pub fn bytes(&self) -> usize {
+        use std::mem;
+        let data: usize = self.iter().flat_map(|rs| rs.iter()).map(|r| row_bytes(&r[..])).sum();
+        let indices: usize = self.state
+            .iter()
+            .map(|&(ref cols, ref s)| {
+                let per_key = mem::size_of::<Vec<Arc<Vec<DataType>>>>() +
+                              cols.len() * mem::size_of::<DataType>();
+                s.len() * per_key + s.rows() * mem::size_of::<Arc<Vec<DataType>>>()
+            })
+            .sum();
+        data + indices
+    }
+}
+
+impl<T: 'static + Hash + Eq + Ord + Clone> IntoIterator for State<T> {
+    type Item = Vec<Arc<Vec<T>>>;
+    type IntoIter = Box<Iterator<Item = Vec<Arc<Vec<T>>>>>;
     fn into_iter(self) -> Self::IntoIter {
-        for (_, state) in self.state {
-            if let KeyedState::Single(map) = state {
-                return map.into_iter();
-            }
+        match self.state.into_iter().next() {
+            Some((_, KeyedState::Single(map))) => Box::new(map.into_iter().map(|(_, rs)| rs)),
+            Some((_, KeyedState::Double(map))) => Box::new(map.into_iter().map(|(_, rs)| rs)),
+            Some((_, KeyedState::Tri(map))) => Box::new(map.into_iter().map(|(_, rs)| rs)),
+            Some((_, KeyedState::Quad(map))) => Box::new(map.into_iter().map(|(_, rs)| rs)),
+            Some((_, KeyedState::Ordered(map))) => Box::new(map.into_iter().map(|(_, rs)| rs)),
+            None => Box::new(iter::empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(a: i32, b: i32) -> Arc<Vec<DataType>> {
+        Arc::new(vec![a.into(), b.into()])
+    }
+
+    #[test]
+    fn ordered_index_finds_ranges() {
+        let mut state = State::default();
+        state.add_ordered_key(&[1]);
+        for i in 0..10 {
+            state.insert(row(i % 2, i));
+        }
+        assert!(state.is_ordered(&[1]));
+
+        let rs: Vec<_> = state.lookup_range(&[1], &[3.into()], &[5.into()]).cloned().collect();
+        assert_eq!(rs, vec![row(1, 3), row(0, 4), row(1, 5)]);
+
+        // point lookups go through the ordered index too
+        assert_eq!(state.lookup(&[1], &KeyType::Single(&7.into())), &[row(1, 7)][..]);
+
+        state.remove(&row(0, 4)[..]);
+        let rs: Vec<_> = state.lookup_range(&[1], &[3.into()], &[5.into()]).cloned().collect();
+        assert_eq!(rs, vec![row(1, 3), row(1, 5)]);
+    }
+
+    #[test]
+    fn ordered_index_replaces_hash_index() {
+        let mut state = State::default();
+        state.add_key(&[0, 1]);
+        for i in 0..4 {
+            state.insert(row(i % 2, i));
         }
-        // TODO: allow into_iter without single key (breaks Self::IntoIter type)
-        unimplemented!();
+        assert!(!state.is_ordered(&[0, 1]));
+
+        state.add_ordered_key(&[0, 1]);
+        assert!(state.is_ordered(&[0, 1]));
+        assert_eq!(state.keys(), vec![vec![0, 1]]);
+        assert_eq!(state.rows(), 4);
+
+        // compound keys are ordered by their first column first
+        let rs: Vec<_> = state.lookup_range(&[0, 1], &[0.into(), 1.into()], &[1.into(), 1.into()])
+            .cloned()
+            .collect();
+        assert_eq!(rs, vec![row(0, 2), row(1, 1)]);
     }
 }
@@ -11,17 +11,38 @@ use timekeeper::{Timer, TimerSet, SimpleTracker, RealTime, ThreadTime};
 
 use flow::prelude::*;
 use flow::payload::{TransactionState, ReplayData};
+use flow::{Batching, ConditionFailed, ReplayPacing, ReplayInterleave};
 pub use flow::domain::single::NodeDescriptor;
 use flow::statistics;
+use flow::metrics;
 
 use slog::Logger;
 
 use ops;
 use checktable;
 
-const BATCH_SIZE: usize = 128;
-
 const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// The number of records carried by `m`, if it carries any.
+fn records(m: &Packet) -> usize {
+    match *m {
+        Packet::Message { ref data, .. } |
+        Packet::Transaction { ref data, .. } |
+        Packet::Replay { data: ReplayData::Records(ref data), .. } => data.len(),
+        _ => 0,
+    }
+}
+
+/// Index `state` by each of the given column sets, using ordered indices for those in `ordered`.
+fn add_indices(state: &mut State, index: Vec<Vec<usize>>, ordered: &[Vec<usize>]) {
+    for idx in index {
+        if ordered.contains(&idx) {
+            state.add_ordered_key(&idx[..]);
+        } else {
+            state.add_key(&idx[..]);
+        }
+    }
+}
 macro_rules! dur_to_ns {
     ($d:expr) => {{
         let d = $d;
@@ -52,6 +73,7 @@ impl Index {
 
 pub mod single;
 pub mod local;
+pub mod affinity;
 
 enum BufferedTransaction {
     RemoteTransaction,
@@ -62,6 +84,65 @@ enum BufferedTransaction {
 
 type InjectCh = mpsc::SyncSender<Packet>;
 
+/// Descriptions of the invariant violations that caused domains to stop processing updates.
+pub type Failures = Arc<Mutex<HashMap<Index, String>>>;
+
+struct ReplayPath {
+    path: Vec<NodeAddress>,
+    /// Where to report the number of records and bytes replayed once the replay has finished, if
+    /// the path ends in this domain.
+    done_tx: Option<mpsc::SyncSender<(usize, usize)>>,
+    /// The node replayed from, if the path starts in this domain.
+    source: Option<NodeAddress>,
+    /// How to split up state that has to be replayed record by record.
+    pacing: ReplayPacing,
+}
+
+impl ReplayPath {
+    /// Where updates enter this domain's part of the path: the first node of the path, along with
+    /// the node it gets them from, unless that is another domain.
+    fn entry(&self) -> ReplayEntry {
+        (self.source, self.path[0])
+    }
+}
+
+/// Where a replay path enters a domain. See `ReplayPath::entry`.
+type ReplayEntry = (Option<NodeAddress>, NodeAddress);
+
+/// A node that one or more replays, which may run concurrently, are reconstructing.
+struct ReplayTarget {
+    node: LocalNodeIndex,
+    /// The replays to the node that have not finished yet.
+    pending: HashSet<Tag>,
+    /// The replays to the node that have finished.
+    finished: Vec<Tag>,
+    /// Where the replays to the node enter this domain.
+    entries: Vec<ReplayEntry>,
+    /// The entries of the replays that have begun.
+    started: Vec<ReplayEntry>,
+    /// Updates that the node has received since the replays began.
+    buffered: Vec<Packet>,
+}
+
+impl ReplayTarget {
+    /// The replay entry that an update from `src` to `me` passes through, if any.
+    fn entered(&self, src: NodeAddress, me: NodeAddress) -> Option<ReplayEntry> {
+        self.entries
+            .iter()
+            .find(|&&(from, node)| node == me && from.map(|from| from == src).unwrap_or(true))
+            .cloned()
+    }
+
+    /// Should an update to the node that came through the given replay entry (if any) be held back
+    /// until all replays have finished?
+    ///
+    /// Updates that came through the entry of a replay that has not yet begun are already
+    /// accounted for in the records that the replay will carry. Other updates are not.
+    fn buffers(&self, entered: Option<ReplayEntry>) -> bool {
+        entered.map(|e| self.started.contains(&e)).unwrap_or(true)
+    }
+}
+
 pub struct Domain {
     index: Index,
 
@@ -82,14 +163,26 @@ pub struct Domain {
 
     checktable: Arc<Mutex<checktable::CheckTable>>,
 
-    replaying_to: Option<(LocalNodeIndex, Vec<Packet>)>,
-    replay_paths: HashMap<Tag, (Vec<NodeAddress>, Option<mpsc::SyncSender<()>>)>,
+    replaying_to: Option<ReplayTarget>,
+    /// States for nodes that were ready before they were materialized, to be handed to the nodes
+    /// when the replay into them begins.
+    pending_states: HashMap<LocalNodeIndex, State>,
+    replay_paths: HashMap<Tag, ReplayPath>,
+    /// Number of records, and their approximate size in bytes, that have been replayed into this
+    /// domain along each terminating path.
+    replayed: HashMap<Tag, (usize, usize)>,
+
+    /// How egress nodes batch up the updates they send to other domains.
+    batching: Batching,
+    /// When the oldest update that an egress node has yet to send was batched up, if any.
+    batched_since: Option<time::Instant>,
 
     total_time: Timer<SimpleTracker, RealTime>,
     total_ptime: Timer<SimpleTracker, ThreadTime>,
     wait_time: Timer<SimpleTracker, RealTime>,
     process_times: TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
     process_ptimes: TimerSet<LocalNodeIndex, SimpleTracker, ThreadTime>,
+    metrics: metrics::Recorder,
 }
 
 impl Domain {
@@ -97,7 +190,8 @@ impl Domain {
                index: Index,
                nodes: DomainNodes,
                checktable: Arc<Mutex<checktable::CheckTable>>,
-               ts: i64)
+               ts: i64,
+               batching: Batching)
                -> Self {
         // initially, all nodes are not ready (except for timestamp egress nodes)!
         let not_ready = nodes.iter()
@@ -115,45 +209,90 @@ impl Domain {
             ts: ts,
             checktable: checktable,
             replaying_to: None,
+            pending_states: HashMap::new(),
             replay_paths: HashMap::new(),
+            replayed: HashMap::new(),
+            batching: batching,
+            batched_since: None,
             total_time: Timer::new(),
             total_ptime: Timer::new(),
             wait_time: Timer::new(),
             process_times: TimerSet::new(),
             process_ptimes: TimerSet::new(),
+            metrics: metrics::Recorder::default(),
         }
     }
 
-    pub fn dispatch(m: Packet,
-                    not_ready: &HashSet<LocalNodeIndex>,
-                    replaying_to: &mut Option<(LocalNodeIndex, Vec<Packet>)>,
-                    states: &mut StateMap,
-                    nodes: &DomainNodes,
-                    process_times: &mut TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
-                    process_ptimes: &mut TimerSet<LocalNodeIndex, SimpleTracker, ThreadTime>,
-                    enable_output: bool)
-                    -> HashMap<NodeAddress, Vec<ops::Record>> {
+    /// The column sets of `node` that a node in this domain wants an ordered index on (see
+    /// `Ingredient::suggest_ordered_indexes`).
+    fn ordered_indices(&self, node: LocalNodeIndex) -> Vec<Vec<usize>> {
+        self.nodes
+            .iter()
+            .map(|n| n.borrow())
+            .filter(|n| n.is_internal())
+            .flat_map(|n| n.suggest_ordered_indexes(n.addr()).into_iter())
+            .filter(|&(ref a, _)| *a.as_local() == node)
+            .map(|(_, cols)| cols)
+            .collect()
+    }
+
+    fn dispatch(m: Packet,
+                entered: Option<ReplayEntry>,
+                not_ready: &HashSet<LocalNodeIndex>,
+                replaying_to: &mut Option<ReplayTarget>,
+                states: &mut StateMap,
+                nodes: &DomainNodes,
+                process_times: &mut TimerSet<LocalNodeIndex, SimpleTracker, RealTime>,
+                process_ptimes: &mut TimerSet<LocalNodeIndex, SimpleTracker, ThreadTime>,
+                metrics: &mut metrics::Recorder,
+                enable_output: bool)
+                -> HashMap<NodeAddress, Vec<ops::Record>> {
 
         let me = m.link().dst;
         let mut output_messages = HashMap::new();
 
-        if let Some((ref bufnode, ref mut buffered)) = *replaying_to {
-            if bufnode == me.as_local() {
-                buffered.push(m);
-                return output_messages;
+        if !nodes.contains_key(me.as_local()) {
+            // the node was removed while this update was on its way to it, or has yet to be
+            // handed over from the domain it is moving from (see `Packet::MoveNodes`)
+            return output_messages;
+        }
+
+        let mut stateless = false;
+        let mut entered = entered;
+        if let Some(ref mut target) = *replaying_to {
+            entered = target.entered(m.link().src, me).or(entered);
+            if target.node == *me.as_local() {
+                if target.buffers(entered) {
+                    target.buffered.push(m);
+                    return output_messages;
+                }
+                // the replay that this update is part of has yet to begin. if the node is already
+                // running, it must keep processing updates like before it had any state.
+                stateless = true;
             }
         }
         if !not_ready.is_empty() && not_ready.contains(me.as_local()) {
             return output_messages;
         }
 
+        let held = if stateless {
+            states.remove(me.as_local())
+        } else {
+            None
+        };
         let mut n = nodes[me.as_local()].borrow_mut();
+        let records_in = records(&m);
+        let start = time::Instant::now();
         process_times.start(*me.as_local());
         process_ptimes.start(*me.as_local());
         let m = n.process(m, states, nodes, true);
         process_ptimes.stop();
         process_times.stop();
+        metrics.processed(*me.as_local(), records_in, records(&m), start.elapsed());
         drop(n);
+        if let Some(state) = held {
+            states.insert(*me.as_local(), state);
+        }
 
         match m {
             Packet::Message { .. } if m.is_empty() => {
@@ -190,12 +329,14 @@ impl Domain {
                 m.link_mut().dst = n.children[i];
 
                 for (k, mut v) in Self::dispatch(m,
+                                                 entered,
                                                  not_ready,
                                                  replaying_to,
                                                  states,
                                                  nodes,
                                                  process_times,
                                                  process_ptimes,
+                                                 metrics,
                                                  enable_output) {
                     output_messages.entry(k).or_insert_with(Vec::new).append(&mut v);
                 }
@@ -220,12 +361,14 @@ impl Domain {
                  enable_output: bool)
                  -> HashMap<NodeAddress, Vec<ops::Record>> {
         Self::dispatch(m,
+                       None,
                        &self.not_ready,
                        &mut self.replaying_to,
                        &mut self.state,
                        &self.nodes,
                        &mut self.process_times,
                        &mut self.process_ptimes,
+                       &mut self.metrics,
                        enable_output)
     }
 
@@ -266,13 +409,16 @@ impl Domain {
                 continue;
             }
 
+            let records_in = records(&m);
+            let start = time::Instant::now();
             self.process_times.start(*addr.as_local());
             self.process_ptimes.start(*addr.as_local());
-            self.nodes[addr.as_local()]
+            let m = self.nodes[addr.as_local()]
                 .borrow_mut()
                 .process(m, &mut self.state, &self.nodes, true);
             self.process_ptimes.stop();
             self.process_times.stop();
+            self.metrics.processed(*addr.as_local(), records_in, records(&m), start.elapsed());
             assert_eq!(n.borrow().children.len(), 0);
         }
     }
@@ -380,6 +526,43 @@ impl Domain {
         }
     }
 
+    /// Have egress nodes send on the updates they have batched up, either all of them (if `all`
+    /// is set, or the oldest update has waited for long enough), or only full batches.
+    fn flush_egress(&mut self, all: bool) {
+        if self.batched_since.is_none() && self.batching.max_records > 1 {
+            // nothing was batched up since the last flush, so there is no need to look
+            let any = self.nodes.iter().any(|n| !n.borrow().batched.is_empty());
+            if !any {
+                return;
+            }
+        }
+
+        let max_delay = self.batching.max_delay;
+        let expired = self.batched_since.map(|since| since.elapsed() >= max_delay).unwrap_or(false);
+        let all = all || expired || self.batching.max_records <= 1;
+
+        let mut held = false;
+        for n in self.nodes.iter() {
+            let mut n = n.borrow_mut();
+            if n.batched.is_empty() {
+                continue;
+            }
+            if all || n.batched.len() >= self.batching.max_records {
+                n.flush();
+            } else {
+                held = true;
+            }
+        }
+
+        if held {
+            if self.batched_since.is_none() {
+                self.batched_since = Some(time::Instant::now());
+            }
+        } else {
+            self.batched_since = None;
+        }
+    }
+
     fn handle(&mut self,
               mut m: Packet,
               domain_rx: &mut mpsc::Receiver<Packet>,
@@ -391,9 +574,16 @@ impl Domain {
             return;
         }
 
+        // only regular updates may be batched up behind other updates at egress nodes
+        match m {
+            Packet::Message { .. } => {}
+            _ => self.flush_egress(true),
+        }
+
         match m {
             m @ Packet::Message { .. } => {
                 self.dispatch_(m, true);
+                self.flush_egress(false);
             }
             m @ Packet::Transaction { .. } => {
                 self.buffer_transaction(m);
@@ -419,14 +609,225 @@ impl Domain {
                 self.nodes.insert(addr, cell::RefCell::new(node));
                 trace!(self.log, "new node incorporated"; "local" => addr.id());
             }
+            Packet::RemoveNodes { nodes } => {
+                for &addr in &nodes {
+                    self.nodes.remove(&addr);
+                    self.state.remove(&addr);
+                    self.pending_states.remove(&addr);
+                    self.not_ready.remove(&addr);
+                    trace!(self.log, "node removed"; "local" => addr.id());
+                }
+                for n in self.nodes.iter() {
+                    n.borrow_mut().children.retain(|c| !nodes.contains(c.as_local()));
+                }
+                let dead: Vec<_> = self.replay_paths
+                    .iter()
+                    .filter(|&(_, p)| p.path.iter().any(|n| nodes.contains(n.as_local())))
+                    .map(|(&tag, _)| tag)
+                    .collect();
+                for tag in dead {
+                    self.replay_paths.remove(&tag);
+                }
+            }
+            Packet::MoveNodes { nodes,
+                                domain,
+                                unmap,
+                                remap,
+                                egress,
+                                ingress,
+                                disconnect,
+                                orphaned,
+                                to,
+                                ack } => {
+                use std::cell;
+                use flow::node::Type;
+
+                for (e, ingress) in disconnect {
+                    if let Type::Egress { ref txs, .. } = *self.nodes[&e].borrow().inner {
+                        txs.lock().unwrap().retain(|&(i, _, _)| i != ingress);
+                    }
+                }
+
+                let mut gone: HashSet<_> = orphaned.iter().cloned().collect();
+                let mut adopted = Vec::new();
+                let mut state = HashMap::new();
+                for (addr, to_addr, children) in nodes {
+                    let mut n = self.nodes.remove(&addr).unwrap().into_inner();
+                    n.inner.relocate(domain, to_addr, &unmap, &remap);
+                    n.children = children;
+                    if let Some(s) = self.state.remove(&addr) {
+                        state.insert(*to_addr.as_local(), s);
+                    }
+                    assert!(!self.not_ready.contains(&addr), "cannot move node that is not ready");
+                    gone.insert(addr);
+                    adopted.push(n);
+                }
+                for addr in &orphaned {
+                    self.nodes.remove(addr);
+                }
+                for n in self.nodes.iter() {
+                    n.borrow_mut().children.retain(|c| !gone.contains(c.as_local()));
+                }
+
+                for (parent, e) in egress {
+                    let addr = e.addr();
+                    self.nodes[&parent].borrow_mut().children.push(addr);
+                    self.nodes.insert(*addr.as_local(), cell::RefCell::new(e));
+                }
+                for (i, from) in ingress {
+                    if let Some(from) = from {
+                        state.insert(*i.addr().as_local(), self.state[&from].clone());
+                    }
+                    adopted.push(i);
+                }
+
+                let dead: Vec<_> = self.replay_paths
+                    .iter()
+                    .filter(|&(_, p)| p.path.iter().any(|n| gone.contains(n.as_local())))
+                    .map(|(&tag, _)| tag)
+                    .collect();
+                for tag in dead {
+                    self.replay_paths.remove(&tag);
+                }
+
+                debug!(self.log, "handing nodes over";
+                       "domain" => domain.index(),
+                       "#nodes" => adopted.len());
+                to.send(Packet::AdoptNodes {
+                        nodes: adopted,
+                        state: state,
+                    })
+                    .unwrap();
+                ack.send(()).unwrap();
+            }
+            Packet::AdoptNodes { nodes, state } => {
+                use std::cell;
+                for n in nodes {
+                    let addr = *n.addr().as_local();
+                    trace!(self.log, "node adopted"; "local" => addr.id());
+                    self.nodes.insert(addr, cell::RefCell::new(n));
+                }
+                for (addr, s) in state {
+                    self.state.insert(addr, s);
+                }
+            }
+            Packet::AddBaseColumn { node, column, default } => {
+                let (me, children) = {
+                    let mut n = self.nodes[&node].borrow_mut();
+                    n.add_column(column, default.clone());
+                    (n.addr(), n.children.clone())
+                };
+
+                // the rows the base already holds get the default too. they are re-issued as a
+                // retraction of the old row followed by an insertion of the padded one, which
+                // views that only use the existing columns see as no change at all.
+                let mut data = Vec::new();
+                if let Some(state) = self.state.get_mut(&node) {
+                    for r in state.all_rows() {
+                        let mut padded = (*r).clone();
+                        padded.push(default.clone());
+                        let padded = Arc::new(padded);
+                        state.remove(&r[..]);
+                        state.insert(padded.clone());
+                        data.push(Record::Negative(r));
+                        data.push(Record::Positive(padded));
+                    }
+                }
+                debug!(self.log, "base column added";
+                       "local" => node.id(),
+                       "#rows" => data.len() / 2);
+
+                if !data.is_empty() {
+                    let data: Records = data.into();
+                    for child in children {
+                        let m = Packet::Message {
+                            link: Link::new(me, child),
+                            data: data.clone(),
+                            trace: None,
+                        };
+                        self.dispatch_(m, true);
+                    }
+                }
+            }
             Packet::PrepareState { node, index } => {
                 let mut state = State::default();
-                for idx in index {
-                    state.add_key(&idx[..]);
+                add_indices(&mut state, index, &self.ordered_indices(node)[..]);
+                if self.not_ready.contains(&node) {
+                    self.state.insert(node, state);
+                } else {
+                    // the node is already processing updates, and if it had a state now, it would
+                    // absorb updates that the replay will also carry. we only hand it the state
+                    // once the replay begins.
+                    self.pending_states.insert(node, state);
+                }
+            }
+            Packet::AddIndices { node, index } => {
+                let ordered = self.ordered_indices(node);
+                let state = self.state
+                    .get_mut(&node)
+                    .expect("indices can only be added to materialized nodes");
+                add_indices(state, index, &ordered[..]);
+                debug!(self.log, "indices added"; "local" => node.id(), "#rows" => state.rows());
+            }
+            Packet::StateSize { node, tx } => {
+                use flow::node::Type;
+                let rows = {
+                    let n = self.nodes[&node].borrow();
+                    match *n.inner {
+                        Type::Reader(_, ref r) => {
+                            r.state.as_ref().and_then(|s| s.all_rows().ok()).map(|rs| rs.len())
+                        }
+                        _ => None,
+                    }
+                };
+                let rows = rows.or_else(|| self.state.get(&node).map(|s| s.rows())).unwrap_or(0);
+                tx.send(rows).unwrap();
+            }
+            Packet::StateLookup { node, columns, key, tx } => {
+                let rows = self.state
+                    .get(&node)
+                    .and_then(|s| if s.keys().contains(&columns) {
+                        Some(s.lookup(&columns[..], &KeyType::from(&key[..])).len())
+                    } else {
+                        None
+                    });
+                tx.send(rows).unwrap();
+            }
+            Packet::ConditionalUpdate { link, data, columns, key, column, expected, tx } => {
+                // the ingress node for a base only ever feeds that base
+                let base = {
+                    let n = self.nodes[link.dst.as_local()].borrow();
+                    assert_eq!(n.children.len(), 1);
+                    n.children[0]
+                };
+                let current = self.state
+                    .get(base.as_local())
+                    .expect("base must have its own state materialized to support conditional \
+                             updates")
+                    .lookup(&columns[..], &KeyType::from(&key[..]))
+                    .get(0)
+                    .map(|r| (**r).clone());
+
+                // nothing else can touch the base's state until we have dispatched the update, so
+                // the row cannot change between the check and the update
+                let holds = current.as_ref().and_then(|r| r.get(column)) == Some(&expected);
+                if holds {
+                    let m = Packet::Message {
+                        link: link,
+                        data: data,
+                        trace: None,
+                    };
+                    self.dispatch_(m, true);
+                    self.flush_egress(false);
+                    tx.send(Ok(())).unwrap();
+                } else {
+                    trace!(self.log, "conditional update failed";
+                           "base" => base.as_local().id(),
+                           "column" => column);
+                    tx.send(Err(ConditionFailed { current: current })).unwrap();
                 }
-                self.state.insert(node, state);
             }
-            Packet::SetupReplayPath { tag, path, done_tx, ack } => {
+            Packet::SetupReplayPath { tag, path, source, pacing, done_tx, ack } => {
                 // let coordinator know that we've registered the tagged path
                 ack.send(()).unwrap();
 
@@ -436,9 +837,15 @@ impl Domain {
                 } else {
                     info!(self.log, "tag" => tag.id(); "told about replay path {:?}", path);
                 }
-                self.replay_paths.insert(tag, (path, done_tx));
+                self.replay_paths.insert(tag,
+                                         ReplayPath {
+                                             path: path,
+                                             done_tx: done_tx,
+                                             source: source,
+                                             pacing: pacing,
+                                         });
             }
-            Packet::StartReplay { tag, from, ack } => {
+            Packet::StartReplay { tag, from, reader, ack } => {
                 // let coordinator know that we've entered replay loop
                 ack.send(()).unwrap();
 
@@ -452,10 +859,31 @@ impl Domain {
                 // we clone the entire state so that we can continue to occasionally process
                 // incoming updates to the domain without disturbing the state that is being
                 // replayed.
-                let state: State = self.state
-                    .get(from.as_local())
-                    .expect("migration replay path started with non-materialized node")
-                    .clone();
+                let state: State = if let Some(reader) = reader {
+                    // the node's reader holds the same rows as the node itself would, but in a
+                    // different structure, so we have to build a state from it.
+                    use flow::node::Type;
+                    let n = self.nodes[&reader].borrow();
+                    let r = match *n.inner {
+                        Type::Reader(_, ref r) => {
+                            r.state.as_ref().expect("replaying from reader without state")
+                        }
+                        _ => unreachable!("replay source is not a reader"),
+                    };
+
+                    let mut state = State::default();
+                    state.add_key(&[r.key()]);
+                    let rows = r.all_rows().expect("replaying from reader that was never swapped");
+                    for row in rows {
+                        state.insert(row);
+                    }
+                    state
+                } else {
+                    self.state
+                        .get(from.as_local())
+                        .expect("migration replay path started with non-materialized node")
+                        .clone()
+                };
 
                 debug!(self.log, "current state cloned for replay"; "μs" => dur_to_ns!(start.elapsed()) / 1000);
 
@@ -478,9 +906,7 @@ impl Domain {
                             State::default()
                         }
                     };
-                    for idx in index {
-                        s.add_key(&idx[..]);
-                    }
+                    add_indices(&mut s, index, &self.ordered_indices(node)[..]);
                     assert!(self.state.insert(node, s).is_none());
                 } else {
                     // NOTE: just because index_on is None does *not* mean we're not materialized
@@ -546,6 +972,101 @@ impl Domain {
 
                 sender.send((domain_stats, node_stats)).unwrap();
             }
+            Packet::GetMemory(tx) => {
+                use flow::node::Type;
+                let memory = self.nodes
+                    .iter()
+                    .filter_map(|nd| {
+                        let n = nd.borrow();
+                        let m = match *n.inner {
+                            Type::Reader(_, ref r) if r.state.is_some() => Some(r.memory()),
+                            _ => {
+                                self.state.get(n.addr().as_local()).map(|s| {
+                                    statistics::NodeMemory {
+                                        rows: s.rows(),
+                                        bytes: s.bytes(),
+                                    }
+                                })
+                            }
+                        };
+                        m.map(|m| (n.index, m))
+                    })
+                    .collect();
+                let _ = tx.send(memory);
+            }
+            Packet::GetMetrics(tx) => {
+                use flow::node::Type;
+                let readers = self.nodes
+                    .iter()
+                    .filter_map(|nd| {
+                        let n = nd.borrow();
+                        match *n.inner {
+                            Type::Reader(_, ref r) => {
+                                let (hits, misses) = r.trace.lookups();
+                                Some((NodeAddress::make_global(n.index),
+                                      metrics::ReaderMetrics {
+                                          hits: hits,
+                                          misses: misses,
+                                      }))
+                            }
+                            _ => None,
+                        }
+                    })
+                    .collect();
+                let nodes = &self.nodes;
+                let global = |ni| {
+                    nodes.get(&ni).map(|n| NodeAddress::make_global(n.borrow().index))
+                };
+                let _ = tx.send(self.metrics.metrics(global, readers));
+            }
+            Packet::SwapReader(node) => {
+                use flow::node::Type;
+                let mut n = self.nodes[&node].borrow_mut();
+                if let Type::Reader(Some(ref mut w), ref r) = *n.inner {
+                    trace!(self.log, "swapping state on request"; "local" => node.id());
+                    single::fill_reader_misses(node, w, r, &self.nodes, &self.state);
+                    w.swap();
+                }
+            }
+            Packet::SetSwapPolicy { node, policy } => {
+                use flow::node::Type;
+                let mut n = self.nodes[&node].borrow_mut();
+                if let Type::Reader(ref mut state, ref mut r) = *n.inner {
+                    trace!(self.log, "changing swap policy";
+                           "local" => node.id(),
+                           "policy" => format!("{:?}", policy));
+                    r.swap = policy;
+                    // writes held back under the old policy should not wait for the new one
+                    if let Some(ref mut state) = *state {
+                        state.swap();
+                    }
+                }
+            }
+            Packet::Subscribe { node, tx, snapshot } => {
+                use flow::node::Type;
+                let mut n = self.nodes[&node].borrow_mut();
+                if let Type::Reader(Some(ref mut w), ref r) = *n.inner {
+                    // the stream will only carry updates that follow this packet, so the
+                    // snapshot must include every update processed so far, swapped in or not.
+                    w.swap();
+
+                    let state = r.state.as_ref().unwrap();
+                    let rows = state.all_rows()
+                        .unwrap()
+                        .into_iter()
+                        .map(|row| (*row).clone())
+                        .collect();
+                    let ts = state.find_many_and(&[], |_| ()).unwrap().1;
+
+                    r.streamers.lock().unwrap().push(tx);
+                    // the caller may have given up on the subscription
+                    let _ = snapshot.send((rows, ts));
+                }
+            }
+            Packet::Quiesce(ack) => {
+                // the caller may have timed out and stopped listening
+                let _ = ack.send(self.buffered_transactions.is_empty());
+            }
             Packet::None => unreachable!("None packets should never be sent around"),
             Packet::Quit => unreachable!("Quit messages are handled by event loop"),
         }
@@ -558,18 +1079,12 @@ impl Domain {
         let mut finished = None;
         let mut playback = None;
         if let Packet::Replay { mut link, tag, last, data } = m {
-            let &mut (ref path, ref mut done_tx) = self.replay_paths.get_mut(&tag).unwrap();
-
-            if done_tx.is_some() && self.replaying_to.is_none() {
-                // this is the first message we receive for this tagged replay path. only at this
-                // point should we start buffering messages for the target node. since the node is
-                // not yet marked ready, all previous messages for this node will automatically be
-                // discarded by dispatch(). the reason we should ignore all messages preceeding the
-                // first replay message is that those have already been accounted for in the state
-                // we are being replayed. if we buffered them and applied them after all the state
-                // has been replayed, we would double-apply those changes, which is bad.
-                self.replaying_to = Some((*path.last().unwrap().as_local(), vec![]));
-            }
+            self.begin_replay(tag);
+            // if other replays are filling the same state, we cannot just replace it
+            let sole = self.replaying_to.as_ref().map(|t| t.pending.len() == 1).unwrap_or(false);
+            let ReplayPath { ref path, ref mut done_tx, pacing, .. } = *self.replay_paths
+                .get_mut(&tag)
+                .unwrap();
 
             // we may be able to just absorb all the state in one go if we're lucky!
             let mut can_handle_directly = path.len() == 1;
@@ -588,7 +1103,9 @@ impl Domain {
             // `can_handle_directly` again here because it will have been changed for reader
             // nodes above, and this check only applies to non-reader nodes.
             if can_handle_directly && done_tx.is_some() {
-                if let ReplayData::StateCopy(ref state) = data {
+                if !sole {
+                    can_handle_directly = false;
+                } else if let ReplayData::StateCopy(ref state) = data {
                     let local_pkey = self.state[path[0].as_local()].keys();
                     if local_pkey != state.keys() {
                         debug!(self.log, "cannot use state directly, so falling back to regular replay";
@@ -614,6 +1131,13 @@ impl Domain {
                         let node = path[0];
                         debug!(self.log, "absorbing state clone"; "node" => node.as_local().id());
                         assert_eq!(self.state[node.as_local()].keys(), state.keys());
+                        let bytes = state.iter()
+                            .flat_map(|rs| rs.iter())
+                            .map(|r| local::row_bytes(&r[..]))
+                            .sum::<usize>();
+                        let replayed = self.replayed.entry(tag).or_insert((0, 0));
+                        replayed.0 += state.rows();
+                        replayed.1 += bytes;
                         self.state.insert(*node.as_local(), state);
                         debug!(self.log, "direct state clone absorbed");
                         finished = Some((tag, *node.as_local()));
@@ -680,7 +1204,7 @@ impl Domain {
                         let inject_tx = inject_tx.clone();
                         thread::Builder::new()
                         .name(format!("replay{}.{}",
-                                      self.nodes.iter().next().unwrap().borrow().domain().index(),
+                                      self.index.index(),
                                       link.src))
                         .spawn(move || {
                             use itertools::Itertools;
@@ -689,21 +1213,35 @@ impl Domain {
                             let to = link.dst;
 
                             let start = time::Instant::now();
-                            debug!(log, "starting state chunker"; "node" => to.as_local().id());
+                            let total = state.rows();
+                            debug!(log, "starting state chunker";
+                                   "node" => to.as_local().id(),
+                                   "rows" => total,
+                                   "batch" => pacing.batch_size);
 
                             let iter = state.into_iter()
-                                .flat_map(|(_, rs)| rs)
-                                .chunks(BATCH_SIZE);
+                                .flat_map(|rs| rs)
+                                .chunks(pacing.batch_size);
                             let mut iter = iter
                                 .into_iter()
                                 .enumerate()
                                 .peekable();
 
                             let link = Link::new(from, to);
+                            let mut sent = 0;
+                            let mut last_batch = None;
+                            let mut last_report = start;
 
                             // process all records in state to completion within domain
                             // and then forward on tx (if there is one)
                             while let Some((i, chunk)) = iter.next() {
+                                // give the domains along the path a chance to handle regular
+                                // updates before we send them more replayed records
+                                if let Some(last_batch) = last_batch {
+                                    Self::pace(pacing.interleave, last_batch);
+                                }
+
+
                                 use std::iter::FromIterator;
                                 let chunk = Records::from_iter(chunk.into_iter());
                                 let len = chunk.len();
@@ -716,6 +1254,16 @@ impl Domain {
 
                                 trace!(log, "sending batch"; "#" => i, "[]" => len);
                                 inject_tx.send(p).unwrap();
+                                last_batch = Some(time::Instant::now());
+
+                                sent += len;
+                                if last_report.elapsed() >= time::Duration::from_secs(1) {
+                                    info!(log, "replay in progress";
+                                          "node" => to.as_local().id(),
+                                          "rows" => sent,
+                                          "of" => total);
+                                    last_report = time::Instant::now();
+                                }
                             }
 
                             debug!(log, "state chunker finished"; "node" => to.as_local().id(), "μs" => dur_to_ns!(start.elapsed()) / 1000);
@@ -724,6 +1272,11 @@ impl Domain {
                 }
                 ReplayData::Records(data) => {
                     debug!(self.log, "replaying batch"; "#" => data.len());
+                    if done_tx.is_some() {
+                        let replayed = self.replayed.entry(tag).or_insert((0, 0));
+                        replayed.0 += data.len();
+                        replayed.1 += data.iter().map(|r| local::row_bytes(&r[..])).sum::<usize>();
+                    }
 
                     // forward the current message through all local nodes
                     let mut m = Packet::Replay {
@@ -787,17 +1340,97 @@ impl Domain {
         }
     }
 
+    /// Wait until the next replay batch may be sent, given when the last one was sent.
+    fn pace(interleave: ReplayInterleave, last_batch: time::Instant) {
+        use std::thread;
+        match interleave {
+            ReplayInterleave::Eager => (),
+            ReplayInterleave::Pause(pause) => thread::sleep(pause),
+            ReplayInterleave::Rate(per_sec) => {
+                let next = last_batch + time::Duration::from_secs(1) / per_sec;
+                let now = time::Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                }
+            }
+        }
+    }
+
+    /// Note that a packet for the given replay has arrived, and start buffering the updates to the
+    /// replay's target node that come through the replay's entry if it is the first.
+    fn begin_replay(&mut self, tag: Tag) {
+        let (target, entry) = match self.replay_paths[&tag] {
+            ref p @ ReplayPath { done_tx: Some(_), .. } => {
+                (*p.path.last().unwrap().as_local(), p.entry())
+            }
+            _ => return,
+        };
+
+        if self.replaying_to.is_none() {
+            // the first replay to this node has begun. all the replays to the node have been set
+            // up before any of them started, so we know about all of them by now.
+            let paths: Vec<_> = self.replay_paths
+                .iter()
+                .filter(|&(_, p)| {
+                    p.done_tx.is_some() && *p.path.last().unwrap().as_local() == target
+                })
+                .map(|(&tag, p)| (tag, p.entry()))
+                .collect();
+            debug!(self.log, "starting replays"; "local" => target.id(), "#paths" => paths.len());
+
+            // nodes that were already running before the migration only get their state now
+            if let Some(state) = self.pending_states.remove(&target) {
+                self.state.insert(target, state);
+            }
+            self.replaying_to = Some(ReplayTarget {
+                node: target,
+                pending: paths.iter().map(|&(tag, _)| tag).collect(),
+                finished: Vec::new(),
+                entries: paths.into_iter().map(|(_, entry)| entry).collect(),
+                started: Vec::new(),
+                buffered: Vec::new(),
+            });
+        }
+
+        let t = self.replaying_to.as_mut().unwrap();
+        assert_eq!(t.node, target, "only one node can be replayed to at a time");
+        if !t.started.contains(&entry) {
+            // this is the first message we receive for this tagged replay path. only at this
+            // point should we start buffering the messages that come through its entry. if the
+            // node is not yet marked ready, all previous ones are discarded by dispatch(). the
+            // reason we should ignore all messages preceeding the first replay message is that
+            // those have already been accounted for in the state we are being replayed. if we
+            // buffered them and applied them after all the state has been replayed, we would
+            // double-apply those changes, which is bad.
+            t.started.push(entry);
+        }
+    }
+
     fn replay_done(&mut self, tag: Tag, node: LocalNodeIndex, rx: &mut mpsc::Receiver<Packet>) {
         use std::time;
 
+        let finished = {
+            let t = self.replaying_to.as_mut().unwrap();
+            assert_eq!(t.node, node);
+            t.pending.remove(&tag);
+            t.finished.push(tag);
+            if !t.pending.is_empty() {
+                // the node is only complete once every replay to it has finished
+                trace!(self.log, "replay finished"; "local" => node.id(), "tag" => tag.id());
+                return;
+            }
+            t.finished.clone()
+        };
+
         // node is now ready, and should start accepting "real" updates
         trace!(self.log, "readying node"; "local" => node.id());
         self.not_ready.remove(&node);
 
         let start = time::Instant::now();
         let mut iterations = 0;
-        while let Some((target, buffered)) = self.replaying_to.take() {
-            assert_eq!(target, node);
+        while let Some(mut target) = self.replaying_to.take() {
+            assert_eq!(target.node, node);
+            let buffered = ::std::mem::replace(&mut target.buffered, Vec::new());
             if buffered.is_empty() {
                 break;
             }
@@ -822,7 +1455,8 @@ impl Domain {
             // make sure any updates from rx that we handle, and that hit this node, are buffered
             // so we can get back to them later.
             if switching {
-                self.replaying_to = Some((target, Vec::with_capacity(buffered.len() / 2)));
+                target.buffered = Vec::with_capacity(buffered.len() / 2);
+                self.replaying_to = Some(target);
             }
 
             for m in buffered {
@@ -846,12 +1480,14 @@ impl Domain {
                     // override the buffering behavior that our self.replaying_to = Some above would
                     // initiate.
                     Self::dispatch(m,
+                                   None,
                                    &self.not_ready,
                                    &mut None,
                                    &mut self.state,
                                    &self.nodes,
                                    &mut self.process_times,
                                    &mut self.process_ptimes,
+                                   &mut self.metrics,
                                    true);
                 } else {
                     // no transactions allowed here since we're still in a migration
@@ -866,22 +1502,43 @@ impl Domain {
             info!(self.log, "backlog drained"; "iterations" => iterations, "μs" => dur_to_ns!(start.elapsed()) / 1000);
         }
 
-        if let Some(done_tx) = self.replay_paths.get_mut(&tag).and_then(|p| p.1.as_mut()) {
-            info!(self.log, "acknowledging replay completed"; "node" => node.id());
-            done_tx.send(()).unwrap();
-        } else {
-            unreachable!()
+        for tag in finished {
+            if let Some(done_tx) = self.replay_paths.get_mut(&tag).and_then(|p| p.done_tx.take()) {
+                let replayed = self.replayed.remove(&tag).unwrap_or((0, 0));
+                info!(self.log, "acknowledging replay completed";
+                      "node" => node.id(),
+                      "records" => replayed.0,
+                      "bytes" => replayed.1);
+                done_tx.send(replayed).unwrap();
+            } else {
+                unreachable!()
+            }
         }
     }
 
-    pub fn boot(mut self, mut rx: mpsc::Receiver<Packet>) {
+    /// Start processing packets on a new thread.
+    ///
+    /// If `failures` is given, a panic while handling a packet does not take down the domain
+    /// thread. Instead, the panic message is recorded in `failures`, and the domain quarantines
+    /// all further packets apart from requests for statistics and quiescence.
+    pub fn boot(mut self,
+                mut rx: mpsc::Receiver<Packet>,
+                core: Option<usize>,
+                failures: Option<Failures>) {
+        use std::panic;
         use std::thread;
 
         info!(self.log, "booting domain"; "nodes" => self.nodes.iter().count());
-        let name: usize = self.nodes.iter().next().unwrap().borrow().domain().into();
         thread::Builder::new()
-            .name(format!("domain{}", name))
+            .name(format!("domain{}", self.index.index()))
             .spawn(move || {
+                if let Some(core) = core {
+                    match affinity::pin_current_thread(core) {
+                        Ok(()) => debug!(self.log, "pinned domain thread"; "core" => core),
+                        Err(e) => warn!(self.log, "could not pin domain thread"; "error" => e),
+                    }
+                }
+
                 // we want to keep around a second handle to the data channel so that we can access
                 // it during replay. we know that that's safe, because while handle_control is
                 // executing, we know we're not also using the Select or its handles.
@@ -901,19 +1558,44 @@ impl Domain {
                     inject_rx_handle.add();
                 }
 
+                let mut failed = false;
+                let mut polled = false;
+
                 self.total_time.start();
                 self.total_ptime.start();
                 loop {
-                    self.wait_time.start();
-                    let id = sel.wait();
-                    self.wait_time.stop();
-
-                    let m = if id == rx_handle.id() {
-                        rx_handle.recv()
-                    } else if id == inject_rx_handle.id() {
-                        inject_rx_handle.recv()
+                    // updates that egress nodes have batched up are sent on as soon as there is
+                    // nothing else to do. we also look for queued-up packets after every packet
+                    // that did not itself come from looking, so that the metrics can tell how far
+                    // behind the domain is, without starving the inject channel.
+                    let mut next = None;
+                    if !failed && (self.batched_since.is_some() || !polled) {
+                        match secondary_rx.try_recv() {
+                            Ok(m) => next = Some(m),
+                            Err(_) => {
+                                self.metrics.drained();
+                                if self.batched_since.is_some() {
+                                    self.flush_egress(true);
+                                }
+                            }
+                        }
+                    }
+                    polled = next.is_some();
+
+                    let m = if let Some(m) = next {
+                        Ok(m)
                     } else {
-                        unreachable!()
+                        self.wait_time.start();
+                        let id = sel.wait();
+                        self.wait_time.stop();
+
+                        if id == rx_handle.id() {
+                            rx_handle.recv()
+                        } else if id == inject_rx_handle.id() {
+                            inject_rx_handle.recv()
+                        } else {
+                            unreachable!()
+                        }
                     };
                     if m.is_err() {
                         break;
@@ -922,7 +1604,55 @@ impl Domain {
                     if let Packet::Quit = m {
                         break;
                     }
-                    self.handle(m, secondary_rx, &mut inject_tx);
+                    self.metrics.received();
+
+                    let m = if failed {
+                        match m {
+                            m @ Packet::GetStatistics(..) => m,
+                            m @ Packet::GetMemory(..) => m,
+                            m @ Packet::GetMetrics(..) => m,
+                            Packet::Quiesce(done) => {
+                                // we will never make progress, so there is no point in waiting
+                                let _ = done.send(true);
+                                continue;
+                            }
+                            _ => {
+                                debug!(self.log, "dropping packet sent to failed domain");
+                                continue;
+                            }
+                        }
+                    } else {
+                        m
+                    };
+
+                    let failures = match failures {
+                        Some(ref failures) => failures,
+                        None => {
+                            self.handle(m, secondary_rx, &mut inject_tx);
+                            continue;
+                        }
+                    };
+
+                    let handled = {
+                        let domain = &mut self;
+                        let inject_tx = &mut inject_tx;
+                        let secondary_rx = &mut *secondary_rx;
+                        panic::catch_unwind(panic::AssertUnwindSafe(move || {
+                            domain.handle(m, secondary_rx, inject_tx)
+                        }))
+                    };
+                    if let Err(e) = handled {
+                        let e = if let Some(e) = e.downcast_ref::<&str>() {
+                            e.to_string()
+                        } else if let Some(e) = e.downcast_ref::<String>() {
+                            e.clone()
+                        } else {
+                            String::from("unknown error")
+                        };
+                        error!(self.log, "domain failed; quarantining"; "error" => e.as_str());
+                        failures.lock().unwrap().insert(self.index, e);
+                        failed = true;
+                    }
                 }
             })
             .unwrap();
@@ -1,7 +1,12 @@
 use ops;
 use flow;
+use backlog;
 use petgraph::graph::NodeIndex;
 use flow::prelude::*;
+use flow::provenance::TraceKind;
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Mutex};
 
 macro_rules! broadcast {
     ($from:expr, $handoffs:ident, $m:expr, $children:expr) => {{
@@ -25,6 +30,8 @@ pub struct NodeDescriptor {
     pub index: NodeIndex,
     pub inner: Node,
     pub children: Vec<NodeAddress>,
+    /// Regular updates that an egress node has batched up, and has yet to send on (see `flush`).
+    pub batched: Vec<ops::Record>,
 }
 
 impl NodeDescriptor {
@@ -41,6 +48,25 @@ impl NodeDescriptor {
             index: node,
             inner: inner,
             children: children,
+            batched: Vec::new(),
+        }
+    }
+
+    /// Send any updates this egress node has batched up on to the other domains.
+    pub fn flush(&mut self) {
+        if self.batched.is_empty() {
+            return;
+        }
+
+        let data = ::std::mem::replace(&mut self.batched, Vec::new());
+        let addr = self.addr();
+        let m = Packet::Message {
+            link: Link::new(addr, addr),
+            data: data.into(),
+            trace: None,
+        };
+        if let flow::node::Type::Egress { ref txs, ref tags } = *self.inner {
+            send_egress(self.index, txs, tags, m);
         }
     }
 
@@ -52,25 +78,51 @@ impl NodeDescriptor {
                    -> Packet {
 
         use flow::payload::TransactionState;
-        let addr = *self.addr().as_local();
-        match *self.inner {
+        let me = self.addr();
+        let addr = *me.as_local();
+
+        // writes to base nodes are announced to any registered listeners once applied
+        let write_ts = match m {
+            Packet::Message { .. } => Some(None),
+            Packet::Transaction { state: TransactionState::Committed(ts, ..), .. } => Some(Some(ts)),
+            _ => None,
+        };
+        let mut notify = false;
+
+        // traced writes are reported by every node they pass through (see `provenance`)
+        let trace = match m {
+            Packet::Message { trace: Some(ref trace), .. } => Some(trace.clone()),
+            _ => None,
+        };
+        let index = self.index;
+        let domain = self.inner.domain();
+        let emit = |kind, data: &Records| if let Some(ref trace) = trace {
+            trace.emit(index, domain, kind, data);
+        };
+
+        let m = match *self.inner {
             flow::node::Type::Ingress => {
                 materialize(m.data(), state.get_mut(&addr));
+                emit(TraceKind::Ingress, m.data());
                 m
             }
             flow::node::Type::Reader(ref mut w, ref r) => {
-                if let Some(ref mut state) = *w {
-                    state.add(m.data().iter().cloned());
+                if let Some(ref mut w) = *w {
+                    // update the timestamp first so that the added rows are tagged with it
                     if let Packet::Transaction { state: TransactionState::Committed(ts, ..), .. } =
                         m {
-                        state.update_ts(ts);
+                        w.update_ts(ts);
                     }
+                    w.add(m.data().iter().cloned());
 
-                    if swap {
-                        state.swap();
+                    if swap && r.swap == flow::node::SwapPolicy::EveryBatch {
+                        fill_reader_misses(addr, w, r, nodes, state);
+                        w.swap();
                     }
                 }
 
+                emit(TraceKind::Reader, m.data());
+
                 let mut data = Some(m.take_data()); // so we can .take() for last tx
                 let mut txs = r.streamers.lock().unwrap();
                 let mut left = txs.len();
@@ -90,68 +142,132 @@ impl NodeDescriptor {
                 Packet::None
             }
             flow::node::Type::Egress { ref txs, ref tags } => {
-                // send any queued updates to all external children
-                let mut txs = txs.lock().unwrap();
-                let txn = txs.len() - 1;
-
-                debug_assert!(self.children.is_empty());
-
-                // we need to find the ingress node following this egress according to the path
-                // with replay.tag, and then forward this message only on the channel corresponding
-                // to that ingress node.
-                let replay_to = if let Packet::Replay { tag, .. } = m {
-                    Some(tags.lock()
-                        .unwrap()
-                        .get(&tag)
-                        .map(|n| *n)
-                        .expect("egress node told about replay message, but not on replay path"))
-                } else {
-                    None
-                };
-
-                let mut m = Some(m); // so we can use .take()
-                for (txi, &mut (ref globaddr, dst, ref mut tx)) in txs.iter_mut().enumerate() {
-                    let mut take = txi == txn;
-                    if let Some(replay_to) = replay_to.as_ref() {
-                        if replay_to == globaddr {
-                            take = true;
-                        } else {
-                            continue;
-                        }
-                    }
-
-                    // avoid cloning if this is last send
-                    let mut m = if take {
-                        m.take().unwrap()
-                    } else {
-                        // we know this is a data (not a replay)
-                        // because, a replay will force a take
-                        m.as_ref().map(|m| m.clone_data()).unwrap()
+                // regular updates are batched up until the domain flushes them, but other packets
+                // must not overtake the updates that were sent before them. traced updates are
+                // sent on by themselves, so that they do not pick up unrelated records.
+                if let Packet::Message { trace: None, .. } = m {
+                    self.batched.extend(m.take_data());
+                    return Packet::None;
+                }
+                if trace.is_some() {
+                    emit(TraceKind::Egress, m.data());
+                }
+                if !self.batched.is_empty() {
+                    let data = ::std::mem::replace(&mut self.batched, Vec::new());
+                    let batch = Packet::Message {
+                        link: Link::new(me, me),
+                        data: data.into(),
+                        trace: None,
                     };
-
-                    m.link_mut().src = NodeAddress::make_global(self.index);
-                    m.link_mut().dst = dst;
-
-                    tx.send(m).unwrap();
-
-                    if take {
-                        break;
-                    }
+                    send_egress(self.index, txs, tags, batch);
                 }
-                debug_assert!(m.is_none());
+
+                debug_assert!(self.children.is_empty());
+                send_egress(self.index, txs, tags, m);
                 Packet::None
             }
             flow::node::Type::Internal(ref mut i) => {
                 let from = m.link().src;
                 m.map_data(|data| i.on_input(from, data, nodes, state));
                 materialize(m.data(), state.get_mut(&addr));
+                notify = i.is_base() && write_ts.is_some();
+                emit(if i.is_base() {
+                         TraceKind::Base
+                     } else {
+                         TraceKind::Operator
+                     },
+                     m.data());
                 m
             }
             flow::node::Type::Source => unreachable!(),
+        };
+
+        if notify {
+            self.inner.notify_write(m.data(), write_ts.unwrap());
         }
+        m
     }
 }
 
+/// Send `m` from the egress node `index` to all of its external children, or only to the one on the
+/// replay path `m` is on if it is a replay.
+fn send_egress(index: NodeIndex,
+               txs: &Mutex<Vec<(NodeAddress, NodeAddress, mpsc::SyncSender<Packet>)>>,
+               tags: &Mutex<HashMap<Tag, NodeAddress>>,
+               m: Packet) {
+    let mut txs = txs.lock().unwrap();
+    let txn = txs.len() - 1;
+
+    // we need to find the ingress node following this egress according to the path
+    // with replay.tag, and then forward this message only on the channel corresponding
+    // to that ingress node.
+    let replay_to = if let Packet::Replay { tag, .. } = m {
+        Some(tags.lock()
+            .unwrap()
+            .get(&tag)
+            .map(|n| *n)
+            .expect("egress node told about replay message, but not on replay path"))
+    } else {
+        None
+    };
+
+    let mut m = Some(m); // so we can use .take()
+    for (txi, &mut (ref globaddr, dst, ref mut tx)) in txs.iter_mut().enumerate() {
+        let mut take = txi == txn;
+        if let Some(replay_to) = replay_to.as_ref() {
+            if replay_to == globaddr {
+                take = true;
+            } else {
+                continue;
+            }
+        }
+
+        // avoid cloning if this is last send
+        let mut m = if take {
+            m.take().unwrap()
+        } else {
+            // we know this is a data (not a replay)
+            // because, a replay will force a take
+            m.as_ref().map(|m| m.clone_data()).unwrap()
+        };
+
+        m.link_mut().src = NodeAddress::make_global(index);
+        m.link_mut().dst = dst;
+
+        tx.send(m).unwrap();
+
+        if take {
+            break;
+        }
+    }
+    debug_assert!(m.is_none());
+}
+
+/// Fill in the keys of the reader `reader` that reads have missed because they were evicted (see
+/// `backlog::EvictionPolicy`), from the state of the reader's parent if it is materialized on the
+/// reader's key. Keys that cannot be filled in this way stay evicted.
+pub fn fill_reader_misses(reader: LocalNodeIndex,
+                          w: &mut backlog::WriteHandle,
+                          r: &flow::node::Reader,
+                          nodes: &DomainNodes,
+                          state: &StateMap) {
+    let key = match r.state {
+        Some(ref s) => s.key(),
+        None => return,
+    };
+
+    // the reader itself is borrowed by the caller, but it is not its own parent anyway
+    let parent = nodes.iter()
+        .filter_map(|n| n.try_borrow().ok())
+        .find(|n| n.children.iter().any(|c| *c.as_local() == reader))
+        .map(|n| *n.addr().as_local());
+    let parent = match parent.and_then(|p| state.get(&p)) {
+        Some(s) if s.keys().contains(&vec![key]) => s,
+        _ => return,
+    };
+    w.fill_misses(|k| Some(parent.lookup(&[key], &KeyType::Single(k)).to_vec()));
+}
+
 pub fn materialize(rs: &Records, state: Option<&mut State>) {
     // our output changed -- do we need to modify materialized state?
     if state.is_none() {
@@ -165,7 +281,8 @@ pub fn materialize(rs: &Records, state: Option<&mut State>) {
         match *r {
             ops::Record::Positive(ref r) => state.insert(r.clone()),
             ops::Record::Negative(ref r) => state.remove(r),
-            ops::Record::DeleteRequest(..) => unreachable!(),
+            ops::Record::DeleteRequest(..) |
+            ops::Record::TruncateRequest => unreachable!(),
         }
     }
 }
@@ -0,0 +1,123 @@
+//! Explanations of how reads of a view are answered.
+//!
+//! An `Explanation` is obtained from `Blender::explain` for a view and a key, and describes the
+//! nodes a read of that key goes through, much like `EXPLAIN` does for a SQL query. Reads are
+//! answered by the reader of the view on their own. Only if the reader cannot answer a read (for
+//! example because the key has been evicted) do the view's ancestors matter: the explanation then
+//! lists, for every ancestor between the view and the nearest materialized state, how the rows
+//! for the key could be found there, and roughly how many of them there are.
+
+use std::fmt;
+
+use flow::NodeAddress;
+use flow::data::DataType;
+
+/// How the rows for a key are found at a node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReadAccess {
+    /// The node is a reader, and its state has an index on the key.
+    Reader,
+    /// The node is materialized, and its state has an index on the columns holding the key.
+    Index(Vec<usize>),
+    /// The node is materialized, but its state has no index on the columns holding the key (or
+    /// the key cannot be traced to this node), so all of its rows have to be scanned.
+    Scan,
+    /// The node is not materialized, so its rows are computed from those of its ancestors.
+    Computed,
+    /// The node is a base node that keeps no state, so its rows cannot be read again at all.
+    Unavailable,
+}
+
+/// A single node consulted by a read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExplainedNode {
+    /// The node.
+    pub node: NodeAddress,
+    /// The name of the node.
+    pub name: String,
+    /// A compact description of what the node does (see `Ingredient::description`).
+    pub description: String,
+    /// The domain the node runs in.
+    pub domain: usize,
+    /// The columns of this node that hold the key, or `None` if the key is computed further down
+    /// the graph, so that all of the node's rows are relevant.
+    pub columns: Option<Vec<usize>>,
+    /// How the rows for the key are found at this node.
+    pub access: ReadAccess,
+    /// The number of rows for the key held by this node, or all the rows it holds for a scan. Only
+    /// known for materialized nodes.
+    pub rows: Option<usize>,
+    /// Whether a read of the key actually consults this node. Ancestors of the view are only
+    /// consulted if the reader cannot answer the read.
+    pub consulted: bool,
+    /// How far the node is from the reader, which is at depth 0.
+    pub depth: usize,
+}
+
+/// A description of how a read of a view is answered.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Explanation {
+    /// The view that is read from.
+    pub view: NodeAddress,
+    /// The key that is read.
+    pub key: DataType,
+    /// Whether the reader of the view can answer the read from its state.
+    pub hit: bool,
+    /// The nodes involved in the read, in depth-first order, starting with the view's reader.
+    /// The ancestors of a node immediately follow it, at one greater depth.
+    pub nodes: Vec<ExplainedNode>,
+}
+
+impl Explanation {
+    /// Whether the read has to look beyond the reader, to the ancestors of the view.
+    pub fn queries_ancestors(&self) -> bool {
+        !self.hit
+    }
+
+    /// The nodes a read of the key actually consults.
+    pub fn consulted(&self) -> Vec<&ExplainedNode> {
+        self.nodes.iter().filter(|n| n.consulted).collect()
+    }
+}
+
+impl fmt::Display for ReadAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadAccess::Reader => write!(f, "reader lookup"),
+            ReadAccess::Index(ref cols) => write!(f, "index lookup on {:?}", cols),
+            ReadAccess::Scan => write!(f, "full scan"),
+            ReadAccess::Computed => write!(f, "computed"),
+            ReadAccess::Unavailable => write!(f, "no state"),
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f,
+                 "read of {:?} from view {}: {}",
+                 self.key,
+                 self.view.as_global().index(),
+                 if self.hit {
+                     "answered by reader"
+                 } else {
+                     "reader miss"
+                 })?;
+        for n in &self.nodes {
+            write!(f,
+                   "{}{} {} [{}] (node {}, domain {}): {}",
+                   "  ".repeat(n.depth + 1),
+                   if n.consulted { "->" } else { "--" },
+                   n.name,
+                   n.description,
+                   n.node.as_global().index(),
+                   n.domain,
+                   n.access)?;
+            if let Some(rows) = n.rows {
+                write!(f, ", ~{} rows", rows)?;
+            }
+            writeln!(f, "")?;
+        }
+        Ok(())
+    }
+}
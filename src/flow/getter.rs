@@ -0,0 +1,343 @@
+//! Getter handles that can be cloned cheaply, so that every thread serving reads can have its own.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use backlog;
+use flow::{NodeAddress, WriteToken};
+use flow::data::DataType;
+
+/// A handle for querying a reader node that converts every returned row into a `T`.
+///
+/// Cloning a handle is cheap, and the intended use is for every thread that serves reads to have
+/// its own clone. A handle can also keep a small cache of the converted rows for the keys it most
+/// recently read (see `GetterHandle::with_cache`), so that very hot keys are not converted over
+/// and over again. Every clone starts out with an empty cache of its own, so the cache needs no
+/// synchronization between threads.
+pub struct GetterHandle<T> {
+    handle: backlog::ReadHandle,
+    convert: Arc<Fn(&[DataType]) -> T + Send + Sync>,
+
+    // the most recently read keys, most recent first, along with the number of times the reader
+    // had swapped when each was read
+    cache: VecDeque<(DataType, usize, Arc<Vec<T>>)>,
+    capacity: usize,
+}
+
+impl<T> GetterHandle<T> {
+    pub(crate) fn new<F>(handle: backlog::ReadHandle, convert: F) -> Self
+        where F: Fn(&[DataType]) -> T + Send + Sync + 'static
+    {
+        GetterHandle {
+            handle: handle,
+            convert: Arc::new(convert),
+            cache: VecDeque::new(),
+            capacity: 0,
+        }
+    }
+
+    /// Cache the converted rows of up to `capacity` of the most recently read keys.
+    ///
+    /// Cached rows are only used until the reader next swaps in new writes, so reads through the
+    /// cache are never staler than other reads. A capacity of 0, which is the default, disables
+    /// the cache. Clones of this handle will use the same capacity.
+    ///
+    /// Reads that are answered from the cache are not seen by `ReadHandle::track_accesses`.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self.cache.clear();
+        self
+    }
+
+    /// Find all rows with the given key, converted into `T`s.
+    pub fn lookup(&mut self, key: &DataType) -> Result<Arc<Vec<T>>, ()> {
+        if self.capacity == 0 {
+            return self.find(key);
+        }
+
+        // the swap count must be read *before* the key. otherwise, a swap could happen in
+        // between, and we would cache old rows as if they reflected the new swap.
+        let swaps = self.handle.swaps();
+        if let Some(i) = self.cache.iter().position(|&(ref k, _, _)| k == key) {
+            let (k, at, rs) = self.cache.remove(i).unwrap();
+            if at == swaps {
+                self.cache.push_front((k, at, rs.clone()));
+                return Ok(rs);
+            }
+        }
+
+        let rs = self.find(key)?;
+        self.cache.push_front((key.clone(), swaps, rs.clone()));
+        self.cache.truncate(self.capacity);
+        Ok(rs)
+    }
+
+    /// Find all rows with the given key, converted into `T`s, once the reader reflects the write
+    /// that `token` was issued for.
+    ///
+    /// If the reader has yet to swap in the write, the read is retried until it has, for up to
+    /// `timeout`. Returns `Err(())` if the reader cannot answer the read (see `lookup`), or if it
+    /// still does not reflect the write when the timeout expires. The cache is not used, since it
+    /// may hold rows from before the write.
+    pub fn find_after(&self,
+                      key: &DataType,
+                      token: WriteToken,
+                      timeout: time::Duration)
+                      -> Result<Arc<Vec<T>>, ()> {
+        let convert = &self.convert;
+        let start = time::Instant::now();
+        let mut wait = time::Duration::from_millis(1);
+        loop {
+            let rs = self.handle.find_after_and(key, token.ts(), |rs| {
+                    rs.iter().map(|r| convert(&r[..])).collect()
+                })?;
+            if let Some(rs) = rs {
+                return Ok(Arc::new(rs));
+            }
+            if start.elapsed() >= timeout {
+                return Err(());
+            }
+            thread::sleep(wait);
+            if wait < time::Duration::from_millis(64) {
+                wait = wait * 2;
+            }
+        }
+    }
+
+    fn find(&self, key: &DataType) -> Result<Arc<Vec<T>>, ()> {
+        let convert = &self.convert;
+        self.handle
+            .find_and(key, |rs| rs.iter().map(|r| convert(&r[..])).collect())
+            .map(|r| Arc::new(r.0))
+    }
+}
+
+impl<T> Clone for GetterHandle<T> {
+    fn clone(&self) -> Self {
+        GetterHandle {
+            handle: self.handle.clone(),
+            convert: self.convert.clone(),
+            cache: VecDeque::new(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// A handle for reading from several reader nodes as of the same timestamp, so that results
+/// composed from several views are consistent with one another.
+///
+/// Reads name the timestamp they are made as of with a `WriteToken`, such as the one for the
+/// caller's latest write, and see each reader as it was right after the write with that timestamp.
+/// Readers only remember the rows that later writes removed for as many timestamps as they were
+/// asked to keep (see `Migration::keep_versions`), so reads as of older timestamps fail. Readers
+/// only reach a timestamp if they are downstream of the base node that the write with that
+/// timestamp was made to, so all the nodes read from should be downstream of the base nodes that
+/// timestamped writes are made to. Like `GetterHandle`s, snapshot handles are cheap to clone.
+#[derive(Clone)]
+pub struct SnapshotHandle {
+    readers: HashMap<NodeAddress, backlog::ReadHandle>,
+}
+
+impl SnapshotHandle {
+    pub(crate) fn new(readers: HashMap<NodeAddress, backlog::ReadHandle>) -> Self {
+        SnapshotHandle { readers: readers }
+    }
+
+    /// Find all rows with the given key in each of the given nodes, as of the timestamp of `at`.
+    ///
+    /// The rows for each lookup are returned in the order the lookups were given. Readers that
+    /// have yet to swap in the write with that timestamp are retried for up to `timeout`, like
+    /// with `GetterHandle::find_after`. Returns `Err(())` if a reader still has not done so when
+    /// the timeout expires, if a key has been evicted, or if a reader no longer remembers its rows
+    /// as of the timestamp.
+    pub fn lookup(&self,
+                  lookups: &[(NodeAddress, DataType)],
+                  at: WriteToken,
+                  timeout: time::Duration)
+                  -> Result<Vec<Vec<Vec<DataType>>>, ()> {
+        let start = time::Instant::now();
+        let mut wait = time::Duration::from_millis(1);
+        let mut results = vec![None; lookups.len()];
+        loop {
+            // rows as of a timestamp a reader has reached never change, so only the readers that
+            // had not reached it need to be read again
+            for (&(node, ref key), res) in lookups.iter().zip(results.iter_mut()) {
+                if res.is_some() {
+                    continue;
+                }
+                let reader = self.readers
+                    .get(&node)
+                    .expect("snapshot handles can only read from the nodes they were obtained for");
+                *res = reader.find_at_and(key, at.ts(), |rs| {
+                        rs.iter().map(|r| (**r).clone()).collect::<Vec<_>>()
+                    })?;
+            }
+            if results.iter().all(Option::is_some) {
+                return Ok(results.into_iter().map(Option::unwrap).collect());
+            }
+            if start.elapsed() >= timeout {
+                return Err(());
+            }
+            thread::sleep(wait);
+            if wait < time::Duration::from_millis(64) {
+                wait = wait * 2;
+            }
+        }
+    }
+
+    /// The nodes this handle can read from.
+    pub fn nodes(&self) -> Vec<NodeAddress> {
+        self.readers.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use backlog;
+    use ops::Record;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, PartialEq)]
+    struct Article {
+        id: i64,
+        title: String,
+    }
+
+    fn article(r: &[DataType]) -> Article {
+        Article {
+            id: r[0].clone().into(),
+            title: r[1].clone().into(),
+        }
+    }
+
+    fn row(id: i32, title: &str) -> Record {
+        Record::Positive(Arc::new(vec![id.into(), title.into()]))
+    }
+
+    #[test]
+    fn it_converts_rows() {
+        let (r, mut w) = backlog::new(2, 0);
+        let mut g = GetterHandle::new(r, article);
+        assert_eq!(g.lookup(&1.into()), Err(()));
+
+        w.add(vec![row(1, "a")]);
+        w.swap();
+        assert_eq!(*g.lookup(&1.into()).unwrap(),
+                   vec![Article {
+                            id: 1,
+                            title: "a".into(),
+                        }]);
+        assert!(g.lookup(&2.into()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_caches_until_swap() {
+        let conversions = Arc::new(AtomicUsize::new(0));
+        let c = conversions.clone();
+
+        let (r, mut w) = backlog::new(2, 0);
+        w.add(vec![row(1, "a"), row(2, "b")]);
+        w.swap();
+
+        let mut g = GetterHandle::new(r, move |r| {
+                c.fetch_add(1, Ordering::SeqCst);
+                article(r)
+            })
+            .with_cache(1);
+
+        // repeated reads of a hot key are only converted once
+        assert_eq!(g.lookup(&1.into()).unwrap()[0].title, "a");
+        assert_eq!(g.lookup(&1.into()).unwrap()[0].title, "a");
+        assert_eq!(conversions.load(Ordering::SeqCst), 1);
+
+        // the cache only holds one key, so reading another pushes out the first
+        g.lookup(&2.into()).unwrap();
+        g.lookup(&1.into()).unwrap();
+        assert_eq!(conversions.load(Ordering::SeqCst), 3);
+
+        // clones start out with an empty cache
+        let mut g2 = g.clone();
+        g2.lookup(&1.into()).unwrap();
+        assert_eq!(conversions.load(Ordering::SeqCst), 4);
+
+        // and new writes are seen once they are swapped in
+        w.add(vec![row(1, "c")]);
+        g.lookup(&1.into()).unwrap();
+        assert_eq!(conversions.load(Ordering::SeqCst), 4);
+        w.swap();
+        assert_eq!(g.lookup(&1.into()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn it_waits_for_writes() {
+        use std::thread;
+        use std::time::Duration;
+
+        let (r, mut w) = backlog::new(2, 0);
+        let g = GetterHandle::new(r, article);
+        w.update_ts(1);
+        w.add(vec![row(1, "a")]);
+        w.swap();
+
+        // the reader already reflects the first write
+        let token = WriteToken::from(1);
+        assert_eq!(g.find_after(&1.into(), token, Duration::from_millis(0)).unwrap().len(), 1);
+
+        // but not the second one, until it has been swapped in
+        let token = WriteToken::from(2);
+        assert_eq!(g.find_after(&1.into(), token, Duration::from_millis(10)), Err(()));
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            w.update_ts(2);
+            w.add(vec![row(1, "b")]);
+            w.swap();
+            w
+        });
+        assert_eq!(g.find_after(&1.into(), token, Duration::from_secs(5)).unwrap().len(), 2);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn it_reads_snapshots() {
+        use petgraph::graph::NodeIndex;
+        use std::time::Duration;
+
+        let (ra, mut wa) = backlog::new(2, 0);
+        let (rb, mut wb) = backlog::new(2, 0);
+        wa.keep_versions(1);
+        wb.keep_versions(1);
+        let a = NodeAddress::mock_global(NodeIndex::new(0));
+        let b = NodeAddress::mock_global(NodeIndex::new(1));
+        let mut readers = HashMap::new();
+        readers.insert(a, ra);
+        readers.insert(b, rb);
+        let s = SnapshotHandle::new(readers);
+
+        for w in vec![&mut wa, &mut wb] {
+            w.update_ts(1);
+            w.add(vec![row(1, "a")]);
+            w.swap();
+        }
+        // only one of the readers has moved on to the next timestamp
+        wa.update_ts(2);
+        wa.add(vec![Record::Negative(Arc::new(vec![1.into(), "a".into()])), row(1, "b")]);
+        wa.swap();
+
+        let lookups = vec![(a, 1.into()), (b, 1.into())];
+        let rs = s.lookup(&lookups, WriteToken::from(1), Duration::from_millis(0)).unwrap();
+        assert_eq!(rs,
+                   vec![vec![vec![1.into(), "a".into()]], vec![vec![1.into(), "a".into()]]]);
+        assert_eq!(s.lookup(&lookups, WriteToken::from(2), Duration::from_millis(10)),
+                   Err(()));
+
+        wb.update_ts(2);
+        wb.swap();
+        let rs = s.lookup(&lookups, WriteToken::from(2), Duration::from_millis(0)).unwrap();
+        assert_eq!(rs,
+                   vec![vec![vec![1.into(), "b".into()]], vec![vec![1.into(), "a".into()]]]);
+    }
+}
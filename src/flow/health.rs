@@ -0,0 +1,46 @@
+//! Summaries of whether a running graph is able to make progress.
+
+use std::collections::HashMap;
+use std::time;
+
+use flow::prelude::*;
+use flow::domain;
+
+/// The state of a single domain, as observed by `Blender::health`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DomainHealth {
+    /// The domain answered a liveness probe.
+    Alive,
+    /// The domain's input queue was full, so it could not be probed.
+    ///
+    /// This usually means that the domain is busy, but it may also mean that it has stalled.
+    Saturated,
+    /// The domain did not answer a liveness probe in time.
+    Unresponsive,
+    /// The domain has stopped processing updates because of the given error.
+    ///
+    /// Domains only fail this way if `Blender::isolate_domain_failures` was enabled when they
+    /// were booted; otherwise, a failure takes down the domain thread, and the domain is
+    /// reported as `Unresponsive`.
+    Failed(String),
+}
+
+/// A summary of the health of an entire graph.
+#[derive(Clone, Debug)]
+pub struct Health {
+    /// The state of every domain.
+    pub domains: HashMap<domain::Index, DomainHealth>,
+    /// For every node with an external reader, the time since that reader last swapped in new
+    /// writes, or `None` if it has never swapped.
+    pub readers: HashMap<NodeAddress, Option<time::Duration>>,
+}
+
+impl Health {
+    /// Whether every domain is alive or merely saturated.
+    pub fn is_healthy(&self) -> bool {
+        self.domains.values().all(|d| match *d {
+            DomainHealth::Alive | DomainHealth::Saturated => true,
+            DomainHealth::Unresponsive | DomainHealth::Failed(_) => false,
+        })
+    }
+}
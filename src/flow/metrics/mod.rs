@@ -0,0 +1,283 @@
+//! Counters and histograms describing the work done by the domains of a graph.
+//!
+//! Every domain keeps a `Recorder` with the number of records each of its nodes has processed and
+//! produced, how long each node took to process each update, and how many packets were queued up
+//! for the domain whenever it got to them. Together with the hit and miss counts of the readers,
+//! and the durations of the replays performed by migrations, these can be collected from a
+//! running graph at any time through a `Collector` (see `Blender::metrics_collector`).
+//!
+//! Metrics can be handed to a monitoring system by implementing `Exporter`, and calling
+//! `Collector::export_every`. With the `prometheus` feature, the `prometheus` module additionally
+//! renders metrics in the Prometheus text format, and can serve them over HTTP.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time;
+
+use flow::prelude::*;
+use flow::{domain, statistics, transport};
+use flow::payload::Packet;
+use flow::trace::Histogram;
+
+#[cfg(feature="prometheus")]
+pub mod prometheus;
+
+/// Metrics about the updates processed by a single node. Times are in nanoseconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeMetrics {
+    /// Number of records the node has been given to process.
+    pub records_in: u64,
+    /// Number of records the node has produced for its children.
+    pub records_out: u64,
+    /// How long the node took to process each update it was given.
+    pub process_time: Histogram,
+}
+
+/// Metrics about the lookups made through the getters of a single reader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReaderMetrics {
+    /// Number of lookups answered by the reader.
+    pub hits: u64,
+    /// Number of lookups the reader could not answer (see `ReadStats::misses`).
+    pub misses: u64,
+}
+
+/// Metrics about a single domain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DomainMetrics {
+    /// Number of packets the domain has received.
+    pub packets: u64,
+    /// The number of packets the domain has worked through each time before it found its input
+    /// channel empty.
+    ///
+    /// Channels do not reveal how many packets they hold, so this is an approximation of how deep
+    /// the domain's queue got: a domain that keeps up with its inputs records mostly ones, while a
+    /// domain that falls behind records long runs.
+    pub queue_depth: Histogram,
+    /// Metrics for every node in the domain that has processed at least one update.
+    pub nodes: HashMap<NodeAddress, NodeMetrics>,
+    /// Lookup counts for every reader in the domain.
+    pub readers: HashMap<NodeAddress, ReaderMetrics>,
+}
+
+/// Metrics about an entire graph.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphMetrics {
+    /// Metrics for every domain that responded.
+    pub domains: HashMap<domain::Index, DomainMetrics>,
+    /// How long each replay performed by a migration took, in nanoseconds.
+    pub replay_time: Histogram,
+    /// How many records each replay performed by a migration carried.
+    pub replay_records: Histogram,
+    /// Approximately how many bytes each replay performed by a migration carried.
+    pub replay_bytes: Histogram,
+}
+
+impl GraphMetrics {
+    /// The metrics for the given node, if its domain responded and it has processed any updates.
+    pub fn node(&self, node: NodeAddress) -> Option<&NodeMetrics> {
+        self.domains.values().filter_map(|d| d.nodes.get(&node)).next()
+    }
+
+    /// The lookup counts for the given reader, if its domain responded.
+    pub fn reader(&self, node: NodeAddress) -> Option<ReaderMetrics> {
+        self.domains.values().filter_map(|d| d.readers.get(&node)).cloned().next()
+    }
+}
+
+/// Records the metrics of a single domain as it processes updates.
+#[derive(Default)]
+pub struct Recorder {
+    packets: u64,
+    run: u64,
+    queue_depth: Histogram,
+    nodes: HashMap<LocalNodeIndex, NodeMetrics>,
+}
+
+impl Recorder {
+    /// Count a packet received by the domain.
+    pub fn received(&mut self) {
+        self.packets += 1;
+        self.run += 1;
+    }
+
+    /// Note that the domain found its input channel empty.
+    pub fn drained(&mut self) {
+        if self.run != 0 {
+            self.queue_depth.record(self.run);
+            self.run = 0;
+        }
+    }
+
+    /// Record that `node` turned `records_in` records into `records_out` records in `took`.
+    pub fn processed(&mut self,
+                     node: LocalNodeIndex,
+                     records_in: usize,
+                     records_out: usize,
+                     took: time::Duration) {
+        let m = self.nodes.entry(node).or_insert_with(NodeMetrics::default);
+        m.records_in += records_in as u64;
+        m.records_out += records_out as u64;
+        m.process_time.record(took.as_secs() * 1_000_000_000 + took.subsec_nanos() as u64);
+    }
+
+    /// The metrics recorded so far, with `node` giving the global address of each local node, and
+    /// `readers` the lookup counts of the domain's readers.
+    pub fn metrics<F>(&self,
+                      node: F,
+                      readers: HashMap<NodeAddress, ReaderMetrics>)
+                      -> DomainMetrics
+        where F: Fn(LocalNodeIndex) -> Option<NodeAddress>
+    {
+        DomainMetrics {
+            packets: self.packets,
+            queue_depth: self.queue_depth.clone(),
+            nodes: self.nodes
+                .iter()
+                .filter_map(|(&ni, m)| node(ni).map(|addr| (addr, m.clone())))
+                .collect(),
+            readers: readers,
+        }
+    }
+}
+
+/// Something that periodically hands metrics on elsewhere, for example to a monitoring system.
+pub trait Exporter: Send {
+    /// Export the given metrics. Returning an error stops any further exports.
+    fn export(&mut self, metrics: &GraphMetrics) -> io::Result<()>;
+}
+
+/// Collects metrics from the domains of a `Blender`.
+///
+/// A `Collector` can be cloned and moved to other threads, and keeps working as the graph is
+/// migrated. Domains booted after the collector was obtained are included as well.
+#[derive(Clone)]
+pub struct Collector {
+    domains: transport::Inputs,
+    replays: Arc<Mutex<(Histogram, Histogram, Histogram)>>,
+}
+
+impl Collector {
+    pub(crate) fn new(domains: transport::Inputs) -> Self {
+        Collector {
+            domains: domains,
+            replays: Arc::default(),
+        }
+    }
+
+    /// Record a replay performed by a migration.
+    pub(crate) fn replayed(&self, replay: &statistics::ReplayStats) {
+        let mut replays = self.replays.lock().unwrap();
+        replays.0.record(replay.duration);
+        replays.1.record(replay.records as u64);
+        replays.2.record(replay.bytes as u64);
+    }
+
+    /// Collect metrics from every domain, along with the number of domains that did not respond
+    /// because they have shut down.
+    fn gather(&self) -> (GraphMetrics, usize) {
+        let domains: Vec<_> = self.domains
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(di, tx)| (*di, tx.clone()))
+            .collect();
+
+        let mut gone = 0;
+        let mut metrics = GraphMetrics::default();
+        for (di, tx) in domains {
+            let (mtx, mrx) = mpsc::sync_channel(1);
+            if tx.send(Packet::GetMetrics(mtx)).is_err() {
+                gone += 1;
+                continue;
+            }
+            match mrx.recv() {
+                Ok(m) => {
+                    metrics.domains.insert(di, m);
+                }
+                Err(_) => gone += 1,
+            }
+        }
+
+        let replays = self.replays.lock().unwrap();
+        metrics.replay_time = replays.0.clone();
+        metrics.replay_records = replays.1.clone();
+        metrics.replay_bytes = replays.2.clone();
+        (metrics, gone)
+    }
+
+    /// Collect the current metrics of every domain that is still running.
+    pub fn collect(&self) -> GraphMetrics {
+        self.gather().0
+    }
+
+    /// Hand the current metrics to `exporter` every `interval` on a separate thread.
+    ///
+    /// Exporting stops when `exporter` returns an error, or once all the domains of the graph
+    /// have shut down.
+    pub fn export_every<E>(&self,
+                           interval: time::Duration,
+                           mut exporter: E)
+                           -> thread::JoinHandle<()>
+        where E: Exporter + 'static
+    {
+        let collector = self.clone();
+        thread::Builder::new()
+            .name("metrics".to_owned())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                let (metrics, gone) = collector.gather();
+                if gone != 0 && metrics.domains.is_empty() {
+                    break;
+                }
+                if exporter.export(&metrics).is_err() {
+                    break;
+                }
+            })
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use petgraph::graph::NodeIndex;
+
+    #[test]
+    fn it_records_queue_depth() {
+        let mut r = Recorder::default();
+        r.drained();
+        r.received();
+        r.drained();
+        for _ in 0..5 {
+            r.received();
+        }
+        r.drained();
+        r.drained();
+
+        let m = r.metrics(|_| None, HashMap::new());
+        assert_eq!(m.packets, 6);
+        assert_eq!(m.queue_depth.count(), 2);
+        assert_eq!(m.queue_depth.max(), 5);
+    }
+
+    #[test]
+    fn it_records_nodes() {
+        let l0 = *NodeAddress::mock_local(0).as_local();
+        let l1 = *NodeAddress::mock_local(1).as_local();
+        let mut r = Recorder::default();
+        r.processed(l0, 3, 1, time::Duration::from_millis(1));
+        r.processed(l0, 2, 0, time::Duration::from_millis(3));
+        r.processed(l1, 1, 1, time::Duration::from_millis(1));
+
+        let a = NodeAddress::mock_global(NodeIndex::new(0));
+        let m = r.metrics(|ni| if ni == l0 { Some(a) } else { None }, HashMap::new());
+        assert_eq!(m.nodes.len(), 1);
+        assert_eq!(m.nodes[&a].records_in, 5);
+        assert_eq!(m.nodes[&a].records_out, 1);
+        assert_eq!(m.nodes[&a].process_time.count(), 2);
+        assert_eq!(m.nodes[&a].process_time.sum(), 4_000_000);
+    }
+}
@@ -0,0 +1,294 @@
+//! Export of metrics in the Prometheus text format.
+//!
+//! `render` formats a set of metrics as a Prometheus text exposition, and `serve` answers HTTP
+//! requests for `/metrics` with the current metrics of a graph, so that a Prometheus server can
+//! scrape them directly. Times are reported in seconds, as Prometheus expects. Node metrics are
+//! labelled with the domain and the global index of the node.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+use flow::trace::Histogram;
+use super::{Collector, Exporter, GraphMetrics};
+
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+/// `name`, with the given labels if there are any.
+fn labelled(name: &str, labels: &str) -> String {
+    if labels.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}{{{}}}", name, labels)
+    }
+}
+
+/// Write `h` as a Prometheus histogram called `name`, dividing every value by `scale`.
+fn histogram(out: &mut String, name: &str, labels: &str, h: &Histogram, scale: f64) {
+    let sep = if labels.is_empty() { "" } else { "," };
+    let buckets = h.cumulative()
+        .into_iter()
+        .map(|(bound, count)| (format!("{}", bound as f64 / scale), count))
+        .chain(Some((String::from("+Inf"), h.count())));
+    for (le, count) in buckets {
+        writeln!(out,
+                 "{}_bucket{{{}{}le=\"{}\"}} {}",
+                 name,
+                 labels,
+                 sep,
+                 le,
+                 count)
+            .unwrap();
+    }
+    writeln!(out,
+             "{} {}",
+             labelled(&format!("{}_sum", name), labels),
+             h.sum() as f64 / scale)
+        .unwrap();
+    writeln!(out,
+             "{} {}",
+             labelled(&format!("{}_count", name), labels),
+             h.count())
+        .unwrap();
+}
+
+/// Render `metrics` in the Prometheus text exposition format.
+pub fn render(metrics: &GraphMetrics) -> String {
+    let mut out = String::new();
+
+    let mut domains: Vec<_> = metrics.domains.iter().collect();
+    domains.sort_by_key(|&(di, _)| *di);
+
+    header(&mut out,
+           "distributary_domain_packets_total",
+           "counter",
+           "Packets received by a domain.");
+    for &(di, d) in &domains {
+        writeln!(out,
+                 "distributary_domain_packets_total{{domain=\"{}\"}} {}",
+                 di.index(),
+                 d.packets)
+            .unwrap();
+    }
+
+    header(&mut out,
+           "distributary_domain_queue_depth",
+           "histogram",
+           "Packets a domain worked through before it found its input channel empty.");
+    for &(di, d) in &domains {
+        let labels = format!("domain=\"{}\"", di.index());
+        histogram(&mut out,
+                  "distributary_domain_queue_depth",
+                  &labels,
+                  &d.queue_depth,
+                  1.0);
+    }
+
+    let mut nodes = Vec::new();
+    let mut readers = Vec::new();
+    for &(di, d) in &domains {
+        nodes.extend(d.nodes.iter().map(|(a, m)| (di.index(), a.as_global().index(), m)));
+        readers.extend(d.readers.iter().map(|(a, m)| (di.index(), a.as_global().index(), m)));
+    }
+    nodes.sort_by_key(|&(di, ni, _)| (di, ni));
+    readers.sort_by_key(|&(di, ni, _)| (di, ni));
+
+    header(&mut out,
+           "distributary_node_records_in_total",
+           "counter",
+           "Records given to a node to process.");
+    for &(di, ni, m) in &nodes {
+        writeln!(out,
+                 "distributary_node_records_in_total{{domain=\"{}\",node=\"{}\"}} {}",
+                 di,
+                 ni,
+                 m.records_in)
+            .unwrap();
+    }
+
+    header(&mut out,
+           "distributary_node_records_out_total",
+           "counter",
+           "Records produced by a node for its children.");
+    for &(di, ni, m) in &nodes {
+        writeln!(out,
+                 "distributary_node_records_out_total{{domain=\"{}\",node=\"{}\"}} {}",
+                 di,
+                 ni,
+                 m.records_out)
+            .unwrap();
+    }
+
+    header(&mut out,
+           "distributary_node_process_seconds",
+           "histogram",
+           "Time a node took to process an update.");
+    for &(di, ni, m) in &nodes {
+        let labels = format!("domain=\"{}\",node=\"{}\"", di, ni);
+        histogram(&mut out,
+                  "distributary_node_process_seconds",
+                  &labels,
+                  &m.process_time,
+                  NANOS_PER_SEC);
+    }
+
+    header(&mut out,
+           "distributary_reader_hits_total",
+           "counter",
+           "Lookups answered by a reader.");
+    for &(di, ni, m) in &readers {
+        writeln!(out,
+                 "distributary_reader_hits_total{{domain=\"{}\",node=\"{}\"}} {}",
+                 di,
+                 ni,
+                 m.hits)
+            .unwrap();
+    }
+
+    header(&mut out,
+           "distributary_reader_misses_total",
+           "counter",
+           "Lookups a reader could not answer.");
+    for &(di, ni, m) in &readers {
+        writeln!(out,
+                 "distributary_reader_misses_total{{domain=\"{}\",node=\"{}\"}} {}",
+                 di,
+                 ni,
+                 m.misses)
+            .unwrap();
+    }
+
+    header(&mut out,
+           "distributary_replay_seconds",
+           "histogram",
+           "Time taken by a replay that populated new materialized state.");
+    histogram(&mut out,
+              "distributary_replay_seconds",
+              "",
+              &metrics.replay_time,
+              NANOS_PER_SEC);
+
+    header(&mut out,
+           "distributary_replay_records",
+           "histogram",
+           "Records carried by a replay that populated new materialized state.");
+    histogram(&mut out,
+              "distributary_replay_records",
+              "",
+              &metrics.replay_records,
+              1.0);
+
+    header(&mut out,
+           "distributary_replay_bytes",
+           "histogram",
+           "Approximate bytes carried by a replay that populated new materialized state.");
+    histogram(&mut out,
+              "distributary_replay_bytes",
+              "",
+              &metrics.replay_bytes,
+              1.0);
+
+    out
+}
+
+/// An `Exporter` that writes every set of metrics it is given to `W` in the Prometheus text format,
+/// one after the other.
+pub struct TextExporter<W: Write + Send>(pub W);
+
+impl<W: Write + Send> Exporter for TextExporter<W> {
+    fn export(&mut self, metrics: &GraphMetrics) -> io::Result<()> {
+        self.0.write_all(render(metrics).as_bytes())?;
+        self.0.flush()
+    }
+}
+
+/// Answer a single HTTP request on `stream`.
+fn respond(stream: TcpStream, collector: &Collector) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // skip the headers; we do not need any of them
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(&collector.collect())),
+        (Some("GET"), Some(_)) => ("404 Not Found", String::from("not found\n")),
+        _ => ("405 Method Not Allowed", String::from("method not allowed\n")),
+    };
+
+    let mut stream = stream;
+    write!(stream,
+           "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+            Connection: close\r\n\r\n",
+           status,
+           body.len())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+/// Serve the metrics collected by `collector` over HTTP on `addr`, and return the address that is
+/// listened on.
+///
+/// Metrics are available at `/metrics`, and are collected afresh for every request. Requests are
+/// answered one at a time on a separate thread.
+pub fn serve<A: ToSocketAddrs>(addr: A, collector: Collector) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local = listener.local_addr()?;
+    thread::Builder::new()
+        .name("metrics-http".to_owned())
+        .spawn(move || for stream in listener.incoming() {
+            if let Ok(stream) = stream {
+                // a client that goes away mid-request is not our problem
+                let _ = respond(stream, &collector);
+            }
+        })?;
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flow::metrics::{DomainMetrics, NodeMetrics};
+    use flow::NodeAddress;
+    use petgraph::graph::NodeIndex;
+
+    #[test]
+    fn it_renders() {
+        let mut d = DomainMetrics::default();
+        d.packets = 3;
+        d.queue_depth.record(1);
+        let mut n = NodeMetrics::default();
+        n.records_in = 5;
+        n.records_out = 2;
+        n.process_time.record(1_000);
+        d.nodes.insert(NodeAddress::mock_global(NodeIndex::new(4)), n);
+
+        let mut m = GraphMetrics::default();
+        m.domains.insert(0.into(), d);
+        m.replay_time.record(2_000_000_000);
+
+        let out = render(&m);
+        assert!(out.contains("distributary_domain_packets_total{domain=\"0\"} 3\n"));
+        assert!(out.contains("distributary_node_records_in_total{domain=\"0\",node=\"4\"} 5\n"));
+        assert!(out.contains("distributary_node_records_out_total{domain=\"0\",node=\"4\"} 2\n"));
+        assert!(out.contains("distributary_node_process_seconds_count{domain=\"0\",node=\"4\"} \
+                              1\n"));
+        assert!(out.contains("distributary_node_process_seconds_bucket{domain=\"0\",node=\"4\",\
+                              le=\"+Inf\"} 1\n"));
+        assert!(out.contains("distributary_replay_seconds_sum 2\n"));
+        assert!(out.contains("# TYPE distributary_reader_hits_total counter\n"));
+    }
+}
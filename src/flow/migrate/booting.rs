@@ -3,6 +3,7 @@
 //! This includes constructing local identifiers for nodes, construcing domain-local structures
 //! such as `DomainNodes`, and initializing transaction handling.
 
+use flow;
 use flow::prelude::*;
 use flow::domain::single;
 use flow::domain;
@@ -29,8 +30,11 @@ pub fn boot_new(log: Logger,
                 nodes: Vec<(NodeIndex, bool)>,
                 checktable: Arc<Mutex<checktable::CheckTable>>,
                 rx: mpsc::Receiver<Packet>,
-                ts: i64) {
+                ts: i64,
+                batching: flow::Batching,
+                core: Option<usize>,
+                failures: Option<domain::Failures>) {
     let nodes = build_descriptors(graph, nodes);
-    let domain = domain::Domain::new(log, index, nodes, checktable, ts);
-    domain.boot(rx)
+    let domain = domain::Domain::new(log, index, nodes, checktable, ts, batching);
+    domain.boot(rx, core, failures)
 }
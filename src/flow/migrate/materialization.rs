@@ -8,6 +8,7 @@
 use flow;
 use flow::domain;
 use flow::prelude::*;
+use flow::statistics::ReplayStats;
 
 use petgraph;
 use petgraph::graph::NodeIndex;
@@ -280,26 +281,208 @@ pub fn index(log: &Logger,
         .collect()
 }
 
+/// Predict which of the given new nodes will be materialized, and what indices the states they
+/// and their ancestors need will have, before the nodes have been assigned to domains.
+///
+/// This follows `pick` and `index`, except that lookups are assumed to go directly to the
+/// ancestor a node queries. Nodes that end up in a different domain than that ancestor instead
+/// look up records in a materialized ingress node, which holds the same records, and indices
+/// that an existing ancestor already has are reported anyway.
+pub fn preview(graph: &Graph, new: &HashSet<NodeIndex>) -> HashMap<NodeIndex, Vec<Vec<usize>>> {
+    let mut materialize: HashMap<NodeIndex, HashSet<Vec<usize>>> = HashMap::new();
+    for &ni in new {
+        let n = &graph[ni];
+        if !n.is_internal() {
+            continue;
+        }
+        if n.should_materialize() ||
+           graph.edges_directed(ni, petgraph::EdgeDirection::Outgoing).any(|e| *e.weight()) {
+            materialize.entry(ni).or_insert_with(HashSet::new);
+        }
+    }
+
+    for &ni in new {
+        let n = &graph[ni];
+        if !n.is_internal() || !n.will_query(false) {
+            continue;
+        }
+        for (v, idx) in n.suggest_indexes(NodeAddress::make_global(ni)) {
+            materialize.entry(*v.as_global()).or_insert_with(HashSet::new).insert(idx);
+        }
+    }
+
+    materialize.into_iter()
+        .map(|(ni, idxs)| {
+            let mut idxs: Vec<_> = idxs.into_iter().collect();
+            idxs.sort();
+            (ni, idxs)
+        })
+        .collect()
+}
+
+/// Predict the replays that will populate the given new materializations, before the nodes have
+/// been assigned to domains.
+///
+/// `targets` holds the new nodes that will have state, and `materialized` tells whether an
+/// existing node has state. This follows `initialize` and `trace`, except that a join that could
+/// replay through several of its ancestors is assumed to go through the one it is told to replay
+/// through, or else the first one, since the sizes of the ancestors' states are not considered.
+/// Each returned path starts at the materialization replayed from, and ends at the node it
+/// populates.
+pub fn preview_replays(graph: &Graph,
+                       source: NodeIndex,
+                       new: &HashSet<NodeIndex>,
+                       targets: &HashSet<NodeIndex>,
+                       materialized: &Fn(NodeIndex) -> bool)
+                       -> Vec<Vec<NodeIndex>> {
+    let has_state = |ni: NodeIndex| if new.contains(&ni) {
+        targets.contains(&ni)
+    } else {
+        materialized(ni)
+    };
+
+    let mut empty = HashSet::new();
+    let mut replays = Vec::new();
+    let mut topo = petgraph::visit::Topo::new(graph);
+    while let Some(node) = topo.next(graph) {
+        if node == source || !new.contains(&node) {
+            continue;
+        }
+
+        if graph.neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+            .filter(|&ni| ni != source)
+            .all(|n| empty.contains(&n)) {
+            empty.insert(node);
+        } else if targets.contains(&node) {
+            for mut path in preview_trace(graph, node, &empty, &has_state, vec![node]) {
+                path.reverse();
+                replays.push(path);
+            }
+        }
+    }
+    replays
+}
+
+/// Find the paths that `preview_replays` predicts will be replayed along to populate `node`.
+fn preview_trace(graph: &Graph,
+                 node: NodeIndex,
+                 empty: &HashSet<NodeIndex>,
+                 has_state: &Fn(NodeIndex) -> bool,
+                 path: Vec<NodeIndex>)
+                 -> Vec<Vec<NodeIndex>> {
+    if path.len() != 1 && has_state(node) {
+        return vec![path];
+    }
+
+    let mut parents: Vec<_> = graph.neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+        .collect();
+    assert!(!parents.is_empty(), "base node was not materialized!");
+
+    let n = &graph[node];
+    if parents.len() > 1 {
+        if let Some(candidates) = n.replay_ancestors() {
+            // nodes that have not been committed yet refer to their ancestors by global address
+            let candidates: Vec<_> = candidates.into_iter()
+                .map(|a| {
+                    *parents.iter()
+                        .find(|&&p| graph[p].addr() == a || NodeAddress::make_global(p) == a)
+                        .expect("replay ancestor is not a parent")
+                })
+                .collect();
+            if candidates.iter().any(|c| empty.contains(c)) {
+                return Vec::new();
+            }
+            let hinted = n.replay_via()
+                .and_then(|via| candidates.iter().find(|&&c| origin(graph, c) == via).cloned());
+            parents = vec![hinted.unwrap_or(candidates[0])];
+        }
+    }
+
+    parents.retain(|&parent| !empty.contains(&parent));
+    parents.into_iter()
+        .flat_map(|parent| {
+            let mut path = path.clone();
+            path.push(parent);
+            preview_trace(graph, parent, empty, has_state, path)
+        })
+        .collect()
+}
+
 pub fn initialize(log: &Logger,
                   graph: &Graph,
                   source: NodeIndex,
                   new: &HashSet<NodeIndex>,
                   mut materialize: HashMap<domain::Index,
                                            HashMap<LocalNodeIndex, Vec<Vec<usize>>>>,
-                  txs: &mut HashMap<domain::Index, mpsc::SyncSender<Packet>>) {
+                  materialized: &mut HashMap<domain::Index,
+                                             HashMap<LocalNodeIndex, Vec<Vec<usize>>>>,
+                  replay_source: flow::ReplaySource,
+                  pacing: flow::ReplayPacing,
+                  txs: &mut HashMap<domain::Index, mpsc::SyncSender<Packet>>)
+                  -> Vec<ReplayStats> {
+    let readers = replay_readers(graph, new, replay_source);
+    let mut replays = Vec::new();
     let mut topo_list = Vec::with_capacity(new.len());
+    let mut existing = Vec::new();
     let mut topo = petgraph::visit::Topo::new(&*graph);
     while let Some(node) = topo.next(&*graph) {
         if node == source {
             continue;
         }
         if !new.contains(&node) {
+            existing.push(node);
             continue;
         }
         topo_list.push(node);
     }
 
-    // TODO: what about adding materialization to *existing* views?
+    // the new nodes may need existing nodes to have state that they do not have yet. we deal with
+    // those first, since the new nodes may replay from them. a node that is already materialized
+    // only needs the new indices built from the rows it holds, whereas any other node has to have
+    // its state replayed from its ancestors like a new node would.
+    for node in existing {
+        let n = &graph[node];
+        let d = n.domain();
+        let want = match materialize.get(&d).and_then(|ss| ss.get(n.addr().as_local())) {
+            Some(idxs) => idxs,
+            None => continue,
+        };
+
+        match materialized.get(&d).and_then(|ss| ss.get(n.addr().as_local())) {
+            Some(have) => {
+                let missing: Vec<_> =
+                    want.iter().filter(|idx| !have.contains(idx)).cloned().collect();
+                if missing.is_empty() {
+                    continue;
+                }
+                info!(log, "adding indices to existing view";
+                      "node" => node.index(),
+                      "cols" => format!("{:?}", missing));
+                txs[&d]
+                    .send(Packet::AddIndices {
+                        node: *n.addr().as_local(),
+                        index: missing,
+                    })
+                    .unwrap();
+            }
+            None => {
+                let start = ::std::time::Instant::now();
+                let log = log.new(o!("node" => node.index()));
+                info!(log, "beginning reconstruction of existing {:?}", *graph[node]);
+                replays.extend(reconstruct(&log,
+                                           graph,
+                                           source,
+                                           &HashSet::new(),
+                                           &materialize,
+                                           &readers,
+                                           pacing,
+                                           txs,
+                                           node,
+                                           want.clone()));
+                info!(log, "reconstruction completed"; "ms" => dur_to_ns!(start.elapsed()) / 1_000_000);
+            }
+        }
+    }
 
     let mut empty = HashSet::new();
     for node in topo_list {
@@ -366,14 +549,16 @@ pub fn initialize(log: &Logger,
             let start = ::std::time::Instant::now();
             let log = log.new(o!("node" => node.index()));
             info!(log, "beginning reconstruction of {:?}", *graph[node]);
-            reconstruct(&log,
-                        graph,
-                        source,
-                        &empty,
-                        &materialize,
-                        txs,
-                        node,
-                        index_on);
+            replays.extend(reconstruct(&log,
+                                       graph,
+                                       source,
+                                       &empty,
+                                       &materialize,
+                                       &readers,
+                                       pacing,
+                                       txs,
+                                       node,
+                                       index_on));
             debug!(log, "reconstruction started");
             // NOTE: the state has already been marked ready by the replay completing,
             // but we want to wait for the domain to finish replay, which a Ready does.
@@ -381,6 +566,53 @@ pub fn initialize(log: &Logger,
             info!(log, "reconstruction completed"; "ms" => dur_to_ns!(start.elapsed()) / 1_000_000);
         }
     }
+
+    // remember what every node has been given, so that later migrations know what is missing
+    for (d, nodes) in materialize {
+        let have = materialized.entry(d).or_insert_with(HashMap::new);
+        for (n, idxs) in nodes {
+            let have = have.entry(n).or_insert_with(Vec::new);
+            for idx in idxs {
+                if !have.contains(&idx) {
+                    have.push(idx);
+                }
+            }
+        }
+    }
+
+    replays
+}
+
+/// Find the readers whose state may seed replays, keyed by the node they are a reader for.
+///
+/// Each reader is accompanied by whether it should be preferred over its node's internal state,
+/// or only be used if the node has none.
+fn replay_readers(graph: &Graph,
+                  new: &HashSet<NodeIndex>,
+                  replay_source: flow::ReplaySource)
+                  -> HashMap<NodeIndex, (NodeIndex, bool)> {
+    let prefer = match replay_source {
+        flow::ReplaySource::Internal => return HashMap::new(),
+        flow::ReplaySource::PreferInternal => false,
+        flow::ReplaySource::PreferReader => true,
+    };
+
+    graph.node_indices()
+        .filter(|ri| !new.contains(ri))
+        .filter(|&ri| if let flow::node::Type::Reader(_, ref r) = *graph[ri] {
+            // the reader must hold full rows, and must never lag behind its node
+            r.swap == flow::node::SwapPolicy::EveryBatch &&
+            r.state.as_ref().map(|s| !s.is_counting()).unwrap_or(false)
+        } else {
+            false
+        })
+        .map(|ri| {
+            let n = graph.neighbors_directed(ri, petgraph::EdgeDirection::Incoming)
+                .next()
+                .expect("reader has no parent");
+            (n, (ri, prefer))
+        })
+        .collect()
 }
 
 pub fn reconstruct(log: &Logger,
@@ -389,9 +621,12 @@ pub fn reconstruct(log: &Logger,
                    empty: &HashSet<NodeIndex>,
                    materialized: &HashMap<domain::Index,
                                           HashMap<LocalNodeIndex, Vec<Vec<usize>>>>,
+                   readers: &HashMap<NodeIndex, (NodeIndex, bool)>,
+                   pacing: flow::ReplayPacing,
                    txs: &mut HashMap<domain::Index, mpsc::SyncSender<Packet>>,
                    node: NodeIndex,
-                   index_on: Vec<Vec<usize>>) {
+                   index_on: Vec<Vec<usize>>)
+                   -> Vec<ReplayStats> {
 
     // okay, so here's the situation: `node` is a node that
     //
@@ -410,7 +645,28 @@ pub fn reconstruct(log: &Logger,
     //   4. tell the domain nearest to the root to start replaying
     //
     // so, first things first, let's find our closest materialized parents
-    let paths = trace(graph, source, node, empty, materialized, vec![node]);
+    let paths = {
+        // when there is a choice, we prefer to replay fewer records
+        let txs = &*txs;
+        let estimate = |ni: NodeIndex| -> usize {
+            let (tx, rx) = mpsc::sync_channel(1);
+            txs[&graph[ni].domain()]
+                .send(Packet::StateSize {
+                    node: *graph[ni].addr().as_local(),
+                    tx: tx,
+                })
+                .unwrap();
+            rx.recv().unwrap()
+        };
+        trace(graph,
+              source,
+              node,
+              empty,
+              materialized,
+              readers,
+              &estimate,
+              vec![node])
+    };
 
     if let flow::node::Type::Reader(..) = *graph[node] {
         // readers have their own internal state
@@ -433,13 +689,18 @@ pub fn reconstruct(log: &Logger,
     // unfortunately, skipping things this way would make `Message::to` and `Message::from` contain
     // weird values, and cause breakage.
 
-    // set up channels for replay along each path
-    for mut path in paths {
+    // set up all the replay paths before starting any of them, so that the target domain knows
+    // about all the replays to the node by the time the first one arrives. the replays then run
+    // concurrently, and the target only reports them as done once they have all finished.
+    let mut replays = Vec::with_capacity(paths.len());
+    for (mut path, reader) in paths {
         // we want path to have the ancestor closest to the root *first*
         path.reverse();
 
+        let start = ::std::time::Instant::now();
         let tag = Tag(TAG_GENERATOR.fetch_add(1, Ordering::SeqCst) as u32);
         trace!(log, "tag" => tag.id(); "replaying along path {:?}", path);
+        let path_addrs = path.iter().map(|&ni| NodeAddress::make_global(ni)).collect();
 
         // first, find out which domains we are crossing
         let mut segments = Vec::new();
@@ -456,24 +717,6 @@ pub fn reconstruct(log: &Logger,
 
         debug!(log, "domain replay path is {:?}", segments);
 
-        let locals = |i: usize| -> Vec<NodeAddress> {
-            if i == 0 {
-                // we're not replaying through the starter node
-                segments[i]
-                    .1
-                    .iter()
-                    .skip(1)
-                    .map(|&ni| graph[ni].addr())
-                    .collect::<Vec<_>>()
-            } else {
-                segments[i]
-                    .1
-                    .iter()
-                    .map(|&ni| graph[ni].addr())
-                    .collect::<Vec<_>>()
-            }
-        };
-
         let (wait_tx, wait_rx) = mpsc::sync_channel(segments.len());
         let (done_tx, done_rx) = mpsc::sync_channel(1);
         let mut main_done_tx = Some(done_tx);
@@ -488,7 +731,9 @@ pub fn reconstruct(log: &Logger,
                     "a-b-a domain replays are not yet supported");
             seen.insert(*domain);
 
-            let locals = locals(i);
+            // we're not replaying through the starter node
+            let skip = if i == 0 { 1 } else { 0 };
+            let locals: Vec<_> = nodes.iter().skip(skip).map(|&ni| graph[ni].addr()).collect();
             if locals.is_empty() {
                 // first domain may *only* have the starter state
                 assert_eq!(i, 0);
@@ -498,14 +743,19 @@ pub fn reconstruct(log: &Logger,
             let mut setup = Packet::SetupReplayPath {
                 tag: tag,
                 path: locals,
+                source: None,
+                pacing: pacing,
                 done_tx: None,
                 ack: wait_tx.clone(),
             };
             if i == segments.len() - 1 {
                 // last domain should report when it's done
                 assert!(main_done_tx.is_some());
-                if let Packet::SetupReplayPath { ref mut done_tx, .. } = setup {
+                if let Packet::SetupReplayPath { ref mut done_tx, ref mut source, .. } = setup {
                     *done_tx = main_done_tx.take();
+                    if i == 0 {
+                        *source = Some(graph[nodes[0]].addr());
+                    }
                 }
             } else {
                 // the last node *must* be an egress node since there's a later domain
@@ -525,31 +775,81 @@ pub fn reconstruct(log: &Logger,
         for _ in &segments {
             wait_rx.recv().unwrap();
         }
-        trace!(log, "all domains ready for replay");
+        trace!(log, "all domains ready for replay"; "tag" => tag.id());
+
+        replays.push(Replay {
+            tag: tag,
+            path: path_addrs,
+            segments: segments,
+            reader: reader,
+            start: start,
+            ack: (wait_tx, wait_rx),
+            done_rx: done_rx,
+        });
+    }
 
-        // next, tell the first domain to start playing
-        trace!(log, "telling root domain to start replay"; "domain" => segments[0].0.index());
-        txs[&segments[0].0]
+    // next, tell the first domain of each path to start playing
+    for r in &replays {
+        let (domain, ref nodes) = r.segments[0];
+        trace!(log, "telling root domain to start replay";
+               "domain" => domain.index(),
+               "tag" => r.tag.id());
+        txs[&domain]
             .send(Packet::StartReplay {
-                tag: tag,
-                from: graph[segments[0].1[0]].addr(),
-                ack: wait_tx.clone(),
+                tag: r.tag,
+                from: graph[nodes[0]].addr(),
+                reader: r.reader.map(|ri| *graph[ri].addr().as_local()),
+                ack: r.ack.0.clone(),
             })
             .unwrap();
-
-        // and finally, wait for the last domain to finish the replay
-        trace!(log, "waiting for done message from target"; "domain" => segments.last().unwrap().0.index());
-        done_rx.recv().unwrap();
     }
+
+    // and finally, wait for the last domain to finish the replays
+    replays.into_iter()
+        .map(|r| {
+            trace!(log, "waiting for done message from target";
+                   "domain" => r.segments.last().unwrap().0.index(),
+                   "tag" => r.tag.id());
+            let (records, bytes) = r.done_rx.recv().unwrap();
+            ReplayStats {
+                tag: r.tag.id(),
+                path: r.path,
+                domains: r.segments.iter().map(|&(d, _)| d).collect(),
+                records: records,
+                bytes: bytes,
+                duration: dur_to_ns!(r.start.elapsed()),
+            }
+        })
+        .collect()
 }
 
+/// A replay path that has been set up, and may or may not have finished.
+struct Replay {
+    tag: Tag,
+    path: Vec<NodeAddress>,
+    segments: Vec<(domain::Index, Vec<NodeIndex>)>,
+    reader: Option<NodeIndex>,
+    start: ::std::time::Instant,
+    /// Acknowledgements from the domains along the path. The receiver has to stay around until
+    /// the replay has started.
+    ack: (mpsc::SyncSender<()>, mpsc::Receiver<()>),
+    done_rx: mpsc::Receiver<(usize, usize)>,
+}
+
+/// Find the paths to replay along to populate `node`.
+///
+/// Each path starts at `node`, and ends at the materialization to replay from. If that
+/// materialization is a reader of the last node on the path, the reader is also returned.
+/// `estimate` gives the number of records held by a materialization.
 fn trace<T>(graph: &Graph,
             source: NodeIndex,
             node: NodeIndex,
             empty: &HashSet<NodeIndex>,
             materialized: &HashMap<domain::Index, HashMap<LocalNodeIndex, T>>,
+            readers: &HashMap<NodeIndex, (NodeIndex, bool)>,
+            estimate: &Fn(NodeIndex) -> usize,
             path: Vec<NodeIndex>)
-            -> Vec<Vec<NodeIndex>> {
+            -> Vec<(Vec<NodeIndex>, Option<NodeIndex>)> {
 
     if node == source {
         unreachable!("base node was not materialized!");
@@ -566,40 +866,132 @@ fn trace<T>(graph: &Graph,
             .unwrap_or(false)
     };
 
-    if is_materialized {
-        vec![path]
+    let reader = if path.len() == 1 {
+        None
     } else {
-        let mut parents: Vec<_> = graph.neighbors_directed(node, petgraph::EdgeDirection::Incoming)
-            .collect();
-        if parents.len() != 1 {
-            // there are two cases where we have multiple parents: joins and unions
-            // for unions, we should replay *all* paths. for joins, we should only replay one path.
-            // in particular, for a join, we should only replay the ancestor that yields the full
-            // result-set (i.e., the left side of a left join).
-            assert!(n.is_internal());
-            // find empty parents
-            let empty: HashSet<_> = parents.iter()
-                .filter(|ni| empty.contains(ni))
-                .map(|ni| graph[*ni].addr())
+        readers.get(&node).cloned()
+    };
+
+    match reader {
+        Some((ri, prefer)) if prefer || !is_materialized => return vec![(path, Some(ri))],
+        _ => (),
+    }
+
+    if is_materialized {
+        return vec![(path, None)];
+    }
+
+    let mut parents: Vec<_> = graph.neighbors_directed(node, petgraph::EdgeDirection::Incoming)
+        .collect();
+    if parents.len() != 1 {
+        // there are two cases where we have multiple parents: joins and unions
+        // for unions, we should replay *all* paths. for joins, we should only replay one path.
+        // in particular, for a join, we should only replay the ancestor that yields the full
+        // result-set (i.e., the left side of a left join).
+        assert!(n.is_internal());
+        if let Some(candidates) = n.replay_ancestors() {
+            let candidates = candidates.into_iter()
+                .map(|a| {
+                    *parents.iter()
+                        .find(|&&p| graph[p].addr() == a)
+                        .expect("replay ancestor is not a parent")
+                })
                 .collect();
-            if let Some(picked_ancestor) = n.replay_ancestor(&empty) {
-                // join, only replay picked ancestor
-                // NOTE: this is a *non-deterministic* choice
-                parents.retain(|&parent| graph[parent].addr() == picked_ancestor);
-            } else {
-                // union; just replay all
-            }
+            return trace_one(graph,
+                             source,
+                             node,
+                             candidates,
+                             empty,
+                             materialized,
+                             readers,
+                             estimate,
+                             path);
+        }
+        // union; just replay all
+    }
+
+    // there's no point in replaying parents that are empty
+    parents.retain(|&parent| !empty.contains(&parent));
+
+    parents.into_iter()
+        .flat_map(|parent| {
+            let mut path = path.clone();
+            path.push(parent);
+            trace(graph,
+                  source,
+                  parent,
+                  empty,
+                  materialized,
+                  readers,
+                  estimate,
+                  path)
+        })
+        .collect()
+}
+
+/// Find the paths to replay along to populate `node` through just one of the given ancestors,
+/// which are in the node's order of preference.
+///
+/// If one of the ancestors is empty, so is `node`, and there is nothing to replay. Otherwise, we
+/// go through the ancestor that `node` has been told to replay through, if it is one of them, or
+/// else through the one that has the fewest records to replay.
+fn trace_one<T>(graph: &Graph,
+                source: NodeIndex,
+                node: NodeIndex,
+                candidates: Vec<NodeIndex>,
+                empty: &HashSet<NodeIndex>,
+                materialized: &HashMap<domain::Index, HashMap<LocalNodeIndex, T>>,
+                readers: &HashMap<NodeIndex, (NodeIndex, bool)>,
+                estimate: &Fn(NodeIndex) -> usize,
+                path: Vec<NodeIndex>)
+                -> Vec<(Vec<NodeIndex>, Option<NodeIndex>)> {
+
+    if candidates.iter().any(|c| empty.contains(c)) {
+        return Vec::new();
+    }
+
+    let hinted = graph[node]
+        .replay_via()
+        .and_then(|via| candidates.iter().find(|&&c| origin(graph, c) == via).cloned());
+    let candidates = match hinted {
+        Some(c) => vec![c],
+        None => candidates,
+    };
+
+    let only = candidates.len() == 1;
+    let mut best: Option<(usize, Vec<_>)> = None;
+    for c in candidates {
+        let mut path = path.clone();
+        path.push(c);
+        let paths = trace(graph,
+                          source,
+                          c,
+                          empty,
+                          materialized,
+                          readers,
+                          estimate,
+                          path);
+        if only {
+            return paths;
         }
 
-        // there's no point in replaying parents that are empty
-        parents.retain(|&parent| !empty.contains(&parent));
+        let size: usize = paths.iter()
+            .map(|&(ref path, reader)| estimate(reader.unwrap_or_else(|| *path.last().unwrap())))
+            .sum();
+        // ties go to the ancestor that comes first
+        if best.as_ref().map(|&(fewest, _)| size < fewest).unwrap_or(true) {
+            best = Some((size, paths));
+        }
+    }
+    best.map(|(_, paths)| paths).unwrap_or_else(Vec::new)
+}
 
-        parents.into_iter()
-            .flat_map(|parent| {
-                let mut path = path.clone();
-                path.push(parent);
-                trace(graph, source, parent, empty, materialized, path)
-            })
-            .collect()
+/// The node whose records `ni` carries, looking past any ingress and egress nodes.
+pub fn origin(graph: &Graph, mut ni: NodeIndex) -> NodeIndex {
+    while graph[ni].is_ingress() || graph[ni].is_egress() {
+        ni = graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            .next()
+            .expect("ingress and egress nodes always have a parent");
     }
+    ni
 }
@@ -13,6 +13,8 @@
 //!    *initialized* before data starts to flow to the new nodes. This may require two domains to
 //!    communicate directly, and may delay migration completion.
 //!  - Index requirements must be resolved, and checked for conflicts.
+//!  - Removed nodes must be disconnected, and their domains told to drop them.
+//!  - Moved nodes must be handed over, along with their state, to the domain they move to.
 //!
 //! Furthermore, these must be performed in the correct *order* so as to prevent dead- or
 //! livelocks. This module defines methods for performing each step in relative isolation, as well
@@ -25,3 +27,5 @@ pub mod transactions;
 pub mod materialization;
 pub mod augmentation;
 pub mod booting;
+pub mod removal;
+pub mod relocation;
@@ -0,0 +1,335 @@
+//! Functions for moving nodes, along with their state, from one domain to another.
+//!
+//! In particular:
+//!
+//!  - Readers and egress nodes move along with the nodes they belong to
+//!  - Nodes that stay behind, but feed moved nodes, must forward their updates through an egress
+//!    node to a new ingress node in the domain the nodes move to
+//!  - Egress nodes below moved nodes that forward to the domain the nodes move to are no longer
+//!    needed, since the moved nodes can feed those ingress nodes directly
+//!  - Moved nodes must be given local addresses in their new domain, and refer to their ancestors
+//!    by their new addresses
+//!  - The domain the nodes leave must hand them over, along with their state, in between two
+//!    updates, so that every update is processed by the moved nodes exactly once
+//!
+//! Nodes cannot move to a domain that feeds the domain they leave, as the two domains would then
+//! send updates to each other, and could end up waiting on each other forever.
+
+use flow::prelude::*;
+use flow::domain;
+use flow::node;
+
+use petgraph;
+use petgraph::graph::NodeIndex;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+
+use slog::Logger;
+
+/// A move of nodes from one domain to another, as set up in the graph by `rewire`.
+pub struct Relocation {
+    from: domain::Index,
+    to: domain::Index,
+    /// The moved nodes, along with their local addresses in the domain they leave.
+    moved: Vec<(NodeIndex, LocalNodeIndex)>,
+    unmap: HashMap<NodeAddress, NodeAddress>,
+    remap: HashMap<NodeAddress, NodeAddress>,
+    /// Egress nodes added to the domain the nodes leave.
+    egress: Vec<NodeIndex>,
+    /// Ingress nodes added to the domain the nodes move to, along with the node they receive
+    /// updates from.
+    ingress: Vec<(NodeIndex, NodeIndex)>,
+    /// Egress nodes, by their address before the move, and the ingress nodes they must stop
+    /// forwarding to.
+    disconnect: Vec<(LocalNodeIndex, NodeIndex)>,
+    /// Egress nodes that are no longer needed.
+    orphaned: Vec<NodeIndex>,
+}
+
+impl Relocation {
+    /// The domain the nodes move to.
+    pub fn destination(&self) -> domain::Index {
+        self.to
+    }
+
+    /// The nodes that the domain the nodes move to gains, including new ingress nodes.
+    pub fn arrivals(&self) -> HashSet<NodeIndex> {
+        self.moved.iter().map(|&(ni, _)| ni).chain(self.ingress_nodes()).collect()
+    }
+
+    /// The new ingress nodes in the domain the nodes move to.
+    pub fn ingress_nodes(&self) -> HashSet<NodeIndex> {
+        self.ingress.iter().map(|&(ni, _)| ni).collect()
+    }
+
+    /// The egress nodes that are no longer needed once the nodes have moved.
+    pub fn orphaned(&self) -> &[NodeIndex] {
+        &self.orphaned[..]
+    }
+}
+
+/// The local address to give the next node added to `domain`.
+///
+/// Addresses are handed out in increasing order, so the addresses of removed nodes, which stay in
+/// the graph, are never handed out again. Nodes in `skip` are ignored, as they have not been given
+/// an address yet.
+pub fn next_local(graph: &Graph,
+                  source: NodeIndex,
+                  domain: domain::Index,
+                  skip: &HashSet<NodeIndex>)
+                  -> usize {
+    graph.node_indices()
+        .filter(|&ni| ni != source && !skip.contains(&ni))
+        .filter(|&ni| graph[ni].assigned_domain() == Some(domain))
+        .map(|ni| graph[ni].addr().as_local().id() + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Change the graph so that `nodes`, which must all be in the same domain, are in the domain `to`,
+/// along with their readers and egress nodes.
+///
+/// The domains are only told about the move once `hand_over` is called.
+pub fn rewire(log: &Logger,
+              graph: &mut Graph,
+              source: NodeIndex,
+              nodes: &[NodeIndex],
+              to: domain::Index)
+              -> Relocation {
+    let from = graph[nodes[0]].domain();
+    let mut moving: HashSet<_> = nodes.iter().cloned().collect();
+    for &ni in nodes {
+        assert_eq!(graph[ni].domain(), from);
+        for c in graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing) {
+            let is_reader = if let node::Type::Reader(..) = *graph[c] {
+                true
+            } else {
+                false
+            };
+            if is_reader || graph[c].is_egress() {
+                moving.insert(c);
+            }
+        }
+    }
+
+    let mut parents: Vec<_> = moving.iter()
+        .flat_map(|&ni| graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming))
+        .filter(|p| !moving.contains(p))
+        .collect();
+    parents.sort();
+    parents.dedup();
+
+    // the nodes that stay behind must not depend on the domain the nodes move to
+    let mut upstream = parents.clone();
+    let mut seen = HashSet::new();
+    while let Some(ni) = upstream.pop() {
+        if ni == source || !seen.insert(ni) {
+            continue;
+        }
+        assert!(graph[ni].domain() != to,
+                "nodes cannot move to domain {}, since it feeds domain {}",
+                to.index(),
+                from.index());
+        upstream.extend(graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming));
+    }
+
+    let mut next_from = next_local(graph, source, from, &HashSet::new());
+    let mut next_to = next_local(graph, source, to, &HashSet::new());
+
+    // egress nodes that forward to the domain the nodes move to are not needed for that anymore
+    let mut disconnect = Vec::new();
+    let mut orphaned = Vec::new();
+    let mut egresses: Vec<_> =
+        moving.iter().cloned().filter(|&ni| graph[ni].is_egress()).collect();
+    egresses.sort();
+    for e in egresses {
+        let parent = graph.neighbors_directed(e, petgraph::EdgeDirection::Incoming)
+            .next()
+            .expect("egress node has no parent");
+        let local: Vec<_> = graph.neighbors_directed(e, petgraph::EdgeDirection::Outgoing)
+            .filter(|&i| graph[i].domain() == to)
+            .collect();
+        for i in local {
+            trace!(log, "feeding ingress directly"; "node" => parent.index(), "ingress" => i.index());
+            let old = graph.find_edge(e, i).unwrap();
+            let was_materialized = graph.remove_edge(old).unwrap();
+            graph.add_edge(parent, i, was_materialized);
+            disconnect.push((*graph[e].addr().as_local(), i));
+        }
+
+        if graph.neighbors_directed(e, petgraph::EdgeDirection::Outgoing).next().is_none() {
+            trace!(log, "dropping orphaned egress"; "egress" => e.index());
+            let old = graph.find_edge(parent, e).unwrap();
+            graph.remove_edge(old);
+            moving.remove(&e);
+            orphaned.push(e);
+        }
+    }
+
+    // the nodes that feed moved nodes now do so through an egress and an ingress node
+    let mut egress = Vec::new();
+    let mut ingress = Vec::new();
+    for p in parents {
+        let existing = graph.neighbors_directed(p, petgraph::EdgeDirection::Outgoing)
+            .find(|&c| graph[c].is_egress());
+        let e = existing.unwrap_or_else(|| {
+            let mut proxy = graph[p].mirror(node::Type::Egress {
+                txs: Default::default(),
+                tags: Default::default(),
+            });
+            proxy.set_addr(NodeAddress::make_local(next_from));
+            next_from += 1;
+            let e = graph.add_node(proxy);
+            graph.add_edge(p, e, false);
+            trace!(log, "adding egress to node left behind"; "node" => p.index(), "egress" => e.index());
+            egress.push(e);
+            e
+        });
+
+        let mut i = graph[p].mirror(node::Type::Ingress);
+        i.add_to(to);
+        i.set_addr(NodeAddress::make_local(next_to));
+        next_to += 1;
+        let i = graph.add_node(i);
+        graph.add_edge(e, i, false);
+        trace!(log, "adding ingress for moved nodes"; "from" => p.index(), "ingress" => i.index());
+
+        let children: Vec<_> = graph.neighbors_directed(p, petgraph::EdgeDirection::Outgoing)
+            .filter(|c| moving.contains(c))
+            .collect();
+        for c in children {
+            let old = graph.find_edge(p, c).unwrap();
+            let was_materialized = graph.remove_edge(old).unwrap();
+            graph.add_edge(i, c, was_materialized);
+        }
+        ingress.push((i, p));
+    }
+
+    // moved nodes refer to ancestors that stay behind through the new ingress nodes
+    let mut unmap = HashMap::new();
+    let mut remap = HashMap::new();
+    for &(i, p) in &ingress {
+        unmap.insert(graph[p].addr(), NodeAddress::make_global(p));
+        remap.insert(NodeAddress::make_global(p), graph[i].addr());
+    }
+
+    let mut moving: Vec<_> = moving.into_iter().collect();
+    moving.sort();
+    let mut moved = Vec::with_capacity(moving.len());
+    for ni in moving {
+        let old = graph[ni].addr();
+        let new = NodeAddress::make_local(next_to);
+        next_to += 1;
+        debug!(log, "moving node";
+               "node" => ni.index(),
+               "domain" => to.index(),
+               "local" => new.as_local().id());
+        unmap.insert(old, NodeAddress::make_global(ni));
+        remap.insert(NodeAddress::make_global(ni), new);
+        moved.push((ni, *old.as_local()));
+    }
+    for &(ni, _) in &moved {
+        let addr = remap[&NodeAddress::make_global(ni)];
+        graph[ni].relocate(to, addr, &unmap, &remap);
+    }
+
+    Relocation {
+        from: from,
+        to: to,
+        moved: moved,
+        unmap: unmap,
+        remap: remap,
+        egress: egress,
+        ingress: ingress,
+        disconnect: disconnect,
+        orphaned: orphaned,
+    }
+}
+
+/// Tell the domain the nodes leave to hand them over, and wait until it has.
+///
+/// `index` holds the materializations picked for every domain by the migration. New ingress nodes
+/// that are to be materialized are given a copy of the state of the node they receive updates
+/// from, which must itself be materialized. `materialized` is updated to account for the state
+/// that moves.
+pub fn hand_over(log: &Logger,
+                 graph: &mut Graph,
+                 txs: &HashMap<domain::Index, mpsc::SyncSender<Packet>>,
+                 relocation: Relocation,
+                 index: &HashMap<domain::Index, HashMap<LocalNodeIndex, Vec<Vec<usize>>>>,
+                 materialized: &mut HashMap<domain::Index,
+                                            HashMap<LocalNodeIndex, Vec<Vec<usize>>>>) {
+    let Relocation { from, to, moved, unmap, remap, egress, ingress, disconnect, orphaned } =
+        relocation;
+
+    let mut arrived = Vec::new();
+    let nodes = moved.into_iter()
+        .map(|(ni, old)| {
+            let n = &graph[ni];
+            if let Some(idxs) = materialized.get_mut(&from).and_then(|have| have.remove(&old)) {
+                arrived.push((*n.addr().as_local(), idxs));
+            }
+            let children: Vec<_> = graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .filter(|&c| graph[c].domain() == to)
+                .map(|c| graph[c].addr())
+                .collect();
+            (old, n.addr(), children)
+        })
+        .collect();
+
+    let mut ingress_nodes = Vec::with_capacity(ingress.len());
+    for (i, p) in ingress {
+        let addr = *graph[i].addr().as_local();
+        let wanted = index.get(&to).map(|idx| idx.contains_key(&addr)).unwrap_or(false);
+        let copy = if wanted {
+            let p = *graph[p].addr().as_local();
+            let idxs = materialized.get(&from)
+                .and_then(|have| have.get(&p))
+                .cloned()
+                .expect("moved nodes look up records in a node that is not materialized");
+            arrived.push((addr, idxs));
+            Some(p)
+        } else {
+            None
+        };
+        ingress_nodes.push((domain::NodeDescriptor::new(graph, i), copy));
+    }
+
+    let egress = egress.into_iter()
+        .map(|e| {
+            let parent = graph.neighbors_directed(e, petgraph::EdgeDirection::Incoming)
+                .next()
+                .unwrap();
+            let parent = *graph[parent].addr().as_local();
+            (parent, domain::NodeDescriptor::new(graph, e))
+        })
+        .collect();
+    let disconnect = disconnect.into_iter()
+        .map(|(e, i)| (e, NodeAddress::make_global(i)))
+        .collect();
+    let orphaned = orphaned.into_iter().map(|e| *graph[e].addr().as_local()).collect();
+
+    let have = materialized.entry(to).or_insert_with(HashMap::new);
+    for (addr, idxs) in arrived {
+        have.insert(addr, idxs);
+    }
+
+    info!(log, "handing over nodes"; "from" => from.index(), "to" => to.index());
+    let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+    txs[&from]
+        .send(Packet::MoveNodes {
+            nodes: nodes,
+            domain: to,
+            unmap: unmap,
+            remap: remap,
+            egress: egress,
+            ingress: ingress_nodes,
+            disconnect: disconnect,
+            orphaned: orphaned,
+            to: txs[&to].clone(),
+            ack: ack_tx,
+        })
+        .unwrap();
+    ack_rx.recv().unwrap();
+}
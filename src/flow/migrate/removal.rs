@@ -0,0 +1,172 @@
+//! Functions for tearing down nodes that are no longer needed.
+//!
+//! In particular:
+//!
+//!  - Readers of removed nodes must be removed along with them
+//!  - Ingress and egress nodes that only connect removed nodes must be removed
+//!  - Removed nodes must be disconnected from the graph
+//!  - Egress nodes must stop forwarding to removed ingress nodes
+//!  - Domains must drop removed nodes, along with their materialized state
+//!
+//! Removed nodes are left in the graph (without any edges), so that the indices of the remaining
+//! nodes do not change.
+
+use flow::prelude::*;
+use flow::domain;
+use flow::node;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+
+use petgraph;
+use petgraph::graph::NodeIndex;
+
+use slog::Logger;
+
+/// Determine every node that must be torn down to remove the given nodes.
+///
+/// Panics if any of the nodes still has a child that is not also being removed.
+pub fn plan(graph: &Graph, nodes: &HashSet<NodeIndex>) -> HashSet<NodeIndex> {
+    let mut removed = nodes.clone();
+
+    // readers, and the ingress and egress nodes connecting removed nodes to the rest of the graph,
+    // go away once everything they feed into does. repeat until no more nodes are found.
+    loop {
+        let candidates: Vec<_> = removed.iter()
+            .flat_map(|&ni| {
+                graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .chain(graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing))
+            })
+            .filter(|ni| !removed.contains(ni))
+            .collect();
+
+        let mut changed = false;
+        for ni in candidates {
+            if removed.contains(&ni) {
+                continue;
+            }
+
+            let n = &graph[ni];
+            let kind = if let node::Type::Reader(..) = **n {
+                true
+            } else {
+                n.is_ingress() || n.is_egress()
+            };
+            if !kind {
+                continue;
+            }
+
+            let mut children = graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                .peekable();
+            let orphaned = if children.peek().is_none() {
+                graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .all(|p| removed.contains(&p))
+            } else {
+                children.all(|c| removed.contains(&c))
+            };
+            if orphaned {
+                removed.insert(ni);
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    for &ni in &removed {
+        let n = &graph[ni];
+        assert!(!n.is_internal() || !n.is_base(),
+                "base node {} cannot be removed",
+                ni.index());
+        if let Some(c) = graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+            .find(|c| !removed.contains(c)) {
+            panic!("node {} cannot be removed while node {} depends on it",
+                   ni.index(),
+                   c.index());
+        }
+    }
+
+    removed
+}
+
+/// Disconnect the given nodes from the graph, and release the read handles of removed readers.
+///
+/// Returns the connections between surviving egress nodes and removed ingress nodes, which must be
+/// torn down (using `notify`) once the domains have been told about the migration.
+pub fn detach(log: &Logger,
+              graph: &mut Graph,
+              removed: &HashSet<NodeIndex>)
+              -> Vec<(NodeIndex, NodeIndex)> {
+
+    // egress nodes that we keep will have to stop forwarding to removed ingress nodes
+    let mut disconnect = Vec::new();
+    for &ni in removed {
+        if !graph[ni].is_ingress() {
+            continue;
+        }
+        for egress in graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming) {
+            if !removed.contains(&egress) && graph[egress].is_egress() {
+                disconnect.push((egress, ni));
+            }
+        }
+    }
+
+    // release the read handles of removed readers, so that their state can be freed
+    for &ni in removed {
+        if let node::Type::Reader(_, ref mut r) = *graph[ni] {
+            trace!(log, "releasing reader"; "node" => ni.index());
+            r.state = None;
+            r.indexes.clear();
+            r.streamers.lock().unwrap().clear();
+        }
+    }
+
+    // cut every edge touching a removed node
+    let edges: Vec<_> = removed.iter()
+        .flat_map(|&ni| {
+            graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                .map(move |p| (p, ni))
+                .chain(graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing)
+                    .map(move |c| (ni, c)))
+        })
+        .collect();
+    for (src, dst) in edges {
+        while let Some(e) = graph.find_edge(src, dst) {
+            graph.remove_edge(e);
+        }
+    }
+
+    disconnect
+}
+
+/// Stop forwarding to removed ingress nodes, and tell the domains of removed nodes to drop them.
+///
+/// This must only happen once every domain has processed all transactions that precede the
+/// migration, since those still flow through the removed nodes.
+pub fn notify(log: &Logger,
+              graph: &Graph,
+              txs: &HashMap<domain::Index, mpsc::SyncSender<Packet>>,
+              removed: &HashSet<NodeIndex>,
+              disconnect: Vec<(NodeIndex, NodeIndex)>) {
+
+    for (egress, ingress) in disconnect {
+        if let node::Type::Egress { ref txs, .. } = *graph[egress] {
+            trace!(log, "disconnecting"; "egress" => egress.index(), "ingress" => ingress.index());
+            let ingress = NodeAddress::make_global(ingress);
+            txs.lock().unwrap().retain(|&(to, _, _)| to != ingress);
+        }
+    }
+
+    let mut per_domain = HashMap::new();
+    for &ni in removed {
+        let n = &graph[ni];
+        per_domain.entry(n.domain()).or_insert_with(Vec::new).push(*n.addr().as_local());
+    }
+    for (domain, nodes) in per_domain {
+        debug!(log, "removing nodes"; "domain" => domain.index(), "#nodes" => nodes.len());
+        // don't unwrap, since the domain may have failed
+        let _ = txs[&domain].send(Packet::RemoveNodes { nodes: nodes });
+    }
+}
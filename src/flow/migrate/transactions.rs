@@ -15,9 +15,15 @@ fn count_base_ingress(graph: &Graph,
                       nodes: &[(NodeIndex, bool)])
                       -> HashMap<NodeIndex, usize> {
 
+    // an ingress node that is fed by a node in its own domain (which happens when that node has
+    // been moved into the domain) receives transactions along with that node, not separately
     let ingress_nodes: Vec<_> = nodes.into_iter()
         .map(|&(ni, _)| ni)
         .filter(|&ni| graph[ni].borrow().is_ingress())
+        .filter(|&ni| {
+            graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                .all(|p| p == source || graph[p].is_egress())
+        })
         .collect();
 
     graph.neighbors_directed(source, petgraph::EdgeDirection::Outgoing)
@@ -5,6 +5,7 @@ mod sql;
 use petgraph;
 use petgraph::graph::NodeIndex;
 use ops;
+use backlog;
 use checktable;
 
 use std::sync::mpsc;
@@ -12,16 +13,37 @@ use std::sync::{Arc, Mutex};
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt;
+use std::io;
+use std::net;
+use std::thread;
 use std::time;
 
 use slog;
 
+use prelude::RangeGetter;
+
 pub mod domain;
 pub mod prelude;
 pub mod node;
 pub mod payload;
 pub mod statistics;
+pub mod metrics;
+pub mod health;
+pub mod getter;
+pub mod sink;
+pub mod diff;
+pub mod prepared;
+pub mod advisor;
+pub mod trace;
+pub mod persistence;
+pub mod plan;
+pub mod explain;
+pub mod provenance;
+pub mod placement;
+pub mod sharding;
+pub mod transport;
 mod migrate;
 
 const NANOS_PER_SEC: u64 = 1_000_000_000;
@@ -141,6 +163,19 @@ impl NodeAddress {
     }
 }
 
+/// A node in the data-flow graph.
+///
+/// Most ingredients keep everything they need to produce correct output as rows in their own
+/// materialized state, or look it up in the state of their ancestors (see `will_query`), so that
+/// the domain's `StateMap` is all that is needed to rebuild them. Aggregates that cannot be updated
+/// from their output row alone, such as averages and distinct aggregations, recompute their groups
+/// from the ancestor for this reason.
+///
+/// Some ingredients do keep state of their own in their fields, which is lost whenever they are
+/// rebuilt from a replay: `Sequence` keeps the next number for each group, so replayed records
+/// are numbered anew, and `Window` can only recover a lower bound on how far it had moved from
+/// the rows in its state. Ingredients like these document how their output may differ after
+/// they have been rebuilt.
 pub trait Ingredient
     where Self: Send
 {
@@ -151,7 +186,12 @@ pub trait Ingredient
     fn ancestors(&self) -> Vec<NodeAddress>;
     fn should_materialize(&self) -> bool;
 
-    fn replay_ancestor(&self, &HashSet<NodeAddress>) -> Option<NodeAddress> {
+    /// The ancestors that records may be replayed through to reconstruct this node, in order of
+    /// preference, if only one of them should be used. Each of them must on its own yield all the
+    /// records of this node (like the left side of a left join).
+    ///
+    /// By default, all ancestors are replayed (like for a union).
+    fn replay_ancestors(&self) -> Option<Vec<NodeAddress>> {
         None
     }
 
@@ -164,6 +204,15 @@ pub trait Ingredient
     /// *compound* key, *not* that multiple columns should be independently indexed.
     fn suggest_indexes(&self, you: NodeAddress) -> HashMap<NodeAddress, Vec<usize>>;
 
+    /// Suggest indexes, like `suggest_indexes`, that should keep their keys in order, so that the
+    /// rows with keys in a range can be found without a full scan (see `State::lookup_range`).
+    ///
+    /// Ordered indexes are only built for nodes in the same domain as this node, and replace any
+    /// hash index on the same columns. By default, all indexes are hash indexes.
+    fn suggest_ordered_indexes(&self, _you: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        HashMap::new()
+    }
+
     /// Resolve where the given field originates from. If the view is materialized, or the value is
     /// otherwise created by this view, None should be returned.
     fn resolve(&self, i: usize) -> Option<Vec<(NodeAddress, usize)>>;
@@ -173,6 +222,24 @@ pub trait Ingredient
         false
     }
 
+    /// Obtain a channel that receives every row this node refuses to apply, for node types that
+    /// can refuse rows.
+    fn rejections(&self) -> Option<mpsc::Receiver<ops::base::Rejection>> {
+        None
+    }
+
+    /// The type of the values this node produces in the given column, if the node determines it
+    /// itself. The types of columns passed on from ancestors are found through `parent_columns`.
+    fn column_type(&self, _column: usize) -> Option<data::ColumnType> {
+        None
+    }
+
+    /// Add a column with the given index to a base node. Rows that are written without it from
+    /// now on have it filled in with `default`.
+    fn add_column(&mut self, _column: usize, _default: prelude::DataType) {
+        unreachable!("columns can only be added to base nodes");
+    }
+
     /// Produce a compact, human-readable description of this node.
     ///
     ///  Symbol   Description
@@ -183,11 +250,25 @@ pub trait Ingredient
     ///    γ    |  Group by
     ///   |*|   |  Count
     ///    𝛴    |  Sum
+    ///    μ    |  Average
     ///    ⋈    |  Join
     ///    ⋉    |  Left join
+    ///    ∈    |  Semi-join
+    ///    ∉    |  Anti-join
     ///    ⋃    |  Union
+    ///   top   |  Top-K
+    ///    ω    |  Window
     fn description(&self) -> String;
 
+    /// Check that this node is consistent with its ancestors in `graph` (for example, that the
+    /// columns it refers to exist), before it is connected to the graph.
+    ///
+    /// Nodes that fail this check are never added (see `Migration::try_add_ingredient`). Only
+    /// addresses of the type `NodeAddress::Global` may be used.
+    fn validate(&self, _graph: &prelude::Graph) -> Result<(), String> {
+        Ok(())
+    }
+
     /// Called when a node is first connected to the graph.
     ///
     /// All its ancestors are present, but this node and its children may not have been connected
@@ -258,24 +339,120 @@ pub trait Ingredient
     fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)>;
 }
 
+/// What a `Mutator` does when the domain of its base node cannot keep up with the writes made
+/// through it.
+///
+/// The channels between domains are bounded (see `Blender::set_channel_capacity`), and a domain
+/// that cannot send to a downstream domain stops taking packets from upstream ones in turn. A
+/// burst of writes therefore eventually fills up the input channel of the base node's domain,
+/// rather than the memory of the domains further down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the domain has room for the write. This is the default.
+    Block,
+    /// Refuse the write, and return `Busy` from `Mutator::try_put` and friends.
+    Reject,
+}
+
+impl Default for Backpressure {
+    fn default() -> Self {
+        Backpressure::Block
+    }
+}
+
+/// The error returned by a `Mutator` that rejects writes its base domain has no room for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Busy;
+
+/// The error returned by `Mutator::update_if` when the row to update does not hold the expected
+/// value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConditionFailed {
+    /// The row the base node holds for the key, or `None` if it holds no row for the key.
+    pub current: Option<Vec<prelude::DataType>>,
+}
+
+/// A token for a write, which lets reads wait until they reflect it (see
+/// `GetterHandle::find_after`).
+///
+/// Tokens are obtained from `Mutator::put_with_token`, or from the timestamp returned by any
+/// committed transactional write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WriteToken(i64);
+
+impl WriteToken {
+    /// The timestamp the write was assigned.
+    pub fn ts(&self) -> i64 {
+        self.0
+    }
+}
+
+impl From<i64> for WriteToken {
+    fn from(ts: i64) -> Self {
+        WriteToken(ts)
+    }
+}
+
 /// A `Mutator` is used to perform reads and writes to base nodes.
+///
+/// Writes do not tell the caller whether the base node applied them. Rows that the base node
+/// refuses, such as inserts with a conflicting key or rows that violate a column's constraints,
+/// are instead reported on its rejections channel (see `Blender::rejections`).
 #[derive(Clone)]
 pub struct Mutator {
     src: NodeAddress,
     tx: mpsc::SyncSender<payload::Packet>,
     addr: NodeAddress,
     primary_key: Vec<usize>,
+    backpressure: Backpressure,
 }
 
 impl Mutator {
     fn send(&self, r: prelude::Records) {
+        self.send_traced(r, None)
+    }
+
+    fn send_traced(&self, r: prelude::Records, trace: Option<provenance::Tracer>) {
         let m = payload::Packet::Message {
             link: payload::Link::new(self.src, self.addr),
             data: r,
+            trace: trace,
         };
         self.tx.clone().send(m).unwrap();
     }
 
+    fn try_send(&self, r: prelude::Records) -> Result<(), Busy> {
+        if self.backpressure == Backpressure::Block {
+            self.send(r);
+            return Ok(());
+        }
+
+        let m = payload::Packet::Message {
+            link: payload::Link::new(self.src, self.addr),
+            data: r,
+            trace: None,
+        };
+        match self.tx.try_send(m) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => Err(Busy),
+            Err(mpsc::TrySendError::Disconnected(_)) => panic!("base domain has gone away"),
+        }
+    }
+
+    /// The primary key columns of the base node this Mutator was generated for, if it has any.
+    pub(crate) fn primary_key(&self) -> &[usize] {
+        &self.primary_key[..]
+    }
+
+    /// Decide what the `try_` methods of this Mutator do when the base domain has no room for a
+    /// write. See `Backpressure`.
+    ///
+    /// The other methods always wait for the domain to make room.
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Mutator {
+        self.backpressure = backpressure;
+        self
+    }
+
     fn tx_send(&self, r: prelude::Records, t: checktable::Token) -> Result<i64, ()> {
         let (send, recv) = mpsc::channel();
         let m = payload::Packet::Transaction {
@@ -294,6 +471,43 @@ impl Mutator {
         self.send(vec![u.into()].into())
     }
 
+    /// Perform a non-transactional write to the base node this Mutator was generated for, and
+    /// follow it through the graph with `tracer`.
+    ///
+    /// Every node that processes the write, or an update derived from it, reports the records it
+    /// produced on the tracer's channel, down to the readers whose state the write changes. See
+    /// the `provenance` module.
+    pub fn put_traced<V>(&self, u: V, tracer: provenance::Tracer)
+        where V: Into<Vec<prelude::DataType>>
+    {
+        self.send_traced(vec![u.into()].into(), Some(tracer))
+    }
+
+    /// Perform a non-transactional write to the base node this Mutator was generated for, or
+    /// return `Busy` if the base domain has no room for it and the Mutator rejects such writes.
+    pub fn try_put<V>(&self, u: V) -> Result<(), Busy>
+        where V: Into<Vec<prelude::DataType>>
+    {
+        self.try_send(vec![u.into()].into())
+    }
+
+    /// Perform a write to the base node this Mutator was generated for, and return a token that
+    /// reads can use to wait until they reflect it.
+    ///
+    /// The write is made as a transaction that conflicts with nothing, so that it is assigned a
+    /// timestamp, and so waits for the base domain to process it. The readers downstream of the
+    /// base node reach the timestamp once they have swapped in the write. Readers that are not
+    /// downstream of the base node never see the write, so there is no point in waiting for them
+    /// to. Writes through mutators for other processes (see `Blender::get_remote_mutator`) cannot
+    /// be made this way.
+    pub fn put_with_token<V>(&self, u: V) -> WriteToken
+        where V: Into<Vec<prelude::DataType>>
+    {
+        self.tx_send(vec![u.into()].into(), checktable::Token::empty())
+            .map(WriteToken)
+            .expect("writes that conflict with nothing always commit")
+    }
+
     /// Perform a transactional write to the base node this Mutator was generated for.
     pub fn transactional_put<V>(&self, u: V, t: checktable::Token) -> Result<i64, ()>
         where V: Into<Vec<prelude::DataType>>
@@ -301,11 +515,32 @@ impl Mutator {
         self.tx_send(vec![u.into()].into(), t)
     }
 
+    /// Check that `key` can be used to delete a row from the base node, and turn it into a
+    /// deletion.
+    fn delete_request(&self, key: Vec<prelude::DataType>) -> prelude::Record {
+        assert!(!self.primary_key.is_empty(),
+                "delete operations can only be applied to base nodes with key columns");
+        assert_eq!(key.len(),
+                   self.primary_key.len(),
+                   "deletion key must have a value for every key column");
+        prelude::Record::DeleteRequest(key)
+    }
+
     /// Perform a non-transactional delete frome the base node this Mutator was generated for.
+    ///
+    /// `key` holds the values of the base node's key columns for the row to delete.
     pub fn delete<I>(&self, key: I)
         where I: Into<Vec<prelude::DataType>>
     {
-        self.send(vec![prelude::Record::DeleteRequest(key.into())].into())
+        self.send(vec![self.delete_request(key.into())].into())
+    }
+
+    /// Perform a non-transactional delete from the base node this Mutator was generated for, or
+    /// return `Busy` if the base domain has no room for it and the Mutator rejects such writes.
+    pub fn try_delete<I>(&self, key: I) -> Result<(), Busy>
+        where I: Into<Vec<prelude::DataType>>
+    {
+        self.try_send(vec![self.delete_request(key.into())].into())
     }
 
     /// Perform a transactional delete from the base node this Mutator was generated for.
@@ -315,7 +550,33 @@ impl Mutator {
                                    -> Result<i64, ()>
         where I: Into<Vec<prelude::DataType>>
     {
-        self.tx_send(vec![prelude::Record::DeleteRequest(key.into())].into(), t)
+        self.tx_send(vec![self.delete_request(key.into())].into(), t)
+    }
+
+    /// Remove all rows from the base node this Mutator was generated for, non-transactionally.
+    ///
+    /// Downstream views observe the removal as a deletion of every row that was in the base node.
+    pub fn truncate(&self) {
+        self.send(vec![prelude::Record::TruncateRequest].into())
+    }
+
+    /// Remove all rows from the base node this Mutator was generated for, transactionally.
+    pub fn transactional_truncate(&self, t: checktable::Token) -> Result<i64, ()> {
+        self.tx_send(vec![prelude::Record::TruncateRequest].into(), t)
+    }
+
+    /// Turn the row `u` into an update (delete followed by put) of the row with the same key.
+    fn update_request(&self, u: Vec<prelude::DataType>) -> prelude::Records {
+        assert!(!self.primary_key.is_empty(),
+                "update operations can only be applied to base nodes with key columns");
+
+        vec![prelude::Record::DeleteRequest(self.primary_key
+                 .iter()
+                 .map(|&col| &u[col])
+                 .cloned()
+                 .collect()),
+             u.into()]
+            .into()
     }
 
     /// Perform a non-transactional update (delete followed by put) to the base node this Mutator
@@ -323,17 +584,16 @@ impl Mutator {
     pub fn update<V>(&self, u: V)
         where V: Into<Vec<prelude::DataType>>
     {
-        assert!(!self.primary_key.is_empty(),
-                "update operations can only be applied to base nodes with key columns");
+        self.send(self.update_request(u.into()))
+    }
 
-        let u = u.into();
-        self.send(vec![prelude::Record::DeleteRequest(self.primary_key
-                           .iter()
-                           .map(|&col| &u[col])
-                           .cloned()
-                           .collect()),
-                       u.into()]
-            .into())
+    /// Perform a non-transactional update (delete followed by put) to the base node this Mutator
+    /// was generated for, or return `Busy` if the base domain has no room for it and the Mutator
+    /// rejects such writes.
+    pub fn try_update<V>(&self, u: V) -> Result<(), Busy>
+        where V: Into<Vec<prelude::DataType>>
+    {
+        self.try_send(self.update_request(u.into()))
     }
 
     /// Perform a transactional update (delete followed by put) to the base node this Mutator was
@@ -344,18 +604,239 @@ impl Mutator {
                                    -> Result<i64, ()>
         where V: Into<Vec<prelude::DataType>>
     {
-        assert!(!self.primary_key.is_empty(),
-                "update operations can only be applied to base nodes with key columns");
+        self.tx_send(self.update_request(u.into()), t)
+    }
+
+    /// Update (delete followed by put) the row with the same key as `u` in the base node this
+    /// Mutator was generated for, but only if that row still has the value `expected` in column
+    /// `column`.
+    ///
+    /// The row is checked against the base node's state by the base domain itself, just before it
+    /// applies the update, so no other write can come between the two. This makes it possible to
+    /// implement compare-and-set: read a row, compute its new version, and retry from the row in
+    /// `ConditionFailed` if someone else got there first. The update is made
+    /// non-transactionally, and waits for the base domain to process it. Mutators for other
+    /// processes (see `Blender::get_remote_mutator`) cannot make conditional updates.
+    pub fn update_if<V, D>(&self, u: V, column: usize, expected: D) -> Result<(), ConditionFailed>
+        where V: Into<Vec<prelude::DataType>>,
+              D: Into<prelude::DataType>
+    {
+        let u = u.into();
+        let key = self.primary_key.iter().map(|&col| u[col].clone()).collect();
+        let (tx, rx) = mpsc::sync_channel(1);
+        let m = payload::Packet::ConditionalUpdate {
+            link: payload::Link::new(self.src, self.addr),
+            data: self.update_request(u),
+            columns: self.primary_key.clone(),
+            key: key,
+            column: column,
+            expected: expected.into(),
+            tx: tx,
+        };
+        self.tx.clone().send(m).unwrap();
+        rx.recv().expect("base domain has gone away, or is in another process")
+    }
+}
+
+/// An `OrderedMutator` writes to a base node through several ingestion queues, and only preserves
+/// the order of writes that share a value in the base's ordering key column.
+///
+/// Every write is routed to a queue chosen by its value in the ordering key column, so writes for
+/// the same key (e.g., the same user) are always applied in the order they were issued. Writes for
+/// different keys go through different queues, each drained by a thread of its own that forwards
+/// everything queued up since its last batch to the base domain in one go. Many producers can thus
+/// write to a single base without all their writes being serialized one by one through the same
+/// channel.
+///
+/// Writes are applied asynchronously; dropping the `OrderedMutator` waits for all of them to have
+/// been handed to the base domain. Writes wait if the queue they go to is full, so a burst of
+/// writes is held up by the base domain like writes through a regular `Mutator` are. Since the
+/// relative order of writes for different keys is not preserved, only puts and updates are
+/// supported, and only non-transactionally.
+pub struct OrderedMutator {
+    key: usize,
+    primary_key: Vec<usize>,
+    queues: Vec<mpsc::SyncSender<Vec<prelude::Record>>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+/// The largest number of records an ingestion queue forwards to the base domain in one batch.
+const MAX_INGESTION_BATCH: usize = 1024;
+
+impl Mutator {
+    /// Write through `queues` parallel ingestion queues, only preserving the order of writes
+    /// that have the same value in column `key`.
+    ///
+    /// See `OrderedMutator` for details.
+    pub fn ordered_by(self, key: usize, queues: usize) -> OrderedMutator {
+        assert!(queues > 0, "ordered mutators need at least one ingestion queue");
+        let primary_key = self.primary_key.clone();
+        let (txs, threads) = (0..queues)
+            .map(|i| {
+                let (tx, rx) = mpsc::sync_channel::<Vec<prelude::Record>>(MAX_INGESTION_BATCH);
+                let m = self.clone();
+                let t = thread::Builder::new()
+                    .name(format!("ingest{}", i))
+                    .spawn(move || {
+                        while let Ok(mut batch) = rx.recv() {
+                            // pick up anything else that has queued up in the meantime
+                            while batch.len() < MAX_INGESTION_BATCH {
+                                match rx.try_recv() {
+                                    Ok(rs) => batch.extend(rs),
+                                    Err(_) => break,
+                                }
+                            }
+                            m.send(batch.into());
+                        }
+                    })
+                    .unwrap();
+                (tx, t)
+            })
+            .unzip();
+
+        OrderedMutator {
+            key: key,
+            primary_key: primary_key,
+            queues: txs,
+            threads: threads,
+        }
+    }
+}
+
+impl OrderedMutator {
+    fn queue(&self, u: &[prelude::DataType]) -> &mpsc::SyncSender<Vec<prelude::Record>> {
+        &self.queues[sharding::shard_of(&u[self.key], self.queues.len())]
+    }
+
+    /// Perform a non-transactional write to the base node this OrderedMutator was generated for.
+    pub fn put<V>(&self, u: V)
+        where V: Into<Vec<prelude::DataType>>
+    {
+        let u = u.into();
+        let q = self.queue(&u[..]);
+        q.send(vec![u.into()]).unwrap();
+    }
+
+    /// Perform a non-transactional update (delete followed by put) to the base node this
+    /// OrderedMutator was generated for.
+    ///
+    /// The ordering key must be one of the base node's key columns, as otherwise an update could
+    /// be reordered with respect to an earlier write of the row it replaces.
+    pub fn update<V>(&self, u: V)
+        where V: Into<Vec<prelude::DataType>>
+    {
+        assert!(self.primary_key.contains(&self.key),
+                "ordered updates require the ordering key to be a key column of the base node");
 
         let u = u.into();
-        let m = vec![prelude::Record::DeleteRequest(self.primary_key
-                         .iter()
-                         .map(|&col| &u[col])
-                         .cloned()
-                         .collect()),
-                     u.into()]
-            .into();
-        self.tx_send(m, t)
+        let q = self.queue(&u[..]);
+        q.send(vec![prelude::Record::DeleteRequest(self.primary_key
+                            .iter()
+                            .map(|&col| &u[col])
+                            .cloned()
+                            .collect()),
+                        u.into()])
+            .unwrap();
+    }
+}
+
+impl Drop for OrderedMutator {
+    fn drop(&mut self) {
+        self.queues.clear();
+        for t in self.threads.drain(..) {
+            t.join().unwrap();
+        }
+    }
+}
+
+/// A `ReplaySource` determines which materializations may seed the replays that populate newly
+/// materialized nodes during a migration.
+///
+/// Replays start at the closest materialized ancestor of the node being populated, so allowing
+/// more kinds of state to seed them can make replay paths shorter, and avoid crossing domains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplaySource {
+    /// Only replay from the internal state of operators. This is the default.
+    Internal,
+    /// Also replay from the state of existing readers, but prefer an operator's internal state if
+    /// it has both.
+    PreferInternal,
+    /// Also replay from the state of existing readers, and prefer it over an operator's internal
+    /// state if it has both.
+    PreferReader,
+}
+
+impl Default for ReplaySource {
+    fn default() -> Self {
+        ReplaySource::Internal
+    }
+}
+
+/// `ReplayPacing` determines how the records of the replays that populate new materializations
+/// are fed through the domains along each replay path.
+///
+/// Replayed records are sent in batches, and the domains along the path handle regular updates in
+/// between those batches. Smaller batches and slower pacing keep writes flowing while a large
+/// state is being reconstructed, at the cost of making the migration take longer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReplayPacing {
+    /// The number of records in each replay batch. Must be greater than zero.
+    pub batch_size: usize,
+    /// How replay batches are interleaved with regular updates.
+    pub interleave: ReplayInterleave,
+}
+
+impl Default for ReplayPacing {
+    fn default() -> Self {
+        ReplayPacing {
+            batch_size: 128,
+            interleave: ReplayInterleave::default(),
+        }
+    }
+}
+
+/// A `ReplayInterleave` determines how quickly a replay sends its batches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayInterleave {
+    /// Send each batch as soon as the previous one has been taken. Regular updates are only
+    /// handled when they happen to arrive between batches. This is the default.
+    Eager,
+    /// Wait for the given amount of time after every batch.
+    Pause(time::Duration),
+    /// Send at most the given number of batches per second. Must be greater than zero.
+    Rate(u32),
+}
+
+impl Default for ReplayInterleave {
+    fn default() -> Self {
+        ReplayInterleave::Eager
+    }
+}
+
+/// `Batching` determines how egress nodes batch up the regular updates they send to other domains.
+///
+/// Rather than sending every update on as a packet of its own, an egress node holds on to updates
+/// until it has `max_records` of them, until the oldest of them has waited for `max_delay`, or
+/// until its domain runs out of packets to handle, whichever comes first. The domains at the other
+/// end then handle the whole batch at once. Updates are never held back once their domain is idle,
+/// so batching mostly kicks in under load, when it saves the most.
+///
+/// By default, batching is disabled, and every update is sent on by itself. Use
+/// `Blender::batch_updates` to enable it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Batching {
+    /// The number of records after which a batch is sent. A value of one disables batching.
+    pub max_records: usize,
+    /// The longest an update may be held back while its domain is busy.
+    pub max_delay: time::Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            max_records: 1,
+            max_delay: time::Duration::from_millis(1),
+        }
     }
 }
 
@@ -372,6 +853,29 @@ pub struct Blender {
     checktable: Arc<Mutex<checktable::CheckTable>>,
 
     txs: HashMap<domain::Index, mpsc::SyncSender<payload::Packet>>,
+    /// Channels to the domains that packets received over the network are handed to.
+    inputs: transport::Inputs,
+    channel_capacity: usize,
+
+    /// The last `statistics::REPLAY_HISTORY` replays, oldest first.
+    replays: VecDeque<statistics::ReplayStats>,
+    metrics: metrics::Collector,
+    /// The threads swapping readers with `SwapPolicy::Interval`, by reader.
+    swappers: HashMap<NodeIndex, Swapper>,
+    cores: Vec<usize>,
+
+    isolate_failures: bool,
+    failures: domain::Failures,
+    replay_source: ReplaySource,
+    replay_pacing: ReplayPacing,
+    batching: Batching,
+    placement: Box<placement::Placement>,
+
+    /// Nodes that have been removed from the graph. They stay in `ingredients` without any edges,
+    /// so that the indices of other nodes do not change.
+    removed: HashSet<NodeIndex>,
+    /// The indices of the state of every materialized node, by domain.
+    materialized: HashMap<domain::Index, HashMap<LocalNodeIndex, Vec<Vec<usize>>>>,
 
     log: slog::Logger,
 }
@@ -381,6 +885,7 @@ impl Default for Blender {
         let mut g = petgraph::Graph::new();
         let source =
             g.add_node(node::Node::new("source", &["because-type-inference"], node::Type::Source));
+        let inputs = transport::Inputs::default();
         Blender {
             ingredients: g,
             source: source,
@@ -388,6 +893,23 @@ impl Default for Blender {
             checktable: Arc::new(Mutex::new(checktable::CheckTable::new())),
 
             txs: HashMap::default(),
+            inputs: inputs.clone(),
+            channel_capacity: 10,
+
+            replays: VecDeque::new(),
+            metrics: metrics::Collector::new(inputs),
+            swappers: HashMap::new(),
+            cores: Vec::new(),
+
+            isolate_failures: false,
+            failures: Arc::default(),
+            replay_source: ReplaySource::default(),
+            replay_pacing: ReplayPacing::default(),
+            batching: Batching::default(),
+            placement: Box::new(placement::SeparateDomains),
+
+            removed: HashSet::new(),
+            materialized: HashMap::new(),
 
             log: slog::Logger::root(slog::Discard, None),
         }
@@ -407,6 +929,85 @@ impl Blender {
         self.log = log;
     }
 
+    /// Bound the input channels of newly booted domains to `capacity` packets.
+    ///
+    /// A domain that sends to a domain whose input channel is full waits until there is room,
+    /// which holds up the domains that feed it in turn, and eventually the writers to base nodes
+    /// (see `Backpressure`). Smaller channels thus bound how much memory queued-up updates can
+    /// take, whereas larger channels better absorb bursts of writes. Domains that have already
+    /// been booted are not affected. The default capacity is 10 packets.
+    pub fn set_channel_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "domain channels must be able to hold at least one packet");
+        self.channel_capacity = capacity;
+    }
+
+    /// Choose how the egress nodes of newly booted domains batch up the updates they send on.
+    ///
+    /// Batching is disabled by default. Domains that have already been booted are not affected.
+    pub fn batch_updates(&mut self, batching: Batching) {
+        assert!(batching.max_records > 0, "batches must hold at least one record");
+        self.batching = batching;
+    }
+
+    /// Pin the threads of newly booted domains to the given CPU cores.
+    ///
+    /// Domain `i` is pinned to core `cores[i % cores.len()]`, so listing the cores of a single
+    /// socket keeps all domains (and the state they allocate) local to that socket. Domains that
+    /// have already been booted are not affected. Pinning failures are logged, and leave the
+    /// domain unpinned. By default, domain threads are not pinned.
+    pub fn pin_domains_to(&mut self, cores: Vec<usize>) {
+        self.cores = cores;
+    }
+
+    /// Keep failures in newly booted domains from taking down the domain thread.
+    ///
+    /// By default, an operator or domain invariant violation panics the thread of the domain it
+    /// occurs in, which in turn brings down every domain that later tries to send to it. When
+    /// isolation is enabled, the domain instead records the failure (see `failed_domains`) and
+    /// drops all further updates sent to it, while the rest of the graph keeps processing writes
+    /// and serving reads. Migrations that touch a failed domain will still fail. Domains that have
+    /// already been booted are not affected.
+    pub fn isolate_domain_failures(&mut self, isolate: bool) {
+        self.isolate_failures = isolate;
+    }
+
+    /// Choose which materializations future migrations may replay from when populating new
+    /// materializations.
+    ///
+    /// Readers can only seed a replay if they keep full rows (i.e., were not set up with
+    /// `Migration::maintain_counts`), swap after every batch, and existed before the migration.
+    pub fn prefer_replay_source(&mut self, source: ReplaySource) {
+        self.replay_source = source;
+    }
+
+    /// Choose how future migrations pace the replays that populate new materializations.
+    ///
+    /// By default, replays are sent in batches of 128 records as fast as the domains along the
+    /// replay path can take them, which can hold up writes for a while if the replayed state is
+    /// large.
+    pub fn pace_replays(&mut self, pacing: ReplayPacing) {
+        assert!(pacing.batch_size > 0, "replay batches must not be empty");
+        if let ReplayInterleave::Rate(0) = pacing.interleave {
+            panic!("replays must be allowed to send at least one batch per second");
+        }
+        self.replay_pacing = pacing;
+    }
+
+    /// Choose how future migrations pick domains for new nodes that have not been explicitly
+    /// assigned one.
+    ///
+    /// By default, every such node is placed in a new domain of its own. See `placement`.
+    pub fn place_with(&mut self, policy: Box<placement::Placement>) {
+        self.placement = policy;
+    }
+
+    /// Get the domains that have stopped processing updates, along with a description of why.
+    ///
+    /// Only domains booted while `isolate_domain_failures` was enabled are reported.
+    pub fn failed_domains(&self) -> HashMap<domain::Index, String> {
+        self.failures.lock().unwrap().clone()
+    }
+
     /// Start setting up a new `Migration`.
     pub fn start_migration(&mut self) -> Migration {
         info!(self.log, "starting migration");
@@ -416,6 +1017,12 @@ impl Blender {
             added: Default::default(),
             materialize: Default::default(),
             readers: Default::default(),
+            swap_policies: Default::default(),
+            removed: Default::default(),
+            columns: Default::default(),
+            colocated: Default::default(),
+            isolated: Default::default(),
+            relocate: None,
 
             start: time::Instant::now(),
             log: miglog,
@@ -462,6 +1069,7 @@ impl Blender {
     pub fn outputs(&self) -> Vec<(NodeAddress, &node::Node, &node::Reader)> {
         self.ingredients
             .externals(petgraph::EdgeDirection::Outgoing)
+            .filter(|n| !self.removed.contains(n))
             .filter_map(|n| {
                 use flow::node;
                 if let node::Type::Reader(_, ref inner) = *self.ingredients[n] {
@@ -479,67 +1087,736 @@ impl Blender {
             .collect()
     }
 
-    /// Obtain a new function for querying a given (already maintained) reader node.
-    pub fn get_getter
-        (&self,
-         node: NodeAddress)
-         -> Option<Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync>> {
-
+    fn find_reader_node(&self, node: NodeAddress) -> Option<NodeIndex> {
         // reader should be a child of the given node
-        trace!(self.log, "creating reader"; "for" => node.as_global().index());
-        let reader = self.ingredients
+        self.ingredients
             .neighbors_directed(*node.as_global(), petgraph::EdgeDirection::Outgoing)
-            .filter_map(|ni| if let node::Type::Reader(_, ref inner) = *self.ingredients[ni] {
-                Some(inner)
+            .filter(|&ni| if let node::Type::Reader(..) = *self.ingredients[ni] {
+                true
             } else {
-                None
+                false
             })
-            .next(); // there should be at most one
-
-        reader.and_then(|r| r.get_reader())
+            .next() // there should be at most one
     }
 
-    /// Obtain a mutator that can be used to perform writes and deletes from the given base node.
-    pub fn get_mutator(&self, base: NodeAddress) -> Mutator {
-        let n = self.ingredients
-            .neighbors_directed(*base.as_global(), petgraph::EdgeDirection::Incoming)
-            .next()
-            .unwrap();
-        let node = &self.ingredients[n];
-        let tx = self.txs[&node.domain()].clone();
+    fn find_reader(&self, node: NodeAddress) -> Option<&node::Reader> {
+        trace!(self.log, "creating reader"; "for" => node.as_global().index());
+        self.find_reader_node(node).map(|ni| if let node::Type::Reader(_, ref inner) =
+            *self.ingredients[ni] {
+            inner
+        } else {
+            unreachable!()
+        })
+    }
 
-        trace!(self.log, "creating mutator"; "for" => n.index());
-        Mutator {
-            src: NodeAddress::make_global(self.source),
-            tx: tx,
-            addr: node.addr(),
-            primary_key: self.ingredients[*base.as_global()]
-                .suggest_indexes(base)
-                .remove(&base)
-                .unwrap_or_else(Vec::new),
-        }
+    /// Expose all writes processed so far by the reader for the given node to its readers.
+    ///
+    /// This is only needed for readers whose `SwapPolicy` is not `SwapPolicy::EveryBatch`. The
+    /// swap happens asynchronously, shortly after this method returns.
+    pub fn swap_reader(&self, node: NodeAddress) {
+        let ni = self.find_reader_node(node).expect("no reader is maintained for the given node");
+        let n = &self.ingredients[ni];
+        self.txs[&n.domain()].send(payload::Packet::SwapReader(*n.addr().as_local())).unwrap();
     }
 
-    /// Get statistics about the time spent processing different parts of the graph.
-    pub fn get_statistics(&mut self) -> statistics::GraphStats {
-        // TODO: request stats from domains in parallel.
-        let domains = self.txs.iter().map(|(di, s)|{
-            let (tx, rx) = mpsc::sync_channel(1);
-            s.send(payload::Packet::GetStatistics(tx)).unwrap();
+    /// Subscribe to a given (already maintained) reader node.
+    ///
+    /// The returned `Subscription` holds a consistent snapshot of the node's current contents,
+    /// along with a stream of every update to the node after the snapshot was taken. To make the
+    /// snapshot include all writes the node has processed, the reader swaps as part of taking it,
+    /// regardless of its `SwapPolicy`. Returns `None` if the node is not maintained, or if its
+    /// domain has failed.
+    pub fn subscribe(&self, node: NodeAddress) -> Option<node::Subscription> {
+        let ri = match self.find_reader_node(node) {
+            Some(ri) => ri,
+            None => return None,
+        };
+        let r = &self.ingredients[ri];
 
-            let (domain_stats, node_stats) = rx.recv().unwrap();
-            let node_map = node_stats.into_iter().map(|(ni, ns)| (NodeAddress::make_global(ni), ns)).collect();
+        let (tx, rx) = mpsc::channel();
+        let (snapshot_tx, snapshot_rx) = mpsc::sync_channel(1);
+        self.txs[&r.domain()]
+            .send(payload::Packet::Subscribe {
+                node: *r.addr().as_local(),
+                tx: tx,
+                snapshot: snapshot_tx,
+            })
+            .unwrap();
 
-            (*di, (domain_stats, node_map))
-        }).collect();
+        snapshot_rx.recv().ok().map(|(snapshot, ts)| {
+            node::Subscription {
+                snapshot: snapshot,
+                ts: ts,
+                updates: rx,
+            }
+        })
+    }
 
-        statistics::GraphStats {
-            domains: domains,
-        }
+    /// Obtain a new function for querying a given (already maintained) reader node.
+    pub fn get_getter
+        (&self,
+         node: NodeAddress)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_reader())
     }
-}
 
-impl fmt::Display for Blender {
+    /// Sample one in every `every` reads through the getters for a given (already maintained)
+    /// reader node, or stop sampling if `every` is 0.
+    ///
+    /// Sampling applies to all getters for the node, including ones obtained earlier. For each
+    /// sampled read, the time the lookup took, the number of rows returned, the number of bytes
+    /// copied, and whether the reader could answer it at all are recorded, and can be retrieved
+    /// with `read_stats`. Reads the reader cannot answer (because its state is not yet ready, or
+    /// because the key has been evicted) are counted as misses rather than as lookups that
+    /// returned no rows, so that slow reads caused by misses can be told apart from slow
+    /// lookups in the reader.
+    pub fn trace_reads(&self, node: NodeAddress, every: usize) {
+        self.find_reader(node)
+            .expect("no reader is maintained for the given node")
+            .trace
+            .sample_every(every);
+    }
+
+    /// The statistics recorded so far about sampled reads of a given (already maintained) reader
+    /// node.
+    ///
+    /// See `trace_reads`.
+    pub fn read_stats(&self, node: NodeAddress) -> Option<trace::ReadStats> {
+        self.find_reader(node).map(|r| r.trace.stats())
+    }
+
+    /// Obtain a new function for querying all keys in a range of a given (already maintained)
+    /// reader node.
+    ///
+    /// Only readers set up with `Migration::maintain_ordered` support range lookups.
+    pub fn get_range_getter(&self, node: NodeAddress) -> Option<RangeGetter> {
+        self.find_reader(node).and_then(|r| r.get_range_reader())
+    }
+
+    /// Obtain a new function for querying a given (already maintained) reader node by column
+    /// `col`, which must be either the reader's key or one of its secondary indexes (see
+    /// `Migration::maintain_index`).
+    pub fn get_index_getter
+        (&self,
+         node: NodeAddress,
+         col: usize)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_index_reader(col))
+    }
+
+    /// Obtain a new function for querying a given (already maintained) reader node by the values
+    /// of all of the columns `cols` at once (see `Migration::maintain_compound`).
+    ///
+    /// One of the columns must be either the reader's key or one of its secondary indexes.
+    pub fn get_compound_getter
+        (&self,
+         node: NodeAddress,
+         cols: &[usize])
+         -> Option<Box<Fn(&[prelude::DataType]) -> Result<ops::Datas, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_compound_reader(cols))
+    }
+
+    /// Obtain a new handle for querying a given (already maintained) reader node, which converts
+    /// every returned row using `convert`.
+    ///
+    /// The handle is cheap to clone, and each clone can keep its own cache of converted rows for
+    /// hot keys. See `GetterHandle` for details.
+    pub fn get_handle<T, F>(&self,
+                            node: NodeAddress,
+                            convert: F)
+                            -> Option<getter::GetterHandle<T>>
+        where F: Fn(&[prelude::DataType]) -> T + Send + Sync + 'static
+    {
+        self.find_reader(node)
+            .and_then(|r| r.state.clone())
+            .map(|h| getter::GetterHandle::new(h, convert))
+    }
+
+    /// Obtain a new handle for reading from the given (already maintained) reader nodes as of the
+    /// same timestamp.
+    ///
+    /// Returns `None` if any of the nodes is not maintained. See `SnapshotHandle` for details.
+    pub fn get_snapshot_handle(&self, nodes: &[NodeAddress]) -> Option<getter::SnapshotHandle> {
+        nodes.iter()
+            .map(|&n| self.find_reader(n).and_then(|r| r.state.clone()).map(|h| (n, h)))
+            .collect::<Option<_>>()
+            .map(getter::SnapshotHandle::new)
+    }
+
+    /// Obtain a new function for counting the records with a given key in a given (already
+    /// maintained) reader node.
+    pub fn get_count_getter
+        (&self,
+         node: NodeAddress)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<usize, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_count_reader())
+    }
+
+    /// Obtain a new function for checking whether there are any records with a given key in a
+    /// given (already maintained) reader node.
+    pub fn get_contains_getter
+        (&self,
+         node: NodeAddress)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<bool, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_contains_reader())
+    }
+
+    /// Obtain a new function for querying many keys of a given (already maintained) reader node
+    /// at once.
+    ///
+    /// The returned function yields the matching records for each given key, in order. All keys
+    /// are read at the same timestamp, and the per-call overhead is paid only once, which makes
+    /// this considerably cheaper than repeated calls to a getter for pages that read many keys.
+    pub fn get_bulk_getter
+        (&self,
+         node: NodeAddress)
+         -> Option<Box<Fn(&[prelude::DataType]) -> Result<Vec<ops::Datas>, ()> + Send + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_bulk_reader())
+    }
+
+    /// Obtain a new function for querying a given (already maintained) reader node that only
+    /// returns the rows, and columns, selected by `query`.
+    ///
+    /// The returned function takes just the value of the reader's key, and evaluates the rest of
+    /// the query while reading the reader's state.
+    pub fn get_prepared_getter
+        (&self,
+         node: NodeAddress,
+         query: node::PreparedQuery)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync>> {
+        let cols = self.ingredients[*node.as_global()].fields().len();
+        assert!(query.columns().into_iter().all(|c| c < cols),
+                "prepared query refers to columns that {} does not have",
+                self.ingredients[*node.as_global()].name());
+        self.find_reader(node).and_then(|r| r.get_prepared_reader(query))
+    }
+
+    /// Obtain a new function for querying a given (already maintained) reader node, where each
+    /// returned row is accompanied by the timestamp of the write that produced it.
+    ///
+    /// Rows produced by non-transactional writes carry the timestamp of the most recent
+    /// transaction the reader had seen when they were written, or -1 if there was none.
+    pub fn get_timestamped_getter
+        (&self,
+         node: NodeAddress)
+         -> Option<Box<Fn(&prelude::DataType) -> Result<Vec<(Vec<prelude::DataType>, i64)>, ()>
+                           + Send
+                           + Sync>> {
+        self.find_reader(node).and_then(|r| r.get_timestamped_reader())
+    }
+
+    /// The types of the values in each of the given node's columns, where they are known.
+    ///
+    /// See `ColumnSpec` for how column types are declared on base nodes.
+    pub fn column_types(&self, node: NodeAddress) -> Vec<Option<data::ColumnType>> {
+        let ni = *node.as_global();
+        let n = &self.ingredients[ni];
+        (0..n.fields().len()).map(|c| n.column_type(c, &self.ingredients, ni)).collect()
+    }
+
+    /// Summarize the current structure of the graph.
+    ///
+    /// Compare the result with `Migration::summary` to see what a migration would change before
+    /// committing it.
+    pub fn summary(&self) -> diff::GraphSummary {
+        diff::summarize(&self.ingredients,
+                        self.source,
+                        &HashMap::new(),
+                        &self.indexes(),
+                        &self.removed)
+    }
+
+    /// The indices on the state of every materialized node in the graph.
+    fn indexes(&self) -> HashMap<NodeIndex, Vec<Vec<usize>>> {
+        self.ingredients
+            .node_indices()
+            .filter(|&ni| ni != self.source)
+            .filter_map(|ni| {
+                let n = &self.ingredients[ni];
+                n.assigned_domain()
+                    .and_then(|d| self.materialized.get(&d))
+                    .and_then(|dm| dm.get(n.addr().as_local()))
+                    .map(|idxs| (ni, idxs.clone()))
+            })
+            .collect()
+    }
+
+    /// Obtain a mutator that can be used to perform writes and deletes from the given base node.
+    pub fn get_mutator(&self, base: NodeAddress) -> Mutator {
+        let n = self.ingredients
+            .neighbors_directed(*base.as_global(), petgraph::EdgeDirection::Incoming)
+            .next()
+            .unwrap();
+        let node = &self.ingredients[n];
+        let tx = self.txs[&node.domain()].clone();
+
+        trace!(self.log, "creating mutator"; "for" => n.index());
+        Mutator {
+            src: NodeAddress::make_global(self.source),
+            tx: tx,
+            addr: node.addr(),
+            primary_key: self.ingredients[*base.as_global()]
+                .suggest_indexes(base)
+                .remove(&base)
+                .unwrap_or_else(Vec::new),
+            backpressure: Backpressure::default(),
+        }
+    }
+
+    /// Obtain a mutator that writes to the given sharded base node (see
+    /// `Migration::add_sharded_base`).
+    pub fn get_sharded_mutator(&self, base: &sharding::Sharded) -> sharding::ShardedMutator {
+        let key = base.key().expect("only sharded base nodes can be written to");
+        let shards = base.shards().iter().map(|&s| self.get_mutator(s)).collect();
+        sharding::ShardedMutator::new(key, shards)
+    }
+
+    /// Obtain a mutator that writes to the given base node of the `Blender` listening on `addr`
+    /// (see `listen`), rather than to the base node in this `Blender`.
+    ///
+    /// The other `Blender` must have been set up by the same sequence of migrations as this one,
+    /// so that the base node has the same address in both. Only non-transactional writes can be
+    /// sent to another `Blender`.
+    pub fn get_remote_mutator<A: net::ToSocketAddrs>(&self,
+                                                      addr: A,
+                                                      base: NodeAddress)
+                                                      -> io::Result<Mutator> {
+        let mut m = self.get_mutator(base);
+        m.tx = transport::connect(addr, self.ingredients[*base.as_global()].domain())?;
+        Ok(m)
+    }
+
+    /// Accept writes from other processes on `addr`, and return the address that is listened on.
+    ///
+    /// Writes are sent through mutators obtained with `get_remote_mutator`, and are handed to the
+    /// domains of this `Blender` as if they had been made locally. See the `transport` module.
+    pub fn listen<A: net::ToSocketAddrs>(&self, addr: A) -> io::Result<net::SocketAddr> {
+        let listener = net::TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        let inputs = self.inputs.clone();
+        let log = self.log.new(o!("listen" => format!("{}", local)));
+        thread::Builder::new()
+            .name("listen".to_owned())
+            .spawn(move || transport::serve(listener, inputs, log))?;
+        Ok(local)
+    }
+
+    /// Remove all rows from the given base node, propagating their deletion to downstream views.
+    ///
+    /// This is equivalent to `get_mutator(base).truncate()`.
+    pub fn truncate(&self, base: NodeAddress) {
+        self.get_mutator(base).truncate()
+    }
+
+    /// Obtain a channel that is notified whenever the given base node applies a batch of writes.
+    ///
+    /// Each notification carries the records that were applied and, for transactional writes, the
+    /// timestamp they were assigned, in the order the base node processed them. Like `stream`,
+    /// the returned channel is not bounded.
+    pub fn on_write(&self, base: NodeAddress) -> mpsc::Receiver<node::BaseWrite> {
+        self.ingredients[*base.as_global()].on_write()
+    }
+
+    /// Obtain a channel that receives every row the given base node rejects.
+    ///
+    /// This is the only place that writes which the base node refuses are reported. Each
+    /// rejection carries the rejected row (or, for a deletion, the key), which is what ties it to
+    /// the write that caused it. See `Base::rejections`.
+    pub fn rejections(&self, base: NodeAddress) -> mpsc::Receiver<ops::base::Rejection> {
+        self.ingredients[*base.as_global()]
+            .rejections()
+            .expect("only base nodes reject rows")
+    }
+
+    /// Persist the contents of the given base node to disk, after restoring whatever was
+    /// previously persisted for it.
+    ///
+    /// The base node is identified on disk by its name, so it must be given the same name every
+    /// time the graph is set up. Any rows persisted for it are written back into it, and once
+    /// they have propagated through the graph, every batch of writes the base node applies is
+    /// logged to disk before the next is, and periodically folded into a snapshot, as dictated by
+    /// `policy`. Since the restored rows are written to the base node like any others, this
+    /// should be called before the application starts writing to it. Returns the number of rows
+    /// that were restored.
+    ///
+    /// Only base nodes are persisted; the state of all other nodes is recomputed from them.
+    pub fn persist(&self,
+                   base: NodeAddress,
+                   policy: persistence::PersistencePolicy)
+                   -> io::Result<usize> {
+        let name = self.ingredients[*base.as_global()].name().to_owned();
+        let (gen, contents) = persistence::restore(&name, &policy)?;
+
+        let mutator = self.get_mutator(base);
+        let mut restored = 0;
+        for (row, &n) in &contents {
+            for _ in 0..n {
+                mutator.put(row.clone());
+                restored += 1;
+            }
+        }
+        // the restored rows must not be logged again, so only start listening once they are in
+        if !self.wait_until_quiescent(time::Duration::from_secs(60)) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut,
+                                      format!("restored rows of {} did not propagate", name)));
+        }
+
+        let rx = self.on_write(base);
+        persistence::spawn(name, rx, contents, gen, policy);
+        Ok(restored)
+    }
+
+    /// Wait until all writes issued before this call have propagated through the graph.
+    ///
+    /// Each domain is asked to confirm that it has processed everything it has been sent, in an
+    /// order such that a domain is only asked once all domains that feed into it have confirmed.
+    /// Returns `false` if the graph did not quiesce within `timeout`. Note that readers whose
+    /// `SwapPolicy` is not `EveryBatch` may still not expose the written data.
+    pub fn wait_until_quiescent(&self, timeout: time::Duration) -> bool {
+        let start = time::Instant::now();
+
+        // order domains such that each domain comes after all the domains that send to it
+        let mut upstream: HashMap<_, HashSet<_>> =
+            self.txs.keys().map(|&d| (d, HashSet::new())).collect();
+        for e in self.ingredients.raw_edges() {
+            let (src, dst) = (e.source(), e.target());
+            if src == self.source {
+                continue;
+            }
+            let (sd, dd) = (self.ingredients[src].domain(), self.ingredients[dst].domain());
+            if sd != dd {
+                if let Some(up) = upstream.get_mut(&dd) {
+                    up.insert(sd);
+                }
+            }
+        }
+        let mut order = Vec::with_capacity(upstream.len());
+        while !upstream.is_empty() {
+            let ready: Vec<_> = upstream.iter()
+                .filter(|&(_, up)| up.iter().all(|d| !upstream.contains_key(d)))
+                .map(|(&d, _)| d)
+                .collect();
+            if ready.is_empty() {
+                // domains should never form a cycle, but if they do, flushing them in any order
+                // and going around again is the best we can do.
+                order.extend(upstream.keys().cloned());
+                break;
+            }
+            for d in ready {
+                upstream.remove(&d);
+                order.push(d);
+            }
+        }
+
+        loop {
+            let mut idle = true;
+            for d in &order {
+                let (tx, rx) = mpsc::sync_channel(1);
+                self.txs[d].send(payload::Packet::Quiesce(tx)).unwrap();
+
+                let left = match timeout.checked_sub(start.elapsed()) {
+                    Some(left) => left,
+                    None => return false,
+                };
+                match rx.recv_timeout(left) {
+                    Ok(i) => idle = idle && i,
+                    Err(_) => return false,
+                }
+            }
+
+            if idle {
+                return true;
+            }
+
+            // some domain is still waiting on buffered transactions; give it a chance to catch up
+            thread::sleep(time::Duration::from_millis(1));
+        }
+    }
+
+    /// Check whether every domain is able to make progress.
+    ///
+    /// Every domain is sent a liveness probe, and must answer it within `timeout`. Since probes
+    /// are queued behind the updates a domain has yet to process, a domain that is far behind may
+    /// also be reported as unresponsive. Domains whose input queue is full are not probed.
+    pub fn health(&self, timeout: time::Duration) -> health::Health {
+        use self::health::DomainHealth;
+
+        let start = time::Instant::now();
+        let failed = self.failed_domains();
+
+        // send out all the probes first, so that domains can answer them in parallel
+        let mut probes = Vec::with_capacity(self.txs.len());
+        let mut domains = HashMap::with_capacity(self.txs.len());
+        for (&d, tx) in &self.txs {
+            if let Some(e) = failed.get(&d) {
+                domains.insert(d, DomainHealth::Failed(e.clone()));
+                continue;
+            }
+
+            let (ptx, prx) = mpsc::sync_channel(1);
+            match tx.try_send(payload::Packet::Quiesce(ptx)) {
+                Ok(()) => probes.push((d, prx)),
+                Err(mpsc::TrySendError::Full(_)) => {
+                    domains.insert(d, DomainHealth::Saturated);
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => {
+                    domains.insert(d, DomainHealth::Unresponsive);
+                }
+            }
+        }
+
+        for (d, prx) in probes {
+            let answered = match timeout.checked_sub(start.elapsed()) {
+                Some(left) => prx.recv_timeout(left).is_ok(),
+                None => prx.try_recv().is_ok(),
+            };
+            domains.insert(d,
+                           if answered {
+                               DomainHealth::Alive
+                           } else {
+                               DomainHealth::Unresponsive
+                           });
+        }
+
+        let readers = self.outputs()
+            .into_iter()
+            .filter_map(|(ni, _, r)| {
+                r.state.as_ref().map(|s| (ni, s.last_swap().map(|t| t.elapsed())))
+            })
+            .collect();
+
+        health::Health {
+            domains: domains,
+            readers: readers,
+        }
+    }
+
+    /// Get statistics about the time spent processing different parts of the graph.
+    pub fn get_statistics(&mut self) -> statistics::GraphStats {
+        // TODO: request stats from domains in parallel.
+        let domains = self.txs.iter().map(|(di, s)|{
+            let (tx, rx) = mpsc::sync_channel(1);
+            s.send(payload::Packet::GetStatistics(tx)).unwrap();
+
+            let (domain_stats, node_stats) = rx.recv().unwrap();
+            let node_map = node_stats.into_iter().map(|(ni, ns)| (NodeAddress::make_global(ni), ns)).collect();
+
+            (*di, (domain_stats, node_map))
+        }).collect();
+
+        statistics::GraphStats {
+            domains: domains,
+            replays: self.replays.iter().cloned().collect(),
+        }
+    }
+
+    /// Estimate the memory used by every materialized node and reader in the graph, along with
+    /// the total for each domain.
+    ///
+    /// The estimates count the rows held by each node, and approximately how many bytes those rows
+    /// and the indices over them take up. Readers only count the rows their readers can see, so
+    /// writes that have not yet been swapped in are not included.
+    pub fn memory_usage(&self) -> statistics::GraphMemory {
+        let domains = self.txs
+            .iter()
+            .map(|(di, s)| {
+                let (tx, rx) = mpsc::sync_channel(1);
+                s.send(payload::Packet::GetMemory(tx)).unwrap();
+
+                let mut memory = statistics::DomainMemory::default();
+                for (ni, m) in rx.recv().unwrap() {
+                    memory.total.add(&m);
+                    memory.nodes.insert(NodeAddress::make_global(ni), m);
+                }
+                (*di, memory)
+            })
+            .collect();
+
+        statistics::GraphMemory { domains: domains }
+    }
+
+    /// Explain how a read of `key` from the given (already maintained) view is answered, or
+    /// return `None` if no reader is maintained for it.
+    ///
+    /// The explanation lists the view's reader, and the ancestors that would have to be consulted
+    /// if the reader could not answer the read, up to the nearest materialized state on every
+    /// path. Row counts for materialized nodes are found by asking the domain holding them, which
+    /// means waiting for the domain to handle the updates already sent to it. See the `explain`
+    /// module.
+    pub fn explain(&self,
+                   view: NodeAddress,
+                   key: &prelude::DataType)
+                   -> Option<explain::Explanation> {
+        let ri = match self.find_reader_node(view) {
+            Some(ri) => ri,
+            None => return None,
+        };
+        let r = &self.ingredients[ri];
+        let (col, found) = match **r {
+            node::Type::Reader(_, node::Reader { state: Some(ref s), .. }) => {
+                (s.key(), s.find_and(key, |rs| rs.len()).map(|r| r.0))
+            }
+            _ => return None,
+        };
+
+        let mut nodes = vec![explain::ExplainedNode {
+                                 node: NodeAddress::make_global(ri),
+                                 name: r.name().to_owned(),
+                                 description: String::from("reader"),
+                                 domain: r.domain().index(),
+                                 columns: Some(vec![col]),
+                                 access: explain::ReadAccess::Reader,
+                                 rows: found.ok(),
+                                 consulted: true,
+                                 depth: 0,
+                             }];
+        self.explain_ancestors(*view.as_global(),
+                               Some(vec![col]),
+                               key,
+                               found.is_err(),
+                               1,
+                               &mut nodes);
+
+        Some(explain::Explanation {
+            view: view,
+            key: key.clone(),
+            hit: found.is_ok(),
+            nodes: nodes,
+        })
+    }
+
+    /// Add how the rows for `key` in `columns` of node `ni` are found to `nodes`, followed by the
+    /// same for the ancestors of `ni` if it is not materialized.
+    fn explain_ancestors(&self,
+                         ni: NodeIndex,
+                         columns: Option<Vec<usize>>,
+                         key: &prelude::DataType,
+                         consulted: bool,
+                         depth: usize,
+                         nodes: &mut Vec<explain::ExplainedNode>) {
+        use self::explain::ReadAccess;
+
+        let n = &self.ingredients[ni];
+        let parents: Vec<_> = self.ingredients
+            .neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+            .filter(|&p| p != self.source)
+            .collect();
+        let indices = self.materialized
+            .get(&n.domain())
+            .and_then(|dm| dm.get(n.addr().as_local()));
+
+        // the nodes connecting domains pass rows on unchanged, unless an ingress node is
+        // materialized in place of its ancestor in the other domain
+        if n.is_egress() || (n.is_ingress() && indices.is_none()) {
+            for p in parents {
+                self.explain_ancestors(p, columns.clone(), key, consulted, depth, nodes);
+            }
+            return;
+        }
+
+        let (access, rows) = match indices {
+            Some(indices) => {
+                let domain = &self.txs[&n.domain()];
+                match columns {
+                    Some(ref cols) if indices.contains(cols) => {
+                        let (tx, rx) = mpsc::sync_channel(1);
+                        domain.send(payload::Packet::StateLookup {
+                                node: *n.addr().as_local(),
+                                columns: cols.clone(),
+                                key: vec![key.clone()],
+                                tx: tx,
+                            })
+                            .unwrap();
+                        (ReadAccess::Index(cols.clone()), rx.recv().ok().and_then(|rows| rows))
+                    }
+                    _ => {
+                        // scanning touches every row the node holds
+                        let (tx, rx) = mpsc::sync_channel(1);
+                        domain.send(payload::Packet::StateSize {
+                                node: *n.addr().as_local(),
+                                tx: tx,
+                            })
+                            .unwrap();
+                        (ReadAccess::Scan, rx.recv().ok())
+                    }
+                }
+            }
+            None if n.is_internal() && n.is_base() => (ReadAccess::Unavailable, None),
+            None => (ReadAccess::Computed, None),
+        };
+
+        nodes.push(explain::ExplainedNode {
+            node: NodeAddress::make_global(ni),
+            name: n.name().to_owned(),
+            description: if n.is_internal() {
+                n.description()
+            } else {
+                String::from("ingress")
+            },
+            domain: n.domain().index(),
+            columns: columns.clone(),
+            access: access.clone(),
+            rows: rows,
+            consulted: consulted,
+            depth: depth,
+        });
+
+        if access != ReadAccess::Computed {
+            return;
+        }
+        for p in parents {
+            // the columns of the parent that the key columns of this node come from, if all of
+            // them come from the parent unchanged
+            let pcols = columns.as_ref().and_then(|cols| {
+                cols.iter()
+                    .map(|&c| {
+                        n.parent_columns(c)
+                            .into_iter()
+                            .find(|&(pa, _)| if pa.is_global() {
+                                *pa.as_global() == p
+                            } else {
+                                self.ingredients[p].addr() == pa
+                            })
+                            .and_then(|(_, pc)| pc)
+                    })
+                    .collect::<Option<Vec<_>>>()
+            });
+            self.explain_ancestors(p, pcols, key, consulted, depth + 1, nodes);
+        }
+    }
+
+    /// Get the metrics recorded so far by the domains of this graph.
+    ///
+    /// See the `metrics` module for what is recorded.
+    pub fn metrics(&self) -> metrics::GraphMetrics {
+        self.metrics.collect()
+    }
+
+    /// Obtain a handle that collects the metrics of this graph, and that can be moved to other
+    /// threads. See `metrics::Collector`.
+    pub fn metrics_collector(&self) -> metrics::Collector {
+        self.metrics.clone()
+    }
+
+    /// Serve the metrics of this graph in the Prometheus text format over HTTP on `addr`, and
+    /// return the address that is listened on.
+    ///
+    /// Metrics are collected afresh for every request. See `metrics::prometheus`.
+    #[cfg(feature="prometheus")]
+    pub fn serve_metrics<A: net::ToSocketAddrs>(&self, addr: A) -> io::Result<net::SocketAddr> {
+        metrics::prometheus::serve(addr, self.metrics.clone())
+    }
+
+    /// Recommend changes to this graph that would relieve its overloaded domains, based on the
+    /// statistics `advisor` has observed so far.
+    ///
+    /// See `Advisor` for details. The graph itself is not changed.
+    pub fn recommendations(&self, advisor: &advisor::Advisor) -> Vec<advisor::Recommendation> {
+        advisor.recommend(&self.ingredients)
+    }
+}
+
+impl fmt::Display for Blender {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let indentln = |f: &mut fmt::Formatter| write!(f, "    ");
 
@@ -584,7 +1861,14 @@ pub struct Migration<'a> {
     mainline: &'a mut Blender,
     added: HashMap<NodeIndex, Option<domain::Index>>,
     readers: HashMap<NodeIndex, NodeIndex>,
+    /// New swap policies for readers that already exist, by reader.
+    swap_policies: HashMap<NodeIndex, node::SwapPolicy>,
     materialize: HashSet<(NodeIndex, NodeIndex)>,
+    removed: HashSet<NodeIndex>,
+    columns: Vec<(NodeIndex, usize, prelude::DataType)>,
+    colocated: Vec<HashSet<NodeIndex>>,
+    isolated: HashSet<NodeIndex>,
+    relocate: Option<(Vec<NodeIndex>, domain::Index)>,
 
     start: time::Instant,
     log: slog::Logger,
@@ -603,13 +1887,37 @@ impl<'a> Migration<'a> {
     /// The returned identifier can later be used to refer to the added ingredient.
     /// Edges in the data flow graph are automatically added based on the ingredient's reported
     /// `ancestors`.
+    ///
+    /// Panics if the ingredient is not consistent with its ancestors (see `try_add_ingredient`).
     pub fn add_ingredient<S1, FS, S2, I>(&mut self, name: S1, fields: FS, i: I) -> NodeAddress
         where S1: ToString,
               S2: ToString,
               FS: IntoIterator<Item = S2>,
               I: Into<node::Type>
+    {
+        let name = name.to_string();
+        match self.try_add_ingredient(&*name, fields, i) {
+            Ok(n) => n,
+            Err(e) => panic!("cannot add {}: {}", name, e),
+        }
+    }
+
+    /// Add the given `Ingredient` to the Soup, like `add_ingredient`, unless it is not consistent
+    /// with its ancestors (for example, because it refers to columns they do not have).
+    ///
+    /// In that case, nothing is added, and the returned error describes what is wrong.
+    pub fn try_add_ingredient<S1, FS, S2, I>(&mut self,
+                                             name: S1,
+                                             fields: FS,
+                                             i: I)
+                                             -> Result<NodeAddress, String>
+        where S1: ToString,
+              S2: ToString,
+              FS: IntoIterator<Item = S2>,
+              I: Into<node::Type>
     {
         let mut i = i.into();
+        i.validate(&self.mainline.ingredients)?;
         i.on_connected(&self.mainline.ingredients);
 
         let parents = i.ancestors();
@@ -629,7 +1937,56 @@ impl<'a> Migration<'a> {
             }
         }
         // and tell the caller its id
-        NodeAddress::make_global(ni)
+        Ok(NodeAddress::make_global(ni))
+    }
+
+    /// Remove the node with identifier `n` from the graph.
+    ///
+    /// The node is torn down when the migration is committed, along with its readers, and the
+    /// domains that held it release any state it had materialized. Ingress and egress nodes that
+    /// only served removed nodes are removed too, but ancestors are never removed implicitly.
+    /// Every child of `n` must also be removed in this migration, and `n` must not be a base node
+    /// or have been added in this migration.
+    pub fn remove_node(&mut self, n: NodeAddress) {
+        let ni = *n.as_global();
+        assert!(!self.added.contains_key(&ni),
+                "nodes added in a migration cannot be removed in it");
+        assert!(!self.mainline.removed.contains(&ni), "node was already removed");
+        {
+            let node = &self.mainline.ingredients[ni];
+            assert!(!node.is_internal() || !node.is_base(), "base nodes cannot be removed");
+        }
+        info!(self.log, "removing node"; "node" => ni.index());
+        self.removed.insert(ni);
+    }
+
+    /// Add a column named `field` to the base node with identifier `base`, and return its index.
+    ///
+    /// Writes that leave out the new column (such as those from clients that predate it) have it
+    /// filled in with `default`, and so do the rows the base already holds once the migration is
+    /// committed. Existing views never see the new column, and keep working as before.
+    pub fn add_column<S: ToString>(&mut self,
+                                   base: NodeAddress,
+                                   field: S,
+                                   default: prelude::DataType)
+                                   -> usize {
+        let ni = *base.as_global();
+        assert!(!self.mainline.removed.contains(&ni), "node was removed");
+        let column = {
+            let node = &mut self.mainline.ingredients[ni];
+            assert!(node.is_internal() && node.is_base(),
+                    "columns can only be added to base nodes");
+            node.add_field(field)
+        };
+        info!(self.log, "adding column"; "node" => ni.index(), "column" => column);
+
+        if self.added.contains_key(&ni) {
+            // the base has not been handed to a domain yet
+            self.mainline.ingredients[ni].add_column(column, default);
+        } else {
+            self.columns.push((ni, column, default));
+        }
+        column
     }
 
     #[cfg(test)]
@@ -637,6 +1994,148 @@ impl<'a> Migration<'a> {
         self.mainline.graph()
     }
 
+    /// Returns the names of the fields of the node with identifier `n`.
+    pub fn fields(&self, n: NodeAddress) -> &[String] {
+        self.mainline.ingredients[*n.as_global()].fields()
+    }
+
+    /// The types of the values in each of the columns of the node with identifier `n`, where they
+    /// are known (see `Blender::column_types`).
+    pub fn column_types(&self, n: NodeAddress) -> Vec<Option<data::ColumnType>> {
+        self.mainline.column_types(n)
+    }
+
+    /// Summarize the structure the graph will have once this migration is committed.
+    ///
+    /// Nodes added by this migration that have not been explicitly assigned to a domain are
+    /// reported without one, and the ingress and egress nodes needed to connect domains do not
+    /// appear, since those are only decided upon at commit time. The indices the migration will
+    /// add are those predicted by `materializations`. Diffing this against `Blender::summary`
+    /// taken before the migration started yields the changes the migration will make.
+    pub fn summary(&self) -> diff::GraphSummary {
+        let removed: HashSet<_> = self.mainline.removed.union(&self.removed).cloned().collect();
+        let mut indexes = self.mainline.indexes();
+        let new = self.added.keys().cloned().collect();
+        for (ni, idxs) in migrate::materialization::preview(&self.mainline.ingredients, &new) {
+            let existing = indexes.entry(ni).or_insert_with(Vec::new);
+            for idx in idxs {
+                if !existing.contains(&idx) {
+                    existing.push(idx);
+                }
+            }
+        }
+        diff::summarize(&self.mainline.ingredients,
+                        self.mainline.source,
+                        &self.added,
+                        &indexes,
+                        &removed)
+    }
+
+    /// Predict which of the nodes added by this migration will be materialized, and the indices
+    /// that will be needed on them and on the nodes they look up records in.
+    ///
+    /// The prediction is made before nodes are assigned to domains, so it does not include the
+    /// ingress nodes that are materialized in place of an ancestor in another domain. See
+    /// `migrate::materialization::preview`.
+    pub fn materializations(&self) -> HashMap<NodeAddress, Vec<Vec<usize>>> {
+        let new = self.added.keys().cloned().collect();
+        migrate::materialization::preview(&self.mainline.ingredients, &new)
+            .into_iter()
+            .map(|(ni, idxs)| (NodeAddress::make_global(ni), idxs))
+            .collect()
+    }
+
+    /// Describe what committing this migration will do: which nodes will be materialized, what
+    /// indices their state will have, and which replays will populate them.
+    ///
+    /// Like `materializations`, the plan is made before nodes are assigned to domains, so it does
+    /// not include the ingress nodes that will be added between domains, and replays are assumed
+    /// to start at the internal state of operators rather than at existing readers (see
+    /// `migrate::materialization::preview_replays`). The number of rows a replay will carry is
+    /// estimated from the state it replays from, which means waiting for the domain holding that
+    /// state to handle the updates already sent to it.
+    pub fn plan(&self) -> plan::MigrationPlan {
+        let mainline = &*self.mainline;
+        let graph = &mainline.ingredients;
+
+        let mut new: HashSet<_> = self.added.keys().cloned().collect();
+        let materializations = migrate::materialization::preview(graph, &new);
+        let mut targets: HashSet<_> =
+            materializations.keys().filter(|ni| new.contains(ni)).cloned().collect();
+        let mut reader_of = HashMap::new();
+        for (&n, &ri) in &self.readers {
+            reader_of.insert(ri, n);
+            new.insert(ri);
+            if let node::Type::Reader(_, ref r) = *graph[ri] {
+                if r.state.is_some() {
+                    targets.insert(ri);
+                }
+            }
+        }
+
+        let materialized = |ni: NodeIndex| {
+            let n = &graph[ni];
+            n.assigned_domain()
+                .and_then(|d| mainline.materialized.get(&d))
+                .map(|dm| dm.contains_key(n.addr().as_local()))
+                .unwrap_or(false)
+        };
+        let paths = migrate::materialization::preview_replays(graph,
+                                                              mainline.source,
+                                                              &new,
+                                                              &targets,
+                                                              &materialized);
+
+        let rows = |ni: NodeIndex| {
+            if new.contains(&ni) {
+                // the state is only populated once the migration is committed
+                return None;
+            }
+            let n = &graph[ni];
+            let (tx, rx) = mpsc::sync_channel(1);
+            mainline.txs[&n.domain()]
+                .send(payload::Packet::StateSize {
+                    node: *n.addr().as_local(),
+                    tx: tx,
+                })
+                .unwrap();
+            rx.recv().ok()
+        };
+
+        let replays = paths.into_iter()
+            .map(|path| {
+                let mut domains = Vec::new();
+                let mut last = None;
+                for &ni in &path {
+                    // readers end up in the domain of the node they read from
+                    let owner = reader_of.get(&ni).cloned().unwrap_or(ni);
+                    let d = self.added
+                        .get(&owner)
+                        .cloned()
+                        .unwrap_or_else(|| graph[owner].assigned_domain());
+                    // every node that has not been assigned a domain gets one of its own
+                    let at = (d, if d.is_none() { Some(owner) } else { None });
+                    if last != Some(at) {
+                        domains.push(d.map(|d| d.index()));
+                        last = Some(at);
+                    }
+                }
+                plan::PlannedReplay {
+                    rows: rows(path[0]),
+                    domains: domains,
+                    path: path.into_iter().map(NodeAddress::make_global).collect(),
+                }
+            })
+            .collect();
+
+        plan::MigrationPlan {
+            materializations: materializations.into_iter()
+                .map(|(ni, idxs)| (NodeAddress::make_global(ni), idxs))
+                .collect(),
+            replays: replays,
+        }
+    }
+
     /// Mark the edge between `src` and `dst` in the graph as requiring materialization.
     ///
     /// The reason this is placed per edge rather than per node is that only some children of a
@@ -675,6 +2174,309 @@ impl<'a> Migration<'a> {
         assert_eq!(self.added.insert(*n.as_global(), Some(d)).unwrap(), None);
     }
 
+    /// Place all of the given ingredients in the same domain.
+    ///
+    /// The ingredients must have been added in this migration. If one of them is assigned to a
+    /// domain with `assign_domain`, all of them are placed in that domain. Otherwise, the domain is
+    /// picked for whichever of them comes first in the graph, and the others follow it.
+    pub fn colocate(&mut self, nodes: &[NodeAddress]) {
+        let mut group: HashSet<_> = nodes.iter().map(|n| *n.as_global()).collect();
+        for ni in &group {
+            assert!(self.added.contains_key(ni),
+                    "only nodes added in this migration can be colocated");
+            assert!(!self.isolated.contains(ni), "isolated nodes cannot be colocated");
+        }
+        debug!(self.log, "colocating nodes"; "nodes" => format!("{:?}", group));
+
+        // groups that share a node are merged
+        let mut i = 0;
+        while i < self.colocated.len() {
+            if self.colocated[i].is_disjoint(&group) {
+                i += 1;
+            } else {
+                group.extend(self.colocated.swap_remove(i));
+            }
+        }
+        self.colocated.push(group);
+    }
+
+    /// Place the ingredient with identifier `n` in a new domain of its own, regardless of the
+    /// `Placement` policy in use.
+    ///
+    /// `n` must have been added in this migration, and can be neither assigned to a domain nor
+    /// colocated with other ingredients.
+    pub fn isolate(&mut self, n: NodeAddress) {
+        let ni = *n.as_global();
+        assert!(self.added.contains_key(&ni),
+                "only nodes added in this migration can be isolated");
+        assert!(self.colocated.iter().all(|g| !g.contains(&ni)),
+                "colocated nodes cannot be isolated");
+        debug!(self.log, "isolating node"; "node" => ni.index());
+        self.isolated.insert(ni);
+    }
+
+    /// Move the ingredients with the given identifiers, along with their readers and any state
+    /// they hold, to the thread domain `to`.
+    ///
+    /// This allows a domain that has too much work to be split up, by moving some of its
+    /// ingredients to a domain created with `add_domain`, and domains with little work to be
+    /// combined (see `merge_domains`). The ingredients must all be in the same domain, and every
+    /// child of a moved ingredient in that domain must be moved too. The domain they leave keeps
+    /// running, and forwards the updates the moved ingredients need to `to`. No updates are lost
+    /// or processed twice while the ingredients move.
+    ///
+    /// Base nodes cannot be moved, nor can readers that swap periodically, and ingredients cannot
+    /// be moved to a domain that feeds the domain they are in. A migration that moves ingredients
+    /// cannot also add or remove any.
+    pub fn move_nodes(&mut self, nodes: &[NodeAddress], to: domain::Index) {
+        assert!(self.relocate.is_none(), "a migration can only move one set of nodes");
+        assert!(self.added.is_empty() && self.readers.is_empty() && self.removed.is_empty(),
+                "migrations that move nodes cannot add or remove nodes");
+        assert!(!nodes.is_empty(), "no nodes to move");
+        assert!(to.index() < self.mainline.ndomains,
+                "cannot move nodes to unknown domain {}",
+                to.index());
+
+        let moved: HashSet<_> = nodes.iter().map(|n| *n.as_global()).collect();
+        let graph = &self.mainline.ingredients;
+        let from = graph[*nodes[0].as_global()].domain();
+        assert!(from != to, "nodes are already in domain {}", to.index());
+        for &ni in &moved {
+            assert!(!self.mainline.removed.contains(&ni), "node was removed");
+            let n = &graph[ni];
+            assert!(n.is_internal() && !n.is_base(),
+                    "only internal nodes that are not base nodes can be moved");
+            assert_eq!(n.domain(), from, "nodes can only be moved out of one domain at a time");
+            for ci in graph.neighbors_directed(ni, petgraph::EdgeDirection::Outgoing) {
+                let c = &graph[ci];
+                if let node::Type::Reader(_, ref r) = **c {
+                    if let node::SwapPolicy::Interval(..) = r.swap {
+                        panic!("readers that swap periodically cannot be moved");
+                    }
+                } else if c.is_internal() && c.domain() == from {
+                    assert!(moved.contains(&ci),
+                            "node {} must be moved along with its parent {}",
+                            ci.index(),
+                            ni.index());
+                }
+            }
+        }
+
+        info!(self.log, "moving nodes";
+              "nodes" => format!("{:?}", moved),
+              "from" => from.index(),
+              "to" => to.index());
+        let mut nodes: Vec<_> = moved.into_iter().collect();
+        nodes.sort();
+        self.relocate = Some((nodes, to));
+    }
+
+    /// Move every ingredient in domain `from` that can be moved to domain `into`.
+    ///
+    /// Only the base nodes of `from`, and the ingress nodes through which it receives updates from
+    /// other domains, stay behind. The thread of `from` keeps forwarding their updates to `into`,
+    /// but does no other work. See `move_nodes`.
+    pub fn merge_domains(&mut self, from: domain::Index, into: domain::Index) {
+        let nodes: Vec<_> = {
+            let mainline = &*self.mainline;
+            let graph = &mainline.ingredients;
+            graph.node_indices()
+                .filter(|&ni| ni != mainline.source && !mainline.removed.contains(&ni))
+                .filter(|&ni| {
+                    let n = &graph[ni];
+                    n.assigned_domain() == Some(from) && n.is_internal() && !n.is_base()
+                })
+                .map(NodeAddress::make_global)
+                .collect()
+        };
+        assert!(!nodes.is_empty(),
+                "domain {} has no nodes that can be moved",
+                from.index());
+        self.move_nodes(&nodes[..], into);
+    }
+
+    /// Add a base node that is split into `shards` shards by the value of column `key`.
+    ///
+    /// Every shard is a base node of its own, constructed from `base`, and placed in a new domain
+    /// of its own. Writes go through a `ShardedMutator` (see `Blender::get_sharded_mutator`), which
+    /// sends every row to the shard picked by its value in column `key`.
+    pub fn add_sharded_base<S1, FS, S2>(&mut self,
+                                        name: S1,
+                                        fields: FS,
+                                        base: ops::base::Base,
+                                        key: usize,
+                                        shards: usize)
+                                        -> sharding::Sharded
+        where S1: ToString,
+              S2: ToString,
+              FS: IntoIterator<Item = S2>
+    {
+        assert!(shards > 0, "sharded nodes need at least one shard");
+        let name = name.to_string();
+        let fields: Vec<_> = fields.into_iter().map(|f| f.to_string()).collect();
+        assert!(key < fields.len(), "cannot shard by column {}", key);
+
+        let nodes = (0..shards)
+            .map(|i| {
+                let n = self.add_ingredient(format!("{}_{}", name, i), &fields[..], base.clone());
+                let d = self.add_domain();
+                self.assign_domain(n, d);
+                n
+            })
+            .collect();
+        info!(self.log, "added sharded base node"; "name" => name, "shards" => shards);
+        sharding::Sharded::new(nodes, key)
+    }
+
+    /// Add an ingredient to every shard of its sharded inputs, and return the resulting sharded
+    /// node, which is sharded by column `key`.
+    ///
+    /// Each input is given along with the column the ingredient needs it to be sharded by (e.g.,
+    /// the join column for a join, or a group-by column for an aggregation). Inputs that are not
+    /// already split into the same number of shards by that column are split up again by it, with
+    /// `Shard` nodes below each of their shards, whose output is unioned up for every new shard.
+    /// `f` is then called with the inputs for each shard, in the order the inputs were given, and
+    /// constructs the ingredient for that shard.
+    ///
+    /// All inputs with more than one shard must have the same number of shards. Every shard of the
+    /// new node is placed in the domain of the shard of the first input that did not have to be
+    /// split up again, or in a new domain if every input had to be.
+    pub fn add_sharded<S1, FS, S2, I, F>(&mut self,
+                                         name: S1,
+                                         fields: FS,
+                                         inputs: &[(&sharding::Sharded, usize)],
+                                         key: usize,
+                                         mut f: F)
+                                         -> sharding::Sharded
+        where S1: ToString,
+              S2: ToString,
+              FS: IntoIterator<Item = S2>,
+              I: Into<node::Type>,
+              F: FnMut(&[NodeAddress]) -> I
+    {
+        assert!(!inputs.is_empty(), "sharded ingredients need at least one input");
+        let name = name.to_string();
+        let fields: Vec<_> = fields.into_iter().map(|f| f.to_string()).collect();
+        assert!(key < fields.len(), "cannot shard by column {}", key);
+
+        let shards = inputs.iter().map(|&(s, _)| s.shards().len()).max().unwrap();
+        for &(s, _) in inputs {
+            assert!(s.shards().len() == 1 || s.shards().len() == shards,
+                    "inputs to sharded ingredients must have the same number of shards");
+        }
+
+        // split up the inputs that are not sharded the way the ingredient needs them to be
+        let home = inputs.iter().position(|&(s, column)| s.sharded_by(column, shards));
+        let inputs: Vec<_> = inputs.iter()
+            .enumerate()
+            .map(|(i, &(s, column))| if Some(i) == home || s.sharded_by(column, shards) {
+                (s.clone(), false)
+            } else {
+                let fan_in = s.shards().len() > 1;
+                (self.reshard(&name, i, s, column, shards), fan_in)
+            })
+            .collect();
+
+        let nodes = (0..shards)
+            .map(|j| {
+                let parents: Vec<_> = inputs.iter().map(|&(ref s, _)| s.shards()[j]).collect();
+                let i = f(&parents[..]);
+                let n = self.add_ingredient(format!("{}_{}", name, j), &fields[..], i);
+                if let Some(h) = home {
+                    self.place_near(n, parents[h]);
+                } else {
+                    let d = self.add_domain();
+                    self.assign_domain(n, d);
+                }
+
+                // unions that gather a resharded input for this shard belong with the shard
+                for (&(_, fan_in), &p) in inputs.iter().zip(parents.iter()) {
+                    if fan_in {
+                        self.place_near(p, n);
+                    }
+                }
+                n
+            })
+            .collect();
+        info!(self.log, "added sharded node"; "name" => name, "shards" => shards);
+        sharding::Sharded::new(nodes, key)
+    }
+
+    /// Split the sharded node `s` into `shards` shards by column `column`, for input `input` of
+    /// the sharded node `name`.
+    ///
+    /// Every shard of `s` is given a `Shard` node for each new shard, in the same domain. If `s`
+    /// has more than one shard, the `Shard` nodes for each new shard are then unioned up, and the
+    /// unions are left for the caller to place.
+    fn reshard(&mut self,
+               name: &str,
+               input: usize,
+               s: &sharding::Sharded,
+               column: usize,
+               shards: usize)
+               -> sharding::Sharded {
+        debug!(self.log, "resharding input of sharded node";
+               "node" => name, "input" => input, "column" => column);
+
+        let nodes = (0..shards)
+            .map(|j| {
+                let pieces: Vec<_> = s.shards()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &p)| {
+                        let fields = Vec::from(self.fields(p));
+                        let n = self.add_ingredient(format!("{}_in{}_{}_{}", name, input, i, j),
+                                                    &fields[..],
+                                                    ops::shard::Shard::new(p, column, j, shards));
+                        self.place_near(n, p);
+                        n
+                    })
+                    .collect();
+
+                if pieces.len() == 1 {
+                    return pieces[0];
+                }
+                let fields = Vec::from(self.fields(pieces[0]));
+                let emit = pieces.iter().map(|&p| (p, (0..fields.len()).collect())).collect();
+                self.add_ingredient(format!("{}_in{}_{}", name, input, j),
+                                    &fields[..],
+                                    ops::union::Union::new(emit))
+            })
+            .collect();
+        sharding::Sharded::new(nodes, column)
+    }
+
+    /// Place the new node `n` in the same domain as `near`.
+    fn place_near(&mut self, n: NodeAddress, near: NodeAddress) {
+        let d = match self.added.get(near.as_global()) {
+            Some(&Some(d)) => Some(d),
+            Some(&None) => None,
+            None => Some(self.mainline.ingredients[*near.as_global()].domain()),
+        };
+        match d {
+            Some(d) => self.assign_domain(n, d),
+            None => self.colocate(&[n, near]),
+        }
+    }
+
+    /// Replay records through `ancestor` whenever `node` has to be reconstructed, and could be
+    /// reconstructed through more than one of its ancestors (like the two sides of an inner join).
+    ///
+    /// This is only a hint. It is ignored if `ancestor` alone does not yield all of the records of
+    /// `node`.
+    pub fn replay_through(&mut self, node: NodeAddress, ancestor: NodeAddress) {
+        let (ni, ai) = (*node.as_global(), *ancestor.as_global());
+        {
+            let graph = &self.mainline.ingredients;
+            assert!(graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                        .any(|p| migrate::materialization::origin(graph, p) == ai),
+                    "node {} is not an ancestor of node {}",
+                    ai.index(),
+                    ni.index());
+        }
+        self.mainline.ingredients[ni].set_replay_via(ai);
+    }
+
     fn ensure_reader_for(&mut self, n: NodeAddress) {
         if !self.readers.contains_key(n.as_global()) {
             // make a reader
@@ -736,6 +2538,141 @@ impl<'a> Migration<'a> {
                     n: NodeAddress,
                     key: usize)
                     -> Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync> {
+        self.maintain_inner(n, key, None, false, false)
+    }
+
+    /// Set up the given node such that its output can be efficiently queried, with the records
+    /// for each key returned in ascending order of column `sort`.
+    ///
+    /// The order is maintained as records arrive, so reads never need to sort.
+    pub fn maintain_sorted(&mut self,
+                           n: NodeAddress,
+                           key: usize,
+                           sort: usize)
+                           -> Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync> {
+        self.maintain_inner(n, key, Some(sort), false, false)
+    }
+
+    /// Set up the given node such that the number of records with each key can be efficiently
+    /// queried.
+    ///
+    /// Only the count for each key is stored, which is much cheaper than keeping the records
+    /// themselves. The returned function gives the number of records with the given key, and
+    /// `Blender::get_contains_getter` can be used to only check whether there are any. A regular
+    /// getter for the node yields a single record holding the count.
+    pub fn maintain_counts(&mut self,
+                           n: NodeAddress,
+                           key: usize)
+                           -> Box<Fn(&prelude::DataType) -> Result<usize, ()> + Send + Sync> {
+        self.maintain_inner(n, key, None, true, false);
+        self.reader_for(n).get_count_reader().unwrap()
+    }
+
+    /// Set up the given node such that its output can be efficiently queried, both by key and for
+    /// all keys in a range.
+    ///
+    /// The returned function takes the lowest and highest key of the range (inclusive), and yields
+    /// the records for every key in between, in ascending key order. A regular getter for the node
+    /// can still be used to read single keys.
+    pub fn maintain_ordered(&mut self, n: NodeAddress, key: usize) -> RangeGetter {
+        self.maintain_inner(n, key, None, false, true);
+        self.reader_for(n).get_range_reader().unwrap()
+    }
+
+    /// Set up the given node such that its output can be efficiently queried by the values of
+    /// several columns at once.
+    ///
+    /// The returned function takes one value for each of the columns in `keys`, in the same order,
+    /// and yields the records that match all of them. Readers are indexed by a single column, so
+    /// the lookup goes through the reader's index on one of `keys` (the first of them, unless the
+    /// node is already maintained on another), and the records found there are then filtered by
+    /// the remaining columns. Listing the most selective column first thus makes lookups cheaper.
+    /// Adding that index to a node maintained on another column is subject to the same
+    /// restrictions as `maintain_index`.
+    pub fn maintain_compound
+        (&mut self,
+         n: NodeAddress,
+         keys: &[usize])
+         -> Box<Fn(&[prelude::DataType]) -> Result<ops::Datas, ()> + Send + Sync> {
+        assert!(!keys.is_empty(), "compound keys need at least one column");
+
+        let existing = if self.readers.contains_key(n.as_global()) {
+            self.reader_for(n).key().ok()
+        } else {
+            None
+        };
+        match existing {
+            Some(key) if keys.contains(&key) => {}
+            Some(_) => {
+                self.maintain_index(n, keys[0]);
+            }
+            None => {
+                self.maintain(n, keys[0]);
+            }
+        }
+        self.reader_for(n).get_compound_reader(keys).unwrap()
+    }
+
+    /// Set up every shard of the given sharded node such that its output can be efficiently
+    /// queried, and return a function that queries the node as a whole.
+    ///
+    /// If `key` is the column the node is sharded by, every lookup is sent only to the shard that
+    /// holds the records with that key. Otherwise, every shard is queried, and their results are
+    /// concatenated.
+    pub fn maintain_sharded(&mut self,
+                            n: &sharding::Sharded,
+                            key: usize)
+                            -> Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync> {
+        let getters: Vec<_> = n.shards().iter().map(|&s| self.maintain(s, key)).collect();
+        if getters.len() > 1 && n.key() == Some(key) {
+            Box::new(move |k: &prelude::DataType| getters[sharding::shard_of(k, getters.len())](k))
+        } else {
+            Box::new(move |k: &prelude::DataType| {
+                let mut rs = Vec::new();
+                for g in &getters {
+                    rs.extend(g(k)?);
+                }
+                Ok(rs)
+            })
+        }
+    }
+
+    /// Also index the given node's reader by column `col`, so that its output can be efficiently
+    /// queried by that column as well as by the key it is maintained on.
+    ///
+    /// The secondary index shares its records with the reader's existing state, so reading a view
+    /// by several columns does not require keeping a copy of the view for each. The node must
+    /// already be maintained (see `maintain`), and only regular readers that were created by this
+    /// migration can be given secondary indexes.
+    pub fn maintain_index(&mut self,
+                          n: NodeAddress,
+                          col: usize)
+                          -> Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync> {
+        let ri = *self.readers
+            .get(n.as_global())
+            .expect("secondary indexes can only be added to maintained nodes");
+
+        if let node::Type::Reader(ref mut wh, ref mut inner) = *self.mainline.ingredients[ri] {
+            if inner.index(col).is_none() {
+                let r = wh.as_mut()
+                    .expect("secondary indexes can only be added to readers created by the \
+                             current migration")
+                    .add_index(col);
+                inner.indexes.push(r);
+            }
+            inner.get_index_reader(col).unwrap()
+        } else {
+            unreachable!("tried to use non-reader node as a reader")
+        }
+    }
+
+    fn maintain_inner(&mut self,
+                      n: NodeAddress,
+                      key: usize,
+                      sort: Option<usize>,
+                      counting: bool,
+                      ordered: bool)
+                      -> Box<Fn(&prelude::DataType) -> Result<ops::Datas, ()> + Send + Sync> {
         self.ensure_reader_for(n);
         let ri = self.readers[n.as_global()];
 
@@ -745,9 +2682,19 @@ impl<'a> Migration<'a> {
         if let node::Type::Reader(ref mut wh, ref mut inner) = *self.mainline.ingredients[ri] {
             if let Some(ref s) = inner.state {
                 assert_eq!(s.key(), key);
+                assert!(sort.is_none(), "cannot change the sort order of an existing reader");
+                assert_eq!(s.is_counting(),
+                           counting,
+                           "cannot change whether an existing reader only keeps counts");
+                assert!(!ordered || s.is_ordered(),
+                        "cannot add range lookups to an existing reader");
             } else {
-                use backlog;
-                let (r, w) = backlog::new(cols, key);
+                let (r, w) = match sort {
+                    Some(sort) => backlog::new_sorted(cols, key, sort),
+                    None if counting => backlog::new_counting(cols, key),
+                    None if ordered => backlog::new_ordered(cols, key),
+                    None => backlog::new(cols, key),
+                };
                 inner.state = Some(r);
                 *wh = Some(w);
             }
@@ -780,7 +2727,6 @@ impl<'a> Migration<'a> {
             if let Some(ref s) = inner.state {
                 assert_eq!(s.key(), key);
             } else {
-                use backlog;
                 let (r, w) = backlog::new(cols, key);
                 inner.state = Some(r);
                 *wh = Some(w);
@@ -814,36 +2760,195 @@ impl<'a> Migration<'a> {
         rx
     }
 
+    /// Write the output stream of the given node to an external `Sink`.
+    ///
+    /// Every update processed by the given node is handed to `out`, batched and retried
+    /// according to `policy`. The writes happen on a separate thread, so a slow sink does not
+    /// hold up processing of the graph, but like with `Migration::stream`, updates queue up in
+    /// memory for as long as the sink falls behind.
+    pub fn sink(&mut self, n: NodeAddress, out: Box<sink::Sink>, policy: sink::SinkPolicy) {
+        let rx = self.stream(n);
+        sink::spawn(format!("sink{}", n.as_global().index()), rx, out, policy);
+    }
+
+    /// Set how often the reader for the given node exposes new writes to its readers.
+    ///
+    /// See `SwapPolicy` for the available policies. The default is `SwapPolicy::EveryBatch`. If
+    /// the node is already maintained by an earlier migration, the policy of its existing reader
+    /// is changed once this migration is committed, and any writes that reader has held back are
+    /// exposed.
+    pub fn set_swap_policy(&mut self, n: NodeAddress, policy: node::SwapPolicy) {
+        if !self.readers.contains_key(n.as_global()) {
+            if let Some(ri) = self.mainline.find_reader_node(n) {
+                self.swap_policies.insert(ri, policy);
+                return;
+            }
+        }
+        self.ensure_reader_for(n);
+        let ri = self.readers[n.as_global()];
+        if let node::Type::Reader(_, ref mut inner) = *self.mainline.ingredients[ri] {
+            inner.swap = policy;
+        } else {
+            unreachable!("tried to use non-reader node as a reader")
+        }
+    }
+
+    /// Bound the size of the given node's reader, evicting keys once it grows beyond the budget
+    /// given by `policy`.
+    ///
+    /// Getters for the node fail for evicted keys, instead of returning no records, so that
+    /// applications can fetch them elsewhere. If the node's own state is materialized on the
+    /// reader's key, keys that getters missed are filled in again from it when the reader next
+    /// swaps, and later reads of them succeed. The node must already be maintained by a regular
+    /// reader (see `maintain`) created by this migration.
+    pub fn set_eviction_policy(&mut self, n: NodeAddress, policy: backlog::EvictionPolicy) {
+        let ri = *self.readers
+            .get(n.as_global())
+            .expect("node must be maintained to bound its reader");
+        if let node::Type::Reader(ref mut wh, _) = *self.mainline.ingredients[ri] {
+            wh.as_mut()
+                .expect("only readers created by the current migration can be bounded")
+                .set_eviction(policy);
+        } else {
+            unreachable!("tried to use non-reader node as a reader")
+        }
+    }
+
+    /// Keep the rows removed from the given node's reader by the last `versions` timestamps that
+    /// removed any, so that the reader can be read as of those timestamps (see `SnapshotHandle`).
+    ///
+    /// The node must already be maintained by a regular reader (see `maintain`) created by this
+    /// migration.
+    pub fn keep_versions(&mut self, n: NodeAddress, versions: usize) {
+        let ri = *self.readers
+            .get(n.as_global())
+            .expect("node must be maintained to keep versions of its reader");
+        if let node::Type::Reader(ref mut wh, _) = *self.mainline.ingredients[ri] {
+            wh.as_mut()
+                .expect("only readers created by the current migration can keep versions")
+                .keep_versions(versions);
+        } else {
+            unreachable!("tried to use non-reader node as a reader")
+        }
+    }
+
+    /// Record how often, and how recently, each key of the given node's reader is read.
+    ///
+    /// The node must already be maintained. The recorded reads are used to pick keys to evict
+    /// from the reader, preferring keys that are written but never read.
+    pub fn track_reads(&mut self, n: NodeAddress) {
+        let ri = *self.readers.get(n.as_global()).expect("node must be maintained to track reads");
+        if let node::Type::Reader(_, ref inner) = *self.mainline.ingredients[ri] {
+            inner.state
+                .as_ref()
+                .expect("node must be maintained to track reads")
+                .track_accesses(true);
+        } else {
+            unreachable!("tried to use non-reader node as a reader")
+        }
+    }
+
+    /// Obtain a channel that is notified whenever the given base node applies a batch of writes.
+    ///
+    /// See `Blender::on_write`.
+    pub fn on_write(&self, base: NodeAddress) -> mpsc::Receiver<node::BaseWrite> {
+        self.mainline.ingredients[*base.as_global()].on_write()
+    }
+
+    /// Obtain a channel that receives every row the given base node rejects.
+    ///
+    /// See `Blender::rejections`.
+    pub fn rejections(&self, base: NodeAddress) -> mpsc::Receiver<ops::base::Rejection> {
+        self.mainline.ingredients[*base.as_global()]
+            .rejections()
+            .expect("only base nodes reject rows")
+    }
+
+    /// Abandon this `Migration`, and undo every change it made to the graph.
+    ///
+    /// Domains are not told about a migration until it is committed, so the running graph is
+    /// unaffected by an aborted migration.
+    pub fn abort(self) {
+        info!(self.log, "aborting migration"; "#nodes" => self.added.len());
+        let mainline = self.mainline;
+
+        for (src, dst) in self.materialize {
+            if let Some(e) = mainline.ingredients.find_edge(src, dst) {
+                *mainline.ingredients.edge_weight_mut(e).unwrap() = false;
+            }
+        }
+        for &(ni, column, _) in self.columns.iter().rev() {
+            mainline.ingredients[ni].remove_field(column);
+        }
+
+        // the nodes added by a migration are the last ones in the graph, so removing them from
+        // the last one back leaves the indices of all other nodes unchanged
+        let mut added: Vec<_> = self.added.keys().chain(self.readers.values()).cloned().collect();
+        added.sort();
+        for ni in added.into_iter().rev() {
+            mainline.ingredients.remove_node(ni);
+        }
+    }
+
     /// Commit the changes introduced by this `Migration` to the master `Soup`.
     ///
     /// This will spin up an execution thread for each new thread domain, and hook those new
     /// domains into the larger Soup graph. The returned map contains entry points through which
     /// new updates should be sent to introduce them into the Soup.
+    ///
+    /// This is equivalent to calling `prepare` followed immediately by `PreparedMigration::commit`.
     pub fn commit(self) {
-        info!(self.log, "finalizing migration"; "#nodes" => self.added.len());
+        self.prepare().commit()
+    }
+
+    /// Prepare the changes introduced by this `Migration`, without yet activating them.
+    ///
+    /// Once this returns, all new domains have been booted, existing domains have been told about
+    /// their new nodes, and all new materializations have been fully replayed. However, none of
+    /// the affected domains will process transactional writes issued after the migration started
+    /// until `PreparedMigration::commit` is called, at which point all of them switch over to the
+    /// new graph at the same timestamp. This allows external changes (such as an application
+    /// rollout) to be coordinated with the migration in between the two phases.
+    ///
+    /// The affected domains hold back writes until the returned `PreparedMigration` is committed.
+    /// If it is dropped without being committed (for example because the caller panicked), it is
+    /// committed when dropped, since the new nodes cannot be taken out of the domains again.
+    pub fn prepare(self) -> PreparedMigration<'a> {
+        info!(self.log, "preparing migration"; "#nodes" => self.added.len());
         let mut new = HashSet::new();
 
         let log = self.log;
         let start = self.start;
         let mainline = self.mainline;
+        let swap_policies = self.swap_policies;
+
+        // Disconnect the nodes that are being removed
+        let removed = migrate::removal::plan(&mainline.ingredients, &self.removed);
+        let disconnect = migrate::removal::detach(&log, &mut mainline.ingredients, &removed);
+        mainline.removed.extend(removed.iter().cloned());
+        for ri in &removed {
+            if let Some(swapper) = mainline.swappers.remove(ri) {
+                swapper.stop();
+            }
+        }
 
         // Make sure all new nodes are assigned to a domain
-        for (node, domain) in self.added {
-            let domain = domain.unwrap_or_else(|| {
-                // new node that doesn't belong to a domain
-                // create a new domain just for that node
-                // NOTE: this is the same code as in add_domain(), but we can't use self here
-                trace!(log, "node automatically added to domain"; "node" => node.index(), "domain" => mainline.ndomains);
-                mainline.ndomains += 1;
-                (mainline.ndomains - 1).into()
-
-            });
+        let placed = placement::assign(&log,
+                                       &mainline.ingredients,
+                                       mainline.source,
+                                       &self.added,
+                                       &self.colocated,
+                                       &self.isolated,
+                                       &mut *mainline.placement,
+                                       &mut mainline.ndomains);
+        for (node, domain) in placed {
             mainline.ingredients[node].add_to(domain);
             new.insert(node);
         }
 
         // Readers are nodes too.
         // And they should be assigned the same domain as their parents
+        let readers: Vec<_> = self.readers.values().cloned().collect();
         for (parent, reader) in self.readers {
             let domain = mainline.ingredients[parent].domain();
             mainline.ingredients[reader].add_to(domain);
@@ -854,12 +2959,32 @@ impl<'a> Migration<'a> {
         let mut swapped =
             migrate::routing::add(&log, &mut mainline.ingredients, mainline.source, &mut new);
 
+        // Move nodes between domains
+        let relocation = match self.relocate {
+            Some((nodes, to)) => {
+                assert!(new.is_empty() && removed.is_empty(),
+                        "migrations that move nodes cannot add or remove nodes");
+                let r = migrate::relocation::rewire(&log,
+                                                    &mut mainline.ingredients,
+                                                    mainline.source,
+                                                    &nodes[..],
+                                                    to);
+                mainline.removed.extend(r.orphaned().iter().cloned());
+                Some(r)
+            }
+            None => None,
+        };
+
         // Find all nodes for domains that have changed
-        let changed_domains: HashSet<_> =
+        let mut changed_domains: HashSet<_> =
             new.iter().map(|&ni| mainline.ingredients[ni].domain()).collect();
+        if let Some(ref r) = relocation {
+            changed_domains.insert(r.destination());
+        }
         let mut domain_nodes = mainline.ingredients
             .node_indices()
             .filter(|&ni| ni != mainline.source)
+            .filter(|ni| !mainline.removed.contains(ni))
             .map(|ni| {
                 let domain = mainline.ingredients[ni].domain();
                 (domain, ni, new.contains(&ni))
@@ -868,30 +2993,38 @@ impl<'a> Migration<'a> {
                 dns.entry(d).or_insert_with(Vec::new).push((ni, new));
                 dns
             });
+        // Domains that lose nodes must take part in the migration, even if they lose all of them
+        for &ni in &removed {
+            domain_nodes.entry(mainline.ingredients[ni].domain()).or_insert_with(Vec::new);
+        }
 
         let mut rxs = HashMap::new();
 
         // Set up input channels for new domains
         for domain in domain_nodes.keys() {
             if !mainline.txs.contains_key(domain) {
-                let (tx, rx) = mpsc::sync_channel(10);
+                let (tx, rx) = mpsc::sync_channel(mainline.channel_capacity);
                 rxs.insert(*domain, rx);
+                mainline.inputs.lock().unwrap().insert(*domain, tx.clone());
                 mainline.txs.insert(*domain, tx);
             }
         }
 
         // Assign local addresses to all new nodes, and initialize them
         for (domain, nodes) in &mut domain_nodes {
-            // Number of pre-existing nodes
-            let mut nnodes = nodes.iter().filter(|&&(_, new)| !new).count();
-
-            if nnodes == nodes.len() {
+            if nodes.iter().all(|&(_, new)| !new) {
                 // Nothing to do here
                 continue;
             }
 
             let log = log.new(o!("domain" => domain.index()));
 
+            // Removed nodes keep their local addresses, so they must not be handed out again
+            let mut nnodes = migrate::relocation::next_local(&mainline.ingredients,
+                                                             mainline.source,
+                                                             *domain,
+                                                             &new);
+
             // Give local addresses to every (new) node
             for &(ni, new) in nodes.iter() {
                 if new {
@@ -976,13 +3109,31 @@ impl<'a> Migration<'a> {
             }
 
             // Start up new domain
+            let core = if mainline.cores.is_empty() {
+                None
+            } else {
+                Some(mainline.cores[domain.index() % mainline.cores.len()])
+            };
+            // nodes that move to a new domain are handed to it by the domain they leave
+            let mut nodes = uninformed_domain_nodes.remove(&domain).unwrap();
+            if let Some(ref r) = relocation {
+                let arrivals = r.arrivals();
+                nodes.retain(|&(ni, _)| !arrivals.contains(&ni));
+            }
             migrate::booting::boot_new(log.new(o!("domain" => domain.index())),
                                        domain.index().into(),
                                        &mut mainline.ingredients,
-                                       uninformed_domain_nodes.remove(&domain).unwrap(),
+                                       nodes,
                                        mainline.checktable.clone(),
                                        rxs.remove(&domain).unwrap(),
-                                       start_ts);
+                                       start_ts,
+                                       mainline.batching,
+                                       core,
+                                       if mainline.isolate_failures {
+                                           Some(mainline.failures.clone())
+                                       } else {
+                                           None
+                                       });
         }
         drop(rxs);
 
@@ -996,29 +3147,207 @@ impl<'a> Migration<'a> {
                                       start_ts,
                                       prevs);
 
+        // All domains have now processed every transaction that preceeds the migration, so removed
+        // nodes can safely be torn down
+        if !removed.is_empty() {
+            debug!(log, "tearing down removed nodes"; "#nodes" => removed.len());
+            migrate::removal::notify(&log,
+                                     &mainline.ingredients,
+                                     &mainline.txs,
+                                     &removed,
+                                     disconnect);
+        }
+
+        // Existing base nodes that gained columns must pad the rows they hold before any new
+        // materializations are replayed from them
+        for (ni, column, default) in self.columns {
+            let n = &mainline.ingredients[ni];
+            trace!(log, "padding base rows"; "node" => ni.index(), "column" => column);
+            mainline.txs[&n.domain()]
+                .send(payload::Packet::AddBaseColumn {
+                    node: *n.addr().as_local(),
+                    column: column,
+                    default: default,
+                })
+                .unwrap();
+        }
+
         // Set up inter-domain connections
         // NOTE: once we do this, we are making existing domains block on new domains!
         info!(log, "bringing up inter-domain connections");
         migrate::routing::connect(&log, &mut mainline.ingredients, &mainline.txs, &new);
 
+        // Hand moved nodes over to their new domain
+        if let Some(relocation) = relocation {
+            migrate::routing::connect(&log,
+                                      &mut mainline.ingredients,
+                                      &mainline.txs,
+                                      &relocation.ingress_nodes());
+            migrate::relocation::hand_over(&log,
+                                           &mut mainline.ingredients,
+                                           &mainline.txs,
+                                           relocation,
+                                           &index,
+                                           &mut mainline.materialized);
+        }
+
         // And now, the last piece of the puzzle -- set up materializations
         info!(log, "initializing new materializations");
-        migrate::materialization::initialize(&log,
-                                             &mainline.ingredients,
-                                             mainline.source,
-                                             &new,
-                                             index,
-                                             &mut mainline.txs);
+        let replays = migrate::materialization::initialize(&log,
+                                                           &mainline.ingredients,
+                                                           mainline.source,
+                                                           &new,
+                                                           index,
+                                                           &mut mainline.materialized,
+                                                           mainline.replay_source,
+                                                           mainline.replay_pacing,
+                                                           &mut mainline.txs);
+        for replay in replays {
+            mainline.metrics.replayed(&replay);
+            if mainline.replays.len() == statistics::REPLAY_HISTORY {
+                mainline.replays.pop_front();
+            }
+            mainline.replays.push_back(replay);
+        }
+
+        // Existing readers whose swap policy has changed
+        for (&ri, &policy) in &swap_policies {
+            let n = &mut mainline.ingredients[ri];
+            if let node::Type::Reader(_, ref mut r) = **n {
+                r.swap = policy;
+            }
+            mainline.txs[&n.domain()]
+                .send(payload::Packet::SetSwapPolicy {
+                    node: *n.addr().as_local(),
+                    policy: policy,
+                })
+                .unwrap();
+        }
+
+        // Periodically swap readers that should not swap after every batch. Every reader has at
+        // most one thread doing so, which is replaced whenever the reader's policy is set.
+        for ri in swap_policies.keys() {
+            if let Some(swapper) = mainline.swappers.remove(ri) {
+                swapper.stop();
+            }
+        }
+        for ri in readers.into_iter().chain(swap_policies.keys().cloned()) {
+            let n = &mainline.ingredients[ri];
+            if let node::Type::Reader(_, node::Reader { swap: node::SwapPolicy::Interval(every),
+                                                        .. }) = **n {
+                let tx = mainline.txs[&n.domain()].clone();
+                let swapper = Swapper::spawn(ri, *n.addr().as_local(), every, tx);
+                mainline.swappers.insert(ri, swapper);
+            }
+        }
+
+        info!(log, "migration prepared"; "ms" => dur_to_ns!(start.elapsed()) / 1_000_000);
+        PreparedMigration {
+            mainline: mainline,
+            ingresses_from_base: Some(ingresses_from_base),
+            end_ts: end_ts,
+
+            start: start,
+            log: log,
+        }
+    }
+}
+
+/// A `Migration` that has been fully set up, but whose changes have not yet been activated.
+///
+/// See `Migration::prepare`. Dropping a `PreparedMigration` commits it.
+#[must_use]
+pub struct PreparedMigration<'a> {
+    mainline: &'a mut Blender,
+    // taken once the migration has been finalized
+    ingresses_from_base: Option<HashMap<domain::Index, HashMap<NodeIndex, usize>>>,
+    end_ts: i64,
+
+    start: time::Instant,
+    log: slog::Logger,
+}
+
+impl<'a> PreparedMigration<'a> {
+    /// The timestamp at which the changes introduced by this migration take effect.
+    pub fn timestamp(&self) -> i64 {
+        self.end_ts
+    }
 
+    /// Activate the changes introduced by this migration.
+    ///
+    /// All affected domains switch over to the new graph at the timestamp given by `timestamp`.
+    pub fn commit(mut self) {
+        self.finalize();
+    }
+
+    fn finalize(&mut self) {
+        let ingresses_from_base = match self.ingresses_from_base.take() {
+            Some(ingresses_from_base) => ingresses_from_base,
+            None => return,
+        };
+
+        let log = &self.log;
         info!(log, "finalizing migration");
-        migrate::transactions::finalize(ingresses_from_base, &log, &mut mainline.txs, end_ts);
+        migrate::transactions::finalize(ingresses_from_base,
+                                        log,
+                                        &mut self.mainline.txs,
+                                        self.end_ts);
+
+        warn!(log, "migration completed"; "ms" => dur_to_ns!(self.start.elapsed()) / 1_000_000);
+    }
+}
 
-        warn!(log, "migration completed"; "ms" => dur_to_ns!(start.elapsed()) / 1_000_000);
+impl<'a> Drop for PreparedMigration<'a> {
+    fn drop(&mut self) {
+        if self.ingresses_from_base.is_some() {
+            warn!(self.log, "prepared migration dropped without being committed");
+            self.finalize();
+        }
+    }
+}
+
+/// A thread that periodically asks a domain to swap one of its readers.
+struct Swapper {
+    done: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Swapper {
+    fn spawn(reader: NodeIndex,
+             addr: LocalNodeIndex,
+             every: time::Duration,
+             tx: mpsc::SyncSender<payload::Packet>)
+             -> Swapper {
+        let (done, stopped) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name(format!("swap{}", reader.index()))
+            .spawn(move || {
+                // swap until asked to stop, or until the domain has gone away
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(every) {
+                    if tx.send(payload::Packet::SwapReader(addr)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        Swapper {
+            done: done,
+            thread: thread,
+        }
+    }
+
+    /// Stop swapping, and wait for the thread to exit.
+    fn stop(self) {
+        drop(self.done);
+        self.thread.join().unwrap();
     }
 }
 
 impl Drop for Blender {
     fn drop(&mut self) {
+        for (_, swapper) in self.swappers.drain() {
+            swapper.stop();
+        }
         for (_, tx) in &mut self.txs {
             // don't unwrap, because given domain may already have terminated
             drop(tx.send(payload::Packet::Quit));
@@ -4,20 +4,25 @@ use petgraph::graph::NodeIndex;
 use std::sync::mpsc;
 use std::sync;
 use std::fmt;
+use std::time;
 use std::collections::HashMap;
 
 use std::ops::{Deref, DerefMut};
 
 use checktable;
 
-use flow::data::DataType;
-use ops::{Record, Datas};
+use flow::data::{ColumnType, DataType};
+use ops::{Record, Records, Datas};
+use ops::predicate::Predicate;
 use flow::domain;
 use flow::{Ingredient, NodeAddress, Edge};
 use flow::payload::Packet;
 use flow::migrate::materialization::Tag;
+use flow::statistics;
+use flow::trace::ReadTracer;
 
 use backlog;
+use prelude::RangeGetter;
 
 /// A StreamUpdate reflects the addition or deletion of a row from a reader node.
 #[derive(Clone, Debug, PartialEq)]
@@ -33,7 +38,8 @@ impl From<Record> for StreamUpdate {
         match other {
             Record::Positive(u) => StreamUpdate::AddRow(u),
             Record::Negative(u) => StreamUpdate::DeleteRow(u),
-            Record::DeleteRequest(..) => unreachable!(),
+            Record::DeleteRequest(..) |
+            Record::TruncateRequest => unreachable!(),
         }
     }
 }
@@ -44,26 +50,242 @@ impl From<Vec<DataType>> for StreamUpdate {
     }
 }
 
+/// A Subscription holds the contents of a view at some point in time, along with a stream of all
+/// updates to the view after that point.
+///
+/// Applying the updates to the snapshot, in order, keeps it identical to the view.
+pub struct Subscription {
+    /// The contents of the view when the subscription started.
+    pub snapshot: Vec<Vec<DataType>>,
+    /// The timestamp of the view when the subscription started.
+    pub ts: i64,
+    /// Every update to the view after the snapshot was taken.
+    pub updates: mpsc::Receiver<Vec<StreamUpdate>>,
+}
+
+/// A PreparedQuery describes a lookup on a reader node that, besides matching the reader's key,
+/// only returns the rows that match a fixed predicate, and only some of their columns.
+///
+/// The predicate and projection are applied while reading from the reader's state, so rows that
+/// do not match are never copied out of it. See `Blender::get_prepared_getter`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreparedQuery {
+    /// Only return rows that match this predicate, if set.
+    pub predicate: Option<Predicate>,
+    /// Only return these columns of each row, in order, if set.
+    pub projection: Option<Vec<usize>>,
+}
+
+impl PreparedQuery {
+    /// Every column the query refers to.
+    pub(crate) fn columns(&self) -> Vec<usize> {
+        let mut cols = self.predicate.as_ref().map(|p| p.columns()).unwrap_or_else(Vec::new);
+        cols.extend(self.projection.iter().flat_map(|cols| cols.iter().cloned()));
+        cols
+    }
+}
+
+/// A BaseWrite describes a batch of updates that has been applied by a base node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BaseWrite {
+    /// The rows that were added to or removed from the base node.
+    pub records: Vec<StreamUpdate>,
+    /// The timestamp assigned to the batch, if it was written transactionally.
+    pub ts: Option<i64>,
+}
+
+/// A SwapPolicy determines how often a reader node exposes new writes to its readers.
+///
+/// Every swap makes queries observe all writes processed by the reader so far, but also has a
+/// cost proportional to the number of writes since the last swap. Swapping less often trades
+/// read freshness for write throughput.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapPolicy {
+    /// Expose writes after every batch the reader processes. This is the default.
+    EveryBatch,
+    /// Expose writes periodically, with the given interval between swaps.
+    Interval(time::Duration),
+    /// Only expose writes when explicitly asked to with `Blender::swap_reader`.
+    Manual,
+}
+
+impl Default for SwapPolicy {
+    fn default() -> Self {
+        SwapPolicy::EveryBatch
+    }
+}
+
 #[derive(Clone)]
 pub struct Reader {
     pub streamers: sync::Arc<sync::Mutex<Vec<mpsc::Sender<Vec<StreamUpdate>>>>>,
     pub state: Option<backlog::ReadHandle>,
+    pub indexes: Vec<backlog::ReadHandle>,
     pub token_generator: Option<checktable::TokenGenerator>,
+    pub swap: SwapPolicy,
+    pub trace: sync::Arc<ReadTracer>,
+}
+
+/// Look up `q` in `state`, counting the lookup in `trace`, and recording it if it is sampled.
+fn traced_lookup(state: &backlog::ReadHandle,
+                 trace: &ReadTracer,
+                 q: &DataType)
+                 -> Result<Datas, ()> {
+    let start = trace.sample();
+    let res = state.find_and(q, |rs| rs.into_iter().map(|v| (&**v).clone()).collect::<Vec<_>>())
+        .map(|r| r.0);
+    trace.count(&res);
+    if let Some(start) = start {
+        trace.record(start, &res);
+    }
+    res
 }
 
 impl Reader {
     pub fn get_reader
         (&self)
          -> Option<Box<Fn(&DataType) -> Result<Vec<Vec<DataType>>, ()> + Send + Sync>> {
+        let trace = self.trace.clone();
         self.state.clone().map(|arc| {
             Box::new(move |q: &DataType| -> Result<Datas, ()> {
-                arc.find_and(q,
-                              |rs| rs.into_iter().map(|v| (&**v).clone()).collect::<Vec<_>>())
+                traced_lookup(&arc, &trace, q)
+            }) as Box<_>
+        })
+    }
+
+    /// A handle for reading this reader's state by column `col`, which is either its key or one of
+    /// its secondary indexes.
+    pub fn index(&self, col: usize) -> Option<&backlog::ReadHandle> {
+        self.state
+            .iter()
+            .chain(self.indexes.iter())
+            .find(|s| s.key() == col)
+    }
+
+    pub fn get_index_reader
+        (&self,
+         col: usize)
+         -> Option<Box<Fn(&DataType) -> Result<Vec<Vec<DataType>>, ()> + Send + Sync>> {
+        let trace = self.trace.clone();
+        self.index(col).cloned().map(|arc| {
+            Box::new(move |q: &DataType| -> Result<Datas, ()> {
+                traced_lookup(&arc, &trace, q)
+            }) as Box<_>
+        })
+    }
+
+    /// A function that looks up the rows with the given values in all of the columns `cols`.
+    ///
+    /// The lookup goes through whichever of the reader's key and secondary indexes is on one of
+    /// the columns, and filters the rows it finds by the others (see
+    /// `backlog::ReadHandle::find_compound_and`). `None` is returned if none of the columns are
+    /// indexed.
+    pub fn get_compound_reader
+        (&self,
+         cols: &[usize])
+         -> Option<Box<Fn(&[DataType]) -> Result<Vec<Vec<DataType>>, ()> + Send + Sync>> {
+        let cols = Vec::from(cols);
+        self.state
+            .iter()
+            .chain(self.indexes.iter())
+            .find(|s| cols.contains(&s.key()))
+            .cloned()
+            .map(|arc| {
+                Box::new(move |q: &[DataType]| -> Result<Datas, ()> {
+                    arc.find_compound_and(&cols[..], q, |rs| {
+                            rs.into_iter().map(|v| (&***v).clone()).collect::<Vec<_>>()
+                        })
+                        .map(|r| r.0)
+                }) as Box<_>
+            })
+    }
+
+    pub fn get_range_reader(&self) -> Option<RangeGetter> {
+        self.state.clone().and_then(|arc| if arc.is_ordered() {
+            Some(Box::new(move |lo: &DataType, hi: &DataType| -> Result<Datas, ()> {
+                arc.find_range_and(lo, hi, |rs| {
+                        rs.into_iter().map(|v| (&**v).clone()).collect::<Vec<_>>()
+                    })
+                    .map(|r| r.0.into_iter().flat_map(|(_, rs)| rs).collect())
+            }) as Box<_>)
+        } else {
+            None
+        })
+    }
+
+    pub fn get_bulk_reader
+        (&self)
+         -> Option<Box<Fn(&[DataType]) -> Result<Vec<Datas>, ()> + Send + Sync>> {
+        self.state.clone().map(|arc| {
+            Box::new(move |qs: &[DataType]| -> Result<Vec<Datas>, ()> {
+                arc.find_many_and(qs,
+                                   |rs| rs.into_iter().map(|v| (&**v).clone()).collect::<Vec<_>>())
+                    .map(|r| r.0)
+            }) as Box<_>
+        })
+    }
+
+    pub fn get_prepared_reader
+        (&self,
+         query: PreparedQuery)
+         -> Option<Box<Fn(&DataType) -> Result<Datas, ()> + Send + Sync>> {
+        self.state.clone().map(|arc| {
+            Box::new(move |q: &DataType| -> Result<Datas, ()> {
+                arc.find_and(q, |rs| {
+                        rs.into_iter()
+                            .filter(|v| query.predicate.as_ref().map_or(true, |p| p.matches(v)))
+                            .map(|v| match query.projection {
+                                Some(ref cols) => cols.iter().map(|&c| v[c].clone()).collect(),
+                                None => (&**v).clone(),
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .map(|r| r.0)
+            }) as Box<_>
+        })
+    }
+
+    pub fn get_timestamped_reader
+        (&self)
+         -> Option<Box<Fn(&DataType) -> Result<Vec<(Vec<DataType>, i64)>, ()> + Send + Sync>> {
+        self.state.clone().map(|arc| {
+            Box::new(move |q: &DataType| -> Result<Vec<(Vec<DataType>, i64)>, ()> {
+                arc.find_and(q, |rs| {
+                        rs.into_iter().map(|v| ((&**v).clone(), v.ts())).collect::<Vec<_>>()
+                    })
                     .map(|r| r.0)
             }) as Box<_>
         })
     }
 
+    pub fn get_count_reader(&self)
+                            -> Option<Box<Fn(&DataType) -> Result<usize, ()> + Send + Sync>> {
+        self.state.clone().map(|arc| {
+            Box::new(move |q: &DataType| -> Result<usize, ()> { arc.count(q) }) as Box<_>
+        })
+    }
+
+    pub fn get_contains_reader(&self)
+                               -> Option<Box<Fn(&DataType) -> Result<bool, ()> + Send + Sync>> {
+        self.state.clone().map(|arc| {
+            Box::new(move |q: &DataType| -> Result<bool, ()> { arc.contains(q) }) as Box<_>
+        })
+    }
+
+    /// The estimated memory used by this reader's state and its secondary indexes.
+    pub fn memory(&self) -> statistics::NodeMemory {
+        let mut m = statistics::NodeMemory::default();
+        if let Some(ref s) = self.state {
+            let (rows, bytes) = s.memory(false);
+            m.rows = rows;
+            m.bytes = bytes;
+        }
+        for index in &self.indexes {
+            // secondary indexes share their rows with the reader's state
+            m.bytes += index.memory(true).1;
+        }
+        m
+    }
+
     pub fn key(&self) -> Result<usize, String> {
         match self.state {
             None => Err(String::from("no state on reader")),
@@ -84,7 +306,10 @@ impl Default for Reader {
         Reader {
             streamers: sync::Arc::default(),
             state: None,
+            indexes: Vec::new(),
             token_generator: None,
+            swap: SwapPolicy::default(),
+            trace: sync::Arc::default(),
         }
     }
 }
@@ -202,6 +427,56 @@ impl Type {
             Type::Source => unreachable!(),
         }
     }
+
+    /// The type of the values in the given column of this node, if it is known.
+    ///
+    /// Types are declared on base nodes, and follow a column through the graph for as long as it
+    /// is passed on unchanged. A column that merges columns of different parents (as in a union)
+    /// has the type all of theirs can be coerced to, if any. Computed columns only have a type if
+    /// the node that computes them knows it.
+    pub fn column_type(&self,
+                       column: usize,
+                       graph: &petgraph::Graph<Node, Edge>,
+                       index: NodeIndex)
+                       -> Option<ColumnType> {
+        let parents: Vec<_> = graph.neighbors_directed(index, petgraph::EdgeDirection::Incoming)
+            .collect();
+
+        match *self {
+            Type::Ingress |
+            Type::Reader(..) |
+            Type::Egress { .. } => {
+                assert_eq!(parents.len(), 1);
+                graph[parents[0]].column_type(column, graph, parents[0])
+            }
+            Type::Internal(ref i) => {
+                if let Some(ty) = i.column_type(column) {
+                    return Some(ty);
+                }
+                if i.is_base() {
+                    return None;
+                }
+
+                let mut types = i.parent_columns(column).into_iter().map(|(n, c)| {
+                    let n = if n.is_global() {
+                        *n.as_global()
+                    } else {
+                        // see base_columns
+                        *parents.iter()
+                            .find(|p| graph[**p].addr == Some(n))
+                            .unwrap()
+                    };
+                    c.and_then(|c| graph[n].column_type(c, graph, n))
+                });
+                let first = types.next().and_then(|ty| ty);
+                types.fold(first, |acc, ty| match (acc, ty) {
+                    (Some(a), Some(b)) => a.unify(b),
+                    _ => None,
+                })
+            }
+            Type::Source => unreachable!(),
+        }
+    }
 }
 
 impl fmt::Debug for Type {
@@ -250,6 +525,10 @@ pub struct Node {
 
     fields: Vec<String>,
     inner: NodeHandle,
+
+    replay_via: Option<NodeIndex>,
+
+    write_listeners: sync::Arc<sync::Mutex<Vec<mpsc::Sender<BaseWrite>>>>,
 }
 
 impl Node {
@@ -265,6 +544,10 @@ impl Node {
 
             fields: fields.into_iter().map(|s| s.to_string()).collect(),
             inner: NodeHandle::Owned(inner),
+
+            replay_via: None,
+
+            write_listeners: sync::Arc::default(),
         }
     }
 
@@ -282,6 +565,17 @@ impl Node {
         &self.fields[..]
     }
 
+    /// Add a field after the node's existing ones, and return its index.
+    pub fn add_field<S: ToString>(&mut self, field: S) -> usize {
+        self.fields.push(field.to_string());
+        self.fields.len() - 1
+    }
+
+    /// Remove the field with the given index.
+    pub fn remove_field(&mut self, column: usize) -> String {
+        self.fields.remove(column)
+    }
+
     pub fn domain(&self) -> domain::Index {
         match self.domain {
             Some(domain) => domain,
@@ -291,6 +585,19 @@ impl Node {
         }
     }
 
+    pub fn assigned_domain(&self) -> Option<domain::Index> {
+        self.domain
+    }
+
+    /// The ancestor that replays to this node should preferably go through, if any.
+    pub fn replay_via(&self) -> Option<NodeIndex> {
+        self.replay_via
+    }
+
+    pub fn set_replay_via(&mut self, ancestor: NodeIndex) {
+        self.replay_via = Some(ancestor);
+    }
+
     pub fn addr(&self) -> NodeAddress {
         match self.addr {
             Some(addr) => addr,
@@ -323,9 +630,39 @@ impl Node {
 
         let mut n = self.mirror(inner);
         n.addr = self.addr;
+        // listeners can still be added externally after the node has been handed to its domain
+        n.write_listeners = self.write_listeners.clone();
         n
     }
 
+    /// Obtain a channel that is notified of every batch of updates applied by this base node.
+    ///
+    /// Batches are delivered in the order the base applies them, after any deletions have been
+    /// resolved to the rows they remove.
+    pub fn on_write(&self) -> mpsc::Receiver<BaseWrite> {
+        assert!(self.is_internal() && self.is_base(),
+                "only base nodes can notify about writes");
+        let (tx, rx) = mpsc::channel();
+        self.write_listeners.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Notify all listeners registered with `on_write` that a batch of updates has been applied.
+    pub fn notify_write(&self, rs: &Records, ts: Option<i64>) {
+        let mut txs = self.write_listeners.lock().unwrap();
+        if txs.is_empty() || rs.is_empty() {
+            return;
+        }
+
+        let w = BaseWrite {
+            records: rs.iter().cloned().map(|r| r.into()).collect(),
+            ts: ts,
+        };
+
+        // remove any channels where the receiver has hung up
+        txs.retain(|tx| tx.send(w.clone()).is_ok());
+    }
+
     pub fn add_to(&mut self, domain: domain::Index) {
         self.domain = Some(domain);
     }
@@ -339,6 +676,31 @@ impl Node {
         self.inner.on_commit(self.addr.unwrap(), remap)
     }
 
+    /// Move this node to the domain `domain`, where it has the local address `addr`.
+    ///
+    /// Internal nodes refer to their ancestors by local address, so those references are first
+    /// translated back to global addresses using `unmap`, and then to the ancestors' addresses in
+    /// the new domain using `remap`. Going through global addresses keeps an address that is used
+    /// in both domains, but by different nodes, from being translated twice. Unlike `on_commit`,
+    /// this also works for nodes that have already been handed to their domain.
+    pub fn relocate(&mut self,
+                    domain: domain::Index,
+                    addr: NodeAddress,
+                    unmap: &HashMap<NodeAddress, NodeAddress>,
+                    remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.domain = Some(domain);
+        self.addr = Some(addr);
+
+        let inner = match self.inner {
+            NodeHandle::Owned(ref mut t) |
+            NodeHandle::Taken(ref mut t) => t,
+        };
+        if let Type::Internal(ref mut i) = *inner {
+            i.on_commit(addr, unmap);
+            i.on_commit(addr, remap);
+        }
+    }
+
     pub fn describe(&self, f: &mut fmt::Write, idx: NodeIndex) -> fmt::Result {
         use regex::Regex;
 
@@ -4,6 +4,8 @@ use flow;
 use checktable;
 use flow::domain;
 use flow::statistics;
+use flow::metrics;
+use flow::provenance;
 use flow::prelude::*;
 
 use std::fmt;
@@ -44,8 +46,13 @@ pub enum TransactionState {
 pub enum Packet {
     // Data messages
     //
-    /// Regular data-flow update.
-    Message { link: Link, data: Records },
+    /// Regular data-flow update, which is followed through the graph by `trace` if it is set
+    /// (see `provenance`).
+    Message {
+        link: Link,
+        data: Records,
+        trace: Option<provenance::Tracer>,
+    },
 
     /// Transactional data-flow update.
     Transaction {
@@ -54,6 +61,20 @@ pub enum Packet {
         state: TransactionState,
     },
 
+    /// Update of a row of a base node, sent to the base domain's ingress node for the base like a
+    /// regular update, that is only applied if the row the base holds with the values `key` in its
+    /// key `columns` has the value `expected` in `column`. Whether it was applied is sent on `tx`,
+    /// along with the row the base holds if it was not.
+    ConditionalUpdate {
+        link: Link,
+        data: Records,
+        columns: Vec<usize>,
+        key: Vec<DataType>,
+        column: usize,
+        expected: DataType,
+        tx: mpsc::SyncSender<Result<(), flow::ConditionFailed>>,
+    },
+
     /// Update that is part of a tagged data-flow replay path.
     Replay {
         link: Link,
@@ -70,6 +91,50 @@ pub enum Packet {
         parents: Vec<flow::LocalNodeIndex>,
     },
 
+    /// Remove the given nodes from this domain, along with any state they have materialized.
+    RemoveNodes { nodes: Vec<flow::LocalNodeIndex> },
+
+    /// Hand the given nodes, along with the state they hold, over to the domain `domain`.
+    ///
+    /// Each moved node is listed with its local address in this domain, its address in the other
+    /// domain, and its children there. References to ancestors are translated using `unmap` and
+    /// `remap` (see `Node::relocate`). The nodes that are left behind, but that fed moved nodes,
+    /// feed the `egress` nodes added below them instead (if they did not already have one), and
+    /// those forward to the `ingress` nodes added to the other domain. Some ingress nodes are
+    /// given a copy of the state of the node they receive updates from. Egress nodes stop
+    /// forwarding to the ingress nodes in `disconnect`, which the moved nodes feed directly once
+    /// they have moved, and `orphaned` egress nodes are dropped altogether.
+    ///
+    /// The nodes are sent to the other domain on `to` before the domain handles any other
+    /// packets, so that every update is processed exactly once, either before or after the move.
+    MoveNodes {
+        nodes: Vec<(flow::LocalNodeIndex, NodeAddress, Vec<NodeAddress>)>,
+        domain: domain::Index,
+        unmap: HashMap<NodeAddress, NodeAddress>,
+        remap: HashMap<NodeAddress, NodeAddress>,
+        egress: Vec<(flow::LocalNodeIndex, domain::NodeDescriptor)>,
+        ingress: Vec<(domain::NodeDescriptor, Option<flow::LocalNodeIndex>)>,
+        disconnect: Vec<(flow::LocalNodeIndex, NodeAddress)>,
+        orphaned: Vec<flow::LocalNodeIndex>,
+        to: mpsc::SyncSender<Packet>,
+        ack: mpsc::SyncSender<()>,
+    },
+
+    /// Take over nodes, and the state they hold, from another domain. See `MoveNodes`.
+    ///
+    /// The nodes are ready to process updates right away.
+    AdoptNodes {
+        nodes: Vec<domain::NodeDescriptor>,
+        state: HashMap<flow::LocalNodeIndex, State>,
+    },
+
+    /// Add a column to the given base node, and fill it in with `default` for the rows it holds.
+    AddBaseColumn {
+        node: flow::LocalNodeIndex,
+        column: usize,
+        default: DataType,
+    },
+
     /// Set up a fresh, empty state for a node, indexed by a particular column.
     ///
     /// This is done in preparation of a subsequent state replay.
@@ -78,18 +143,49 @@ pub enum Packet {
         index: Vec<Vec<usize>>,
     },
 
+    /// Add indices to the existing state of a node, built from the rows it already holds.
+    AddIndices {
+        node: flow::LocalNodeIndex,
+        index: Vec<Vec<usize>>,
+    },
+
+    /// Report the number of rows held by the state of a node, or by a reader node.
+    StateSize {
+        node: flow::LocalNodeIndex,
+        tx: mpsc::SyncSender<usize>,
+    },
+
+    /// Send the number of rows in the state of the given node that have the values `key` in
+    /// `columns`, or `None` if the state has no index on those columns.
+    StateLookup {
+        node: flow::LocalNodeIndex,
+        columns: Vec<usize>,
+        key: Vec<DataType>,
+        tx: mpsc::SyncSender<Option<usize>>,
+    },
+
     /// Inform domain about a new replay path.
+    ///
+    /// If the path ends in the domain, `done_tx` is set, and `source` is the node replayed from if
+    /// that node is in the domain too. If the domain has to split replayed state into batches, it
+    /// does so according to `pacing`.
     SetupReplayPath {
         tag: Tag,
         path: Vec<NodeAddress>,
-        done_tx: Option<mpsc::SyncSender<()>>,
+        source: Option<NodeAddress>,
+        pacing: flow::ReplayPacing,
+        done_tx: Option<mpsc::SyncSender<(usize, usize)>>,
         ack: mpsc::SyncSender<()>,
     },
 
     /// Instruct domain to replay the state of a particular node along an existing replay path.
+    ///
+    /// If `reader` is set, the state is read from that reader of the node instead of from the
+    /// node's own materialization.
     StartReplay {
         tag: Tag,
         from: NodeAddress,
+        reader: Option<flow::LocalNodeIndex>,
         ack: mpsc::SyncSender<()>,
     },
 
@@ -133,6 +229,35 @@ pub enum Packet {
     GetStatistics(mpsc::SyncSender<(statistics::DomainStats,
                                     HashMap<petgraph::graph::NodeIndex, statistics::NodeStats>)>),
 
+    /// Request that a domain send the estimated memory used by each of its materialized nodes and
+    /// readers on the given sender.
+    GetMemory(mpsc::SyncSender<HashMap<petgraph::graph::NodeIndex, statistics::NodeMemory>>),
+
+    /// Request that a domain send the metrics it has recorded so far on the given sender.
+    GetMetrics(mpsc::SyncSender<metrics::DomainMetrics>),
+
+    /// Instruct a domain to expose all writes made so far to the given reader node.
+    SwapReader(flow::LocalNodeIndex),
+
+    /// Change how often the given reader node exposes new writes, and expose the writes it has
+    /// processed so far.
+    SetSwapPolicy {
+        node: flow::LocalNodeIndex,
+        policy: flow::node::SwapPolicy,
+    },
+
+    /// Send the current contents of the given reader node, along with its timestamp, on
+    /// `snapshot`, and stream all later updates to the node on `tx`.
+    Subscribe {
+        node: flow::LocalNodeIndex,
+        tx: mpsc::Sender<Vec<flow::node::StreamUpdate>>,
+        snapshot: mpsc::SyncSender<(Vec<Vec<DataType>>, i64)>,
+    },
+
+    /// Ask a domain whether it has any buffered work left once it has handled all the packets it
+    /// received before this one. The domain replies with `true` if it is idle.
+    Quiesce(mpsc::SyncSender<bool>),
+
     /// Notify a domain about a timestamp it would otherwise have missed.
     ///
     /// This message will be sent to domains from transactional base nodes with no connection to
@@ -181,10 +306,11 @@ impl Packet {
     {
         use std::mem;
         let m = match mem::replace(self, Packet::Timestamp(0)) {
-            Packet::Message { link, data } => {
+            Packet::Message { link, data, trace } => {
                 Packet::Message {
                     link: link,
                     data: map(data),
+                    trace: trace,
                 }
             }
             Packet::Transaction { link, data, state } => {
@@ -229,10 +355,11 @@ impl Packet {
 
     pub fn clone_data(&self) -> Self {
         match *self {
-            Packet::Message { ref link, ref data } => {
+            Packet::Message { ref link, ref data, ref trace } => {
                 Packet::Message {
                     link: link.clone(),
                     data: data.clone(),
+                    trace: trace.clone(),
                 }
             }
             Packet::Transaction { ref link, ref data, ref state } => {
@@ -0,0 +1,438 @@
+//! Persistence of the contents of base nodes across restarts.
+//!
+//! Each persisted base node has a snapshot file, which holds all of the base's rows as of some
+//! point in time, and a log file, which holds every write the base has applied since. Both are
+//! plain text, with one row per line. Every so often, the log is folded into a new snapshot, and
+//! then discarded. To let a crash happen at any point, snapshots and logs are numbered by
+//! generation: the snapshot of generation `g` covers all logs of earlier generations, so only the
+//! log of generation `g` (if any) needs to be replayed on top of it.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use chrono::Timelike;
+use chrono::naive::datetime::NaiveDateTime;
+
+use flow::data::{self, DataType};
+use flow::node::{BaseWrite, StreamUpdate};
+
+/// A `PersistencePolicy` determines where and how often the contents of a base node are saved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PersistencePolicy {
+    /// The directory to keep snapshots and logs in. It is created if it does not exist.
+    pub dir: PathBuf,
+    /// How often to fold the log of writes into a new snapshot.
+    pub snapshot_interval: time::Duration,
+}
+
+impl Default for PersistencePolicy {
+    fn default() -> Self {
+        PersistencePolicy {
+            dir: PathBuf::from("soup-data"),
+            snapshot_interval: time::Duration::from_secs(60),
+        }
+    }
+}
+
+/// The rows of a base node, and how many copies of each it holds.
+pub type Contents = HashMap<Vec<DataType>, usize>;
+
+/// The files that persist the base node with the given name.
+struct Files {
+    dir: PathBuf,
+    name: String,
+}
+
+impl Files {
+    fn new(dir: &Path, name: &str) -> Self {
+        // node names are chosen by applications, and may not make for valid file names
+        let name = name.chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            })
+            .collect();
+        Files {
+            dir: dir.to_path_buf(),
+            name: name,
+        }
+    }
+
+    fn snapshot(&self) -> PathBuf {
+        self.dir.join(format!("{}.snapshot", self.name))
+    }
+
+    fn log(&self, gen: u64) -> PathBuf {
+        self.dir.join(format!("{}.{}.log", self.name, gen))
+    }
+
+    /// Read the latest snapshot, and replay the log of writes since on top of it.
+    ///
+    /// Returns the generation of the snapshot along with the base's contents.
+    fn load(&self) -> io::Result<(u64, Contents)> {
+        let mut contents = Contents::new();
+        let gen = match read(&self.snapshot())? {
+            None => 0,
+            Some(s) => {
+                let mut lines = s.lines();
+                let gen = lines.next()
+                    .and_then(|header| header.trim_left_matches("snapshot ").parse().ok())
+                    .ok_or_else(|| invalid(format!("{} has no header", self.name)))?;
+                for line in lines {
+                    apply(&mut contents, line)?;
+                }
+                gen
+            }
+        };
+
+        if let Some(log) = read(&self.log(gen))? {
+            // the last write may have been cut short by a crash, in which case it is ignored
+            let complete = log.rfind('\n').map(|i| &log[..i + 1]).unwrap_or("");
+            for line in complete.lines() {
+                apply(&mut contents, line)?;
+            }
+        }
+        Ok((gen, contents))
+    }
+
+    /// Write `contents` to a new snapshot of generation `gen`, and remove any older logs.
+    fn save(&self, gen: u64, contents: &Contents) -> io::Result<()> {
+        let mut s = format!("snapshot {}\n", gen);
+        for (row, &n) in contents {
+            for _ in 0..n {
+                encode('+', row, &mut s);
+            }
+        }
+
+        // write the snapshot in full before it replaces the previous one
+        let tmp = self.dir.join(format!("{}.snapshot.tmp", self.name));
+        {
+            let mut f = fs::File::create(&tmp)?;
+            f.write_all(s.as_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp, self.snapshot())?;
+
+        for old in 0..gen {
+            match fs::remove_file(self.log(old)) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                r => r?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read the file at `path`, if it exists.
+fn read(path: &Path) -> io::Result<Option<String>> {
+    match fs::File::open(path) {
+        Ok(mut f) => {
+            let mut s = String::new();
+            f.read_to_string(&mut s)?;
+            Ok(Some(s))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Append a line that adds (`+`) or removes (`-`) `row` to `out`.
+pub fn encode(sign: char, row: &[DataType], out: &mut String) {
+    use std::fmt::Write;
+
+    out.push(sign);
+    for v in row {
+        out.push('\t');
+        match *v {
+            DataType::None => out.push('N'),
+            DataType::Bool(b) => out.push_str(if b { "b1" } else { "b0" }),
+            DataType::Bytes(ref b) => {
+                out.push('x');
+                out.push_str(&data::hex(&b[..]));
+            }
+            DataType::Timestamp(ref t) => {
+                write!(out, "d{}:{}", t.timestamp(), t.nanosecond()).unwrap()
+            }
+            DataType::Int(n) => write!(out, "i{}", n).unwrap(),
+            DataType::BigInt(n) => write!(out, "I{}", n).unwrap(),
+            DataType::Real(i, frac) => write!(out, "r{}:{}", i, frac).unwrap(),
+            DataType::Text(..) |
+            DataType::TinyText(..) => {
+                let s: String = v.into();
+                out.push('t');
+                for c in s.chars() {
+                    match c {
+                        '\\' => out.push_str("\\\\"),
+                        '\t' => out.push_str("\\t"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        c => out.push(c),
+                    }
+                }
+            }
+        }
+    }
+    out.push('\n');
+}
+
+/// Parse a line written by `encode`.
+pub fn decode(line: &str) -> Result<(bool, Vec<DataType>), String> {
+    fn value(v: &str) -> Option<DataType> {
+        let mut chars = v.chars();
+        let kind = chars.next();
+        let rest = chars.as_str();
+        match kind {
+            Some('N') if rest.is_empty() => Some(DataType::None),
+            Some('b') if rest == "0" || rest == "1" => Some(DataType::Bool(rest == "1")),
+            Some('i') => rest.parse().ok().map(DataType::Int),
+            Some('I') => rest.parse().ok().map(DataType::BigInt),
+            Some('r') => {
+                let mut parts = rest.splitn(2, ':');
+                match (parts.next().and_then(|i| i.parse().ok()),
+                       parts.next().and_then(|f| f.parse().ok())) {
+                    (Some(i), Some(frac)) => Some(DataType::Real(i, frac)),
+                    _ => None,
+                }
+            }
+            Some('x') => {
+                let digits: Vec<_> = rest.chars().map(|c| c.to_digit(16)).collect();
+                if digits.len() % 2 != 0 {
+                    return None;
+                }
+                digits.chunks(2)
+                    .map(|d| match (d[0], d[1]) {
+                        (Some(hi), Some(lo)) => Some((hi * 16 + lo) as u8),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<u8>>>()
+                    .map(DataType::from)
+            }
+            Some('d') => {
+                let mut parts = rest.splitn(2, ':');
+                match (parts.next().and_then(|s| s.parse().ok()),
+                       parts.next().and_then(|n| n.parse().ok())) {
+                    (Some(secs), Some(nanos)) => {
+                        NaiveDateTime::from_timestamp_opt(secs, nanos).map(DataType::Timestamp)
+                    }
+                    _ => None,
+                }
+            }
+            Some('t') => {
+                let mut s = String::with_capacity(rest.len());
+                let mut chars = rest.chars();
+                while let Some(c) = chars.next() {
+                    if c != '\\' {
+                        s.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('\\') => s.push('\\'),
+                        Some('t') => s.push('\t'),
+                        Some('n') => s.push('\n'),
+                        Some('r') => s.push('\r'),
+                        _ => return None,
+                    }
+                }
+                Some(s.into())
+            }
+            _ => None,
+        }
+    }
+
+    let mut fields = line.split('\t');
+    let positive = match fields.next() {
+        Some("+") => true,
+        Some("-") => false,
+        _ => return Err(format!("invalid record: {}", line)),
+    };
+    let row = fields.map(|v| if v.is_empty() { None } else { value(v) })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| format!("invalid value in record: {}", line))?;
+    Ok((positive, row))
+}
+
+/// Apply a line written by `encode` to `contents`.
+fn apply(contents: &mut Contents, line: &str) -> io::Result<()> {
+    let (positive, row) = decode(line).map_err(invalid)?;
+    update(contents, positive, row);
+    Ok(())
+}
+
+fn update(contents: &mut Contents, positive: bool, row: Vec<DataType>) {
+    if positive {
+        *contents.entry(row).or_insert(0) += 1;
+    } else {
+        let gone = match contents.get_mut(&row) {
+            Some(n) => {
+                *n -= 1;
+                *n == 0
+            }
+            None => false,
+        };
+        if gone {
+            contents.remove(&row);
+        }
+    }
+}
+
+/// Read the persisted contents of the base node called `name`.
+///
+/// The contents are immediately written out as a fresh snapshot, so that the log can start from
+/// scratch. Returns the generation of the new snapshot, along with the contents.
+pub fn restore(name: &str, policy: &PersistencePolicy) -> io::Result<(u64, Contents)> {
+    fs::create_dir_all(&policy.dir)?;
+    let files = Files::new(&policy.dir, name);
+    let (gen, contents) = files.load()?;
+    files.save(gen + 1, &contents)?;
+    Ok((gen + 1, contents))
+}
+
+/// Persist every write received on `rx` for the base node called `name`, until the base node
+/// goes away.
+///
+/// `contents` and `gen` should be as returned by `restore`. The writes happen on a thread of their
+/// own. Each batch is flushed to disk before the next one is picked up.
+pub fn spawn(name: String,
+             rx: mpsc::Receiver<BaseWrite>,
+             mut contents: Contents,
+             mut gen: u64,
+             policy: PersistencePolicy)
+             -> thread::JoinHandle<()> {
+    let files = Files::new(&policy.dir, &name);
+    thread::Builder::new()
+        .name(format!("persist-{}", name))
+        .spawn(move || {
+            let open = |gen| {
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(files.log(gen))
+                    .expect("failed to open log of writes")
+            };
+
+            let mut log = open(gen);
+            let mut last_snapshot = time::Instant::now();
+            let mut dirty = false;
+            loop {
+                let wait = policy.snapshot_interval
+                    .checked_sub(last_snapshot.elapsed())
+                    .unwrap_or(time::Duration::from_secs(0));
+                let first = match rx.recv_timeout(wait) {
+                    Ok(w) => Some(w),
+                    Err(mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                };
+
+                if let Some(first) = first {
+                    // pick up anything else that has queued up in the meantime
+                    let mut s = String::new();
+                    for w in Some(first).into_iter().chain(rx.try_iter()) {
+                        for u in w.records {
+                            let (positive, row) = match u {
+                                StreamUpdate::AddRow(row) => (true, row),
+                                StreamUpdate::DeleteRow(row) => (false, row),
+                            };
+                            encode(if positive { '+' } else { '-' }, &row[..], &mut s);
+                            update(&mut contents, positive, (*row).clone());
+                        }
+                    }
+                    log.write_all(s.as_bytes()).expect("failed to write to log of writes");
+                    log.sync_data().expect("failed to flush log of writes");
+                    dirty = true;
+                }
+
+                if dirty && last_snapshot.elapsed() >= policy.snapshot_interval {
+                    gen += 1;
+                    files.save(gen, &contents).expect("failed to write snapshot");
+                    log = open(gen);
+                    dirty = false;
+                }
+                if last_snapshot.elapsed() >= policy.snapshot_interval {
+                    last_snapshot = time::Instant::now();
+                }
+            }
+        })
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::naive::date::NaiveDate;
+    use std::env;
+    use std::fs;
+    use std::time;
+
+    fn policy(test: &str) -> PersistencePolicy {
+        let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap();
+        let dir = env::temp_dir().join(format!("soup-{}-{}", test, now.subsec_nanos()));
+        PersistencePolicy {
+            dir: dir,
+            ..PersistencePolicy::default()
+        }
+    }
+
+    #[test]
+    fn it_encodes() {
+        let row = vec![DataType::None,
+                       1.into(),
+                       DataType::BigInt(-2),
+                       true.into(),
+                       vec![0u8, 0x7f, 0xff].into(),
+                       NaiveDate::from_ymd(2017, 3, 1).and_hms_nano(12, 30, 5, 7).into(),
+                       (-1.5f64).into(),
+                       "a".into(),
+                       "a longer string with\ttabs\nand \\ newlines".into()];
+        let mut s = String::new();
+        encode('-', &row[..], &mut s);
+        assert!(s.ends_with('\n'));
+        assert_eq!(s.lines().count(), 1);
+        assert_eq!(decode(s.trim_right_matches('\n')), Ok((false, row)));
+        assert!(decode("+\tx1").is_err());
+        assert!(decode("*\ti1").is_err());
+    }
+
+    #[test]
+    fn it_restores() {
+        let policy = policy("restore");
+
+        // nothing is persisted yet
+        let (gen, contents) = restore("base", &policy).unwrap();
+        assert_eq!(gen, 1);
+        assert!(contents.is_empty());
+
+        // write a log on top of the snapshot, with a write cut short at the end
+        let files = Files::new(&policy.dir, "base");
+        let mut s = String::new();
+        encode('+', &[1.into()], &mut s);
+        encode('+', &[1.into()], &mut s);
+        encode('+', &[2.into()], &mut s);
+        encode('-', &[1.into()], &mut s);
+        s.push_str("+\ti3");
+        fs::File::create(files.log(gen)).unwrap().write_all(s.as_bytes()).unwrap();
+
+        let (gen, contents) = restore("base", &policy).unwrap();
+        assert_eq!(gen, 2);
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[&vec![1.into()]], 1);
+        assert_eq!(contents[&vec![2.into()]], 1);
+
+        // the log has been folded into the snapshot
+        assert!(!files.log(1).exists());
+        let (_, again) = restore("base", &policy).unwrap();
+        assert_eq!(again, contents);
+
+        fs::remove_dir_all(&policy.dir).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+//! Policies for assigning new nodes to domains.
+//!
+//! Every domain is processed by a thread of its own, so which nodes share a domain determines
+//! which nodes compete for the same thread, and which updates have to be sent between threads.
+//! A node can be assigned to a domain explicitly with `Migration::assign_domain`, kept in the same
+//! domain as other nodes with `Migration::colocate`, or kept apart from all other nodes with
+//! `Migration::isolate`. The domains of all other new nodes are picked by the `Placement` policy
+//! set with `Blender::place_with`.
+
+use petgraph;
+use petgraph::graph::NodeIndex;
+
+use std::collections::{HashMap, HashSet};
+
+use flow::domain;
+use flow::prelude::*;
+use flow::NodeAddress;
+
+use slog::Logger;
+
+/// A new node that a `Placement` policy is asked to pick a domain for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unplaced {
+    /// The node's address.
+    pub node: NodeAddress,
+    /// The node's name.
+    pub name: String,
+    /// What the node computes (e.g., `"internal ⋈ node"`), as in `NodeSummary`.
+    pub kind: String,
+    /// Whether the node is a base node.
+    pub base: bool,
+    /// The node's parents, in the order they were added, along with the domains they are in.
+    ///
+    /// New nodes are placed in topological order, so all parents have a domain by the time a
+    /// node is placed. Base nodes have no parents.
+    pub parents: Vec<(NodeAddress, domain::Index)>,
+}
+
+/// A `Placement` policy picks the domains of new nodes that have not been assigned one.
+pub trait Placement: Send {
+    /// Pick the domain to place the given node in, or return `None` to place it in a new domain
+    /// of its own.
+    ///
+    /// The returned domain must already exist, either because it was created by an earlier
+    /// migration, or through `Migration::add_domain`.
+    fn place(&mut self, node: &Unplaced) -> Option<domain::Index>;
+}
+
+/// Place every node in a new domain of its own. This is the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SeparateDomains;
+
+impl Placement for SeparateDomains {
+    fn place(&mut self, _: &Unplaced) -> Option<domain::Index> {
+        None
+    }
+}
+
+/// Place every node in the domain of its first parent, so that updates pass through chains of
+/// operators without being sent between threads. Base nodes get a domain of their own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParentDomain;
+
+impl Placement for ParentDomain {
+    fn place(&mut self, node: &Unplaced) -> Option<domain::Index> {
+        node.parents.first().map(|&(_, d)| d)
+    }
+}
+
+/// Pick the domains of the nodes added by a migration.
+///
+/// `added` holds the domains the nodes have explicitly been assigned, if any. Every node in a
+/// group in `colocated` ends up in the same domain, and every node in `isolated` ends up in a new
+/// domain of its own. All other nodes are placed by `policy`. New domains are numbered from
+/// `ndomains`, which is updated to count them.
+pub fn assign(log: &Logger,
+              graph: &Graph,
+              source: NodeIndex,
+              added: &HashMap<NodeIndex, Option<domain::Index>>,
+              colocated: &[HashSet<NodeIndex>],
+              isolated: &HashSet<NodeIndex>,
+              policy: &mut Placement,
+              ndomains: &mut usize)
+              -> HashMap<NodeIndex, domain::Index> {
+    let mut placed: HashMap<_, _> =
+        added.iter().filter_map(|(&ni, &d)| d.map(|d| (ni, d))).collect();
+    for ni in placed.keys() {
+        assert!(!isolated.contains(ni),
+                "node {} was both isolated and assigned to a domain",
+                ni.index());
+    }
+
+    // nodes that were assigned a domain take the rest of their group with them
+    let mut group_of = HashMap::new();
+    for (i, group) in colocated.iter().enumerate() {
+        for &ni in group {
+            group_of.insert(ni, i);
+        }
+    }
+    let mut group_domain = HashMap::new();
+    for (ni, &d) in &placed {
+        if let Some(&g) = group_of.get(ni) {
+            if let Some(other) = group_domain.insert(g, d) {
+                assert_eq!(other, d, "colocated nodes were assigned to different domains");
+            }
+        }
+    }
+
+    let mut topo = petgraph::visit::Topo::new(graph);
+    while let Some(ni) = topo.next(graph) {
+        if !added.contains_key(&ni) || placed.contains_key(&ni) {
+            continue;
+        }
+
+        let group = group_of.get(&ni).cloned();
+        let d = if isolated.contains(&ni) {
+            None
+        } else if let Some(&d) = group.and_then(|g| group_domain.get(&g)) {
+            Some(d)
+        } else {
+            let n = &graph[ni];
+            let mut parents: Vec<_> =
+                graph.neighbors_directed(ni, petgraph::EdgeDirection::Incoming)
+                    .filter(|&p| p != source)
+                    .collect();
+            parents.sort();
+            let unplaced = Unplaced {
+                node: NodeAddress::make_global(ni),
+                name: n.name().to_owned(),
+                kind: format!("{:?}", **n),
+                base: n.is_internal() && n.is_base(),
+                parents: parents.into_iter()
+                    .map(|p| {
+                        let d = placed.get(&p).cloned().unwrap_or_else(|| graph[p].domain());
+                        (NodeAddress::make_global(p), d)
+                    })
+                    .collect(),
+            };
+            let d = policy.place(&unplaced);
+            if let Some(d) = d {
+                assert!(d.index() < *ndomains,
+                        "placement policy picked unknown domain {}",
+                        d.index());
+                trace!(log, "node placed by policy"; "node" => ni.index(), "domain" => d.index());
+            }
+            d
+        };
+
+        let d = d.unwrap_or_else(|| {
+            trace!(log, "node automatically added to domain"; "node" => ni.index(), "domain" => *ndomains);
+            *ndomains += 1;
+            (*ndomains - 1).into()
+        });
+        if let Some(g) = group {
+            group_domain.insert(g, d);
+        }
+        placed.insert(ni, d);
+    }
+    placed
+}
@@ -0,0 +1,42 @@
+//! Descriptions of the work a migration will do once it is committed.
+//!
+//! A `MigrationPlan` is obtained from `Migration::plan` before the migration is committed, and
+//! describes which nodes will be materialized, which indices their state will have, and which
+//! replays will be needed to populate them. This makes it possible to spot migrations that will
+//! copy a lot of state around (and so take a long time, and slow down writes while they run)
+//! before deploying them.
+
+use std::collections::HashMap;
+
+use flow::NodeAddress;
+
+/// A replay that a migration is expected to perform to populate a new materialization.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedReplay {
+    /// The nodes the replay goes through, starting with the materialization that is replayed
+    /// from, and ending with the node that is populated.
+    pub path: Vec<NodeAddress>,
+    /// The domains the replay passes through, in order. New nodes that have not been assigned a
+    /// domain will be placed in a new domain of their own, and are listed as `None`.
+    pub domains: Vec<Option<usize>>,
+    /// The number of rows held by the materialization that is replayed from, if it is already
+    /// running.
+    pub rows: Option<usize>,
+}
+
+/// A description of what a migration will do once committed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// The nodes that will be materialized, along with the indices their state will have. This
+    /// includes existing nodes that new nodes will look up records in.
+    pub materializations: HashMap<NodeAddress, Vec<Vec<usize>>>,
+    /// The replays that will populate the new materializations, in the order they will run.
+    pub replays: Vec<PlannedReplay>,
+}
+
+impl MigrationPlan {
+    /// The estimated total number of rows that will be replayed.
+    pub fn rows(&self) -> usize {
+        self.replays.iter().filter_map(|r| r.rows).sum()
+    }
+}
@@ -0,0 +1,223 @@
+//! Prepared reads that look up the results of a parameterized SQL query, and prepared writes that
+//! modify the rows of a base table.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use flow::Mutator;
+use flow::data::DataType;
+use flow::sql::mutation::MutationPlan;
+use ops::Datas;
+
+/// A row returned by a `PreparedRead`, which knows the names of its columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedRow {
+    fields: Arc<Vec<String>>,
+    values: Vec<DataType>,
+}
+
+impl TypedRow {
+    /// The names of the row's columns, in order.
+    pub fn fields(&self) -> &[String] {
+        &self.fields[..]
+    }
+
+    /// The value of the column with the given name, if the row has such a column.
+    pub fn get(&self, field: &str) -> Option<&DataType> {
+        self.fields.iter().position(|f| f == field).map(|i| &self.values[i])
+    }
+
+    /// Turn the row into its values, in column order.
+    pub fn into_values(self) -> Vec<DataType> {
+        self.values
+    }
+}
+
+impl Deref for TypedRow {
+    type Target = [DataType];
+    fn deref(&self) -> &Self::Target {
+        &self.values[..]
+    }
+}
+
+impl fmt::Display for TypedRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, (field, value)) in self.fields.iter().zip(self.values.iter()).enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", field, value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A parameterized SQL query whose results can be read with different parameter values, much
+/// like a prepared statement in a traditional database.
+///
+/// A `PreparedRead` is obtained from `SqlIncorporator::prepare_read` once the query has been added
+/// to the graph. It knows which parameters the query takes and which columns its results have,
+/// and checks every set of parameters it is executed with before looking them up.
+pub struct PreparedRead {
+    getter: Box<Fn(&[DataType]) -> Result<Datas, ()> + Send + Sync>,
+    parameters: Vec<String>,
+    fields: Arc<Vec<String>>,
+}
+
+impl PreparedRead {
+    pub(crate) fn new(getter: Box<Fn(&[DataType]) -> Result<Datas, ()> + Send + Sync>,
+                      parameters: Vec<String>,
+                      fields: Vec<String>)
+                      -> Self {
+        PreparedRead {
+            getter: getter,
+            parameters: parameters,
+            fields: Arc::new(fields),
+        }
+    }
+
+    /// The names of the columns the query's parameters are compared against, in order.
+    pub fn parameters(&self) -> &[String] {
+        &self.parameters[..]
+    }
+
+    /// The names of the columns of the query's results, in order.
+    pub fn fields(&self) -> &[String] {
+        &self.fields[..]
+    }
+
+    /// Read the query's results for the given parameter values.
+    ///
+    /// An error is returned if the wrong number of parameters is given, if a parameter is NULL
+    /// (which no row can ever match), or if the results could not be read.
+    pub fn execute(&self, params: &[DataType]) -> Result<Vec<TypedRow>, String> {
+        if params.len() != self.parameters.len() {
+            return Err(format!("query takes {} parameters, but {} were given",
+                               self.parameters.len(),
+                               params.len()));
+        }
+        if let Some(i) = params.iter().position(|p| *p == DataType::None) {
+            return Err(format!("parameter for {} is NULL", self.parameters[i]));
+        }
+
+        let rows = (self.getter)(params)
+            .map_err(|_| String::from("query results are not yet available"))?;
+        Ok(rows.into_iter()
+            .map(|values| {
+                TypedRow {
+                    fields: self.fields.clone(),
+                    values: values,
+                }
+            })
+            .collect())
+    }
+}
+
+/// A parameterized SQL `DELETE` or `UPDATE` statement that can be executed with different
+/// parameter values.
+///
+/// A `PreparedWrite` is obtained from `SqlIncorporator::prepare_write`. Statements are applied
+/// through a `Mutator` for the table they modify, and so, like other non-transactional writes,
+/// take effect asynchronously.
+///
+/// An `UPDATE` that does not set every column needs to read the current row, which is only possible
+/// if the table is maintained by its primary key. If the table is maintained, updates of rows that
+/// do not exist are ignored; if it is not, an `UPDATE` that sets every column inserts the row if
+/// it does not yet exist.
+pub struct PreparedWrite {
+    mutator: Mutator,
+    plan: MutationPlan,
+    current: Option<Box<Fn(&DataType) -> Result<Datas, ()> + Send + Sync>>,
+}
+
+impl PreparedWrite {
+    pub(crate) fn new(mutator: Mutator,
+                      plan: MutationPlan,
+                      current: Option<Box<Fn(&DataType) -> Result<Datas, ()> + Send + Sync>>)
+                      -> Self {
+        assert!(current.is_some() || plan.assignments.is_none() || plan.sets_all_columns());
+        PreparedWrite {
+            mutator: mutator,
+            plan: plan,
+            current: current,
+        }
+    }
+
+    /// The names of the columns the statement's parameters are assigned to or compared against,
+    /// in order.
+    pub fn parameters(&self) -> &[String] {
+        &self.plan.parameters[..]
+    }
+
+    /// Execute the statement with the given parameter values.
+    ///
+    /// An error is returned if the wrong number of parameters is given, if the primary key of the
+    /// row to modify is NULL, or if the current row could not be read.
+    pub fn execute(&self, params: &[DataType]) -> Result<(), String> {
+        if params.len() != self.plan.parameters.len() {
+            return Err(format!("statement takes {} parameters, but {} were given",
+                               self.plan.parameters.len(),
+                               params.len()));
+        }
+        let key = self.plan.key(params);
+        if key.iter().any(|k| *k == DataType::None) {
+            return Err(String::from("primary key of the row to modify is NULL"));
+        }
+
+        if self.plan.assignments.is_none() {
+            self.mutator.delete(key);
+            return Ok(());
+        }
+
+        let row = match self.current {
+            Some(ref current) => {
+                let rows = current(&key[0])
+                    .map_err(|_| String::from("current row is not yet available"))?;
+                match rows.into_iter().next() {
+                    Some(row) => self.plan.updated_row(params, Some(&row[..])),
+                    None => return Ok(()),
+                }
+            }
+            None => self.plan.updated_row(params, None),
+        };
+        self.mutator.update(row);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepared() -> PreparedRead {
+        PreparedRead::new(Box::new(|k: &[DataType]| if k[0] == DataType::from(1) {
+                              Ok(vec![vec![1.into(), "a".into()]])
+                          } else {
+                              Err(())
+                          }),
+                          vec!["id".into()],
+                          vec!["id".into(), "title".into()])
+    }
+
+    #[test]
+    fn it_executes() {
+        let r = prepared();
+        let rows = r.execute(&[1.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("title"), Some(&"a".into()));
+        assert_eq!(rows[0].get("body"), None);
+        assert_eq!(&rows[0][..], &[1.into(), "a".into()][..]);
+        assert_eq!(format!("{}", rows[0]), "(id: 1, title: \"a\")");
+    }
+
+    #[test]
+    fn it_validates_parameters() {
+        let r = prepared();
+        assert!(r.execute(&[]).is_err());
+        assert!(r.execute(&[1.into(), 2.into()]).is_err());
+        assert!(r.execute(&[DataType::None]).is_err());
+        assert!(r.execute(&[2.into()]).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Tracing of individual writes through the graph.
+//!
+//! A write made with `Mutator::put_traced` carries a `Tracer` along with it. Every node that
+//! processes the write, or an update derived from it, reports the records it produced as a
+//! `TraceEvent` on the tracer's channel: the base node first, then every operator, ingress and
+//! egress node downstream of it, and finally the readers whose state was updated. A node that
+//! produced nothing for the update also reports so, which shows where a write stopped having an
+//! effect.
+//!
+//! Tracing follows packets, not individual records, so the records reported are all the records
+//! produced from the traced write. Egress nodes send traced updates on by themselves rather than
+//! batching them up with other updates (see `Batching`), so that no unrelated records are
+//! reported. Only non-transactional writes can be traced, and tracing stops at domains in other
+//! processes (see the `transport` module).
+
+use std::fmt;
+use std::sync::mpsc;
+
+use petgraph::graph::NodeIndex;
+
+use flow::NodeAddress;
+use flow::domain;
+use flow::node::StreamUpdate;
+use flow::prelude::Records;
+
+/// The kind of node a `TraceEvent` was reported by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceKind {
+    /// The base node that the traced write was made to.
+    Base,
+    /// An operator downstream of the base node.
+    Operator,
+    /// An ingress node, which receives updates from another domain.
+    Ingress,
+    /// An egress node, which sends updates on to other domains.
+    Egress,
+    /// A reader, whose state was updated with the records.
+    Reader,
+}
+
+/// A report of a node having processed a traced write, or an update derived from it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEvent {
+    /// The identifier of the traced write.
+    pub id: u64,
+    /// The node that processed the update.
+    pub node: NodeAddress,
+    /// The domain the node runs in.
+    pub domain: domain::Index,
+    /// What kind of node the node is.
+    pub kind: TraceKind,
+    /// The records the node produced for the update. For readers, these are the records added
+    /// to the reader's state.
+    pub records: Vec<StreamUpdate>,
+}
+
+/// Follows a single write through the graph, reporting every node it passes through.
+#[derive(Clone)]
+pub struct Tracer {
+    id: u64,
+    tx: mpsc::Sender<TraceEvent>,
+}
+
+impl Tracer {
+    /// Report the events for the traced write on `tx`, tagged with `id`.
+    ///
+    /// The same channel can be used for several traced writes, with a different `id` for each.
+    pub fn new(id: u64, tx: mpsc::Sender<TraceEvent>) -> Self {
+        Tracer { id: id, tx: tx }
+    }
+
+    /// The identifier that the events for the traced write are tagged with.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Report that `node` produced `data` from the traced write.
+    pub fn emit(&self, node: NodeIndex, domain: domain::Index, kind: TraceKind, data: &Records) {
+        // whoever was listening may have lost interest; that is no reason to stop processing
+        let _ = self.tx.send(TraceEvent {
+            id: self.id,
+            node: NodeAddress::make_global(node),
+            domain: domain,
+            kind: kind,
+            records: data.iter().cloned().map(|r| r.into()).collect(),
+        });
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer({})", self.id)
+    }
+}
@@ -0,0 +1,134 @@
+//! Horizontal sharding of base nodes, and of the operators below them.
+//!
+//! A sharded node is split into a number of shards, each of which holds the records that hash to
+//! it by their value in the node's key column. The shards are separate nodes, usually in separate
+//! domains, so the work for a single sharded node is spread across as many threads as it has
+//! shards. Where an operator needs its input partitioned by a different column than it is (such as
+//! for a join on another column), every input shard is split up again by the new column, and the
+//! pieces that belong together are unioned back up (see `Migration::add_sharded`).
+
+use flow::{Mutator, NodeAddress};
+use flow::prelude::DataType;
+
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+/// Pick which of `shards` shards records with the value `key` in their key column belong to.
+pub fn shard_of(key: &DataType, shards: usize) -> usize {
+    // Int and BigInt compare equal, so they must also end up in the same shard
+    let mut h = DefaultHasher::new();
+    match *key {
+        DataType::Int(i) => DataType::BigInt(i as i64).hash(&mut h),
+        ref k => k.hash(&mut h),
+    }
+    (h.finish() % shards as u64) as usize
+}
+
+/// A node that is split into shards by the value of one of its columns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sharded {
+    shards: Vec<NodeAddress>,
+    key: Option<usize>,
+}
+
+impl Sharded {
+    /// Treat an ordinary node as a node with a single shard.
+    ///
+    /// Sharded operators that take it as an input split it up by whichever column they need.
+    pub fn unsharded(n: NodeAddress) -> Sharded {
+        Sharded {
+            shards: vec![n],
+            key: None,
+        }
+    }
+
+    /// Describe a node that is split into the given shards by column `key`.
+    pub fn new(shards: Vec<NodeAddress>, key: usize) -> Sharded {
+        assert!(!shards.is_empty(), "sharded nodes need at least one shard");
+        Sharded {
+            shards: shards,
+            key: Some(key),
+        }
+    }
+
+    /// The nodes that make up the shards, in shard order.
+    pub fn shards(&self) -> &[NodeAddress] {
+        &self.shards[..]
+    }
+
+    /// The column records are assigned to shards by, if the node is sharded.
+    pub fn key(&self) -> Option<usize> {
+        self.key
+    }
+
+    /// Whether the node is already split into `shards` shards by column `column`.
+    pub fn sharded_by(&self, column: usize, shards: usize) -> bool {
+        self.shards.len() == shards && (shards == 1 || self.key == Some(column))
+    }
+}
+
+/// A `ShardedMutator` is used to write to a sharded base node.
+///
+/// Every write goes to the shard picked by the row's value in the base's key column. Deletes and
+/// updates are only supported if that column is one of the base's key columns, since the shard
+/// that holds the row is otherwise not known. All writes are non-transactional.
+#[derive(Clone)]
+pub struct ShardedMutator {
+    key: usize,
+    shards: Vec<Mutator>,
+}
+
+impl ShardedMutator {
+    /// Write to the shards of a base node, sharded by column `key`, through the given mutators.
+    pub fn new(key: usize, shards: Vec<Mutator>) -> ShardedMutator {
+        ShardedMutator {
+            key: key,
+            shards: shards,
+        }
+    }
+
+    fn shard(&self, key: &DataType) -> &Mutator {
+        &self.shards[shard_of(key, self.shards.len())]
+    }
+
+    /// The position of the shard key among the base's key columns.
+    fn key_position(&self) -> usize {
+        self.shards[0]
+            .primary_key
+            .iter()
+            .position(|&c| c == self.key)
+            .expect("sharded deletes require the shard key to be a key column of the base node")
+    }
+
+    /// Perform a non-transactional write to the sharded base node.
+    pub fn put<V>(&self, u: V)
+        where V: Into<Vec<DataType>>
+    {
+        let u = u.into();
+        let key = u[self.key].clone();
+        self.shard(&key).put(u)
+    }
+
+    /// Perform a non-transactional delete from the sharded base node.
+    ///
+    /// `key` holds the values of the base node's key columns for the row to delete.
+    pub fn delete<I>(&self, key: I)
+        where I: Into<Vec<DataType>>
+    {
+        let key = key.into();
+        let shard = key[self.key_position()].clone();
+        self.shard(&shard).delete(key)
+    }
+
+    /// Perform a non-transactional update (delete followed by put) to the sharded base node.
+    pub fn update<V>(&self, u: V)
+        where V: Into<Vec<DataType>>
+    {
+        assert!(self.shards[0].primary_key.contains(&self.key),
+                "sharded updates require the shard key to be a key column of the base node");
+
+        let u = u.into();
+        let key = u[self.key].clone();
+        self.shard(&key).update(u)
+    }
+}
@@ -0,0 +1,205 @@
+//! Sinks that write the output of a view to a system outside of Soup.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use flow::node::StreamUpdate;
+
+/// A destination outside of Soup that a view's updates are written to, such as a message queue,
+/// a file, or a webhook.
+///
+/// Sinks are installed with `Migration::sink`.
+pub trait Sink: Send {
+    /// Write a batch of updates to the external system.
+    ///
+    /// If an error is returned, the same batch will be retried, as dictated by the sink's
+    /// `SinkPolicy`.
+    fn write(&mut self, updates: &[StreamUpdate]) -> Result<(), String>;
+
+    /// Handle a batch that could not be written even after retrying, along with the last error.
+    ///
+    /// By default, the batch is dropped. Writing continues with the next batch either way.
+    fn give_up(&mut self, updates: Vec<StreamUpdate>, error: String) {
+        let _ = (updates, error);
+    }
+}
+
+/// A `SinkPolicy` determines how updates are batched and retried when written to a `Sink`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SinkPolicy {
+    /// The largest number of updates to write in one batch.
+    ///
+    /// Updates are never held back to fill up a batch: each batch holds whatever updates are
+    /// waiting to be written when the previous one completes, up to this limit.
+    pub batch_size: usize,
+    /// How many times to retry a batch that failed to be written before giving up on it.
+    pub retries: usize,
+    /// How long to wait before the first retry. The wait doubles with every further retry.
+    pub backoff: time::Duration,
+}
+
+impl Default for SinkPolicy {
+    fn default() -> Self {
+        SinkPolicy {
+            batch_size: 1024,
+            retries: 3,
+            backoff: time::Duration::from_millis(100),
+        }
+    }
+}
+
+/// Write all updates received on `rx` to `sink`, until the sending side hangs up.
+///
+/// This happens on a thread of its own, so that a slow or unavailable external system does not
+/// hold up the domain that produces the updates.
+pub fn spawn(name: String,
+             rx: mpsc::Receiver<Vec<StreamUpdate>>,
+             mut sink: Box<Sink>,
+             policy: SinkPolicy)
+             -> thread::JoinHandle<()> {
+    assert!(policy.batch_size > 0, "sink batches must hold at least one update");
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            let mut pending = Vec::new();
+            while let Ok(updates) = rx.recv() {
+                pending.extend(updates);
+                // pick up anything else that has queued up in the meantime
+                while pending.len() < policy.batch_size {
+                    match rx.try_recv() {
+                        Ok(updates) => pending.extend(updates),
+                        Err(_) => break,
+                    }
+                }
+
+                while !pending.is_empty() {
+                    let rest = if pending.len() > policy.batch_size {
+                        pending.split_off(policy.batch_size)
+                    } else {
+                        Vec::new()
+                    };
+                    write(&mut *sink, pending, &policy);
+                    pending = rest;
+                }
+            }
+        })
+        .unwrap()
+}
+
+fn write(sink: &mut Sink, batch: Vec<StreamUpdate>, policy: &SinkPolicy) {
+    let mut backoff = policy.backoff;
+    let mut attempt = 0;
+    loop {
+        match sink.write(&batch[..]) {
+            Ok(()) => return,
+            Err(e) => {
+                if attempt == policy.retries {
+                    return sink.give_up(batch, e);
+                }
+                thread::sleep(backoff);
+                backoff = backoff * 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time;
+
+    #[derive(Default)]
+    struct Flaky {
+        written: Arc<Mutex<Vec<Vec<StreamUpdate>>>>,
+        dropped: Arc<Mutex<Vec<(Vec<StreamUpdate>, String)>>>,
+        fail: usize,
+    }
+
+    impl Sink for Flaky {
+        fn write(&mut self, updates: &[StreamUpdate]) -> Result<(), String> {
+            if self.fail > 0 {
+                self.fail -= 1;
+                return Err(String::from("unavailable"));
+            }
+            self.written.lock().unwrap().push(updates.to_vec());
+            Ok(())
+        }
+
+        fn give_up(&mut self, updates: Vec<StreamUpdate>, error: String) {
+            self.dropped.lock().unwrap().push((updates, error));
+        }
+    }
+
+    fn update(i: i32) -> StreamUpdate {
+        StreamUpdate::AddRow(Arc::new(vec![i.into()]))
+    }
+
+    fn policy(batch_size: usize, retries: usize) -> SinkPolicy {
+        SinkPolicy {
+            batch_size: batch_size,
+            retries: retries,
+            backoff: time::Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn it_batches() {
+        let sink = Flaky::default();
+        let written = sink.written.clone();
+
+        // queue everything up before the sink starts, so that it all arrives at once
+        let (tx, rx) = mpsc::channel();
+        tx.send(vec![update(1), update(2)]).unwrap();
+        tx.send(vec![update(3), update(4), update(5)]).unwrap();
+        drop(tx);
+
+        spawn(String::from("sink"), rx, Box::new(sink), policy(2, 0)).join().unwrap();
+        assert_eq!(*written.lock().unwrap(),
+                   vec![vec![update(1), update(2)],
+                        vec![update(3), update(4)],
+                        vec![update(5)]]);
+    }
+
+    #[test]
+    fn it_retries() {
+        let sink = Flaky {
+            fail: 2,
+            ..Flaky::default()
+        };
+        let written = sink.written.clone();
+        let dropped = sink.dropped.clone();
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(vec![update(1)]).unwrap();
+        drop(tx);
+
+        spawn(String::from("sink"), rx, Box::new(sink), policy(10, 2)).join().unwrap();
+        assert_eq!(*written.lock().unwrap(), vec![vec![update(1)]]);
+        assert!(dropped.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_gives_up() {
+        let sink = Flaky {
+            fail: 2,
+            ..Flaky::default()
+        };
+        let written = sink.written.clone();
+        let dropped = sink.dropped.clone();
+
+        let (tx, rx) = mpsc::channel();
+        tx.send(vec![update(1)]).unwrap();
+        tx.send(vec![update(2)]).unwrap();
+        drop(tx);
+
+        // the first batch fails twice, and with only one retry, it is dropped
+        spawn(String::from("sink"), rx, Box::new(sink), policy(1, 1)).join().unwrap();
+        assert_eq!(*dropped.lock().unwrap(),
+                   vec![(vec![update(1)], String::from("unavailable"))]);
+        assert_eq!(*written.lock().unwrap(), vec![vec![update(2)]]);
+    }
+}
@@ -0,0 +1,239 @@
+//! Detection of SQL constructs that parse, but that cannot (yet) be turned into data flow.
+//!
+//! Running `check` over a query before incorporating it means that unsupported queries are
+//! rejected with a description of everything that is wrong with them, rather than panicking (or,
+//! worse, silently computing the wrong result) somewhere in the middle of building the graph.
+
+use nom_sql::{ConditionBase, ConditionExpression, FieldExpression, FunctionExpression, Operator,
+              SelectStatement, SqlQuery};
+
+use flow::sql::planner::to_comparison;
+use flow::sql::query_graph::only_literal_comparisons;
+
+use std::collections::HashSet;
+use std::fmt;
+
+/// A SQL construct that is not supported when turning queries into data flow.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnsupportedFeature {
+    /// A join condition that compares columns using something other than equality.
+    NonEquiJoin(Operator),
+    /// A selection predicate that compares a column to a literal using an operator other than
+    /// `=`, `!=`, `<`, `<=`, `>`, and `>=`.
+    NonEqualityPredicate(Operator),
+    /// A query parameter compared to a column using something other than equality.
+    NonEqualityParameter(Operator),
+    /// A comparison whose left-hand side is not a column.
+    NonColumnComparison,
+    /// Conditions combined with something other than `AND`, or with an `OR` that does anything
+    /// but compare columns of a single table to literals.
+    LogicalOperator(Operator),
+    /// A function used as an aggregation that is not supported.
+    Aggregate(&'static str),
+    /// An aggregation over more than one column.
+    MultiColumnAggregate,
+    /// `SELECT DISTINCT`.
+    Distinct,
+    /// An `ORDER BY` clause without a `LIMIT`, or one that orders by more than one column.
+    OrderBy,
+    /// A `LIMIT` clause without an `ORDER BY`, or one with an offset or a limit of zero.
+    Limit,
+    /// A `HAVING` clause in a query without aggregations.
+    Having,
+}
+
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::UnsupportedFeature::*;
+        match *self {
+            NonEquiJoin(ref op) => write!(f, "join on {:?} comparison", op),
+            NonEqualityPredicate(ref op) => write!(f, "{:?} comparison with a literal", op),
+            NonEqualityParameter(ref op) => write!(f, "{:?} comparison with a parameter", op),
+            NonColumnComparison => write!(f, "comparison without a column on the left-hand side"),
+            LogicalOperator(ref op) => write!(f, "conditions combined with {:?}", op),
+            Aggregate(name) => write!(f, "{} aggregation", name),
+            MultiColumnAggregate => write!(f, "aggregation over multiple columns"),
+            Distinct => write!(f, "SELECT DISTINCT"),
+            OrderBy => write!(f, "ORDER BY"),
+            Limit => write!(f, "LIMIT"),
+            Having => write!(f, "HAVING"),
+        }
+    }
+}
+
+/// Produce a human-readable description of a set of unsupported features.
+pub fn describe(unsupported: &[UnsupportedFeature]) -> String {
+    format!("query uses unsupported features: {}",
+            unsupported.iter()
+                .map(|u| u.to_string())
+                .collect::<Vec<_>>()
+                .join(", "))
+}
+
+/// Check whether the given query can be turned into data flow.
+///
+/// If it cannot, all the unsupported constructs the query uses are returned.
+pub fn check(q: &SqlQuery) -> Result<(), Vec<UnsupportedFeature>> {
+    let mut unsupported = Vec::new();
+    if let SqlQuery::Select(ref st) = *q {
+        check_select(st, &mut unsupported);
+    }
+
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(unsupported)
+    }
+}
+
+fn check_select(st: &SelectStatement, unsupported: &mut Vec<UnsupportedFeature>) {
+    if st.distinct {
+        unsupported.push(UnsupportedFeature::Distinct);
+    }
+    // the first k results ordered by a single column are computed by a top-k node
+    if let Some(ref order) = st.order {
+        if st.limit.is_none() || order.columns.len() != 1 {
+            unsupported.push(UnsupportedFeature::OrderBy);
+        }
+    }
+    if let Some(ref limit) = st.limit {
+        if st.order.is_none() || limit.offset != 0 || limit.limit == 0 {
+            unsupported.push(UnsupportedFeature::Limit);
+        }
+    }
+    let mut aggregates = false;
+    if let FieldExpression::Seq(ref fields) = st.fields {
+        for f in fields {
+            if let Some(ref func) = f.function {
+                aggregates = true;
+                check_function(func, unsupported);
+            }
+        }
+    }
+
+    // HAVING conditions are evaluated over the output of an aggregation
+    if let Some(having) = st.group_by.as_ref().and_then(|gb| gb.having.as_ref()) {
+        if !aggregates {
+            unsupported.push(UnsupportedFeature::Having);
+        }
+        check_condition(having, unsupported);
+    }
+
+    if let Some(ref cond) = st.where_clause {
+        check_condition(cond, unsupported);
+    }
+}
+
+fn check_function(func: &FunctionExpression, unsupported: &mut Vec<UnsupportedFeature>) {
+    use nom_sql::FunctionExpression::*;
+
+    let over = match *func {
+        Avg(ref fe) |
+        Count(ref fe) |
+        Sum(ref fe) |
+        Max(ref fe) |
+        Min(ref fe) |
+        GroupConcat(ref fe) => fe,
+    };
+
+    if let FieldExpression::Seq(ref cols) = *over {
+        if cols.len() > 1 {
+            unsupported.push(UnsupportedFeature::MultiColumnAggregate);
+        }
+    }
+}
+
+fn check_condition(ce: &ConditionExpression, unsupported: &mut Vec<UnsupportedFeature>) {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) => {
+            let supported = match ct.operator {
+                Operator::And => true,
+                Operator::Or => {
+                    let mut tables = HashSet::new();
+                    only_literal_comparisons(ce, &mut tables) && tables.len() <= 1
+                }
+                _ => false,
+            };
+            if !supported {
+                unsupported.push(UnsupportedFeature::LogicalOperator(ct.operator.clone()));
+            }
+            for side in ct.left.iter().chain(ct.right.iter()) {
+                check_condition(side, unsupported);
+            }
+        }
+        ConditionExpression::ComparisonOp(ref ct) => {
+            let l = ct.left.as_ref().map(|l| &**l);
+            let r = ct.right.as_ref().map(|r| &**r);
+            match (l, r) {
+                (Some(&ConditionExpression::Base(ConditionBase::Field(_))),
+                 Some(&ConditionExpression::Base(ref r))) => {
+                    if ct.operator != Operator::Equal {
+                        let op = ct.operator.clone();
+                        match *r {
+                            ConditionBase::Field(_) => {
+                                unsupported.push(UnsupportedFeature::NonEquiJoin(op))
+                            }
+                            ConditionBase::Literal(_) => {
+                                // literals can also be compared using inequalities
+                                if to_comparison(&op).is_none() {
+                                    unsupported.push(UnsupportedFeature::NonEqualityPredicate(op))
+                                }
+                            }
+                            ConditionBase::Placeholder => {
+                                unsupported.push(UnsupportedFeature::NonEqualityParameter(op))
+                            }
+                        }
+                    }
+                }
+                _ => unsupported.push(UnsupportedFeature::NonColumnComparison),
+            }
+        }
+        ConditionExpression::Base(_) => unsupported.push(UnsupportedFeature::NonColumnComparison),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom_sql::parser::parse_query;
+    use nom_sql::Operator;
+    use super::*;
+
+    #[test]
+    fn it_accepts_supported_queries() {
+        let q = parse_query("SELECT a.x, COUNT(a.y) FROM a, b WHERE a.x = b.x AND a.z = ? \
+                             GROUP BY a.x;")
+            .unwrap();
+        assert_eq!(check(&q), Ok(()));
+    }
+
+    #[test]
+    fn it_reports_all_unsupported_features() {
+        let q = parse_query("SELECT COUNT(a.y) FROM a, b WHERE a.x > b.x OR a.z < 3;").unwrap();
+        let errs = check(&q).unwrap_err();
+        assert!(errs.contains(&UnsupportedFeature::LogicalOperator(Operator::Or)));
+        assert!(errs.contains(&UnsupportedFeature::NonEquiJoin(Operator::Greater)));
+    }
+
+    #[test]
+    fn it_accepts_order_by_with_limit() {
+        let q = parse_query("SELECT a.x, a.y FROM a WHERE a.z = ? ORDER BY a.y DESC LIMIT 10;")
+            .unwrap();
+        assert_eq!(check(&q), Ok(()));
+
+        let q = parse_query("SELECT a.x FROM a ORDER BY a.y;").unwrap();
+        assert_eq!(check(&q), Err(vec![UnsupportedFeature::OrderBy]));
+
+        let q = parse_query("SELECT a.x FROM a LIMIT 10;").unwrap();
+        assert_eq!(check(&q), Err(vec![UnsupportedFeature::Limit]));
+    }
+
+    #[test]
+    fn it_accepts_predicates_on_one_table() {
+        let q = parse_query("SELECT a.x FROM a WHERE a.y = 1 OR a.z < 3;").unwrap();
+        assert_eq!(check(&q), Ok(()));
+
+        let q = parse_query("SELECT a.x FROM a, b WHERE a.y = 1 OR b.z < 3;").unwrap();
+        assert_eq!(check(&q),
+                   Err(vec![UnsupportedFeature::LogicalOperator(Operator::Or)]));
+    }
+}
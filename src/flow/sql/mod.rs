@@ -1,3 +1,8 @@
+pub mod capabilities;
+pub mod mutation;
+pub mod optimizer;
 pub mod passes;
+pub mod planner;
 pub mod query_graph;
 pub mod query_signature;
+pub mod subqueries;
@@ -0,0 +1,796 @@
+//! Parsing and planning of SQL statements that modify the rows of base tables.
+//!
+//! The SQL parser only understands the statements that define tables and views, so `DELETE` and
+//! `UPDATE` statements are parsed here. Only the forms that map directly onto the operations of a
+//! `Mutator` are supported: the row to modify is identified by an equality condition on every
+//! column of its table's primary key, as in
+//!
+//! ```sql
+//! DELETE FROM users WHERE id = ?;
+//! UPDATE users SET name = ?, karma = 0 WHERE users.id = ?;
+//! ```
+//!
+//! Column names may be qualified with the name of the statement's table, and unqualified names are
+//! taken to refer to it. Values are either `?` parameters or literals: integers, reals, strings,
+//! `TRUE`, `FALSE`, and `NULL`.
+//!
+//! `DROP VIEW` statements, which remove queries rather than rows, and `ALTER TABLE` statements
+//! that add columns to tables, are parsed here as well. So are the column defaults and `NOT NULL`
+//! constraints of `CREATE TABLE` statements, which the SQL parser drops.
+
+use flow::data::DataType;
+use flow::sql::planner::Catalog;
+use ops::base::ColumnSpec;
+
+/// A value in a statement.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The parameter with the given index, counting from the start of the statement.
+    Parameter(usize),
+    /// A constant value.
+    Literal(DataType),
+}
+
+impl Value {
+    fn resolve(&self, params: &[DataType]) -> DataType {
+        match *self {
+            Value::Parameter(i) => params[i].clone(),
+            Value::Literal(ref v) => v.clone(),
+        }
+    }
+}
+
+/// A parsed `DELETE` or `UPDATE` statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    /// The table whose rows the statement modifies.
+    pub table: String,
+    /// For an `UPDATE`, the columns it sets and their new values. `None` for a `DELETE`.
+    pub assignments: Option<Vec<(String, Value)>>,
+    /// The columns in the `WHERE` clause, and the values they must be equal to.
+    pub conditions: Vec<(String, Value)>,
+    /// The number of parameters in the statement.
+    pub parameters: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(String),
+    Text(String),
+    Parameter,
+    Symbol(char),
+}
+
+fn describe(t: Option<&Token>) -> String {
+    match t {
+        None => String::from("end of statement"),
+        Some(&Token::Word(ref w)) |
+        Some(&Token::Number(ref w)) => w.clone(),
+        Some(&Token::Text(ref s)) => format!("'{}'", s),
+        Some(&Token::Parameter) => String::from("?"),
+        Some(&Token::Symbol(c)) => c.to_string(),
+    }
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    loop {
+        let c = match chars.peek() {
+            Some(&c) => c,
+            None => break,
+        };
+
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_alphabetic() || c == '_' {
+            let mut w = String::new();
+            while chars.peek().map(|&c| c.is_alphanumeric() || c == '_' || c == '.') ==
+                  Some(true) {
+                w.push(chars.next().unwrap());
+            }
+            tokens.push(Token::Word(w));
+        } else if c.is_digit(10) || c == '-' {
+            let mut n = String::new();
+            n.push(c);
+            chars.next();
+            while chars.peek().map(|&c| c.is_digit(10) || c == '.') == Some(true) {
+                n.push(chars.next().unwrap());
+            }
+            tokens.push(Token::Number(n));
+        } else if c == '\'' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some('\'') => {
+                        // a quote is escaped by doubling it
+                        if chars.peek() != Some(&'\'') {
+                            break;
+                        }
+                        chars.next();
+                        s.push('\'');
+                    }
+                    Some(c) => s.push(c),
+                    None => return Err(String::from("unterminated string literal")),
+                }
+            }
+            tokens.push(Token::Text(s));
+        } else if c == '?' {
+            chars.next();
+            tokens.push(Token::Parameter);
+        } else if c == '=' || c == ',' || c == ';' || c == '(' || c == ')' {
+            chars.next();
+            tokens.push(Token::Symbol(c));
+        } else {
+            return Err(format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+const KEYWORDS: &[&str] = &["AND", "DEFAULT", "DELETE", "FALSE", "FROM", "NULL", "SET", "TRUE",
+                            "UPDATE", "WHERE"];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    parameters: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Word(ref w)) => w.to_uppercase() == kw,
+            _ => false,
+        }
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Symbol(c))
+    }
+
+    fn keyword(&mut self, kw: &str) -> Result<(), String> {
+        if self.is_keyword(kw) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected {}, found {}", kw, describe(self.peek())))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(w)) => {
+                if KEYWORDS.contains(&&*w.to_uppercase()) {
+                    Err(format!("expected a name, found keyword {}", w))
+                } else {
+                    Ok(w)
+                }
+            }
+            t => Err(format!("expected a name, found {}", describe(t.as_ref()))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let v = match self.next() {
+            Some(Token::Parameter) => {
+                self.parameters += 1;
+                return Ok(Value::Parameter(self.parameters - 1));
+            }
+            Some(Token::Text(s)) => DataType::from(s),
+            Some(Token::Number(n)) => {
+                let v = if n.contains('.') {
+                    n.parse::<f64>().ok().map(DataType::from)
+                } else {
+                    n.parse::<i64>().ok().map(DataType::from)
+                };
+                v.ok_or_else(|| format!("invalid number {}", n))?
+            }
+            Some(Token::Word(w)) => {
+                match &*w.to_uppercase() {
+                    "NULL" => DataType::None,
+                    "TRUE" => DataType::Bool(true),
+                    "FALSE" => DataType::Bool(false),
+                    _ => return Err(format!("expected a value, found {}", w)),
+                }
+            }
+            t => return Err(format!("expected a value, found {}", describe(t.as_ref()))),
+        };
+        Ok(Value::Literal(v))
+    }
+
+    /// Parse `column = value`.
+    fn equality(&mut self) -> Result<(String, Value), String> {
+        let column = self.identifier()?;
+        if !self.is_symbol('=') {
+            return Err(format!("expected = after {}, found {}", column, describe(self.peek())));
+        }
+        self.pos += 1;
+        Ok((column, self.value()?))
+    }
+
+    /// Check that the statement ends here, optionally with a `;`.
+    fn end(&mut self) -> Result<(), String> {
+        if self.is_symbol(';') {
+            self.pos += 1;
+        }
+        if self.peek().is_some() {
+            return Err(format!("unexpected {} at end of statement", describe(self.peek())));
+        }
+        Ok(())
+    }
+}
+
+/// Parse a `DELETE` or `UPDATE` statement.
+pub fn parse(sql: &str) -> Result<Statement, String> {
+    let mut p = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: 0,
+    };
+
+    let (table, assignments) = if p.is_keyword("DELETE") {
+        p.pos += 1;
+        p.keyword("FROM")?;
+        (p.identifier()?, None)
+    } else if p.is_keyword("UPDATE") {
+        p.pos += 1;
+        let table = p.identifier()?;
+        p.keyword("SET")?;
+        let mut assignments = vec![p.equality()?];
+        while p.is_symbol(',') {
+            p.pos += 1;
+            assignments.push(p.equality()?);
+        }
+        (table, Some(assignments))
+    } else {
+        return Err(format!("expected DELETE or UPDATE, found {}", describe(p.peek())));
+    };
+
+    p.keyword("WHERE")?;
+    let mut conditions = vec![p.equality()?];
+    while p.is_keyword("AND") {
+        p.pos += 1;
+        conditions.push(p.equality()?);
+    }
+
+    p.end()?;
+
+    Ok(Statement {
+        table: table,
+        assignments: assignments,
+        conditions: conditions,
+        parameters: p.parameters,
+    })
+}
+
+/// A parsed `DROP VIEW` statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DropView {
+    /// The views to drop, in order.
+    pub views: Vec<String>,
+    /// Whether views that do not exist should be skipped, rather than be an error.
+    pub if_exists: bool,
+}
+
+/// Parse a `DROP VIEW [IF EXISTS] name [, name ...]` statement.
+pub fn parse_drop_view(sql: &str) -> Result<DropView, String> {
+    let mut p = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: 0,
+    };
+
+    p.keyword("DROP")?;
+    p.keyword("VIEW")?;
+    let if_exists = p.is_keyword("IF");
+    if if_exists {
+        p.pos += 1;
+        p.keyword("EXISTS")?;
+    }
+
+    let mut views = vec![p.identifier()?];
+    while p.is_symbol(',') {
+        p.pos += 1;
+        views.push(p.identifier()?);
+    }
+
+    p.end()?;
+
+    Ok(DropView {
+        views: views,
+        if_exists: if_exists,
+    })
+}
+
+/// A parsed `ALTER TABLE` statement that adds a column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlterTable {
+    /// The table to add the column to.
+    pub table: String,
+    /// The name of the new column.
+    pub column: String,
+    /// The value of the new column in existing rows, and in writes that leave it out.
+    pub default: DataType,
+}
+
+/// Parse an `ALTER TABLE table ADD [COLUMN] name [type] [DEFAULT value]` statement.
+///
+/// The default must be a literal, and is `NULL` if none is given. A type may be given for the
+/// column, but is not checked, since tables do not know the types of their other columns either.
+pub fn parse_alter_table(sql: &str) -> Result<AlterTable, String> {
+    let mut p = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: 0,
+    };
+
+    p.keyword("ALTER")?;
+    p.keyword("TABLE")?;
+    let table = p.identifier()?;
+    p.keyword("ADD")?;
+    if p.is_keyword("COLUMN") {
+        p.pos += 1;
+    }
+    let column = p.identifier()?;
+
+    // skip the type, along with any arguments it has (as in VARCHAR(255))
+    let typed = match p.peek() {
+        Some(&Token::Word(_)) => !p.is_keyword("DEFAULT"),
+        _ => false,
+    };
+    if typed {
+        p.pos += 1;
+        if p.is_symbol('(') {
+            while !p.is_symbol(')') {
+                if p.next().is_none() {
+                    return Err(String::from("expected ), found end of statement"));
+                }
+            }
+            p.pos += 1;
+        }
+    }
+
+    let default = if p.is_keyword("DEFAULT") {
+        p.pos += 1;
+        match p.value()? {
+            Value::Literal(v) => v,
+            Value::Parameter(_) => {
+                return Err(format!("the default of column {} must be a literal", column));
+            }
+        }
+    } else {
+        DataType::None
+    };
+    p.end()?;
+
+    Ok(AlterTable {
+        table: table,
+        column: column,
+        default: default,
+    })
+}
+
+/// A `DELETE` or `UPDATE` statement, resolved against the columns and primary key of its table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MutationPlan {
+    /// The table whose rows the statement modifies.
+    pub table: String,
+    /// The names of the columns that the statement's parameters are assigned to or compared
+    /// against, in order.
+    pub parameters: Vec<String>,
+    /// The number of columns in the table.
+    pub columns: usize,
+    /// The table's primary key columns.
+    pub key_columns: Vec<usize>,
+    /// For each primary key column, the value that identifies the row to modify.
+    pub key: Vec<Value>,
+    /// For an `UPDATE`, the new value of every column it sets. `None` for a `DELETE`.
+    pub assignments: Option<Vec<(usize, Value)>>,
+}
+
+impl MutationPlan {
+    /// The primary key of the row the statement modifies, given its parameters.
+    pub fn key(&self, params: &[DataType]) -> Vec<DataType> {
+        self.key.iter().map(|v| v.resolve(params)).collect()
+    }
+
+    /// Whether the statement is an `UPDATE` that sets every column outside the primary key, so
+    /// that the updated row can be built without knowing the current one.
+    pub fn sets_all_columns(&self) -> bool {
+        match self.assignments {
+            Some(ref assignments) => assignments.len() + self.key_columns.len() == self.columns,
+            None => false,
+        }
+    }
+
+    /// The row that the statement replaces `current` with, given its parameters.
+    ///
+    /// `current` is only used for the columns the statement does not set, and so may be `None` if
+    /// it sets all of them.
+    pub fn updated_row(&self, params: &[DataType], current: Option<&[DataType]>) -> Vec<DataType> {
+        let mut row: Vec<_> = match current {
+            Some(current) => current.to_vec(),
+            None => {
+                assert!(self.sets_all_columns(), "current row is needed for partial updates");
+                vec![DataType::None; self.columns]
+            }
+        };
+        for (&col, k) in self.key_columns.iter().zip(self.key(params)) {
+            row[col] = k;
+        }
+        for &(col, ref v) in self.assignments.as_ref().expect("only updates produce new rows") {
+            row[col] = v.resolve(params);
+        }
+        row
+    }
+}
+
+/// Resolve a parsed statement against the table it modifies.
+///
+/// The table must have a primary key, and the statement's `WHERE` clause must give a value for
+/// each of its columns, and for no other column. An `UPDATE` cannot change the primary key.
+pub fn plan(catalog: &Catalog, stmt: Statement) -> Result<MutationPlan, String> {
+    let table = stmt.table;
+    let fields = catalog.table_fields(&table)
+        .ok_or_else(|| format!("table {} not found", table))?;
+    let key_columns = match catalog.primary_key(&table) {
+        Some(key) if !key.is_empty() => key.to_vec(),
+        _ => return Err(format!("table {} has no primary key, so its rows cannot be modified", table)),
+    };
+
+    let column = |name: &str| -> Result<usize, String> {
+        let unqualified = match name.find('.') {
+            Some(i) if name[..i] == table[..] => &name[i + 1..],
+            Some(_) => return Err(format!("column {} is not in table {}", name, table)),
+            None => name,
+        };
+        fields.iter()
+            .position(|f| f == unqualified)
+            .ok_or_else(|| format!("column {} not found in table {}", name, table))
+    };
+
+    // every parameter is named after the column it is assigned to or compared against
+    let mut parameters = vec![String::new(); stmt.parameters];
+
+    let mut key = vec![None; key_columns.len()];
+    for (name, v) in stmt.conditions {
+        let col = column(&name)?;
+        let i = key_columns.iter()
+            .position(|&k| k == col)
+            .ok_or_else(|| {
+                format!("{} is not part of the primary key of {}, which is the only way to identify \
+                         rows to modify",
+                        name,
+                        table)
+            })?;
+        if key[i].is_some() {
+            return Err(format!("{} appears more than once in the WHERE clause", name));
+        }
+        if let Value::Parameter(p) = v {
+            parameters[p] = fields[col].clone();
+        }
+        key[i] = Some(v);
+    }
+    if let Some(i) = key.iter().position(Option::is_none) {
+        return Err(format!("WHERE clause must give a value for primary key column {} of {}",
+                           fields[key_columns[i]],
+                           table));
+    }
+    let key = key.into_iter().map(Option::unwrap).collect();
+
+    let assignments = match stmt.assignments {
+        None => None,
+        Some(assignments) => {
+            let mut set = Vec::with_capacity(assignments.len());
+            for (name, v) in assignments {
+                let col = column(&name)?;
+                if key_columns.contains(&col) {
+                    return Err(format!("cannot update primary key column {}", name));
+                }
+                if set.iter().any(|&(c, _)| c == col) {
+                    return Err(format!("{} is set more than once", name));
+                }
+                if let Value::Parameter(p) = v {
+                    parameters[p] = fields[col].clone();
+                }
+                set.push((col, v));
+            }
+            Some(set)
+        }
+    };
+
+    Ok(MutationPlan {
+        table: table.clone(),
+        parameters: parameters,
+        columns: fields.len(),
+        key_columns: key_columns,
+        key: key,
+        assignments: assignments,
+    })
+}
+
+/// The column constraints declared by a `CREATE TABLE` statement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreateTable {
+    /// The table being created.
+    pub table: String,
+    /// Each of the table's columns, in order, along with its default and whether it may be NULL.
+    pub columns: Vec<(String, ColumnSpec)>,
+}
+
+/// Skip tokens up to the `,` or `)` that ends the current column or table constraint, stepping
+/// over any parenthesized arguments along the way.
+fn skip_definition(p: &mut Parser) -> Result<(), String> {
+    let mut depth = 0;
+    loop {
+        match p.peek() {
+            None => return Err(String::from("expected ), found end of statement")),
+            Some(&Token::Symbol(',')) |
+            Some(&Token::Symbol(')')) if depth == 0 => return Ok(()),
+            Some(&Token::Symbol('(')) => depth += 1,
+            Some(&Token::Symbol(')')) => depth -= 1,
+            _ => (),
+        }
+        p.pos += 1;
+    }
+}
+
+/// Check that the next token is a `)`, and skip it.
+fn close(p: &mut Parser) -> Result<(), String> {
+    if !p.is_symbol(')') {
+        return Err(format!("expected ), found {}", describe(p.peek())));
+    }
+    p.pos += 1;
+    Ok(())
+}
+
+/// Parse the column defaults and `NOT NULL` constraints of a `CREATE TABLE` statement.
+///
+/// Column types, other column attributes, table constraints such as `PRIMARY KEY`, and table
+/// options are skipped. Types are not recorded in the returned `ColumnSpec`s, since values written
+/// through the SQL interface are not expected to be coerced. Defaults must be literals.
+pub fn parse_create_table(sql: &str) -> Result<CreateTable, String> {
+    let mut p = Parser {
+        tokens: tokenize(sql)?,
+        pos: 0,
+        parameters: 0,
+    };
+
+    p.keyword("CREATE")?;
+    p.keyword("TABLE")?;
+    let table = p.identifier()?;
+    if !p.is_symbol('(') {
+        return Err(format!("expected (, found {}", describe(p.peek())));
+    }
+
+    let mut columns = Vec::new();
+    loop {
+        p.pos += 1;
+        let constraint = ["PRIMARY", "UNIQUE", "KEY", "INDEX", "FULLTEXT", "CONSTRAINT",
+                          "FOREIGN", "CHECK"]
+            .iter()
+            .any(|kw| p.is_keyword(kw));
+        if constraint {
+            skip_definition(&mut p)?;
+        } else {
+            let column = p.identifier()?;
+            let mut spec = ColumnSpec::default();
+            loop {
+                if p.is_keyword("NOT") {
+                    p.pos += 1;
+                    p.keyword("NULL")?;
+                    spec.nullable = false;
+                } else if p.is_keyword("NULL") {
+                    p.pos += 1;
+                    spec.nullable = true;
+                } else if p.is_keyword("DEFAULT") {
+                    p.pos += 1;
+                    spec.default = match p.value()? {
+                        Value::Literal(v) => Some(v),
+                        Value::Parameter(_) => {
+                            return Err(format!("the default of column {} must be a literal",
+                                               column));
+                        }
+                    };
+                } else if p.is_symbol(',') || p.is_symbol(')') {
+                    break;
+                } else if p.is_symbol('(') {
+                    // the arguments of the column's type, as in DECIMAL(10, 2)
+                    p.pos += 1;
+                    skip_definition(&mut p)?;
+                    while p.is_symbol(',') {
+                        p.pos += 1;
+                        skip_definition(&mut p)?;
+                    }
+                    close(&mut p)?;
+                } else if p.next().is_none() {
+                    return Err(String::from("expected ), found end of statement"));
+                }
+            }
+            columns.push((column, spec));
+        }
+
+        if !p.is_symbol(',') {
+            break;
+        }
+    }
+    close(&mut p)?;
+
+    Ok(CreateTable {
+        table: table,
+        columns: columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nom_sql::parser::parse_query;
+    use flow::sql::planner::plan_query;
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        let q = "CREATE TABLE users (id int, name varchar(255), karma int, PRIMARY KEY (id));";
+        let plan = plan_query(&catalog, parse_query(q).unwrap(), None).unwrap();
+        catalog.register(&plan);
+        let q = "INSERT INTO votes (user, story) VALUES (?, ?);";
+        let plan = plan_query(&catalog, parse_query(q).unwrap(), None).unwrap();
+        catalog.register(&plan);
+        catalog
+    }
+
+    #[test]
+    fn it_parses() {
+        let stmt = parse("UPDATE users SET name = ?, karma = -1, bio = 'it''s me' \
+                          WHERE users.id = ?;")
+            .unwrap();
+        assert_eq!(stmt.table, "users");
+        assert_eq!(stmt.assignments,
+                   Some(vec![(String::from("name"), Value::Parameter(0)),
+                             (String::from("karma"), Value::Literal((-1i64).into())),
+                             (String::from("bio"), Value::Literal("it's me".into()))]));
+        assert_eq!(stmt.conditions,
+                   vec![(String::from("users.id"), Value::Parameter(1))]);
+        assert_eq!(stmt.parameters, 2);
+
+        let stmt = parse("delete from users where id = 1 and name = NULL").unwrap();
+        assert_eq!(stmt.assignments, None);
+        assert_eq!(stmt.conditions,
+                   vec![(String::from("id"), Value::Literal(1i64.into())),
+                        (String::from("name"), Value::Literal(DataType::None))]);
+
+        assert!(parse("DELETE FROM users").is_err());
+        assert!(parse("DELETE FROM users WHERE id = ? OR id = ?").is_err());
+        assert!(parse("UPDATE users WHERE id = ?").is_err());
+        assert!(parse("UPDATE users SET name = 'x WHERE id = ?").is_err());
+        assert!(parse("SELECT * FROM users").is_err());
+    }
+
+    #[test]
+    fn it_parses_alter_table() {
+        assert_eq!(parse_alter_table("ALTER TABLE users ADD COLUMN karma INT DEFAULT 0;").unwrap(),
+                   AlterTable {
+                       table: String::from("users"),
+                       column: String::from("karma"),
+                       default: 0.into(),
+                   });
+        assert_eq!(parse_alter_table("alter table users add bio varchar(255)").unwrap(),
+                   AlterTable {
+                       table: String::from("users"),
+                       column: String::from("bio"),
+                       default: DataType::None,
+                   });
+
+        assert!(parse_alter_table("ALTER TABLE users ADD COLUMN karma DEFAULT ?").is_err());
+        assert!(parse_alter_table("ALTER TABLE users ADD COLUMN bio VARCHAR(255").is_err());
+        assert!(parse_alter_table("ALTER TABLE users DROP COLUMN karma").is_err());
+    }
+
+    #[test]
+    fn it_parses_create_table() {
+        let stmt = parse_create_table("CREATE TABLE users (id int NOT NULL, \
+                                       name varchar(255) DEFAULT 'anon', \
+                                       karma decimal(10, 2) null default 0, \
+                                       PRIMARY KEY (id)) ENGINE=InnoDB;")
+            .unwrap();
+        assert_eq!(stmt.table, "users");
+        assert_eq!(stmt.columns,
+                   vec![(String::from("id"),
+                         ColumnSpec {
+                             nullable: false,
+                             ..ColumnSpec::default()
+                         }),
+                        (String::from("name"),
+                         ColumnSpec {
+                             default: Some("anon".into()),
+                             ..ColumnSpec::default()
+                         }),
+                        (String::from("karma"),
+                         ColumnSpec {
+                             default: Some(0i64.into()),
+                             ..ColumnSpec::default()
+                         })]);
+
+        let stmt = parse_create_table("CREATE TABLE votes (user, story)").unwrap();
+        assert_eq!(stmt.columns.len(), 2);
+        assert!(stmt.columns.iter().all(|&(_, ref spec)| *spec == ColumnSpec::default()));
+
+        assert!(parse_create_table("CREATE TABLE users (id int DEFAULT ?)").is_err());
+        assert!(parse_create_table("CREATE TABLE users (id int NOT 5)").is_err());
+        assert!(parse_create_table("CREATE TABLE users (id int").is_err());
+        assert!(parse_create_table("CREATE VIEW users AS SELECT 1").is_err());
+    }
+
+    #[test]
+    fn it_parses_drop_view() {
+        assert_eq!(parse_drop_view("DROP VIEW q_1;").unwrap(),
+                   DropView {
+                       views: vec![String::from("q_1")],
+                       if_exists: false,
+                   });
+        assert_eq!(parse_drop_view("drop view if exists a, b").unwrap(),
+                   DropView {
+                       views: vec![String::from("a"), String::from("b")],
+                       if_exists: true,
+                   });
+
+        assert!(parse_drop_view("DROP VIEW").is_err());
+        assert!(parse_drop_view("DROP TABLE users").is_err());
+        assert!(parse_drop_view("DROP VIEW a b").is_err());
+    }
+
+    #[test]
+    fn it_plans_deletes() {
+        let catalog = catalog();
+        let p = plan(&catalog, parse("DELETE FROM users WHERE id = ?").unwrap()).unwrap();
+        assert_eq!(p.parameters, vec![String::from("id")]);
+        assert_eq!(p.key_columns, vec![0]);
+        assert_eq!(p.key(&[3.into()]), vec![DataType::from(3)]);
+        assert_eq!(p.assignments, None);
+
+        // rows can only be identified by their primary key
+        assert!(plan(&catalog, parse("DELETE FROM users WHERE name = ?").unwrap()).is_err());
+        assert!(plan(&catalog, parse("DELETE FROM votes WHERE user = ?").unwrap()).is_err());
+        assert!(plan(&catalog, parse("DELETE FROM posts WHERE id = ?").unwrap()).is_err());
+        assert!(plan(&catalog, parse("DELETE FROM users WHERE votes.id = ?").unwrap()).is_err());
+    }
+
+    #[test]
+    fn it_plans_updates() {
+        let catalog = catalog();
+        let p = plan(&catalog,
+                     parse("UPDATE users SET karma = 0, name = ? WHERE id = ?").unwrap())
+            .unwrap();
+        assert_eq!(p.parameters, vec![String::from("name"), String::from("id")]);
+        assert!(p.sets_all_columns());
+        let params: Vec<DataType> = vec!["bob".into(), 2.into()];
+        let expected: Vec<DataType> = vec![2.into(), "bob".into(), 0.into()];
+        assert_eq!(p.updated_row(&params, None), expected);
+
+        let p = plan(&catalog, parse("UPDATE users SET karma = ? WHERE id = ?").unwrap()).unwrap();
+        assert!(!p.sets_all_columns());
+        let current: Vec<DataType> = vec![2.into(), "bob".into(), 0.into()];
+        let expected: Vec<DataType> = vec![2.into(), "bob".into(), 5.into()];
+        assert_eq!(p.updated_row(&[5.into(), 2.into()], Some(&current[..])), expected);
+
+        // the primary key cannot be changed
+        assert!(plan(&catalog, parse("UPDATE users SET id = ? WHERE id = ?").unwrap()).is_err());
+        assert!(plan(&catalog,
+                     parse("UPDATE users SET name = ?, name = ? WHERE id = ?").unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,759 @@
+//! Rule-based rewriting of logical query plans.
+//!
+//! An `Optimizer` holds an ordered list of `Rule`s, and repeatedly applies all of them to a
+//! `QueryPlan` until none of them changes it any further. Rules can be registered at runtime, so
+//! applications can add rewrites that are specific to their workloads alongside the built-in ones.
+
+use flow::sql::planner::{Catalog, PlanNode, PlanOp, QueryPlan};
+
+use std::fmt;
+use std::sync::Arc;
+
+/// The maximum number of times all rules are applied to a single plan.
+///
+/// This guards against rules that keep undoing each other's changes.
+const MAX_PASSES: usize = 16;
+
+/// A rewrite rule for logical query plans.
+pub trait Rule: Send + Sync {
+    /// A short, human-readable name for the rule.
+    fn name(&self) -> &str;
+
+    /// Rewrite the given plan, and return true if it was changed.
+    ///
+    /// Rewritten plans must produce the same results in the plan's leaf view. Any view that is not
+    /// part of the plan itself can be looked up in `catalog`.
+    fn apply(&self, catalog: &Catalog, plan: &mut QueryPlan) -> bool;
+}
+
+/// Applies a set of `Rule`s to query plans.
+#[derive(Clone, Default)]
+pub struct Optimizer {
+    rules: Vec<Arc<Rule>>,
+}
+
+impl Optimizer {
+    /// Construct an optimizer without any rules, which leaves all plans unchanged.
+    pub fn new() -> Self {
+        Optimizer::default()
+    }
+
+    /// Construct an optimizer with all the built-in rules registered.
+    pub fn with_default_rules() -> Self {
+        let mut o = Optimizer::new();
+        o.register(PushDownFilters);
+        o.register(EliminateIdentityNodes);
+        o.register(PruneUnusedNodes);
+        o.register(PruneUnusedColumns);
+        o.register(ReuseExistingNodes);
+        o
+    }
+
+    /// Register a new rule. Rules are applied in the order they were registered.
+    pub fn register<R: Rule + 'static>(&mut self, rule: R) {
+        self.rules.push(Arc::new(rule));
+    }
+
+    /// The names of all registered rules, in the order they are applied.
+    pub fn rules(&self) -> Vec<&str> {
+        self.rules.iter().map(|r| r.name()).collect()
+    }
+
+    /// Rewrite the given plan using the registered rules.
+    pub fn optimize(&self, catalog: &Catalog, mut plan: QueryPlan) -> QueryPlan {
+        for _ in 0..MAX_PASSES {
+            let mut changed = false;
+            for r in &self.rules {
+                changed |= r.apply(catalog, &mut plan);
+            }
+            if !changed {
+                break;
+            }
+        }
+        plan
+    }
+}
+
+impl fmt::Debug for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Optimizer({:?})", self.rules())
+    }
+}
+
+impl PartialEq for Optimizer {
+    fn eq(&self, other: &Optimizer) -> bool {
+        self.rules() == other.rules()
+    }
+}
+
+/// Make every node in the plan that uses view `from` use view `to` instead.
+fn redirect(plan: &mut QueryPlan, from: &str, to: &str) {
+    for n in &mut plan.nodes {
+        match n.op {
+            PlanOp::Base { .. } => (),
+            PlanOp::Filter { ref mut parent, .. } |
+            PlanOp::Predicate { ref mut parent, .. } |
+            PlanOp::Permute { ref mut parent, .. } |
+            PlanOp::Project { ref mut parent, .. } |
+            PlanOp::Grouped { ref mut parent, .. } |
+            PlanOp::TopK { ref mut parent, .. } |
+            PlanOp::Identity { ref mut parent } => {
+                if *parent == from {
+                    *parent = String::from(to);
+                }
+            }
+            PlanOp::Join { ref mut left, ref mut right, ref mut emit, .. } => {
+                for side in Some(left).into_iter().chain(Some(right)) {
+                    if *side == from {
+                        *side = String::from(to);
+                    }
+                }
+                for &mut (ref mut side, _) in emit {
+                    if *side == from {
+                        *side = String::from(to);
+                    }
+                }
+            }
+            PlanOp::SemiJoin { ref mut left, ref mut right, .. } => {
+                for side in Some(left).into_iter().chain(Some(right)) {
+                    if *side == from {
+                        *side = String::from(to);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The number of nodes in the plan that use the given view.
+fn uses(plan: &QueryPlan, view: &str) -> usize {
+    plan.nodes
+        .iter()
+        .flat_map(|n| n.op.parents())
+        .filter(|p| *p == view)
+        .count()
+}
+
+/// The output columns of `view`, as seen by the node at index `at` in the plan.
+fn fields_at<'a>(catalog: &'a Catalog,
+                 plan: &'a QueryPlan,
+                 at: usize,
+                 view: &str)
+                 -> Option<&'a [String]> {
+    plan.nodes[..at]
+        .iter()
+        .rev()
+        .find(|n| n.name == view)
+        .map(|n| &n.fields[..])
+        .or_else(|| catalog.fields(view))
+}
+
+/// Moves filters below the permutations that feed them, so that fewer records are permuted.
+///
+/// A filter is only moved if the permutation has no other uses, and is not the plan's leaf.
+pub struct PushDownFilters;
+
+impl Rule for PushDownFilters {
+    fn name(&self) -> &str {
+        "push down filters"
+    }
+
+    fn apply(&self, catalog: &Catalog, plan: &mut QueryPlan) -> bool {
+        for i in 0..plan.nodes.len() {
+            let j = match plan.nodes[i].op {
+                PlanOp::Filter { ref parent, .. } => {
+                    match plan.nodes[..i].iter().rposition(|n| &n.name == parent) {
+                        Some(j) => j,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if plan.nodes[j].name == plan.leaf || uses(plan, &plan.nodes[j].name) != 1 {
+                continue;
+            }
+
+            let (source, columns) = match plan.nodes[j].op {
+                PlanOp::Permute { ref parent, ref columns } => (parent.clone(), columns.clone()),
+                _ => continue,
+            };
+            let source_fields = match fields_at(catalog, plan, j, &source) {
+                Some(fs) => fs.to_vec(),
+                None => continue,
+            };
+
+            // express the filter's conditions in terms of the permutation's input
+            let mut conditions = vec![None; source_fields.len()];
+            let mut conflict = false;
+            if let PlanOp::Filter { conditions: ref cs, .. } = plan.nodes[i].op {
+                for (col, c) in cs.iter().enumerate() {
+                    if let Some(ref v) = *c {
+                        let target = columns[col];
+                        if conditions[target].is_some() && conditions[target].as_ref() != Some(v) {
+                            conflict = true;
+                        } else {
+                            conditions[target] = Some(v.clone());
+                        }
+                    }
+                }
+            }
+            if conflict {
+                continue;
+            }
+
+            // the permutation's name now refers to the filtered input, and the filter's name to
+            // the permutation of that
+            let filter = PlanNode {
+                name: plan.nodes[j].name.clone(),
+                fields: source_fields,
+                op: PlanOp::Filter {
+                    parent: source,
+                    conditions: conditions,
+                },
+            };
+            let permute = PlanNode {
+                name: plan.nodes[i].name.clone(),
+                fields: plan.nodes[i].fields.clone(),
+                op: PlanOp::Permute {
+                    parent: filter.name.clone(),
+                    columns: columns,
+                },
+            };
+            plan.nodes[j] = filter;
+            plan.nodes[i] = permute;
+            return true;
+        }
+        false
+    }
+}
+
+/// Removes nodes that forward their input unchanged, such as identities, filters without
+/// conditions, and permutations that keep all columns in order.
+///
+/// The plan's leaf is never removed.
+pub struct EliminateIdentityNodes;
+
+impl Rule for EliminateIdentityNodes {
+    fn name(&self) -> &str {
+        "eliminate identity nodes"
+    }
+
+    fn apply(&self, catalog: &Catalog, plan: &mut QueryPlan) -> bool {
+        let found = plan.nodes
+            .iter()
+            .enumerate()
+            .filter(|&(_, n)| n.name != plan.leaf)
+            .filter_map(|(i, n)| {
+                let parent = match n.op {
+                    PlanOp::Identity { ref parent } => parent,
+                    PlanOp::Filter { ref parent, ref conditions } if conditions.iter()
+                        .all(Option::is_none) => parent,
+                    PlanOp::Permute { ref parent, ref columns } => {
+                        match fields_at(catalog, plan, i, parent) {
+                            Some(fs) if fs == &n.fields[..] &&
+                                        columns.iter().cloned().eq(0..fs.len()) => parent,
+                            _ => return None,
+                        }
+                    }
+                    _ => return None,
+                };
+                Some((i, parent.clone()))
+            })
+            .next();
+
+        match found {
+            Some((i, parent)) => {
+                let n = plan.nodes.remove(i);
+                redirect(plan, &n.name, &parent);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Removes nodes whose output is not used by any other node in the plan.
+///
+/// The plan's leaf and base tables are never removed.
+pub struct PruneUnusedNodes;
+
+impl Rule for PruneUnusedNodes {
+    fn name(&self) -> &str {
+        "prune unused nodes"
+    }
+
+    fn apply(&self, _: &Catalog, plan: &mut QueryPlan) -> bool {
+        let unused = plan.nodes.iter().position(|n| match n.op {
+            PlanOp::Base { .. } => false,
+            _ => n.name != plan.leaf && uses(plan, &n.name) == 0,
+        });
+
+        match unused {
+            Some(i) => {
+                plan.nodes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Whether any node in the plan uses column `c` of the view produced by node `i`.
+///
+/// All columns of the plan's leaf are used, since they are what the query returns.
+fn demanded(plan: &QueryPlan, i: usize, c: usize) -> bool {
+    let name = &plan.nodes[i].name;
+    if *name == plan.leaf {
+        return true;
+    }
+
+    plan.nodes.iter().enumerate().any(|(j, n)| match n.op {
+        PlanOp::Base { .. } => false,
+        // nodes that forward all of their parent's columns use those that are used downstream
+        PlanOp::Filter { ref parent, ref conditions } => {
+            parent == name && (conditions[c].is_some() || demanded(plan, j, c))
+        }
+        PlanOp::Predicate { ref parent, ref predicate } => {
+            parent == name && (predicate.columns().contains(&c) || demanded(plan, j, c))
+        }
+        PlanOp::TopK { ref parent, ref group_by, order, .. } => {
+            parent == name && (group_by.contains(&c) || order == c || demanded(plan, j, c))
+        }
+        PlanOp::Identity { ref parent } => parent == name && demanded(plan, j, c),
+        PlanOp::Permute { ref parent, ref columns } |
+        PlanOp::Project { ref parent, ref columns, .. } => parent == name && columns.contains(&c),
+        PlanOp::Grouped { ref parent, over, ref group_by, .. } => {
+            parent == name && (over == c || group_by.contains(&c))
+        }
+        PlanOp::Join { ref left, ref right, ref emit, ref left_groups, ref right_groups, .. } => {
+            (left == name && left_groups[c] != 0) || (right == name && right_groups[c] != 0) ||
+            emit.iter().any(|&(ref side, col)| side == name && col == c)
+        }
+        // semi-joins forward the columns of their left side, and only look at the right
+        PlanOp::SemiJoin { ref left, ref right, on, .. } => {
+            (left == name && (on.0 == c || demanded(plan, j, c))) || (right == name && on.1 == c)
+        }
+    })
+}
+
+/// Account for column `c` no longer being part of the view produced by node `i`, by renumbering
+/// the columns of that view that later nodes use.
+fn drop_column(plan: &mut QueryPlan, i: usize, c: usize) {
+    plan.nodes[i].fields.remove(c);
+    let name = plan.nodes[i].name.clone();
+    let shift = |col: usize| if col > c { col - 1 } else { col };
+
+    // nodes that forward all of their parent's columns lose the column too
+    let mut forwarding = Vec::new();
+    for (j, n) in plan.nodes.iter_mut().enumerate() {
+        match n.op {
+            PlanOp::Base { .. } => (),
+            PlanOp::Filter { ref parent, ref mut conditions } => {
+                if *parent == name {
+                    conditions.remove(c);
+                    forwarding.push(j);
+                }
+            }
+            PlanOp::Predicate { ref parent, ref mut predicate } => {
+                if *parent == name {
+                    predicate.map_columns(&shift);
+                    forwarding.push(j);
+                }
+            }
+            PlanOp::TopK { ref parent, ref mut group_by, ref mut order, .. } => {
+                if *parent == name {
+                    *order = shift(*order);
+                    for col in group_by.iter_mut() {
+                        *col = shift(*col);
+                    }
+                    forwarding.push(j);
+                }
+            }
+            PlanOp::Identity { ref parent } => {
+                if *parent == name {
+                    forwarding.push(j);
+                }
+            }
+            PlanOp::Permute { ref parent, ref mut columns } |
+            PlanOp::Project { ref parent, ref mut columns, .. } => {
+                if *parent == name {
+                    for col in columns.iter_mut() {
+                        *col = shift(*col);
+                    }
+                }
+            }
+            PlanOp::Grouped { ref parent, ref mut over, ref mut group_by, .. } => {
+                if *parent == name {
+                    *over = shift(*over);
+                    for col in group_by.iter_mut() {
+                        *col = shift(*col);
+                    }
+                }
+            }
+            PlanOp::Join { ref left,
+                           ref right,
+                           ref mut emit,
+                           ref mut left_groups,
+                           ref mut right_groups,
+                           .. } => {
+                if *left == name {
+                    left_groups.remove(c);
+                }
+                if *right == name {
+                    right_groups.remove(c);
+                }
+                for &mut (ref side, ref mut col) in emit.iter_mut() {
+                    if *side == name {
+                        *col = shift(*col);
+                    }
+                }
+            }
+            PlanOp::SemiJoin { ref left, ref right, ref mut on, .. } => {
+                if *left == name {
+                    on.0 = shift(on.0);
+                    forwarding.push(j);
+                }
+                if *right == name {
+                    on.1 = shift(on.1);
+                }
+            }
+        }
+    }
+
+    for j in forwarding {
+        drop_column(plan, j, c);
+    }
+}
+
+/// Removes columns that no later node in the plan uses from the output of joins and projections,
+/// so that the views downstream of them hold less state.
+///
+/// The columns of the plan's leaf are never removed. Permutations are left alone, since narrowing
+/// one that feeds another permutation only moves the narrowing around.
+pub struct PruneUnusedColumns;
+
+impl Rule for PruneUnusedColumns {
+    fn name(&self) -> &str {
+        "prune unused columns"
+    }
+
+    fn apply(&self, _: &Catalog, plan: &mut QueryPlan) -> bool {
+        let mut changed = false;
+        // removing columns from later nodes may leave columns of earlier nodes unused, so work
+        // backwards through the plan
+        for i in (0..plan.nodes.len()).rev() {
+            if plan.nodes[i].name == plan.leaf {
+                continue;
+            }
+            match plan.nodes[i].op {
+                PlanOp::Project { .. } |
+                PlanOp::Join { .. } => (),
+                _ => continue,
+            }
+
+            // going backwards keeps the indices of the columns still to be checked valid
+            for c in (0..plan.nodes[i].fields.len()).rev() {
+                // views must have at least one column
+                if plan.nodes[i].fields.len() == 1 {
+                    break;
+                }
+                if demanded(plan, i, c) {
+                    continue;
+                }
+
+                match plan.nodes[i].op {
+                    PlanOp::Project { ref mut columns, ref mut literals, .. } => {
+                        if c < columns.len() {
+                            columns.remove(c);
+                        } else {
+                            literals.remove(c - columns.len());
+                        }
+                    }
+                    PlanOp::Join { ref mut emit, .. } => {
+                        emit.remove(c);
+                    }
+                    _ => unreachable!(),
+                }
+                drop_column(plan, i, c);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Replaces nodes that compute exactly what an existing view computes by that view, so that a
+/// query shares the nodes it has in common with earlier queries and only adds those that differ.
+///
+/// A node is compared with the existing views once the nodes it uses have been replaced, so a
+/// chain of nodes that matches an existing chain is replaced in its entirety. The plan's leaf is
+/// never replaced, since it needs a reader of its own.
+pub struct ReuseExistingNodes;
+
+impl Rule for ReuseExistingNodes {
+    fn name(&self) -> &str {
+        "reuse existing nodes"
+    }
+
+    fn apply(&self, catalog: &Catalog, plan: &mut QueryPlan) -> bool {
+        let mut changed = false;
+        // nodes come after the nodes they use, so a single pass replaces entire chains
+        let mut i = 0;
+        while i < plan.nodes.len() {
+            let existing = match plan.nodes[i].op {
+                PlanOp::Base { .. } => None,
+                _ if plan.nodes[i].name == plan.leaf => None,
+                ref op => catalog.equivalent_view(&plan.nodes[i].fields, op).map(String::from),
+            };
+            match existing {
+                Some(view) => {
+                    let n = plan.nodes.remove(i);
+                    redirect(plan, &n.name, &view);
+                    if !plan.reused.contains(&view) {
+                        plan.reused.push(view);
+                    }
+                    changed = true;
+                }
+                None => i += 1,
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom_sql::parser::parse_query;
+    use flow::sql::planner::{plan_query, Catalog, PlanNode, PlanOp, QueryPlan};
+    use super::*;
+
+    fn catalog() -> Catalog {
+        let mut catalog = Catalog::new();
+        let q = parse_query("INSERT INTO users (id, name) VALUES (?, ?);").unwrap();
+        let plan = plan_query(&catalog, q, None).unwrap();
+        catalog.register(&plan);
+        catalog
+    }
+
+    fn node(name: &str, fields: &[&str], op: PlanOp) -> PlanNode {
+        PlanNode {
+            name: name.into(),
+            fields: fields.iter().map(|&f| f.into()).collect(),
+            op: op,
+        }
+    }
+
+    #[test]
+    fn it_pushes_down_filters() {
+        let catalog = catalog();
+        let plan = QueryPlan::new("q".into(),
+                                  vec![node("p",
+                                            &["name", "id"],
+                                            PlanOp::Permute {
+                                                parent: "users".into(),
+                                                columns: vec![1, 0],
+                                            }),
+                                       node("q",
+                                            &["name", "id"],
+                                            PlanOp::Filter {
+                                                parent: "p".into(),
+                                                conditions: vec![None, Some(1.into())],
+                                            })],
+                                  "q".into(),
+                                  Some(vec![0]));
+
+        let mut o = Optimizer::new();
+        o.register(PushDownFilters);
+        let plan = o.optimize(&catalog, plan);
+        assert_eq!(plan.nodes,
+                   vec![node("p",
+                             &["id", "name"],
+                             PlanOp::Filter {
+                                 parent: "users".into(),
+                                 conditions: vec![Some(1.into()), None],
+                             }),
+                        node("q",
+                             &["name", "id"],
+                             PlanOp::Permute {
+                                 parent: "p".into(),
+                                 columns: vec![1, 0],
+                             })]);
+    }
+
+    #[test]
+    fn it_eliminates_identities_and_prunes() {
+        let catalog = catalog();
+        let plan = QueryPlan::new("q".into(),
+                                  vec![node("i",
+                                            &["id", "name"],
+                                            PlanOp::Identity { parent: "users".into() }),
+                                       node("p",
+                                            &["id", "name"],
+                                            PlanOp::Permute {
+                                                parent: "i".into(),
+                                                columns: vec![0, 1],
+                                            }),
+                                       node("unused",
+                                            &["id"],
+                                            PlanOp::Permute {
+                                                parent: "users".into(),
+                                                columns: vec![0],
+                                            }),
+                                       node("q",
+                                            &["name"],
+                                            PlanOp::Permute {
+                                                parent: "p".into(),
+                                                columns: vec![1],
+                                            })],
+                                  "q".into(),
+                                  Some(vec![0]));
+
+        let plan = Optimizer::with_default_rules().optimize(&catalog, plan);
+        assert_eq!(plan.nodes,
+                   vec![node("q",
+                             &["name"],
+                             PlanOp::Permute {
+                                 parent: "users".into(),
+                                 columns: vec![1],
+                             })]);
+    }
+
+    #[test]
+    fn it_prunes_unused_columns() {
+        let catalog = catalog();
+        let plan = QueryPlan::new("q".into(),
+                                  vec![node("p",
+                                            &["id", "name", "one"],
+                                            PlanOp::Project {
+                                                parent: "users".into(),
+                                                columns: vec![0, 1],
+                                                literals: vec![1.into()],
+                                            }),
+                                       node("j",
+                                            &["id", "name", "one", "name2"],
+                                            PlanOp::Join {
+                                                left: "p".into(),
+                                                right: "users".into(),
+                                                emit: vec![("p".into(), 0),
+                                                           ("p".into(), 1),
+                                                           ("p".into(), 2),
+                                                           ("users".into(), 1)],
+                                                left_groups: vec![1, 0, 0],
+                                                right_groups: vec![1, 0],
+                                                left_outer: false,
+                                            }),
+                                       node("q",
+                                            &["name2", "name"],
+                                            PlanOp::Permute {
+                                                parent: "j".into(),
+                                                columns: vec![3, 1],
+                                            })],
+                                  "q".into(),
+                                  Some(vec![0]));
+
+        let mut o = Optimizer::new();
+        o.register(PruneUnusedColumns);
+        let plan = o.optimize(&catalog, plan);
+
+        // the join column is still needed for the join, but not after it
+        assert_eq!(plan.nodes,
+                   vec![node("p",
+                             &["id", "name"],
+                             PlanOp::Project {
+                                 parent: "users".into(),
+                                 columns: vec![0, 1],
+                                 literals: vec![],
+                             }),
+                        node("j",
+                             &["name", "name2"],
+                             PlanOp::Join {
+                                 left: "p".into(),
+                                 right: "users".into(),
+                                 emit: vec![("p".into(), 1), ("users".into(), 1)],
+                                 left_groups: vec![1, 0],
+                                 right_groups: vec![1, 0],
+                                 left_outer: false,
+                             }),
+                        node("q",
+                             &["name2", "name"],
+                             PlanOp::Permute {
+                                 parent: "j".into(),
+                                 columns: vec![1, 0],
+                             })]);
+    }
+
+    #[test]
+    fn it_reuses_existing_nodes() {
+        let mut catalog = catalog();
+        let filter = |name: &str| {
+            node(name,
+                 &["id", "name"],
+                 PlanOp::Filter {
+                     parent: "users".into(),
+                     conditions: vec![Some(42.into()), None],
+                 })
+        };
+        catalog.register(&QueryPlan::new("q1".into(),
+                                         vec![filter("f1"),
+                                              node("q1",
+                                                   &["name"],
+                                                   PlanOp::Permute {
+                                                       parent: "f1".into(),
+                                                       columns: vec![1],
+                                                   })],
+                                         "q1".into(),
+                                         Some(vec![0])));
+
+        // the same filter with a different projection on top only adds the projection
+        let plan = QueryPlan::new("q2".into(),
+                                  vec![filter("f2"),
+                                       node("q2",
+                                            &["id"],
+                                            PlanOp::Permute {
+                                                parent: "f2".into(),
+                                                columns: vec![0],
+                                            })],
+                                  "q2".into(),
+                                  Some(vec![0]));
+        let mut o = Optimizer::new();
+        o.register(ReuseExistingNodes);
+        let plan = o.optimize(&catalog, plan);
+        assert_eq!(plan.nodes,
+                   vec![node("q2",
+                             &["id"],
+                             PlanOp::Permute {
+                                 parent: "f1".into(),
+                                 columns: vec![0],
+                             })]);
+        assert_eq!(plan.reused, vec![String::from("f1")]);
+
+        // leaves are never reused, since they need readers of their own
+        let plan = QueryPlan::new("q3".into(), vec![filter("q3")], "q3".into(), Some(vec![0]));
+        assert_eq!(o.optimize(&catalog, plan).nodes, vec![filter("q3")]);
+    }
+
+    #[test]
+    fn it_runs_custom_rules() {
+        struct Rename;
+        impl Rule for Rename {
+            fn name(&self) -> &str {
+                "rename"
+            }
+
+            fn apply(&self, _: &Catalog, plan: &mut QueryPlan) -> bool {
+                if plan.name == "renamed" {
+                    return false;
+                }
+                plan.name = "renamed".into();
+                true
+            }
+        }
+
+        let mut o = Optimizer::new();
+        o.register(Rename);
+        assert_eq!(o.rules(), vec!["rename"]);
+
+        let plan = QueryPlan::new("q".into(), vec![], "users".into(), None);
+        assert_eq!(o.optimize(&catalog(), plan).name, "renamed");
+    }
+}
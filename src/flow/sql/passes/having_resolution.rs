@@ -0,0 +1,132 @@
+use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, FieldExpression, SqlQuery};
+
+pub trait HavingResolution {
+    fn resolve_having(self) -> SqlQuery;
+}
+
+/// Replace any aggregations in `ce` that are also computed in the field list by references to the
+/// computed columns, since that is where their values are found once the query is grouped.
+fn rewrite_conditional(computed: &[Column], ce: ConditionExpression) -> ConditionExpression {
+    let resolve_column = |f: Column| -> Column {
+        if f.function.is_none() {
+            return f;
+        }
+        match computed.iter().find(|c| c.function == f.function) {
+            Some(c) => {
+                Column {
+                    name: c.name.clone(),
+                    table: None,
+                    function: None,
+                }
+            }
+            None => f,
+        }
+    };
+
+    let resolve_ct_arm =
+        |i: Option<Box<ConditionExpression>>| -> Option<Box<ConditionExpression>> {
+            i.map(|bce| {
+                Box::new(match *bce {
+                    ConditionExpression::Base(ConditionBase::Field(f)) => {
+                        ConditionExpression::Base(ConditionBase::Field(resolve_column(f)))
+                    }
+                    x => rewrite_conditional(computed, x),
+                })
+            })
+        };
+
+    match ce {
+        ConditionExpression::ComparisonOp(ct) => {
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: ct.operator,
+                left: resolve_ct_arm(ct.left),
+                right: resolve_ct_arm(ct.right),
+            })
+        }
+        ConditionExpression::LogicalOp(ct) => {
+            ConditionExpression::LogicalOp(ConditionTree {
+                operator: ct.operator,
+                left: resolve_ct_arm(ct.left),
+                right: resolve_ct_arm(ct.right),
+            })
+        }
+        x => x,
+    }
+}
+
+impl HavingResolution for SqlQuery {
+    fn resolve_having(self) -> SqlQuery {
+        let err = "Must apply StarExpansion pass before HavingResolution"; // for wrapping
+        match self {
+            SqlQuery::Select(mut sq) => {
+                let computed: Vec<Column> = match sq.fields {
+                    FieldExpression::All => panic!(err),
+                    FieldExpression::Seq(ref fs) => {
+                        fs.iter().filter(|f| f.function.is_some()).cloned().collect()
+                    }
+                };
+                if let Some(ref mut gb) = sq.group_by {
+                    gb.having = gb.having.take().map(|h| rewrite_conditional(&computed, h));
+                }
+                SqlQuery::Select(sq)
+            }
+            // nothing to do for other query types, as they cannot have a HAVING clause
+            x => x,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, FieldExpression,
+                  FunctionExpression, GroupByClause, Operator, SelectStatement, SqlQuery, Table};
+    use super::HavingResolution;
+
+    #[test]
+    fn it_resolves_aggregations_in_having() {
+        let wrap = |cb| Some(Box::new(ConditionExpression::Base(cb)));
+        let count = Some(FunctionExpression::Count(FieldExpression::Seq(vec![
+            Column::from("votes.uid"),
+        ])));
+        let having = |left: Column| {
+            ConditionExpression::ComparisonOp(ConditionTree {
+                operator: Operator::Greater,
+                left: wrap(ConditionBase::Field(left)),
+                right: wrap(ConditionBase::Literal(String::from("5"))),
+            })
+        };
+
+        // SELECT votes.aid, COUNT(votes.uid) AS votes FROM votes GROUP BY votes.aid
+        //   HAVING COUNT(votes.uid) > 5;
+        // -->
+        // SELECT votes.aid, COUNT(votes.uid) AS votes FROM votes GROUP BY votes.aid
+        //   HAVING votes > 5;
+        let q = SelectStatement {
+            tables: vec![Table::from("votes")],
+            fields: FieldExpression::Seq(vec![Column::from("votes.aid"),
+                                              Column {
+                                                  name: String::from("votes"),
+                                                  table: None,
+                                                  function: count.clone(),
+                                              }]),
+            group_by: Some(GroupByClause {
+                columns: vec![Column::from("votes.aid")],
+                having: Some(having(Column {
+                    name: String::from("anon_fn"),
+                    table: None,
+                    function: count,
+                })),
+            }),
+            ..Default::default()
+        };
+
+        match SqlQuery::Select(q).resolve_having() {
+            SqlQuery::Select(tq) => {
+                assert_eq!(tq.group_by.unwrap().having,
+                           Some(having(Column::from("votes"))));
+            }
+            // if we get anything other than a selection query back, something really weird is up
+            _ => panic!(),
+        }
+    }
+}
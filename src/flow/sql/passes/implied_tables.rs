@@ -156,6 +156,38 @@ impl ImpliedTableExpansion for SqlQuery {
                     None => None,
                     Some(wc) => Some(rewrite_conditional(&translate_column, wc)),
                 };
+                // Columns in ORDER BY and HAVING clauses may also name a computed column, in
+                // which case they have no implied table
+                let computed: Vec<String> = match sq.fields {
+                    FieldExpression::Seq(ref fs) => {
+                        fs.iter()
+                            .filter(|f| f.function.is_some())
+                            .map(|f| f.name.clone())
+                            .collect()
+                    }
+                    FieldExpression::All => vec![],
+                };
+                let translate_result_column = |c: Column, known_table: Option<Table>| -> Column {
+                    if c.table.is_none() && c.function.is_none() && computed.contains(&c.name) {
+                        c
+                    } else {
+                        translate_column(c, known_table)
+                    }
+                };
+                // Expand within ORDER BY clause
+                if let Some(ref mut order) = sq.order {
+                    let columns = order.columns
+                        .drain(..)
+                        .map(|c| translate_result_column(c, None))
+                        .collect();
+                    order.columns = columns;
+                }
+                // Expand within HAVING clause
+                if let Some(ref mut gb) = sq.group_by {
+                    gb.having = gb.having
+                        .take()
+                        .map(|h| rewrite_conditional(&translate_result_column, h));
+                }
 
                 SqlQuery::Select(sq)
             }
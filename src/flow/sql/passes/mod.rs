@@ -1,4 +1,5 @@
 pub mod alias_removal;
 pub mod count_star_rewrite;
+pub mod having_resolution;
 pub mod implied_tables;
 pub mod star_expansion;
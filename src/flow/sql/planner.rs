@@ -0,0 +1,1554 @@
+//! Planning of SQL queries into logical data flow plans.
+//!
+//! Planning is kept separate from graph construction: `plan_query` is a pure function of a
+//! `Catalog` describing the views that already exist and the query to plan, and it produces a
+//! `QueryPlan` listing the nodes that must be added to the graph to answer the query. This makes
+//! it possible to test (and extend) decisions such as join order, operator selection, and reuse of
+//! existing views without constructing a `Blender`. The `SqlIncorporator` turns plans into nodes.
+
+use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, Operator, SelectStatement,
+              SqlQuery, TableKey};
+
+use flow::data::DataType;
+use flow::sql::query_graph::{QueryGraph, QueryGraphEdge, QueryGraphNode, QueryGraphOrder,
+                             SubqueryColumn, SubqueryCondition, to_query_graph};
+use ops::grouped::aggregate::Aggregation;
+use ops::grouped::extremum::Extremum;
+use ops::join::Comparison;
+use ops::predicate::{Operand, Predicate};
+
+use std::collections::{HashMap, HashSet};
+
+/// The grouped operation computed by a `PlanOp::Grouped` node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupedFunction {
+    /// An aggregation, such as a count or a sum.
+    Aggregation(Aggregation),
+    /// An extremum, such as a minimum or a maximum.
+    Extremum(Extremum),
+    /// A concatenation of the values in each group, joined by the given separator.
+    GroupConcat(&'static str),
+}
+
+/// The operator computed by a planned node.
+///
+/// Parents are referred to by name, and are either views that already exist in the `Catalog` the
+/// query was planned against, or nodes that appear earlier in the same `QueryPlan`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlanOp {
+    /// A base table, optionally with a primary key.
+    Base {
+        /// The columns that make up the table's primary key, if any.
+        primary_key: Option<Vec<usize>>,
+    },
+    /// A filter that only lets through rows whose columns equal the given values.
+    Filter {
+        /// The view being filtered.
+        parent: String,
+        /// For each column of `parent`, the value it must be equal to, if any.
+        conditions: Vec<Option<DataType>>,
+    },
+    /// A filter that only lets through rows that match an arbitrary predicate.
+    Predicate {
+        /// The view being filtered.
+        parent: String,
+        /// The predicate rows must match.
+        predicate: Predicate,
+    },
+    /// A selection and reordering of the columns of `parent`.
+    Permute {
+        /// The view whose columns are permuted.
+        parent: String,
+        /// The columns of `parent` to emit, in order.
+        columns: Vec<usize>,
+    },
+    /// A projection of columns of `parent`, followed by a set of literal values.
+    Project {
+        /// The view whose columns are projected.
+        parent: String,
+        /// The columns of `parent` to emit, in order.
+        columns: Vec<usize>,
+        /// Literal values to emit after the projected columns.
+        literals: Vec<DataType>,
+    },
+    /// An equi-join of two views, or a left join that also emits the rows of `left` that match
+    /// nothing in `right`, with `NULL` in the columns emitted from `right`.
+    Join {
+        /// The left side of the join.
+        left: String,
+        /// The right side of the join.
+        right: String,
+        /// The columns emitted by the join, each given as a side of the join and a column.
+        emit: Vec<(String, usize)>,
+        /// For each column of `left`, the join group it is part of (or 0 if none).
+        left_groups: Vec<usize>,
+        /// For each column of `right`, the join group it is part of (or 0 if none).
+        right_groups: Vec<usize>,
+        /// Whether this is a left join.
+        left_outer: bool,
+    },
+    /// A grouped computation over a single column of `parent`.
+    Grouped {
+        /// The view being grouped.
+        parent: String,
+        /// The function computed for each group.
+        function: GroupedFunction,
+        /// The column of `parent` the function is computed over.
+        over: usize,
+        /// The columns of `parent` to group by.
+        group_by: Vec<usize>,
+    },
+    /// The `k` records with the highest (or lowest) value in one column for every group of
+    /// `parent`'s records.
+    TopK {
+        /// The view whose records are ranked.
+        parent: String,
+        /// The columns of `parent` to group by.
+        group_by: Vec<usize>,
+        /// The column of `parent` that records are ranked by.
+        order: usize,
+        /// The number of records to keep for every group.
+        k: usize,
+        /// Whether to keep the records with the highest values rather than the lowest.
+        descending: bool,
+    },
+    /// The records of `left` that have a match in `right` (or, for an anti-join, that do not).
+    SemiJoin {
+        /// The view whose records are forwarded.
+        left: String,
+        /// The view that records are matched against.
+        right: String,
+        /// The column of `left` that must (or must not) equal the given column of `right`.
+        on: (usize, usize),
+        /// Whether to forward the records that have no match rather than those that do.
+        anti: bool,
+    },
+    /// A node that forwards all of `parent`'s records unchanged.
+    Identity {
+        /// The view being forwarded.
+        parent: String,
+    },
+}
+
+impl PlanOp {
+    /// The names of the views this operator reads from.
+    pub fn parents(&self) -> Vec<&String> {
+        match *self {
+            PlanOp::Base { .. } => vec![],
+            PlanOp::Filter { ref parent, .. } |
+            PlanOp::Predicate { ref parent, .. } |
+            PlanOp::Permute { ref parent, .. } |
+            PlanOp::Project { ref parent, .. } |
+            PlanOp::Grouped { ref parent, .. } |
+            PlanOp::TopK { ref parent, .. } |
+            PlanOp::Identity { ref parent } => vec![parent],
+            PlanOp::Join { ref left, ref right, .. } |
+            PlanOp::SemiJoin { ref left, ref right, .. } => vec![left, right],
+        }
+    }
+}
+
+/// A single node in a `QueryPlan`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlanNode {
+    /// The name of the view this node will produce.
+    pub name: String,
+    /// The names of the node's output columns.
+    pub fields: Vec<String>,
+    /// The operator the node computes.
+    pub op: PlanOp,
+}
+
+/// A logical plan for answering a single query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    /// The name of the query.
+    pub name: String,
+    /// The nodes that must be added to answer the query, in the order they must be added.
+    pub nodes: Vec<PlanNode>,
+    /// The name of the view that holds the query's results.
+    pub leaf: String,
+    /// If set, the columns of `leaf` that a reader should be maintained on, one for each of the
+    /// query's parameters.
+    pub reader_key: Option<Vec<usize>>,
+    /// Existing views that the plan uses in place of nodes of its own.
+    pub reused: Vec<String>,
+
+    query_graph: Option<QueryGraph>,
+}
+
+impl QueryPlan {
+    /// Construct a plan that adds the given nodes to answer the query named `name`.
+    ///
+    /// Plans constructed this way are never reused as a whole by later queries, although their
+    /// nodes may be.
+    pub fn new(name: String,
+               nodes: Vec<PlanNode>,
+               leaf: String,
+               reader_key: Option<Vec<usize>>)
+               -> QueryPlan {
+        QueryPlan {
+            name: name,
+            nodes: nodes,
+            leaf: leaf,
+            reader_key: reader_key,
+            reused: vec![],
+            query_graph: None,
+        }
+    }
+
+    /// The names of the columns that the query's parameters are compared against, in order.
+    pub fn parameters(&self) -> Vec<String> {
+        match self.query_graph {
+            Some(ref qg) => qg.parameters().into_iter().map(|c| c.name.clone()).collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// The set of views that queries can be planned against.
+///
+/// A catalog learns about new views when the plans that produce them are registered with it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Catalog {
+    write_schemas: HashMap<String, Vec<String>>,
+    primary_keys: HashMap<String, Vec<usize>>,
+    view_fields: HashMap<String, Vec<String>>,
+    // the nodes that produce views other than base tables, in the order they were added
+    nodes: Vec<PlanNode>,
+    query_graphs: Vec<(QueryGraph, String)>,
+    // the view that holds the results of every named query that is not a base table
+    queries: HashMap<String, String>,
+    num_queries: usize,
+}
+
+impl Catalog {
+    /// Creates a new, empty catalog.
+    pub fn new() -> Self {
+        Catalog::default()
+    }
+
+    /// The output columns of the named view, if it exists.
+    pub fn fields(&self, view: &str) -> Option<&[String]> {
+        self.view_fields.get(view).map(|fs| &fs[..])
+    }
+
+    /// The columns of the named base table, if it exists.
+    pub fn table_fields(&self, table: &str) -> Option<&[String]> {
+        self.write_schemas.get(table).map(|fs| &fs[..])
+    }
+
+    /// The primary key columns of the named base table, if it exists and has a primary key.
+    pub fn primary_key(&self, table: &str) -> Option<&[usize]> {
+        self.primary_keys.get(table).map(|key| &key[..])
+    }
+
+    /// The name of an existing view that computes `op` with the given output columns, if any.
+    ///
+    /// Views are only equivalent if they compute the same operator over the same parents, so
+    /// comparing chains of nodes that start at the same views finds the views they share.
+    pub fn equivalent_view(&self, fields: &[String], op: &PlanOp) -> Option<&str> {
+        self.nodes
+            .iter()
+            .find(|n| n.op == *op && &n.fields[..] == fields)
+            .map(|n| n.name.as_str())
+    }
+
+    /// The schemas that the columns used in `q` are resolved against.
+    ///
+    /// Selections can use the views of earlier queries (such as those that hold the results of
+    /// derived tables) as well as base tables, but only resolve columns against the tables and
+    /// views that they select from.
+    fn schemas_for(&self, q: &SqlQuery) -> HashMap<String, Vec<String>> {
+        match *q {
+            SqlQuery::Select(ref st) => {
+                st.tables
+                    .iter()
+                    .filter_map(|t| {
+                        self.view_fields.get(&t.name).map(|fs| (t.name.clone(), fs.clone()))
+                    })
+                    .collect()
+            }
+            _ => self.write_schemas.clone(),
+        }
+    }
+
+    /// Record the views produced by the given plan, so that later queries can use them.
+    pub fn register(&mut self, plan: &QueryPlan) {
+        for n in &plan.nodes {
+            if let PlanOp::Base { ref primary_key } = n.op {
+                self.write_schemas.insert(n.name.clone(), n.fields.clone());
+                if let Some(ref key) = *primary_key {
+                    self.primary_keys.insert(n.name.clone(), key.clone());
+                }
+            } else {
+                self.nodes.push(n.clone());
+            }
+            self.view_fields.insert(n.name.clone(), n.fields.clone());
+        }
+        if let Some(ref qg) = plan.query_graph {
+            self.query_graphs.push((qg.clone(), plan.leaf.clone()));
+        }
+        if !self.write_schemas.contains_key(&plan.leaf) {
+            self.queries.insert(plan.name.clone(), plan.leaf.clone());
+        }
+        self.num_queries += 1;
+    }
+
+    /// Add a column to the named base table, and return its index.
+    ///
+    /// Views that were planned before the column was added are left as they are.
+    pub fn add_column(&mut self, table: &str, column: &str) -> Result<usize, String> {
+        let fields = match self.write_schemas.get_mut(table) {
+            Some(fields) => fields,
+            None => return Err(format!("no table named {}", table)),
+        };
+        if fields.iter().any(|f| f == column) {
+            return Err(format!("table {} already has a column named {}", table, column));
+        }
+        fields.push(column.to_owned());
+        self.view_fields.insert(table.to_owned(), fields.clone());
+        Ok(fields.len() - 1)
+    }
+
+    /// The names of the queries whose results are held by views, in no particular order.
+    pub fn views(&self) -> Vec<&str> {
+        self.queries.keys().map(|q| q.as_str()).collect()
+    }
+
+    /// Forget the named query, along with every view that no other query uses.
+    ///
+    /// Returns the names of the views that were forgotten, in the order they were added. Views
+    /// that the query shares with other queries (including those of queries that select from its
+    /// results) are kept, as are base tables.
+    pub fn remove_query(&mut self, name: &str) -> Result<Vec<String>, String> {
+        let leaf = match self.queries.remove(name) {
+            Some(leaf) => leaf,
+            None => return Err(format!("no view named {}", name)),
+        };
+
+        let removed: Vec<String> = {
+            // every view that the remaining queries read from, directly or indirectly, must stay
+            let mut needed = HashSet::new();
+            let mut stack: Vec<&str> = self.queries.values().map(|v| v.as_str()).collect();
+            while let Some(v) = stack.pop() {
+                if !needed.insert(v) {
+                    continue;
+                }
+                if let Some(n) = self.nodes.iter().find(|n| n.name == v) {
+                    stack.extend(n.op.parents().into_iter().map(|p| p.as_str()));
+                }
+            }
+
+            // of the views that the removed query reads from, those that are not needed can go
+            let mut unused = HashSet::new();
+            let mut stack = vec![leaf.as_str()];
+            while let Some(v) = stack.pop() {
+                if needed.contains(v) || !unused.insert(v) {
+                    continue;
+                }
+                if let Some(n) = self.nodes.iter().find(|n| n.name == v) {
+                    stack.extend(n.op.parents().into_iter().map(|p| p.as_str()));
+                }
+            }
+            self.nodes
+                .iter()
+                .filter(|n| unused.contains(n.name.as_str()))
+                .map(|n| n.name.clone())
+                .collect()
+        };
+
+        self.nodes.retain(|n| !removed.contains(&n.name));
+        self.query_graphs.retain(|&(_, ref leaf)| !removed.contains(leaf));
+        for v in &removed {
+            self.view_fields.remove(v);
+        }
+        Ok(removed)
+    }
+}
+
+/// The comparison that a SQL operator performs, if it is one that `Predicate` supports.
+pub fn to_comparison(op: &Operator) -> Option<Comparison> {
+    match *op {
+        Operator::Equal => Some(Comparison::Equal),
+        Operator::NotEqual => Some(Comparison::NotEqual),
+        Operator::Less => Some(Comparison::Less),
+        Operator::LessOrEqual => Some(Comparison::LessOrEqual),
+        Operator::Greater => Some(Comparison::Greater),
+        Operator::GreaterOrEqual => Some(Comparison::GreaterOrEqual),
+        _ => None,
+    }
+}
+
+/// The value of a literal in a SQL condition. Literals that are integers are compared as such.
+fn to_literal(l: &str) -> DataType {
+    match l.parse::<i64>() {
+        Ok(n) => DataType::from(n),
+        Err(_) => DataType::from(l),
+    }
+}
+
+/// Plan the given query against the views in `catalog`.
+///
+/// If no `name` is specified, the table name is used in the case of CREATE TABLE and INSERT
+/// queries, and a deterministic, unique name is generated otherwise. Planning does not modify the
+/// catalog; the returned plan should be registered with it once it has been applied.
+pub fn plan_query(catalog: &Catalog,
+                  q: SqlQuery,
+                  name: Option<String>)
+                  -> Result<QueryPlan, String> {
+    plan_query_with_subqueries(catalog, q, name, vec![], vec![])
+}
+
+/// The table that the column of a query's subquery condition belongs to. Columns can name the
+/// table by its alias, or leave it out if exactly one of the tables selected from has the column.
+fn resolve_subquery_column(catalog: &Catalog,
+                           st: &SelectStatement,
+                           column: &Column)
+                           -> Result<Column, String> {
+    let table = match column.table {
+        Some(ref t) => {
+            st.tables
+                .iter()
+                .find(|table| table.alias.as_ref() == Some(t) || table.name == *t)
+                .map(|table| table.name.clone())
+        }
+        None => {
+            let mut candidates = st.tables.iter().filter(|table| {
+                catalog.fields(&table.name).map_or(false, |fs| fs.contains(&column.name))
+            });
+            match (candidates.next(), candidates.next()) {
+                (Some(table), None) => Some(table.name.clone()),
+                _ => None,
+            }
+        }
+    };
+    match table {
+        Some(table) => {
+            Ok(Column {
+                name: column.name.clone(),
+                table: Some(table),
+                function: None,
+            })
+        }
+        None => Err(format!("cannot resolve column {:?} tested against a subquery", column)),
+    }
+}
+
+/// Plan the given query like `plan_query`, but only keep the results that also satisfy the given
+/// conditions on subqueries, and extend them with the given columns computed by subqueries. The
+/// views of the subqueries must already exist in `catalog`.
+pub fn plan_query_with_subqueries(catalog: &Catalog,
+                                  q: SqlQuery,
+                                  name: Option<String>,
+                                  subqueries: Vec<SubqueryCondition>,
+                                  columns: Vec<SubqueryColumn>)
+                                  -> Result<QueryPlan, String> {
+    use flow::sql::passes::alias_removal::AliasRemoval;
+    use flow::sql::passes::count_star_rewrite::CountStarRewrite;
+    use flow::sql::passes::having_resolution::HavingResolution;
+    use flow::sql::passes::implied_tables::ImpliedTableExpansion;
+    use flow::sql::passes::star_expansion::StarExpansion;
+
+    let name = name.unwrap_or_else(|| match q {
+        SqlQuery::CreateTable(ref ctq) => ctq.table.name.clone(),
+        SqlQuery::Insert(ref iq) => iq.table.name.clone(),
+        SqlQuery::Select(_) => format!("q_{}", catalog.num_queries),
+    });
+
+    // the columns of subquery conditions may use table aliases too, so they are resolved before
+    // the aliases are removed from the query
+    let (subqueries, columns) = match q {
+        SqlQuery::Select(ref st) => {
+            let subqueries = subqueries.into_iter()
+                .map(|mut sq| -> Result<SubqueryCondition, String> {
+                    sq.column = resolve_subquery_column(catalog, st, &sq.column)?;
+                    Ok(sq)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            let columns = columns.into_iter()
+                .map(|mut sc| -> Result<SubqueryColumn, String> {
+                    sc.column = resolve_subquery_column(catalog, st, &sc.column)?;
+                    Ok(sc)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            (subqueries, columns)
+        }
+        _ if subqueries.is_empty() && columns.is_empty() => (subqueries, columns),
+        _ => return Err(String::from("only selections can use subqueries")),
+    };
+
+    // first run some standard rewrite passes on the query. This makes the later work easier,
+    // as we no longer have to consider complications like aliases.
+    let schemas = catalog.schemas_for(&q);
+    let q = q.expand_table_aliases()
+        .expand_stars(&schemas)
+        .expand_implied_tables(&schemas)
+        .resolve_having()
+        .rewrite_count_star(&schemas);
+
+    let mut planner = Planner {
+        catalog: catalog,
+        nodes: Vec::new(),
+    };
+    let (leaf, reader_key, qg) = match q {
+        SqlQuery::CreateTable(ctq) => {
+            assert_eq!(name, ctq.table.name);
+            let leaf = planner.plan_base(&ctq.table.name, &ctq.fields, ctq.keys.as_ref())?;
+            (leaf, None, None)
+        }
+        SqlQuery::Insert(iq) => {
+            assert_eq!(name, iq.table.name);
+            let (cols, _): (Vec<Column>, Vec<String>) = iq.fields.iter().cloned().unzip();
+            let leaf = planner.plan_base(&iq.table.name, &cols, None)?;
+            (leaf, None, None)
+        }
+        SqlQuery::Select(sq) => planner.plan_selection(&sq, &subqueries, &columns, &name)?,
+    };
+
+    Ok(QueryPlan {
+        name: name,
+        nodes: planner.nodes,
+        leaf: leaf,
+        reader_key: reader_key,
+        reused: vec![],
+        query_graph: qg,
+    })
+}
+
+fn target_columns_from_computed_column(computed_col: &Column) -> &Vec<Column> {
+    use nom_sql::FunctionExpression::*;
+    use nom_sql::FieldExpression::*;
+
+    match *computed_col.function.as_ref().unwrap() {
+        Avg(Seq(ref cols)) |
+        Count(Seq(ref cols)) |
+        GroupConcat(Seq(ref cols)) |
+        Max(Seq(ref cols)) |
+        Min(Seq(ref cols)) |
+        Sum(Seq(ref cols)) => cols,
+        Count(All) => {
+            // see comment re COUNT(*) rewriting in plan_function
+            panic!("COUNT(*) should have been rewritten earlier!")
+        }
+        _ => panic!("invalid aggregation function"),
+    }
+}
+
+/// Accumulates the nodes planned for a single query.
+struct Planner<'a> {
+    catalog: &'a Catalog,
+    nodes: Vec<PlanNode>,
+}
+
+impl<'a> Planner<'a> {
+    fn fields_for(&self, view: &str) -> Result<&[String], String> {
+        // nodes planned for this query shadow existing views of the same name
+        self.nodes
+            .iter()
+            .rev()
+            .find(|n| n.name == view)
+            .map(|n| &n.fields[..])
+            .or_else(|| self.catalog.fields(view))
+            .ok_or_else(|| format!("view {} not found", view))
+    }
+
+    fn field_to_columnid(&self, view: &str, f: &str) -> Result<usize, String> {
+        match self.fields_for(view)?.iter().position(|s| *s == f) {
+            None => Err(format!("field {} not found in view {}", f, view)),
+            Some(i) => Ok(i),
+        }
+    }
+
+    fn add(&mut self, name: String, fields: Vec<String>, op: PlanOp) -> String {
+        self.nodes.push(PlanNode {
+            name: name.clone(),
+            fields: fields,
+            op: op,
+        });
+        name
+    }
+
+    /// The columns of `view` that a reader for the query described by `qg` should be keyed on,
+    /// in the order of the query's parameters.
+    fn reader_key(&self, qg: &QueryGraph, view: &str) -> Result<Vec<usize>, String> {
+        let params = qg.parameters();
+        if params.is_empty() {
+            // no query parameters, so we index on the first (and often only) column
+            return Ok(vec![0]);
+        }
+        params.into_iter().map(|c| self.field_to_columnid(view, &c.name)).collect()
+    }
+
+    /// Converts a condition tree stored in the `ConditionExpr` returned by the SQL parser into a
+    /// vector of conditions that `shortcut` understands.
+    fn to_conditions(&self,
+                     ct: &ConditionTree,
+                     view: &str)
+                     -> Result<Vec<Option<DataType>>, String> {
+        // TODO(malte): support other types of operators
+        if ct.operator != Operator::Equal {
+            println!("Conditionals with {:?} are not supported yet, so ignoring {:?}",
+                     ct.operator,
+                     ct);
+            Ok(vec![])
+        } else {
+            // TODO(malte): we only support one level of condition nesting at this point :(
+            let l = match *ct.left.as_ref().unwrap().as_ref() {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
+                _ => unimplemented!(),
+            };
+            let r = match *ct.right.as_ref().unwrap().as_ref() {
+                ConditionExpression::Base(ConditionBase::Literal(ref l)) => l.clone(),
+                _ => unimplemented!(),
+            };
+            let num_columns = self.fields_for(view)?.len();
+            let mut filter = vec![None; num_columns];
+            filter[self.field_to_columnid(view, &l.name)?] = Some(DataType::from(r));
+            Ok(filter)
+        }
+    }
+
+    /// Converts a condition tree of comparisons between columns and literals or other columns,
+    /// combined with `AND` and `OR`, into a `Predicate` over the columns of `view`.
+    fn to_predicate(&self, ct: &ConditionTree, view: &str) -> Result<Predicate, String> {
+        match ct.operator {
+            Operator::And | Operator::Or => {
+                let and = ct.operator == Operator::And;
+                let mut ps = Vec::new();
+                for side in ct.left.iter().chain(ct.right.iter()) {
+                    let p = match **side {
+                        ConditionExpression::LogicalOp(ref ct) |
+                        ConditionExpression::ComparisonOp(ref ct) => self.to_predicate(ct, view)?,
+                        ConditionExpression::Base(_) => {
+                            return Err(format!("cannot combine {:?} with {:?}", side, ct.operator))
+                        }
+                    };
+                    // flatten nested conjunctions and disjunctions
+                    match p {
+                        Predicate::And(inner) if and => ps.extend(inner),
+                        Predicate::Or(inner) if !and => ps.extend(inner),
+                        p => ps.push(p),
+                    }
+                }
+                Ok(if and {
+                    Predicate::And(ps)
+                } else {
+                    Predicate::Or(ps)
+                })
+            }
+            ref op => {
+                let cmp = match to_comparison(op) {
+                    Some(cmp) => cmp,
+                    None => return Err(format!("{:?} comparisons are not supported", op)),
+                };
+                let l = match ct.left.as_ref().map(|l| &**l) {
+                    Some(&ConditionExpression::Base(ConditionBase::Field(ref f))) => {
+                        self.field_to_columnid(view, &f.name)?
+                    }
+                    _ => return Err(format!("left-hand side of {:?} must be a column", ct)),
+                };
+                let r = match ct.right.as_ref().map(|r| &**r) {
+                    Some(&ConditionExpression::Base(ConditionBase::Field(ref f))) => {
+                        Operand::Column(self.field_to_columnid(view, &f.name)?)
+                    }
+                    Some(&ConditionExpression::Base(ConditionBase::Literal(ref l))) => {
+                        Operand::Literal(to_literal(l))
+                    }
+                    _ => {
+                        return Err(format!("right-hand side of {:?} must be a column or a literal",
+                                           ct))
+                    }
+                };
+                Ok(Predicate::Compare(l, cmp, r))
+            }
+        }
+    }
+
+    fn plan_base(&mut self,
+                 name: &str,
+                 cols: &Vec<Column>,
+                 keys: Option<&Vec<TableKey>>)
+                 -> Result<String, String> {
+        if self.catalog.write_schemas.contains_key(name) {
+            println!("WARNING: base table for write type {} already exists: ignoring query.",
+                     name);
+            return Ok(String::from(name));
+        }
+
+        let fields = cols.iter().map(|c| c.name.clone()).collect();
+
+        let primary_keys: Vec<_> = match keys {
+            None => vec![],
+            Some(keys) => {
+                keys.iter()
+                    .filter_map(|k| match *k {
+                        ref k @ TableKey::PrimaryKey(..) => Some(k),
+                        _ => None,
+                    })
+                    .collect()
+            }
+        };
+        assert!(primary_keys.len() <= 1);
+
+        let primary_key = primary_keys.first().map(|k| match **k {
+            TableKey::PrimaryKey(ref key_cols) => {
+                key_cols.iter()
+                    .map(|pkc| {
+                        assert_eq!(pkc.table.as_ref().unwrap(), name);
+                        cols.iter().position(|c| c == pkc).unwrap()
+                    })
+                    .collect()
+            }
+            _ => unreachable!(),
+        });
+
+        Ok(self.add(String::from(name), fields, PlanOp::Base { primary_key: primary_key }))
+    }
+
+    fn plan_grouped(&mut self,
+                    name: &str,
+                    computed_col_name: &str,
+                    parent: String,
+                    over: usize,
+                    group_by: &[Column],
+                    function: GroupedFunction)
+                    -> Result<String, String> {
+        let group_by_ids = group_by.iter()
+            .map(|c| self.field_to_columnid(&parent, &c.name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The function node's set of output columns is the group columns plus the function
+        // column
+        let mut combined_columns: Vec<_> = group_by.iter().map(|c| c.name.clone()).collect();
+        combined_columns.push(String::from(computed_col_name));
+
+        Ok(self.add(String::from(name),
+                    combined_columns,
+                    PlanOp::Grouped {
+                        parent: parent,
+                        function: function,
+                        over: over,
+                        group_by: group_by_ids,
+                    }))
+    }
+
+    fn plan_function(&mut self,
+                     name: &str,
+                     func_col: &Column,
+                     group_cols: &[Column],
+                     parent: Option<String>) // XXX(malte): nasty hack for non-grouped funcs
+                     -> Result<String, String> {
+        use nom_sql::FunctionExpression::*;
+        use nom_sql::FieldExpression::*;
+
+        let (cols, function) = match *func_col.function.as_ref().unwrap() {
+            Sum(Seq(ref cols)) => (cols, GroupedFunction::Aggregation(Aggregation::SUM)),
+            Avg(Seq(ref cols)) => (cols, GroupedFunction::Aggregation(Aggregation::AVG)),
+            Count(Seq(ref cols)) => (cols, GroupedFunction::Aggregation(Aggregation::COUNT)),
+            Count(All) => {
+                // XXX(malte): there is no "over" column, but our aggregation operators' API
+                // requires one to be specified, so we earlier rewrote it to use the last parent
+                // column (see passes/count_star_rewrite.rs). However, this isn't *entirely*
+                // faithful to COUNT(*) semantics, because COUNT(*) is supposed to count all
+                // rows including those with NULL values, and we don't have a mechanism to do that
+                // (but we also don't have a NULL value, so maybe we're okay).
+                panic!("COUNT(*) should have been rewritten earlier!")
+            }
+            Max(Seq(ref cols)) => (cols, GroupedFunction::Extremum(Extremum::MAX)),
+            Min(Seq(ref cols)) => (cols, GroupedFunction::Extremum(Extremum::MIN)),
+            // MySQL's default separator
+            GroupConcat(Seq(ref cols)) => (cols, GroupedFunction::GroupConcat(",")),
+            _ => unimplemented!(),
+        };
+
+        // No support for multi-columns functions at this point
+        assert_eq!(cols.len(), 1);
+
+        let over = cols.iter().next().unwrap();
+        let parent = match parent {
+            // If no explicit parent node is specified, we extract the base node from the
+            // "over" column's specification
+            None => over.table.clone().unwrap(),
+            // We have an explicit parent node (likely a projection helper), so use that
+            Some(p) => p,
+        };
+        let over_col_indx = self.field_to_columnid(&parent, &over.name)?;
+
+        self.plan_grouped(name,
+                          &func_col.name,
+                          parent,
+                          over_col_indx,
+                          group_cols,
+                          function)
+    }
+
+    fn plan_projection_helper(&mut self,
+                              name: &str,
+                              computed_col: &Column)
+                              -> Result<String, String> {
+        let target_cols = target_columns_from_computed_column(computed_col);
+        // TODO(malte): we only support a single column argument at this point
+        assert_eq!(target_cols.len(), 1);
+        let fn_col = target_cols.last().unwrap();
+
+        self.plan_project(name,
+                          fn_col.table.as_ref().unwrap(),
+                          vec![fn_col],
+                          vec![("grp", DataType::from(0 as i32))])
+    }
+
+    fn plan_project(&mut self,
+                    name: &str,
+                    parent: &str,
+                    proj_cols: Vec<&Column>,
+                    literals: Vec<(&str, DataType)>)
+                    -> Result<String, String> {
+        let proj_col_ids = proj_cols.iter()
+            .map(|c| self.field_to_columnid(parent, &c.name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut col_names: Vec<String> = proj_cols.iter().map(|c| c.name.clone()).collect();
+        let (literal_names, literal_values): (Vec<_>, Vec<_>) = literals.into_iter().unzip();
+        col_names.extend(literal_names.into_iter().map(String::from));
+
+        Ok(self.add(String::from(name),
+                    col_names,
+                    PlanOp::Project {
+                        parent: String::from(parent),
+                        columns: proj_col_ids,
+                        literals: literal_values,
+                    }))
+    }
+
+    fn plan_filter_and_project(&mut self,
+                               name: &str,
+                               qgn: &QueryGraphNode)
+                               -> Result<String, String> {
+        let mut parent = qgn.rel_name.clone();
+        // chain all the filters associated with this QGN
+        for (i, cond) in qgn.predicates.iter().enumerate() {
+            // convert ConditionTree to a chain of Filter operators. equality comparisons are
+            // handled by the simpler Filter; anything else needs a general predicate.
+            let parent_fields = self.fields_for(&parent)?.to_vec();
+            let op = if cond.operator == Operator::Equal {
+                PlanOp::Filter {
+                    conditions: self.to_conditions(cond, &parent)?,
+                    parent: parent,
+                }
+            } else {
+                PlanOp::Predicate {
+                    predicate: self.to_predicate(cond, &parent)?,
+                    parent: parent,
+                }
+            };
+            parent = self.add(format!("{}_f{}", name, i), parent_fields, op);
+        }
+        // finally, project only the columns we need
+        let projected_columns = qgn.columns.iter().map(|c| c.name.clone()).collect();
+        let projected_column_ids = qgn.columns
+            .iter()
+            .map(|c| self.field_to_columnid(&parent, &c.name))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.add(String::from(name),
+                    projected_columns,
+                    PlanOp::Permute {
+                        parent: parent,
+                        columns: projected_column_ids,
+                    }))
+    }
+
+    /// Filter the output of the grouped operator `grouped` by the conditions of a HAVING clause.
+    fn plan_having(&mut self,
+                   grouped: String,
+                   predicates: &[ConditionTree])
+                   -> Result<String, String> {
+        let mut parent = grouped.clone();
+        for (i, cond) in predicates.iter().enumerate() {
+            // computed columns hold numbers, so even equality conditions are evaluated by a
+            // general predicate, which compares integer literals numerically
+            let parent_fields = self.fields_for(&parent)?.to_vec();
+            let op = PlanOp::Predicate {
+                predicate: self.to_predicate(cond, &parent)?,
+                parent: parent,
+            };
+            parent = self.add(format!("{}_h{}", grouped, i), parent_fields, op);
+        }
+        Ok(parent)
+    }
+
+    fn plan_join(&mut self,
+                 name: &str,
+                 jps: &[ConditionTree],
+                 left: String,
+                 right: String)
+                 -> Result<String, String> {
+        let projected_cols_left = self.fields_for(&left)?.to_vec();
+        let projected_cols_right = self.fields_for(&right)?.to_vec();
+
+        // non-join columns projected are the union of the ancestor's projected columns
+        // TODO(malte): this will need revisiting when we do smart reuse
+        let mut emit = Vec::with_capacity(projected_cols_left.len() + projected_cols_right.len());
+        for &(ref side, ref cols) in &[(&left, &projected_cols_left),
+                                       (&right, &projected_cols_right)] {
+            for c in cols.iter() {
+                emit.push(((*side).clone(), self.field_to_columnid(side, c)?));
+            }
+        }
+
+        // join columns need us to generate join group configs for the operator
+        let mut left_join_group = vec![0; projected_cols_left.len()];
+        let mut right_join_group = vec![0; projected_cols_right.len()];
+        for (i, p) in jps.iter().enumerate() {
+            // equi-join only
+            assert_eq!(p.operator, Operator::Equal);
+            let l_col = match **p.left.as_ref().unwrap() {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
+                _ => unimplemented!(),
+            };
+            let r_col = match **p.right.as_ref().unwrap() {
+                ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
+                _ => unimplemented!(),
+            };
+            left_join_group[self.field_to_columnid(&left, &l_col.name)?] = i + 1;
+            right_join_group[self.field_to_columnid(&right, &r_col.name)?] = i + 1;
+        }
+
+        let fields = projected_cols_left.into_iter()
+            .chain(projected_cols_right.into_iter())
+            .collect();
+        Ok(self.add(String::from(name),
+                    fields,
+                    PlanOp::Join {
+                        left: left,
+                        right: right,
+                        emit: emit,
+                        left_groups: left_join_group,
+                        right_groups: right_join_group,
+                        left_outer: false,
+                    }))
+    }
+
+    /// Keep only the first `order.limit` records of `parent` for every set of query parameters.
+    fn plan_topk(&mut self,
+                 name: &str,
+                 qg: &QueryGraph,
+                 order: &QueryGraphOrder,
+                 parent: String)
+                 -> Result<String, String> {
+        let mut parent = parent;
+        let mut group_by = qg.parameters()
+            .into_iter()
+            .map(|c| self.field_to_columnid(&parent, &c.name))
+            .collect::<Result<Vec<_>, _>>()?;
+        if group_by.len() > 4 {
+            return Err(format!("cannot order results for {} query parameters", group_by.len()));
+        }
+        if group_by.is_empty() {
+            // without parameters, all records form a single group, so we make up a group column
+            // with an extra projection node (much like for aggregations without GROUP BY)
+            let mut fields = self.fields_for(&parent)?.to_vec();
+            let columns = (0..fields.len()).collect();
+            group_by.push(fields.len());
+            fields.push(String::from("grp"));
+            parent = self.add(format!("{}_prj_hlpr", name),
+                              fields,
+                              PlanOp::Project {
+                                  parent: parent,
+                                  columns: columns,
+                                  literals: vec![DataType::from(0 as i32)],
+                              });
+        }
+
+        let fields = self.fields_for(&parent)?.to_vec();
+        let order_col = self.field_to_columnid(&parent, &order.column.name)?;
+        Ok(self.add(String::from(name),
+                    fields,
+                    PlanOp::TopK {
+                        parent: parent,
+                        group_by: group_by,
+                        order: order_col,
+                        k: order.limit,
+                        descending: order.descending,
+                    }))
+    }
+
+    /// Keep only the records of `parent` that satisfy the condition on a subquery.
+    fn plan_semijoin(&mut self,
+                     name: &str,
+                     cond: &SubqueryCondition,
+                     parent: String)
+                     -> Result<String, String> {
+        let fields = self.fields_for(&parent)?.to_vec();
+        let on = (self.field_to_columnid(&parent, &cond.column.name)?,
+                  self.field_to_columnid(&cond.view, &cond.view_column)?);
+        Ok(self.add(String::from(name),
+                    fields,
+                    PlanOp::SemiJoin {
+                        left: parent,
+                        right: cond.view.clone(),
+                        on: on,
+                        anti: cond.negated,
+                    }))
+    }
+
+    /// Extend the records of `parent` with a column computed by a subquery, by a left join with the
+    /// subquery's view.
+    fn plan_subquery_column(&mut self,
+                            name: &str,
+                            sc: &SubqueryColumn,
+                            parent: String)
+                            -> Result<String, String> {
+        let mut fields = self.fields_for(&parent)?.to_vec();
+        let mut emit: Vec<_> = (0..fields.len()).map(|c| (parent.clone(), c)).collect();
+        emit.push((sc.view.clone(), self.field_to_columnid(&sc.view, &sc.value_column)?));
+        fields.push(sc.name.clone());
+
+        let mut left_groups = vec![0; emit.len() - 1];
+        left_groups[self.field_to_columnid(&parent, &sc.column.name)?] = 1;
+        let mut right_groups = vec![0; self.fields_for(&sc.view)?.len()];
+        right_groups[self.field_to_columnid(&sc.view, &sc.view_column)?] = 1;
+        Ok(self.add(String::from(name),
+                    fields,
+                    PlanOp::Join {
+                        left: parent,
+                        right: sc.view.clone(),
+                        emit: emit,
+                        left_groups: left_groups,
+                        right_groups: right_groups,
+                        left_outer: true,
+                    }))
+    }
+
+    /// Return is (`leaf`, `reader_key`, `query_graph`), where the query graph is only given if a
+    /// new query was planned (rather than an existing one reused).
+    fn plan_selection(&mut self,
+                      st: &SelectStatement,
+                      subqueries: &[SubqueryCondition],
+                      columns: &[SubqueryColumn],
+                      name: &str)
+                      -> Result<(String, Option<Vec<usize>>, Option<QueryGraph>), String> {
+        let mut qg = to_query_graph(st)?;
+        qg.add_subqueries(subqueries)?;
+        qg.add_subquery_columns(columns)?;
+
+        // Do we already have this exact query or a subset of it?
+        // TODO(malte): make this an O(1) lookup by QG signature
+        let catalog = self.catalog;
+        for &(ref existing_qg, ref leaf) in catalog.query_graphs.iter() {
+            // note that this also checks the *order* in which parameters are specified; a
+            // different order means that we cannot simply reuse the existing reader.
+            if existing_qg.signature() == qg.signature() &&
+               existing_qg.parameters() == qg.parameters() {
+                // we already have this exact query, down to the exact same reader key columns
+                // in exactly the same order
+                return Ok((leaf.clone(), None, None));
+            } else if existing_qg.signature() == qg.signature() && qg.order.is_none() {
+                // QGs are identical, except for parameters (or their order)
+
+                // we must add a new reader for this query. This also requires adding an
+                // identity node (at least currently), since a node can only have a single
+                // associated reader. Queries that keep only their first few results cannot be
+                // reused this way, since those are chosen separately for every set of parameters.
+                // TODO(malte): consider the case when the projected columns need reordering
+                let id_fields = self.fields_for(leaf)?.to_vec();
+                let id = self.add(String::from(name),
+                                  id_fields,
+                                  PlanOp::Identity { parent: leaf.clone() });
+                let key = self.reader_key(&qg, &id)?;
+                return Ok((id, Some(key), None));
+            }
+        }
+
+        let hash = qg.signature().hash;
+        let mut i = 0;
+
+        // 1. Generate the necessary filter node for each relation node in the query graph.
+        let mut filter_nodes = HashMap::<String, String>::new();
+        // Need to iterate over relations in a deterministic order, as otherwise nodes will be
+        // added in a different order every time, which will yield different node identifiers
+        // and make it difficult for applications to check what's going on.
+        let mut sorted_rels: Vec<&String> = qg.relations.keys().collect();
+        sorted_rels.sort();
+        for rel in &sorted_rels {
+            let qgn = &qg.relations[*rel];
+            // we'll handle computed columns later
+            if *rel != "computed_columns" {
+                // the following conditional is required to avoid "empty" nodes (without any
+                // projected columns) that are required as inputs to joins
+                if !qgn.columns.is_empty() || !qgn.predicates.is_empty() {
+                    // add a basic filter/permute node for each query graph node if it either
+                    // has: 1) projected columns; or 2) a filter condition
+                    let n = self.plan_filter_and_project(&format!("q_{:x}_n{}", hash, i), qgn)?;
+                    filter_nodes.insert((*rel).clone(), n);
+                } else {
+                    // otherwise, just use the base node for the relation that is being selected
+                    // from
+                    filter_nodes.insert((*rel).clone(), (*rel).clone());
+                }
+            }
+            i += 1;
+        }
+
+        // 2. Generate join nodes for the query. This starts out by joining two of the filter
+        //    nodes corresponding to relations in the first join predicate, and then continues
+        //    to join the result against previously unseen tables from the remaining
+        //    predicates. Note that no (src, dst) pair ever occurs twice, since we've already
+        //    previously moved all predicates pertaining to src/dst joins onto a single edge.
+        let mut joined_tables = HashSet::new();
+        let mut sorted_edges: Vec<(&(String, String), &QueryGraphEdge)> =
+            qg.edges.iter().collect();
+        sorted_edges.sort_by_key(|k| &(k.0).0);
+        let mut prev_join = None;
+        for &(&(ref src, ref dst), edge) in &sorted_edges {
+            match *edge {
+                // Edge represents a JOIN
+                QueryGraphEdge::Join(ref jps) => {
+                    let left = match prev_join {
+                        None => {
+                            joined_tables.insert(src);
+                            filter_nodes[src].clone()
+                        }
+                        Some(ref j) => String::clone(j),
+                    };
+                    let right = if joined_tables.contains(src) {
+                        joined_tables.insert(dst);
+                        filter_nodes[dst].clone()
+                    } else if joined_tables.contains(dst) {
+                        joined_tables.insert(src);
+                        filter_nodes[src].clone()
+                    } else {
+                        // We have already handled *both* tables that are part of the join.
+                        // This should never occur, because their join predicates must be
+                        // associated with the same query graph edge.
+                        unreachable!();
+                    };
+                    let j = self.plan_join(&format!("q_{:x}_n{}", hash, i), jps, left, right)?;
+                    i += 1;
+                    prev_join = Some(j);
+                }
+                // Edge represents a GROUP BY, which we handle later
+                QueryGraphEdge::GroupBy(_) => (),
+            }
+        }
+
+        let mut func_nodes = Vec::new();
+        if let Some(computed_cols_cgn) = qg.relations.get("computed_columns") {
+            // Function columns with GROUP BY clause
+            let mut grouped_fn_columns = HashSet::new();
+            for e in qg.edges.values() {
+                match *e {
+                    QueryGraphEdge::Join(_) => (),
+                    QueryGraphEdge::GroupBy(ref gb_cols) => {
+                        // Generate the right function nodes for all relevant columns in the
+                        // "computed_columns" node
+                        // TODO(malte): there can only be one GROUP BY in each query, but the
+                        // columns can come from different tables. In that case, we would need to
+                        // generate an Agg-Join-Agg sequence for each pair of tables involved.
+                        for fn_col in &computed_cols_cgn.columns {
+                            let n = self.plan_function(&format!("q_{:x}_n{}", hash, i),
+                                               fn_col,
+                                               gb_cols,
+                                               None)?;
+                            func_nodes.push(n);
+                            grouped_fn_columns.insert(fn_col);
+                            i += 1;
+                        }
+                    }
+                }
+            }
+            // Function columns without GROUP BY
+            for computed_col in computed_cols_cgn.columns
+                .iter()
+                .filter(|c| !grouped_fn_columns.contains(c)) {
+
+                let agg_node_name = format!("q_{:x}_n{}", hash, i);
+
+                let over_cols = target_columns_from_computed_column(computed_col);
+                let proj_cols_from_target_table =
+                    &qg.relations[over_cols.iter().next().unwrap().table.as_ref().unwrap()]
+                        .columns;
+                let (group_cols, parent) = if proj_cols_from_target_table.is_empty() {
+                    // slightly messy hack: if there are no group columns and the table on which
+                    // we compute has no projected columns in the output, we make one up a group
+                    // column by adding an extra projection node
+                    let proj_name = format!("{}_prj_hlpr", agg_node_name);
+                    let proj = self.plan_projection_helper(&proj_name, computed_col)?;
+                    func_nodes.push(proj.clone());
+
+                    let bogo_group_col = Column::from(format!("{}.grp", proj_name).as_str());
+                    (vec![bogo_group_col], Some(proj))
+                } else {
+                    (proj_cols_from_target_table.clone(), None)
+                };
+                let n = self.plan_function(&agg_node_name,
+                                   computed_col,
+                                   group_cols.as_slice(),
+                                   parent)?;
+                func_nodes.push(n);
+                i += 1;
+            }
+
+            // HAVING conditions are evaluated over the output of the grouped operator
+            if !computed_cols_cgn.predicates.is_empty() {
+                if prev_join.is_some() {
+                    return Err(String::from("HAVING is not supported in queries with joins"));
+                }
+                let grouped = func_nodes.pop().unwrap();
+                let n = self.plan_having(grouped, &computed_cols_cgn.predicates)?;
+                func_nodes.push(n);
+            }
+        }
+
+        // 3. Generate leaf views that expose the query result
+        let final_node = if let Some(j) = prev_join {
+            j
+        } else if !func_nodes.is_empty() {
+            // XXX(malte): This won't work if (a) there are multiple function nodes in the
+            // query, or (b) computed columns are used within JOIN clauses
+            assert!(func_nodes.len() <= 2);
+            func_nodes.pop().unwrap()
+        } else {
+            assert!(filter_nodes.len() == 1);
+            filter_nodes.values().next().unwrap().clone()
+        };
+        // conditions on subqueries are evaluated over the complete results, since their columns
+        // may come from any of the tables involved
+        let mut final_node = final_node;
+        for cond in &qg.subqueries {
+            final_node = self.plan_semijoin(&format!("q_{:x}_n{}", hash, i), cond, final_node)?;
+            i += 1;
+        }
+        // as are columns computed by subqueries
+        for sc in &qg.subquery_columns {
+            final_node = self.plan_subquery_column(&format!("q_{:x}_n{}", hash, i),
+                                                   sc,
+                                                   final_node)?;
+            i += 1;
+        }
+        let final_node = match qg.order {
+            Some(ref order) => {
+                self.plan_topk(&format!("q_{:x}_n{}", hash, i), &qg, order, final_node)?
+            }
+            None => final_node,
+        };
+        let projected_columns: Vec<&Column> = sorted_rels.iter()
+            .flat_map(|s| qg.relations[*s].columns.iter())
+            .collect();
+        let mut projected_column_ids = projected_columns.iter()
+            .map(|c| self.field_to_columnid(&final_node, &c.name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut fields: Vec<_> = projected_columns.iter().map(|c| c.name.clone()).collect();
+        // the columns computed by subqueries are the last ones of the node that added them
+        let computed = self.fields_for(&final_node)?.len() - qg.subquery_columns.len();
+        for (i, sc) in qg.subquery_columns.iter().enumerate() {
+            projected_column_ids.push(computed + i);
+            fields.push(sc.name.clone());
+        }
+        let leaf = self.add(String::from(name),
+                            fields,
+                            PlanOp::Permute {
+                                parent: final_node,
+                                columns: projected_column_ids,
+                            });
+
+        // We always materialize leaves of queries (at least currently)
+        let key = self.reader_key(&qg, &leaf)?;
+        Ok((leaf, Some(key), Some(qg)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom_sql::parser::parse_query;
+    use super::*;
+
+    fn plan(catalog: &mut Catalog, q: &str) -> QueryPlan {
+        let plan = plan_query(catalog, parse_query(q).unwrap(), None).unwrap();
+        catalog.register(&plan);
+        plan
+    }
+
+    #[test]
+    fn it_plans_base_tables() {
+        let mut catalog = Catalog::new();
+        let p = plan(&mut catalog, "INSERT INTO users (id, name) VALUES (?, ?);");
+        assert_eq!(p.name, "users");
+        assert_eq!(p.leaf, "users");
+        assert_eq!(p.nodes,
+                   vec![PlanNode {
+                            name: "users".into(),
+                            fields: vec!["id".into(), "name".into()],
+                            op: PlanOp::Base { primary_key: None },
+                        }]);
+        assert_eq!(catalog.fields("users"),
+                   Some(&[String::from("id"), String::from("name")][..]));
+
+        // planning the same table again adds nothing
+        let p = plan(&mut catalog, "INSERT INTO users (id, name) VALUES (?, ?);");
+        assert!(p.nodes.is_empty());
+    }
+
+    #[test]
+    fn it_plans_selections() {
+        let mut catalog = Catalog::new();
+        plan(&mut catalog, "INSERT INTO users (id, name) VALUES (?, ?);");
+
+        let p = plan(&mut catalog, "SELECT users.name FROM users WHERE users.id = ?;");
+        // a filter/project node for users, and a leaf keyed on the parameter column
+        assert_eq!(p.nodes.len(), 2);
+        assert_eq!(p.leaf, p.name);
+        assert_eq!(p.nodes[1].fields, vec![String::from("name"), String::from("id")]);
+        assert_eq!(p.reader_key, Some(vec![1]));
+        match p.nodes[1].op {
+            PlanOp::Permute { ref parent, .. } => assert_eq!(parent, &p.nodes[0].name),
+            _ => unreachable!(),
+        }
+
+        // the same query should be reused outright
+        let p2 = plan(&mut catalog, "SELECT users.name FROM users WHERE users.id = ?;");
+        assert!(p2.nodes.is_empty());
+        assert_eq!(p2.leaf, p.leaf);
+        assert_eq!(p2.reader_key, None);
+    }
+
+    #[test]
+    fn it_plans_joins() {
+        let mut catalog = Catalog::new();
+        plan(&mut catalog, "INSERT INTO a (x, y) VALUES (?, ?);");
+        plan(&mut catalog, "INSERT INTO b (x, z) VALUES (?, ?);");
+
+        let p = plan(&mut catalog, "SELECT a.y, b.z FROM a, b WHERE a.x = b.x;");
+        let joins: Vec<_> = p.nodes
+            .iter()
+            .filter_map(|n| match n.op {
+                PlanOp::Join { ref left_groups, ref right_groups, .. } => {
+                    Some((left_groups.clone(), right_groups.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(joins, vec![(vec![0, 1], vec![0, 1])]);
+    }
+
+    #[test]
+    fn it_plans_top_k() {
+        let mut catalog = Catalog::new();
+        plan(&mut catalog,
+             "INSERT INTO articles (id, author, score) VALUES (?, ?, ?);");
+
+        let topk = |p: &QueryPlan| -> Vec<(Vec<usize>, usize, usize, bool)> {
+            p.nodes
+                .iter()
+                .filter_map(|n| match n.op {
+                    PlanOp::TopK { ref group_by, order, k, descending, .. } => {
+                        Some((group_by.clone(), order, k, descending))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // the ordering column is carried through, and results are kept for every author
+        let p = plan(&mut catalog,
+                     "SELECT articles.id FROM articles WHERE articles.author = ? \
+                      ORDER BY articles.score DESC LIMIT 3;");
+        assert_eq!(topk(&p), vec![(vec![1], 2, 3, true)]);
+        assert_eq!(p.nodes.last().unwrap().fields,
+                   vec![String::from("id"), String::from("author"), String::from("score")]);
+        assert_eq!(p.reader_key, Some(vec![1]));
+
+        // without parameters, all results form a single group
+        let p = plan(&mut catalog,
+                     "SELECT articles.id, articles.score FROM articles \
+                      ORDER BY articles.score LIMIT 10;");
+        assert_eq!(topk(&p), vec![(vec![2], 1, 10, false)]);
+        assert_eq!(p.nodes.last().unwrap().fields,
+                   vec![String::from("id"), String::from("score")]);
+    }
+
+    #[test]
+    fn it_plans_having() {
+        use ops::join::Comparison;
+        use ops::predicate::{Operand, Predicate};
+
+        let mut catalog = Catalog::new();
+        plan(&mut catalog, "INSERT INTO votes (aid, uid) VALUES (?, ?);");
+
+        let p = plan(&mut catalog,
+                     "SELECT votes.aid, COUNT(votes.uid) AS votes FROM votes GROUP BY votes.aid \
+                      HAVING votes > 5;");
+        // the condition is evaluated over the output of the aggregation
+        let grouped = p.nodes
+            .iter()
+            .position(|n| match n.op {
+                PlanOp::Grouped { .. } => true,
+                _ => false,
+            })
+            .unwrap();
+        match p.nodes[grouped + 1].op {
+            PlanOp::Predicate { ref parent, ref predicate } => {
+                assert_eq!(parent, &p.nodes[grouped].name);
+                assert_eq!(*predicate,
+                           Predicate::Compare(1, Comparison::Greater, Operand::Literal(5.into())));
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(p.nodes.last().unwrap().fields,
+                   vec![String::from("aid"), String::from("votes")]);
+    }
+
+    #[test]
+    fn it_plans_semi_joins() {
+        use flow::sql::query_graph::SubqueryCondition;
+        use nom_sql::Column;
+
+        let mut catalog = Catalog::new();
+        plan(&mut catalog,
+             "INSERT INTO articles (id, author, title) VALUES (?, ?, ?);");
+        plan(&mut catalog, "INSERT INTO votes (aid, uid) VALUES (?, ?);");
+        let voted = plan(&mut catalog, "SELECT votes.aid FROM votes;");
+
+        // SELECT articles.title FROM articles WHERE id NOT IN (SELECT votes.aid FROM votes);
+        let q = parse_query("SELECT articles.title FROM articles;").unwrap();
+        let cond = SubqueryCondition {
+            column: Column::from("id"),
+            view: voted.leaf.clone(),
+            view_column: String::from("aid"),
+            negated: true,
+        };
+        let p = plan_query_with_subqueries(&catalog, q, None, vec![cond], vec![]).unwrap();
+
+        // the tested column is carried through to the semi-join
+        let semi = p.nodes
+            .iter()
+            .position(|n| match n.op {
+                PlanOp::SemiJoin { .. } => true,
+                _ => false,
+            })
+            .unwrap();
+        match p.nodes[semi].op {
+            PlanOp::SemiJoin { ref left, ref right, on, anti } => {
+                assert_eq!(left, &p.nodes[semi - 1].name);
+                assert_eq!(right, &voted.leaf);
+                assert_eq!(on, (1, 0));
+                assert!(anti);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(p.nodes.last().unwrap().fields,
+                   vec![String::from("title"), String::from("id")]);
+
+        // the same query without the condition computes something else
+        let q = parse_query("SELECT articles.title FROM articles;").unwrap();
+        let other = plan_query(&catalog, q, None).unwrap();
+        assert!(other.nodes.iter().all(|n| match n.op {
+            PlanOp::SemiJoin { .. } => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn it_plans_subquery_columns() {
+        use flow::sql::query_graph::SubqueryColumn;
+        use nom_sql::Column;
+
+        let mut catalog = Catalog::new();
+        plan(&mut catalog,
+             "INSERT INTO articles (id, author, title) VALUES (?, ?, ?);");
+        plan(&mut catalog, "INSERT INTO votes (aid, uid) VALUES (?, ?);");
+        let counted = plan(&mut catalog,
+                           "SELECT votes.aid, COUNT(votes.uid) AS votes FROM votes GROUP BY \
+                            votes.aid;");
+
+        // SELECT articles.title, (SELECT COUNT(votes.uid) FROM votes WHERE votes.aid = id) AS n
+        //   FROM articles;
+        let q = parse_query("SELECT articles.title FROM articles;").unwrap();
+        let sc = SubqueryColumn {
+            column: Column::from("id"),
+            view: counted.leaf.clone(),
+            view_column: String::from("aid"),
+            value_column: String::from("votes"),
+            name: String::from("n"),
+        };
+        let p = plan_query_with_subqueries(&catalog, q, None, vec![], vec![sc]).unwrap();
+
+        // the looked-up column is carried through to a left join with the subquery's view
+        let join = p.nodes
+            .iter()
+            .position(|n| match n.op {
+                PlanOp::Join { .. } => true,
+                _ => false,
+            })
+            .unwrap();
+        let votes = catalog.fields(&counted.leaf)
+            .unwrap()
+            .iter()
+            .position(|f| f == "votes")
+            .unwrap();
+        match p.nodes[join].op {
+            PlanOp::Join { ref left, ref right, ref emit, ref left_groups, left_outer, .. } => {
+                assert_eq!(left, &p.nodes[join - 1].name);
+                assert_eq!(right, &counted.leaf);
+                assert_eq!(emit,
+                           &vec![(left.clone(), 0), (left.clone(), 1), (right.clone(), votes)]);
+                assert_eq!(left_groups, &vec![0, 1]);
+                assert!(left_outer);
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(p.nodes.last().unwrap().fields,
+                   vec![String::from("title"), String::from("id"), String::from("n")]);
+    }
+
+    #[test]
+    fn it_removes_queries() {
+        let mut catalog = Catalog::new();
+        plan(&mut catalog, "INSERT INTO users (id, name) VALUES (?, ?);");
+        let first = plan(&mut catalog, "SELECT id, name FROM users WHERE users.id = ?;");
+        // the same query with a different parameter only adds an identity node
+        let second = plan(&mut catalog, "SELECT id, name FROM users WHERE users.name = ?;");
+        assert_eq!(second.nodes.len(), 1);
+        let mut views = catalog.views();
+        views.sort();
+        assert_eq!(views, vec![first.name.as_str(), second.name.as_str()]);
+
+        // the second query still needs the nodes of the first
+        assert_eq!(catalog.remove_query(&first.name), Ok(vec![]));
+        assert_eq!(catalog.views(), vec![second.name.as_str()]);
+        assert!(catalog.fields(&first.leaf).is_some());
+
+        let mut removed: Vec<_> = first.nodes.iter().map(|n| n.name.clone()).collect();
+        removed.push(second.leaf.clone());
+        assert_eq!(catalog.remove_query(&second.name), Ok(removed));
+        assert!(catalog.views().is_empty());
+        assert!(catalog.fields(&first.leaf).is_none());
+        assert!(catalog.fields("users").is_some());
+
+        assert!(catalog.remove_query(&second.name).is_err());
+    }
+
+    #[test]
+    fn it_adds_columns() {
+        let mut catalog = Catalog::new();
+        plan(&mut catalog, "INSERT INTO users (id, name) VALUES (?, ?);");
+        let old = plan(&mut catalog, "SELECT id, name FROM users WHERE users.id = ?;");
+
+        assert_eq!(catalog.add_column("users", "karma"), Ok(2));
+        assert_eq!(catalog.table_fields("users").unwrap(), &["id", "name", "karma"]);
+        assert!(catalog.add_column("users", "name").is_err());
+        assert!(catalog.add_column("posts", "title").is_err());
+
+        // the new column can be selected, and the old query is unaffected
+        let new = plan(&mut catalog, "SELECT id, karma FROM users WHERE users.id = ?;");
+        assert_eq!(catalog.fields(&new.leaf).unwrap(), &["id", "karma"]);
+        assert_eq!(catalog.fields(&old.leaf).unwrap(), &["id", "name"]);
+    }
+
+    #[test]
+    fn it_rejects_unknown_views() {
+        let catalog = Catalog::new();
+        let q = parse_query("SELECT users.name FROM users;").unwrap();
+        assert!(plan_query(&catalog, q, None).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, FieldExpression, Operator};
-use nom_sql::SelectStatement;
+use nom_sql::{OrderType, SelectStatement};
 
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
@@ -22,10 +22,49 @@ pub enum QueryGraphEdge {
     GroupBy(Vec<Column>),
 }
 
+/// An `ORDER BY` on a single column combined with a `LIMIT`, which keeps only the first `limit`
+/// results for each set of query parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryGraphOrder {
+    pub column: Column,
+    pub descending: bool,
+    pub limit: usize,
+}
+
+/// An `IN` or `EXISTS` condition on a subquery, whose results are held by the view `view`. Only
+/// the records whose `column` is (or, if `negated`, is not) among the values of `view_column` in
+/// that view are part of the query's results.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubqueryCondition {
+    pub column: Column,
+    pub view: String,
+    pub view_column: String,
+    pub negated: bool,
+}
+
+/// A column computed by a scalar subquery, whose results are held by the view `view` with one row
+/// for each value of `view_column`. The query's records are extended with the `value_column` of
+/// the row whose `view_column` equals their `column`, or with `NULL` if there is no such row, and
+/// the computed column is called `name`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubqueryColumn {
+    pub column: Column,
+    pub view: String,
+    pub view_column: String,
+    pub value_column: String,
+    pub name: String,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct QueryGraph {
     pub relations: HashMap<String, QueryGraphNode>,
     pub edges: HashMap<(String, String), QueryGraphEdge>,
+    pub order: Option<QueryGraphOrder>,
+    pub subqueries: Vec<SubqueryCondition>,
+    pub subquery_columns: Vec<SubqueryColumn>,
+    /// The columns compared against the query's placeholders, in the order the placeholders
+    /// appear in the query.
+    pub parameters: Vec<Column>,
 }
 
 impl QueryGraph {
@@ -33,18 +72,54 @@ impl QueryGraph {
         QueryGraph {
             relations: HashMap::new(),
             edges: HashMap::new(),
+            order: None,
+            subqueries: Vec::new(),
+            subquery_columns: Vec::new(),
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Add conditions on subqueries to the query. The columns they test must be carried through
+    /// to the point where the conditions are evaluated, so they are projected if they are not
+    /// already.
+    pub fn add_subqueries(&mut self, conditions: &[SubqueryCondition]) -> Result<(), String> {
+        for cond in conditions {
+            self.carry(&cond.column, &cond.view)?;
+            self.subqueries.push(cond.clone());
+        }
+        Ok(())
+    }
+
+    /// Add columns computed by scalar subqueries to the query. Like the columns tested by
+    /// conditions on subqueries, the columns they are looked up by are projected if they are not
+    /// already.
+    pub fn add_subquery_columns(&mut self, columns: &[SubqueryColumn]) -> Result<(), String> {
+        for sc in columns {
+            self.carry(&sc.column, &sc.view)?;
+            self.subquery_columns.push(sc.clone());
+        }
+        Ok(())
+    }
+
+    /// Project `column`, which is compared against the subquery whose results are held by `view`.
+    fn carry(&mut self, column: &Column, view: &str) -> Result<(), String> {
+        let table = column.table.clone().unwrap_or_default();
+        match self.relations.get_mut(&table) {
+            Some(rel) => {
+                if !rel.columns.contains(column) {
+                    rel.columns.push(column.clone());
+                }
+                Ok(())
+            }
+            None => Err(format!("cannot test column {:?} against subquery {}", column, view)),
         }
     }
 
     /// Returns the set of columns on which this query is parameterized. They can come from
-    /// multiple tables involved in the query.
-    pub fn parameters<'a>(&'a self) -> Vec<&'a Column> {
-        self.relations
-            .values()
-            .fold(Vec::new(), |mut acc: Vec<&'a Column>, ref qgn| {
-                acc.extend(qgn.parameters.iter());
-                acc
-            })
+    /// multiple tables involved in the query, and are given in the order of the placeholders
+    /// they are compared against.
+    pub fn parameters(&self) -> Vec<&Column> {
+        self.parameters.iter().collect()
     }
 
     /// Used to get a concise signature for a query graph. The `hash` member can be used to check
@@ -100,6 +175,19 @@ impl QueryGraph {
             }
         }
 
+        if let Some(ref order) = self.order {
+            attrs_vec.push(&order.column);
+            attrs.insert(&order.column);
+        }
+        for sq in &self.subqueries {
+            attrs_vec.push(&sq.column);
+            attrs.insert(&sq.column);
+        }
+        for sc in &self.subquery_columns {
+            attrs_vec.push(&sc.column);
+            attrs.insert(&sc.column);
+        }
+
         // Compute attributes part of hash
         attrs_vec.sort();
         for a in &attrs_vec {
@@ -112,6 +200,22 @@ impl QueryGraph {
             c.hash(&mut hasher);
         }
 
+        // Queries that keep different numbers of results, or keep them in a different order,
+        // compute different results
+        if let Some(ref order) = self.order {
+            order.descending.hash(&mut hasher);
+            order.limit.hash(&mut hasher);
+        }
+
+        // as do queries whose results are restricted by different subqueries
+        for sq in &self.subqueries {
+            sq.hash(&mut hasher);
+        }
+        // or extended with different columns computed by them
+        for sc in &self.subquery_columns {
+            sc.hash(&mut hasher);
+        }
+
         QuerySignature {
             relations: rels,
             attributes: attrs,
@@ -120,6 +224,28 @@ impl QueryGraph {
     }
 }
 
+/// Whether the given condition only compares columns to literals, combined with AND and OR. The
+/// tables of all the compared columns that name one are added to `tables`.
+pub fn only_literal_comparisons(ce: &ConditionExpression, tables: &mut HashSet<String>) -> bool {
+    match *ce {
+        ConditionExpression::LogicalOp(ref ct) => {
+            (ct.operator == Operator::And || ct.operator == Operator::Or) &&
+            ct.left.iter().chain(ct.right.iter()).all(|c| only_literal_comparisons(c, tables))
+        }
+        ConditionExpression::ComparisonOp(ref ct) => {
+            match (ct.left.as_ref().map(|l| &**l), ct.right.as_ref().map(|r| &**r)) {
+                (Some(&ConditionExpression::Base(ConditionBase::Field(ref f))),
+                 Some(&ConditionExpression::Base(ConditionBase::Literal(_)))) => {
+                    tables.extend(f.table.clone());
+                    true
+                }
+                _ => false,
+            }
+        }
+        ConditionExpression::Base(_) => false,
+    }
+}
+
 // 1. Extract any predictates with placeholder parameters. We push these down to the edge
 //    nodes, since we cannot instantiate the parameters inside the data flow graph (except for
 //    non-materialized nodes).
@@ -134,6 +260,18 @@ fn classify_conditionals(ce: &ConditionExpression,
     use std::cmp::Ordering;
 
     match *ce {
+        ConditionExpression::LogicalOp(ref ct) if ct.operator == Operator::Or => {
+            // disjunction. if it only concerns a single table, it becomes a local predicate that
+            // is evaluated as a whole; otherwise, it must be evaluated after the joins.
+            let mut tables = HashSet::new();
+            if only_literal_comparisons(ce, &mut tables) && tables.len() == 1 {
+                local.entry(tables.into_iter().next().unwrap())
+                    .or_insert(Vec::new())
+                    .push(ct.clone());
+            } else {
+                global.push(ct.clone());
+            }
+        }
         ConditionExpression::LogicalOp(ref ct) => {
             // conjunction, check both sides (which must be selection predicates or
             // atomatic selection predicates)
@@ -261,6 +399,12 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                               &mut global_predicates,
                               &mut query_parameters);
 
+        // we cannot yet evaluate predicates after the joins
+        if !global_predicates.is_empty() {
+            return Err(format!("conditions spanning multiple tables are not supported: {:?}",
+                               global_predicates));
+        }
+
         // Now we're ready to build the query graph
         // 1. Add local predicates for each node that has them
         for (rel, preds) in local_predicates {
@@ -336,6 +480,7 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     rel.parameters.push(column.clone());
                 }
             }
+            qg.parameters.push(column);
         }
     }
 
@@ -349,8 +494,6 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
                     None => (),  // we've already dealt with this column as part of some relation
                     Some(_) => {
                         // add a special node representing the computed columns
-                        // TODO(malte): the predicates here should probably reflect HAVING
-                        // conditions, if any are present
                         let mut n = new_node(String::from("computed_columns"), vec![], st);
                         n.columns.push(column.clone());
                         qg.relations.insert(String::from("computed_columns"), n);
@@ -377,5 +520,60 @@ pub fn to_query_graph(st: &SelectStatement) -> Result<QueryGraph, String> {
         }
     }
 
+    // 5. HAVING conditions are evaluated over the computed columns, so they become predicates of
+    //    the node representing those. Any aggregations they use must have been resolved to the
+    //    computed columns in the field list.
+    if let Some(having) = st.group_by.as_ref().and_then(|gb| gb.having.as_ref()) {
+        let ct = match *having {
+            ConditionExpression::ComparisonOp(ref ct) |
+            ConditionExpression::LogicalOp(ref ct) => ct.clone(),
+            ConditionExpression::Base(_) => {
+                return Err(format!("invalid HAVING condition {:?}", having))
+            }
+        };
+        if ct.contained_columns().iter().any(|c| c.function.is_some()) {
+            return Err(String::from("HAVING can only use aggregations that are also selected"));
+        }
+        match qg.relations.get_mut("computed_columns") {
+            Some(n) => n.predicates.push(ct),
+            None => return Err(String::from("HAVING requires an aggregation")),
+        }
+    }
+
+    // 6. Record the ordering of a query that keeps only its first few results. The ordering
+    //    column must be carried through to the leaf, so it is projected if it is not already.
+    match (st.order.as_ref(), st.limit.as_ref()) {
+        (None, None) => (),
+        (Some(order), Some(limit)) => {
+            if order.columns.len() != 1 || limit.offset != 0 || limit.limit == 0 {
+                return Err(format!("cannot keep the first {} results ordered by {:?}",
+                                   limit.limit,
+                                   order.columns));
+            }
+            let column = order.columns[0].clone();
+            // columns without a table refer to computed columns, which are always projected
+            if let Some(ref table) = column.table {
+                match qg.relations.get_mut(table) {
+                    Some(rel) => {
+                        if !rel.columns.contains(&column) {
+                            rel.columns.push(column.clone());
+                        }
+                    }
+                    None => return Err(format!("cannot order by column of table {}", table)),
+                }
+            }
+            qg.order = Some(QueryGraphOrder {
+                column: column,
+                descending: match order.order {
+                    OrderType::OrderAscending => false,
+                    OrderType::OrderDescending => true,
+                },
+                limit: limit.limit as usize,
+            });
+        }
+        (Some(_), None) => return Err(String::from("ORDER BY is only supported with a LIMIT")),
+        (None, Some(_)) => return Err(String::from("LIMIT is only supported with an ORDER BY")),
+    }
+
     Ok(qg)
 }
@@ -0,0 +1,705 @@
+//! Extraction of subqueries, i.e., derived tables in the `FROM` clause of a query, `IN` and
+//! `EXISTS` conditions in its `WHERE` clause, and scalar subqueries in its field list.
+//!
+//! The SQL parser does not understand subqueries, so they are taken out of the query text before
+//! it is parsed. Each one is incorporated as an internal view of its own. The outer query is then
+//! rewritten to select from the view of a derived table under the derived table's alias, while
+//! conditions on subqueries are evaluated by semi-joins against their views, and the columns
+//! computed by scalar subqueries are added by left joins against theirs.
+
+use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, FieldExpression,
+              GroupByClause, Operator, SelectStatement, SqlQuery};
+
+/// Whether the given byte can be part of an identifier or keyword.
+fn is_ident(b: u8) -> bool {
+    (b as char).is_alphanumeric() || b == b'_'
+}
+
+/// The index of the first byte at or after `i` that is not whitespace.
+fn skip_whitespace(q: &[u8], mut i: usize) -> usize {
+    while i < q.len() && (q[i] as char).is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// The index just past the end of the identifier or keyword starting at `i`.
+fn ident_end(q: &[u8], mut i: usize) -> usize {
+    while i < q.len() && is_ident(q[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// The index just past the closing quote of the quoted string starting at `i`. Quotes are escaped
+/// by doubling them.
+fn skip_quoted(q: &[u8], i: usize) -> Result<usize, String> {
+    let quote = q[i];
+    let mut j = i + 1;
+    while j < q.len() {
+        if q[j] == quote {
+            if j + 1 < q.len() && q[j + 1] == quote {
+                j += 2;
+                continue;
+            }
+            return Ok(j + 1);
+        }
+        j += 1;
+    }
+    Err(format!("unterminated string starting at byte {}", i))
+}
+
+/// The index of the parenthesis that closes the one at `i`.
+fn closing_parenthesis(q: &[u8], i: usize) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut j = i;
+    while j < q.len() {
+        match q[j] {
+            b'\'' | b'"' | b'`' => {
+                j = skip_quoted(q, j)?;
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(j);
+                }
+            }
+            _ => (),
+        }
+        j += 1;
+    }
+    Err(format!("unbalanced parenthesis at byte {}", i))
+}
+
+/// The start and end of the token that follows index `i`, skipping any whitespace. Tokens are
+/// identifiers or keywords, quoted strings, parenthesised groups, and single other characters.
+/// At the end of the query, both are `q.len()`.
+fn next_token(q: &[u8], i: usize) -> Result<(usize, usize), String> {
+    let start = skip_whitespace(q, i);
+    if start == q.len() {
+        return Ok((start, start));
+    }
+    let end = match q[start] {
+        b'\'' | b'"' | b'`' => skip_quoted(q, start)?,
+        b'(' => closing_parenthesis(q, start)? + 1,
+        b if is_ident(b) => ident_end(q, start),
+        _ => start + 1,
+    };
+    Ok((start, end))
+}
+
+/// If the token `t` is a parenthesised `SELECT` statement, the text of that statement.
+fn as_subquery(query: &str, t: (usize, usize)) -> Option<&str> {
+    let q = query.as_bytes();
+    if q[t.0] != b'(' {
+        return None;
+    }
+    let start = skip_whitespace(q, t.0 + 1);
+    if query[start..ident_end(q, start)].to_lowercase() == "select" {
+        Some(query[t.0 + 1..t.1 - 1].trim())
+    } else {
+        None
+    }
+}
+
+/// Whether any of the tokens between `start` and `end` is, or contains, a subquery.
+fn contains_subquery(query: &str, start: usize, end: usize) -> Result<bool, String> {
+    let q = query.as_bytes();
+    let mut i = start;
+    while i < end {
+        let t = next_token(q, i)?;
+        if t.0 >= end {
+            break;
+        }
+        if q[t.0] == b'(' &&
+           (as_subquery(query, t).is_some() || contains_subquery(query, t.0 + 1, t.1 - 1)?) {
+            return Ok(true);
+        }
+        i = t.1;
+    }
+    Ok(false)
+}
+
+/// Replace every derived table in `query` by a reference to a view with the same results.
+///
+/// `incorporate` is called with the text of each subquery (which may itself contain derived
+/// tables), and must return the name of the view that holds its results. Derived tables must be
+/// given an alias, which the outer query uses to refer to the view.
+pub fn expand_derived_tables<F>(query: &str, mut incorporate: F) -> Result<String, String>
+    where F: FnMut(&str) -> Result<String, String>
+{
+    let q = query.as_bytes();
+    let mut expanded = String::with_capacity(query.len());
+    // the part of the query up to this index has been copied to `expanded`
+    let mut copied = 0;
+    let mut in_from = false;
+    let mut i = 0;
+    while i < q.len() {
+        match q[i] {
+            b'\'' | b'"' | b'`' => i = skip_quoted(q, i)?,
+            b'(' => {
+                let end = closing_parenthesis(q, i)?;
+                let start = skip_whitespace(q, i + 1);
+                let subquery = query[start..ident_end(q, start)].to_lowercase() == "select";
+                if !(in_from && subquery) {
+                    // nothing in other parenthesised expressions affects the FROM clause
+                    i = end + 1;
+                    continue;
+                }
+
+                let view = incorporate(query[i + 1..end].trim())?;
+                let mut alias_start = skip_whitespace(q, end + 1);
+                if query[alias_start..ident_end(q, alias_start)].to_lowercase() == "as" {
+                    alias_start = skip_whitespace(q, alias_start + 2);
+                }
+                let alias_end = ident_end(q, alias_start);
+                if alias_end == alias_start {
+                    return Err(format!("derived table ({}) must have an alias",
+                                       query[i + 1..end].trim()));
+                }
+
+                expanded.push_str(&query[copied..i]);
+                expanded.push_str(&format!("{} AS {}", view, &query[alias_start..alias_end]));
+                copied = alias_end;
+                i = alias_end;
+            }
+            b if is_ident(b) => {
+                let end = ident_end(q, i);
+                match query[i..end].to_lowercase().as_str() {
+                    "from" => in_from = true,
+                    "where" | "group" | "having" | "order" | "limit" => in_from = false,
+                    _ => (),
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    expanded.push_str(&query[copied..]);
+    Ok(expanded)
+}
+
+/// A condition on a subquery in the `WHERE` clause of a query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subquery {
+    /// For `IN` conditions, the column whose values must be among the subquery's results. `EXISTS`
+    /// conditions instead test the column that the subquery is correlated with.
+    pub column: Option<String>,
+    /// The text of the subquery.
+    pub query: String,
+    /// Whether the condition is negated (`NOT IN` or `NOT EXISTS`).
+    pub negated: bool,
+}
+
+/// Match a conjunct of a `WHERE` clause, given as its tokens, against `[NOT] EXISTS (SELECT ...)`
+/// and `column [NOT] IN (SELECT ...)`.
+fn as_condition(query: &str, tokens: &[(usize, usize)]) -> Option<Subquery> {
+    let words: Vec<String> = tokens.iter().map(|&(s, e)| query[s..e].to_lowercase()).collect();
+    let n = words.len();
+    if n < 2 {
+        return None;
+    }
+    let subquery = match as_subquery(query, tokens[n - 1]) {
+        Some(sq) => String::from(sq),
+        None => return None,
+    };
+
+    if words[n - 2] == "exists" && n <= 3 {
+        if n == 3 && words[0] != "not" {
+            return None;
+        }
+        return Some(Subquery {
+            column: None,
+            query: subquery,
+            negated: n == 3,
+        });
+    }
+    if words[n - 2] == "in" {
+        let negated = n >= 3 && words[n - 3] == "not";
+        let column_end = if negated { n - 3 } else { n - 2 };
+        if column_end == 0 {
+            return None;
+        }
+        return Some(Subquery {
+            column: Some(String::from(&query[tokens[0].0..tokens[column_end - 1].1])),
+            query: subquery,
+            negated: negated,
+        });
+    }
+    None
+}
+
+/// Take the `IN` and `EXISTS` conditions on subqueries out of the `WHERE` clause of `query`.
+///
+/// Only conditions that are combined with the rest of the clause by `AND` are supported. The
+/// query is returned without them, along with the conditions in the order they appeared in.
+pub fn extract_conditions(query: &str) -> Result<(String, Vec<Subquery>), String> {
+    let q = query.as_bytes();
+
+    // find the extent of the WHERE clause
+    let mut clause = None;
+    let mut clause_end = q.len();
+    let mut i = 0;
+    loop {
+        let t = next_token(q, i)?;
+        if t.0 == q.len() {
+            break;
+        }
+        match query[t.0..t.1].to_lowercase().as_str() {
+            "where" if clause.is_none() => clause = Some(t),
+            "group" | "having" | "order" | "limit" | ";" if clause.is_some() => {
+                clause_end = t.0;
+                break;
+            }
+            _ => (),
+        }
+        i = t.1;
+    }
+    let clause = match clause {
+        Some(t) => t,
+        None => return Ok((String::from(query), vec![])),
+    };
+
+    // split it into conjuncts, and pick out the conditions on subqueries
+    let mut conjuncts = vec![vec![]];
+    let mut disjunction = false;
+    let mut i = clause.1;
+    loop {
+        let t = next_token(q, i)?;
+        if t.0 >= clause_end {
+            break;
+        }
+        match query[t.0..t.1].to_lowercase().as_str() {
+            "and" => conjuncts.push(vec![]),
+            word => {
+                disjunction = disjunction || word == "or";
+                conjuncts.last_mut().unwrap().push(t);
+            }
+        }
+        i = t.1;
+    }
+
+    let mut conditions = Vec::new();
+    let mut kept = Vec::new();
+    for tokens in conjuncts {
+        if tokens.is_empty() {
+            return Err(String::from("empty condition in WHERE clause"));
+        }
+        let (start, end) = (tokens[0].0, tokens[tokens.len() - 1].1);
+        match as_condition(query, &tokens) {
+            Some(cond) => conditions.push(cond),
+            None => {
+                if contains_subquery(query, start, end)? {
+                    return Err(format!("unsupported use of a subquery in condition {}",
+                                       &query[start..end]));
+                }
+                kept.push(&query[start..end]);
+            }
+        }
+    }
+    if conditions.is_empty() {
+        return Ok((String::from(query), vec![]));
+    }
+    if disjunction {
+        return Err(String::from("conditions on subqueries cannot be combined with OR"));
+    }
+
+    let mut rewritten = String::from(query[..clause.0].trim_right());
+    if !kept.is_empty() {
+        rewritten.push_str(" WHERE ");
+        rewritten.push_str(&kept.join(" AND "));
+    }
+    let rest = query[clause_end..].trim_left();
+    if !rest.is_empty() && is_ident(rest.as_bytes()[0]) {
+        rewritten.push(' ');
+    }
+    rewritten.push_str(rest);
+    Ok((rewritten, conditions))
+}
+
+/// A scalar subquery in the field list of a query, which computes one of the query's columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalarSubquery {
+    /// The text of the subquery.
+    pub query: String,
+    /// The name given to the computed column, if any.
+    pub alias: Option<String>,
+}
+
+/// Match a field of the field list, given as its tokens, against `(SELECT ...) [[AS] alias]`.
+fn as_scalar(query: &str, tokens: &[(usize, usize)]) -> Option<ScalarSubquery> {
+    let subquery = match as_subquery(query, tokens[0]) {
+        Some(sq) => String::from(sq),
+        None => return None,
+    };
+    let alias = match tokens.len() {
+        1 => None,
+        2 => Some(tokens[1]),
+        3 if query[tokens[1].0..tokens[1].1].to_lowercase() == "as" => Some(tokens[2]),
+        _ => return None,
+    };
+    if let Some((s, _)) = alias {
+        if !is_ident(query.as_bytes()[s]) {
+            return None;
+        }
+    }
+    Some(ScalarSubquery {
+        query: subquery,
+        alias: alias.map(|(s, e)| String::from(&query[s..e])),
+    })
+}
+
+/// Take the scalar subqueries out of the field list of `query`.
+///
+/// Each subquery must make up a field of its own, optionally followed by an alias, and the query
+/// must select at least one other field. The query is returned without them, along with the
+/// subqueries in the order they appeared in.
+pub fn extract_scalar_subqueries(query: &str) -> Result<(String, Vec<ScalarSubquery>), String> {
+    let q = query.as_bytes();
+    let select = next_token(q, 0)?;
+    if query[select.0..select.1].to_lowercase() != "select" {
+        return Ok((String::from(query), vec![]));
+    }
+
+    // split the field list, which ends at FROM, into its fields
+    let mut fields = vec![vec![]];
+    let mut i = select.1;
+    let list_end = loop {
+        let t = next_token(q, i)?;
+        if t.0 == q.len() {
+            break t.0;
+        }
+        match query[t.0..t.1].to_lowercase().as_str() {
+            "from" => break t.0,
+            "," => fields.push(vec![]),
+            _ => fields.last_mut().unwrap().push(t),
+        }
+        i = t.1;
+    };
+
+    let mut subqueries = Vec::new();
+    let mut kept = Vec::new();
+    for tokens in fields {
+        if tokens.is_empty() {
+            return Err(String::from("empty field in field list"));
+        }
+        let (start, end) = (tokens[0].0, tokens[tokens.len() - 1].1);
+        match as_scalar(query, &tokens) {
+            Some(sq) => subqueries.push(sq),
+            None => {
+                if contains_subquery(query, start, end)? {
+                    return Err(format!("unsupported use of a subquery in field {}",
+                                       &query[start..end]));
+                }
+                kept.push(&query[start..end]);
+            }
+        }
+    }
+    if subqueries.is_empty() {
+        return Ok((String::from(query), vec![]));
+    }
+    if kept.is_empty() {
+        return Err(String::from("queries must select at least one field that is not a subquery"));
+    }
+
+    let mut rewritten = String::from(&query[..select.1]);
+    rewritten.push(' ');
+    rewritten.push_str(&kept.join(", "));
+    rewritten.push(' ');
+    rewritten.push_str(query[list_end..].trim_left());
+    Ok((rewritten, subqueries))
+}
+
+/// If `ct` compares a column of the subquery's `tables` (given by name or alias) with one of the
+/// outer query, the column of the subquery and the outer column.
+fn correlation(ct: &ConditionTree, tables: &[String]) -> Result<Option<(Column, Column)>, String> {
+    let field = |side: &Option<Box<ConditionExpression>>| match side.as_ref().map(|s| &**s) {
+        Some(&ConditionExpression::Base(ConditionBase::Field(ref f))) => Some(f.clone()),
+        _ => None,
+    };
+    let outer = |c: &Column| c.table.as_ref().map_or(false, |t| !tables.contains(t));
+
+    let (l, r) = match (field(&ct.left), field(&ct.right)) {
+        (Some(l), Some(r)) => (l, r),
+        _ => return Ok(None),
+    };
+    let (inner, outer_column) = if outer(&r) && !outer(&l) {
+        (l, r)
+    } else if outer(&l) && !outer(&r) {
+        (r, l)
+    } else {
+        return Ok(None);
+    };
+    if ct.operator != Operator::Equal {
+        return Err(format!("subqueries can only be correlated by equality, not by {:?}",
+                           ct.operator));
+    }
+    Ok(Some((inner, outer_column)))
+}
+
+/// Split the correlation with the outer query off the condition `ce`, returning what remains of
+/// the condition (if anything) and the correlation (if one was found).
+fn split_correlation(ce: ConditionExpression,
+                     tables: &[String])
+                     -> Result<(Option<ConditionExpression>, Option<(Column, Column)>), String> {
+    match ce {
+        ConditionExpression::LogicalOp(ct) => {
+            if ct.operator != Operator::And {
+                return Ok((Some(ConditionExpression::LogicalOp(ct)), None));
+            }
+            let (l, lc) = split_correlation(*ct.left.unwrap(), tables)?;
+            let (r, rc) = split_correlation(*ct.right.unwrap(), tables)?;
+            let correlation = match (lc, rc) {
+                (Some(_), Some(_)) => {
+                    return Err(String::from("subqueries can only be correlated by a single \
+                                             equality"))
+                }
+                (lc, rc) => lc.or(rc),
+            };
+            let rest = match (l, r) {
+                (Some(l), Some(r)) => {
+                    Some(ConditionExpression::LogicalOp(ConditionTree {
+                        operator: Operator::And,
+                        left: Some(Box::new(l)),
+                        right: Some(Box::new(r)),
+                    }))
+                }
+                (l, r) => l.or(r),
+            };
+            Ok((rest, correlation))
+        }
+        ConditionExpression::ComparisonOp(ct) => {
+            match correlation(&ct, tables)? {
+                Some(c) => Ok((None, Some(c))),
+                None => Ok((Some(ConditionExpression::ComparisonOp(ct)), None)),
+            }
+        }
+        x => Ok((Some(x), None)),
+    }
+}
+
+/// Turn the subquery of an `EXISTS` condition into one that can be evaluated on its own.
+///
+/// The subquery must be correlated with the outer query by a single equality between one of its
+/// columns and a column of the outer query, which is removed from its `WHERE` clause. The
+/// returned query instead selects its own column of that equality, so that the condition holds
+/// for the records of the outer query whose column is among its results. That column and the
+/// column of the outer query are returned along with the query.
+pub fn decorrelate(q: SqlQuery) -> Result<(SqlQuery, Column, Column), String> {
+    let mut st = match q {
+        SqlQuery::Select(st) => st,
+        q => return Err(format!("{:?} is not a subquery", q)),
+    };
+    let (inner, outer) = match take_correlation(&mut st)? {
+        Some(c) => c,
+        None => {
+            return Err(String::from("EXISTS subqueries must be correlated with the outer query \
+                                     by an equality"))
+        }
+    };
+    st.fields = FieldExpression::Seq(vec![inner.clone()]);
+    Ok((SqlQuery::Select(st), inner, outer))
+}
+
+/// Turn a scalar subquery in the field list into one that can be evaluated on its own.
+///
+/// The subquery must select a single aggregation, and be correlated with the outer query like the
+/// subquery of an `EXISTS` condition. The returned query instead computes the aggregation grouped
+/// by its own column of that equality, which it selects along with the aggregation. The
+/// aggregation, that column, and the column of the outer query are returned along with the query.
+pub fn decorrelate_aggregate(q: SqlQuery) -> Result<(SqlQuery, Column, Column, Column), String> {
+    let mut st = match q {
+        SqlQuery::Select(st) => st,
+        q => return Err(format!("{:?} is not a subquery", q)),
+    };
+    let aggregation = match st.fields {
+        FieldExpression::Seq(ref fs) if fs.len() == 1 && fs[0].function.is_some() => fs[0].clone(),
+        _ => return Err(String::from("scalar subqueries must select a single aggregation")),
+    };
+    if st.group_by.is_some() {
+        return Err(String::from("scalar subqueries cannot have a GROUP BY clause"));
+    }
+    let (inner, outer) = match take_correlation(&mut st)? {
+        Some(c) => c,
+        None => {
+            return Err(String::from("scalar subqueries must be correlated with the outer query \
+                                     by an equality"))
+        }
+    };
+    st.fields = FieldExpression::Seq(vec![inner.clone(), aggregation.clone()]);
+    st.group_by = Some(GroupByClause {
+        columns: vec![inner.clone()],
+        having: None,
+    });
+    Ok((SqlQuery::Select(st), aggregation, inner, outer))
+}
+
+/// Take the correlation with the outer query out of the `WHERE` clause of the subquery `st`, and
+/// return the column of the subquery and the outer column that it compares, if there is one.
+fn take_correlation(st: &mut SelectStatement) -> Result<Option<(Column, Column)>, String> {
+    let tables: Vec<String> = st.tables
+        .iter()
+        .flat_map(|t| Some(t.name.clone()).into_iter().chain(t.alias.clone()))
+        .collect();
+
+    let (rest, correlation) = match st.where_clause.take() {
+        Some(ce) => split_correlation(ce, &tables)?,
+        None => (None, None),
+    };
+    st.where_clause = rest;
+    Ok(correlation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_expands_derived_tables() {
+        let mut subqueries = Vec::new();
+        let q = expand_derived_tables("SELECT a.title, users.name FROM (SELECT articles.author, \
+                                       articles.title FROM articles) AS a, users \
+                                       WHERE a.author = users.id;",
+                                      |sq| {
+                                          subqueries.push(String::from(sq));
+                                          Ok(format!("v{}", subqueries.len()))
+                                      })
+            .unwrap();
+        assert_eq!(q,
+                   "SELECT a.title, users.name FROM v1 AS a, users WHERE a.author = users.id;");
+        assert_eq!(subqueries,
+                   vec![String::from("SELECT articles.author, articles.title FROM articles")]);
+    }
+
+    #[test]
+    fn it_leaves_other_queries_alone() {
+        let q = "SELECT COUNT(votes.uid) AS votes FROM votes WHERE votes.x = '(SELECT';";
+        assert_eq!(expand_derived_tables(q, |_| unreachable!()), Ok(String::from(q)));
+    }
+
+    #[test]
+    fn it_requires_aliases() {
+        let q = "SELECT x.a FROM (SELECT t.a FROM t);";
+        assert!(expand_derived_tables(q, |_| Ok(String::from("v"))).is_err());
+    }
+
+    #[test]
+    fn it_extracts_conditions() {
+        let (q, conds) = extract_conditions("SELECT articles.title FROM articles WHERE \
+                                             articles.id IN (SELECT votes.aid FROM votes) AND \
+                                             articles.author = ? AND NOT EXISTS (SELECT * FROM \
+                                             flags WHERE flags.aid = articles.id);")
+            .unwrap();
+        assert_eq!(q, "SELECT articles.title FROM articles WHERE articles.author = ?;");
+        assert_eq!(conds,
+                   vec![Subquery {
+                            column: Some(String::from("articles.id")),
+                            query: String::from("SELECT votes.aid FROM votes"),
+                            negated: false,
+                        },
+                        Subquery {
+                            column: None,
+                            query: String::from("SELECT * FROM flags WHERE flags.aid = \
+                                                 articles.id"),
+                            negated: true,
+                        }]);
+
+        // the WHERE clause goes away entirely if nothing else is left in it
+        let (q, conds) = extract_conditions("SELECT a.x FROM a WHERE a.x NOT IN (SELECT b.x FROM \
+                                             b) ORDER BY a.x LIMIT 3;")
+            .unwrap();
+        assert_eq!(q, "SELECT a.x FROM a ORDER BY a.x LIMIT 3;");
+        assert_eq!(conds.len(), 1);
+        assert!(conds[0].negated);
+    }
+
+    #[test]
+    fn it_leaves_queries_without_conditions_alone() {
+        let q = "SELECT t.a FROM t WHERE t.b = '(SELECT' AND t.c = ?;";
+        assert_eq!(extract_conditions(q), Ok((String::from(q), vec![])));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_conditions() {
+        assert!(extract_conditions("SELECT t.a FROM t WHERE t.b = 1 OR t.a IN (SELECT u.a FROM \
+                                    u);")
+            .is_err());
+        assert!(extract_conditions("SELECT t.a FROM t WHERE (t.a IN (SELECT u.a FROM u));")
+            .is_err());
+    }
+
+    #[test]
+    fn it_decorrelates_exists() {
+        use nom_sql::parser::parse_query;
+
+        let q = parse_query("SELECT * FROM flags WHERE flags.aid = articles.id AND flags.kind = \
+                             'spam';")
+            .unwrap();
+        let (q, inner, outer) = decorrelate(q).unwrap();
+        assert_eq!(inner, Column::from("flags.aid"));
+        assert_eq!(outer, Column::from("articles.id"));
+        assert_eq!(q,
+                   parse_query("SELECT flags.aid FROM flags WHERE flags.kind = 'spam';").unwrap());
+
+        // without a correlation, the subquery's result would be the same for every record
+        let q = parse_query("SELECT * FROM flags WHERE flags.kind = 'spam';").unwrap();
+        assert!(decorrelate(q).is_err());
+    }
+
+    #[test]
+    fn it_extracts_scalar_subqueries() {
+        let (q, sqs) = extract_scalar_subqueries("SELECT a.*, (SELECT COUNT(*) FROM vote v WHERE \
+                                                  v.id = a.id) AS votes, a.title FROM article \
+                                                  a WHERE a.id = ?;")
+            .unwrap();
+        assert_eq!(q, "SELECT a.*, a.title FROM article a WHERE a.id = ?;");
+        assert_eq!(sqs,
+                   vec![ScalarSubquery {
+                            query: String::from("SELECT COUNT(*) FROM vote v WHERE v.id = a.id"),
+                            alias: Some(String::from("votes")),
+                        }]);
+
+        let (_, sqs) = extract_scalar_subqueries("SELECT a.id, (SELECT MAX(v.t) FROM vote v \
+                                                  WHERE v.id = a.id) FROM article a;")
+            .unwrap();
+        assert_eq!(sqs[0].alias, None);
+
+        // queries without scalar subqueries are left alone
+        let q = "SELECT a.id, COUNT(a.x) FROM a WHERE a.y IN (SELECT b.y FROM b);";
+        assert_eq!(extract_scalar_subqueries(q), Ok((String::from(q), vec![])));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_scalar_subqueries() {
+        // the subquery must be a field of its own
+        assert!(extract_scalar_subqueries("SELECT a.x, (SELECT COUNT(*) FROM b) + 1 FROM a;")
+            .is_err());
+        // and cannot be the only one
+        assert!(extract_scalar_subqueries("SELECT (SELECT COUNT(*) FROM b) FROM a;").is_err());
+    }
+
+    #[test]
+    fn it_decorrelates_aggregates() {
+        use nom_sql::parser::parse_query;
+
+        let q = parse_query("SELECT COUNT(vote.uid) AS votes FROM vote WHERE vote.id = article.id \
+                             AND vote.up = 1;")
+            .unwrap();
+        let (q, aggregation, inner, outer) = decorrelate_aggregate(q).unwrap();
+        assert_eq!(aggregation.name, "votes");
+        assert_eq!(inner, Column::from("vote.id"));
+        assert_eq!(outer, Column::from("article.id"));
+        assert_eq!(q,
+                   parse_query("SELECT vote.id, COUNT(vote.uid) AS votes FROM vote WHERE \
+                                vote.up = 1 GROUP BY vote.id;")
+                       .unwrap());
+
+        // the subquery must compute a single value for every record of the outer query
+        let q = parse_query("SELECT vote.uid FROM vote WHERE vote.id = article.id;").unwrap();
+        assert!(decorrelate_aggregate(q).is_err());
+        let q = parse_query("SELECT COUNT(vote.uid) AS votes FROM vote;").unwrap();
+        assert!(decorrelate_aggregate(q).is_err());
+    }
+}
@@ -1,18 +1,25 @@
 use nom_sql::parser as sql_parser;
-use flow::{NodeAddress, Migration};
-use flow::sql::query_graph::{QueryGraph, QueryGraphEdge, QueryGraphNode, to_query_graph};
-use nom_sql::{Column, ConditionBase, ConditionExpression, ConditionTree, Operator, TableKey,
-              SqlQuery};
-use nom_sql::SelectStatement;
-use ops;
-use ops::base::Base;
+use flow::{Blender, NodeAddress, Migration};
+use flow::data::DataType;
+use flow::diff::GraphSummary;
+use flow::prepared::{PreparedRead, PreparedWrite};
+use flow::sql::capabilities::{self, UnsupportedFeature};
+use flow::sql::mutation;
+use flow::sql::optimizer::{Optimizer, ReuseExistingNodes, Rule};
+use flow::sql::planner::{self, Catalog, GroupedFunction, PlanNode, PlanOp, QueryPlan};
+use flow::sql::query_graph::{SubqueryColumn, SubqueryCondition};
+use flow::sql::subqueries;
+use nom_sql::{Column, FieldExpression, SqlQuery};
+use ops::Datas;
+use ops::base::{Base, ColumnSpec};
+use ops::grouped::concat::{GroupConcat, TextComponent};
 use ops::identity::Identity;
 use ops::join::Builder as JoinBuilder;
 use ops::permute::Permute;
-use flow::data::DataType;
+use ops::semijoin::SemiJoin;
+use ops::topk::TopK;
 
-use std::collections::{HashMap, HashSet};
-use std::iter::FromIterator;
+use std::collections::HashMap;
 use std::str;
 use std::vec::Vec;
 
@@ -28,102 +35,174 @@ pub struct QueryFlowParts {
     pub query_leaf: NodeAddress,
 }
 
-/// Helper enum to avoid having separate `make_aggregation_node` and `make_extremum_node` functions
-enum GroupedNodeType {
-    Aggregation(ops::grouped::aggregate::Aggregation),
-    Extremum(ops::grouped::extremum::Extremum),
+/// A batch of queries to be added to the flow graph together, using `SqlIncorporator::stage`.
+///
+/// Each query may use the tables and views defined by the queries before it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryBatch {
+    queries: Vec<(String, Option<String>)>,
 }
 
-fn target_columns_from_computed_column(computed_col: &Column) -> &Vec<Column> {
-    use nom_sql::FunctionExpression::*;
-    use nom_sql::FieldExpression::*;
-
-    match *computed_col.function.as_ref().unwrap() {
-        Avg(Seq(ref cols)) |
-        Count(Seq(ref cols)) |
-        GroupConcat(Seq(ref cols)) |
-        Max(Seq(ref cols)) |
-        Min(Seq(ref cols)) |
-        Sum(Seq(ref cols)) => cols,
-        Count(All) => {
-            // see comment re COUNT(*) rewriting in make_aggregation_node
-            panic!("COUNT(*) should have been rewritten earlier!")
-        }
-        _ => panic!("invalid aggregation function"),
+impl QueryBatch {
+    /// Create an empty batch.
+    pub fn new() -> Self {
+        QueryBatch::default()
+    }
+
+    /// Add a query to the batch, with an optional name (see `SqlIncorporator::add_query`).
+    pub fn add_query<S: ToString>(mut self, query: S, name: Option<String>) -> Self {
+        self.queries.push((query.to_string(), name));
+        self
+    }
+
+    /// The number of queries in the batch.
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    /// Whether the batch holds no queries.
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
     }
 }
 
+/// A batch of queries that has been added to a migration, but has not yet been committed.
+///
+/// The nodes of every query in the batch come online together when the batch is committed, and
+/// none of them do if it is aborted instead. The staged batch must be either committed or
+/// aborted.
+#[must_use]
+pub struct StagedQueries<'a, 'b> {
+    inc: &'b mut SqlIncorporator,
+    // the incorporator as it was before the batch was staged, restored if the batch is aborted
+    saved: SqlIncorporator,
+    mig: Migration<'a>,
+    parts: Vec<QueryFlowParts>,
+}
+
 /// Long-lived struct that holds information about the SQL queries that have been incorporated into
 /// the Soup graph `grap`.
 /// The incorporator shares the lifetime of the flow graph it is associated with.
 #[derive(Clone, Debug, PartialEq)]
 pub struct SqlIncorporator {
-    write_schemas: HashMap<String, Vec<String>>,
+    catalog: Catalog,
     node_addresses: HashMap<String, NodeAddress>,
-    node_fields: HashMap<NodeAddress, Vec<String>>,
-    query_graphs: Vec<(QueryGraph, NodeAddress)>,
-    num_queries: usize,
+    optimizer: Optimizer,
+    // for every query with a reader, its leaf, the columns of the leaf that its reader is keyed
+    // on, its parameters, and the fields of its results
+    readers: HashMap<String, (NodeAddress, Vec<usize>, Vec<String>, Vec<String>)>,
+    // the column defaults and NOT NULL constraints declared for tables, by table name
+    columns: HashMap<String, Vec<ColumnSpec>>,
 }
 
 impl Default for SqlIncorporator {
     /// Creates a new `SqlIncorporator` for an empty flow graph.
     fn default() -> Self {
+        // queries share the nodes they have in common with the queries added before them
+        let mut optimizer = Optimizer::new();
+        optimizer.register(ReuseExistingNodes);
         SqlIncorporator {
-            write_schemas: HashMap::default(),
+            catalog: Catalog::default(),
             node_addresses: HashMap::default(),
-            node_fields: HashMap::default(),
-            query_graphs: Vec::new(),
-            num_queries: 0,
+            optimizer: optimizer,
+            readers: HashMap::default(),
+            columns: HashMap::default(),
         }
     }
 }
 
 impl SqlIncorporator {
     /// TODO(malte): modify once `SqlIntegrator` has a better intermediate graph representation.
-    fn fields_for(&self, na: NodeAddress) -> &[String] {
-        self.node_fields[&na].as_slice()
+    pub fn address_for(&self, name: &str) -> NodeAddress {
+        match self.node_addresses.get(name) {
+            None => panic!("node {} unknown!", name),
+            Some(na) => *na,
+        }
     }
 
-    /// TODO(malte): modify once `SqlIntegrator` has a better intermediate graph representation.
-    fn field_to_columnid(&self, na: NodeAddress, f: &str) -> Result<usize, String> {
-        match self.fields_for(na).iter().position(|s| *s == f) {
-            None => Err(format!("field {} not found in view {}", f, na)),
-            Some(i) => Ok(i),
+    /// The catalog of views that new queries are planned against.
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    /// Prepare a read of the results of the named query, which must already have been added to
+    /// the graph behind `blender`.
+    ///
+    /// Only queries with at least one parameter can be prepared. Queries with several parameters
+    /// are looked up by all of them at once (see `Migration::maintain_compound`).
+    pub fn prepare_read(&self, name: &str, blender: &Blender) -> Result<PreparedRead, String> {
+        let &(leaf, ref key, ref parameters, ref fields) =
+            self.readers.get(name).ok_or_else(|| format!("query {} has no reader", name))?;
+        if parameters.is_empty() {
+            return Err(format!("query {} has no parameters, but prepared reads need at least one",
+                               name));
         }
+        let getter: Option<Box<Fn(&[DataType]) -> Result<Datas, ()> + Send + Sync>> =
+            if key.len() == 1 {
+                blender.get_getter(leaf).map(|g| {
+                    Box::new(move |params: &[DataType]| g(&params[0])) as Box<_>
+                })
+            } else {
+                blender.get_compound_getter(leaf, &key[..])
+            };
+        let getter = getter.ok_or_else(|| format!("query {} has not been committed yet", name))?;
+        Ok(PreparedRead::new(getter, parameters.clone(), fields.clone()))
     }
 
-    /// TODO(malte): modify once `SqlIntegrator` has a better intermediate graph representation.
-    pub fn address_for(&self, name: &str) -> NodeAddress {
-        match self.node_addresses.get(name) {
-            None => panic!("node {} unknown!", name),
-            Some(na) => *na,
+    /// Prepare a SQL `DELETE` or `UPDATE` statement that modifies the rows of a table that has
+    /// already been added to the graph behind `blender`.
+    ///
+    /// Rows are identified by their primary key, so the statement's `WHERE` clause must compare
+    /// every primary key column of the table with a parameter or a literal. An `UPDATE` that only
+    /// sets some columns additionally requires the table to be maintained by its (single-column)
+    /// primary key, so that the rest of the current row can be read. See `PreparedWrite`.
+    pub fn prepare_write(&self,
+                         statement: &str,
+                         blender: &Blender)
+                         -> Result<PreparedWrite, String> {
+        let plan = mutation::plan(&self.catalog, mutation::parse(statement)?)?;
+        let base = self.address_for(&plan.table);
+        let current = if plan.key_columns.len() == 1 {
+            blender.get_index_getter(base, plan.key_columns[0])
+        } else {
+            None
+        };
+        if plan.assignments.is_some() && !plan.sets_all_columns() && current.is_none() {
+            return Err(format!("updating only some columns of {} requires it to be maintained \
+                                by its primary key",
+                               plan.table));
         }
+        Ok(PreparedWrite::new(blender.get_mutator(base), plan, current))
     }
 
-    /// Converts a condition tree stored in the `ConditionExpr` returned by the SQL parser into a
-    /// vector of conditions that `shortcut` understands.
-    fn to_conditions(&self, ct: &ConditionTree, na: &NodeAddress) -> Vec<Option<DataType>> {
-        // TODO(malte): support other types of operators
-        if ct.operator != Operator::Equal {
-            println!("Conditionals with {:?} are not supported yet, so ignoring {:?}",
-                     ct.operator,
-                     ct);
-            vec![]
+    /// Record the column defaults and `NOT NULL` constraints declared by a SQL `CREATE TABLE`
+    /// statement, so that the base node later added for its table applies them (see
+    /// `ColumnSpec`).
+    ///
+    /// The SQL parser drops these constraints, so tables added from parsed `SqlQuery` structures
+    /// only get them if their statement is passed here first. `add_query` does so itself.
+    pub fn declare_columns(&mut self, statement: &str) -> Result<(), String> {
+        let stmt = mutation::parse_create_table(statement)?;
+        let columns: Vec<_> = stmt.columns.into_iter().map(|(_, spec)| spec).collect();
+        if columns.iter().any(|spec| *spec != ColumnSpec::default()) {
+            self.columns.insert(stmt.table, columns);
         } else {
-            // TODO(malte): we only support one level of condition nesting at this point :(
-            let l = match *ct.left.as_ref().unwrap().as_ref() {
-                ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
-                _ => unimplemented!(),
-            };
-            let r = match *ct.right.as_ref().unwrap().as_ref() {
-                ConditionExpression::Base(ConditionBase::Literal(ref l)) => l.clone(),
-                _ => unimplemented!(),
-            };
-            let num_columns = self.fields_for(*na).len();
-            let mut filter = vec![None; num_columns];
-            filter[self.field_to_columnid(*na, &l.name).unwrap()] = Some(DataType::from(r));
-            filter
+            self.columns.remove(&stmt.table);
         }
+        Ok(())
+    }
+
+    /// Replace the optimizer that rewrites query plans before they are added to the graph.
+    ///
+    /// By default, only `ReuseExistingNodes` is applied, so that queries share the nodes they
+    /// have in common. An optimizer without that rule gives every query nodes of its own.
+    pub fn set_optimizer(&mut self, optimizer: Optimizer) {
+        self.optimizer = optimizer;
+    }
+
+    /// Register an additional rule with the optimizer used for new queries.
+    pub fn register_rule<R: Rule + 'static>(&mut self, rule: R) {
+        self.optimizer.register(rule);
     }
 
     /// Incorporates a single query into via the flow graph migration in `mig`. The `query` argument is a
@@ -151,659 +230,468 @@ impl SqlIncorporator {
     pub fn add_parsed_query(&mut self,
                             query: SqlQuery,
                             name: Option<String>,
-                            mut mig: &mut Migration)
+                            mig: &mut Migration)
                             -> Result<QueryFlowParts, String> {
-        let res = match name {
-            None => self.nodes_for_query(query, mig),
-            Some(n) => self.nodes_for_named_query(query, n, mig),
-        };
-        // TODO(malte): this currently always succeeds because `nodes_for_query` and
-        // `nodes_for_named_query` can't fail
-        Ok(res)
+        let plan = self.plan_query(query, name)?;
+        Ok(self.apply_plan(plan, mig))
     }
 
-    fn nodes_for_query(&mut self, q: SqlQuery, mig: &mut Migration) -> QueryFlowParts {
-        let name = match q {
-            SqlQuery::CreateTable(ref ctq) => ctq.table.name.clone(),
-            SqlQuery::Insert(ref iq) => iq.table.name.clone(),
-            SqlQuery::Select(_) => format!("q_{}", self.num_queries),
-        };
-        self.nodes_for_named_query(q, name, mig)
+    /// Incorporates a sequence of queries into the flow graph via the migration in `mig`.
+    ///
+    /// All queries are planned before any of them is added to the graph, and each query may refer
+    /// to the tables and views defined by the queries that precede it. If any query cannot be
+    /// planned, an error is returned and the graph is left unchanged.
+    pub fn add_parsed_queries(&mut self,
+                              queries: Vec<(Option<String>, SqlQuery)>,
+                              mig: &mut Migration)
+                              -> Result<Vec<QueryFlowParts>, String> {
+        let mut catalog = self.catalog.clone();
+        let mut plans = Vec::with_capacity(queries.len());
+        for (name, query) in queries {
+            if let Err(unsupported) = self.check_query(&query) {
+                return Err(capabilities::describe(&unsupported[..]));
+            }
+            let plan = planner::plan_query(&catalog, query, name)?;
+            let plan = self.optimizer.optimize(&catalog, plan);
+            catalog.register(&plan);
+            plans.push(plan);
+        }
+
+        Ok(plans.into_iter().map(|plan| self.apply_plan(plan, mig)).collect())
     }
 
-    fn nodes_for_named_query(&mut self,
-                             q: SqlQuery,
-                             query_name: String,
-                             mut mig: &mut Migration)
-                             -> QueryFlowParts {
-        use flow::sql::passes::alias_removal::AliasRemoval;
-        use flow::sql::passes::count_star_rewrite::CountStarRewrite;
-        use flow::sql::passes::implied_tables::ImpliedTableExpansion;
-        use flow::sql::passes::star_expansion::StarExpansion;
-
-        // first run some standard rewrite passes on the query. This makes the later work easier,
-        // as we no longer have to consider complications like aliases.
-        let q = q.expand_table_aliases()
-            .expand_stars(&self.write_schemas)
-            .expand_implied_tables(&self.write_schemas)
-            .rewrite_count_star(&self.write_schemas);
-
-        let (name, new_nodes, leaf) = match q {
-            SqlQuery::CreateTable(ctq) => {
-                assert_eq!(query_name, ctq.table.name);
-                let (na, new) =
-                    self.make_base_node(&ctq.table.name, &ctq.fields, ctq.keys.as_ref(), &mut mig);
-                if new {
-                    (query_name, vec![na], na)
-                } else {
-                    (query_name, vec![], na)
-                }
-            }
-            SqlQuery::Insert(iq) => {
-                assert_eq!(query_name, iq.table.name);
-                let (cols, _): (Vec<Column>, Vec<String>) = iq.fields.iter().cloned().unzip();
-                let (na, new) = self.make_base_node(&iq.table.name, &cols, None, &mut mig);
-                if new {
-                    (query_name, vec![na], na)
-                } else {
-                    (query_name, vec![], na)
-                }
+    /// Removes the named query from the flow graph via the migration in `mig`.
+    ///
+    /// The nodes that no other query uses are torn down when `mig` is committed, along with the
+    /// query's reader, while nodes that the query shares with other queries are kept. The views
+    /// that hold the results of the query's derived tables and subqueries are queries of their
+    /// own, and are not removed with it. The query must not have been added in `mig`.
+    ///
+    /// The return value holds the addresses of the nodes that will be removed.
+    pub fn remove_query(&mut self,
+                        name: &str,
+                        mig: &mut Migration)
+                        -> Result<Vec<NodeAddress>, String> {
+        let removed = self.catalog.remove_query(name)?;
+        self.readers.remove(name);
+
+        let mut nodes = Vec::with_capacity(removed.len());
+        for view in removed {
+            let na = self.address_for(&view);
+            self.node_addresses.remove(&view);
+            mig.remove_node(na);
+            nodes.push(na);
+        }
+        Ok(nodes)
+    }
+
+    /// Removes the queries named in a SQL `DROP VIEW` statement from the flow graph via the
+    /// migration in `mig`.
+    ///
+    /// See `remove_query`. Unless the statement says `IF EXISTS`, naming a query that does not
+    /// exist is an error, in which case no query is removed.
+    pub fn drop_view(&mut self,
+                     statement: &str,
+                     mig: &mut Migration)
+                     -> Result<Vec<NodeAddress>, String> {
+        let stmt = mutation::parse_drop_view(statement)?;
+        let mut views = Vec::with_capacity(stmt.views.len());
+        for view in stmt.views {
+            if views.contains(&view) {
+                continue;
             }
-            SqlQuery::Select(sq) => {
-                let (nodes, leaf) = self.make_nodes_for_selection(&sq, &query_name, &mut mig);
-                // Return new nodes
-                (query_name, nodes, leaf)
+            if self.catalog.views().contains(&view.as_str()) {
+                views.push(view);
+            } else if !stmt.if_exists {
+                return Err(format!("no view named {}", view));
             }
-        };
-
-        self.num_queries += 1;
+        }
 
-        QueryFlowParts {
-            name: name,
-            new_nodes: new_nodes,
-            reused_nodes: vec![],
-            query_leaf: leaf,
+        let mut nodes = Vec::new();
+        for view in views {
+            nodes.extend(self.remove_query(&view, mig)?);
         }
+        Ok(nodes)
     }
 
-    /// Return is (`node`, `is_new`)
-    fn make_base_node(&mut self,
-                      name: &str,
-                      cols: &Vec<Column>,
-                      keys: Option<&Vec<TableKey>>,
-                      mig: &mut Migration)
-                      -> (NodeAddress, bool) {
-        if self.write_schemas.contains_key(name) {
-            println!("WARNING: base table for write type {} already exists: ignoring query.",
-                     name);
-            return (self.node_addresses[name], false);
-        }
+    /// Adds the column described by a SQL `ALTER TABLE ... ADD COLUMN` statement to a table in the
+    /// flow graph via the migration in `mig`.
+    ///
+    /// The table's existing rows, and writes that leave the column out, get the column's default
+    /// (see `Migration::add_column`). Queries added later can use the column, while those added
+    /// before keep working as they did. The return value is the index of the new column.
+    pub fn alter_table(&mut self, statement: &str, mig: &mut Migration) -> Result<usize, String> {
+        let stmt = mutation::parse_alter_table(statement)?;
+        let column = self.catalog.add_column(&stmt.table, &stmt.column)?;
+        let base = self.address_for(&stmt.table);
+        assert_eq!(mig.add_column(base, stmt.column, stmt.default), column);
+        Ok(column)
+    }
 
-        let fields = Vec::from_iter(cols.iter().map(|c| c.name.clone()));
-
-        let primary_keys = match keys {
-            None => vec![],
-            Some(keys) => {
-                keys.iter()
-                    .filter_map(|k| match *k {
-                        ref k @ TableKey::PrimaryKey(..) => Some(k),
-                        _ => None,
-                    })
-                    .collect()
-            }
-        };
-        assert!(primary_keys.len() <= 1);
-
-        // make the new base node and record its information
-        let na = if !primary_keys.is_empty() {
-            match **primary_keys.iter().next().unwrap() {
-                TableKey::PrimaryKey(ref key_cols) => {
-                    debug!(mig.log,
-                           "Assigning primary key {:?} for base {}",
-                           key_cols,
-                           name);
-                    let pkey_column_ids = key_cols.iter()
-                        .map(|pkc| {
-                            assert_eq!(pkc.table.as_ref().unwrap(), name);
-                            cols.iter().position(|c| c == pkc).unwrap()
-                        })
-                        .collect();
-                    mig.add_ingredient(name, fields.as_slice(), Base::new(pkey_column_ids))
+    /// Adds every query in `batch` to a new migration of the graph behind `blender`, without
+    /// committing it.
+    ///
+    /// Every query is checked and planned as it is added, so a batch that stages successfully
+    /// only uses supported SQL and refers to existing tables, views, and columns. If any query
+    /// fails, none of the batch's queries are added, and the error is returned. Otherwise, the
+    /// returned `StagedQueries` can be used to inspect the nodes that will be added and the state
+    /// they will need, before either committing or aborting the whole batch.
+    pub fn stage<'a, 'b>(&'b mut self,
+                         batch: QueryBatch,
+                         blender: &'a mut Blender)
+                         -> Result<StagedQueries<'a, 'b>, String> {
+        let saved = self.clone();
+        let mut mig = blender.start_migration();
+
+        let mut parts = Vec::with_capacity(batch.len());
+        for (query, name) in batch.queries {
+            match self.add_query(&query, name, &mut mig) {
+                Ok(qfp) => parts.push(qfp),
+                Err(e) => {
+                    *self = saved;
+                    mig.abort();
+                    return Err(e);
                 }
-                _ => unreachable!(),
             }
-        } else {
-            mig.add_ingredient(name, fields.as_slice(), Base::default())
-        };
-        self.node_addresses.insert(String::from(name), na);
-        // TODO(malte): get rid of annoying duplication
-        self.node_fields.insert(na, fields.clone());
-        self.write_schemas.insert(String::from(name), fields);
+        }
 
-        (na, true)
+        Ok(StagedQueries {
+            inc: self,
+            saved: saved,
+            mig: mig,
+            parts: parts,
+        })
     }
 
-    fn make_grouped_node(&mut self,
-                         name: &str,
-                         computed_col_name: &str,
-                         over: (NodeAddress, usize), // address, column ID
-                         group_by: &[Column],
-                         node_type: GroupedNodeType,
-                         mig: &mut Migration)
-                         -> NodeAddress {
-        let parent_ni = over.0;
-
-        // Resolve column IDs in parent
-        let over_col_indx = over.1;
-        let group_col_indx = group_by.iter()
-            .map(|c| self.field_to_columnid(parent_ni, &c.name).unwrap())
-            .collect::<Vec<_>>();
-
-        // The function node's set of output columns is the group columns plus the function
-        // column
-        let mut combined_columns = Vec::from_iter(group_by.iter().map(|c| c.name.clone()));
-        combined_columns.push(String::from(computed_col_name));
-
-        // make the new operator and record its metadata
-        let na = match node_type {
-            GroupedNodeType::Aggregation(agg) => {
-                mig.add_ingredient(String::from(name),
-                                   combined_columns.as_slice(),
-                                   agg.over(parent_ni, over_col_indx, group_col_indx.as_slice()))
-            }
-            GroupedNodeType::Extremum(extr) => {
-                mig.add_ingredient(String::from(name),
-                                   combined_columns.as_slice(),
-                                   extr.over(parent_ni, over_col_indx, group_col_indx.as_slice()))
-            }
-        };
-        self.node_addresses.insert(String::from(name), na);
-        self.node_fields.insert(na, combined_columns);
-        na
+    /// Plans the given query against the views incorporated so far, without changing the graph.
+    ///
+    /// Queries that use unsupported SQL constructs are rejected (see `check_query`). The returned
+    /// plan has already been rewritten by the registered optimizer rules.
+    pub fn plan_query(&self, query: SqlQuery, name: Option<String>) -> Result<QueryPlan, String> {
+        self.plan_query_with_subqueries(query, name, vec![], vec![])
     }
 
-    fn make_function_node(&mut self,
-                          name: &str,
-                          func_col: &Column,
-                          group_cols: &[Column],
-                          parent: Option<NodeAddress>, // XXX(malte): nasty hack for non-grouped funcs
-                          mig: &mut Migration)
-                          -> NodeAddress {
-        use ops::grouped::aggregate::Aggregation;
-        use ops::grouped::extremum::Extremum;
-        use nom_sql::FunctionExpression::*;
-        use nom_sql::FieldExpression::*;
-
-        let mut mknode = |cols: &Vec<Column>, t: GroupedNodeType| {
-            // No support for multi-columns functions at this point
-            assert_eq!(cols.len(), 1);
-
-            let over = cols.iter().next().unwrap();
-            let parent_ni = match parent {
-                // If no explicit parent node is specified, we extract the base node from the
-                // "over" column's specification
-                None => self.address_for(over.table.as_ref().unwrap()),
-                // We have an explicit parent node (likely a projection helper), so use that
-                Some(ni) => ni,
-            };
-            let over_col_indx = self.field_to_columnid(parent_ni, &over.name).unwrap();
-
-            self.make_grouped_node(name,
-                                   &func_col.name,
-                                   (parent_ni, over_col_indx),
-                                   group_cols,
-                                   t,
-                                   mig)
-        };
-
-        let func = func_col.function.as_ref().unwrap();
-        match *func {
-            Sum(Seq(ref cols)) => mknode(cols, GroupedNodeType::Aggregation(Aggregation::SUM)),
-            Count(Seq(ref cols)) => mknode(cols, GroupedNodeType::Aggregation(Aggregation::COUNT)),
-            Count(All) => {
-                // XXX(malte): there is no "over" column, but our aggregation operators' API
-                // requires one to be specified, so we earlier rewrote it to use the last parent
-                // column (see passes/count_star_rewrite.rs). However, this isn't *entirely*
-                // faithful to COUNT(*) semantics, because COUNT(*) is supposed to count all
-                // rows including those with NULL values, and we don't have a mechanism to do that
-                // (but we also don't have a NULL value, so maybe we're okay).
-                panic!("COUNT(*) should have been rewritten earlier!")
-            }
-            Max(Seq(ref cols)) => mknode(cols, GroupedNodeType::Extremum(Extremum::MAX)),
-            Min(Seq(ref cols)) => mknode(cols, GroupedNodeType::Extremum(Extremum::MIN)),
-            _ => unimplemented!(),
+    /// Like `plan_query`, but also applies conditions on subqueries, and adds columns computed by
+    /// subqueries, that have already been incorporated.
+    fn plan_query_with_subqueries(&self,
+                                  query: SqlQuery,
+                                  name: Option<String>,
+                                  subqueries: Vec<SubqueryCondition>,
+                                  columns: Vec<SubqueryColumn>)
+                                  -> Result<QueryPlan, String> {
+        if let Err(unsupported) = self.check_query(&query) {
+            return Err(capabilities::describe(&unsupported[..]));
         }
+        let plan = planner::plan_query_with_subqueries(&self.catalog,
+                                                       query,
+                                                       name,
+                                                       subqueries,
+                                                       columns)?;
+        Ok(self.optimizer.optimize(&self.catalog, plan))
     }
 
-    fn make_projection_helper(&mut self,
-                              name: &str,
-                              computed_col: &Column,
-                              mig: &mut Migration)
-                              -> NodeAddress {
-        let target_cols = target_columns_from_computed_column(computed_col);
-        // TODO(malte): we only support a single column argument at this point
-        assert_eq!(target_cols.len(), 1);
-        let fn_col = target_cols.last().unwrap();
-
-        self.make_project_node(name,
-                               fn_col.table.as_ref().unwrap(),
-                               vec![fn_col],
-                               vec![("grp", DataType::from(0 as i32))],
-                               mig)
+    /// Checks whether the given query only uses SQL constructs that can be incorporated into the
+    /// flow graph.
+    ///
+    /// If it does not, every unsupported construct found in the query is returned. Queries that
+    /// fail this check are rejected by `add_query` and `add_parsed_query`.
+    pub fn check_query(&self, query: &SqlQuery) -> Result<(), Vec<UnsupportedFeature>> {
+        capabilities::check(query)
     }
 
-    fn make_project_node(&mut self,
-                         name: &str,
-                         parent_name: &str,
-                         proj_cols: Vec<&Column>,
-                         literals: Vec<(&str, DataType)>,
-                         mig: &mut Migration)
-                         -> NodeAddress {
-        use ops::project::Project;
+    /// Parses a textual query, incorporating its derived tables, the subqueries of its `IN` and
+    /// `EXISTS` conditions, and the scalar subqueries in its field list as internal views along
+    /// the way. The conditions on those views and the columns computed from them are returned
+    /// along with the query, which no longer contains them.
+    fn parse_with_subqueries(&mut self,
+                             query: &str,
+                             mig: &mut Migration)
+                             -> Result<(SqlQuery, Vec<SubqueryCondition>, Vec<SubqueryColumn>),
+                                       String> {
+        // derived tables (subqueries in the FROM clause) become views of their own, which the
+        // query then selects from
+        let query = subqueries::expand_derived_tables(query,
+                                                      |sq| self.add_derived_table(sq, mig))?;
+        let (query, conditions) = subqueries::extract_conditions(&query)?;
+        let (query, scalars) = subqueries::extract_scalar_subqueries(&query)?;
+        let parsed_query = sql_parser::parse_query(&query).map_err(String::from)?;
+        let conditions = conditions.into_iter()
+            .map(|c| self.add_subquery_condition(c, mig))
+            .collect::<Result<Vec<_>, _>>()?;
+        let columns = scalars.into_iter()
+            .map(|sq| self.add_subquery_column(sq, mig))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((parsed_query, conditions, columns))
+    }
 
-        let parent_ni = self.address_for(&parent_name);
-        //assert!(proj_cols.iter().all(|c| c.table == parent_name));
-        let proj_col_ids: Vec<usize> = proj_cols.iter()
-            .map(|c| self.field_to_columnid(parent_ni, &c.name).unwrap())
-            .collect();
+    /// Incorporates the subquery of a derived table as an internal view, and returns the name of
+    /// the view that holds its results.
+    fn add_derived_table(&mut self, query: &str, mig: &mut Migration) -> Result<String, String> {
+        let (query, conditions, columns) = self.parse_with_subqueries(query, mig)?;
+        self.add_internal_view(query, conditions, columns, mig)
+    }
 
-        let mut col_names: Vec<String> =
-            proj_cols.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
-        let (literal_names, literal_values): (Vec<_>, Vec<_>) = literals.iter().cloned().unzip();
-        col_names.extend(literal_names.into_iter().map(String::from));
-
-        let n = mig.add_ingredient(String::from(name),
-                                   col_names.as_slice(),
-                                   Project::new(parent_ni,
-                                                proj_col_ids.as_slice(),
-                                                Some(literal_values)));
-        self.node_addresses.insert(String::from(name), n);
-        self.node_fields.insert(n, col_names);
-        n
+    /// Incorporates the subquery of an `IN` or `EXISTS` condition as an internal view, and returns
+    /// the condition that the outer query's records must satisfy.
+    fn add_subquery_condition(&mut self,
+                              sq: subqueries::Subquery,
+                              mig: &mut Migration)
+                              -> Result<SubqueryCondition, String> {
+        let (query, conditions, columns) = self.parse_with_subqueries(&sq.query, mig)?;
+        let (query, column, view_column) = match sq.column {
+            Some(column) => {
+                // the subquery's results are the values that the column is tested against
+                let view_column = match query {
+                    SqlQuery::Select(ref st) => {
+                        match st.fields {
+                            FieldExpression::Seq(ref fs) if fs.len() == 1 => fs[0].name.clone(),
+                            _ => {
+                                return Err(format!("subquery {} must select one column",
+                                                   sq.query))
+                            }
+                        }
+                    }
+                    _ => return Err(format!("{} is not a subquery", sq.query)),
+                };
+                (query, Column::from(column.as_str()), view_column)
+            }
+            None => {
+                let (query, inner, outer) = subqueries::decorrelate(query)?;
+                (query, outer, inner.name)
+            }
+        };
+        let view = self.add_internal_view(query, conditions, columns, mig)?;
+        Ok(SubqueryCondition {
+            column: column,
+            view: view,
+            view_column: view_column,
+            negated: sq.negated,
+        })
     }
 
-    fn make_filter_and_project_nodes(&mut self,
-                                     name: &str,
-                                     qgn: &QueryGraphNode,
-                                     mig: &mut Migration)
-                                     -> Vec<NodeAddress> {
-        use ops::filter::Filter;
+    /// Incorporates a scalar subquery in the field list as an internal view that holds its result
+    /// for every value of the column it is correlated with, and returns the column it computes.
+    ///
+    /// As with a left join, records of the outer query whose subquery has no rows get `NULL` in
+    /// the computed column, even where SQL would give an aggregation such as `COUNT` a value.
+    fn add_subquery_column(&mut self,
+                           sq: subqueries::ScalarSubquery,
+                           mig: &mut Migration)
+                           -> Result<SubqueryColumn, String> {
+        let (query, conditions, columns) = self.parse_with_subqueries(&sq.query, mig)?;
+        let (query, aggregation, inner, outer) = subqueries::decorrelate_aggregate(query)?;
+        let view = self.add_internal_view(query, conditions, columns, mig)?;
+        Ok(SubqueryColumn {
+            column: outer,
+            view: view,
+            view_column: inner.name,
+            name: sq.alias.unwrap_or_else(|| aggregation.name.clone()),
+            value_column: aggregation.name,
+        })
+    }
 
-        let mut parent_ni = self.address_for(&qgn.rel_name);
-        let mut new_nodes = vec![];
-        // chain all the filters associated with this QGN
-        for (i, cond) in qgn.predicates.iter().enumerate() {
-            // convert ConditionTree to a chain of Filter operators.
-            let filter = self.to_conditions(cond, &parent_ni);
-            let parent_fields = Vec::from(self.fields_for(parent_ni));
-            let f_name = String::from(format!("{}_f{}", name, i));
-            let n = mig.add_ingredient(f_name.clone(),
-                                       parent_fields.as_slice(),
-                                       Filter::new(parent_ni, filter.as_slice()));
-            self.node_addresses.insert(f_name, n);
-            self.node_fields.insert(n, parent_fields);
-            parent_ni = n;
-            new_nodes.push(n);
-        }
-        // finally, project only the columns we need
-        let projected_columns = Vec::from_iter(qgn.columns.iter().map(|c| c.name.clone()));
-        let projected_column_ids: Vec<usize> = qgn.columns
-            .iter()
-            .map(|c| self.field_to_columnid(parent_ni, &c.name).unwrap())
-            .collect();
-        let n = mig.add_ingredient(String::from(name),
-                                   projected_columns.as_slice(),
-                                   Permute::new(parent_ni, projected_column_ids.as_slice()));
-        self.node_addresses.insert(String::from(name), n);
-        self.node_fields.insert(n, projected_columns);
-        new_nodes.push(n);
-        new_nodes
+    /// Incorporates a query that is part of another one, and returns the name of the view that
+    /// holds its results.
+    fn add_internal_view(&mut self,
+                         query: SqlQuery,
+                         conditions: Vec<SubqueryCondition>,
+                         columns: Vec<SubqueryColumn>,
+                         mig: &mut Migration)
+                         -> Result<String, String> {
+        let plan = self.plan_query_with_subqueries(query, None, conditions, columns)?;
+        // the query may have been answered by an existing view, rather than by a new one
+        let leaf = plan.leaf.clone();
+        self.apply_plan(plan, mig);
+        Ok(leaf)
     }
 
-    fn make_join_node(&mut self,
-                      name: &str,
-                      jps: &[ConditionTree],
-                      left_ni: NodeAddress,
-                      right_ni: NodeAddress,
-                      mig: &mut Migration)
-                      -> NodeAddress {
-        let j;
-        let fields;
-        {
-            let projected_cols_left = self.fields_for(left_ni);
-            let projected_cols_right = self.fields_for(right_ni);
-
-            let tuples_for_cols =
-                |ni: NodeAddress, cols: &[String]| -> Vec<(NodeAddress, usize)> {
-                    cols.iter()
-                        .map(|c| (ni, self.field_to_columnid(ni, c).unwrap()))
-                        .collect()
-                };
+    /// Adds the nodes described by the given plan to the graph via `mig`.
+    fn apply_plan(&mut self, plan: QueryPlan, mig: &mut Migration) -> QueryFlowParts {
+        debug!(mig.log,
+               format!("Making nodes for query named \"{}\"", plan.name);
+               "nodes" => plan.nodes.len());
+
+        let mut new_nodes = Vec::with_capacity(plan.nodes.len());
+        for n in &plan.nodes {
+            let na = self.make_node(n, mig);
+            self.node_addresses.insert(n.name.clone(), na);
+            new_nodes.push(na);
+        }
 
-            // non-join columns projected are the union of the ancestor's projected columns
-            // TODO(malte): this will need revisiting when we do smart reuse
-            let mut join_proj_config = tuples_for_cols(left_ni, projected_cols_left);
-            join_proj_config.extend(tuples_for_cols(right_ni, projected_cols_right));
-            // join columns need us to generate join group configs for the operator
-            let mut left_join_group = vec![0; projected_cols_left.len()];
-            let mut right_join_group = vec![0; projected_cols_right.len()];
-            for (i, p) in jps.iter().enumerate() {
-                // equi-join only
-                assert_eq!(p.operator, Operator::Equal);
-                let l_col = match **p.left.as_ref().unwrap() {
-                    ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
-                    _ => unimplemented!(),
-                };
-                let r_col = match **p.right.as_ref().unwrap() {
-                    ConditionExpression::Base(ConditionBase::Field(ref f)) => f.clone(),
-                    _ => unimplemented!(),
-                };
-                left_join_group[self.field_to_columnid(left_ni, &l_col.name).unwrap()] = i + 1;
-                right_join_group[self.field_to_columnid(right_ni, &r_col.name).unwrap()] = i + 1;
+        let leaf = self.address_for(&plan.leaf);
+        if let Some(ref key) = plan.reader_key {
+            if key.len() == 1 {
+                mig.maintain(leaf, key[0]);
+            } else {
+                mig.maintain_compound(leaf, &key[..]);
             }
-            j = JoinBuilder::new(join_proj_config)
-                .from(left_ni, left_join_group)
-                .join(right_ni, right_join_group);
-            fields = projected_cols_left.into_iter()
-                .chain(projected_cols_right.into_iter())
-                .cloned()
-                .collect::<Vec<String>>();
+            let fields = plan.nodes
+                .iter()
+                .find(|n| n.name == plan.leaf)
+                .map(|n| n.fields.clone())
+                .or_else(|| self.catalog.fields(&plan.leaf).map(|fs| fs.to_vec()))
+                .unwrap_or_else(Vec::new);
+            self.readers.insert(plan.name.clone(), (leaf, key.clone(), plan.parameters(), fields));
         }
-        let n = mig.add_ingredient(String::from(name), fields.as_slice(), j);
-        // println!("added join on {:?} and {:?}: {}", left_ni, right_ni, name);
-        self.node_addresses.insert(String::from(name), n);
-        self.node_fields.insert(n, fields);
-        n
-    }
+        debug!(mig.log, format!("Added final node for query named \"{}\"", plan.name);
+               "node" => leaf.as_global().index());
 
-    /// Return is (`new_nodes`, `leaf_node`).
-    fn make_nodes_for_selection(&mut self,
-                                st: &SelectStatement,
-                                name: &str,
-                                mig: &mut Migration)
-                                -> (Vec<NodeAddress>, NodeAddress) {
-        use std::collections::HashMap;
-
-        let qg = match to_query_graph(st) {
-            Ok(qg) => qg,
-            Err(e) => panic!(e),
-        };
+        self.catalog.register(&plan);
 
-        debug!(mig.log,
-               format!("Making nodes for query named \"{}\"", name));
-
-        // Do we already have this exact query or a subset of it?
-        // TODO(malte): make this an O(1) lookup by QG signature
-        for &(ref existing_qg, leaf) in self.query_graphs.iter() {
-            // note that this also checks the *order* in which parameters are specified; a
-            // different order means that we cannot simply reuse the existing reader.
-            if existing_qg.signature() == qg.signature() &&
-               existing_qg.parameters() == qg.parameters() {
-                // we already have this exact query, down to the exact same reader key columns
-                // in exactly the same order
-                info!(mig.log,
-                      "An exact match for query \"{}\" already exists, reusing it",
-                      name);
-                return (vec![], leaf);
-            } else if existing_qg.signature() == qg.signature() {
-                // QGs are identical, except for parameters (or their order)
-
-                // we must add a new reader for this query. This also requires adding an
-                // identity node (at least currently), since a node can only have a single
-                // associated reader.
-                // TODO(malte): consider the case when the projected columns need reordering
-                let id_fields = Vec::from(self.fields_for(leaf));
-                let id_na = mig.add_ingredient(String::from(name),
-                                               id_fields.as_slice(),
-                                               Identity::new(leaf));
-                self.node_addresses.insert(String::from(name), id_na);
-                self.node_fields.insert(id_na, id_fields);
-                // TODO(malte): this does not yet cover the case when there are multiple query
-                // parameters, which compound key support on Reader nodes.
-                let query_params = qg.parameters();
-                if !query_params.is_empty() {
-                    //assert_eq!(query_params.len(), 1);
-                    let key_column = query_params.iter().next().unwrap();
-                    mig.maintain(id_na,
-                                 self.field_to_columnid(id_na, &key_column.name).unwrap());
-                } else {
-                    // no query parameters, so we index on the first (and often only) column
-                    mig.maintain(id_na, 0);
-                }
-                return (vec![id_na], id_na);
-            }
-            // queries are different, but one might be a generalization of the other
-            if existing_qg.signature().is_generalization_of(&qg.signature()) {
-                info!(mig.log,
-                      "this QG: {:#?}\ncandidate query graph for reuse: {:#?}",
-                      qg,
-                      existing_qg);
-            }
+        QueryFlowParts {
+            name: plan.name,
+            new_nodes: new_nodes,
+            reused_nodes: plan.reused.iter().map(|v| self.address_for(v)).collect(),
+            query_leaf: leaf,
         }
+    }
 
-        let nodes_added;
-        let leaf_na;
+    fn make_node(&self, n: &PlanNode, mig: &mut Migration) -> NodeAddress {
+        use ops::filter::Filter;
+        use ops::predicate::PredicateFilter;
+        use ops::project::Project;
 
-        let mut i = 0;
-        {
-            // 1. Generate the necessary filter node for each relation node in the query graph.
-            let mut filter_nodes = HashMap::<String, Vec<NodeAddress>>::new();
-            let mut new_filter_nodes = Vec::new();
-            // Need to iterate over relations in a deterministic order, as otherwise nodes will be
-            // added in a different order every time, which will yield different node identifiers
-            // and make it difficult for applications to check what's going on.
-            let mut sorted_rels: Vec<&String> = qg.relations.keys().collect();
-            sorted_rels.sort();
-            for rel in &sorted_rels {
-                let qgn = &qg.relations[*rel];
-                // we'll handle computed columns later
-                if *rel != "computed_columns" {
-                    // the following conditional is required to avoid "empty" nodes (without any
-                    // projected columns) that are required as inputs to joins
-                    if !qgn.columns.is_empty() || !qgn.predicates.is_empty() {
-                        // add a basic filter/permute node for each query graph node if it either
-                        // has: 1) projected columns; or 2) a filter condition
-                        let fns = self.make_filter_and_project_nodes(&format!("q_{:x}_n{}",
-                                                                              qg.signature().hash,
-                                                                              i),
-                                                                     qgn,
-                                                                     mig);
-                        filter_nodes.insert((*rel).clone(), fns.clone());
-                        new_filter_nodes.extend(fns);
-                    } else {
-                        // otherwise, just record the node index of the base node for the relation
-                        // that is being selected from
-                        filter_nodes.insert((*rel).clone(), vec![self.address_for(rel)]);
-                    }
-                }
-                i += 1;
+        let name = n.name.clone();
+        let fields = n.fields.as_slice();
+        let columns = match self.columns.get(&name) {
+            Some(columns) if columns.len() == fields.len() => Some(columns.clone()),
+            _ => None,
+        };
+        let with_columns = |base: Base| match columns {
+            Some(columns) => base.with_columns(columns),
+            None => base,
+        };
+        match n.op {
+            PlanOp::Base { primary_key: None } => {
+                let base = with_columns(Base::default());
+                mig.add_ingredient(name, fields, base)
             }
-
-            // 2. Generate join nodes for the query. This starts out by joining two of the filter
-            //    nodes corresponding to relations in the first join predicate, and then continues
-            //    to join the result against previously unseen tables from the remaining
-            //    predicates. Note that no (src, dst) pair ever occurs twice, since we've already
-            //    previously moved all predicates pertaining to src/dst joins onto a single edge.
-            let mut join_nodes = Vec::new();
-            let mut joined_tables = HashSet::new();
-            let mut sorted_edges: Vec<(&(String, String), &QueryGraphEdge)> =
-                qg.edges.iter().collect();
-            sorted_edges.sort_by_key(|k| &(k.0).0);
-            let mut prev_ni = None;
-            for &(&(ref src, ref dst), edge) in &sorted_edges {
-                match *edge {
-                    // Edge represents a JOIN
-                    QueryGraphEdge::Join(ref jps) => {
-                        let left_ni = match prev_ni {
-                            None => {
-                                joined_tables.insert(src);
-                                let filters = &filter_nodes[src];
-                                assert_ne!(filters.len(), 0);
-                                *filters.last().unwrap()
-                            }
-                            Some(ni) => ni,
-                        };
-                        let right_ni = if joined_tables.contains(src) {
-                            joined_tables.insert(dst);
-                            *filter_nodes[dst].last().unwrap()
-                        } else if joined_tables.contains(dst) {
-                            joined_tables.insert(src);
-                            *filter_nodes[src].last().unwrap()
-                        } else {
-                            // We have already handled *both* tables that are part of the join.
-                            // This should never occur, because their join predicates must be
-                            // associated with the same query graph edge.
-                            unreachable!();
-                        };
-                        let ni = self.make_join_node(&format!("q_{:x}_n{}", qg.signature().hash, i),
-                                            jps,
-                                            left_ni,
-                                            right_ni,
-                                            mig);
-                        join_nodes.push(ni);
-                        i += 1;
-                        prev_ni = Some(ni);
-                    }
-                    // Edge represents a GROUP BY, which we handle later
-                    QueryGraphEdge::GroupBy(_) => (),
-                }
+            PlanOp::Base { primary_key: Some(ref key) } => {
+                debug!(mig.log, "Assigning primary key {:?} for base {}", key, name);
+                let base = with_columns(Base::new(key.clone()));
+                mig.add_ingredient(name, fields, base)
             }
-            let mut func_nodes = Vec::new();
-            match qg.relations.get("computed_columns") {
-                None => (),
-                Some(computed_cols_cgn) => {
-                    // Function columns with GROUP BY clause
-                    let mut grouped_fn_columns = HashSet::new();
-                    for e in qg.edges.values() {
-                        match *e {
-                            QueryGraphEdge::Join(_) => (),
-                            QueryGraphEdge::GroupBy(ref gb_cols) => {
-                                // Generate the right function nodes for all relevant columns in
-                                // the "computed_columns" node
-                                // TODO(malte): there can only be one GROUP BY in each query, but
-                                // the columns can come from different tables. In that case, we
-                                // would need to generate an Agg-Join-Agg sequence for each pair of
-                                // tables involved.
-                                for fn_col in &computed_cols_cgn.columns {
-                                    let ni = self.make_function_node(&format!("q_{:x}_n{}",
-                                                                              qg.signature().hash,
-                                                                              i),
-                                                                     fn_col,
-                                                                     gb_cols,
-                                                                     None,
-                                                                     mig);
-                                    func_nodes.push(ni);
-                                    grouped_fn_columns.insert(fn_col);
-                                    i += 1;
-                                }
-                            }
-                        }
+            PlanOp::Filter { ref parent, ref conditions } => {
+                mig.add_ingredient(name,
+                                   fields,
+                                   Filter::new(self.address_for(parent), conditions.as_slice()))
+            }
+            PlanOp::Predicate { ref parent, ref predicate } => {
+                mig.add_ingredient(name,
+                                   fields,
+                                   PredicateFilter::new(self.address_for(parent),
+                                                        predicate.clone()))
+            }
+            PlanOp::Permute { ref parent, ref columns } => {
+                mig.add_ingredient(name,
+                                   fields,
+                                   Permute::new(self.address_for(parent), columns.as_slice()))
+            }
+            PlanOp::Project { ref parent, ref columns, ref literals } => {
+                mig.add_ingredient(name,
+                                   fields,
+                                   Project::new(self.address_for(parent),
+                                                columns.as_slice(),
+                                                Some(literals.clone())))
+            }
+            PlanOp::Join { ref left,
+                           ref right,
+                           ref emit,
+                           ref left_groups,
+                           ref right_groups,
+                           left_outer } => {
+                let emit = emit.iter()
+                    .map(|&(ref side, col)| (self.address_for(side), col))
+                    .collect();
+                let j = JoinBuilder::new(emit).from(self.address_for(left), left_groups.clone());
+                let j = if left_outer {
+                    j.left_join(self.address_for(right), right_groups.clone())
+                } else {
+                    j.join(self.address_for(right), right_groups.clone())
+                };
+                mig.add_ingredient(name, fields, j)
+            }
+            PlanOp::Grouped { ref parent, ref function, over, ref group_by } => {
+                let parent = self.address_for(parent);
+                match *function {
+                    GroupedFunction::Aggregation(ref agg) => {
+                        mig.add_ingredient(name,
+                                           fields,
+                                           agg.clone().over(parent, over, group_by.as_slice()))
+                    }
+                    GroupedFunction::Extremum(ref extr) => {
+                        mig.add_ingredient(name,
+                                           fields,
+                                           extr.clone().over(parent, over, group_by.as_slice()))
                     }
-                    // Function columns without GROUP BY
-                    for computed_col in computed_cols_cgn.columns
-                        .iter()
-                        .filter(|c| !grouped_fn_columns.contains(c))
-                        .collect::<Vec<_>>() {
-
-                        let agg_node_name = &format!("q_{:x}_n{}", qg.signature().hash, i);
-
-                        let over_cols = target_columns_from_computed_column(computed_col);
-                        let ref proj_cols_from_target_table = qg.relations
-                            .get(over_cols.iter().next().as_ref().unwrap().table.as_ref().unwrap())
-                            .as_ref()
-                            .unwrap()
-                            .columns;
-                        let (group_cols, parent_ni) = if proj_cols_from_target_table.is_empty() {
-                            // slightly messy hack: if there are no group columns and the table on
-                            // which we compute has no projected columns in the output, we make one
-                            // up a group column by adding an extra projection node
-                            let proj_name = format!("{}_prj_hlpr", agg_node_name);
-                            let proj = self.make_projection_helper(&proj_name, computed_col, mig);
-                            func_nodes.push(proj);
-
-                            let bogo_group_col = Column::from(format!("{}.grp", proj_name)
-                                .as_str());
-                            (vec![bogo_group_col], Some(proj))
-                        } else {
-                            (proj_cols_from_target_table.clone(), None)
-                        };
-                        let ni = self.make_function_node(agg_node_name,
-                                                         computed_col,
-                                                         group_cols.as_slice(),
-                                                         parent_ni,
-                                                         mig);
-                        func_nodes.push(ni);
-                        i += 1;
+                    GroupedFunction::GroupConcat(separator) => {
+                        let components = vec![TextComponent::Column(over)];
+                        mig.add_ingredient(name,
+                                           fields,
+                                           GroupConcat::grouped_by(parent,
+                                                                   components,
+                                                                   separator,
+                                                                   group_by.as_slice()))
                     }
                 }
             }
-
-            // 3. Generate leaf views that expose the query result
-            {
-                let final_ni = if !join_nodes.is_empty() {
-                    join_nodes.last().unwrap()
-                } else if !func_nodes.is_empty() {
-                    // XXX(malte): This won't work if (a) there are multiple function nodes in the
-                    // query, or (b) computed columns are used within JOIN clauses
-                    assert!(func_nodes.len() <= 2);
-                    func_nodes.last().unwrap()
+            PlanOp::TopK { ref parent, ref group_by, order, k, descending } => {
+                let parent = self.address_for(parent);
+                let topk = if descending {
+                    TopK::highest(parent, group_by.clone(), order, k)
                 } else {
-                    assert!(filter_nodes.len() == 1);
-                    let filter = filter_nodes.iter().next().as_ref().unwrap().1;
-                    assert_ne!(filter.len(), 0);
-                    filter.last().unwrap()
+                    TopK::lowest(parent, group_by.clone(), order, k)
                 };
-                let projected_columns: Vec<Column> = sorted_rels.iter()
-                    .fold(Vec::new(), |mut v, s| {
-                        v.extend(qg.relations[*s].columns.clone().into_iter());
-                        v
-                    });
-                let projected_column_ids: Vec<usize> = projected_columns.iter()
-                    .map(|c| self.field_to_columnid(*final_ni, &c.name).unwrap())
-                    .collect();
-                let fields = projected_columns.iter()
-                    .map(|c| c.name.clone())
-                    .collect::<Vec<String>>();
-                leaf_na = mig.add_ingredient(String::from(name),
-                                             fields.as_slice(),
-                                             Permute::new(*final_ni,
-                                                          projected_column_ids.as_slice()));
-                self.node_addresses.insert(String::from(name), leaf_na);
-                self.node_fields.insert(leaf_na, fields);
-
-                // We always materialize leaves of queries (at least currently)
-                let query_params = qg.parameters();
-                // TODO(malte): this does not yet cover the case when there are multiple query
-                // parameters, which compound key support on Reader nodes.
-                if !query_params.is_empty() {
-                    //assert_eq!(query_params.len(), 1);
-                    let key_column = query_params.iter().next().unwrap();
-                    mig.maintain(leaf_na,
-                                 self.field_to_columnid(leaf_na, &key_column.name).unwrap());
+                mig.add_ingredient(name, fields, topk)
+            }
+            PlanOp::SemiJoin { ref left, ref right, on, anti } => {
+                let (left, right) = (self.address_for(left), self.address_for(right));
+                let semi = if anti {
+                    SemiJoin::anti(left, right, on)
                 } else {
-                    // no query parameters, so we index on the first (and often only) column
-                    mig.maintain(leaf_na, 0);
-                }
+                    SemiJoin::semi(left, right, on)
+                };
+                mig.add_ingredient(name, fields, semi)
+            }
+            PlanOp::Identity { ref parent } => {
+                mig.add_ingredient(name, fields, Identity::new(self.address_for(parent)))
             }
-            debug!(mig.log, format!("Added final node for query named \"{}\"", name);
-                   "node" => leaf_na.as_global().index());
-            new_filter_nodes.push(leaf_na);
-
-            // Finally, store the query graph and the corresponding `NodeAddress`
-            self.query_graphs.push((qg.clone(), leaf_na));
-
-            // finally, we output all the nodes we generated
-            nodes_added = new_filter_nodes.into_iter()
-                .chain(join_nodes.into_iter())
-                .chain(func_nodes.into_iter())
-                .collect();
         }
-
-        (nodes_added, leaf_na)
     }
 }
 
 /// Enables incorporation of a textual SQL query into a Soup graph.
+impl<'a, 'b> StagedQueries<'a, 'b> {
+    /// The result of incorporating each query in the batch, in order.
+    pub fn parts(&self) -> &[QueryFlowParts] {
+        &self.parts[..]
+    }
+
+    /// The nodes that the batch will materialize, and the indices their states will have, along
+    /// with the indices the batch needs on existing nodes. See `Migration::materializations`.
+    pub fn materializations(&self) -> HashMap<NodeAddress, Vec<Vec<usize>>> {
+        self.mig.materializations()
+    }
+
+    /// The structure the graph will have once the batch is committed. See `Migration::summary`.
+    pub fn summary(&self) -> GraphSummary {
+        self.mig.summary()
+    }
+
+    /// Bring the nodes of every query in the batch online.
+    pub fn commit(self) -> Vec<QueryFlowParts> {
+        self.mig.commit();
+        self.parts
+    }
+
+    /// Abandon the batch, leaving both the graph and the incorporator as they were before it was
+    /// staged.
+    pub fn abort(self) {
+        *self.inc = self.saved;
+        self.mig.abort();
+    }
+}
+
 pub trait ToFlowParts {
     /// Turn a SQL query into a set of nodes inserted into the Soup graph managed by
     /// the `SqlIncorporator` in the second argument. The query can optionally be named by the
@@ -831,19 +719,20 @@ impl<'a> ToFlowParts for &'a str {
                      name: Option<String>,
                      mig: &mut Migration)
                      -> Result<QueryFlowParts, String> {
-        // try parsing the incoming SQL
-        let parsed_query = sql_parser::parse_query(self);
-
-        // if ok, manufacture a node for the query structure we got
-        match parsed_query {
-            Ok(q) => {
-                match name {
-                    Some(name) => Ok(inc.nodes_for_named_query(q, name, mig)),
-                    None => Ok(inc.nodes_for_query(q, mig)),
-                }
-            }
-            Err(e) => Err(String::from(e)),
+        // subqueries become views of their own, which the query then uses
+        let (q, conditions, columns) = inc.parse_with_subqueries(self, mig)?;
+        let creates_table = match q {
+            SqlQuery::CreateTable(_) => true,
+            _ => false,
+        };
+
+        // manufacture nodes for the query structure we got
+        let plan = inc.plan_query_with_subqueries(q, name, conditions, columns)?;
+        if creates_table {
+            // statements whose constraints cannot be parsed add tables without them
+            let _ = inc.declare_columns(self);
         }
+        Ok(inc.apply_plan(plan, mig))
     }
 }
 
@@ -853,7 +742,8 @@ mod tests {
     use flow::node::Node;
     use flow::Migration;
     use Blender;
-    use super::{SqlIncorporator, ToFlowParts};
+    use super::{QueryBatch, SqlIncorporator, ToFlowParts};
+    use flow::sql::optimizer::Optimizer;
     use nom_sql::{FieldExpression, FunctionExpression};
 
     /// Helper to grab a reference to a named view.
@@ -1051,6 +941,41 @@ mod tests {
         assert_eq!(edge_view.description(), format!("π[1]"));
     }
 
+    #[test]
+    fn it_incorporates_group_concat() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        // Establish a base write type
+        assert!(inc.add_query("INSERT INTO votes (aid, userid) VALUES (?, ?);",
+                       None,
+                       &mut mig)
+            .is_ok());
+
+        let res = inc.add_query("SELECT GROUP_CONCAT(votes.userid) AS voters FROM votes GROUP BY \
+                                 votes.aid;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        // added the concatenation and the edge view, and a reader
+        assert_eq!(mig.graph().node_count(), 5);
+        // check concatenation view
+        let qid = query_id_hash(&["computed_columns", "votes"],
+                                &[&Column::from("votes.aid")],
+                                &[&Column {
+                                    name: String::from("voters"),
+                                    table: None,
+                                    function: Some(FunctionExpression::GroupConcat(
+                                            FieldExpression::Seq(
+                                                vec![Column::from("votes.userid")]))),
+                                }]);
+        let concat_view = get_node(&inc, &mig, &format!("q_{:x}_n2", qid));
+        assert_eq!(concat_view.fields(), &["aid", "voters"]);
+        assert_eq!(concat_view.description(), "||([1], \",\") γ[0]");
+    }
+
     #[test]
     fn it_reuses_identical_query() {
         // set up graph
@@ -1131,6 +1056,185 @@ mod tests {
         assert_eq!(qfp.query_leaf, *id_node);
     }
 
+    #[test]
+    fn it_shares_common_nodes() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO users (id, name) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        let res = inc.add_query("SELECT users.name FROM users WHERE users.id = 42;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        let first = res.unwrap();
+
+        // a query with the same filter, but a different projection, shares the filter
+        let res = inc.add_query("SELECT users.id FROM users WHERE users.id = 42;", None, &mut mig);
+        assert!(res.is_ok());
+        let qfp = res.unwrap();
+        assert_eq!(qfp.reused_nodes.len(), 1);
+        assert!(first.new_nodes.contains(&qfp.reused_nodes[0]));
+        assert!(qfp.new_nodes.iter().all(|na| !first.new_nodes.contains(na)));
+        let filter = mig.graph().node_weight(qfp.reused_nodes[0].as_global().clone()).unwrap();
+        assert_eq!(filter.description(), format!("σ[0=\"42\"]"));
+
+        // without the rule, every query gets nodes of its own
+        inc.set_optimizer(Optimizer::new());
+        let res = inc.add_query("SELECT users.id, users.name FROM users WHERE users.id = 42;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        assert!(res.unwrap().reused_nodes.is_empty());
+    }
+
+    #[test]
+    fn it_drops_views() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let (first, second) = {
+            let mut mig = g.start_migration();
+            assert!(inc.add_query("INSERT INTO users (id, name) VALUES (?, ?);", None, &mut mig)
+                .is_ok());
+            let first = inc.add_query("SELECT users.name FROM users WHERE users.id = 42;",
+                           Some("first".into()),
+                           &mut mig)
+                .unwrap();
+            let second = inc.add_query("SELECT users.id FROM users WHERE users.id = 42;",
+                           Some("second".into()),
+                           &mut mig)
+                .unwrap();
+            mig.commit();
+            (first, second)
+        };
+
+        let mut mig = g.start_migration();
+        assert!(inc.drop_view("DROP VIEW nonexistent;", &mut mig).is_err());
+        assert_eq!(inc.drop_view("DROP VIEW IF EXISTS nonexistent;", &mut mig), Ok(vec![]));
+
+        // the filter that the second query shares is kept
+        let removed = inc.drop_view("DROP VIEW first;", &mut mig).unwrap();
+        assert!(!removed.is_empty());
+        assert!(removed.iter().all(|na| first.new_nodes.contains(na)));
+        assert!(removed.iter().all(|na| !second.reused_nodes.contains(na)));
+        assert!(inc.catalog().views().contains(&"second"));
+        assert!(!inc.catalog().views().contains(&"first"));
+        assert!(inc.drop_view("DROP VIEW first;", &mut mig).is_err());
+
+        // once the second query is gone, so is the filter
+        let removed = inc.remove_query("second", &mut mig).unwrap();
+        assert!(removed.contains(&second.reused_nodes[0]));
+        assert!(inc.catalog().views().is_empty());
+        mig.commit();
+    }
+
+    #[test]
+    fn it_alters_tables() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        {
+            let mut mig = g.start_migration();
+            assert!(inc.add_query("INSERT INTO users (id, name) VALUES (?, ?);", None, &mut mig)
+                .is_ok());
+            mig.commit();
+        }
+
+        let mut mig = g.start_migration();
+        assert_eq!(inc.alter_table("ALTER TABLE users ADD COLUMN karma INT DEFAULT 0;", &mut mig),
+                   Ok(2));
+        assert_eq!(get_node(&inc, &mig, "users").fields(), &["id", "name", "karma"]);
+        assert!(inc.alter_table("ALTER TABLE users ADD name TEXT;", &mut mig).is_err());
+        assert!(inc.alter_table("ALTER TABLE posts ADD title TEXT;", &mut mig).is_err());
+
+        // new queries can use the column
+        let res = inc.add_query("SELECT users.karma FROM users WHERE users.id = ?;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        mig.commit();
+    }
+
+    #[test]
+    fn it_applies_column_constraints() {
+        use flow::data::DataType;
+        use nom_sql::parser::parse_query;
+        use ops::base::{RejectReason, Rejection};
+        use std::time;
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        {
+            let mut mig = g.start_migration();
+            let q = "CREATE TABLE users (id int not null, name varchar(255), PRIMARY KEY (id));";
+            assert!(inc.add_query(q, None, &mut mig).is_ok());
+
+            // parsed statements need their constraints declared separately
+            let q = "CREATE TABLE posts (id int, title varchar(255) DEFAULT 'new');";
+            assert!(inc.declare_columns(q).is_ok());
+            let q = parse_query("CREATE TABLE posts (id int, title varchar(255));").unwrap();
+            assert!(inc.add_parsed_query(q, None, &mut mig).is_ok());
+            assert!(inc.add_query("SELECT posts.id, posts.title FROM posts WHERE posts.id = ?;",
+                           Some("post".into()),
+                           &mut mig)
+                .is_ok());
+            mig.commit();
+        }
+
+        let rejected = g.rejections(inc.address_for("users"));
+        g.get_mutator(inc.address_for("users")).put(vec![DataType::None, "alice".into()]);
+        g.get_mutator(inc.address_for("posts")).put(vec![1.into()]);
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        assert_eq!(rejected.try_iter().collect::<Vec<_>>(),
+                   vec![Rejection {
+                            row: vec![DataType::None, "alice".into()],
+                            reason: RejectReason::Null(0),
+                        }]);
+        let rows = inc.prepare_read("post", &g).unwrap().execute(&[1.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("title"), Some(&"new".into()));
+    }
+
+    #[test]
+    fn it_stages_batches() {
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+
+        // if any query in a batch fails, none of them are added
+        let batch = QueryBatch::new()
+            .add_query("INSERT INTO users (id, name) VALUES (?, ?);", None)
+            .add_query("SELECT posts.title FROM posts WHERE posts.id = ?;", None);
+        assert!(inc.stage(batch, &mut g).is_err());
+        assert!(inc.catalog().table_fields("users").is_none());
+        assert_eq!(g.graph().node_count(), 1);
+
+        // a staged batch can be inspected and aborted
+        let batch = QueryBatch::new()
+            .add_query("INSERT INTO users (id, name) VALUES (?, ?);", None)
+            .add_query("SELECT users.name FROM users WHERE users.id = ?;",
+                       Some("q".into()));
+        {
+            let staged = inc.stage(batch.clone(), &mut g).unwrap();
+            assert_eq!(staged.parts().len(), 2);
+            let users = staged.parts()[0].query_leaf;
+            assert!(staged.materializations().contains_key(&users));
+            staged.abort();
+        }
+        assert!(inc.catalog().table_fields("users").is_none());
+        assert_eq!(g.graph().node_count(), 1);
+
+        // or committed
+        let parts = inc.stage(batch, &mut g).unwrap().commit();
+        assert_eq!(parts[1].name, "q");
+        assert!(inc.catalog().views().contains(&"q"));
+        assert!(g.outputs().iter().any(|&(n, _, _)| n == parts[1].query_leaf));
+    }
+
     #[test]
     fn it_incorporates_aggregation_no_group_by() {
         // set up graph
@@ -1257,4 +1361,343 @@ mod tests {
             // println!("{}", inc.graph);
         }
     }
+
+    #[test]
+    fn it_rejects_unsupported_queries() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO users (id, age) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        assert_eq!(mig.graph().node_count(), 2);
+
+        // Non-equality parameters and ORDER BY are not supported, and should add no nodes
+        let res = inc.add_query("SELECT users.id FROM users WHERE users.age > ? ORDER BY users.id;",
+                                None,
+                                &mut mig);
+        assert!(res.is_err());
+        let err = res.err().unwrap();
+        assert!(err.contains("ORDER BY"));
+        assert!(err.contains("Greater"));
+        assert_eq!(mig.graph().node_count(), 2);
+    }
+
+    #[test]
+    fn it_incorporates_derived_tables() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO users (id, name) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        assert!(inc.add_query("INSERT INTO articles (id, author, title) VALUES (?, ?, ?);",
+                       None,
+                       &mut mig)
+            .is_ok());
+
+        let res = inc.add_query("SELECT a.title, users.name FROM \
+                                 (SELECT articles.author, articles.title FROM articles) AS a, \
+                                 users WHERE a.author = users.id;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        // the derived table became a view of its own, which the query joins with users
+        assert_eq!(get_node(&inc, &mig, "q_2").fields(), &["author", "title"]);
+        let edge = get_node(&inc, &mig, &res.unwrap().name);
+        assert_eq!(edge.fields(), &["title", "author", "name", "id"]);
+    }
+
+    #[test]
+    fn it_incorporates_subquery_conditions() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO articles (id, author, title) VALUES (?, ?, ?);",
+                       None,
+                       &mut mig)
+            .is_ok());
+        assert!(inc.add_query("INSERT INTO votes (aid, uid) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        assert!(inc.add_query("INSERT INTO flags (aid, kind) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+
+        // the subqueries become views of their own, which the query is semi-joined with
+        let res = inc.add_query("SELECT articles.title FROM articles WHERE articles.id IN \
+                                 (SELECT votes.aid FROM votes) AND NOT EXISTS (SELECT * FROM \
+                                 flags WHERE flags.aid = articles.id AND flags.kind = 'spam');",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        let semi_joins = res.new_nodes
+            .iter()
+            .map(|na| mig.graph().node_weight(na.as_global().clone()).unwrap())
+            .filter(|n| n.description().contains("∈") || n.description().contains("∉"))
+            .count();
+        assert_eq!(semi_joins, 2);
+        let edge = get_node(&inc, &mig, &res.name);
+        assert_eq!(edge.fields(), &["title", "id"]);
+
+        // correlated subqueries must be correlated by an equality
+        let res = inc.add_query("SELECT articles.title FROM articles WHERE EXISTS (SELECT * FROM \
+                                 flags WHERE flags.kind = 'spam');",
+                                None,
+                                &mut mig);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn it_incorporates_scalar_subqueries() {
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO article (id, title) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        assert!(inc.add_query("INSERT INTO vote (id, uid) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+
+        // the subquery becomes a view that counts the votes for every article, which the query
+        // is left-joined with
+        let res = inc.add_query("SELECT a.id, a.title, (SELECT COUNT(*) FROM vote v WHERE v.id = \
+                                 a.id) AS votes FROM article a;",
+                                None,
+                                &mut mig);
+        assert!(res.is_ok());
+        let res = res.unwrap();
+        let joins = res.new_nodes
+            .iter()
+            .map(|na| mig.graph().node_weight(na.as_global().clone()).unwrap())
+            .filter(|n| n.description().contains("⋉"))
+            .count();
+        assert_eq!(joins, 1);
+        let edge = get_node(&inc, &mig, &res.name);
+        assert_eq!(edge.fields(), &["id", "title", "votes"]);
+
+        // the subquery must compute a single value for every article
+        let res = inc.add_query("SELECT a.title, (SELECT v.uid FROM vote v WHERE v.id = a.id) \
+                                 FROM article a;",
+                                None,
+                                &mut mig);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn it_incorporates_disjunctions() {
+        use nom_sql::parser::parse_query;
+        use flow::sql::planner::PlanOp;
+        use ops::join::Comparison;
+        use ops::predicate::{Operand, Predicate};
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO articles (id, author, votes) VALUES (?, ?, ?);",
+                       None,
+                       &mut mig)
+            .is_ok());
+
+        // the whole WHERE clause is evaluated by a single predicate
+        let q = parse_query("SELECT articles.id FROM articles WHERE articles.author = 1 OR \
+                             articles.votes > 10 OR articles.votes < 2;")
+            .unwrap();
+        let plan = inc.plan_query(q.clone(), None).unwrap();
+        let predicates: Vec<_> = plan.nodes
+            .iter()
+            .filter_map(|n| match n.op {
+                PlanOp::Predicate { ref predicate, .. } => Some(predicate.clone()),
+                PlanOp::Filter { .. } => panic!("disjunctions cannot be evaluated by a Filter"),
+                _ => None,
+            })
+            .collect();
+        let compare = |col, cmp, v: i32| Predicate::Compare(col, cmp, Operand::Literal(v.into()));
+        assert_eq!(predicates,
+                   vec![Predicate::Or(vec![compare(1, Comparison::Equal, 1),
+                                           compare(2, Comparison::Greater, 10),
+                                           compare(2, Comparison::Less, 2)])]);
+
+        assert!(inc.add_parsed_query(q, None, &mut mig).is_ok());
+        assert!(mig.graph()
+            .raw_nodes()
+            .iter()
+            .any(|n| n.weight.description() == "σ([1]=1 ∨ [2]>10 ∨ [2]<2)"));
+    }
+
+    #[test]
+    fn it_applies_registered_rules() {
+        use flow::sql::optimizer::Rule;
+        use nom_sql::parser::parse_query;
+        use flow::sql::planner::{Catalog, PlanOp, QueryPlan};
+
+        // turns every filter into an identity, so that nothing is ever filtered
+        struct DropFilters;
+        impl Rule for DropFilters {
+            fn name(&self) -> &str {
+                "drop filters"
+            }
+
+            fn apply(&self, _: &Catalog, plan: &mut QueryPlan) -> bool {
+                let mut changed = false;
+                for n in &mut plan.nodes {
+                    let parent = match n.op {
+                        PlanOp::Filter { ref parent, .. } => parent.clone(),
+                        _ => continue,
+                    };
+                    n.op = PlanOp::Identity { parent: parent };
+                    changed = true;
+                }
+                changed
+            }
+        }
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        inc.register_rule(DropFilters);
+        let mut mig = g.start_migration();
+
+        assert!(inc.add_query("INSERT INTO users (id, age) VALUES (?, ?);", None, &mut mig)
+            .is_ok());
+        let q = parse_query("SELECT users.id, users.age FROM users WHERE users.age = 18;").unwrap();
+        let plan = inc.plan_query(q, None).unwrap();
+        assert!(plan.nodes.iter().all(|n| match n.op {
+            PlanOp::Filter { .. } => false,
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn it_prepares_reads() {
+        use std::time;
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        {
+            let mut mig = g.start_migration();
+            assert!(inc.add_query("INSERT INTO users (id, name) VALUES (?, ?);", None, &mut mig)
+                .is_ok());
+            assert!(inc.add_query("SELECT users.id, users.name FROM users WHERE users.id = ?;",
+                           Some("by_id".into()),
+                           &mut mig)
+                .is_ok());
+            assert!(inc.add_query("SELECT users.name FROM users;", Some("all".into()), &mut mig)
+                .is_ok());
+            mig.commit();
+        }
+
+        let mutator = g.get_mutator(inc.address_for("users"));
+        mutator.put(vec![1.into(), "alice".into()]);
+        mutator.put(vec![2.into(), "bob".into()]);
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        let by_id = inc.prepare_read("by_id", &g).unwrap();
+        assert_eq!(by_id.parameters(), &["id"]);
+        assert_eq!(by_id.fields(), &["id", "name"]);
+        let rows = by_id.execute(&[2.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("name"), Some(&"bob".into()));
+        assert!(by_id.execute(&[]).is_err());
+
+        // queries without parameters, and unknown queries, cannot be prepared
+        assert!(inc.prepare_read("all", &g).is_err());
+        assert!(inc.prepare_read("none", &g).is_err());
+    }
+
+    #[test]
+    fn it_prepares_reads_with_several_parameters() {
+        use std::time;
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        {
+            let mut mig = g.start_migration();
+            assert!(inc.add_query("INSERT INTO users (id, name, age) VALUES (?, ?, ?);",
+                           None,
+                           &mut mig)
+                .is_ok());
+            assert!(inc.add_query("SELECT users.id FROM users \
+                                   WHERE users.name = ? AND users.age = ?;",
+                           Some("by_name_and_age".into()),
+                           &mut mig)
+                .is_ok());
+            mig.commit();
+        }
+
+        let mutator = g.get_mutator(inc.address_for("users"));
+        mutator.put(vec![1.into(), "alice".into(), 30.into()]);
+        mutator.put(vec![2.into(), "alice".into(), 40.into()]);
+        mutator.put(vec![3.into(), "bob".into(), 30.into()]);
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        let q = inc.prepare_read("by_name_and_age", &g).unwrap();
+        assert_eq!(q.parameters(), &["name", "age"]);
+        let rows = q.execute(&["alice".into(), 40.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("id"), Some(&2.into()));
+        let rows = q.execute(&["bob".into(), 30.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("id"), Some(&3.into()));
+        assert!(q.execute(&["bob".into(), 40.into()]).unwrap().is_empty());
+        assert!(q.execute(&["bob".into()]).is_err());
+    }
+
+    #[test]
+    fn it_prepares_writes() {
+        use std::time;
+
+        // set up graph
+        let mut g = Blender::new();
+        let mut inc = SqlIncorporator::default();
+        {
+            let mut mig = g.start_migration();
+            assert!(inc.add_query("CREATE TABLE users (id int, name varchar(255), karma int, \
+                                   PRIMARY KEY (id));",
+                           None,
+                           &mut mig)
+                .is_ok());
+            assert!(inc.add_query("SELECT users.id, users.name, users.karma FROM users \
+                                   WHERE users.id = ?;",
+                           Some("by_id".into()),
+                           &mut mig)
+                .is_ok());
+            mig.commit();
+        }
+
+        let mutator = g.get_mutator(inc.address_for("users"));
+        mutator.put(vec![1.into(), "alice".into(), 1.into()]);
+        mutator.put(vec![2.into(), "bob".into(), 2.into()]);
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        let delete = inc.prepare_write("DELETE FROM users WHERE id = ?", &g).unwrap();
+        assert_eq!(delete.parameters(), &["id"]);
+        let rename = inc.prepare_write("UPDATE users SET name = ?, karma = 0 WHERE id = ?", &g)
+            .unwrap();
+        assert_eq!(rename.parameters(), &["name", "id"]);
+        assert!(rename.execute(&["carol".into()]).is_err());
+
+        delete.execute(&[1.into()]).unwrap();
+        rename.execute(&["carol".into(), 2.into()]).unwrap();
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        let by_id = inc.prepare_read("by_id", &g).unwrap();
+        assert!(by_id.execute(&[1.into()]).unwrap().is_empty());
+        let rows = by_id.execute(&[2.into()]).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("name"), Some(&"carol".into()));
+        assert_eq!(rows[0].get("karma"), Some(&0.into()));
+
+        // rows can only be identified by their primary key
+        assert!(inc.prepare_write("DELETE FROM users WHERE name = ?", &g).is_err());
+        assert!(inc.prepare_write("UPDATE users SET id = ? WHERE id = ?", &g).is_err());
+    }
 }
@@ -19,8 +19,93 @@ pub struct NodeStats {
     pub process_ptime: u64,
 }
 
+/// Struct holding information about a single replay performed to populate new materialized
+/// state. Times are in nanoseconds.
+#[derive(Clone, Debug)]
+pub struct ReplayStats {
+    /// Identifier of the replay path. Also appears as `tag` in log messages about the replay.
+    pub tag: u32,
+    /// The nodes along the replay path, starting with the materialized node replayed from.
+    pub path: Vec<NodeAddress>,
+    /// The domains crossed by the replay, in the order they were traversed.
+    pub domains: Vec<domain::Index>,
+    /// Number of records that arrived at the domain of the replay's target node.
+    pub records: usize,
+    /// Approximate size in bytes of the records that arrived at the target's domain.
+    pub bytes: usize,
+    /// Time from the replay being set up until the target reported that it was done.
+    pub duration: u64,
+}
+
+/// The number of replays that are remembered for `GraphStats::replays`.
+pub const REPLAY_HISTORY: usize = 256;
+
 /// Struct holding statistics about an entire graph.
 #[derive(Debug)]
 pub struct GraphStats {
-    pub domains: HashMap<domain::Index, (DomainStats, HashMap<NodeAddress, NodeStats>)>
+    pub domains: HashMap<domain::Index, (DomainStats, HashMap<NodeAddress, NodeStats>)>,
+    /// The most recent replays performed by migrations, oldest first. Only the last
+    /// `REPLAY_HISTORY` replays are kept; `metrics::GraphMetrics` summarizes all of them.
+    pub replays: Vec<ReplayStats>,
+}
+
+/// Estimated memory used by the state of a single materialized node or reader.
+///
+/// Byte counts are approximate: they include the rows themselves and the per-row overhead of each
+/// index over them, but not the unused capacity of the underlying hash tables.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodeMemory {
+    /// Number of rows held.
+    pub rows: usize,
+    /// Approximate number of bytes used by the rows and their indices.
+    pub bytes: usize,
+}
+
+impl NodeMemory {
+    /// Add the memory used by `other` to this.
+    pub fn add(&mut self, other: &NodeMemory) {
+        self.rows += other.rows;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Estimated memory used by the materialized nodes and readers of a domain.
+#[derive(Clone, Debug, Default)]
+pub struct DomainMemory {
+    /// The sum over all of the domain's nodes.
+    pub total: NodeMemory,
+    /// The memory used by each node that holds state.
+    pub nodes: HashMap<NodeAddress, NodeMemory>,
+}
+
+/// Estimated memory used by the materialized nodes and readers of an entire graph.
+#[derive(Clone, Debug, Default)]
+pub struct GraphMemory {
+    pub domains: HashMap<domain::Index, DomainMemory>,
+}
+
+impl GraphMemory {
+    /// The sum over all domains.
+    pub fn total(&self) -> NodeMemory {
+        let mut total = NodeMemory::default();
+        for d in self.domains.values() {
+            total.add(&d.total);
+        }
+        total
+    }
+
+    /// The memory used by the given node, if it holds any state.
+    pub fn node(&self, node: NodeAddress) -> Option<NodeMemory> {
+        self.domains.values().filter_map(|d| d.nodes.get(&node)).next().cloned()
+    }
+
+    /// All nodes that hold state, largest first.
+    pub fn largest(&self) -> Vec<(NodeAddress, NodeMemory)> {
+        let mut nodes: Vec<_> = self.domains
+            .values()
+            .flat_map(|d| d.nodes.iter().map(|(&n, &m)| (n, m)))
+            .collect();
+        nodes.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
+        nodes
+    }
 }
@@ -0,0 +1,284 @@
+//! Sampled tracing of reads through getters.
+
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time;
+
+use flow::data::DataType;
+use ops::Datas;
+
+/// A histogram of non-negative values, with one bucket per power of two.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    // buckets[i] counts the values v with 2^(i-1) <= v < 2^i, and buckets[0] counts zeroes
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// Record a single value.
+    pub fn record(&mut self, v: u64) {
+        let bucket = 64 - v.leading_zeros() as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += v;
+        if v > self.max {
+            self.max = v;
+        }
+    }
+
+    /// The number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean of the values recorded, or 0 if there are none.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// The largest value recorded, or 0 if there are none.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The sum of the values recorded.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// Add all the values recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (b, &n) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *b += n;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        if other.max > self.max {
+            self.max = other.max;
+        }
+    }
+
+    /// The upper bound of every bucket that has been used so far, along with the number of
+    /// values recorded that are no greater than that bound, in increasing order of bound.
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        let mut seen = 0;
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| {
+                seen += n;
+                (bucket_bound(i), seen)
+            })
+            .collect()
+    }
+
+    /// An upper bound on the given percentile (between 0 and 100) of the values recorded.
+    ///
+    /// Since values are only recorded by their power of two, the bound is within a factor of two
+    /// of the true percentile.
+    pub fn percentile(&self, p: f64) -> u64 {
+        let rank = ((p / 100.0) * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank && n > 0 {
+                let bound = bucket_bound(i);
+                return if bound < self.max { bound } else { self.max };
+            }
+        }
+        self.max
+    }
+}
+
+/// The largest value that falls into the `i`th bucket of a `Histogram`.
+fn bucket_bound(i: usize) -> u64 {
+    match i {
+        0 => 0,
+        64 => u64::max_value(),
+        i => (1u64 << i) - 1,
+    }
+}
+
+/// Statistics about the sampled reads of a single view.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadStats {
+    /// How long each lookup took, in nanoseconds.
+    pub latency: Histogram,
+    /// How many rows each lookup returned.
+    pub rows: Histogram,
+    /// Approximately how many bytes each lookup copied out of the reader.
+    pub bytes: Histogram,
+    /// The number of lookups that were answered by the reader's state.
+    pub hits: u64,
+    /// The number of lookups that the reader could not answer, for example because its state
+    /// was not yet ready, or because the key had been evicted. Applications need to fall back to
+    /// some other source for these.
+    pub misses: u64,
+}
+
+/// Records statistics about a sample of the reads through the getters for a view.
+///
+/// Every reader has a tracer, which is shared by all the getters for it. Tracing is disabled
+/// until a sampling rate is set with `sample_every`.
+#[derive(Default)]
+pub struct ReadTracer {
+    every: AtomicUsize,
+    calls: AtomicUsize,
+    stats: Mutex<ReadStats>,
+
+    // counted for every read, sampled or not
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ReadTracer {
+    /// Sample one in every `every` reads from now on, or none if `every` is 0.
+    pub fn sample_every(&self, every: usize) {
+        self.every.store(every, Ordering::Relaxed);
+    }
+
+    /// Decide whether to sample the read that is about to happen, and if so, when it started.
+    pub fn sample(&self) -> Option<time::Instant> {
+        let every = self.every.load(Ordering::Relaxed);
+        if every != 0 && self.calls.fetch_add(1, Ordering::Relaxed) % every == 0 {
+            Some(time::Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Record the result of a sampled read that started at `start`.
+    pub fn record(&self, start: time::Instant, res: &Result<Datas, ()>) {
+        let took = start.elapsed();
+        let took = took.as_secs() * 1_000_000_000 + took.subsec_nanos() as u64;
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.latency.record(took);
+        match *res {
+            Ok(ref rs) => {
+                stats.hits += 1;
+                stats.rows.record(rs.len() as u64);
+                stats.bytes.record(rs.iter().map(|r| row_bytes(&r[..])).sum::<usize>() as u64);
+            }
+            Err(()) => stats.misses += 1,
+        }
+    }
+
+    /// Count the result of a read, whether or not it was sampled.
+    pub fn count(&self, res: &Result<Datas, ()>) {
+        if res.is_ok() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of reads counted so far that were answered by the reader (hits), and that were
+    /// not (misses).
+    pub fn lookups(&self) -> (u64, u64) {
+        (self.hits.load(Ordering::Relaxed) as u64, self.misses.load(Ordering::Relaxed) as u64)
+    }
+
+    /// The statistics recorded so far.
+    pub fn stats(&self) -> ReadStats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// The approximate number of bytes copied when cloning a row.
+fn row_bytes(r: &[DataType]) -> usize {
+    mem::size_of::<Vec<DataType>>() +
+    r.iter()
+        .map(|d| match *d {
+            DataType::Text(ref t) => mem::size_of::<DataType>() + t.to_bytes().len(),
+            DataType::Bytes(ref b) => mem::size_of::<DataType>() + b.len(),
+            _ => mem::size_of::<DataType>(),
+        })
+        .sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_buckets() {
+        let mut h = Histogram::default();
+        assert_eq!(h.percentile(50.0), 0);
+        for v in &[0, 1, 2, 3, 100, 1000] {
+            h.record(*v);
+        }
+        assert_eq!(h.count(), 6);
+        assert_eq!(h.max(), 1000);
+        assert!((h.mean() - 1106.0 / 6.0).abs() < 1e-9);
+        assert_eq!(h.percentile(50.0), 3);
+        assert_eq!(h.percentile(80.0), 127);
+        assert_eq!(h.percentile(100.0), 1000);
+        assert_eq!(h.sum(), 1106);
+        assert_eq!(h.cumulative(),
+                   vec![(0, 1), (1, 2), (3, 4), (7, 4), (15, 4), (31, 4), (63, 4), (127, 5),
+                        (255, 5), (511, 5), (1023, 6)]);
+    }
+
+    #[test]
+    fn it_merges() {
+        let mut a = Histogram::default();
+        a.record(0);
+        let mut b = Histogram::default();
+        b.record(u64::max_value());
+        a.merge(&b);
+        assert_eq!(a.count(), 2);
+        assert_eq!(a.max(), u64::max_value());
+        assert_eq!(a.percentile(100.0), u64::max_value());
+        assert_eq!(a.cumulative().last(), Some(&(u64::max_value(), 2)));
+    }
+
+    #[test]
+    fn it_samples() {
+        let t = ReadTracer::default();
+        assert_eq!(t.sample(), None);
+
+        t.sample_every(3);
+        for i in 0..7 {
+            if let Some(start) = t.sample() {
+                let res = if i == 3 {
+                    Err(())
+                } else {
+                    Ok(vec![vec![i.into()]])
+                };
+                t.record(start, &res);
+            }
+        }
+
+        // only reads 0, 3, and 6 are sampled
+        let stats = t.stats();
+        assert_eq!(stats.latency.count(), 3);
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.rows.count(), 2);
+        assert_eq!(stats.rows.max(), 1);
+
+        t.sample_every(0);
+        assert_eq!(t.sample(), None);
+        assert_eq!(t.stats(), stats);
+
+        t.count(&Ok(vec![]));
+        t.count(&Err(()));
+        t.count(&Ok(vec![]));
+        assert_eq!(t.lookups(), (2, 1));
+    }
+}
@@ -0,0 +1,239 @@
+//! Network channels between processes.
+//!
+//! Domains normally receive packets over in-process channels. This module carries the packets that
+//! hold data over TCP instead, so that writes can be fed into the domains of a `Blender` running in
+//! another process, or on another machine. A `Blender` accepts connections once `Blender::listen`
+//! has been called, and `connect` gives a channel that looks like any other domain channel, but
+//! forwards everything sent on it to a domain of the `Blender` at the other end. Both ends refer to
+//! nodes by their addresses, so the graph at the other end must be set up by the same sequence of
+//! migrations.
+//!
+//! Every packet is sent as a header line, followed by one line per record. Rows are encoded in the
+//! same way as they are in persisted logs (see `persistence::encode`). Only regular data-flow
+//! updates can be sent; transactions and control messages rely on channels back to the sender,
+//! which cannot cross process boundaries.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use flow::{domain, persistence, NodeAddress};
+use flow::payload::{Link, Packet};
+use flow::prelude::*;
+
+use petgraph::graph::NodeIndex;
+use slog::Logger;
+
+/// The channels to the domains of a `Blender` that packets received over the network are handed to.
+pub type Inputs = Arc<Mutex<HashMap<domain::Index, mpsc::SyncSender<Packet>>>>;
+
+/// The number of packets that may be queued up for a connection before senders block.
+const QUEUE_LENGTH: usize = 256;
+
+fn invalid<S: Into<String>>(msg: S) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn decode_addr(s: &str) -> io::Result<NodeAddress> {
+    let mut chars = s.chars();
+    let kind = chars.next();
+    match (kind, chars.as_str().parse().ok()) {
+        (Some('g'), Some(id)) => Ok(NodeAddress::make_global(NodeIndex::new(id))),
+        (Some('l'), Some(id)) => Ok(NodeAddress::make_local(id)),
+        _ => Err(invalid(format!("invalid node address: {}", s))),
+    }
+}
+
+/// Write the data-flow update `p` for domain `domain` to `w`.
+pub fn write_packet<W: Write>(w: &mut W, domain: domain::Index, p: &Packet) -> io::Result<()> {
+    let (link, data) = match *p {
+        // a tracer's channel cannot cross process boundaries, so tracing stops here
+        Packet::Message { ref link, ref data, .. } => (link, data),
+        ref p => return Err(invalid(format!("{:?} cannot be sent over the network", p))),
+    };
+
+    let mut out = format!("M {} {} {} {}\n",
+                          domain.index(),
+                          link.src,
+                          link.dst,
+                          data.len());
+    for r in data.iter() {
+        match *r {
+            Record::Positive(ref row) => persistence::encode('+', &row[..], &mut out),
+            Record::Negative(ref row) => persistence::encode('-', &row[..], &mut out),
+            Record::DeleteRequest(ref key) => {
+                out.push('D');
+                persistence::encode('+', &key[..], &mut out);
+            }
+            Record::TruncateRequest => out.push_str("T\n"),
+        }
+    }
+    w.write_all(out.as_bytes())
+}
+
+/// Read a data-flow update written by `write_packet` from `r`, along with the domain it is for.
+///
+/// Returns `None` once the other end has closed the connection.
+pub fn read_packet<R: BufRead>(r: &mut R) -> io::Result<Option<(domain::Index, Packet)>> {
+    let mut line = String::new();
+    if r.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let header: Vec<_> = line.trim_right_matches('\n').split(' ').collect();
+    if header.len() != 5 || header[0] != "M" {
+        return Err(invalid(format!("invalid packet header: {}", line)));
+    }
+    let domain: usize = header[1].parse().map_err(|_| invalid("invalid domain index"))?;
+    let src = decode_addr(header[2])?;
+    let dst = decode_addr(header[3])?;
+    let n: usize = header[4].parse().map_err(|_| invalid("invalid record count"))?;
+
+    let mut data = Vec::with_capacity(n);
+    for _ in 0..n {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated packet"));
+        }
+        let line = line.trim_right_matches('\n');
+        let record = if line == "T" {
+            Record::TruncateRequest
+        } else if line.starts_with('D') {
+            let (_, key) = persistence::decode(&line[1..]).map_err(invalid)?;
+            Record::DeleteRequest(key)
+        } else {
+            let (positive, row) = persistence::decode(line).map_err(invalid)?;
+            (row, positive).into()
+        };
+        data.push(record);
+    }
+
+    let p = Packet::Message {
+        link: Link::new(src, dst),
+        data: data.into(),
+        trace: None,
+    };
+    Ok(Some((domain.into(), p)))
+}
+
+/// Connect to the `Blender` listening on `addr`, and return a channel whose packets are handed to
+/// its domain `domain`.
+///
+/// The packets are sent by a thread of their own, which exits once the channel is dropped, or the
+/// connection fails. Sending on the channel fails from then on.
+pub fn connect<A: ToSocketAddrs>(addr: A,
+                                 domain: domain::Index)
+                                 -> io::Result<mpsc::SyncSender<Packet>> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LENGTH);
+    thread::Builder::new()
+        .name(format!("remote{}", domain.index()))
+        .spawn(move || {
+            let mut w = BufWriter::new(stream);
+            while let Ok(p) = rx.recv() {
+                if write_packet(&mut w, domain, &p).is_err() {
+                    return;
+                }
+                // pick up anything else that has queued up before flushing
+                while let Ok(p) = rx.try_recv() {
+                    if write_packet(&mut w, domain, &p).is_err() {
+                        return;
+                    }
+                }
+                if w.flush().is_err() {
+                    return;
+                }
+            }
+        })?;
+    Ok(tx)
+}
+
+/// Accept connections on `listener`, and hand the packets received on them to the domains in
+/// `inputs`.
+pub fn serve(listener: TcpListener, inputs: Inputs, log: Logger) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(log, "failed to accept connection"; "err" => format!("{}", e));
+                continue;
+            }
+        };
+        let inputs = inputs.clone();
+        let log = log.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|a| format!("{}", a)).unwrap_or_default();
+            debug!(log, "accepted connection"; "peer" => peer.as_str());
+            let mut r = BufReader::new(stream);
+            loop {
+                match read_packet(&mut r) {
+                    Ok(Some((domain, p))) => {
+                        let tx = inputs.lock().unwrap().get(&domain).cloned();
+                        match tx {
+                            Some(tx) => {
+                                if tx.send(p).is_err() {
+                                    return;
+                                }
+                            }
+                            None => {
+                                warn!(log, "dropping packet for unknown domain";
+                                      "domain" => domain.index());
+                            }
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!(log, "closing connection";
+                              "peer" => peer.as_str(), "err" => format!("{}", e));
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn it_roundtrips_packets() {
+        let data: Records = vec![Record::Positive(Arc::new(vec![1.into(), "a\tb".into()])),
+                                 Record::Negative(Arc::new(vec![2.into(), DataType::None])),
+                                 Record::DeleteRequest(vec![3.into()]),
+                                 Record::TruncateRequest]
+            .into();
+        let p = Packet::Message {
+            link: Link::new(NodeAddress::mock_global(0.into()), NodeAddress::mock_local(4)),
+            data: data.clone(),
+            trace: None,
+        };
+
+        let mut buf = Vec::new();
+        write_packet(&mut buf, 3.into(), &p).unwrap();
+        write_packet(&mut buf, 1.into(), &p).unwrap();
+
+        let mut r = Cursor::new(buf);
+        for &d in &[3, 1] {
+            let (domain, p) = read_packet(&mut r).unwrap().unwrap();
+            assert_eq!(domain.index(), d);
+            assert_eq!(p.link().src, NodeAddress::mock_global(0.into()));
+            assert_eq!(p.link().dst, NodeAddress::mock_local(4));
+            assert_eq!(p.data(), &data);
+        }
+        assert!(read_packet(&mut r).unwrap().is_none());
+    }
+
+    #[test]
+    fn it_rejects_control_packets() {
+        let mut buf = Vec::new();
+        assert!(write_packet(&mut buf, 0.into(), &Packet::Quit).is_err());
+        assert!(buf.is_empty());
+    }
+}
@@ -318,12 +318,11 @@
 #![feature(pub_restricted)]
 #![feature(conservative_impl_trait)]
 #![feature(try_from)]
-#![feature(float_extras)]
 #![deny(missing_docs)]
-#![cfg_attr(feature="b_netsoup", feature(plugin))]
-#![cfg_attr(feature="b_netsoup", plugin(tarpc_plugins))]
+#![cfg_attr(feature="netsoup", feature(plugin))]
+#![cfg_attr(feature="netsoup", plugin(tarpc_plugins))]
 
-#[cfg(feature="b_netsoup")]
+#[cfg(feature="netsoup")]
 #[macro_use]
 extern crate serde_derive;
 
@@ -331,9 +330,11 @@ extern crate serde_derive;
 extern crate slog;
 extern crate slog_term;
 
+extern crate chrono;
 extern crate fnv;
 extern crate evmap;
 extern crate arccstr;
+extern crate unicode_normalization;
 
 extern crate itertools;
 extern crate petgraph;
@@ -349,11 +350,11 @@ extern crate rustc_serialize;
 extern crate rustful;
 
 #[macro_use]
-#[cfg(feature="b_netsoup")]
+#[cfg(feature="netsoup")]
 extern crate tarpc;
-#[cfg(feature="b_netsoup")]
+#[cfg(feature="netsoup")]
 extern crate futures;
-#[cfg(feature="b_netsoup")]
+#[cfg(feature="netsoup")]
 extern crate tokio_core;
 
 mod checktable;
@@ -362,28 +363,63 @@ mod ops;
 mod backlog;
 mod recipe;
 
+pub mod prelude;
+
 pub use checktable::{Token, TransactionResult};
-pub use flow::{Blender, Migration, NodeAddress, Mutator};
-pub use flow::node::StreamUpdate;
-pub use flow::sql_to_flow::{SqlIncorporator, ToFlowParts};
-pub use flow::data::DataType;
+pub use flow::{Blender, Migration, PreparedMigration, NodeAddress, Mutator, OrderedMutator,
+               Backpressure, Busy, Batching, ConditionFailed, ReplaySource, ReplayPacing,
+               ReplayInterleave, WriteToken};
+pub use flow::node::{BaseWrite, PreparedQuery, StreamUpdate, Subscription, SwapPolicy};
+pub use flow::advisor::{Advisor, AdvisorPolicy, DomainLoad, Recommendation};
+pub use flow::trace::{Histogram, ReadStats};
+pub use flow::statistics::{DomainMemory, GraphMemory, NodeMemory};
+pub use flow::metrics::{Collector, DomainMetrics, Exporter, GraphMetrics, NodeMetrics,
+                       ReaderMetrics};
+#[cfg(feature="prometheus")]
+pub use flow::metrics::prometheus;
+pub use flow::health::{DomainHealth, Health};
+pub use flow::getter::{GetterHandle, SnapshotHandle};
+pub use flow::sink::{Sink, SinkPolicy};
+pub use flow::persistence::PersistencePolicy;
+pub use flow::diff::{GraphDiff, GraphSummary, NodeSummary};
+pub use flow::plan::{MigrationPlan, PlannedReplay};
+pub use flow::explain::{ExplainedNode, Explanation, ReadAccess};
+pub use flow::provenance::{TraceEvent, TraceKind, Tracer};
+pub use flow::placement::{Placement, Unplaced, SeparateDomains, ParentDomain};
+pub use flow::sharding::{Sharded, ShardedMutator};
+pub use flow::prepared::{PreparedRead, PreparedWrite, TypedRow};
+pub use flow::sql_to_flow::{QueryBatch, SqlIncorporator, StagedQueries, ToFlowParts};
+pub use flow::sql::capabilities::UnsupportedFeature;
+pub use flow::sql::optimizer::{EliminateIdentityNodes, Optimizer, PruneUnusedNodes,
+                               PushDownFilters, Rule};
+pub use flow::sql::planner::{Catalog, GroupedFunction, PlanNode, PlanOp, QueryPlan, plan_query};
+pub use flow::data::{ColumnType, DataType};
+pub use backlog::{Budget, Eviction, EvictionPolicy};
 pub use ops::Datas;
-pub use ops::base::Base;
+pub use ops::base::{Base, ColumnSpec, KeyConflict, RejectReason, Rejection, TextPolicy};
 pub use ops::grouped::aggregate::{Aggregator, Aggregation};
 pub use ops::grouped::concat::{GroupConcat, TextComponent};
 pub use ops::grouped::extremum::{Extremum, ExtremumOperator};
 pub use ops::identity::Identity;
 pub use ops::permute::Permute;
+pub use ops::project::{BinaryOperator, DatePart, Expression, Project};
 pub use ops::join::Builder as JoinBuilder;
+pub use ops::join::{Comparison, HighFanout};
 pub use ops::union::Union;
 pub use ops::latest::Latest;
+pub use ops::topk::TopK;
+pub use ops::window::Window;
 pub use ops::filter::Filter;
+pub use ops::shard::Shard;
+pub use ops::predicate::{Operand, Predicate, PredicateFilter};
+pub use ops::sequence::Sequence;
+pub use ops::semijoin::SemiJoin;
 pub use recipe::Recipe;
 
 #[cfg(feature="web")]
 /// web provides a simple REST HTTP server for reading from and writing to the data flow graph.
 pub mod web;
 
-#[cfg(feature="b_netsoup")]
-/// srv provides a networked RPC server for accessing the data flow graph.
+#[cfg(feature="netsoup")]
+/// srv provides a networked RPC server for accessing the data flow graph, and a client for it.
 pub mod srv;
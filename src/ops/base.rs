@@ -1,4 +1,123 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str;
+use std::sync::{self, mpsc};
+
+use unicode_normalization::UnicodeNormalization;
+
+use flow::data::ColumnType;
+
+/// A `TextPolicy` determines how a base node checks and cleans up the text values written to it.
+///
+/// Text values that are not valid UTF-8 are always rejected, since they would otherwise compare
+/// unequal to the (lossily decoded) strings they are displayed as.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextPolicy {
+    /// Normalize all text to Unicode Normalization Form C, so that strings that look the same
+    /// also compare equal when used as keys downstream. Enabled by default.
+    pub normalize: bool,
+    /// The maximum number of characters allowed in each of the given columns.
+    pub max_len: HashMap<usize, usize>,
+    /// Truncate text values that are too long, rather than rejecting the rows they are part of.
+    pub truncate: bool,
+}
+
+impl Default for TextPolicy {
+    fn default() -> Self {
+        TextPolicy {
+            normalize: true,
+            max_len: HashMap::new(),
+            truncate: false,
+        }
+    }
+}
+
+/// Constraints on the values of one of a base node's columns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnSpec {
+    /// The type of the column's values, if it is restricted to one. Written values of other types
+    /// are coerced to it where that loses no information (e.g., integers to reals), and rejected
+    /// otherwise.
+    pub ty: Option<ColumnType>,
+    /// The value to fill in for writes that leave out this column. Without a default, such
+    /// writes get `DataType::None`.
+    pub default: Option<DataType>,
+    /// Whether the column may hold `DataType::None`.
+    pub nullable: bool,
+}
+
+impl Default for ColumnSpec {
+    fn default() -> Self {
+        ColumnSpec {
+            ty: None,
+            default: None,
+            nullable: true,
+        }
+    }
+}
+
+/// Why a base node refused to apply a row.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RejectReason {
+    /// The text in the given column is not valid UTF-8.
+    InvalidUtf8(usize),
+    /// The text in the given column is longer than the given number of characters.
+    TooLong(usize, usize),
+    /// A row with the given primary key already exists.
+    DuplicateKey(Vec<DataType>),
+    /// There is no row with the primary key a deletion was given.
+    NoSuchKey,
+    /// The given column is NOT NULL, but has no value.
+    Null(usize),
+    /// The value in the given column cannot be coerced to the column's type.
+    WrongType(usize, ColumnType),
+    /// The row has more values than the base node's given number of columns.
+    TooManyValues(usize),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RejectReason::InvalidUtf8(col) => write!(f, "column {} is not valid UTF-8", col),
+            RejectReason::TooLong(col, max) => {
+                write!(f, "column {} is longer than {} characters", col, max)
+            }
+            RejectReason::DuplicateKey(ref key) => {
+                write!(f, "a row with key {:?} already exists", key)
+            }
+            RejectReason::NoSuchKey => write!(f, "no row has the given key"),
+            RejectReason::Null(col) => write!(f, "column {} may not be NULL", col),
+            RejectReason::WrongType(col, ty) => write!(f, "column {} must be of type {}", col, ty),
+            RejectReason::TooManyValues(n) => write!(f, "row has more than {} values", n),
+        }
+    }
+}
+
+/// A row that a base node refused to apply.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    /// The rejected row, or the key of a rejected deletion.
+    pub row: Vec<DataType>,
+    /// Why the row was rejected.
+    pub reason: RejectReason,
+}
+
+/// What a base node does with an inserted row whose primary key is that of a row it already has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyConflict {
+    /// Keep both rows. This is the default.
+    Allow,
+    /// Reject the inserted row.
+    Reject,
+    /// Replace the existing row with the inserted one.
+    Overwrite,
+}
+
+impl Default for KeyConflict {
+    fn default() -> Self {
+        KeyConflict::Allow
+    }
+}
 
 /// Base is used to represent the root nodes of the distributary data flow graph.
 ///
@@ -9,6 +128,10 @@ use std::collections::HashMap;
 pub struct Base {
     primary_key: Option<Vec<usize>>,
     us: Option<NodeAddress>,
+    columns: Option<Vec<ColumnSpec>>,
+    text: Option<TextPolicy>,
+    conflict: KeyConflict,
+    rejections: sync::Arc<sync::Mutex<Vec<mpsc::Sender<Rejection>>>>,
 }
 
 impl Base {
@@ -16,9 +139,271 @@ impl Base {
     pub fn new(primary_key: Vec<usize>) -> Self {
         Base {
             primary_key: Some(primary_key),
-            us: None,
+            ..Base::default()
         }
     }
+
+    /// Declare the type, default, and nullability of each of this base node's columns.
+    ///
+    /// Written rows that leave out trailing columns have them filled in, and values are coerced to
+    /// their column's type. Rows that leave a NOT NULL column empty, or that hold values that
+    /// cannot be coerced, are not applied, but are instead sent to every channel obtained with
+    /// `rejections`.
+    pub fn with_columns(mut self, columns: Vec<ColumnSpec>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    /// Check and clean up all text values written to this base node according to `policy`.
+    ///
+    /// Rows that violate the policy are not applied, but are instead sent to every channel
+    /// obtained with `rejections`.
+    pub fn with_text_policy(mut self, policy: TextPolicy) -> Self {
+        self.text = Some(policy);
+        self
+    }
+
+    /// Decide what to do with inserted rows whose primary key is that of an existing row.
+    ///
+    /// Rows rejected because of their key are sent to every channel obtained with `rejections`.
+    pub fn with_key_conflict(mut self, conflict: KeyConflict) -> Self {
+        assert!(conflict == KeyConflict::Allow || self.primary_key.is_some(),
+                "only base nodes with a primary key can detect conflicting keys");
+        self.conflict = conflict;
+        self
+    }
+
+    /// Obtain a channel that receives every row this base node rejects.
+    ///
+    /// Once the base node has been added to a migration, further channels are obtained with
+    /// `Blender::rejections`. Like `Blender::on_write`, the channel is not bounded.
+    pub fn rejections(&self) -> mpsc::Receiver<Rejection> {
+        let (tx, rx) = mpsc::channel();
+        self.rejections.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Fill in defaults for the columns left out of the given records, and reject the ones that
+    /// violate the base's column constraints.
+    fn fill_columns(&self, rs: Records) -> Records {
+        let columns = match self.columns {
+            Some(ref columns) => columns,
+            None => return rs,
+        };
+
+        let mut rejected = Vec::new();
+        let rs = rs.into_iter()
+            .filter_map(|r| {
+                let (positive, u) = match r {
+                    Record::Positive(u) => (true, u),
+                    Record::Negative(u) => (false, u),
+                    r => return Some(r),
+                };
+
+                let filled = fill_row(columns, &u[..]);
+                let u = match filled {
+                    Ok(Some(filled)) => sync::Arc::new(filled),
+                    Ok(None) => u,
+                    Err(reason) => {
+                        rejected.push(Rejection {
+                            row: (*u).clone(),
+                            reason: reason,
+                        });
+                        return None;
+                    }
+                };
+                Some(if positive {
+                    Record::Positive(u)
+                } else {
+                    Record::Negative(u)
+                })
+            })
+            .collect();
+
+        self.reject(rejected);
+        rs
+    }
+
+    /// Clean up the text in the given records according to the base's `TextPolicy`, and reject
+    /// the ones that violate it.
+    fn clean_text(&self, rs: Records) -> Records {
+        let policy = match self.text {
+            Some(ref policy) => policy,
+            None => return rs,
+        };
+
+        let mut rejected = Vec::new();
+        let rs = rs.into_iter()
+            .filter_map(|r| {
+                let cleaned = match r {
+                    Record::Positive(ref u) => {
+                        clean_row(policy, &u[..], 0..u.len())
+                            .map(|u| Record::Positive(sync::Arc::new(u)))
+                    }
+                    Record::Negative(ref u) => {
+                        clean_row(policy, &u[..], 0..u.len())
+                            .map(|u| Record::Negative(sync::Arc::new(u)))
+                    }
+                    Record::DeleteRequest(ref key) => {
+                        // the key holds the values of the primary key columns
+                        let cols = self.primary_key
+                            .clone()
+                            .unwrap_or_else(|| (0..key.len()).collect());
+                        clean_row(policy, &key[..], cols).map(Record::DeleteRequest)
+                    }
+                    Record::TruncateRequest => Ok(Record::TruncateRequest),
+                };
+
+                match cleaned {
+                    Ok(r) => Some(r),
+                    Err(reason) => {
+                        let row = match r {
+                            Record::Positive(u) |
+                            Record::Negative(u) => (*u).clone(),
+                            Record::DeleteRequest(key) => key,
+                            Record::TruncateRequest => unreachable!(),
+                        };
+                        rejected.push(Rejection {
+                            row: row,
+                            reason: reason,
+                        });
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        self.reject(rejected);
+        rs
+    }
+
+    /// Apply the base's `KeyConflict` policy to inserted rows whose key is already present.
+    fn enforce_key(&self, rs: Records, state: &StateMap) -> Records {
+        let cols = match self.primary_key {
+            Some(ref cols) if self.conflict != KeyConflict::Allow => cols,
+            _ => return rs,
+        };
+        let db = state.get(self.us.as_ref().unwrap().as_local())
+            .expect("base must have its own state materialized to detect conflicting keys");
+
+        // earlier records in the same batch have not yet been applied to the state, so keep track
+        // of the row each key they touch maps to (if any)
+        let mut pending: HashMap<Vec<DataType>, Option<sync::Arc<Vec<DataType>>>> = HashMap::new();
+        let mut rejected = Vec::new();
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs {
+            let (positive, u) = match r {
+                Record::Positive(u) => (true, u),
+                Record::Negative(u) => (false, u),
+                r => unreachable!("{:?} should have been resolved to a row", r),
+            };
+            let key: Vec<_> = cols.iter().map(|&c| u[c].clone()).collect();
+            if !positive {
+                pending.insert(key, None);
+                out.push(Record::Negative(u));
+                continue;
+            }
+
+            let existing = match pending.get(&key) {
+                Some(row) => row.clone(),
+                None => db.lookup(&cols[..], &KeyType::from(&key[..])).get(0).cloned(),
+            };
+            if let Some(old) = existing {
+                if self.conflict == KeyConflict::Reject {
+                    rejected.push(Rejection {
+                        row: (*u).clone(),
+                        reason: RejectReason::DuplicateKey(key),
+                    });
+                    continue;
+                }
+                out.push(Record::Negative(old));
+            }
+            pending.insert(key, Some(u.clone()));
+            out.push(Record::Positive(u));
+        }
+
+        self.reject(rejected);
+        out.into()
+    }
+
+    /// Send the given rejected rows to every channel obtained with `rejections`.
+    fn reject(&self, rejected: Vec<Rejection>) {
+        if rejected.is_empty() {
+            return;
+        }
+
+        // remove any channels where the receiver has hung up
+        let mut txs = self.rejections.lock().unwrap();
+        txs.retain(|tx| rejected.iter().all(|r| tx.send(r.clone()).is_ok()));
+    }
+}
+
+/// Fill in defaults for the columns left out of `row`, if any, coerce its values to their
+/// columns' types, and check that no NOT NULL column is empty.
+fn fill_row(columns: &[ColumnSpec],
+            row: &[DataType])
+            -> Result<Option<Vec<DataType>>, RejectReason> {
+    if row.len() > columns.len() {
+        return Err(RejectReason::TooManyValues(columns.len()));
+    }
+
+    let mut changed = row.len() < columns.len();
+    let mut filled = Vec::with_capacity(columns.len());
+    for (col, c) in columns.iter().enumerate() {
+        let v = match row.get(col) {
+            Some(v) => v.clone(),
+            None => c.default.clone().unwrap_or(DataType::None),
+        };
+        if !c.nullable && v == DataType::None {
+            return Err(RejectReason::Null(col));
+        }
+        let v = match c.ty {
+            Some(ty) if ColumnType::of(&v) != Some(ty) && v != DataType::None => {
+                changed = true;
+                ty.coerce(&v).ok_or(RejectReason::WrongType(col, ty))?
+            }
+            _ => v,
+        };
+        filled.push(v);
+    }
+    Ok(if changed { Some(filled) } else { None })
+}
+
+/// Clean up the text values in `row`, whose values belong to the columns `cols`.
+fn clean_row<I>(policy: &TextPolicy,
+                row: &[DataType],
+                cols: I)
+                -> Result<Vec<DataType>, RejectReason>
+    where I: IntoIterator<Item = usize>
+{
+    row.iter().zip(cols).map(|(v, col)| clean_value(policy, col, v)).collect()
+}
+
+fn clean_value(policy: &TextPolicy, col: usize, v: &DataType) -> Result<DataType, RejectReason> {
+    let s = match *v {
+        DataType::Text(ref s) => s.to_str(),
+        DataType::TinyText(ref bts) => {
+            let len = bts.iter().position(|&b| b == 0).unwrap_or(bts.len());
+            str::from_utf8(&bts[..len])
+        }
+        _ => return Ok(v.clone()),
+    };
+    let s = s.map_err(|_| RejectReason::InvalidUtf8(col))?;
+
+    let mut s: String = if policy.normalize {
+        s.nfc().collect()
+    } else {
+        s.to_owned()
+    };
+    if let Some(&max) = policy.max_len.get(&col) {
+        if s.chars().count() > max {
+            if !policy.truncate {
+                return Err(RejectReason::TooLong(col, max));
+            }
+            s = s.chars().take(max).collect();
+        }
+    }
+    Ok(s.into())
 }
 
 impl Default for Base {
@@ -26,6 +411,10 @@ impl Default for Base {
         Base {
             primary_key: None,
             us: None,
+            columns: None,
+            text: None,
+            conflict: KeyConflict::default(),
+            rejections: sync::Arc::default(),
         }
     }
 }
@@ -61,10 +450,18 @@ impl Ingredient for Base {
                 _: &DomainNodes,
                 state: &StateMap)
                 -> Records {
-        rs.into_iter()
-            .map(|r| match r {
-                Record::Positive(u) => Record::Positive(u),
-                Record::Negative(u) => Record::Negative(u),
+        let rs = self.fill_columns(rs);
+        let mut missing = Vec::new();
+        let rs: Records = self.clean_text(rs)
+            .into_iter()
+            .flat_map(|r| match r {
+                Record::Positive(u) => vec![Record::Positive(u)],
+                Record::Negative(u) => vec![Record::Negative(u)],
+                Record::TruncateRequest => {
+                    let db = state.get(self.us.as_ref().unwrap().as_local())
+                        .expect("base must have its own state materialized to support truncation");
+                    db.all_rows().into_iter().map(Record::Negative).collect()
+                }
                 Record::DeleteRequest(key) => {
                     let cols = self.primary_key
                         .as_ref()
@@ -72,12 +469,19 @@ impl Ingredient for Base {
                     let db = state.get(self.us.as_ref().unwrap().as_local())
                         .expect("base must have its own state materialized to support deletions");
                     let rows = db.lookup(cols.as_slice(), &KeyType::from(&key[..]));
-                    assert_eq!(rows.len(), 1);
-
-                    Record::Negative(rows[0].clone())
+                    if rows.is_empty() {
+                        // deleting a row that does not exist is the caller's mistake, not ours
+                        missing.push(Rejection {
+                            row: key,
+                            reason: RejectReason::NoSuchKey,
+                        });
+                    }
+                    rows.iter().cloned().map(Record::Negative).collect()
                 }
             })
-            .collect()
+            .collect();
+        self.reject(missing);
+        self.enforce_key(rs, state)
     }
 
     fn suggest_indexes(&self, n: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
@@ -96,6 +500,31 @@ impl Ingredient for Base {
         true
     }
 
+    fn rejections(&self) -> Option<mpsc::Receiver<Rejection>> {
+        Some(Base::rejections(self))
+    }
+
+    fn column_type(&self, column: usize) -> Option<ColumnType> {
+        self.columns.as_ref().and_then(|cs| cs.get(column)).and_then(|c| c.ty)
+    }
+
+    fn add_column(&mut self, column: usize, default: DataType) {
+        // a base without column specifications accepts rows of any length, so it gets
+        // unrestricted ones for its existing columns. rows that leave those out are then padded
+        // with `DataType::None`, as they are for bases that declare their columns.
+        let mut columns = self.columns
+            .take()
+            .unwrap_or_else(|| vec![ColumnSpec::default(); column]);
+        assert_eq!(columns.len(),
+                   column,
+                   "columns can only be added after all the existing ones");
+        columns.push(ColumnSpec {
+            default: Some(default),
+            ..ColumnSpec::default()
+        });
+        self.columns = Some(columns);
+    }
+
     fn description(&self) -> String {
         "B".into()
     }
@@ -104,3 +533,219 @@ impl Ingredient for Base {
         unreachable!();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync;
+
+    use flow::data::ColumnType;
+    use flow::prelude::*;
+    use petgraph::graph::NodeIndex;
+
+    fn input(b: &mut Base, rs: Vec<Vec<DataType>>) -> Records {
+        input_with(b, rs, &StateMap::new())
+    }
+
+    fn input_with(b: &mut Base, rs: Vec<Vec<DataType>>, states: &StateMap) -> Records {
+        let rs = rs.into_iter().map(Record::from).collect();
+        let n = NodeAddress::mock_global(NodeIndex::new(0));
+        b.on_input(n, rs, &DomainNodes::default(), states)
+    }
+
+    // a base keyed by its first column, with its state holding the given rows
+    fn keyed(conflict: KeyConflict, rows: Vec<Vec<DataType>>) -> (Base, StateMap) {
+        let mut b = Base::new(vec![0]).with_key_conflict(conflict);
+        let us = NodeAddress::mock_local(0);
+        b.on_commit(us, &HashMap::new());
+
+        let mut state = State::default();
+        state.add_key(&[0]);
+        for r in rows {
+            state.insert(sync::Arc::new(r));
+        }
+        let mut states = StateMap::new();
+        states.insert(*us.as_local(), state);
+        (b, states)
+    }
+
+    fn max_len(col: usize, len: usize, truncate: bool) -> TextPolicy {
+        TextPolicy {
+            max_len: Some((col, len)).into_iter().collect(),
+            truncate: truncate,
+            ..TextPolicy::default()
+        }
+    }
+
+    #[test]
+    fn it_normalizes() {
+        let mut b = Base::default().with_text_policy(TextPolicy::default());
+        // an e followed by a combining acute accent is composed into a single é
+        let rs = input(&mut b, vec![vec![1.into(), "cafe\u{301}".into()]]);
+        assert_eq!(rs[0].rec(), &[1.into(), "caf\u{e9}".into()][..]);
+    }
+
+    #[test]
+    fn it_rejects() {
+        let mut b = Base::default().with_text_policy(max_len(1, 3, false));
+        let rejected = b.rejections();
+
+        let invalid = DataType::TinyText([0xff, 0, 0, 0, 0, 0, 0, 0]);
+        let rs = input(&mut b,
+                       vec![vec![1.into(), "abc".into()],
+                            vec![2.into(), "abcd".into()],
+                            vec![3.into(), invalid.clone()]]);
+        assert_eq!(rs.len(), 1);
+        assert_eq!(rs[0].rec(), &[1.into(), "abc".into()][..]);
+
+        let rejected: Vec<_> = rejected.try_iter().map(|r| r.row).collect();
+        assert_eq!(rejected,
+                   vec![vec![2.into(), "abcd".into()], vec![3.into(), invalid]]);
+    }
+
+    #[test]
+    fn it_truncates() {
+        let mut b = Base::default().with_text_policy(max_len(1, 3, true));
+        // lengths are counted in characters, not bytes
+        let rs = input(&mut b, vec![vec![1.into(), "añbcd".into()]]);
+        assert_eq!(rs[0].rec(), &[1.into(), "añb".into()][..]);
+    }
+
+    #[test]
+    fn it_rejects_duplicate_keys() {
+        let (mut b, states) = keyed(KeyConflict::Reject, vec![vec![1.into(), "a".into()]]);
+        let rejected = b.rejections();
+
+        let rs = input_with(&mut b,
+                            vec![vec![1.into(), "b".into()],
+                                 vec![2.into(), "c".into()],
+                                 vec![2.into(), "d".into()]],
+                            &states);
+        let expected: Records = vec![vec![2.into(), "c".into()]].into();
+        assert_eq!(rs, expected);
+
+        let rejected: Vec<_> = rejected.try_iter().map(|r| r.row).collect();
+        assert_eq!(rejected,
+                   vec![vec![1.into(), "b".into()], vec![2.into(), "d".into()]]);
+    }
+
+    #[test]
+    fn it_rejects_deletes_of_missing_keys() {
+        let (mut b, states) = keyed(KeyConflict::Allow, vec![vec![1.into(), "a".into()]]);
+        let rejected = b.rejections();
+
+        let rs = b.on_input(NodeAddress::mock_global(NodeIndex::new(0)),
+                            vec![Record::DeleteRequest(vec![2.into()]),
+                                 Record::DeleteRequest(vec![1.into()])]
+                                .into(),
+                            &DomainNodes::default(),
+                            &states);
+        let expected: Records = vec![(vec![1.into(), "a".into()], false)].into();
+        assert_eq!(rs, expected);
+
+        let rejected: Vec<_> = rejected.try_iter().collect();
+        assert_eq!(rejected,
+                   vec![Rejection {
+                            row: vec![2.into()],
+                            reason: RejectReason::NoSuchKey,
+                        }]);
+    }
+
+    #[test]
+    fn it_overwrites_duplicate_keys() {
+        let (mut b, states) = keyed(KeyConflict::Overwrite, vec![vec![1.into(), "a".into()]]);
+        let rs = input_with(&mut b,
+                            vec![vec![1.into(), "b".into()], vec![1.into(), "c".into()]],
+                            &states);
+        let expected: Records = vec![(vec![1.into(), "a".into()], false),
+                                     (vec![1.into(), "b".into()], true),
+                                     (vec![1.into(), "b".into()], false),
+                                     (vec![1.into(), "c".into()], true)]
+            .into();
+        assert_eq!(rs, expected);
+    }
+
+    #[test]
+    fn it_fills_defaults() {
+        let columns = vec![ColumnSpec::default(),
+                           ColumnSpec {
+                               default: Some(0.into()),
+                               nullable: false,
+                               ..ColumnSpec::default()
+                           },
+                           ColumnSpec::default()];
+        let mut b = Base::default().with_columns(columns);
+        let rejected = b.rejections();
+
+        let rs = input(&mut b,
+                       vec![vec![1.into()],
+                            vec![2.into(), 3.into()],
+                            vec![3.into(), DataType::None],
+                            vec![4.into(), 1.into(), 2.into(), 3.into()]]);
+        let expected: Records = vec![vec![1.into(), 0.into(), DataType::None],
+                                     vec![2.into(), 3.into(), DataType::None]]
+            .into();
+        assert_eq!(rs, expected);
+
+        let rejected: Vec<_> = rejected.try_iter().map(|r| r.reason).collect();
+        assert_eq!(rejected,
+                   vec![RejectReason::Null(1), RejectReason::TooManyValues(3)]);
+    }
+
+    #[test]
+    fn it_adds_columns() {
+        let mut b = Base::default();
+        let rejected = b.rejections();
+        b.add_column(2, 0.into());
+
+        let rs = input(&mut b,
+                       vec![vec![1.into(), "a".into()],
+                            vec![2.into(), "b".into(), 3.into()],
+                            vec![3.into(), "c".into(), 4.into(), 5.into()]]);
+        let expected: Records = vec![vec![1.into(), "a".into(), 0.into()],
+                                     vec![2.into(), "b".into(), 3.into()]]
+            .into();
+        assert_eq!(rs, expected);
+
+        // declared columns are kept
+        let mut b = Base::default().with_columns(vec![ColumnSpec {
+                                                          nullable: false,
+                                                          ..ColumnSpec::default()
+                                                      }]);
+        b.add_column(1, "x".into());
+        let rs = input(&mut b, vec![vec![1.into()], vec![DataType::None]]);
+        let expected: Records = vec![vec![1.into(), "x".into()]].into();
+        assert_eq!(rs, expected);
+
+        let rejected: Vec<_> = rejected.try_iter().map(|r| r.reason).collect();
+        assert_eq!(rejected, vec![RejectReason::TooManyValues(3)]);
+    }
+
+    #[test]
+    fn it_coerces_types() {
+        let columns = vec![ColumnSpec {
+                               ty: Some(ColumnType::Real),
+                               ..ColumnSpec::default()
+                           },
+                           ColumnSpec {
+                               ty: Some(ColumnType::Int),
+                               ..ColumnSpec::default()
+                           }];
+        let mut b = Base::default().with_columns(columns);
+        let rejected = b.rejections();
+
+        let rs = input(&mut b,
+                       vec![vec![1.into(), 2.into()],
+                            vec![1.5f64.into(), DataType::None],
+                            vec![1.into(), "2".into()]]);
+        let expected: Records = vec![vec![1.0f64.into(), 2.into()],
+                                     vec![1.5f64.into(), DataType::None]]
+            .into();
+        assert_eq!(rs, expected);
+
+        let rejected: Vec<_> = rejected.try_iter().map(|r| r.reason).collect();
+        assert_eq!(rejected, vec![RejectReason::WrongType(1, ColumnType::Int)]);
+    }
+}
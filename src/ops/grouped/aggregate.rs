@@ -1,15 +1,26 @@
 use ops::grouped::GroupedOperation;
 use ops::grouped::GroupedOperator;
 
+use std::collections::HashSet;
+
+use flow::data::ColumnType;
 use flow::prelude::*;
 
 /// Supported aggregation operators.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Aggregation {
     /// Count the number of records for each group. The value for the `over` column is ignored.
     COUNT,
     /// Sum the value of the `over` column for all records of each group.
+    ///
+    /// The sum is an integer if all values are integers, and a real value otherwise. Values that
+    /// are not numbers are treated as NULL, and so are left out of the sum.
     SUM,
+    /// Average the value of the `over` column for all records of each group.
+    ///
+    /// The average is always a real value. Like for `SUM`, values that are not numbers are left
+    /// out of the average.
+    AVG,
 }
 
 impl Aggregation {
@@ -23,6 +34,30 @@ impl Aggregation {
                 over: usize,
                 group_by: &[usize])
                 -> GroupedOperator<Aggregator> {
+        self.new(src, over, group_by, false)
+    }
+
+    /// Construct a new `Aggregator` that performs this operation over only the distinct values of
+    /// the `over` column in each group.
+    ///
+    /// This is equivalent to `COUNT(DISTINCT over)` (or `SUM(DISTINCT over)` etc.) in SQL: a value
+    /// that appears in several records of a group only contributes to the group's aggregate once,
+    /// and only stops contributing when the last of those records is removed. See
+    /// `Aggregation::over`.
+    pub fn over_distinct(self,
+                         src: NodeAddress,
+                         over: usize,
+                         group_by: &[usize])
+                         -> GroupedOperator<Aggregator> {
+        self.new(src, over, group_by, true)
+    }
+
+    fn new(self,
+           src: NodeAddress,
+           over: usize,
+           group_by: &[usize],
+           distinct: bool)
+           -> GroupedOperator<Aggregator> {
         assert!(!group_by.iter().any(|&i| i == over),
                 "cannot group by aggregation column");
         GroupedOperator::new(src,
@@ -30,6 +65,7 @@ impl Aggregation {
                                  op: self,
                                  over: over,
                                  group: group_by.into(),
+                                 distinct: distinct,
                              })
     }
 }
@@ -51,15 +87,31 @@ impl Aggregation {
 /// identifying the group, and appending the aggregated value. For example, for a sum with
 /// `self.over == 1`, a previous sum of `3`, and an incoming record with `[a, 1, x]`, the output
 /// would be `[a, x, 4]`.
+///
+/// An average cannot be updated from its current value alone, and neither can an aggregate over
+/// distinct values, since removing a record only changes it if no other record in the group has
+/// the same value. For those, the aggregator instead recomputes the value of every affected group
+/// from the group's records in its ancestor, which must therefore be materialized.
 #[derive(Debug, Clone)]
 pub struct Aggregator {
     op: Aggregation,
     over: usize,
     group: Vec<usize>,
+    distinct: bool,
+}
+
+/// Whether `v` can be summed or averaged.
+fn is_number(v: &DataType) -> bool {
+    match *v {
+        DataType::Int(..) |
+        DataType::BigInt(..) |
+        DataType::Real(..) => true,
+        _ => false,
+    }
 }
 
 impl GroupedOperation for Aggregator {
-    type Diff = i64;
+    type Diff = (DataType, bool);
 
     fn setup(&mut self, parent: &Node) {
         assert!(self.over < parent.fields().len(),
@@ -71,41 +123,72 @@ impl GroupedOperation for Aggregator {
     }
 
     fn zero(&self) -> Option<DataType> {
-        Some(0i64.into())
+        match self.op {
+            Aggregation::COUNT | Aggregation::SUM => Some(0i64.into()),
+            Aggregation::AVG => Some(0.0f64.into()),
+        }
     }
 
     fn to_diff(&self, r: &[DataType], pos: bool) -> Self::Diff {
         match self.op {
-            Aggregation::COUNT if pos => 1,
-            Aggregation::COUNT => -1,
-            Aggregation::SUM => {
-                let v = match r[self.over] {
-                    DataType::Int(n) => n as i64,
-                    DataType::BigInt(n) => n,
-                    _ => unreachable!(),
-                };
-                if pos { v } else { 0i64 - v }
-            }
+            Aggregation::COUNT => (1i64.into(), pos),
+            // values that are not numbers do not contribute to sums
+            Aggregation::SUM if !is_number(&r[self.over]) => (0i64.into(), pos),
+            _ => (r[self.over].clone(), pos),
         }
     }
 
-    fn apply(&self, current: Option<&DataType>, diffs: Vec<Self::Diff>) -> DataType {
-        if let Some(data) = current {
-            let n = match *data {
-                DataType::Int(n) => n as i64,
-                DataType::BigInt(n) => n,
-                _ => unreachable!(),
-            };
-            diffs.into_iter().fold(n, |n, d| n + d).into()
+    fn apply(&mut self,
+             _: &[&DataType],
+             current: Option<&DataType>,
+             diffs: Vec<Self::Diff>)
+             -> DataType {
+        let current = current.expect("aggregations always have a zero value").clone();
+        diffs.into_iter().fold(current, |acc, (v, pos)| if pos {
+            &acc + &v
         } else {
-            unreachable!();
+            &acc - &v
+        })
+    }
+
+    fn recomputes(&self) -> bool {
+        self.distinct || self.op == Aggregation::AVG
+    }
+
+    fn recompute(&self, records: &[&[DataType]]) -> DataType {
+        let mut seen = HashSet::new();
+        let values = records.iter()
+            .map(|r| &r[self.over])
+            // only the first record with a given value counts towards a distinct aggregate
+            .filter(|v| !self.distinct || seen.insert(*v));
+
+        match self.op {
+            Aggregation::COUNT => (values.count() as i64).into(),
+            Aggregation::SUM => {
+                values.filter(|v| is_number(v)).fold(DataType::from(0i64), |acc, v| &acc + v)
+            }
+            Aggregation::AVG => {
+                let (sum, count) = values.filter(|v| is_number(v))
+                    .fold((DataType::from(0i64), 0), |(sum, n), v| (&sum + v, n + 1));
+                if count == 0 {
+                    return 0.0f64.into();
+                }
+                let sum: f64 = (&sum).into();
+                (sum / count as f64).into()
+            }
         }
     }
 
     fn description(&self) -> String {
-        let op_string = match self.op {
-            Aggregation::COUNT => "|*|".into(),
-            Aggregation::SUM => format!("𝛴({})", self.over),
+        let op_string = match (&self.op, self.distinct) {
+            (&Aggregation::COUNT, false) => "|*|".into(),
+            (&Aggregation::COUNT, true) => format!("|δ{}|", self.over),
+            (&Aggregation::SUM, distinct) => {
+                format!("𝛴({}{})", if distinct { "δ" } else { "" }, self.over)
+            }
+            (&Aggregation::AVG, distinct) => {
+                format!("μ({}{})", if distinct { "δ" } else { "" }, self.over)
+            }
         };
         let group_cols = self.group
             .iter()
@@ -114,6 +197,15 @@ impl GroupedOperation for Aggregator {
             .join(", ");
         format!("{} γ[{}]", op_string, group_cols)
     }
+
+    fn output_type(&self) -> Option<ColumnType> {
+        match self.op {
+            Aggregation::COUNT => Some(ColumnType::Int),
+            Aggregation::AVG => Some(ColumnType::Real),
+            // the sum of integers is an integer, but the sum of reals is not
+            Aggregation::SUM => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -149,6 +241,15 @@ mod tests {
         let c = Aggregation::COUNT.over(s, 1, &[0, 2]);
         assert_eq!(c.description(), "|*| γ[0, 2]");
 
+        let a = Aggregation::AVG.over(s, 1, &[0]);
+        assert_eq!(a.description(), "μ(1) γ[0]");
+
+        let c = Aggregation::COUNT.over_distinct(s, 1, &[0]);
+        assert_eq!(c.description(), "|δ1| γ[0]");
+
+        let a = Aggregation::SUM.over_distinct(s, 1, &[0]);
+        assert_eq!(a.description(), "𝛴(δ1) γ[0]");
+
         let s = Aggregation::SUM.over(s, 1, &[2, 0]);
         assert_eq!(s.description(), "𝛴(1) γ[2, 0]");
     }
@@ -394,7 +495,172 @@ mod tests {
         }
     }
 
-    // TODO: also test SUM
+    fn setup_over(op: Aggregation) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("identity", &["x", "ys"], op.over(s, 1, &[0]), true);
+        g
+    }
+
+    fn setup_recomputing(op: Aggregation, distinct: bool) -> (ops::test::MockGraph, NodeAddress) {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        let a = if distinct {
+            op.over_distinct(s, 1, &[0])
+        } else {
+            op.over(s, 1, &[0])
+        };
+        g.set_op("identity", &["x", "ys"], a, true);
+        (g, s)
+    }
+
+    /// Apply `rs` to the ancestor's state, and then feed them to the node, like a domain would.
+    fn feed(g: &mut ops::test::MockGraph,
+            s: NodeAddress,
+            rs: Vec<(Vec<DataType>, bool)>)
+            -> ops::Records {
+        for &(ref r, pos) in &rs {
+            if pos {
+                g.seed(s, r.clone());
+            } else {
+                g.unseed(s, r.clone());
+            }
+        }
+        g.narrow_one(rs, true)
+    }
+
+    fn row(x: i32, y: DataType) -> Vec<DataType> {
+        vec![x.into(), y]
+    }
+
+    fn last(rs: ops::Records) -> DataType {
+        match rs.into_iter().last().unwrap() {
+            ops::Record::Positive(r) => r[1].clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn it_sums_reals() {
+        let mut c = setup_over(Aggregation::SUM);
+
+        // integers are summed as integers
+        let rs = c.narrow_one_row(row(1, 2.into()), true);
+        assert_eq!(last(rs), 2.into());
+
+        // until a real value comes along
+        let rs = c.narrow_one_row(row(1, 0.25.into()), true);
+        assert_eq!(last(rs), 2.25.into());
+        let rs = c.narrow_one_row(row(1, 0.1.into()), true);
+        assert_eq!(last(rs), 2.35.into());
+
+        // removals are exact
+        let rs = c.narrow_one_row((row(1, 0.1.into()), false), true);
+        assert_eq!(last(rs), 2.25.into());
+        let rs = c.narrow_one_row((row(1, 2.25.into()), false), true);
+        assert_eq!(last(rs), 0.0.into());
+    }
+
+    #[test]
+    fn it_averages() {
+        let (mut c, s) = setup_recomputing(Aggregation::AVG, false);
+
+        // the first row for a group should emit -0.0 and the value itself
+        let rs = feed(&mut c, s, vec![(row(1, 2.into()), true)]);
+        assert_eq!(rs.len(), 2);
+        match rs[0] {
+            ops::Record::Negative(ref r) => assert_eq!(r[1], 0.0.into()),
+            _ => unreachable!(),
+        }
+        assert_eq!(last(rs), 2.0.into());
+
+        // averages are real values, even for integer inputs
+        let rs = feed(&mut c, s, vec![(row(1, 3.into()), true)]);
+        assert_eq!(last(rs), 2.5.into());
+        let rs = feed(&mut c, s, vec![(row(1, 5.5.into()), true)]);
+        assert_eq!(last(rs), 3.5.into());
+
+        // other groups are averaged separately
+        let rs = feed(&mut c, s, vec![(row(2, 7.into()), true)]);
+        assert_eq!(last(rs), 7.0.into());
+
+        // removing a row takes it out of the average
+        let rs = feed(&mut c, s, vec![(row(1, 5.5.into()), false)]);
+        assert_eq!(last(rs), 2.5.into());
+
+        // and emptying a group brings it back to zero
+        let rs = feed(&mut c,
+                      s,
+                      vec![(row(1, 2.into()), false), (row(1, 3.into()), false)]);
+        assert_eq!(last(rs), 0.0.into());
+    }
+
+    #[test]
+    fn it_ignores_values_that_are_not_numbers() {
+        let mut c = setup_over(Aggregation::SUM);
+        assert_eq!(last(c.narrow_one_row(row(1, 2.into()), true)), 2.into());
+        assert_eq!(c.narrow_one_row(row(1, "text".into()), true).len(), 0);
+        assert_eq!(c.narrow_one_row(row(1, DataType::None), true).len(), 0);
+        assert_eq!(c.narrow_one_row((row(1, "text".into()), false), true).len(), 0);
+
+        let (mut c, s) = setup_recomputing(Aggregation::AVG, false);
+        assert_eq!(last(feed(&mut c, s, vec![(row(1, 2.into()), true)])), 2.0.into());
+        assert_eq!(feed(&mut c, s, vec![(row(1, "a long piece of text".into()), true)]).len(),
+                   0);
+        assert_eq!(last(feed(&mut c, s, vec![(row(1, 4.into()), true)])), 3.0.into());
+    }
+
+    #[test]
+    fn it_counts_distinct() {
+        let (mut g, s) = setup_recomputing(Aggregation::COUNT, true);
+
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 1.into()), true)])), 1.into());
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 2.into()), true)])), 2.into());
+
+        // duplicates don't count
+        assert_eq!(feed(&mut g, s, vec![(row(1, 1.into()), true)]).len(), 0);
+        assert_eq!(feed(&mut g,
+                        s,
+                        vec![(row(1, 2.into()), true), (row(1, 3.into()), true)])
+                       .len(),
+                   2);
+
+        // and neither does removing one of them
+        assert_eq!(feed(&mut g, s, vec![(row(1, 1.into()), false)]).len(), 0);
+
+        // but removing the last one does
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 1.into()), false)])), 2.into());
+
+        // values are only compared within a group
+        assert_eq!(last(feed(&mut g, s, vec![(row(2, 2.into()), true)])), 1.into());
+    }
+
+    #[test]
+    fn it_sums_distinct() {
+        let (mut g, s) = setup_recomputing(Aggregation::SUM, true);
+
+        feed(&mut g,
+             s,
+             vec![(row(1, 2.into()), true), (row(1, 2.into()), true), (row(1, 3.into()), true)]);
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 4.into()), true)])), 9.into());
+        assert_eq!(feed(&mut g, s, vec![(row(1, 2.into()), false)]).len(), 0);
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 2.into()), false)])), 7.into());
+    }
+
+    #[test]
+    fn it_reads_groups_from_its_ancestor() {
+        // the aggregate does not remember anything itself, so a group that is already in the
+        // ancestor (for example after the node's state was rebuilt) is accounted for
+        let (mut g, s) = setup_recomputing(Aggregation::AVG, false);
+        g.seed(s, row(1, 1.into()));
+        g.seed(s, row(1, 2.into()));
+        assert_eq!(last(feed(&mut g, s, vec![(row(1, 6.into()), true)])), 3.0.into());
+
+        // and the ancestor is indexed by group for it
+        let me = NodeAddress::mock_global(1.into());
+        let idx = g.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 2);
+    }
 
     #[test]
     fn it_suggests_indices() {
@@ -3,6 +3,7 @@ use ops::grouped::GroupedOperator;
 
 use std::collections::HashSet;
 
+use flow::data::ColumnType;
 use flow::prelude::*;
 
 /// Designator for what a given position in a group concat output should contain.
@@ -38,11 +39,15 @@ pub enum Modify {
 /// is the primary reason for the "separator as sentinel" behavior mentioned above, and may be made
 /// optional in the future such that more efficient incremental updating and relaxed separator
 /// semantics can be implemented.
+///
+/// Records with the same string representation each contribute one copy of that string, so
+/// removing one of them leaves the others in place.
 #[derive(Debug, Clone)]
 pub struct GroupConcat {
     components: Vec<TextComponent>,
     separator: &'static str,
     group: Vec<usize>,
+    explicit_group: bool,
     slen: usize,
 }
 
@@ -71,6 +76,30 @@ impl GroupConcat {
                                  components: components,
                                  separator: separator,
                                  group: Vec::new(),
+                                 explicit_group: false,
+                                 slen: 0,
+                             })
+    }
+
+    /// Construct a new `GroupConcat` operator that groups by the given columns.
+    ///
+    /// This behaves like `GroupConcat::new`, except that columns that are neither mentioned in
+    /// `components` nor in `group_by` are ignored, rather than used as group by parameters.
+    pub fn grouped_by(src: NodeAddress,
+                      components: Vec<TextComponent>,
+                      separator: &'static str,
+                      group_by: &[usize])
+                      -> GroupedOperator<GroupConcat> {
+        assert!(!separator.is_empty(),
+                "group concat separator cannot be empty");
+        assert!(!group_by.is_empty(), "cannot group by nothing");
+
+        GroupedOperator::new(src,
+                             GroupConcat {
+                                 components: components,
+                                 separator: separator,
+                                 group: group_by.into(),
+                                 explicit_group: true,
                                  slen: 0,
                              })
     }
@@ -92,7 +121,10 @@ impl GroupConcat {
                         }
                         DataType::Int(ref n) => s.push_str(&n.to_string()),
                         DataType::BigInt(ref n) => s.push_str(&n.to_string()),
-                        DataType::Real((ref i, ref f)) => s.push_str(&format!("{}.{}", i, f)),
+                        DataType::Bool(..) |
+                        DataType::Timestamp(..) |
+                        DataType::Bytes(..) |
+                        DataType::Real(..) => s.push_str(&rec[i].to_string()),
                         DataType::None => unreachable!(),
                     }
                 }
@@ -118,7 +150,16 @@ impl GroupedOperation for GroupConcat {
                 group.remove(&col);
             }
         }
-        self.group = group.into_iter().collect();
+
+        if self.explicit_group {
+            for col in &self.group {
+                assert!(*col < cols, "group concat groups by field parent doesn't have");
+                assert!(group.contains(col),
+                        "group concat cannot group by a field it emits");
+            }
+        } else {
+            self.group = group.into_iter().collect();
+        }
 
         // how long are we expecting strings to be?
         self.slen = 0;
@@ -149,15 +190,20 @@ impl GroupedOperation for GroupConcat {
         }
     }
 
-    fn apply(&self, current: Option<&DataType>, diffs: Vec<Self::Diff>) -> DataType {
-        use std::collections::BTreeSet;
-        use std::iter::FromIterator;
+    fn apply(&mut self,
+             _: &[&DataType],
+             current: Option<&DataType>,
+             diffs: Vec<Self::Diff>)
+             -> DataType {
+        use std::collections::BTreeMap;
+        use std::iter;
 
         // updating the value is a bit tricky because we want to retain ordering of the
         // elements. we therefore need to first split the value, add the new ones,
         // remove revoked ones, sort, and then join again. ugh. we try to make it more
         // efficient by splitting into a BTree, which maintains sorting while
-        // supporting efficient add/remove.
+        // supporting efficient add/remove. since several records may have the same string
+        // representation, we keep a count for every string.
 
         use std::borrow::Cow;
         let current: Cow<str> = match current {
@@ -168,28 +214,43 @@ impl GroupedOperation for GroupConcat {
         let clen = current.len();
 
         // TODO this is not particularly robust, and requires a non-empty separator
-        let mut current = BTreeSet::from_iter(current.split_terminator(self.separator));
+        let mut counts = BTreeMap::new();
+        for s in current.split_terminator(self.separator) {
+            *counts.entry(s).or_insert(0) += 1;
+        }
         for diff in &diffs {
             match *diff {
                 Modify::Add(ref s) => {
-                    current.insert(s);
+                    *counts.entry(&**s).or_insert(0) += 1;
                 }
                 Modify::Remove(ref s) => {
-                    current.remove(&**s);
+                    let gone = match counts.get_mut(&**s) {
+                        Some(n) => {
+                            *n -= 1;
+                            *n == 0
+                        }
+                        None => false,
+                    };
+                    if gone {
+                        counts.remove(&**s);
+                    }
                 }
             }
         }
 
         // WHY doesn't rust have an iterator joiner?
-        let mut new = current.into_iter()
+        let mut new = counts.into_iter()
+            .flat_map(|(s, n)| iter::repeat(s).take(n))
             .fold(String::with_capacity(2 * clen), |mut acc, s| {
                 acc.push_str(s);
                 acc.push_str(self.separator);
                 acc
             });
-        // we pushed one separator too many above
-        let real_len = new.len() - self.separator.len();
-        new.truncate(real_len);
+        // we pushed one separator too many above, unless the group is now empty
+        if !new.is_empty() {
+            let real_len = new.len() - self.separator.len();
+            new.truncate(real_len);
+        }
         new.into()
     }
 
@@ -216,6 +277,10 @@ impl GroupedOperation for GroupConcat {
                 self.separator,
                 group_cols)
     }
+
+    fn output_type(&self) -> Option<ColumnType> {
+        Some(ColumnType::Text)
+    }
 }
 
 #[cfg(test)]
@@ -350,7 +415,7 @@ mod tests {
         // multiple positives and negatives should update aggregation value by appropriate amount
         let rs = c.narrow_one(u, true);
         assert_eq!(rs.len(), 6); // one - and one + for each group
-        // group 1 had [2], now has [1,2]
+        // group 1 had [2], now has [1,2,2]
         assert!(rs.iter().any(|r| if let ops::Record::Negative(ref r) = *r {
             if r[0] == 1.into() {
                 assert_eq!(r[1], ".2;".into());
@@ -363,7 +428,7 @@ mod tests {
         }));
         assert!(rs.iter().any(|r| if let ops::Record::Positive(ref r) = *r {
             if r[0] == 1.into() {
-                assert_eq!(r[1], ".1;#.2;".into());
+                assert_eq!(r[1], ".1;#.2;#.2;".into());
                 true
             } else {
                 false
@@ -415,6 +480,44 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn it_keeps_duplicates() {
+        let mut c = setup(true);
+
+        c.narrow_one(vec![vec![1.into(), 1.into()], vec![1.into(), 1.into()]], true);
+
+        // removing one of two identical records leaves the other in place
+        let rs = c.narrow_one_row((vec![1.into(), 1.into()], false), true);
+        assert_eq!(rs,
+                   vec![(vec![1.into(), ".1;#.1;".into()], false),
+                        (vec![1.into(), ".1;".into()], true)]
+                       .into());
+
+        // and removing the last one empties the group
+        let rs = c.narrow_one_row((vec![1.into(), 1.into()], false), true);
+        assert_eq!(rs,
+                   vec![(vec![1.into(), ".1;".into()], false),
+                        (vec![1.into(), "".into()], true)]
+                       .into());
+    }
+
+    #[test]
+    fn it_groups_by_given_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        let c = GroupConcat::grouped_by(s, vec![TextComponent::Column(1)], ",", &[0]);
+        g.set_op("concat", &["x", "ys"], c, true);
+        assert_eq!(g.node().description(), "||([1], \",\") γ[0]");
+
+        // z is ignored
+        g.narrow_one_row(vec![1.into(), "b".into(), 1.into()], true);
+        let rs = g.narrow_one_row(vec![1.into(), "a".into(), 2.into()], true);
+        assert_eq!(rs,
+                   vec![(vec![1.into(), "b".into()], false),
+                        (vec![1.into(), "a,b".into()], true)]
+                       .into());
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = NodeAddress::mock_global(1.into());
@@ -4,7 +4,7 @@ use ops::grouped::GroupedOperator;
 use flow::prelude::*;
 
 /// Supported kinds of extremum operators.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Extremum {
     /// The minimum value that occurs in the `over` column in each group.
     MIN,
@@ -92,7 +92,11 @@ impl GroupedOperation for ExtremumOperator {
         }
     }
 
-    fn apply(&self, current: Option<&DataType>, diffs: Vec<Self::Diff>) -> DataType {
+    fn apply(&mut self,
+             _: &[&DataType],
+             current: Option<&DataType>,
+             diffs: Vec<Self::Diff>)
+             -> DataType {
         // Extreme values are those that are at least as extreme as the current min/max (if any).
         // let mut is_extreme_value : Box<Fn(i64) -> bool> = Box::new(|_|true);
         let mut extreme_values: Vec<i64> = vec![];
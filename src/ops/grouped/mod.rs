@@ -4,6 +4,7 @@ use std::fmt;
 use std::collections::HashMap;
 use std::sync;
 
+use flow::data::ColumnType;
 use flow::prelude::*;
 
 // pub mod latest;
@@ -60,9 +61,36 @@ pub trait GroupedOperation: fmt::Debug + Clone {
 
     /// Given the given `current` value, and a number of changes for a group (`diffs`), compute the
     /// updated group value. When the group is empty, current is set to the zero value.
-    fn apply(&self, current: Option<&DataType>, diffs: Vec<Self::Diff>) -> DataType;
+    ///
+    /// `group` holds the values of the group's `group_by` columns, in column order.
+    fn apply(&mut self,
+             group: &[&DataType],
+             current: Option<&DataType>,
+             diffs: Vec<Self::Diff>)
+             -> DataType;
+
+    /// Whether the operation computes a group's value from all of the group's records with
+    /// `recompute`, instead of updating its current value with `apply`.
+    ///
+    /// This is for operations whose value cannot be updated from the current value alone, such as
+    /// averages. Rather than keeping extra state of their own, which would not follow the node's
+    /// materialized state around, they read the group's records from their ancestor's state. The
+    /// ancestor is therefore materialized, and its state already reflects the incoming records.
+    fn recomputes(&self) -> bool {
+        false
+    }
+
+    /// Compute the value of a group from all of its records. Only used if `recomputes` is true.
+    fn recompute(&self, _records: &[&[DataType]]) -> DataType {
+        unreachable!("operation does not recompute its groups")
+    }
 
     fn description(&self) -> String;
+
+    /// The type of the values this operation produces, if it is always the same.
+    fn output_type(&self) -> Option<ColumnType> {
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -109,7 +137,7 @@ impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T> {
     }
 
     fn will_query(&self, materialized: bool) -> bool {
-        !materialized
+        !materialized || self.inner.recomputes()
     }
 
     fn on_connected(&mut self, g: &Graph) {
@@ -153,7 +181,7 @@ impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T> {
     fn on_input(&mut self,
                 from: NodeAddress,
                 rs: Records,
-                _: &DomainNodes,
+                nodes: &DomainNodes,
                 state: &StateMap)
                 -> Records {
         debug_assert_eq!(from, self.src);
@@ -197,8 +225,23 @@ impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T> {
                 let current = old.map(|r| Some(Cow::Borrowed(&r[r.len() - 1])))
                     .unwrap_or_else(|| self.inner.zero().map(Cow::Owned));
 
-                // new is the result of applying all diffs for the group to the current value
-                let new = self.inner.apply(current.as_ref().map(|v| &**v), diffs);
+                // new is the result of applying all diffs for the group to the current value, or
+                // of recomputing the group from our ancestor, whose state includes the diffs
+                let new = if self.inner.recomputes() {
+                    let records: Vec<_> =
+                        self.lookup(self.src,
+                                    &self.group_by[..],
+                                    &KeyType::from(&group[..]),
+                                    nodes,
+                                    state)
+                            .expect("grouped operators that recompute groups must have their \
+                                     ancestor materialized")
+                            .collect();
+                    let records: Vec<_> = records.iter().map(|r| &r[..]).collect();
+                    self.inner.recompute(&records[..])
+                } else {
+                    self.inner.apply(&group[..], current.as_ref().map(|v| &**v), diffs)
+                };
                 (current, new)
             };
 
@@ -243,8 +286,21 @@ impl<T: GroupedOperation + Send + 'static> Ingredient for GroupedOperator<T> {
     }
 
     fn suggest_indexes(&self, this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
-        // index by our primary key
-        Some((this, self.out_key.clone())).into_iter().collect()
+        // index by our primary key, and our ancestor by group if we read groups from it
+        let mut idx: HashMap<_, _> = Some((this, self.out_key.clone())).into_iter().collect();
+        if self.inner.recomputes() {
+            idx.insert(self.src, self.group_by.clone());
+        }
+        idx
+    }
+
+    fn column_type(&self, column: usize) -> Option<ColumnType> {
+        // the computed value comes after the group columns
+        if column == self.group_by.len() {
+            self.inner.output_type()
+        } else {
+            None
+        }
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
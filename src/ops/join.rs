@@ -1,11 +1,85 @@
 use ops;
+use ops::predicate;
 
+use std::cmp::Ordering;
 use std::sync;
+use std::sync::mpsc;
 use std::iter;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
 use flow::prelude::*;
+use flow::data::ColumnType;
+use flow::Migration;
+
+/// A comparison between a pair of values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// The two values are equal.
+    Equal,
+    /// The left value is smaller than the right.
+    Less,
+    /// The left value is smaller than or equal to the right.
+    LessOrEqual,
+    /// The left value is greater than the right.
+    Greater,
+    /// The left value is greater than or equal to the right.
+    GreaterOrEqual,
+    /// The two values differ.
+    NotEqual,
+}
+
+impl Comparison {
+    /// Check whether the comparison holds for the given values.
+    ///
+    /// As in a `Predicate`, comparisons involving `DataType::None`, or values of incomparable
+    /// types, are unknown, and so never hold.
+    pub fn matches(&self, left: &DataType, right: &DataType) -> bool {
+        predicate::compare(left, right).map_or(false, |ord| self.holds(ord))
+    }
+
+    /// Whether the comparison holds for two values ordered as `ord`.
+    pub(crate) fn holds(&self, ord: Ordering) -> bool {
+        match *self {
+            Comparison::Equal => ord == Ordering::Equal,
+            Comparison::NotEqual => ord != Ordering::Equal,
+            Comparison::Less => ord == Ordering::Less,
+            Comparison::LessOrEqual => ord != Ordering::Greater,
+            Comparison::Greater => ord == Ordering::Greater,
+            Comparison::GreaterOrEqual => ord != Ordering::Less,
+        }
+    }
+
+    pub(crate) fn symbol(&self) -> &'static str {
+        match *self {
+            Comparison::Equal => "=",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "≤",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => "≥",
+            Comparison::NotEqual => "≠",
+        }
+    }
+}
+
+/// A join value that matched more rows than the limit given to `Builder::warn_on_fanout`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighFanout {
+    /// The join source whose records were being joined.
+    pub from: NodeAddress,
+    /// The join value of those records.
+    pub key: DataType,
+    /// The number of rows on the other side of the join that share the join value.
+    pub matches: usize,
+}
+
+/// An additional condition that joined rows must satisfy, of the form `left cmp right`.
+#[derive(Debug, Clone)]
+struct Condition {
+    left: (NodeAddress, usize),
+    cmp: Comparison,
+    right: (NodeAddress, usize),
+}
 
 #[derive(Debug, Clone)]
 struct JoinTarget {
@@ -24,6 +98,8 @@ struct Join {
 pub struct Builder {
     emit: Vec<(NodeAddress, usize)>,
     join: HashMap<NodeAddress, (bool, Vec<usize>)>,
+    conditions: Vec<Condition>,
+    fanout: Option<(usize, mpsc::Sender<HighFanout>)>,
 }
 
 impl Builder {
@@ -34,6 +110,8 @@ impl Builder {
         Builder {
             emit: emit,
             join: HashMap::new(),
+            conditions: Vec::new(),
+            fanout: None,
         }
     }
 
@@ -66,7 +144,10 @@ impl Builder {
     /// Builder::new(vec![(a, 0), (b, 0)]).from(a, vec![1, 0]).join(b, vec![0, 1, 0]);
     /// ```
     pub fn join(mut self, node: NodeAddress, groups: Vec<usize>) -> Self {
-        assert!(self.join.insert(node, (false, groups)).is_none());
+        check_groups(node, &groups[..]);
+        assert!(self.join.insert(node, (false, groups)).is_none(),
+                "{} is already part of this join",
+                node);
         self
     }
 
@@ -77,9 +158,175 @@ impl Builder {
     /// regardless of whether matching records exist in `node`. For such *zero rows*, all columns
     /// emitted from this node will be set to `DataType::None`.
     pub fn left_join(mut self, node: NodeAddress, groups: Vec<usize>) -> Self {
-        assert!(self.join.insert(node, (true, groups)).is_none());
+        check_groups(node, &groups[..]);
+        assert!(self.join.insert(node, (true, groups)).is_none(),
+                "{} is already part of this join",
+                node);
+        self
+    }
+
+    /// Only produce joined rows for which column `left.1` of `left.0` compares to column `right.1`
+    /// of `right.0` as given by `cmp`.
+    ///
+    /// Such conditions are checked for each pair of rows that match on the join's groups, so the
+    /// sources must still share at least one group. For example, the temporal join
+    ///
+    /// ```sql
+    /// SELECT a.0, b.0
+    /// FROM a JOIN b ON (a.0 == b.1 AND a.1 < b.2)
+    /// ```
+    ///
+    /// can be expressed as
+    ///
+    /// ```rust,ignore
+    /// Builder::new(vec![(a, 0), (b, 0)])
+    ///     .from(a, vec![1, 0])
+    ///     .join(b, vec![0, 1, 0])
+    ///     .compare((a, 1), Comparison::Less, (b, 2));
+    /// ```
+    pub fn compare(mut self,
+                   left: (NodeAddress, usize),
+                   cmp: Comparison,
+                   right: (NodeAddress, usize))
+                   -> Self {
+        assert!(left.0 != right.0,
+                "join conditions must compare columns from different sources");
+        self.conditions.push(Condition {
+            left: left,
+            cmp: cmp,
+            right: right,
+        });
+        self
+    }
+
+    /// Report join values that match more than `limit` rows on the other side of the join.
+    ///
+    /// A single such value can make the join produce a very large number of records, which can
+    /// stall the domain the join is in. Every time a batch of records contains a join value with
+    /// too many matches, a `HighFanout` describing it is sent on `tx`. The join still produces
+    /// its full output.
+    pub fn warn_on_fanout(mut self, limit: usize, tx: mpsc::Sender<HighFanout>) -> Self {
+        self.fanout = Some((limit, tx));
+        self
+    }
+
+    /// Perform a full outer join between the view given to `from` and the given `node`.
+    ///
+    /// This corresponds to the SQL notion of a `FULL OUTER JOIN`: records from *either* side that
+    /// have no matching records on the other side are still present in the output, with all
+    /// columns emitted from the other side set to `DataType::None`. When a matching record later
+    /// arrives, the padded record is retracted, and it is re-added when the last match goes away.
+    ///
+    /// Neither side of a full outer join yields its full result-set on its own, so views below one
+    /// cannot be populated by a replay. Migrations that would need such a replay are rejected.
+    pub fn outer_join(mut self, node: NodeAddress, groups: Vec<usize>) -> Self {
+        check_groups(node, &groups[..]);
+        assert_eq!(self.join.len(),
+                   1,
+                   "outer joins must be added right after the source view");
+        for side in self.join.values_mut() {
+            side.0 = true;
+        }
+        assert!(self.join.insert(node, (true, groups)).is_none(),
+                "{} is already part of this join",
+                node);
         self
     }
+
+    /// Validate this join against the current graph, and produce the names of its output columns.
+    ///
+    /// This checks that every emitted column and every group assignment refers to a column that
+    /// actually exists in the corresponding join source, so that a misconfigured join can be
+    /// detected before it is added to the graph. The returned names are those of the source
+    /// columns selected by `emit`, in order.
+    pub fn fields(&self, mig: &Migration) -> Result<Vec<String>, String> {
+        validate(&self.emit[..],
+                 &self.join,
+                 &self.conditions[..],
+                 |n| mig.fields(n))
+    }
+
+    /// Validate this join against the current graph like `fields`, and produce the types of its
+    /// output columns, where they are known (see `Migration::column_types`).
+    pub fn column_types(&self, mig: &Migration) -> Result<Vec<Option<ColumnType>>, String> {
+        self.fields(mig)?;
+        Ok(self.emit
+            .iter()
+            .map(|&(node, col)| mig.column_types(node)[col])
+            .collect())
+    }
+}
+
+/// Check that no group number is assigned to more than one column of `node`.
+fn check_groups(node: NodeAddress, groups: &[usize]) {
+    let mut seen = HashSet::new();
+    for &g in groups.iter().filter(|&&g| g != 0) {
+        assert!(seen.insert(g),
+                "group {} is assigned to more than one column of {}",
+                g,
+                node);
+    }
+}
+
+/// Check that the columns referenced by a join exist in its sources, and compute the names of the
+/// join's output columns.
+fn validate<'a, F>(emit: &[(NodeAddress, usize)],
+                   join: &HashMap<NodeAddress, (bool, Vec<usize>)>,
+                   conditions: &[Condition],
+                   fields: F)
+                   -> Result<Vec<String>, String>
+    where F: Fn(NodeAddress) -> &'a [String]
+{
+    for (&node, &(_, ref groups)) in join {
+        let arity = fields(node).len();
+        if groups.len() != arity {
+            return Err(format!("join gives {} group assignments for {}, which has {} columns",
+                               groups.len(),
+                               node,
+                               arity));
+        }
+    }
+
+    for c in conditions {
+        for &(node, col) in &[c.left, c.right] {
+            if !join.contains_key(&node) {
+                return Err(format!("join condition refers to {}, which is not part of the join",
+                                   node));
+            }
+            let arity = fields(node).len();
+            if col >= arity {
+                return Err(format!("join condition refers to column {} of {}, which only has {} \
+                                    columns",
+                                   col,
+                                   node,
+                                   arity));
+            }
+        }
+    }
+
+    emit.iter()
+        .enumerate()
+        .map(|(i, &(node, col))| {
+            if !join.contains_key(&node) {
+                return Err(format!("output column {} of join is emitted from {}, which is not \
+                                    part of the join",
+                                   i,
+                                   node));
+            }
+            let fs = fields(node);
+            match fs.get(col) {
+                Some(f) => Ok(f.clone()),
+                None => {
+                    Err(format!("output column {} of join is emitted from column {} of {}, \
+                                 which only has {} columns",
+                                i,
+                                col,
+                                node,
+                                fs.len()))
+                }
+            }
+        })
+        .collect()
 }
 
 impl From<Builder> for Joiner {
@@ -89,11 +336,21 @@ impl From<Builder> for Joiner {
             unimplemented!();
         }
 
-        // we technically want this assert, but we don't have self.nodes until .prime() has been
-        // called. unfortunately, at that time, we don't have .join in the original format, and so
-        // the debug doesn't makes sense. it's probably not worth carrying along the original join
-        // map just to verify this, but maybe...
-        // assert!(self.nodes.iter().all(|(ni, n)| self.join[ni].len() == n.args().len()));
+        // we can't check column bounds until we're added to the graph, but we can at least
+        // make sure that every emitted column comes from one of the join's sources.
+        for (i, &(node, _)) in b.emit.iter().enumerate() {
+            assert!(b.join.contains_key(&node),
+                    "output column {} of join is emitted from {}, which is not part of the join",
+                    i,
+                    node);
+        }
+        for c in &b.conditions {
+            for node in &[c.left.0, c.right.0] {
+                assert!(b.join.contains_key(node),
+                        "join condition refers to {}, which is not part of the join",
+                        node);
+            }
+        }
 
         // the format of `join` is convenient for users, but not particulary convenient for lookups
         // the particular use-case we want to be efficient is:
@@ -163,6 +420,9 @@ impl From<Builder> for Joiner {
         Joiner {
             emit: b.emit,
             join: join,
+            conditions: b.conditions,
+            fanout: b.fanout,
+            groups: b.join,
         }
     }
 }
@@ -184,64 +444,147 @@ impl Into<node::Type> for Builder {
 pub struct Joiner {
     emit: Vec<(NodeAddress, usize)>,
     join: HashMap<NodeAddress, Join>,
+
+    // additional conditions that every joined pair of rows must satisfy
+    conditions: Vec<Condition>,
+
+    // where to report join values with more matches than the given limit
+    fanout: Option<(usize, mpsc::Sender<HighFanout>)>,
+
+    // the join specification as originally given to the `Builder`, kept for validation
+    groups: HashMap<NodeAddress, (bool, Vec<usize>)>,
 }
 
 impl Joiner {
-    fn join<'a>(&'a self,
-                left: (NodeAddress, sync::Arc<Vec<DataType>>),
-                domain: &DomainNodes,
-                states: &StateMap)
-                -> Box<Iterator<Item = Vec<DataType>> + 'a> {
+    /// The join source that is not `src`.
+    fn other(&self, src: NodeAddress) -> NodeAddress {
+        // NOTE: this only works for two-way joins
+        *self.join.keys().find(|&other| other != &src).unwrap()
+    }
+
+    /// Check whether `row` from `src` and `other` from the other side satisfy all join conditions.
+    fn satisfies(&self, src: NodeAddress, row: &[DataType], other: &[DataType]) -> bool {
+        self.conditions.iter().all(|c| if c.left.0 == src {
+            c.cmp.matches(&row[c.left.1], &other[c.right.1])
+        } else {
+            c.cmp.matches(&other[c.left.1], &row[c.right.1])
+        })
+    }
+
+    /// Produce the output row for `row` from `src` joined with `other` from the other side.
+    fn weave(&self, src: NodeAddress, row: &[DataType], other: &[DataType]) -> Vec<DataType> {
+        self.emit
+            .iter()
+            .map(|&(source, column)| if source == src {
+                row[column].clone()
+            } else {
+                // FIXME: this clone is unnecessary.
+                // it's tricky to remove though, because it means we'd need to
+                // be removing things from right. what if a later column also needs
+                // to select from right? we'd need to keep track of which things we
+                // have removed, and subtract that many from the index of the
+                // later column. ugh.
+                other[column].clone()
+            })
+            .collect()
+    }
+
+    /// Produce the output row for `row` from `src` when it matches nothing on the other side.
+    fn pad(&self, src: NodeAddress, row: &[DataType]) -> Vec<DataType> {
+        self.emit
+            .iter()
+            .map(|&(source, column)| if source == src {
+                // this clone is unnecessary
+                row[column].clone()
+            } else {
+                DataType::None
+            })
+            .collect()
+    }
+
+    /// Join a record from one side against `matches`, the rows on the other side that share its
+    /// join key.
+    ///
+    /// Each output row is paired with whether it should carry the same sign as the input record.
+    /// Rows with the opposite sign occur in full outer joins, where rows from the other side stop
+    /// (or start) being padded when the record is added (or removed). To decide when that happens,
+    /// `counts` holds the number of rows on our side that each row from the other side matched
+    /// just before this record, for the rows seen so far in `batch`, the batch this record is
+    /// part of.
+    fn join(&self,
+            left: (NodeAddress, sync::Arc<Vec<DataType>>),
+            positive: bool,
+            matches: &[sync::Arc<Vec<DataType>>],
+            batch: &[(sync::Arc<Vec<DataType>>, bool)],
+            counts: &mut HashMap<sync::Arc<Vec<DataType>>, usize>,
+            domain: &DomainNodes,
+            states: &StateMap)
+            -> Vec<(Vec<DataType>, bool)> {
 
         // NOTE: this only works for two-way joins
-        let other = *self.join.keys().find(|&other| other != &left.0).unwrap();
-        let this = &self.join[&left.0];
-        let target = &this.against[&other];
-
-        // send the parameters to start the query.
-        let rx: Vec<_> = self.lookup(other,
-                    &[target.on.1],
-                    &KeyType::Single(&left.1[target.on.0]),
-                    domain,
-                    states)
-            .expect("joins must have inputs materialized")
-            .cloned()
+        let other = self.other(left.0);
+        let target = &self.join[&left.0].against[&other];
+
+        let rx: Vec<_> = matches.iter()
+            .filter(|right| self.satisfies(left.0, &left.1[..], &right[..]))
             .collect();
 
         if rx.is_empty() && target.outer {
-            return Box::new(Some(self.emit
-                    .iter()
-                    .map(|&(source, column)| {
-                        if source == other {
-                            DataType::None
-                        } else {
-                            // this clone is unnecessary
-                            left.1[column].clone()
-                        }
-                    })
-                    .collect::<Vec<_>>())
-                .into_iter());
+            return vec![(self.pad(left.0, &left.1[..]), true)];
         }
 
-        Box::new(rx.into_iter().map(move |right| {
-            // weave together r and j according to join rules
-            self.emit
-                .iter()
-                .map(|&(source, column)| {
-                    if source == other {
-                        // FIXME: this clone is unnecessary.
-                        // it's tricky to remove though, because it means we'd need to
-                        // be removing things from right. what if a later column also needs
-                        // to select from right? we'd need to keep track of which things we
-                        // have removed, and subtract that many from the index of the
-                        // later column. ugh.
-                        right[column].clone()
-                    } else {
-                        left.1[column].clone()
+        let mut out = Vec::with_capacity(rx.len());
+
+        // in a full outer join, rows from the other side are padded when they match nothing on our
+        // side, so the rows we matched may have been padded until now, or need to be padded again.
+        // TODO: do the same for the left side of left joins.
+        let reverse = &self.join[&other].against[&left.0];
+        if target.outer && reverse.outer {
+            let matching = |l: &[DataType], right: &[DataType]| {
+                l[reverse.on.1] == right[reverse.on.0] && self.satisfies(left.0, l, right)
+            };
+            let mut seen = HashSet::new();
+            for right in &rx {
+                // identical rows are padded alike, so handle each of them only once
+                if !seen.insert(right) {
+                    continue;
+                }
+
+                let n = counts.entry((*right).clone()).or_insert_with(|| {
+                    // our side's state already reflects the whole batch, so take the batch's
+                    // records back out to find how many of our rows matched before it
+                    let now = self.lookup(left.0,
+                                &[reverse.on.1],
+                                &KeyType::Single(&right[reverse.on.0]),
+                                domain,
+                                states)
+                        .expect("joins must have inputs materialized")
+                        .filter(|l| self.satisfies(left.0, &l[..], &right[..]))
+                        .count() as isize;
+                    let delta: isize = batch.iter()
+                        .filter(|&&(ref l, _)| matching(&l[..], &right[..]))
+                        .map(|&(_, pos)| if pos { 1 } else { -1 })
+                        .sum();
+                    (now - delta) as usize
+                });
+
+                let before = *n;
+                if positive {
+                    *n += 1;
+                } else {
+                    *n -= 1;
+                }
+                if (positive && before == 0) || (!positive && *n == 0) {
+                    let pad = self.pad(other, &right[..]);
+                    for _ in rx.iter().filter(|r| **r == *right) {
+                        out.push((pad.clone(), false));
                     }
-                })
-                .collect()
-        }))
+                }
+            }
+        }
+
+        out.extend(rx.into_iter().map(|right| (self.weave(left.0, &left.1[..], &right[..]), true)));
+        out
     }
 }
 
@@ -258,34 +601,44 @@ impl Ingredient for Joiner {
         false
     }
 
-    fn replay_ancestor(&self, empty: &HashSet<NodeAddress>) -> Option<NodeAddress> {
+    fn replay_ancestors(&self) -> Option<Vec<NodeAddress>> {
         // we want to replay an ancestor that we are *not* doing an outer join against
         // it's not *entirely* clear how to extract that from self.join, but we'll use the
         // following heuristic: find an ancestor that is never performed an outer join against.
-        let mut options: HashSet<_> = self.join.keys().collect();
+        let mut options: Vec<_> = self.join.keys().cloned().collect();
         for left in self.join.values() {
             for right in left.against.keys() {
                 if left.against[right].outer {
-                    options.remove(right);
+                    options.retain(|o| o != right);
                 }
             }
         }
-        assert!(!options.is_empty());
-
-        // we may have multiple options in the case of an inner join
-        // if any of them are empty, choose that one, since our output is also empty!
-        for &option in &options {
-            if empty.contains(option) {
-                return Some(*option);
-            }
-        }
-        options.into_iter().next().cloned()
+        // in a full outer join, neither ancestor yields the full result-set. replaying just one
+        // would silently lose the padded rows of the other, so refuse instead.
+        assert!(!options.is_empty(),
+                "cannot replay through a full outer join");
+
+        // in the case of an inner join, either will do. prefer the one whose columns we emit
+        // first, which is usually the one the join was built from.
+        options.sort();
+        options.sort_by_key(|o| {
+            self.emit.iter().position(|&(n, _)| n == *o).unwrap_or(usize::max_value())
+        });
+        Some(options)
     }
 
     fn will_query(&self, _: bool) -> bool {
         true
     }
 
+    fn validate(&self, g: &Graph) -> Result<(), String> {
+        validate(&self.emit[..],
+                 &self.groups,
+                 &self.conditions[..],
+                 |n| g[*n.as_global()].fields())
+            .map(|_| ())
+    }
+
     fn on_connected(&mut self, g: &Graph) {
         for j in self.join.values_mut() {
             for (t, jt) in &mut j.against {
@@ -319,6 +672,11 @@ impl Ingredient for Joiner {
         for &mut (ref mut ni, _) in &mut self.emit {
             *ni = remap[&*ni];
         }
+
+        for c in &mut self.conditions {
+            c.left.0 = remap[&c.left.0];
+            c.right.0 = remap[&c.right.0];
+        }
     }
 
     fn on_input(&mut self,
@@ -332,22 +690,53 @@ impl Ingredient for Joiner {
         // other side(s) for records matching the incoming records on that side's join
         // fields.
 
-        // TODO: we should be clever here, and only query once per *distinct join value*,
-        // instead of once per received record.
-        rs.into_iter()
-            .flat_map(|rec| {
-                let (r, pos) = rec.extract();
-
-                self.join((from, r), nodes, state).map(move |res| {
-                    // return new row with appropriate sign
-                    if pos {
-                        ops::Record::Positive(sync::Arc::new(res))
-                    } else {
-                        ops::Record::Negative(sync::Arc::new(res))
+        // many of the records may share a join value, so we only query the other side once per
+        // *distinct* join value, and re-use the results for all records with that value.
+        let other = self.other(from);
+        let on = self.join[&from].against[&other].on;
+        let mut matches: HashMap<DataType, Vec<sync::Arc<Vec<DataType>>>> = HashMap::new();
+
+        // in a full outer join, the number of our rows that each row on the other side matches
+        let mut counts = HashMap::new();
+        let batch: Vec<_> = rs.into_iter().map(|rec| rec.extract()).collect();
+
+        let mut out = Vec::with_capacity(batch.len());
+        for &(ref r, pos) in &batch {
+            if !matches.contains_key(&r[on.0]) {
+                let rx = self.lookup(other, &[on.1], &KeyType::Single(&r[on.0]), nodes, state)
+                    .expect("joins must have inputs materialized")
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if let Some((limit, ref tx)) = self.fanout {
+                    if rx.len() > limit {
+                        // the receiver may have gone away, but we still need to do the join
+                        let _ = tx.send(HighFanout {
+                            from: from,
+                            key: r[on.0].clone(),
+                            matches: rx.len(),
+                        });
                     }
-                })
-            })
-            .collect()
+                }
+                matches.insert(r[on.0].clone(), rx);
+            }
+
+            let joined = self.join((from, r.clone()),
+                                   pos,
+                                   &matches[&r[on.0]][..],
+                                   &batch[..],
+                                   &mut counts,
+                                   nodes,
+                                   state);
+            out.extend(joined.into_iter().map(|(res, same)| {
+                // return new row with appropriate sign
+                if pos == same {
+                    ops::Record::Positive(sync::Arc::new(res))
+                } else {
+                    ops::Record::Negative(sync::Arc::new(res))
+                }
+            }));
+        }
+        out.into()
     }
 
     fn suggest_indexes(&self, _this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
@@ -386,10 +775,31 @@ impl Ingredient for Joiner {
                     .iter()
                     .filter(move |&(right, _)| left < right)
                     .map(move |(right, rs)| {
-                        let op = if rs.outer { "⋉" } else { "⋈" };
-                        format!("{}:{} {} {}:{}", left, rs.on.0, op, right, rs.on.1)
+                        match (rs.outer, self.join[right].against[left].outer) {
+                            (true, true) => {
+                                format!("{}:{} ⟗ {}:{}", left, rs.on.0, right, rs.on.1)
+                            }
+                            (true, false) => {
+                                format!("{}:{} ⋉ {}:{}", left, rs.on.0, right, rs.on.1)
+                            }
+                            // the side whose rows are always kept goes first
+                            (false, true) => {
+                                format!("{}:{} ⋉ {}:{}", right, rs.on.1, left, rs.on.0)
+                            }
+                            (false, false) => {
+                                format!("{}:{} ⋈ {}:{}", left, rs.on.0, right, rs.on.1)
+                            }
+                        }
                     })
             })
+            .chain(self.conditions.iter().map(|c| {
+                format!("{}:{} {} {}:{}",
+                        c.left.0,
+                        c.left.1,
+                        c.cmp.symbol(),
+                        c.right.0,
+                        c.right.1)
+            }))
             .collect::<Vec<_>>()
             .join(", ");
         format!("[{}] {}", emit, joins)
@@ -418,6 +828,7 @@ mod tests {
     use super::*;
 
     use ops;
+    use std::sync::mpsc;
 
     fn setup(left: bool) -> (ops::test::MockGraph, NodeAddress, NodeAddress) {
         let mut g = ops::test::MockGraph::new();
@@ -545,6 +956,269 @@ mod tests {
         forward_non_weird(j, l, r);
     }
 
+    #[test]
+    fn it_picks_replay_ancestors() {
+        let (j, l, r) = setup(false);
+        assert_eq!(j.node().replay_ancestors(), Some(vec![l, r]));
+        let (j, l, _) = setup(true);
+        assert_eq!(j.node().replay_ancestors(), Some(vec![l]));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot replay through a full outer join")]
+    fn it_refuses_to_replay_full_outer() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        let j: Joiner = Builder::new(vec![(l, 0), (l, 1), (r, 1)])
+            .from(l, vec![1, 0])
+            .outer_join(r, vec![1, 0])
+            .into();
+        g.set_op("join", &["j0", "j1", "j2"], j, false);
+        g.node().replay_ancestors();
+    }
+
+    #[test]
+    fn it_works_with_shared_keys() {
+        let (mut j, l, _) = setup(false);
+
+        // both records join with x1 and y1, using only a single lookup
+        let rs = j.one(l,
+                       vec![vec![1.into(), "a".into()], vec![1.into(), "b".into()]],
+                       false);
+        assert_eq!(rs.len(), 4);
+        for l1 in &["a", "b"] {
+            for r1 in &["x", "y"] {
+                assert!(rs.iter().any(|r| r.rec()[1] == (*l1).into() && r.rec()[2] == (*r1).into()));
+            }
+        }
+    }
+
+    #[test]
+    fn it_warns_on_fanout() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let (tx, rx) = mpsc::channel();
+        let j: Joiner = Builder::new(vec![(l, 0), (l, 1), (r, 1)])
+            .from(l, vec![1, 0])
+            .join(r, vec![1, 0])
+            .warn_on_fanout(1, tx)
+            .into();
+        g.set_op("join", &["j0", "j1", "j2"], j, false);
+        g.seed(r, vec![1.into(), "x".into()]);
+        g.seed(r, vec![1.into(), "y".into()]);
+        g.seed(r, vec![2.into(), "z".into()]);
+        let l = g.to_local(l);
+
+        // 2 only matches z2, which is within the limit
+        assert_eq!(g.one_row(l, vec![2.into(), "b".into()], false).len(), 1);
+        assert!(rx.try_recv().is_err());
+
+        // 1 matches both x1 and y1, and should be reported once, even if it appears twice
+        let rs = g.one(l,
+                       vec![vec![1.into(), "a".into()], vec![1.into(), "b".into()]],
+                       false);
+        assert_eq!(rs.len(), 4);
+        assert_eq!(rx.try_recv(),
+                   Ok(HighFanout {
+                       from: l,
+                       key: 1.into(),
+                       matches: 2,
+                   }));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn it_works_outer() {
+        use std::sync::Arc;
+
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let j: Joiner = Builder::new(vec![(l, 0), (l, 1), (r, 1)])
+            .from(l, vec![1, 0])
+            .outer_join(r, vec![1, 0])
+            .into();
+        g.set_op("join", &["j0", "j1", "j2"], j, false);
+        g.seed(l, vec![1.into(), "a".into()]);
+        g.seed(l, vec![3.into(), "c".into()]);
+        g.seed(l, vec![4.into(), "d".into()]);
+        g.seed(l, vec![5.into(), "e".into()]);
+        g.seed(r, vec![1.into(), "x".into()]);
+        g.seed(r, vec![2.into(), "z".into()]);
+        g.seed(r, vec![4.into(), "w".into()]);
+        g.seed(r, vec![5.into(), "v".into()]);
+        let (l, r) = (g.to_local(l), g.to_local(r));
+
+        assert_eq!(g.node().description(),
+                   format!("[{}:0, {}:1, {}:1] {}:0 ⟗ {}:0", l, l, r, l, r));
+
+        // unmatched rows from either side are padded
+        assert_eq!(g.one_row(l, vec![3.into(), "c".into()], false),
+                   vec![vec![3.into(), "c".into(), DataType::None]].into());
+        assert_eq!(g.one_row(r, vec![2.into(), "z".into()], false),
+                   vec![vec![DataType::None, DataType::None, "z".into()]].into());
+
+        // d4 is the only match for w4, so w4 should no longer be padded
+        assert_eq!(g.one_row(l, vec![4.into(), "d".into()], false),
+                   vec![Record::Negative(Arc::new(vec![DataType::None,
+                                                       DataType::None,
+                                                       "w".into()])),
+                        Record::Positive(Arc::new(vec![4.into(), "d".into(), "w".into()]))]
+                       .into());
+
+        // e5 was the only match for v5, so v5 should be padded again once e5 is removed
+        g.unseed(l, vec![5.into(), "e".into()]);
+        let e5 = Record::Negative(Arc::new(vec![5.into(), "e".into()]));
+        assert_eq!(g.one_row(l, e5, false),
+                   vec![Record::Positive(Arc::new(vec![DataType::None,
+                                                       DataType::None,
+                                                       "v".into()])),
+                        Record::Negative(Arc::new(vec![5.into(), "e".into(), "v".into()]))]
+                       .into());
+    }
+
+    #[test]
+    fn it_works_outer_with_batches() {
+        use std::sync::Arc;
+
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let j: Joiner = Builder::new(vec![(l, 0), (l, 1), (r, 1)])
+            .from(l, vec![1, 0])
+            .outer_join(r, vec![1, 0])
+            .into();
+        g.set_op("join", &["j0", "j1", "j2"], j, false);
+        g.seed(r, vec![4.into(), "w".into()]);
+        g.seed(r, vec![6.into(), "u".into()]);
+        let gl = l;
+        let l = g.to_local(l);
+
+        let pos = |r: Vec<DataType>| Record::Positive(Arc::new(r));
+        let neg = |r: Vec<DataType>| Record::Negative(Arc::new(r));
+        let w4 = vec![DataType::None, DataType::None, "w".into()];
+        let u6 = vec![DataType::None, DataType::None, "u".into()];
+
+        // two matches for w4 arrive in the same batch, so its padding is retracted only once
+        g.seed(gl, vec![4.into(), "d".into()]);
+        g.seed(gl, vec![4.into(), "f".into()]);
+        let rs = g.one(l,
+                       vec![pos(vec![4.into(), "d".into()]), pos(vec![4.into(), "f".into()])],
+                       false);
+        assert_eq!(rs,
+                   vec![neg(w4.clone()),
+                        pos(vec![4.into(), "d".into(), "w".into()]),
+                        pos(vec![4.into(), "f".into(), "w".into()])]
+                       .into());
+
+        // and once both are removed in one batch, it is padded again, once
+        g.unseed(gl, vec![4.into(), "d".into()]);
+        g.unseed(gl, vec![4.into(), "f".into()]);
+        let rs = g.one(l,
+                       vec![neg(vec![4.into(), "d".into()]), neg(vec![4.into(), "f".into()])],
+                       false);
+        assert_eq!(rs,
+                   vec![neg(vec![4.into(), "d".into(), "w".into()]),
+                        pos(w4.clone()),
+                        neg(vec![4.into(), "f".into(), "w".into()])]
+                       .into());
+
+        // a row that is added and removed within a batch leaves the padding as it was
+        let rs = g.one(l,
+                       vec![pos(vec![6.into(), "g".into()]), neg(vec![6.into(), "g".into()])],
+                       false);
+        assert_eq!(rs,
+                   vec![neg(u6.clone()),
+                        pos(vec![6.into(), "g".into(), "u".into()]),
+                        pos(u6.clone()),
+                        neg(vec![6.into(), "g".into(), "u".into()])]
+                       .into());
+    }
+
+    #[test]
+    #[should_panic(expected = "group 1 is assigned to more than one column")]
+    fn it_rejects_duplicate_groups() {
+        let l = NodeAddress::mock_global(1.into());
+        Builder::new(vec![(l, 0)]).from(l, vec![1, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "which only has 2 columns")]
+    fn it_rejects_out_of_bounds_emit() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        let j = Builder::new(vec![(l, 0), (r, 2)]).from(l, vec![1, 0]).join(r, vec![1, 0]);
+        g.set_op("join", &["j0", "j1"], j, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "which has 2 columns")]
+    fn it_rejects_wrong_group_count() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+        let j = Builder::new(vec![(l, 0), (r, 1)]).from(l, vec![1]).join(r, vec![1, 0]);
+        g.set_op("join", &["j0", "j1"], j, false);
+    }
+
+    #[test]
+    fn it_applies_conditions() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        // join on first field, but only where l1 < r1
+        let j: Joiner = Builder::new(vec![(l, 0), (l, 1), (r, 1)])
+            .from(l, vec![1, 0])
+            .join(r, vec![1, 0])
+            .compare((l, 1), Comparison::Less, (r, 1))
+            .into();
+        g.set_op("join", &["j0", "j1", "j2"], j, false);
+        g.seed(l, vec![1.into(), 5.into()]);
+        g.seed(r, vec![1.into(), 3.into()]);
+        g.seed(r, vec![1.into(), 7.into()]);
+        let (l, r) = (g.to_local(l), g.to_local(r));
+
+        assert_eq!(g.node().description(),
+                   format!("[{}:0, {}:1, {}:1] {}:0 ⋈ {}:0, {}:1 < {}:1",
+                           l,
+                           l,
+                           r,
+                           l,
+                           r,
+                           l,
+                           r));
+
+        // only 7 is greater than 5
+        assert_eq!(g.one_row(l, vec![1.into(), 5.into()], false),
+                   vec![vec![1.into(), 5.into(), 7.into()]].into());
+
+        // from the right, the condition is still evaluated as l1 < r1
+        assert_eq!(g.one_row(r, vec![1.into(), 4.into()], false).len(), 0);
+        assert_eq!(g.one_row(r, vec![1.into(), 6.into()], false),
+                   vec![vec![1.into(), 5.into(), 6.into()]].into());
+
+        // comparisons with NULL are unknown, and so never hold
+        assert_eq!(g.one_row(r, vec![1.into(), DataType::None], false).len(), 0);
+    }
+
+    #[test]
+    fn it_compares_like_predicates() {
+        // numbers compare by value, regardless of their representation
+        assert!(Comparison::Less.matches(&DataType::from(0.5f64), &DataType::Int(100)));
+        assert!(Comparison::Equal.matches(&DataType::Int(2), &DataType::BigInt(2)));
+        // and NULL or mismatched types never match, not even for inequality
+        assert!(!Comparison::Less.matches(&DataType::None, &1.into()));
+        assert!(!Comparison::NotEqual.matches(&DataType::None, &1.into()));
+        assert!(!Comparison::NotEqual.matches(&"a".into(), &1.into()));
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
@@ -1,7 +1,6 @@
 use ops;
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 
 use flow::prelude::*;
 
@@ -9,13 +8,17 @@ use flow::prelude::*;
 ///
 /// Whenever a new record arrives for a group, the latest operator will negative the previous
 /// latest for that group.
+///
+/// By default, the latest record is the one that was processed most recently. A latest operator
+/// constructed with `Latest::over` instead keeps the record with the greatest value in a given
+/// column, so that records that arrive late or out of order do not replace newer ones.
 #[derive(Debug, Clone)]
 pub struct Latest {
     us: Option<NodeAddress>,
     src: NodeAddress,
-    // MUST be in reverse sorted order!
+    // in ascending order, which is also the order of the columns of our state's index
     key: Vec<usize>,
-    key_m: HashMap<usize, usize>,
+    order: Option<usize>,
 }
 
 impl Latest {
@@ -23,19 +26,34 @@ impl Latest {
     ///
     /// `src` should be the ancestor the operation is performed over, and `keys` should be a list
     /// of fields used to group records by. The latest record *within each group* will be
-    /// maintained.
+    /// maintained. Groups may be keyed by up to four columns.
     pub fn new(src: NodeAddress, mut keys: Vec<usize>) -> Latest {
-        assert_eq!(keys.len(),
-                   1,
-                   "only latest over a single column is supported");
+        assert!(!keys.is_empty() && keys.len() <= 4,
+                "latest must group by between one and four columns");
         keys.sort();
-        let key_m = keys.clone().into_iter().enumerate().map(|(idx, col)| (col, idx)).collect();
-        keys.reverse();
+        keys.dedup();
         Latest {
             us: None,
             src: src,
             key: keys,
-            key_m: key_m,
+            order: None,
+        }
+    }
+
+    /// Construct a new latest operator that orders records by the value in column `order`.
+    ///
+    /// A record only replaces the current latest record for its group if its value in `order`
+    /// is strictly greater than that of the current latest record. Other records are ignored.
+    pub fn over(src: NodeAddress, keys: Vec<usize>, order: usize) -> Latest {
+        let mut l = Latest::new(src, keys);
+        l.order = Some(order);
+        l
+    }
+
+    fn supersedes(&self, new: &[DataType], current: &[DataType]) -> bool {
+        match self.order {
+            Some(col) => new[col] > current[col],
+            None => true,
         }
     }
 }
@@ -82,29 +100,42 @@ impl Ingredient for Latest {
         // Then, we assert that there are no negatives whose key does not appear in the
         // list of keys that have been handled.
         let (pos, _): (Vec<_>, _) = rs.into_iter().partition(|r| r.is_positive());
-        let mut handled = HashSet::new();
+
+        // the current latest for every group we have seen in this batch. our own state is not
+        // updated until we return, so later records in the batch must be compared against these.
+        let mut handled = HashMap::new();
 
         // buffer emitted records
         let mut out = Vec::with_capacity(pos.len());
         for r in pos {
+            let (r, _) = r.extract();
             let group: Vec<_> = self.key.iter().map(|&col| r[col].clone()).collect();
-            handled.insert(group);
-
-            {
-                let r = r.rec();
-
-                // find the current value for this group
-                let db = state.get(self.us.as_ref().unwrap().as_local())
-                    .expect("latest must have its own state materialized");
-                let rs = db.lookup(&[self.key[0]], &KeyType::Single(&r[self.key[0]]));
-                debug_assert!(rs.len() <= 1, "a group had more than 1 result");
-                if let Some(current) = rs.get(0) {
-                    out.push(ops::Record::Negative(current.clone()));
+
+            let current = match handled.get(&group) {
+                Some(current) => Some(Clone::clone(current)),
+                None => {
+                    // find the current value for this group
+                    let db = state.get(self.us.as_ref().unwrap().as_local())
+                        .expect("latest must have its own state materialized");
+                    let rs = db.lookup(&self.key[..], &KeyType::from(&group[..]));
+                    debug_assert!(rs.len() <= 1, "a group had more than 1 result");
+                    rs.get(0).cloned()
+                }
+            };
+
+            if let Some(current) = current {
+                if !self.supersedes(&r[..], &current[..]) {
+                    // an older record arrived late; the current latest stays
+                    handled.insert(group, current);
+                    continue;
                 }
+
+                // if there was a previous latest for this key, revoke old record
+                out.push(ops::Record::Negative(current));
             }
 
-            // if there was a previous latest for this key, revoke old record
-            out.push(r);
+            handled.insert(group, r.clone());
+            out.push(ops::Record::Positive(r));
         }
 
         // TODO: check that there aren't any standalone negatives
@@ -127,7 +158,10 @@ impl Ingredient for Latest {
             .map(|k| k.to_string())
             .collect::<Vec<_>>()
             .join(", ");
-        format!("⧖ γ[{}]", key_cols)
+        match self.order {
+            Some(col) => format!("⧖[{}] γ[{}]", col, key_cols),
+            None => format!("⧖ γ[{}]", key_cols),
+        }
     }
 
     fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
@@ -140,6 +174,7 @@ mod tests {
     use super::*;
 
     use ops;
+    use std::sync::Arc;
 
     fn setup(key: usize, mat: bool) -> ops::test::MockGraph {
         let mut g = ops::test::MockGraph::new();
@@ -245,6 +280,69 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn it_orders_by_column() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("latest", &["x", "y"], Latest::over(s, vec![0], 1), true);
+        assert_eq!(g.node().description(), "⧖[1] γ[0]");
+
+        // first record for a group is always the latest
+        let rs = g.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 2.into()]].into());
+
+        // an older record should not replace it
+        let rs = g.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(rs.len(), 0);
+
+        // neither should one with the same ordering value
+        let rs = g.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(rs.len(), 0);
+
+        // but a newer one should
+        let rs = g.narrow_one_row(vec![1.into(), 3.into()], true);
+        assert_eq!(rs,
+                   vec![ops::Record::Negative(Arc::new(vec![1.into(), 2.into()])),
+                        ops::Record::Positive(Arc::new(vec![1.into(), 3.into()]))]
+                       .into());
+
+        // out-of-order records in a single batch should only leave the newest one
+        let u = vec![vec![1.into(), 5.into()], vec![1.into(), 4.into()], vec![1.into(), 6.into()]];
+        let rs = g.narrow_one(u, true);
+        assert_eq!(rs,
+                   vec![ops::Record::Negative(Arc::new(vec![1.into(), 3.into()])),
+                        ops::Record::Positive(Arc::new(vec![1.into(), 5.into()])),
+                        ops::Record::Negative(Arc::new(vec![1.into(), 5.into()])),
+                        ops::Record::Positive(Arc::new(vec![1.into(), 6.into()]))]
+                       .into());
+    }
+
+    #[test]
+    fn it_groups_by_several_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        g.set_op("latest", &["x", "y", "z"], Latest::new(s, vec![1, 0]), true);
+        assert_eq!(g.node().description(), "⧖ γ[0, 1]");
+
+        let rs = g.narrow_one_row(vec![1.into(), 1.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 1.into(), 1.into()]].into());
+
+        // a record that only shares one of the key columns is in a different group
+        let rs = g.narrow_one_row(vec![1.into(), 2.into(), 2.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 2.into(), 2.into()]].into());
+
+        // but one that shares both replaces the latest for its group
+        let rs = g.narrow_one_row(vec![1.into(), 1.into(), 3.into()], true);
+        assert_eq!(rs,
+                   vec![ops::Record::Negative(Arc::new(vec![1.into(), 1.into(), 1.into()])),
+                        ops::Record::Positive(Arc::new(vec![1.into(), 1.into(), 3.into()]))]
+                       .into());
+
+        // all key columns are indexed together
+        let me = NodeAddress::mock_global(1.into());
+        assert_eq!(g.node().suggest_indexes(me)[&me], vec![0, 1]);
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = NodeAddress::mock_global(1.into());
@@ -8,6 +8,12 @@ pub mod union;
 pub mod identity;
 pub mod gatedid;
 pub mod filter;
+pub mod shard;
+pub mod predicate;
+pub mod sequence;
+pub mod semijoin;
+pub mod topk;
+pub mod window;
 
 use flow::data::DataType;
 use std::ops::{Deref, DerefMut};
@@ -19,6 +25,7 @@ pub enum Record {
     Positive(sync::Arc<Vec<DataType>>),
     Negative(sync::Arc<Vec<DataType>>),
     DeleteRequest(Vec<DataType>),
+    TruncateRequest,
 }
 
 impl Record {
@@ -26,7 +33,8 @@ impl Record {
         match *self {
             Record::Positive(ref v) |
             Record::Negative(ref v) => &v[..],
-            Record::DeleteRequest(..) => unreachable!(),
+            Record::DeleteRequest(..) |
+            Record::TruncateRequest => unreachable!(),
         }
     }
 
@@ -42,7 +50,8 @@ impl Record {
         match self {
             Record::Positive(v) => (v, true),
             Record::Negative(v) => (v, false),
-            Record::DeleteRequest(..) => unreachable!(),
+            Record::DeleteRequest(..) |
+            Record::TruncateRequest => unreachable!(),
         }
     }
 }
@@ -53,7 +62,8 @@ impl Deref for Record {
         match *self {
             Record::Positive(ref r) |
             Record::Negative(ref r) => r,
-            Record::DeleteRequest(..) => unreachable!(),
+            Record::DeleteRequest(..) |
+            Record::TruncateRequest => unreachable!(),
         }
     }
 }
@@ -63,7 +73,8 @@ impl DerefMut for Record {
         match *self {
             Record::Positive(ref mut r) |
             Record::Negative(ref mut r) => r,
-            Record::DeleteRequest(..) => unreachable!(),
+            Record::DeleteRequest(..) |
+            Record::TruncateRequest => unreachable!(),
         }
     }
 }
@@ -179,6 +190,7 @@ pub mod test {
     use flow::prelude::*;
     use flow::domain::single;
     use flow::node;
+    use ops::base::Base;
 
     use petgraph::graph::NodeIndex;
 
@@ -208,8 +220,15 @@ pub mod test {
         }
 
         pub fn add_base(&mut self, name: &str, fields: &[&str]) -> NodeAddress {
-            use ops::base::Base;
-            let mut i: node::Type = Base::default().into();
+            self.add_base_with(name, fields, Base::default())
+        }
+
+        pub fn add_base_with(&mut self,
+                             name: &str,
+                             fields: &[&str],
+                             base: Base)
+                             -> NodeAddress {
+            let mut i: node::Type = base.into();
             i.on_connected(&self.graph);
             let ni = self.graph.add_node(Node::new(name, fields, i));
             self.graph.add_edge(self.source, ni, false);
@@ -231,6 +250,9 @@ pub mod test {
             assert!(self.nut.is_none(), "only one node under test is supported");
 
             let mut i: node::Type = i.into();
+            if let Err(e) = i.validate(&self.graph) {
+                panic!("cannot add {}: {}", name, e);
+            }
             i.on_connected(&self.graph);
 
             let parents = i.ancestors();
@@ -256,6 +278,12 @@ pub mod test {
                     s.add_key(&col[..]);
                 }
             }
+            let idx = self.graph[ni].suggest_ordered_indexes(local);
+            for (tbl, col) in idx {
+                if let Some(ref mut s) = self.states.get_mut(tbl.as_local()) {
+                    s.add_ordered_key(&col[..]);
+                }
+            }
             // and get rid of states we don't need
             let unused: Vec<_> = self.remap
                 .values()
@@ -286,6 +314,7 @@ pub mod test {
                         index: ni,
                         inner: n,
                         children: Vec::default(),
+                        batched: Vec::new(),
                     }
                 })
                 .collect();
@@ -316,7 +345,8 @@ pub mod test {
                 match data.into() {
                     Record::Positive(r) => state.insert(r),
                     Record::Negative(_) => unreachable!(),
-                    Record::DeleteRequest(..) => unreachable!(),
+                    Record::DeleteRequest(..) |
+                    Record::TruncateRequest => unreachable!(),
                 }
             } else {
                 assert!(false,
@@ -325,6 +355,17 @@ pub mod test {
             }
         }
 
+        pub fn unseed(&mut self, base: NodeAddress, data: Vec<DataType>) {
+            assert!(self.nut.is_some(), "unseed must happen after set_op");
+
+            // see seed for why this works
+            let local = self.to_local(base);
+            self.states
+                .get_mut(local.as_local())
+                .expect("cannot unseed a base that has no state")
+                .remove(&data[..]);
+        }
+
         pub fn one<U: Into<Records>>(&mut self, src: NodeAddress, u: U, remember: bool) -> Records {
             assert!(self.nut.is_some());
             assert!(!remember || self.states.contains_key(self.nut.unwrap().1.as_local()));
@@ -0,0 +1,406 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync;
+
+use flow::prelude::*;
+use ops::join::Comparison;
+
+/// The right-hand side of a comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    /// The value of the given column.
+    Column(usize),
+    /// A constant value.
+    Literal(DataType),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Operand::Column(c) => write!(f, "[{}]", c),
+            Operand::Literal(ref v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A boolean expression over the columns of a row.
+///
+/// Predicates follow SQL's three-valued logic: a comparison involving a `DataType::None` (or
+/// values of incomparable types) is neither true nor false, but unknown, and so is its negation.
+/// Rows are only let through if their predicate is true.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// Compare the given column with an operand.
+    Compare(usize, Comparison, Operand),
+    /// Whether the given column is `DataType::None`.
+    IsNull(usize),
+    /// True if all the given predicates are true.
+    And(Vec<Predicate>),
+    /// True if any of the given predicates is true.
+    Or(Vec<Predicate>),
+    /// True if the given predicate is false.
+    Not(Box<Predicate>),
+}
+
+/// Order two values, if they are comparable.
+pub(crate) fn compare(a: &DataType, b: &DataType) -> Option<Ordering> {
+    // the integral and fractional parts of a numeric value. for normalized values, ordering these
+    // lexicographically orders the values themselves.
+    fn numeric(d: &DataType) -> Option<(i64, i32)> {
+        match *d {
+            DataType::Int(n) => Some((n as i64, 0)),
+            DataType::BigInt(n) => Some((n, 0)),
+            DataType::Real(i, frac) => Some((i, frac)),
+            _ => None,
+        }
+    }
+
+    match (a, b) {
+        (&DataType::None, _) | (_, &DataType::None) => None,
+        (&DataType::Bool(a), &DataType::Bool(b)) => Some(a.cmp(&b)),
+        (&DataType::Bool(..), _) | (_, &DataType::Bool(..)) => None,
+        (&DataType::Timestamp(ref a), &DataType::Timestamp(ref b)) => Some(a.cmp(b)),
+        (&DataType::Timestamp(..), _) | (_, &DataType::Timestamp(..)) => None,
+        (&DataType::Bytes(ref a), &DataType::Bytes(ref b)) => Some(a.cmp(b)),
+        (&DataType::Bytes(..), _) | (_, &DataType::Bytes(..)) => None,
+        (&DataType::Text(..), _) |
+        (&DataType::TinyText(..), _) => {
+            match *b {
+                DataType::Text(..) |
+                DataType::TinyText(..) => {
+                    let (a, b): (String, String) = (a.into(), b.into());
+                    Some(a.cmp(&b))
+                }
+                _ => None,
+            }
+        }
+        _ => {
+            match (numeric(a), numeric(b)) {
+                (Some(a), Some(b)) => Some(a.cmp(&b)),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl Predicate {
+    /// Evaluate the predicate over the given row. `None` means that the result is unknown.
+    fn eval(&self, r: &[DataType]) -> Option<bool> {
+        match *self {
+            Predicate::Compare(col, op, ref rhs) => {
+                let rhs = match *rhs {
+                    Operand::Column(c) => &r[c],
+                    Operand::Literal(ref v) => v,
+                };
+                compare(&r[col], rhs).map(|ord| op.holds(ord))
+            }
+            Predicate::IsNull(col) => Some(r[col] == DataType::None),
+            Predicate::And(ref ps) => {
+                // false wins over unknown, which wins over true
+                let mut result = Some(true);
+                for p in ps {
+                    match p.eval(r) {
+                        Some(false) => return Some(false),
+                        None => result = None,
+                        Some(true) => (),
+                    }
+                }
+                result
+            }
+            Predicate::Or(ref ps) => {
+                // true wins over unknown, which wins over false
+                let mut result = Some(false);
+                for p in ps {
+                    match p.eval(r) {
+                        Some(true) => return Some(true),
+                        None => result = None,
+                        Some(false) => (),
+                    }
+                }
+                result
+            }
+            Predicate::Not(ref p) => p.eval(r).map(|b| !b),
+        }
+    }
+
+    /// Whether the predicate holds for the given row.
+    pub fn matches(&self, r: &[DataType]) -> bool {
+        self.eval(r) == Some(true)
+    }
+
+    /// The columns the predicate refers to, possibly with duplicates.
+    pub fn columns(&self) -> Vec<usize> {
+        fn collect(p: &Predicate, cols: &mut Vec<usize>) {
+            match *p {
+                Predicate::Compare(col, _, ref rhs) => {
+                    cols.push(col);
+                    if let Operand::Column(c) = *rhs {
+                        cols.push(c);
+                    }
+                }
+                Predicate::IsNull(col) => cols.push(col),
+                Predicate::And(ref ps) |
+                Predicate::Or(ref ps) => {
+                    for p in ps {
+                        collect(p, cols);
+                    }
+                }
+                Predicate::Not(ref p) => collect(p, cols),
+            }
+        }
+
+        let mut cols = Vec::new();
+        collect(self, &mut cols);
+        cols
+    }
+
+    /// Renumber every column the predicate refers to using `f`.
+    pub fn map_columns<F>(&mut self, f: &F)
+        where F: Fn(usize) -> usize
+    {
+        match *self {
+            Predicate::Compare(ref mut col, _, ref mut rhs) => {
+                *col = f(*col);
+                if let Operand::Column(ref mut c) = *rhs {
+                    *c = f(*c);
+                }
+            }
+            Predicate::IsNull(ref mut col) => *col = f(*col),
+            Predicate::And(ref mut ps) |
+            Predicate::Or(ref mut ps) => {
+                for p in ps {
+                    p.map_columns(f);
+                }
+            }
+            Predicate::Not(ref mut p) => p.map_columns(f),
+        }
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Predicate::Compare(col, op, ref rhs) => write!(f, "[{}]{}{}", col, op.symbol(), rhs),
+            Predicate::IsNull(col) => write!(f, "[{}]=∅", col),
+            Predicate::And(ref ps) |
+            Predicate::Or(ref ps) => {
+                let sep = if let Predicate::And(..) = *self {
+                    " ∧ "
+                } else {
+                    " ∨ "
+                };
+                write!(f,
+                       "({})",
+                       ps.iter()
+                           .map(|p| p.to_string())
+                           .collect::<Vec<_>>()
+                           .join(sep))
+            }
+            Predicate::Not(ref p) => write!(f, "¬{}", p),
+        }
+    }
+}
+
+/// Filters incoming records according to an arbitrary `Predicate` over their columns.
+///
+/// Unlike `Filter`, which can only check columns for equality with constants, this can express
+/// disjunctions, negations, inequalities, and comparisons between columns, so that an entire
+/// `WHERE` clause can be evaluated by a single node.
+#[derive(Debug, Clone)]
+pub struct PredicateFilter {
+    src: NodeAddress,
+    predicate: sync::Arc<Predicate>,
+}
+
+impl PredicateFilter {
+    /// Construct a new filter operator that only lets through the records from `src` that match
+    /// `predicate`.
+    pub fn new(src: NodeAddress, predicate: Predicate) -> PredicateFilter {
+        PredicateFilter {
+            src: src,
+            predicate: sync::Arc::new(predicate),
+        }
+    }
+}
+
+impl Ingredient for PredicateFilter {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.src]
+    }
+
+    fn should_materialize(&self) -> bool {
+        false
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        false
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[*self.src.as_global()];
+        assert!(self.predicate.columns().into_iter().all(|c| c < srcn.fields().len()),
+                "predicate refers to columns that {} does not have",
+                srcn.name());
+    }
+
+    fn on_commit(&mut self, _: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.src = remap[&self.src];
+    }
+
+    fn on_input(&mut self,
+                _: NodeAddress,
+                mut rs: Records,
+                _: &DomainNodes,
+                _: &StateMap)
+                -> Records {
+        rs.retain(|r| self.predicate.matches(&r[..]));
+        rs
+    }
+
+    fn suggest_indexes(&self, _: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        HashMap::new()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        Some(vec![(self.src, col)])
+    }
+
+    fn description(&self) -> String {
+        format!("σ{}", self.predicate)
+    }
+
+    fn can_query_through(&self) -> bool {
+        true
+    }
+
+    fn query_through<'a>(&self,
+                         columns: &[usize],
+                         key: &KeyType<DataType>,
+                         states: &'a StateMap)
+                         -> Option<Box<Iterator<Item = &'a sync::Arc<Vec<DataType>>> + 'a>> {
+        states.get(self.src.as_local()).map(|state| {
+            let p = self.predicate.clone();
+            Box::new(state.lookup(columns, key).iter().filter(move |r| p.matches(&r[..]))) as
+            Box<_>
+        })
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        vec![(self.src, Some(column))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+    use ops::join::Comparison;
+
+    fn setup(p: Predicate) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("filter", &["x", "y"], PredicateFilter::new(s, p), false);
+        g
+    }
+
+    fn lit(c: usize, op: Comparison, v: DataType) -> Predicate {
+        Predicate::Compare(c, op, Operand::Literal(v))
+    }
+
+    fn passes(g: &mut ops::test::MockGraph, r: Vec<DataType>) -> bool {
+        !g.narrow_one_row(r, false).is_empty()
+    }
+
+    #[test]
+    fn it_describes() {
+        let g = setup(Predicate::Or(vec![lit(0, Comparison::Less, 1.into()),
+                                         Predicate::Not(Box::new(Predicate::IsNull(1)))]));
+        assert_eq!(g.node().description(), "σ([0]<1 ∨ ¬[1]=∅)");
+    }
+
+    #[test]
+    fn it_compares() {
+        let mut g = setup(Predicate::And(vec![lit(0, Comparison::GreaterOrEqual, 2.into()),
+                                              lit(1, Comparison::NotEqual, "a".into())]));
+        assert!(passes(&mut g, vec![2.into(), "b".into()]));
+        assert!(passes(&mut g, vec![DataType::BigInt(3), "b".into()]));
+        assert!(passes(&mut g, vec![2.5f64.into(), "b".into()]));
+        assert!(!passes(&mut g, vec![1.into(), "b".into()]));
+        assert!(!passes(&mut g, vec![1.5f64.into(), "b".into()]));
+        assert!(!passes(&mut g, vec![2.into(), "a".into()]));
+    }
+
+    #[test]
+    fn it_compares_columns() {
+        let mut g = setup(Predicate::Compare(0, Comparison::Less, Operand::Column(1)));
+        assert!(passes(&mut g, vec![1.into(), 2.into()]));
+        assert!(!passes(&mut g, vec![2.into(), 2.into()]));
+        assert!(passes(&mut g, vec!["a".into(), "b".into()]));
+        // values of different types are not comparable
+        assert!(!passes(&mut g, vec![1.into(), "b".into()]));
+    }
+
+    #[test]
+    fn it_compares_booleans() {
+        let mut g = setup(lit(0, Comparison::Equal, true.into()));
+        assert!(passes(&mut g, vec![true.into(), "a".into()]));
+        assert!(!passes(&mut g, vec![false.into(), "a".into()]));
+        // booleans are not integers
+        assert!(!passes(&mut g, vec![1.into(), "a".into()]));
+
+        let mut g = setup(lit(0, Comparison::Less, true.into()));
+        assert!(passes(&mut g, vec![false.into(), "a".into()]));
+        assert!(!passes(&mut g, vec![true.into(), "a".into()]));
+    }
+
+    #[test]
+    fn it_compares_timestamps() {
+        use chrono::naive::date::NaiveDate;
+
+        let day = |d| NaiveDate::from_ymd(2017, 3, d).and_hms(12, 0, 0);
+        let mut g = setup(lit(0, Comparison::GreaterOrEqual, day(2).into()));
+        assert!(passes(&mut g, vec![day(2).into(), "a".into()]));
+        assert!(passes(&mut g, vec![day(3).into(), "a".into()]));
+        assert!(!passes(&mut g, vec![day(1).into(), "a".into()]));
+        // timestamps are not strings
+        assert!(!passes(&mut g, vec!["2017-03-03 12:00:00".into(), "a".into()]));
+    }
+
+    #[test]
+    fn it_handles_disjunctions() {
+        let mut g = setup(Predicate::Or(vec![lit(0, Comparison::Equal, 1.into()),
+                                             lit(1, Comparison::Equal, "a".into())]));
+        assert!(passes(&mut g, vec![1.into(), "b".into()]));
+        assert!(passes(&mut g, vec![2.into(), "a".into()]));
+        assert!(!passes(&mut g, vec![2.into(), "b".into()]));
+    }
+
+    #[test]
+    fn it_handles_nulls() {
+        let mut g = setup(Predicate::Not(Box::new(lit(0, Comparison::Equal, 1.into()))));
+        assert!(passes(&mut g, vec![2.into(), "a".into()]));
+        assert!(!passes(&mut g, vec![1.into(), "a".into()]));
+        // a comparison with NULL is unknown, and so is its negation
+        assert!(!passes(&mut g, vec![DataType::None, "a".into()]));
+
+        // but an unknown disjunct does not stop another from being true
+        let mut g = setup(Predicate::Or(vec![lit(0, Comparison::Equal, 1.into()),
+                                             Predicate::IsNull(0)]));
+        assert!(passes(&mut g, vec![DataType::None, "a".into()]));
+        assert!(passes(&mut g, vec![1.into(), "a".into()]));
+        assert!(!passes(&mut g, vec![2.into(), "a".into()]));
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(Predicate::IsNull(0));
+        assert_eq!(g.node().resolve(0), Some(vec![(g.narrow_base_id(), 0)]));
+        assert_eq!(g.node().resolve(1), Some(vec![(g.narrow_base_id(), 1)]));
+    }
+}
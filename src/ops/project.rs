@@ -1,14 +1,168 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::sync;
 
+use chrono::{Datelike, Timelike};
+
 use flow::prelude::*;
 
-/// Permutes or omits columns from its source node, or adds additional literal value columns.
+/// An operator that combines two values in an `Expression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    /// Numeric addition.
+    Add,
+    /// Numeric subtraction.
+    Subtract,
+    /// Numeric multiplication.
+    Multiply,
+    /// Numeric division, which always produces a real value.
+    Divide,
+    /// Concatenation of the two values as text.
+    Concat,
+}
+
+impl BinaryOperator {
+    fn apply(&self, a: &DataType, b: &DataType) -> DataType {
+        // as in SQL, any operation on NULL produces NULL
+        if *a == DataType::None || *b == DataType::None {
+            return DataType::None;
+        }
+
+        match *self {
+            BinaryOperator::Add => a + b,
+            BinaryOperator::Subtract => a - b,
+            BinaryOperator::Multiply => a * b,
+            BinaryOperator::Divide => a / b,
+            BinaryOperator::Concat => {
+                let text = |d: &DataType| -> String {
+                    match *d {
+                        DataType::Text(..) |
+                        DataType::TinyText(..) => d.into(),
+                        _ => d.to_string(),
+                    }
+                };
+                let mut s = text(a);
+                s.push_str(&text(b));
+                s.into()
+            }
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match *self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "×",
+            BinaryOperator::Divide => "÷",
+            BinaryOperator::Concat => "||",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// A part of a timestamp that an `Expression` can extract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatePart {
+    /// The year, such as 2017.
+    Year,
+    /// The month, from 1 to 12.
+    Month,
+    /// The day of the month, from 1 to 31.
+    Day,
+    /// The hour of the day, from 0 to 23.
+    Hour,
+}
+
+impl DatePart {
+    fn extract(&self, v: &DataType) -> DataType {
+        let t = match *v {
+            DataType::None => return DataType::None,
+            DataType::Timestamp(ref t) => t,
+            _ => unreachable!("cannot extract the {} of non-timestamp value {}", self, v),
+        };
+
+        let n = match *self {
+            DatePart::Year => t.year(),
+            DatePart::Month => t.month() as i32,
+            DatePart::Day => t.day() as i32,
+            DatePart::Hour => t.hour() as i32,
+        };
+        DataType::Int(n)
+    }
+}
+
+impl fmt::Display for DatePart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let part = match *self {
+            DatePart::Year => "year",
+            DatePart::Month => "month",
+            DatePart::Day => "day",
+            DatePart::Hour => "hour",
+        };
+        write!(f, "{}", part)
+    }
+}
+
+/// A value computed from the columns of a record, such as `price * quantity`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    /// The value of the given column.
+    Column(usize),
+    /// A constant value.
+    Literal(DataType),
+    /// The result of combining the values of two expressions.
+    Op(BinaryOperator, Box<Expression>, Box<Expression>),
+    /// The given part of the timestamp an expression evaluates to, as an integer.
+    Extract(DatePart, Box<Expression>),
+}
+
+impl Expression {
+    /// Evaluate the expression over the given record.
+    pub fn eval(&self, r: &[DataType]) -> DataType {
+        match *self {
+            Expression::Column(c) => r[c].clone(),
+            Expression::Literal(ref v) => v.clone(),
+            Expression::Op(op, ref left, ref right) => op.apply(&left.eval(r), &right.eval(r)),
+            Expression::Extract(part, ref e) => part.extract(&e.eval(r)),
+        }
+    }
+
+    /// The columns the expression refers to, possibly with duplicates.
+    pub fn columns(&self) -> Vec<usize> {
+        match *self {
+            Expression::Column(c) => vec![c],
+            Expression::Literal(_) => vec![],
+            Expression::Op(_, ref left, ref right) => {
+                let mut cols = left.columns();
+                cols.extend(right.columns());
+                cols
+            }
+            Expression::Extract(_, ref e) => e.columns(),
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expression::Column(c) => write!(f, "[{}]", c),
+            Expression::Literal(ref v) => write!(f, "{}", v),
+            Expression::Op(op, ref left, ref right) => write!(f, "({} {} {})", left, op, right),
+            Expression::Extract(part, ref e) => write!(f, "{}({})", part, e),
+        }
+    }
+}
+
+/// Permutes or omits columns from its source node, or adds additional literal value columns, or
+/// columns computed from the values of other columns.
 #[derive(Debug, Clone)]
 pub struct Project {
     us: Option<NodeAddress>,
     emit: Option<Vec<usize>>,
     additional: Option<Vec<DataType>>,
+    expressions: Option<Vec<Expression>>,
     src: NodeAddress,
     cols: usize,
 }
@@ -19,12 +173,36 @@ impl Project {
         Project {
             emit: Some(emit.into()),
             additional: additional,
+            expressions: None,
+            src: src,
+            cols: 0,
+            us: None,
+        }
+    }
+
+    /// Construct a new projection operator that emits the given columns of `src`, followed by one
+    /// column for each of the given expressions, computed over the columns of `src`.
+    pub fn computed(src: NodeAddress, emit: &[usize], expressions: Vec<Expression>) -> Project {
+        Project {
+            emit: Some(emit.into()),
+            additional: None,
+            expressions: Some(expressions),
             src: src,
             cols: 0,
             us: None,
         }
     }
 
+    /// Whether the given column is computed by one of our expressions.
+    fn is_computed(&self, col: usize) -> bool {
+        match self.emit {
+            Some(ref emit) => {
+                col >= emit.len() + self.additional.as_ref().map(|a| a.len()).unwrap_or(0)
+            }
+            None => false,
+        }
+    }
+
     fn resolve_col(&self, col: usize) -> usize {
         if self.emit.is_some() && col >= self.emit.as_ref().unwrap().len() {
             panic!("can't resolve literal column {} that doesn't come from parent node!",
@@ -54,6 +232,12 @@ impl Ingredient for Project {
 
     fn on_connected(&mut self, g: &Graph) {
         self.cols = g[*self.src.as_global()].fields().len();
+        if let Some(ref es) = self.expressions {
+            let cols = self.cols;
+            assert!(es.iter().flat_map(|e| e.columns()).all(|c| c < cols),
+                    "expressions refer to columns that {} does not have",
+                    g[*self.src.as_global()].name());
+        }
     }
 
     fn on_commit(&mut self, us: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
@@ -64,7 +248,8 @@ impl Ingredient for Project {
         // the inputs, so we don't needlessly perform extra work on each
         // update.
         self.emit = self.emit.take().and_then(|emit| {
-            let complete = emit.len() == self.cols && self.additional.is_none();
+            let complete = emit.len() == self.cols && self.additional.is_none() &&
+                           self.expressions.is_none();
             let sequential = emit.iter().enumerate().all(|(i, &j)| i == j);
             if complete && sequential {
                 None
@@ -93,9 +278,15 @@ impl Ingredient for Project {
                 for i in e {
                     new_r.push(r[*i].clone());
                 }
-                let a = self.additional.as_ref().unwrap();
-                for i in a {
-                    new_r.push(i.clone());
+                if let Some(ref a) = self.additional {
+                    for i in a {
+                        new_r.push(i.clone());
+                    }
+                }
+                if let Some(ref es) = self.expressions {
+                    for e in es {
+                        new_r.push(e.eval(&r[..]));
+                    }
                 }
                 **r = sync::Arc::new(new_r);
             }
@@ -109,6 +300,10 @@ impl Ingredient for Project {
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        if self.is_computed(col) {
+            // the value is created by us
+            return None;
+        }
         Some(vec![(self.src, self.resolve_col(col))])
     }
 
@@ -116,21 +311,14 @@ impl Ingredient for Project {
         let emit_cols = match self.emit.as_ref() {
             None => "*".into(),
             Some(emit) => {
-                match self.additional {
-                    None => {
-                        emit.iter()
-                            .map(|e| e.to_string())
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    }
-                    Some(ref add) => {
-                        emit.iter()
-                            .map(|e| e.to_string())
-                            .chain(add.iter().map(|e| format!("lit: {}", e.to_string())))
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    }
-                }
+                emit.iter()
+                    .map(|e| e.to_string())
+                    .chain(self.additional
+                        .iter()
+                        .flat_map(|add| add.iter().map(|e| format!("lit: {}", e.to_string()))))
+                    .chain(self.expressions.iter().flat_map(|es| es.iter().map(|e| e.to_string())))
+                    .collect::<Vec<_>>()
+                    .join(", ")
             }
         };
         format!("π[{}]", emit_cols)
@@ -216,6 +404,101 @@ mod tests {
                        .into());
     }
 
+    fn setup_computed(expressions: Vec<Expression>) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        let mut fields = vec!["x"];
+        fields.extend(expressions.iter().map(|_| "e"));
+        g.set_op("computed", &fields[..], Project::computed(s, &[0], expressions), false);
+        g
+    }
+
+    fn op(op: BinaryOperator, left: Expression, right: Expression) -> Expression {
+        Expression::Op(op, Box::new(left), Box::new(right))
+    }
+
+    fn col(c: usize) -> Expression {
+        Expression::Column(c)
+    }
+
+    fn lit<T: Into<DataType>>(v: T) -> Expression {
+        Expression::Literal(v.into())
+    }
+
+    #[test]
+    fn it_computes_arithmetic() {
+        let mut p = setup_computed(vec![op(BinaryOperator::Multiply, col(1), col(2)),
+                                        op(BinaryOperator::Add, col(1), lit(1)),
+                                        op(BinaryOperator::Divide, col(2), lit(4))]);
+        assert_eq!(p.node().description(), "π[0, ([1] × [2]), ([1] + 1), ([2] ÷ 4)]");
+
+        let rec: Vec<DataType> = vec!["a".into(), 3.into(), 2.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), 6.into(), 4.into(), 0.5f64.into()]].into());
+
+        let rec: Vec<DataType> = vec!["a".into(), 1.5f64.into(), 0.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), 0.0f64.into(), 2.5f64.into(), 0.0f64.into()]].into());
+    }
+
+    #[test]
+    fn it_computes_with_nulls() {
+        let mut p = setup_computed(vec![op(BinaryOperator::Add, col(1), col(2)),
+                                        op(BinaryOperator::Divide, col(1), col(2))]);
+
+        // NULLs propagate, and so do divisions by zero
+        let rec: Vec<DataType> = vec!["a".into(), DataType::None, 2.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), DataType::None, DataType::None]].into());
+        let rec: Vec<DataType> = vec!["a".into(), 1.into(), 0.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), 1.into(), DataType::None]].into());
+    }
+
+    #[test]
+    fn it_concatenates() {
+        let mut p = setup_computed(vec![op(BinaryOperator::Concat,
+                                           col(1),
+                                           op(BinaryOperator::Concat, lit("#"), col(2)))]);
+
+        let rec: Vec<DataType> = vec!["a".into(), "item".into(), 42.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), "item#42".into()]].into());
+    }
+
+    #[test]
+    fn it_extracts_date_parts() {
+        use chrono::naive::date::NaiveDate;
+
+        let extract = |part| Expression::Extract(part, Box::new(col(1)));
+        let mut p = setup_computed(vec![extract(DatePart::Year),
+                                        extract(DatePart::Month),
+                                        extract(DatePart::Day),
+                                        extract(DatePart::Hour)]);
+        assert_eq!(p.node().description(), "π[0, year([1]), month([1]), day([1]), hour([1])]");
+
+        let t = NaiveDate::from_ymd(2017, 3, 14).and_hms(15, 9, 26);
+        let rec: Vec<DataType> = vec!["a".into(), t.into(), 0.into()];
+        assert_eq!(p.narrow_one_row(rec, false),
+                   vec![vec!["a".into(), 2017.into(), 3.into(), 14.into(), 15.into()]].into());
+
+        let rec: Vec<DataType> = vec!["a".into(), DataType::None, 0.into()];
+        let expected: Vec<DataType> = vec!["a".into(),
+                                           DataType::None,
+                                           DataType::None,
+                                           DataType::None,
+                                           DataType::None];
+        assert_eq!(p.narrow_one_row(rec, false), vec![expected].into());
+    }
+
+    #[test]
+    fn it_resolves_computed() {
+        let p = setup_computed(vec![op(BinaryOperator::Add, col(1), lit(1))]);
+        assert_eq!(p.node().resolve(0), Some(vec![(p.narrow_base_id(), 0)]));
+        assert_eq!(p.node().resolve(1), None);
+        assert_eq!(p.node().parent_columns(1), vec![(p.narrow_base_id(), None)]);
+    }
+
     #[test]
     fn it_suggests_indices() {
         let me = NodeAddress::mock_global(1.into());
@@ -0,0 +1,273 @@
+use ops;
+
+use std::collections::HashMap;
+
+use flow::prelude::*;
+
+/// SemiJoin provides an operator that forwards the records of one view that have (or, for an
+/// anti-join, do not have) at least one record with the same value in another view.
+///
+/// Unlike a join, a semi-join never emits columns of the other view, and forwards every matching
+/// record exactly once, no matter how many records it matches. This is what SQL's `IN` and
+/// `EXISTS` conditions compute (and `NOT IN` and `NOT EXISTS`, for an anti-join).
+///
+/// When records arrive from the other view, the semi-join looks up the records that start or stop
+/// matching, so both ancestors must be materialized.
+#[derive(Debug, Clone)]
+pub struct SemiJoin {
+    left: NodeAddress,
+    right: NodeAddress,
+    on: (usize, usize),
+    anti: bool,
+}
+
+impl SemiJoin {
+    /// Construct a new operator that forwards the records of `left` whose column `on.0` is equal
+    /// to column `on.1` of at least one record of `right`.
+    pub fn semi(left: NodeAddress, right: NodeAddress, on: (usize, usize)) -> SemiJoin {
+        SemiJoin::new(left, right, on, false)
+    }
+
+    /// Construct a new operator that forwards the records of `left` whose column `on.0` is not
+    /// equal to column `on.1` of any record of `right`.
+    ///
+    /// See `SemiJoin::semi`.
+    pub fn anti(left: NodeAddress, right: NodeAddress, on: (usize, usize)) -> SemiJoin {
+        SemiJoin::new(left, right, on, true)
+    }
+
+    fn new(left: NodeAddress, right: NodeAddress, on: (usize, usize), anti: bool) -> SemiJoin {
+        assert!(left != right, "semi-joins need two different views");
+        SemiJoin {
+            left: left,
+            right: right,
+            on: on,
+            anti: anti,
+        }
+    }
+
+    /// The number of records in `right` that match the given value.
+    fn matches(&self, value: &DataType, nodes: &DomainNodes, state: &StateMap) -> usize {
+        self.lookup(self.right, &[self.on.1], &KeyType::Single(value), nodes, state)
+            .expect("semi-joins must have their inputs materialized")
+            .count()
+    }
+}
+
+impl Ingredient for SemiJoin {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.left, self.right]
+    }
+
+    fn should_materialize(&self) -> bool {
+        false
+    }
+
+    fn replay_ancestors(&self) -> Option<Vec<NodeAddress>> {
+        // all of our records come from the left
+        Some(vec![self.left])
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        true
+    }
+
+    fn on_connected(&mut self, _: &Graph) {}
+
+    fn on_commit(&mut self, _: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.left = remap[&self.left];
+        self.right = remap[&self.right];
+    }
+
+    fn on_input(&mut self,
+                from: NodeAddress,
+                rs: Records,
+                nodes: &DomainNodes,
+                state: &StateMap)
+                -> Records {
+        if from == self.left {
+            // forward the records that (don't) match anything, with their own sign
+            let mut matched = HashMap::new();
+            return rs.into_iter()
+                .filter(|r| {
+                    let value = &r[self.on.0];
+                    if !matched.contains_key(value) {
+                        let n = self.matches(value, nodes, state);
+                        matched.insert(value.clone(), n > 0);
+                    }
+                    matched[value] != self.anti
+                })
+                .collect();
+        }
+        debug_assert_eq!(from, self.right);
+
+        // our ancestor's state already reflects the records in this batch, so a value has just
+        // gained its first match if it has matches now but did not have any without this batch,
+        // and has just lost its last match if it had matches without this batch but does not now.
+        let mut delta = HashMap::new();
+        for r in rs.iter() {
+            let d = delta.entry(r[self.on.1].clone()).or_insert(0isize);
+            if r.is_positive() {
+                *d += 1;
+            } else {
+                *d -= 1;
+            }
+        }
+
+        let mut out = Vec::new();
+        for (value, d) in delta {
+            let now = self.matches(&value, nodes, state) as isize;
+            let before = now - d;
+            if (before > 0) == (now > 0) {
+                continue;
+            }
+
+            // the records from the left with this value start (or stop) being forwarded
+            let forward = (now > 0) != self.anti;
+            let records = self.lookup(self.left,
+                        &[self.on.0],
+                        &KeyType::Single(&value),
+                        nodes,
+                        state)
+                .expect("semi-joins must have their inputs materialized");
+            out.extend(records.cloned().map(|r| if forward {
+                ops::Record::Positive(r)
+            } else {
+                ops::Record::Negative(r)
+            }));
+        }
+        out.into()
+    }
+
+    fn suggest_indexes(&self, _: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        vec![(self.left, vec![self.on.0]), (self.right, vec![self.on.1])].into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        Some(vec![(self.left, col)])
+    }
+
+    fn description(&self) -> String {
+        format!("{}:{} {} {}:{}",
+                self.left,
+                self.on.0,
+                if self.anti { "∉" } else { "∈" },
+                self.right,
+                self.on.1)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        if column == self.on.0 {
+            vec![(self.left, Some(column)), (self.right, Some(self.on.1))]
+        } else {
+            vec![(self.left, Some(column))]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+    use std::sync;
+
+    /// Returns the graph, the local addresses of the left and right ancestors, and the global
+    /// address of the right ancestor (for seeding it).
+    fn setup(anti: bool) -> (ops::test::MockGraph, NodeAddress, NodeAddress, NodeAddress) {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0"]);
+        let s = if anti {
+            SemiJoin::anti(l, r, (0, 0))
+        } else {
+            SemiJoin::semi(l, r, (0, 0))
+        };
+        g.set_op("semi", &["l0", "l1"], s, false);
+        g.seed(l, vec![1.into(), "a".into()]);
+        g.seed(l, vec![1.into(), "b".into()]);
+        g.seed(l, vec![2.into(), "c".into()]);
+        g.seed(r, vec![1.into()]);
+
+        let (ll, rl) = (g.to_local(l), g.to_local(r));
+        (g, ll, rl, r)
+    }
+
+    fn pos(r: Vec<DataType>) -> ops::Record {
+        ops::Record::Positive(sync::Arc::new(r))
+    }
+
+    fn neg(r: Vec<DataType>) -> ops::Record {
+        ops::Record::Negative(sync::Arc::new(r))
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, l, r, _) = setup(false);
+        assert_eq!(g.node().description(), format!("{}:0 ∈ {}:0", l, r));
+        let (g, l, r, _) = setup(true);
+        assert_eq!(g.node().description(), format!("{}:0 ∉ {}:0", l, r));
+    }
+
+    #[test]
+    fn it_forwards_matching_records() {
+        let (mut g, l, _, _) = setup(false);
+        assert_eq!(g.one_row(l, vec![1.into(), "a".into()], false),
+                   vec![pos(vec![1.into(), "a".into()])].into());
+        assert_eq!(g.one_row(l, vec![2.into(), "c".into()], false).len(), 0);
+        assert_eq!(g.one_row(l, (vec![1.into(), "b".into()], false), false),
+                   vec![neg(vec![1.into(), "b".into()])].into());
+    }
+
+    #[test]
+    fn it_follows_changes_on_the_right() {
+        let (mut g, _, r, rg) = setup(false);
+
+        // the first match for a value brings in all the records with that value
+        g.seed(rg, vec![2.into()]);
+        assert_eq!(g.one_row(r, vec![2.into()], false),
+                   vec![pos(vec![2.into(), "c".into()])].into());
+
+        // further matches change nothing
+        g.seed(rg, vec![2.into()]);
+        assert_eq!(g.one_row(r, vec![2.into()], false).len(), 0);
+
+        // and the records leave again with their last match
+        g.unseed(rg, vec![1.into()]);
+        let rs = g.one_row(r, (vec![1.into()], false), false);
+        assert_eq!(rs.len(), 2);
+        assert!(rs.iter().all(|r| !r.is_positive() && r.rec()[0] == DataType::from(1)));
+    }
+
+    #[test]
+    fn it_anti_joins() {
+        let (mut g, l, r, rg) = setup(true);
+        assert_eq!(g.one_row(l, vec![1.into(), "a".into()], false).len(), 0);
+        assert_eq!(g.one_row(l, vec![2.into(), "c".into()], false),
+                   vec![pos(vec![2.into(), "c".into()])].into());
+
+        g.seed(rg, vec![2.into()]);
+        assert_eq!(g.one_row(r, vec![2.into()], false),
+                   vec![neg(vec![2.into(), "c".into()])].into());
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        use std::collections::HashMap;
+        let me = NodeAddress::mock_global(2.into());
+        let (g, l, r, _) = setup(false);
+        let hm: HashMap<_, _> = vec![(l, vec![0]), (r, vec![0])].into_iter().collect();
+        assert_eq!(g.node().suggest_indexes(me), hm);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, l, _, _) = setup(false);
+        assert_eq!(g.node().resolve(0), Some(vec![(l, 0)]));
+        assert_eq!(g.node().resolve(1), Some(vec![(l, 1)]));
+    }
+}
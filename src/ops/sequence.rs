@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::sync;
+
+use flow::prelude::*;
+
+/// Sequence assigns a monotonically increasing sequence number to every record that passes
+/// through it, either globally or within each group.
+///
+/// The sequence number is appended as an additional, final column. Sequence numbers start at 1,
+/// and while the operator runs they are never reused, even if the record they were assigned to is
+/// later revoked. The next number for each group is only kept in memory, however. If the
+/// operator's state is reconstructed (e.g., after a restart), the records replayed into it are
+/// numbered anew in the order they arrive, so the numbers are neither stable across
+/// reconstructions nor unique across them. Only base nodes are persisted, so this also holds
+/// when durability is on.
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    us: Option<NodeAddress>,
+    src: NodeAddress,
+    group: Vec<usize>,
+    cols: usize,
+    next: HashMap<Vec<DataType>, i64>,
+}
+
+impl Sequence {
+    /// Construct a new sequence operator.
+    ///
+    /// `src` should be the ancestor whose records are to be numbered, and `group` should be a
+    /// list of fields used to group records by. Each group is numbered independently; if `group`
+    /// is empty, a single global sequence is used.
+    pub fn new(src: NodeAddress, group: Vec<usize>) -> Sequence {
+        Sequence {
+            us: None,
+            src: src,
+            group: group,
+            cols: 0,
+            next: HashMap::new(),
+        }
+    }
+
+    /// The columns our own state is indexed by. A global sequence is indexed by its first column,
+    /// so that revoked records can be found without scanning all of its state.
+    fn key(&self) -> Vec<usize> {
+        if self.group.is_empty() {
+            vec![0]
+        } else {
+            self.group.clone()
+        }
+    }
+
+    /// All currently materialized records that belong to the given group.
+    fn existing<'a>(&self, group: &[DataType], db: &'a State) -> Vec<&'a [DataType]> {
+        if self.group.is_empty() {
+            db.iter().flat_map(|rs| rs.iter()).map(|r| &r[..]).collect()
+        } else {
+            self.matching(group, group, db)
+        }
+    }
+
+    /// The currently materialized records that may have been assigned to the record `r` of the
+    /// given group.
+    fn matching<'a>(&self,
+                    r: &[DataType],
+                    group: &[DataType],
+                    db: &'a State)
+                    -> Vec<&'a [DataType]> {
+        let (cols, key) = if self.group.is_empty() {
+            (vec![0], KeyType::Single(&r[0]))
+        } else {
+            (self.group.clone(), KeyType::from(group))
+        };
+        db.lookup(&cols[..], &key).iter().map(|r| &r[..]).collect()
+    }
+}
+
+impl Ingredient for Sequence {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.src]
+    }
+
+    fn should_materialize(&self) -> bool {
+        true
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        true // to find the numbers assigned to revoked records
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        self.cols = g[*self.src.as_global()].fields().len();
+    }
+
+    fn on_commit(&mut self, us: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.us = Some(us);
+        self.src = remap[&self.src];
+    }
+
+    fn on_input(&mut self,
+                from: NodeAddress,
+                rs: Records,
+                _: &DomainNodes,
+                state: &StateMap)
+                -> Records {
+        debug_assert_eq!(from, self.src);
+
+        let db = state.get(self.us.as_ref().unwrap().as_local())
+            .expect("sequence must have its own state materialized");
+
+        // records revoked earlier in this batch, which are not yet reflected in our state
+        let mut revoked: Vec<sync::Arc<Vec<DataType>>> = Vec::new();
+
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs {
+            let (r, positive) = r.extract();
+            let group: Vec<_> = self.group.iter().map(|&col| r[col].clone()).collect();
+
+            if positive {
+                if !self.next.contains_key(&group) {
+                    // first time we see this group -- resume after whatever we've emitted before
+                    let max = self.existing(&group[..], db)
+                        .into_iter()
+                        .map(|e| -> i64 { e[self.cols].clone().into() })
+                        .max()
+                        .unwrap_or(0);
+                    self.next.insert(group.clone(), max + 1);
+                }
+
+                let next = self.next.get_mut(&group).unwrap();
+                let mut new_r = Vec::with_capacity(self.cols + 1);
+                new_r.extend(r.iter().cloned());
+                new_r.push((*next).into());
+                *next += 1;
+                out.push(Record::Positive(sync::Arc::new(new_r)));
+            } else {
+                // find the number we assigned to this record the last time we saw it
+                let numbered = {
+                    let emitted = out.iter().filter(|o| o.is_positive()).map(|o| &o[..]);
+                    self.matching(&r[..], &group[..], db)
+                        .into_iter()
+                        .chain(emitted)
+                        .filter(|e| &e[..self.cols] == &r[..])
+                        .find(|e| !revoked.iter().any(|rv| &rv[..] == *e))
+                        .map(|e| sync::Arc::new(e.to_vec()))
+                };
+                match numbered {
+                    Some(e) => {
+                        revoked.push(e.clone());
+                        out.push(Record::Negative(e));
+                    }
+                    None => unreachable!("sequence saw negative for record it never numbered"),
+                }
+            }
+        }
+
+        out.into()
+    }
+
+    fn suggest_indexes(&self, this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        Some((this, self.key())).into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        if col == self.cols {
+            return None;
+        }
+        Some(vec![(self.src, col)])
+    }
+
+    fn description(&self) -> String {
+        let group_cols = self.group
+            .iter()
+            .map(|g| g.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("# γ[{}]", group_cols)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        if column == self.cols {
+            return vec![(self.src, None)];
+        }
+        vec![(self.src, Some(column))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+
+    fn setup(group: Vec<usize>) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("sequence", &["x", "y", "seq"], Sequence::new(s, group), true);
+        g
+    }
+
+    #[test]
+    fn it_describes() {
+        let c = setup(vec![0]);
+        assert_eq!(c.node().description(), "# γ[0]");
+    }
+
+    #[test]
+    fn it_numbers_globally() {
+        let mut c = setup(vec![]);
+
+        let rs = c.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 1.into(), 1.into()]].into());
+
+        let rs = c.narrow_one_row(vec![2.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![2.into(), 1.into(), 2.into()]].into());
+
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 2.into(), 3.into()]].into());
+    }
+
+    #[test]
+    fn it_numbers_per_group() {
+        let mut c = setup(vec![0]);
+
+        let rs = c.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 1.into(), 1.into()]].into());
+
+        let rs = c.narrow_one_row(vec![2.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![2.into(), 1.into(), 1.into()]].into());
+
+        let rs = c.narrow_one_row(vec![1.into(), 2.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 2.into(), 2.into()]].into());
+    }
+
+    #[test]
+    fn it_revokes_and_does_not_reuse() {
+        let mut c = setup(vec![0]);
+
+        c.narrow_one_row(vec![1.into(), 1.into()], true);
+        c.narrow_one_row(vec![1.into(), 2.into()], true);
+
+        // revoking a record should revoke it with the number it was assigned
+        let rs = c.narrow_one_row((vec![1.into(), 1.into()], false), true);
+        assert_eq!(rs,
+                   vec![(vec![1.into(), 1.into(), 1.into()], false)].into());
+
+        // and the number should not be handed out again
+        let rs = c.narrow_one_row(vec![1.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![1.into(), 1.into(), 3.into()]].into());
+    }
+
+    #[test]
+    fn it_does_not_reuse_revoked_max() {
+        let mut c = setup(vec![]);
+
+        c.narrow_one_row(vec![1.into(), 1.into()], true);
+        c.narrow_one_row(vec![2.into(), 1.into()], true);
+
+        // revoking the record with the highest number does not make that number available again
+        let rs = c.narrow_one_row((vec![2.into(), 1.into()], false), true);
+        assert_eq!(rs,
+                   vec![(vec![2.into(), 1.into(), 2.into()], false)].into());
+
+        let rs = c.narrow_one_row(vec![3.into(), 1.into()], true);
+        assert_eq!(rs, vec![vec![3.into(), 1.into(), 3.into()]].into());
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = NodeAddress::mock_global(1.into());
+        let c = setup(vec![1]);
+        let idx = c.node().suggest_indexes(me);
+        assert_eq!(idx.len(), 1);
+        assert_eq!(idx[&me], vec![1]);
+
+        // global sequences are indexed by their first column
+        let c = setup(vec![]);
+        let idx = c.node().suggest_indexes(me);
+        assert_eq!(idx[&me], vec![0]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let c = setup(vec![0]);
+        assert_eq!(c.node().resolve(0), Some(vec![(c.narrow_base_id(), 0)]));
+        assert_eq!(c.node().resolve(1), Some(vec![(c.narrow_base_id(), 1)]));
+        assert_eq!(c.node().resolve(2), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::sync;
+
+use flow::prelude::*;
+use flow::sharding;
+
+/// Forwards only the records that belong to one shard of a node sharded by some column.
+///
+/// Nodes that are sharded by one column are split up again by another by placing a `Shard` for
+/// every new shard below each of the old shards (see `Migration::add_sharded`).
+#[derive(Debug, Clone)]
+pub struct Shard {
+    src: NodeAddress,
+    column: usize,
+    shard: usize,
+    shards: usize,
+}
+
+impl Shard {
+    /// Construct a new shard operator, which forwards the records from `src` that belong to shard
+    /// `shard` of `shards` by their value in column `column`.
+    pub fn new(src: NodeAddress, column: usize, shard: usize, shards: usize) -> Shard {
+        assert!(shard < shards, "shard {} is out of range", shard);
+        Shard {
+            src: src,
+            column: column,
+            shard: shard,
+            shards: shards,
+        }
+    }
+}
+
+impl Ingredient for Shard {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.src]
+    }
+
+    fn should_materialize(&self) -> bool {
+        false
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        false
+    }
+
+    fn on_connected(&mut self, g: &Graph) {
+        let srcn = &g[*self.src.as_global()];
+        assert!(self.column < srcn.fields().len(),
+                "cannot shard by column {} of a node with {} columns",
+                self.column,
+                srcn.fields().len());
+    }
+
+    fn on_commit(&mut self, _: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.src = remap[&self.src];
+    }
+
+    fn on_input(&mut self,
+                _: NodeAddress,
+                mut rs: Records,
+                _: &DomainNodes,
+                _: &StateMap)
+                -> Records {
+        let (column, shard, shards) = (self.column, self.shard, self.shards);
+        rs.retain(|r| sharding::shard_of(&r[column], shards) == shard);
+        rs
+    }
+
+    fn suggest_indexes(&self, _: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        HashMap::new()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        Some(vec![(self.src, col)])
+    }
+
+    fn description(&self) -> String {
+        format!("shard[{}: {}/{}]", self.column, self.shard, self.shards)
+    }
+
+    fn can_query_through(&self) -> bool {
+        true
+    }
+
+    fn query_through<'a>(&self,
+                         columns: &[usize],
+                         key: &KeyType<DataType>,
+                         states: &'a StateMap)
+                         -> Option<Box<Iterator<Item = &'a sync::Arc<Vec<DataType>>> + 'a>> {
+        let (column, shard, shards) = (self.column, self.shard, self.shards);
+        states.get(self.src.as_local()).map(|state| {
+            Box::new(state.lookup(columns, key)
+                .iter()
+                .filter(move |r| sharding::shard_of(&r[column], shards) == shard)) as Box<_>
+        })
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        vec![(self.src, Some(column))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+
+    fn setup(shard: usize) -> ops::test::MockGraph {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y"]);
+        g.set_op("shard", &["x", "y"], Shard::new(s, 0, shard, 2), false);
+        g
+    }
+
+    #[test]
+    fn it_splits_records_between_shards() {
+        let mut gs = vec![setup(0), setup(1)];
+        for i in 0..10 {
+            let r: Vec<DataType> = vec![i.into(), "a".into()];
+            let out: Vec<_> = gs.iter_mut()
+                .map(|g| g.narrow_one_row(r.clone(), false))
+                .collect();
+
+            // every record ends up in exactly the shard its key hashes to
+            let s = sharding::shard_of(&r[0], 2);
+            assert_eq!(out[s], vec![r.clone()].into());
+            assert!(out[1 - s].is_empty());
+        }
+    }
+
+    #[test]
+    fn it_resolves() {
+        let g = setup(0);
+        assert_eq!(g.node().resolve(0), Some(vec![(g.narrow_base_id(), 0)]));
+        assert_eq!(g.node().resolve(1), Some(vec![(g.narrow_base_id(), 1)]));
+    }
+}
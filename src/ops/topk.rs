@@ -0,0 +1,267 @@
+use ops;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use flow::prelude::*;
+
+/// TopK provides an operator that will maintain the `k` highest (or lowest) records in every
+/// group, ordered by a chosen column.
+///
+/// Whenever a record enters the top `k` of its group, the record it displaces (if any) is
+/// negated. Whenever a record in the top `k` is removed, the next record in line (if any) takes
+/// its place. To find that record, the ancestor must be materialized.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    us: Option<NodeAddress>,
+    src: NodeAddress,
+    group: Vec<usize>,
+    order: usize,
+    k: usize,
+    highest: bool,
+}
+
+impl TopK {
+    /// Construct a new operator that maintains the `k` records with the highest value in column
+    /// `order` for every group.
+    ///
+    /// `src` should be the ancestor the operation is performed over, and `group` should be a list
+    /// of fields used to group records by.
+    pub fn highest(src: NodeAddress, group: Vec<usize>, order: usize, k: usize) -> TopK {
+        TopK::new(src, group, order, k, true)
+    }
+
+    /// Construct a new operator that maintains the `k` records with the lowest value in column
+    /// `order` for every group.
+    ///
+    /// See `TopK::highest`.
+    pub fn lowest(src: NodeAddress, group: Vec<usize>, order: usize, k: usize) -> TopK {
+        TopK::new(src, group, order, k, false)
+    }
+
+    fn new(src: NodeAddress, group: Vec<usize>, order: usize, k: usize, highest: bool) -> TopK {
+        assert!(!group.is_empty(), "top-k needs at least one group column");
+        assert!(group.len() <= 4, "top-k supports at most four group columns");
+        assert!(k > 0, "top-k must keep at least one record per group");
+        TopK {
+            us: None,
+            src: src,
+            group: group,
+            order: order,
+            k: k,
+            highest: highest,
+        }
+    }
+
+    fn compare(&self, a: &[DataType], b: &[DataType]) -> Ordering {
+        if self.highest {
+            b[self.order].cmp(&a[self.order])
+        } else {
+            a[self.order].cmp(&b[self.order])
+        }
+    }
+}
+
+impl Ingredient for TopK {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.src]
+    }
+
+    fn should_materialize(&self) -> bool {
+        true
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        true // because we need to find replacements for records that leave the top k
+    }
+
+    fn on_connected(&mut self, _: &Graph) {}
+
+    fn on_commit(&mut self, us: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.us = Some(us);
+        self.src = remap[&self.src];
+    }
+
+    fn on_input(&mut self,
+                from: NodeAddress,
+                rs: Records,
+                nodes: &DomainNodes,
+                state: &StateMap)
+                -> Records {
+        debug_assert_eq!(from, self.src);
+
+        // our ancestor's state already reflects the records in this batch, so for every group
+        // that was touched we simply recompute the top k from scratch, and emit the difference
+        // from what we had before.
+        let mut seen = HashSet::new();
+        let groups: Vec<Vec<DataType>> = rs.iter()
+            .map(|r| self.group.iter().map(|&col| r[col].clone()).collect())
+            .filter(|g: &Vec<DataType>| seen.insert(g.clone()))
+            .collect();
+
+        let mut out = Vec::new();
+        for group in groups {
+            let key = KeyType::from(&group[..]);
+
+            let mut new: Vec<_> = self.lookup(self.src, &self.group[..], &key, nodes, state)
+                .expect("top-k must have its ancestor materialized")
+                .cloned()
+                .collect();
+            new.sort_by(|a, b| self.compare(&a[..], &b[..]));
+            new.truncate(self.k);
+
+            let db = state.get(self.us.as_ref().unwrap().as_local())
+                .expect("top-k must have its own state materialized");
+            let mut old: Vec<_> = db.lookup(&self.group[..], &key).iter().cloned().collect();
+
+            for r in new {
+                if let Some(i) = old.iter().position(|o| o[..] == r[..]) {
+                    // still in the top k
+                    old.swap_remove(i);
+                } else {
+                    out.push(ops::Record::Positive(r));
+                }
+            }
+            out.extend(old.into_iter().map(ops::Record::Negative));
+        }
+
+        out.into()
+    }
+
+    fn suggest_indexes(&self, this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        // index the group columns both in ourselves and in our ancestor
+        vec![(this, self.group.clone()), (self.src, self.group.clone())].into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        Some(vec![(self.src, col)])
+    }
+
+    fn description(&self) -> String {
+        let group_cols = self.group
+            .iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("top{} {}{} γ[{}]",
+                self.k,
+                if self.highest { "↓" } else { "↑" },
+                self.order,
+                group_cols)
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        vec![(self.src, Some(column))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+    use std::sync;
+
+    fn setup(highest: bool) -> (ops::test::MockGraph, NodeAddress) {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "y", "z"]);
+        let t = if highest {
+            TopK::highest(s, vec![0], 2, 2)
+        } else {
+            TopK::lowest(s, vec![0], 2, 2)
+        };
+        g.set_op("topk", &["x", "y", "z"], t, true);
+        (g, s)
+    }
+
+    fn row(x: i32, y: &str, z: i32) -> Vec<DataType> {
+        vec![x.into(), y.into(), z.into()]
+    }
+
+    fn pos(r: Vec<DataType>) -> ops::Record {
+        ops::Record::Positive(sync::Arc::new(r))
+    }
+
+    fn neg(r: Vec<DataType>) -> ops::Record {
+        ops::Record::Negative(sync::Arc::new(r))
+    }
+
+    #[test]
+    fn it_describes() {
+        let (g, _) = setup(true);
+        assert_eq!(g.node().description(), "top2 ↓2 γ[0]");
+        let (g, _) = setup(false);
+        assert_eq!(g.node().description(), "top2 ↑2 γ[0]");
+    }
+
+    #[test]
+    fn it_forwards() {
+        let (mut g, s) = setup(true);
+
+        // the first k records for a group all make it in
+        g.seed(s, row(1, "a", 10));
+        assert_eq!(g.narrow_one_row(row(1, "a", 10), true), vec![pos(row(1, "a", 10))].into());
+        g.seed(s, row(1, "b", 5));
+        assert_eq!(g.narrow_one_row(row(1, "b", 5), true), vec![pos(row(1, "b", 5))].into());
+
+        // a record that is lower than all of them does not
+        g.seed(s, row(1, "c", 1));
+        assert_eq!(g.narrow_one_row(row(1, "c", 1), true).len(), 0);
+
+        // other groups are unaffected
+        g.seed(s, row(2, "d", 1));
+        assert_eq!(g.narrow_one_row(row(2, "d", 1), true), vec![pos(row(2, "d", 1))].into());
+
+        // a higher record pushes out the lowest one
+        g.seed(s, row(1, "e", 7));
+        assert_eq!(g.narrow_one_row(row(1, "e", 7), true),
+                   vec![pos(row(1, "e", 7)), neg(row(1, "b", 5))].into());
+
+        // and removing a record from the top k brings back the next one in line
+        g.unseed(s, row(1, "a", 10));
+        assert_eq!(g.narrow_one_row((row(1, "a", 10), false), true),
+                   vec![pos(row(1, "b", 5)), neg(row(1, "a", 10))].into());
+
+        // removing a record that is not in the top k does nothing
+        g.unseed(s, row(1, "c", 1));
+        assert_eq!(g.narrow_one_row((row(1, "c", 1), false), true).len(), 0);
+    }
+
+    #[test]
+    fn it_keeps_lowest() {
+        let (mut g, s) = setup(false);
+
+        g.seed(s, row(1, "a", 10));
+        g.seed(s, row(1, "b", 5));
+        assert_eq!(g.narrow_one(vec![row(1, "a", 10), row(1, "b", 5)], true).len(),
+                   2);
+
+        g.seed(s, row(1, "c", 1));
+        assert_eq!(g.narrow_one_row(row(1, "c", 1), true),
+                   vec![pos(row(1, "c", 1)), neg(row(1, "a", 10))].into());
+    }
+
+    #[test]
+    fn it_suggests_indices() {
+        let me = NodeAddress::mock_global(1.into());
+        let (g, _) = setup(true);
+        let idx = g.node().suggest_indexes(me);
+
+        // should index the group columns in both ourselves and the source
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx[&me], vec![0]);
+        assert_eq!(idx[&g.narrow_base_id()], vec![0]);
+    }
+
+    #[test]
+    fn it_resolves() {
+        let (g, _) = setup(true);
+        assert_eq!(g.node().resolve(0), Some(vec![(g.narrow_base_id(), 0)]));
+        assert_eq!(g.node().resolve(2), Some(vec![(g.narrow_base_id(), 2)]));
+    }
+}
@@ -3,6 +3,7 @@ use ops;
 use std::collections::HashMap;
 use std::sync;
 
+use flow::data::ColumnType;
 use flow::prelude::*;
 
 /// A union of a set of views.
@@ -10,28 +11,110 @@ use flow::prelude::*;
 pub struct Union {
     emit: HashMap<NodeAddress, Vec<usize>>,
     cols: HashMap<NodeAddress, usize>,
+    // for each source, the emitted columns whose values must be coerced to the union's type
+    coerce: HashMap<NodeAddress, Vec<(usize, ColumnType)>>,
+    distinct: bool,
+    us: Option<NodeAddress>,
 }
 
 impl Union {
     /// Construct a new union operator.
     ///
     /// When receiving an update from node `a`, a union will emit the columns selected in `emit[a]`.
-    /// `emit` only supports omitting columns, not rearranging them.
+    /// `emit` may both omit and rearrange columns, so sources whose columns appear in a different
+    /// order do not need a separate projection in front of the union. Every source must emit the
+    /// same number of columns.
+    ///
+    /// Where the types of the emitted columns are known, they must agree: a column may only merge
+    /// values of types that can all be coerced to one type (see `ColumnType::unify`), and the
+    /// union converts the values of sources of other types to that type as it emits them.
     pub fn new(emit: HashMap<NodeAddress, Vec<usize>>) -> Union {
-        for emit in emit.values() {
-            let mut last = &emit[0];
-            for i in emit {
-                if i < last {
-                    unimplemented!();
-                }
-                last = i;
+        assert!(!emit.is_empty(), "union must have at least one source");
+        {
+            let mut arities = emit.iter().map(|(src, emit)| (src, emit.len()));
+            let (first, arity) = arities.next().unwrap();
+            assert!(arity != 0, "union source {} emits no columns", first);
+            for (src, a) in arities {
+                assert!(a == arity,
+                        "union sources emit different numbers of columns ({} from {}, {} from {})",
+                        arity,
+                        first,
+                        a,
+                        src);
             }
         }
         Union {
             emit: emit,
             cols: HashMap::new(),
+            coerce: HashMap::new(),
+            distinct: false,
+            us: None,
         }
     }
+
+    /// Construct a new union operator that only emits one copy of every distinct row.
+    ///
+    /// This corresponds to SQL's `UNION`, whereas `new` corresponds to `UNION ALL`. A row is
+    /// emitted when its first copy arrives from any source, and retracted when the last copy
+    /// across all sources is removed. To do so, the union keeps its own output materialized, and
+    /// queries its sources for remaining copies, so rows can have at most four columns.
+    pub fn new_distinct(emit: HashMap<NodeAddress, Vec<usize>>) -> Union {
+        let mut u = Union::new(emit);
+        assert!(u.arity() <= 4,
+                "distinct unions can only compare rows of up to four columns");
+        u.distinct = true;
+        u
+    }
+
+    fn arity(&self) -> usize {
+        self.emit.values().next().unwrap().len()
+    }
+
+    /// The number of copies of `row` that the sources currently hold.
+    fn copies(&self, row: &[DataType], domain: &DomainNodes, states: &StateMap) -> usize {
+        self.emit
+            .iter()
+            .map(|(&src, emit)| {
+                self.lookup(src, &emit[..], &KeyType::from(row), domain, states)
+                    .expect("distinct unions must have inputs materialized")
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Only forward rows that change the set of distinct rows in the union's output.
+    fn dedup(&self,
+             rs: Vec<(sync::Arc<Vec<DataType>>, bool)>,
+             domain: &DomainNodes,
+             states: &StateMap)
+             -> Records {
+        let us = self.us.expect("union must be committed before receiving records");
+        let all: Vec<_> = (0..self.arity()).collect();
+
+        // whether each row is currently part of our output, taking earlier records into account
+        let mut present = HashMap::new();
+        let mut out = Vec::with_capacity(rs.len());
+        for (r, pos) in rs {
+            let p = present.entry(r.clone()).or_insert_with(|| {
+                self.lookup(us, &all[..], &KeyType::from(&r[..]), domain, states)
+                    .expect("distinct unions must be materialized")
+                    .next()
+                    .is_some()
+            });
+
+            if pos {
+                if !*p {
+                    *p = true;
+                    out.push(ops::Record::Positive(r));
+                }
+            } else if *p && self.copies(&r[..], domain, states) == 0 {
+                // the states of our sources already reflect this removal
+                *p = false;
+                out.push(ops::Record::Negative(r));
+            }
+        }
+        out.into()
+    }
 }
 
 impl Ingredient for Union {
@@ -44,7 +127,7 @@ impl Ingredient for Union {
     }
 
     fn should_materialize(&self) -> bool {
-        false
+        self.distinct
     }
 
     fn will_query(&self, _: bool) -> bool {
@@ -53,9 +136,67 @@ impl Ingredient for Union {
 
     fn on_connected(&mut self, g: &Graph) {
         self.cols.extend(self.emit.keys().map(|&n| (n, g[*n.as_global()].fields().len())));
+
+        // make sure every source actually has the columns we are going to emit from it
+        for (src, emit) in &self.emit {
+            let cols = self.cols[src];
+            if let Some(&col) = emit.iter().find(|&&col| col >= cols) {
+                panic!("union emits column {} from {}, which only has {} columns",
+                       col,
+                       src,
+                       cols);
+            }
+        }
+
+        // and that the columns merged into each of ours can all be given the same type
+        for i in 0..self.arity() {
+            let types: Vec<_> = self.emit
+                .iter()
+                .map(|(&src, emit)| {
+                    let ni = *src.as_global();
+                    (src, g[ni].column_type(emit[i], g, ni))
+                })
+                .collect();
+
+            let mut ty: Option<(ColumnType, NodeAddress)> = None;
+            for &(src, t) in &types {
+                let t = match t {
+                    Some(t) => t,
+                    None => continue,
+                };
+                ty = match ty {
+                    None => Some((t, src)),
+                    Some((u, first)) => {
+                        match u.unify(t) {
+                            Some(u) => Some((u, first)),
+                            None => {
+                                panic!("union column {} merges {} values from {} with {} values \
+                                        from {}",
+                                       i,
+                                       u,
+                                       first,
+                                       t,
+                                       src)
+                            }
+                        }
+                    }
+                };
+            }
+
+            if let Some((ty, _)) = ty {
+                for (src, t) in types {
+                    if t.is_some() && t != Some(ty) {
+                        self.coerce.entry(src).or_insert_with(Vec::new).push((i, ty));
+                    }
+                }
+            }
+        }
+        assert!(!self.distinct || self.coerce.is_empty(),
+                "distinct unions require the columns they merge to have the same types");
     }
 
-    fn on_commit(&mut self, _: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+    fn on_commit(&mut self, us: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.us = Some(us);
         for (from, to) in remap {
             if from == to {
                 continue;
@@ -67,36 +208,59 @@ impl Ingredient for Union {
             if let Some(e) = self.cols.remove(from) {
                 assert!(self.cols.insert(*to, e).is_none());
             }
+            if let Some(e) = self.coerce.remove(from) {
+                assert!(self.coerce.insert(*to, e).is_none());
+            }
         }
     }
 
     fn on_input(&mut self,
                 from: NodeAddress,
                 rs: Records,
-                _: &DomainNodes,
-                _: &StateMap)
+                domain: &DomainNodes,
+                states: &StateMap)
                 -> Records {
-        rs.into_iter()
-            .map(move |rec| {
-                let (r, pos) = rec.extract();
+        let coerce = self.coerce.get(&from);
+        let rs = rs.into_iter().map(|rec| {
+            let (r, pos) = rec.extract();
+
+            // yield selected columns for this source
+            // TODO: if emitting all in same order then avoid clone
+            let mut res: Vec<_> = self.emit[&from].iter().map(|&col| r[col].clone()).collect();
+            for &(col, ty) in coerce.into_iter().flat_map(|c| c.iter()) {
+                if let Some(v) = ty.coerce(&res[col]) {
+                    res[col] = v;
+                }
+            }
+            (sync::Arc::new(res), pos)
+        });
 
-                // yield selected columns for this source
-                // TODO: if emitting all in same order then avoid clone
-                let res = self.emit[&from].iter().map(|&col| r[col].clone()).collect();
+        if self.distinct {
+            return self.dedup(rs.collect(), domain, states);
+        }
 
+        rs.map(|(res, pos)| {
                 // return new row with appropriate sign
                 if pos {
-                    ops::Record::Positive(sync::Arc::new(res))
+                    ops::Record::Positive(res)
                 } else {
-                    ops::Record::Negative(sync::Arc::new(res))
+                    ops::Record::Negative(res)
                 }
             })
             .collect()
     }
 
-    fn suggest_indexes(&self, _: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
-        // index nothing (?)
-        HashMap::new()
+    fn suggest_indexes(&self, this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        if !self.distinct {
+            // index nothing (?)
+            return HashMap::new();
+        }
+
+        // we look up rows in our own output, and copies of rows in all our sources
+        let mut idx: HashMap<_, _> =
+            self.emit.iter().map(|(&src, emit)| (src, emit.clone())).collect();
+        idx.insert(this, (0..self.arity()).collect());
+        idx
     }
 
     fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
@@ -107,7 +271,7 @@ impl Ingredient for Union {
         // Ensure we get a consistent output by sorting.
         let mut emit = self.emit.iter().collect::<Vec<_>>();
         emit.sort();
-        emit.iter()
+        let union = emit.iter()
             .map(|&(src, emit)| {
                 let cols = emit.iter()
                     .map(|e| e.to_string())
@@ -116,7 +280,12 @@ impl Ingredient for Union {
                 format!("{}:[{}]", src, cols)
             })
             .collect::<Vec<_>>()
-            .join(" ⋃ ")
+            .join(" ⋃ ");
+        if self.distinct {
+            format!("DISTINCT {}", union)
+        } else {
+            union
+        }
     }
     fn parent_columns(&self, col: usize) -> Vec<(NodeAddress, Option<usize>)> {
         self.emit.iter().map(|(src, emit)| (*src, Some(emit[col]))).collect()
@@ -164,6 +333,138 @@ mod tests {
                    vec![vec![1.into(), "x".into()]].into());
     }
 
+    #[test]
+    fn it_rearranges() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![1, 0]);
+        g.set_op("union", &["u0", "u1"], Union::new(emits), false);
+        let r = g.to_local(r);
+
+        // forward from right should emit the columns swapped
+        let right = vec![1.into(), "x".into()];
+        assert_eq!(g.one_row(r, right, false),
+                   vec![vec!["x".into(), 1.into()]].into());
+    }
+
+    fn typed(g: &mut ops::test::MockGraph, name: &str, tys: &[ColumnType]) -> NodeAddress {
+        use ops::base::{Base, ColumnSpec};
+        let columns = tys.iter()
+            .map(|&ty| {
+                ColumnSpec {
+                    ty: Some(ty),
+                    ..ColumnSpec::default()
+                }
+            })
+            .collect();
+        let fields: Vec<_> = (0..tys.len()).map(|i| format!("{}{}", name, i)).collect();
+        let fields: Vec<_> = fields.iter().map(|f| &f[..]).collect();
+        g.add_base_with(name, &fields[..], Base::default().with_columns(columns))
+    }
+
+    #[test]
+    fn it_coerces_merged_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = typed(&mut g, "left", &[ColumnType::Int, ColumnType::Real]);
+        let r = typed(&mut g, "right", &[ColumnType::Real, ColumnType::Int]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![1, 0]);
+        g.set_op("union", &["u0", "u1"], Union::new(emits), false);
+        let (l, r) = (g.to_local(l), g.to_local(r));
+
+        // integers merged with reals come out as reals
+        assert_eq!(g.one_row(l, vec![1.into(), 2.5.into()], false),
+                   vec![vec![1.0.into(), 2.5.into()]].into());
+        assert_eq!(g.one_row(r, vec![0.5.into(), 3.into()], false),
+                   vec![vec![3.0.into(), 0.5.into()]].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "union column 1 merges")]
+    fn it_rejects_mismatched_types() {
+        let mut g = ops::test::MockGraph::new();
+        let l = typed(&mut g, "left", &[ColumnType::Int, ColumnType::Text]);
+        let r = typed(&mut g, "right", &[ColumnType::Int, ColumnType::Bool]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![0, 1]);
+        g.set_op("union", &["u0", "u1"], Union::new(emits), false);
+    }
+
+    #[test]
+    fn it_works_distinct() {
+        use std::sync::Arc;
+
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![0, 1]);
+        g.set_op("union", &["u0", "u1"], Union::new_distinct(emits), true);
+        let (l, r) = (g.to_local(l), g.to_local(r));
+        assert_eq!(g.node().description(),
+                   format!("DISTINCT {}:[0, 1] ⋃ {}:[0, 1]", l, r));
+
+        // the first copy of a row is emitted
+        let row = vec![1.into(), "a".into()];
+        g.seed(l, row.clone());
+        assert_eq!(g.one_row(l, row.clone(), true), vec![row.clone()].into());
+
+        // but later copies, from either side, are not
+        g.seed(r, row.clone());
+        assert_eq!(g.one_row(r, row.clone(), true).len(), 0);
+
+        // duplicates within a single batch are emitted once
+        let other = vec![2.into(), "b".into()];
+        g.seed(l, other.clone());
+        g.seed(l, other.clone());
+        assert_eq!(g.one(l, vec![other.clone(), other.clone()], true),
+                   vec![other.clone()].into());
+
+        // removing a copy while another one remains in the sources emits nothing
+        let neg = Record::Negative(Arc::new(row.clone()));
+        assert_eq!(g.one_row(l, neg.clone(), true).len(), 0);
+
+        // a negative for a row without any remaining copies is emitted
+        let gone = vec![3.into(), "c".into()];
+        assert_eq!(g.one_row(l, gone.clone(), true), vec![gone.clone()].into());
+        assert_eq!(g.one_row(l, Record::Negative(Arc::new(gone.clone())), true),
+                   vec![Record::Negative(Arc::new(gone))].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "union sources emit different numbers of columns")]
+    fn it_rejects_mismatched_arity() {
+        let l = NodeAddress::mock_global(1.into());
+        let r = NodeAddress::mock_global(2.into());
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![0]);
+        Union::new(emits);
+    }
+
+    #[test]
+    #[should_panic(expected = "which only has 2 columns")]
+    fn it_rejects_missing_columns() {
+        let mut g = ops::test::MockGraph::new();
+        let l = g.add_base("left", &["l0", "l1"]);
+        let r = g.add_base("right", &["r0", "r1"]);
+
+        let mut emits = HashMap::new();
+        emits.insert(l, vec![0, 1]);
+        emits.insert(r, vec![0, 2]);
+        g.set_op("union", &["u0", "u1"], Union::new(emits), false);
+    }
+
     #[test]
     fn it_suggests_indices() {
         use std::collections::HashMap;
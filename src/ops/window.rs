@@ -0,0 +1,295 @@
+use ops;
+
+use std::collections::HashMap;
+
+use flow::prelude::*;
+
+/// The kind of time window maintained by a `Window` operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Records with a timestamp within the given distance of the newest timestamp.
+    Sliding(i64),
+    /// Records whose timestamp falls in the same multiple of the given size as the newest one.
+    Tumbling(i64),
+}
+
+/// Window provides an operator that only lets through records whose timestamp lies within a time
+/// window, and retracts records as they fall out of it.
+///
+/// Time is measured by the timestamp column itself: the window ends at the largest timestamp seen
+/// so far, and only moves when records with a larger timestamp arrive. Records that are already
+/// outside the window when they arrive are dropped. Aggregating the output of a window (for
+/// example using `Aggregation::COUNT`) yields a windowed aggregation such as "votes per article
+/// over the last ten minutes".
+///
+/// Where the window ends is not itself part of the operator's state. If the operator is rebuilt
+/// from state it did not build itself, the window is taken to end at the newest timestamp in that
+/// state, which is earlier than where it had moved to if the newest records have since been
+/// removed. Until a newer record arrives, records older than where the window had moved to may
+/// then be let through again.
+#[derive(Debug, Clone)]
+pub struct Window {
+    us: Option<NodeAddress>,
+    src: NodeAddress,
+    ts: usize,
+    kind: Kind,
+
+    // the largest timestamp seen so far. like `oldest`, this is only a cache of what is in our
+    // state, and is recovered from it if we start out with state we did not build ourselves.
+    now: Option<i64>,
+    // a lower bound on the timestamps of the records in our state
+    oldest: Option<i64>,
+}
+
+fn timestamp(d: &DataType) -> i64 {
+    match *d {
+        DataType::Int(i) => i as i64,
+        DataType::BigInt(i) => i,
+        _ => unreachable!("window timestamps must be integers"),
+    }
+}
+
+impl Window {
+    /// Construct a new sliding window operator.
+    ///
+    /// `src` should be the ancestor the operation is performed over, and `ts` the column holding
+    /// each record's timestamp. A record stays in the window as long as its timestamp is greater
+    /// than the largest timestamp seen minus `width`.
+    pub fn sliding(src: NodeAddress, ts: usize, width: i64) -> Window {
+        assert!(width > 0, "windows must have a positive width");
+        Window::new(src, ts, Kind::Sliding(width))
+    }
+
+    /// Construct a new tumbling window operator.
+    ///
+    /// Time is divided into consecutive windows of length `size`, and only records in the same
+    /// window as the largest timestamp seen are let through. When a record from a later window
+    /// arrives, all records from earlier windows are retracted.
+    pub fn tumbling(src: NodeAddress, ts: usize, size: i64) -> Window {
+        assert!(size > 0, "windows must have a positive size");
+        Window::new(src, ts, Kind::Tumbling(size))
+    }
+
+    fn new(src: NodeAddress, ts: usize, kind: Kind) -> Window {
+        Window {
+            us: None,
+            src: src,
+            ts: ts,
+            kind: kind,
+            now: None,
+            oldest: None,
+        }
+    }
+
+    fn expired(&self, ts: i64) -> bool {
+        let now = match self.now {
+            Some(now) => now,
+            None => return false,
+        };
+        match self.kind {
+            Kind::Sliding(width) => ts <= now - width,
+            Kind::Tumbling(size) => ts < now - (now % size + size) % size,
+        }
+    }
+}
+
+impl Ingredient for Window {
+    fn take(&mut self) -> Box<Ingredient> {
+        Box::new(Clone::clone(self))
+    }
+
+    fn ancestors(&self) -> Vec<NodeAddress> {
+        vec![self.src]
+    }
+
+    fn should_materialize(&self) -> bool {
+        true
+    }
+
+    fn will_query(&self, _: bool) -> bool {
+        true // because we need to find the records that have expired
+    }
+
+    fn on_connected(&mut self, _: &Graph) {}
+
+    fn on_commit(&mut self, us: NodeAddress, remap: &HashMap<NodeAddress, NodeAddress>) {
+        self.us = Some(us);
+        self.src = remap[&self.src];
+    }
+
+    fn on_input(&mut self,
+                from: NodeAddress,
+                rs: Records,
+                _: &DomainNodes,
+                state: &StateMap)
+                -> Records {
+        debug_assert_eq!(from, self.src);
+
+        // our state may have been built by another copy of this operator (for example if the
+        // node was moved to another domain), in which case we have to find out where the window
+        // is. the newest record in our state is a lower bound on the time it was last moved to.
+        if self.now.is_none() {
+            let db = state.get(self.us.as_ref().unwrap().as_local())
+                .expect("window must have its own state materialized");
+            for r in db.all_rows() {
+                let ts = timestamp(&r[self.ts]);
+                if self.now.map(|now| ts > now).unwrap_or(true) {
+                    self.now = Some(ts);
+                }
+                if self.oldest.map(|o| ts < o).unwrap_or(true) {
+                    self.oldest = Some(ts);
+                }
+            }
+        }
+
+        // move the window forward first, so that records in this batch that are already too old
+        // are not let through only to be retracted right away
+        for r in rs.iter().filter(|r| r.is_positive()) {
+            let ts = timestamp(&r[self.ts]);
+            if self.now.map(|now| ts > now).unwrap_or(true) {
+                self.now = Some(ts);
+            }
+        }
+
+        let mut out = Vec::with_capacity(rs.len());
+        for r in rs {
+            let ts = timestamp(&r[self.ts]);
+            if self.expired(ts) {
+                // either too late, or already retracted when it expired
+                continue;
+            }
+            if r.is_positive() && self.oldest.map(|o| ts < o).unwrap_or(true) {
+                self.oldest = Some(ts);
+            }
+            out.push(r);
+        }
+
+        // retract everything that has fallen out of the window. our state only holds records that
+        // were in the window at some point, so we only need to look at it if the oldest of them
+        // may have expired.
+        if self.oldest.map(|o| self.expired(o)).unwrap_or(false) {
+            let db = state.get(self.us.as_ref().unwrap().as_local())
+                .expect("window must have its own state materialized");
+            let mut oldest = None;
+            for r in db.all_rows() {
+                let ts = timestamp(&r[self.ts]);
+                if self.expired(ts) {
+                    out.push(ops::Record::Negative(r));
+                } else if oldest.map(|o| ts < o).unwrap_or(true) {
+                    oldest = Some(ts);
+                }
+            }
+
+            // records let through in this batch are not yet in our state
+            for r in out.iter().filter(|r| r.is_positive()) {
+                let ts = timestamp(&r[self.ts]);
+                if oldest.map(|o| ts < o).unwrap_or(true) {
+                    oldest = Some(ts);
+                }
+            }
+            self.oldest = oldest;
+        }
+
+        out.into()
+    }
+
+    fn suggest_indexes(&self, this: NodeAddress) -> HashMap<NodeAddress, Vec<usize>> {
+        // we need *some* index to store our records; the timestamp is as good as any
+        Some((this, vec![self.ts])).into_iter().collect()
+    }
+
+    fn resolve(&self, col: usize) -> Option<Vec<(NodeAddress, usize)>> {
+        Some(vec![(self.src, col)])
+    }
+
+    fn description(&self) -> String {
+        match self.kind {
+            Kind::Sliding(width) => format!("ω[{}] last {}", self.ts, width),
+            Kind::Tumbling(size) => format!("ω[{}] every {}", self.ts, size),
+        }
+    }
+
+    fn parent_columns(&self, column: usize) -> Vec<(NodeAddress, Option<usize>)> {
+        vec![(self.src, Some(column))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ops;
+    use std::sync::Arc;
+
+    fn setup<F>(make: F) -> ops::test::MockGraph
+        where F: FnOnce(NodeAddress) -> Window
+    {
+        let mut g = ops::test::MockGraph::new();
+        let s = g.add_base("source", &["x", "ts"]);
+        g.set_op("window", &["x", "ts"], make(s), true);
+        g
+    }
+
+    fn pos(x: i32, ts: i32) -> ops::Record {
+        ops::Record::Positive(Arc::new(vec![x.into(), ts.into()]))
+    }
+
+    fn neg(x: i32, ts: i32) -> ops::Record {
+        ops::Record::Negative(Arc::new(vec![x.into(), ts.into()]))
+    }
+
+    fn sliding(width: i64) -> ops::test::MockGraph {
+        setup(|s| Window::sliding(s, 1, width))
+    }
+
+    fn tumbling(size: i64) -> ops::test::MockGraph {
+        setup(|s| Window::tumbling(s, 1, size))
+    }
+
+    #[test]
+    fn it_describes() {
+        assert_eq!(sliding(10).node().description(), "ω[1] last 10");
+        assert_eq!(tumbling(10).node().description(), "ω[1] every 10");
+    }
+
+    #[test]
+    fn it_slides() {
+        let mut g = sliding(10);
+
+        assert_eq!(g.narrow_one_row(pos(1, 5), true), vec![pos(1, 5)].into());
+        assert_eq!(g.narrow_one_row(pos(2, 12), true), vec![pos(2, 12)].into());
+
+        // moving the window past 5 retracts it
+        assert_eq!(g.narrow_one_row(pos(1, 16), true),
+                   vec![pos(1, 16), neg(1, 5)].into());
+
+        // records that are too old are dropped
+        assert_eq!(g.narrow_one_row(pos(3, 4), true).len(), 0);
+
+        // records inside the window can still be removed
+        assert_eq!(g.narrow_one_row(neg(2, 12), true), vec![neg(2, 12)].into());
+
+        // a batch can move the window past some of its own records
+        assert_eq!(g.narrow_one(vec![pos(4, 20), pos(5, 30)], true),
+                   vec![pos(5, 30), neg(1, 16)].into());
+    }
+
+    #[test]
+    fn it_tumbles() {
+        let mut g = tumbling(10);
+
+        assert_eq!(g.narrow_one_row(pos(1, 5), true), vec![pos(1, 5)].into());
+        assert_eq!(g.narrow_one_row(pos(2, 9), true), vec![pos(2, 9)].into());
+
+        // the next window retracts everything in the previous one
+        let rs = g.narrow_one_row(pos(3, 10), true);
+        assert_eq!(rs.len(), 3);
+        assert_eq!(rs[0], pos(3, 10));
+        assert!(rs.contains(&neg(1, 5)));
+        assert!(rs.contains(&neg(2, 9)));
+
+        // and late records for it are dropped
+        assert_eq!(g.narrow_one_row(pos(4, 9), true).len(), 0);
+        assert_eq!(g.narrow_one_row(pos(5, 19), true), vec![pos(5, 19)].into());
+    }
+}
@@ -0,0 +1,42 @@
+//! The parts of Soup's API that applications are expected to use.
+//!
+//! Everything exported here follows semantic versioning: it will only change in
+//! backwards-incompatible ways along with a major version bump. Items that are only exported
+//! from the crate root, such as the individual operators and the SQL planner's internals, are
+//! tied closely to how the data flow graph is implemented, and may change between any two
+//! releases.
+//!
+//! Most applications should be able to get by with
+//!
+//! ```rust
+//! use distributary::prelude::*;
+//! ```
+
+pub use checktable::{Token, TransactionResult};
+pub use flow::{Blender, Migration, Mutator, NodeAddress, OrderedMutator};
+pub use flow::data::{ColumnType, DataType};
+pub use flow::getter::GetterHandle;
+pub use flow::sink::{Sink, SinkPolicy};
+pub use flow::persistence::PersistencePolicy;
+pub use flow::prepared::{PreparedRead, TypedRow};
+pub use flow::node::{BaseWrite, StreamUpdate, Subscription, SwapPolicy};
+pub use flow::sql_to_flow::{SqlIncorporator, ToFlowParts};
+pub use ops::Datas;
+pub use recipe::Recipe;
+
+/// A function that returns all records with a given key in a view.
+///
+/// See `Blender::get_getter` and `Migration::maintain`.
+pub type Getter = Box<Fn(&DataType) -> Result<Datas, ()> + Send + Sync>;
+
+/// A function that returns all records with keys between two given keys (inclusive) in a view,
+/// in ascending key order.
+///
+/// See `Blender::get_range_getter` and `Migration::maintain_ordered`.
+pub type RangeGetter = Box<Fn(&DataType, &DataType) -> Result<Datas, ()> + Send + Sync>;
+
+/// A function that returns all records with a given key in a view, along with a token that can
+/// be used to perform a transactional write that depends on them.
+///
+/// See `Migration::transactional_maintain`.
+pub type TransactionalGetter = Box<Fn(&DataType) -> Result<(Datas, Token), ()> + Send + Sync>;
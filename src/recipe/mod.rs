@@ -17,6 +17,9 @@ pub struct Recipe {
     expression_order: Vec<QueryID>,
     /// Named read/write expression aliases, mapping to queries in `expressions`.
     aliases: HashMap<String, QueryID>,
+    /// The text of the `CREATE TABLE` statements in `expressions`, whose column constraints are
+    /// lost in parsing.
+    tables: HashMap<QueryID, String>,
     /// Recipe revision.
     version: usize,
     /// Preceding recipe.
@@ -34,6 +37,56 @@ fn hash_query(q: &SqlQuery) -> QueryID {
     h.finish()
 }
 
+/// Orders queries such that every query comes after the queries defining the tables and views it
+/// reads from. Queries that do not depend on each other retain their relative order.
+fn dependency_order(qs: Vec<(Option<String>, SqlQuery)>)
+                    -> Result<Vec<(Option<String>, SqlQuery)>, String> {
+    let defines = |&(ref n, ref q): &(Option<String>, SqlQuery)| match *q {
+        SqlQuery::CreateTable(ref ctq) => Some(ctq.table.name.clone()),
+        SqlQuery::Insert(ref iq) => Some(iq.table.name.clone()),
+        SqlQuery::Select(_) => n.clone(),
+    };
+    let definitions: HashMap<String, usize> = qs.iter()
+        .enumerate()
+        .filter_map(|(i, q)| defines(q).map(|n| (n, i)))
+        .collect();
+    let deps: Vec<Vec<usize>> = qs.iter()
+        .enumerate()
+        .map(|(i, &(_, ref q))| match *q {
+            SqlQuery::Select(ref st) => {
+                st.tables
+                    .iter()
+                    .filter_map(|t| definitions.get(&t.name).cloned())
+                    .filter(|&d| d != i)
+                    .collect()
+            }
+            _ => vec![],
+        })
+        .collect();
+
+    let mut done = vec![false; qs.len()];
+    let mut order = Vec::with_capacity(qs.len());
+    while order.len() < qs.len() {
+        // add the first query whose dependencies have all been added already
+        match (0..qs.len()).find(|&i| !done[i] && deps[i].iter().all(|&d| done[d])) {
+            Some(i) => {
+                done[i] = true;
+                order.push(i);
+            }
+            None => {
+                let cyclic: Vec<_> = (0..qs.len())
+                    .filter(|&i| !done[i])
+                    .map(|i| defines(&qs[i]).unwrap_or_else(|| format!("query {}", i)))
+                    .collect();
+                return Err(format!("cyclic dependency between {}", cyclic.join(", ")));
+            }
+        }
+    }
+
+    let mut qs: Vec<_> = qs.into_iter().map(Some).collect();
+    Ok(order.into_iter().map(|i| qs[i].take().unwrap()).collect())
+}
+
 impl Recipe {
     /// Creates a blank recipe. This is useful for bootstrapping, e.g., in interactive
     /// settings, and for temporary recipes.
@@ -42,6 +95,7 @@ impl Recipe {
             expressions: HashMap::default(),
             expression_order: Vec::default(),
             aliases: HashMap::default(),
+            tables: HashMap::default(),
             version: 0,
             prior: None,
             inc: None,
@@ -86,7 +140,17 @@ impl Recipe {
 
         // parse and compute differences to current recipe
         let parsed_queries = Recipe::parse(&cleaned_recipe_text)?;
-        Ok(Recipe::from_queries(parsed_queries))
+        let tables = parsed_queries.iter()
+            .filter_map(|&(_, ref text, ref q)| match *q {
+                SqlQuery::CreateTable(_) => Some((hash_query(q), text.clone())),
+                _ => None,
+            })
+            .collect();
+        let mut recipe = Recipe::from_queries(parsed_queries.into_iter()
+            .map(|(n, _, q)| (n, q))
+            .collect());
+        recipe.tables = tables;
+        Ok(recipe)
     }
 
     /// Creates a recipe from a set of pre-parsed `SqlQuery` structures.
@@ -113,6 +177,7 @@ impl Recipe {
             expressions: expressions,
             expression_order: expression_order,
             aliases: aliases,
+            tables: HashMap::default(),
             version: 0,
             prior: None,
             inc: None,
@@ -122,6 +187,9 @@ impl Recipe {
     /// Activate the recipe by migrating the Soup data-flow graph wrapped in `mig` to the recipe.
     /// This causes all necessary changes to said graph to be applied; however, it is the caller's
     /// responsibility to call `mig.commit()` afterwards.
+    ///
+    /// New queries are added after the tables and views they read from, regardless of their order
+    /// in the recipe. If any of them cannot be added, none of them are.
     pub fn activate(&mut self,
                     mig: &mut Migration)
                     -> Result<HashMap<String, NodeAddress>, String> {
@@ -143,9 +211,17 @@ impl Recipe {
         // incorporator in `inc`. `NodeAddress`es for new nodes are collected in `new_nodes` to be
         // returned to the caller (who may use them to obtain mutators and getters)
         let mut new_nodes = HashMap::default();
-        for qid in added {
-            let (n, q) = self.expressions[&qid].clone();
-            let qfp = self.inc.as_mut().unwrap().add_parsed_query(q, n, mig)?;
+        for qid in added.iter() {
+            if let Some(text) = self.tables.get(qid) {
+                // tables whose constraints cannot be parsed are added without them
+                let _ = self.inc.as_mut().unwrap().declare_columns(text);
+            }
+        }
+        let queries = dependency_order(added.iter()
+            .map(|qid| self.expressions[qid].clone())
+            .collect())?;
+        let qfps = self.inc.as_mut().unwrap().add_parsed_queries(queries, mig)?;
+        for qfp in qfps {
             let d = mig.add_domain();
             for na in qfp.new_nodes.iter() {
                 mig.assign_domain(na.clone(), d);
@@ -199,6 +275,7 @@ impl Recipe {
             expressions: self.expressions.clone(),
             expression_order: self.expression_order.clone(),
             aliases: self.aliases.clone(),
+            tables: self.tables.clone(),
             version: self.version + 1,
             // retain the old recipe for future reference
             prior: Some(Box::new(self)),
@@ -210,13 +287,16 @@ impl Recipe {
             let q = add_rp.expressions[&qid].clone();
             new.expressions.insert(qid, q);
             new.expression_order.push(qid);
+            if let Some(text) = add_rp.tables.get(&qid) {
+                new.tables.insert(qid, text.clone());
+            }
         }
 
         // return new recipe as replacement for self
         Ok(new)
     }
 
-    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, SqlQuery)>, String> {
+    fn parse(recipe_text: &str) -> Result<Vec<(Option<String>, String, SqlQuery)>, String> {
         let lines: Vec<&str> = recipe_text.lines()
             .filter(|l| !l.is_empty() && !l.starts_with("#"))
             .map(|l| {
@@ -267,7 +347,9 @@ impl Recipe {
             return Err(String::from("Failed to parse recipe!"));
         }
 
-        Ok(parsed_queries.into_iter().map(|t| (t.0, t.2.unwrap())).collect::<Vec<_>>())
+        Ok(parsed_queries.into_iter()
+            .map(|t| (t.0, String::from(t.1), t.2.unwrap()))
+            .collect::<Vec<_>>())
     }
 
     /// Replace this recipe with a new one, retaining queries that exist in both. Any queries only
@@ -357,6 +439,17 @@ mod tests {
         assert_eq!(r2.prior, Some(Box::new(r1_copy)));
     }
 
+    #[test]
+    fn it_keeps_table_definitions() {
+        let r_txt = "CREATE TABLE b (a int not null, c int);\nSELECT a FROM b;";
+        let r = Recipe::from_str(r_txt).unwrap();
+        assert_eq!(r.tables.len(), 1);
+        assert!(r.tables.values().all(|t| t.starts_with("CREATE TABLE b")));
+
+        let r = r.extend("CREATE TABLE d (e int not null);").unwrap();
+        assert_eq!(r.tables.len(), 2);
+    }
+
     #[test]
     fn it_activates() {
         use Blender;
@@ -448,4 +541,47 @@ mod tests {
         }
         println!("{}", g);
     }
+
+    #[test]
+    fn it_orders_by_dependencies() {
+        use Blender;
+
+        // the view is listed before the tables it reads from
+        let r_txt = "v: SELECT a.y, b.s FROM a, b WHERE a.x = b.r;\n
+                     INSERT INTO a (x, y) VALUES (?, ?);\n
+                     INSERT INTO b (r, s) VALUES (?, ?);\n";
+        let mut r = Recipe::from_str(r_txt).unwrap();
+
+        let mut g = Blender::new();
+        {
+            let mut mig = g.start_migration();
+            let new_nodes = r.activate(&mut mig).unwrap();
+            assert!(new_nodes.contains_key("v"));
+            mig.commit();
+        }
+
+        let cyclic = vec![(Some(String::from("v")),
+                           sql_parser::parse_query("SELECT w.x FROM w;").unwrap()),
+                          (Some(String::from("w")),
+                           sql_parser::parse_query("SELECT v.x FROM v;").unwrap())];
+        assert!(dependency_order(cyclic).is_err());
+    }
+
+    #[test]
+    fn it_activates_all_or_nothing() {
+        use Blender;
+
+        // the last query joins on an inequality, which is unsupported, so the tables should not
+        // be added either
+        let r_txt = "INSERT INTO a (x, y) VALUES (?, ?);\n
+                     INSERT INTO b (x, y) VALUES (?, ?);\n
+                     SELECT a.x FROM a, b WHERE a.y > b.y;\n";
+        let mut r = Recipe::from_str(r_txt).unwrap();
+
+        let mut g = Blender::new();
+        let mut mig = g.start_migration();
+        assert!(r.activate(&mut mig).is_err());
+        // just the source node
+        assert_eq!(mig.graph().node_count(), 1);
+    }
 }
@@ -1,69 +1,415 @@
+//! An RPC server that exposes a running graph over the network, and a client for it.
+//!
+//! A server started with `run` (or `run_with_recipe`) lets applications read from the graph's
+//! maintained views and write to its base nodes over TCP, so that they do not need to run the
+//! data-flow graph in-process. Applications talk to the server through a `Client`. If the server
+//! was started with the recipe that built the graph, clients can also add new queries to the
+//! graph, which are then available to all clients.
+//!
+//! Clients can also subscribe to a view, and then receive the updates to it as they happen (see
+//! `Client::subscribe`), instead of repeatedly querying it.
+//!
+//! Views are identified by the global index of their node, which `Client::views` maps from their
+//! names. The wire protocol is that of the `ext` service below. It only ever changes in
+//! backwards-compatible ways for as long as `PROTOCOL_VERSION` stays the same, and clients refuse
+//! to talk to a server with a different version.
+
 use flow::prelude::*;
+use flow::node::StreamUpdate;
 use flow;
+use recipe::Recipe;
 
 use tarpc;
-use tarpc::util::Never;
+use tarpc::future::client::{ClientExt, Options};
 use futures;
+use futures::Future;
 use tokio_core::reactor;
 
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 
+/// The version of the wire protocol spoken by this server and client.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The reasons an RPC can fail.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RpcError {
+    /// There is no view with the given identifier that supports the operation. Reads need a
+    /// maintained view, and writes need a base node.
+    NoSuchView(usize),
+    /// The view could not answer the read, for example because it is not ready yet, or because
+    /// the key has been evicted.
+    Unavailable,
+    /// The request does not make sense for the view, such as a deletion from a base node without
+    /// a primary key.
+    BadRequest(String),
+    /// The graph could not be migrated as requested.
+    Migration(String),
+    /// There is no subscription with the given identifier, either because it was never made, or
+    /// because it has ended.
+    NoSuchSubscription(u64),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RpcError::NoSuchView(view) => write!(f, "no such view: {}", view),
+            RpcError::Unavailable => write!(f, "view cannot answer the read"),
+            RpcError::BadRequest(ref e) => write!(f, "bad request: {}", e),
+            RpcError::Migration(ref e) => write!(f, "migration failed: {}", e),
+            RpcError::NoSuchSubscription(sub) => write!(f, "no such subscription: {}", sub),
+        }
+    }
+}
+
+impl error::Error for RpcError {
+    fn description(&self) -> &str {
+        match *self {
+            RpcError::NoSuchView(..) => "no such view",
+            RpcError::Unavailable => "view cannot answer the read",
+            RpcError::BadRequest(..) => "bad request",
+            RpcError::Migration(..) => "migration failed",
+            RpcError::NoSuchSubscription(..) => "no such subscription",
+        }
+    }
+}
+
 /// Available RPC methods
 pub mod ext {
     use flow::data::DataType;
     use std::collections::HashMap;
+    use super::RpcError;
     service! {
-        /// Query the given `view` for all records whose columns match the given values.
-        ///
-        /// If `args = None`, all records are returned. Otherwise, all records are returned whose
-        /// `i`th column matches the value contained in `args[i]` (or any value if `args[i] =
-        /// None`).
-        rpc query(view: usize, key: DataType) -> Vec<Vec<DataType>> | ();
+        /// The version of the wire protocol the server speaks (see `PROTOCOL_VERSION`).
+        rpc version() -> u32 | RpcError;
+
+        /// Query the given `view` for all records whose key column holds `key`.
+        rpc query(view: usize, key: DataType) -> Vec<Vec<DataType>> | RpcError;
 
-        /// Insert a new record into the given view.
+        /// Insert a new record into the given base node.
         ///
         /// `args` gives the column values for the new record.
-        rpc insert(view: usize, args: Vec<DataType>) -> i64;
+        rpc insert(view: usize, args: Vec<DataType>) -> () | RpcError;
+
+        /// Delete the record whose primary key columns hold the values in `key` from the given
+        /// base node.
+        rpc delete(view: usize, key: Vec<DataType>) -> () | RpcError;
+
+        /// Replace the record with the same primary key as `args` in the given base node with
+        /// `args`.
+        rpc update(view: usize, args: Vec<DataType>) -> () | RpcError;
 
         /// List all available views, their names, and whether they are writeable.
-        rpc list() -> HashMap<String, (usize, bool)>;
+        rpc list() -> HashMap<String, (usize, bool)> | RpcError;
+
+        /// The names of the columns of the given view.
+        rpc columns(view: usize) -> Vec<String> | RpcError;
+
+        /// Add the queries in `additions` to the recipe the server was started with, migrate the
+        /// graph accordingly, and return the views the queries added.
+        rpc extend_recipe(additions: String) -> HashMap<String, usize> | RpcError;
+
+        /// Subscribe to the given view, and return the subscription's identifier, along with the
+        /// view's current timestamp and contents.
+        rpc subscribe(view: usize) -> (u64, i64, Vec<Vec<DataType>>) | RpcError;
+
+        /// Wait for the next updates to the view of the given subscription, and return all the
+        /// updates that have arrived since the last call. Each update is a record along with
+        /// whether it was added (`true`) or deleted (`false`).
+        rpc updates(subscription: u64) -> Vec<(bool, Vec<DataType>)> | RpcError;
+
+        /// End the given subscription.
+        rpc unsubscribe(subscription: u64) -> () | RpcError;
     }
 }
 
 use self::ext::*;
 
-type Put = Box<Fn(Vec<DataType>) + Send + 'static>;
 type Get = Box<Fn(&DataType) -> Result<Vec<Vec<DataType>>, ()> + Send + Sync>;
 
-struct Server {
-    put: HashMap<NodeAddress, (String, Vec<String>, Mutex<Put>)>,
+/// The base nodes and maintained views exposed by a server.
+struct Views {
+    put: HashMap<NodeAddress, (String, Vec<String>, Mutex<flow::Mutator>)>,
     get: HashMap<NodeAddress, (String, Vec<String>, Get)>,
-    _g: Mutex<flow::Blender>, // never read or written, just needed so the server doesn't stop
+}
+
+impl Views {
+    fn of(soup: &flow::Blender) -> Self {
+        Views {
+            put: soup.inputs()
+                .into_iter()
+                .map(|(ni, n)| {
+                    (ni,
+                     (n.name().to_owned(),
+                      n.fields().iter().cloned().collect(),
+                      Mutex::new(soup.get_mutator(ni))))
+                })
+                .collect(),
+            get: soup.outputs()
+                .into_iter()
+                .map(|(ni, n, r)| {
+                    (ni,
+                     (n.name().to_owned(),
+                      n.fields().iter().cloned().collect(),
+                      r.get_reader().unwrap()))
+                })
+                .collect(),
+        }
+    }
+}
+
+type Updates = Vec<(bool, Vec<DataType>)>;
+
+/// The updates to a subscribed view that have not yet been sent to the client.
+#[derive(Default)]
+struct Feed {
+    pending: Updates,
+    // a call to `updates` that is waiting for the next batch
+    waiting: Option<futures::sync::oneshot::Sender<Updates>>,
+    // set once the client has unsubscribed, or the view has gone away
+    ended: bool,
+}
+
+/// Forward the updates received on `rx` to `feed`, until either side goes away.
+fn forward(rx: mpsc::Receiver<Vec<StreamUpdate>>, feed: Arc<Mutex<Feed>>) {
+    for batch in rx {
+        let batch = batch.into_iter().map(|u| match u {
+            StreamUpdate::AddRow(row) => (true, (*row).clone()),
+            StreamUpdate::DeleteRow(row) => (false, (*row).clone()),
+        });
+
+        let mut feed = feed.lock().unwrap();
+        if feed.ended {
+            return;
+        }
+        match feed.waiting.take() {
+            Some(waiting) => waiting.complete(batch.collect()),
+            None => feed.pending.extend(batch),
+        }
+    }
+
+    // dropping the waiting call's sender fails it
+    let mut feed = feed.lock().unwrap();
+    feed.ended = true;
+    feed.waiting.take();
+}
+
+struct Server {
+    views: RwLock<Views>,
+    // the graph, and the recipe it was built from, if known. the graph must be kept around so
+    // that the server doesn't stop, and is otherwise used for migrations and subscriptions.
+    soup: Mutex<(flow::Blender, Option<Recipe>)>,
+    subscriptions: Mutex<HashMap<u64, Arc<Mutex<Feed>>>>,
+    next_subscription: AtomicUsize,
+}
+
+impl Server {
+    /// Write to the base node `view` with `write`, which is given the mutator, the base's
+    /// primary key columns, and its number of columns.
+    fn write<F>(&self, view: usize, write: F) -> Result<(), RpcError>
+        where F: FnOnce(&flow::Mutator, &[usize], usize) -> Result<(), RpcError>
+    {
+        let views = self.views.read().unwrap();
+        let put = views.put.get(&view.into()).ok_or(RpcError::NoSuchView(view))?;
+        let mutator = put.2.lock().unwrap();
+        write(&*mutator, mutator.primary_key(), put.1.len())
+    }
+
+    fn extend(&self, additions: &str) -> Result<HashMap<String, usize>, RpcError> {
+        let mut soup = self.soup.lock().unwrap();
+        let (ref mut g, ref mut recipe) = *soup;
+        let mut new = {
+            let old = recipe.as_ref()
+                .ok_or_else(|| {
+                    RpcError::Migration("the server was not started with a recipe".to_owned())
+                })?;
+            // keep the old recipe around in case the migration fails
+            old.clone().extend(additions).map_err(RpcError::Migration)?
+        };
+
+        let added = {
+            let mut mig = g.start_migration();
+            match new.activate(&mut mig) {
+                Ok(added) => {
+                    mig.commit();
+                    added
+                }
+                Err(e) => {
+                    mig.abort();
+                    return Err(RpcError::Migration(e));
+                }
+            }
+        };
+        *recipe = Some(new);
+        *self.views.write().unwrap() = Views::of(g);
+        Ok(added.into_iter().map(|(name, na)| (name, na.into())).collect())
+    }
+
+    fn subscribe(&self, view: usize) -> Result<(u64, i64, Vec<Vec<DataType>>), RpcError> {
+        if !self.views.read().unwrap().get.contains_key(&view.into()) {
+            return Err(RpcError::NoSuchView(view));
+        }
+        let sub = self.soup.lock().unwrap().0.subscribe(view.into()).ok_or(RpcError::Unavailable)?;
+
+        let id = self.next_subscription.fetch_add(1, Ordering::SeqCst) as u64;
+        let feed = Arc::new(Mutex::new(Feed::default()));
+        self.subscriptions.lock().unwrap().insert(id, feed.clone());
+        let updates = sub.updates;
+        thread::Builder::new()
+            .name(format!("subscription{}", id))
+            .spawn(move || forward(updates, feed))
+            .unwrap();
+        Ok((id, sub.ts, sub.snapshot))
+    }
+
+    fn updates(&self, subscription: u64) -> Result<futures::sync::oneshot::Receiver<Updates>,
+                                                   RpcError> {
+        let feed = self.subscriptions
+            .lock()
+            .unwrap()
+            .get(&subscription)
+            .cloned()
+            .ok_or(RpcError::NoSuchSubscription(subscription))?;
+        let mut feed = feed.lock().unwrap();
+        if feed.ended {
+            self.subscriptions.lock().unwrap().remove(&subscription);
+            return Err(RpcError::NoSuchSubscription(subscription));
+        }
+
+        let (tx, rx) = futures::sync::oneshot::channel();
+        if feed.pending.is_empty() {
+            // a previous call that is still waiting gets nothing
+            feed.waiting = Some(tx);
+        } else {
+            tx.complete(feed.pending.drain(..).collect());
+        }
+        Ok(rx)
+    }
+
+    fn unsubscribe(&self, subscription: u64) -> Result<(), RpcError> {
+        let feed = self.subscriptions
+            .lock()
+            .unwrap()
+            .remove(&subscription)
+            .ok_or(RpcError::NoSuchSubscription(subscription))?;
+        let mut feed = feed.lock().unwrap();
+        feed.ended = true;
+        feed.waiting.take();
+        Ok(())
+    }
 }
 
 impl ext::FutureService for Arc<Server> {
-    type QueryFut = futures::future::FutureResult<Vec<Vec<DataType>>, ()>;
+    type VersionFut = futures::future::FutureResult<u32, RpcError>;
+    fn version(&self) -> Self::VersionFut {
+        futures::future::ok(PROTOCOL_VERSION)
+    }
+
+    type QueryFut = futures::future::FutureResult<Vec<Vec<DataType>>, RpcError>;
     fn query(&self, view: usize, key: DataType) -> Self::QueryFut {
-        let get = &self.get[&view.into()];
-        futures::future::result(get.2(&key))
+        let views = self.views.read().unwrap();
+        futures::future::result(match views.get.get(&view.into()) {
+            Some(get) => get.2(&key).map_err(|_| RpcError::Unavailable),
+            None => Err(RpcError::NoSuchView(view)),
+        })
     }
 
-    type InsertFut = futures::Finished<i64, Never>;
+    type InsertFut = futures::future::FutureResult<(), RpcError>;
     fn insert(&self, view: usize, args: Vec<DataType>) -> Self::InsertFut {
-        self.put[&view.into()].2.lock().unwrap()(args);
-        futures::finished(0)
+        futures::future::result(self.write(view, |m, _, arity| {
+            if args.len() != arity {
+                return Err(RpcError::BadRequest(format!("base has {} columns, but the inserted \
+                                                         row has {} values",
+                                                        arity,
+                                                        args.len())));
+            }
+            m.put(args);
+            Ok(())
+        }))
+    }
+
+    type DeleteFut = futures::future::FutureResult<(), RpcError>;
+    fn delete(&self, view: usize, key: Vec<DataType>) -> Self::DeleteFut {
+        futures::future::result(self.write(view, |m, pk, _| {
+            if pk.is_empty() || key.len() != pk.len() {
+                return Err(RpcError::BadRequest(format!("base has key columns {:?}, but the \
+                                                         deletion key has {} values",
+                                                        pk,
+                                                        key.len())));
+            }
+            m.delete(key);
+            Ok(())
+        }))
+    }
+
+    type UpdateFut = futures::future::FutureResult<(), RpcError>;
+    fn update(&self, view: usize, args: Vec<DataType>) -> Self::UpdateFut {
+        futures::future::result(self.write(view, |m, pk, arity| {
+            if pk.is_empty() {
+                return Err(RpcError::BadRequest("base has no key columns".to_owned()));
+            }
+            if args.len() != arity {
+                return Err(RpcError::BadRequest(format!("base has {} columns, but the updated \
+                                                         row has {} values",
+                                                        arity,
+                                                        args.len())));
+            }
+            m.update(args);
+            Ok(())
+        }))
     }
 
-    type ListFut = futures::Finished<HashMap<String, (usize, bool)>, Never>;
+    type ListFut = futures::future::FutureResult<HashMap<String, (usize, bool)>, RpcError>;
     fn list(&self) -> Self::ListFut {
-        futures::finished(self.get
+        let views = self.views.read().unwrap();
+        futures::future::ok(views.get
             .iter()
             .map(|(&ni, &(ref n, _, _))| (n.clone(), (ni.into(), false)))
-            .chain(self.put.iter().map(|(&ni, &(ref n, _, _))| (n.clone(), (ni.into(), true))))
+            .chain(views.put.iter().map(|(&ni, &(ref n, _, _))| (n.clone(), (ni.into(), true))))
             .collect())
     }
+
+    type ColumnsFut = futures::future::FutureResult<Vec<String>, RpcError>;
+    fn columns(&self, view: usize) -> Self::ColumnsFut {
+        let views = self.views.read().unwrap();
+        let na = view.into();
+        futures::future::result(views.get
+            .get(&na)
+            .map(|g| g.1.clone())
+            .or_else(|| views.put.get(&na).map(|p| p.1.clone()))
+            .ok_or(RpcError::NoSuchView(view)))
+    }
+
+    type ExtendRecipeFut = futures::future::FutureResult<HashMap<String, usize>, RpcError>;
+    fn extend_recipe(&self, additions: String) -> Self::ExtendRecipeFut {
+        futures::future::result(self.extend(&additions))
+    }
+
+    type SubscribeFut = futures::future::FutureResult<(u64, i64, Vec<Vec<DataType>>), RpcError>;
+    fn subscribe(&self, view: usize) -> Self::SubscribeFut {
+        futures::future::result(Server::subscribe(self, view))
+    }
+
+    type UpdatesFut = Box<Future<Item = Updates, Error = RpcError>>;
+    fn updates(&self, subscription: u64) -> Self::UpdatesFut {
+        match Server::updates(self, subscription) {
+            // the sender is dropped if the subscription ends while we wait
+            Ok(rx) => Box::new(rx.map_err(move |_| RpcError::NoSuchSubscription(subscription))),
+            Err(e) => Box::new(futures::future::err(e)),
+        }
+    }
+
+    type UnsubscribeFut = futures::future::FutureResult<(), RpcError>;
+    fn unsubscribe(&self, subscription: u64) -> Self::UnsubscribeFut {
+        futures::future::result(Server::unsubscribe(self, subscription))
+    }
 }
 
 /// A handle for a running RPC server.
@@ -90,47 +436,36 @@ impl Drop for ServerHandle {
 
 /// Starts a server which allows read/write access to the Soup using a binary protocol.
 ///
-/// In particular, requests should all be of the form `types::Request`
-pub fn run<T: Into<::std::net::SocketAddr>>(soup: flow::Blender,
+/// Requests are made through the `ext` service, for example with a `Client`. The graph cannot be
+/// migrated through the server; use `run_with_recipe` for that.
+pub fn run<T: Into<SocketAddr>>(soup: flow::Blender, addr: T, threads: usize) -> ServerHandle {
+    serve(soup, None, addr.into(), threads)
+}
+
+/// Starts a server like `run`, for a graph built from `recipe`.
+///
+/// Clients can then add queries to the recipe, and the graph is migrated to match (see
+/// `Client::extend_recipe`).
+pub fn run_with_recipe<T: Into<SocketAddr>>(soup: flow::Blender,
+                                            recipe: Recipe,
                                             addr: T,
                                             threads: usize)
                                             -> ServerHandle {
-    // Figure out what inputs and outputs to expose
-    let (ins, outs) = {
-        let ins: Vec<_> = soup.inputs()
-            .into_iter()
-            .map(|(ni, n)| {
-                (ni,
-                 (n.name().to_owned(), n.fields().iter().cloned().collect(), soup.get_mutator(ni)))
-            })
-            .collect();
-        let outs: Vec<_> = soup.outputs()
-            .into_iter()
-            .map(|(ni, n, r)| {
-                (ni,
-                 (n.name().to_owned(),
-                  n.fields().iter().cloned().collect(),
-                  r.get_reader().unwrap()))
-            })
-            .collect();
-        (ins, outs)
-    };
-
-    let s = Server {
-        put: ins.into_iter()
-            .map(|(ni, (nm, args, mutator))| {
-                (ni,
-                 (nm, args, Mutex::new(Box::new(move |v: Vec<DataType>| mutator.put(v)) as Box<_>)))
-            })
-            .collect(),
-        get: outs.into_iter()
-            .map(|(ni, (nm, args, getter))| (ni, (nm, args, getter)))
-            .collect(),
-        _g: Mutex::new(soup),
-    };
-
-    let addr = addr.into();
-    let s = Arc::new(s);
+    serve(soup, Some(recipe), addr.into(), threads)
+}
+
+fn serve(soup: flow::Blender,
+         recipe: Option<Recipe>,
+         addr: SocketAddr,
+         threads: usize)
+         -> ServerHandle {
+    let s = Arc::new(Server {
+        views: RwLock::new(Views::of(&soup)),
+        soup: Mutex::new((soup, recipe)),
+        subscriptions: Mutex::default(),
+        next_subscription: AtomicUsize::new(0),
+    });
+
     let threads = (0..threads)
         .map(move |i| {
             let s = s.clone();
@@ -157,3 +492,140 @@ pub fn run<T: Into<::std::net::SocketAddr>>(soup: flow::Blender,
 
     ServerHandle { threads: threads }
 }
+
+/// The error returned by calls made through a `Client`.
+///
+/// Errors reported by the server itself are `tarpc::Error::App(RpcError)`; all others mean that
+/// the request or response could not be transferred.
+pub type Error = tarpc::Error<RpcError>;
+
+/// A client for a server started with `run` or `run_with_recipe`.
+///
+/// Every call blocks until the server has responded. A client runs its own event loop, and so
+/// cannot be moved between threads; every thread should connect a client of its own.
+pub struct Client {
+    client: ext::FutureClient,
+    core: reactor::Core,
+}
+
+impl Client {
+    /// Connect to the server listening on `addr`.
+    ///
+    /// Fails if the server speaks a different version of the wire protocol.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Client> {
+        let addr = addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+            })?;
+        let mut core = reactor::Core::new()?;
+        let client = core.run(ext::FutureClient::connect(addr,
+                                                       Options::default().handle(core.handle())))?;
+        let mut c = Client {
+            client: client,
+            core: core,
+        };
+
+        let version = c.core
+            .run(c.client.version())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+        if version != PROTOCOL_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("server speaks protocol version {}, not {}",
+                                              version,
+                                              PROTOCOL_VERSION)));
+        }
+        Ok(c)
+    }
+
+    /// All available views, by name, along with their identifier and whether they are base nodes
+    /// that can be written to.
+    pub fn views(&mut self) -> Result<HashMap<String, (usize, bool)>, Error> {
+        self.core.run(self.client.list())
+    }
+
+    /// The identifier of the view called `name`, if there is one.
+    pub fn view(&mut self, name: &str) -> Result<Option<usize>, Error> {
+        self.views().map(|mut vs| vs.remove(name).map(|(view, _)| view))
+    }
+
+    /// The names of the columns of the given view.
+    pub fn columns(&mut self, view: usize) -> Result<Vec<String>, Error> {
+        self.core.run(self.client.columns(view))
+    }
+
+    /// Find all records in the given view whose key column holds `key`.
+    pub fn query(&mut self, view: usize, key: DataType) -> Result<Vec<Vec<DataType>>, Error> {
+        self.core.run(self.client.query(view, key))
+    }
+
+    /// Insert a record into the given base node.
+    pub fn insert(&mut self, view: usize, args: Vec<DataType>) -> Result<(), Error> {
+        self.core.run(self.client.insert(view, args))
+    }
+
+    /// Delete the record whose primary key columns hold the values in `key` from the given base
+    /// node.
+    pub fn delete(&mut self, view: usize, key: Vec<DataType>) -> Result<(), Error> {
+        self.core.run(self.client.delete(view, key))
+    }
+
+    /// Replace the record with the same primary key as `args` in the given base node with `args`.
+    pub fn update(&mut self, view: usize, args: Vec<DataType>) -> Result<(), Error> {
+        self.core.run(self.client.update(view, args))
+    }
+
+    /// Add the queries in `additions` to the recipe the server was started with, and return the
+    /// views they added, by name.
+    ///
+    /// Only servers started with `run_with_recipe` can be migrated this way.
+    pub fn extend_recipe(&mut self, additions: &str) -> Result<HashMap<String, usize>, Error> {
+        self.core.run(self.client.extend_recipe(additions.to_owned()))
+    }
+
+    /// Subscribe to the given view.
+    ///
+    /// Like with `Blender::subscribe`, applying the updates returned by `updates` to the
+    /// subscription's snapshot, in order, keeps it identical to the view.
+    pub fn subscribe(&mut self, view: usize) -> Result<Subscription, Error> {
+        self.core.run(self.client.subscribe(view)).map(|(id, ts, snapshot)| {
+            Subscription {
+                id: id,
+                ts: ts,
+                snapshot: snapshot,
+            }
+        })
+    }
+
+    /// Wait for the next updates to the view of the given subscription.
+    ///
+    /// Returns every update that has arrived since the last call, and only blocks if there are
+    /// none. Fails with `RpcError::NoSuchSubscription` once the subscription has ended.
+    pub fn updates(&mut self, sub: &Subscription) -> Result<Vec<StreamUpdate>, Error> {
+        self.core.run(self.client.updates(sub.id)).map(|updates| {
+            updates.into_iter()
+                .map(|(add, row)| if add {
+                    StreamUpdate::AddRow(Arc::new(row))
+                } else {
+                    StreamUpdate::DeleteRow(Arc::new(row))
+                })
+                .collect()
+        })
+    }
+
+    /// End the given subscription, so that the server stops buffering updates for it.
+    pub fn unsubscribe(&mut self, sub: Subscription) -> Result<(), Error> {
+        self.core.run(self.client.unsubscribe(sub.id))
+    }
+}
+
+/// A subscription made with `Client::subscribe`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    /// The server's identifier for the subscription.
+    pub id: u64,
+    /// The timestamp of the view when the subscription started.
+    pub ts: i64,
+    /// The contents of the view when the subscription started.
+    pub snapshot: Vec<Vec<DataType>>,
+}
@@ -49,8 +49,12 @@ fn main() {
         mig.commit();
     }
 
-    web::run(g).unwrap();
-    loop {}
+    // serve until the process is killed
+    let server = web::run(g).unwrap();
+    println!("listening on {}", server.addr());
+    loop {
+        ::std::thread::park();
+    }
 }
 
 #[cfg(not(feature="web"))]
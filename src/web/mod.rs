@@ -1,14 +1,21 @@
-use rustful::{Server, Handler, Context, Response, TreeRouter, HttpResult};
+use rustful::{Server, Handler, Context, Response, TreeRouter, HttpResult, StatusCode};
 use rustful::server::Listening;
 use rustful::server::Global;
+use rustc_serialize::json::Json;
+use std::net::SocketAddr;
 use std::sync::Mutex;
 
-use flow::Blender;
+use flow::{Blender, NodeAddress};
 use flow::data::DataType;
-use std::collections::HashMap;
+use flow::health::DomainHealth;
+use flow::node::StreamUpdate;
+use std::collections::{BTreeMap, HashMap};
+use std::time;
 
 struct GetEndpoint<F> {
+    node: NodeAddress,
     arguments: Vec<String>,
+    key: String,
     f: F,
 }
 
@@ -17,16 +24,103 @@ struct PutEndpoint<Mutator> {
     mutator: Mutator,
 }
 
-/// Start exposing the given `FlowGraph` over HTTP.
+/// A running HTTP frontend, as returned by `run` and `run_on`.
 ///
-/// All base nodes are available for writing by POSTing to `localhost:8080/<view>`. Each POST
-/// should contain a single JSON object representing the record with field names equal to those
-/// passed to `new()`.
+/// The server keeps serving requests until `shutdown` is called, or the handle is dropped.
+pub struct WebServer {
+    listening: Option<Listening>,
+    addr: SocketAddr,
+}
+
+impl WebServer {
+    /// The address the server is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting new connections, and wait for requests that are in flight to complete.
+    ///
+    /// The `Blender` the server was started with is dropped once the last request is done.
+    pub fn shutdown(mut self) -> HttpResult<()> {
+        self.listening.take().unwrap().close()
+    }
+}
+
+impl Drop for WebServer {
+    fn drop(&mut self) {
+        if let Some(mut listening) = self.listening.take() {
+            let _ = listening.close();
+        }
+    }
+}
+
+/// Convert a JSON value sent by a client into a `DataType`.
+///
+/// Returns `None` for values that have no `DataType` equivalent (i.e., arrays and objects).
+fn from_json(v: &Json) -> Option<DataType> {
+    match *v {
+        Json::I64(n) => Some(n.into()),
+        Json::U64(n) if n <= i64::max_value() as u64 => Some((n as i64).into()),
+        Json::U64(_) => None,
+        Json::F64(f) => Some(f.into()),
+        Json::Boolean(b) => Some(b.into()),
+        Json::String(ref s) => Some(s.as_str().into()),
+        Json::Null => Some(DataType::None),
+        Json::Array(_) | Json::Object(_) => None,
+    }
+}
+
+/// Represent `row` as a JSON object, with the field names given by `args`.
+fn row_to_json(args: &[String], row: Vec<DataType>) -> Json {
+    use rustc_serialize::json::ToJson;
+    args.iter().cloned().zip(row.into_iter()).collect::<HashMap<_, _>>().to_json()
+}
+
+/// Respond to a request that could not be served with the given status and a JSON error message.
+fn send_error(mut res: Response, status: StatusCode, msg: &str) {
+    use rustc_serialize::json::ToJson;
+    use rustful::header::ContentType;
+
+    let mut body = BTreeMap::new();
+    body.insert("error".to_owned(), msg.to_json());
+    res.set_status(status);
+    res.headers_mut().set(ContentType::json());
+    res.send(format!("{}", Json::Object(body)));
+}
+
+/// Start exposing the given `FlowGraph` over HTTP on port 8080 on all interfaces.
+///
+/// See `run_on` for the endpoints that are exposed.
+pub fn run(soup: Blender) -> HttpResult<WebServer> {
+    run_on(soup, ([0, 0, 0, 0], 8080))
+}
+
+/// Start exposing the given `FlowGraph` over HTTP on the given address.
+///
+/// Endpoints are generated for every named node in the graph at the time the server starts:
+///
+///  - All base nodes are available for writing by POSTing to `<addr>/<base>`. Each POST should
+///    contain a single JSON object representing the record with field names equal to those
+///    passed to `new()`. Fields that are left out are inserted as `null`.
+///  - All leaf views are available for reading by GETing from `<addr>/<view>?key=<key>`, or
+///    equivalently `<addr>/<view>?<key field>=<key>`. A JSON array with all matching records is
+///    returned. Each record is represented as a JSON object with field names as dictated by those
+///    passed to `new()` for the view being queried.
+///  - All leaf views can be subscribed to by GETing from `<addr>/<view>/stream` (see
+///    `Blender::subscribe`). The response is streamed with one JSON value per line. The first line
+///    is an object holding the view's current contents in `snapshot` and its timestamp in `ts`.
+///    Every following line is an array with the next batch of updates to the view, each of which
+///    is an object with `op` set to `"add"` or `"delete"` and the record in `row`. The stream
+///    ends when the client disconnects, and occupies one of the server's threads until then.
+///
+/// Malformed requests get a response with status 400, and reads from views that are not yet
+/// ready to serve the given key get status 503. Both carry a JSON object with an `error` field.
 ///
-/// All nodes are available for reading by GETing from `localhost:8080/<view>?key=<key>`. A JSON
-/// array with all matching records is returned. Each record is represented as a JSON object with
-/// field names as dictated by those passed to `new()` for the view being queried.
-pub fn run(soup: Blender) -> HttpResult<Listening> {
+/// A summary of the graph's health (see `Blender::health`) is available by GETing from
+/// `<addr>/healthz`. The response has status 200 if the graph is healthy, and 503 otherwise.
+///
+/// Passing port 0 binds an arbitrary free port; use `WebServer::addr` to find out which.
+pub fn run_on<A: Into<SocketAddr>>(soup: Blender, addr: A) -> HttpResult<WebServer> {
     use rustc_serialize::json::ToJson;
     use rustful::header::ContentType;
 
@@ -46,12 +140,20 @@ pub fn run(soup: Blender) -> HttpResult<Listening> {
             .collect();
         let outs: Vec<_> = soup.outputs()
             .into_iter()
-            .map(|(_, n, r)| {
-                (n.name().to_owned(),
-                 GetEndpoint {
-                     arguments: n.fields().iter().cloned().collect(),
-                     f: r.get_reader().unwrap(),
-                 })
+            .filter_map(|(ni, n, r)| {
+                let key = match r.state {
+                    Some(ref s) => n.fields()[s.key()].clone(),
+                    None => return None,
+                };
+                r.get_reader().map(|f| {
+                    (n.name().to_owned(),
+                     GetEndpoint {
+                         node: ni,
+                         arguments: n.fields().iter().cloned().collect(),
+                         key: key,
+                         f: f,
+                     })
+                })
             })
             .collect();
         (ins, outs)
@@ -63,17 +165,42 @@ pub fn run(soup: Blender) -> HttpResult<Listening> {
         insert_routes! {
             &mut router => {
                 path => Post: Box::new(move |mut ctx: Context, mut res: Response| {
-                    let json = ctx.body.read_json_body().unwrap();
+                    let json = match ctx.body.read_json_body() {
+                        Ok(json) => json,
+                        Err(_) => {
+                            return send_error(res,
+                                              StatusCode::BadRequest,
+                                              "body is not valid JSON");
+                        }
+                    };
+                    let json = match json.as_object() {
+                        Some(o) => o.clone(),
+                        None => {
+                            return send_error(res,
+                                              StatusCode::BadRequest,
+                                              "body must be a JSON object");
+                        }
+                    };
 
-                    let ts = put.lock().unwrap().put((args.iter().map(|arg| {
-                        if let Some(num) = json[&**arg].as_i64() {
-                            num.into()
-                        } else {
-                            json[&**arg].as_string().unwrap().into()
+                    let mut row = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        match json.get(arg).map(from_json) {
+                            Some(Some(v)) => row.push(v),
+                            Some(None) => {
+                                let msg = format!("field {} must be a scalar", arg);
+                                return send_error(res, StatusCode::BadRequest, &msg);
+                            }
+                            None => row.push(DataType::None),
                         }
-                    })).collect::<Vec<DataType>>());
+                    }
+                    if let Some(f) = json.keys().find(|f| !args.contains(f)) {
+                        let msg = format!("no such field: {}", f);
+                        return send_error(res, StatusCode::BadRequest, &msg);
+                    }
+
+                    put.lock().unwrap().put(row);
                     res.headers_mut().set(ContentType::json());
-                    res.send(format!("{}", ts.to_json()));
+                    res.send(format!("{}", Json::Null));
                 }) as Box<Handler>,
             }
         };
@@ -82,37 +209,152 @@ pub fn run(soup: Blender) -> HttpResult<Listening> {
     for (path, ep) in outs.into_iter() {
         let get = ep.f;
         let args = ep.arguments;
+        let keyfield = ep.key;
+        let node = ep.node;
+        let stream_path = format!("{}/stream", path);
+        let stream_args = args.clone();
         insert_routes! {
             &mut router => {
                 path => Get: Box::new(move |ctx: Context, mut res: Response| {
-                    if let Some(key) = ctx.query.get("key") {
-                        let key = if let Ok(n) = ctx.query.parse("key") {
-                            let n: i64 = n;
-                            n.into()
-                        } else {
-                            key.into_owned().into()
-                        };
+                    let param = if ctx.query.get("key").is_some() {
+                        "key"
+                    } else {
+                        &*keyfield
+                    };
+                    let key = match ctx.query.get(param) {
+                        Some(key) => key,
+                        None => {
+                            let msg = format!("missing key (pass ?key= or ?{}=)", keyfield);
+                            return send_error(res, StatusCode::BadRequest, &msg);
+                        }
+                    };
+                    let key = if let Ok(n) = ctx.query.parse(param) {
+                        let n: i64 = n;
+                        n.into()
+                    } else {
+                        key.into_owned().into()
+                    };
+
+                    let rows = match get(&key) {
+                        Ok(rows) => rows,
+                        Err(()) => {
+                            return send_error(res,
+                                              StatusCode::ServiceUnavailable,
+                                              "view is not ready to serve this key");
+                        }
+                    };
+                    let data = rows.into_iter().map(|row| row_to_json(&args, row));
+                    res.headers_mut().set(ContentType::json());
+                    res.send(format!("{}", Json::Array(data.collect())));
+                }) as Box<Handler>,
+                stream_path => Get: Box::new(move |ctx: Context, mut res: Response| {
+                    use std::io::Write;
 
-                        let data = get(&key).into_iter().map(|row| {
-                                args
-                                .clone()
-                                .into_iter()
-                                .zip(row.into_iter())
-                                .collect::<HashMap<_, _>>()
-                        }).collect::<Vec<_>>();
-                        res.headers_mut().set(ContentType::json());
-                        res.send(format!("{}", data.to_json()));
+                    let sub = {
+                        let soup = ctx.global.get::<Mutex<Blender>>().unwrap();
+                        let sub = soup.lock().unwrap().subscribe(node);
+                        match sub {
+                            Some(sub) => sub,
+                            None => {
+                                return send_error(res,
+                                                  StatusCode::ServiceUnavailable,
+                                                  "view cannot be subscribed to");
+                            }
+                        }
+                    };
+
+                    let mut first = BTreeMap::new();
+                    let snapshot = sub.snapshot.into_iter().map(|r| row_to_json(&stream_args, r));
+                    first.insert("snapshot".to_owned(), Json::Array(snapshot.collect()));
+                    first.insert("ts".to_owned(), sub.ts.to_json());
+
+                    res.headers_mut().set(ContentType::json());
+                    let mut res = res.into_chunked();
+                    if writeln!(res, "{}", Json::Object(first)).and_then(|_| res.flush()).is_err() {
+                        return;
+                    }
+
+                    // the channel closes if the view goes away
+                    for batch in sub.updates {
+                        let batch = batch.into_iter()
+                            .map(|u| {
+                                let (op, row) = match u {
+                                    StreamUpdate::AddRow(row) => ("add", row),
+                                    StreamUpdate::DeleteRow(row) => ("delete", row),
+                                };
+                                let mut u = BTreeMap::new();
+                                u.insert("op".to_owned(), op.to_json());
+                                let row = row_to_json(&stream_args, (*row).clone());
+                                u.insert("row".to_owned(), row);
+                                Json::Object(u)
+                            })
+                            .collect();
+                        if writeln!(res, "{}", Json::Array(batch))
+                            .and_then(|_| res.flush())
+                            .is_err() {
+                            // the client has gone away
+                            return;
+                        }
                     }
                 }) as Box<Handler>,
             }
         };
     }
 
-    Server {
+    insert_routes! {
+        &mut router => {
+            "healthz" => Get: Box::new(|ctx: Context, mut res: Response| {
+                let soup = ctx.global.get::<Mutex<Blender>>().unwrap();
+                let health = soup.lock().unwrap().health(time::Duration::from_secs(1));
+                let healthy = health.is_healthy();
+
+                let domains = health.domains
+                    .into_iter()
+                    .map(|(d, h)| {
+                        let h = match h {
+                            DomainHealth::Alive => "alive".to_owned(),
+                            DomainHealth::Saturated => "saturated".to_owned(),
+                            DomainHealth::Unresponsive => "unresponsive".to_owned(),
+                            DomainHealth::Failed(e) => format!("failed: {}", e),
+                        };
+                        (d.index().to_string(), Json::String(h))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                let readers = health.readers
+                    .into_iter()
+                    .map(|(n, since)| {
+                        // seconds since the reader last swapped
+                        let since = since.map(|d| {
+                                Json::F64(d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9)
+                            })
+                            .unwrap_or(Json::Null);
+                        (n.to_string(), since)
+                    })
+                    .collect::<BTreeMap<_, _>>();
+
+                let mut body = BTreeMap::new();
+                body.insert("healthy".to_owned(), Json::Boolean(healthy));
+                body.insert("domains".to_owned(), Json::Object(domains));
+                body.insert("readers".to_owned(), Json::Object(readers));
+
+                if !healthy {
+                    res.set_status(StatusCode::ServiceUnavailable);
+                }
+                res.headers_mut().set(ContentType::json());
+                res.send(format!("{}", Json::Object(body)));
+            }) as Box<Handler>,
+        }
+    };
+
+    let listening = Server {
             handlers: router,
-            host: 8080.into(),
+            host: addr.into().into(),
             global: Global::from(Box::new(Mutex::new(soup))),
             ..Server::default()
         }
-        .run()
+        .run()?;
+    Ok(WebServer {
+        addr: listening.socket,
+        listening: Some(listening),
+    })
 }
@@ -3,7 +3,8 @@ extern crate distributary;
 
 #[cfg(feature="web")]
 fn main() {
-    use distributary::*;
+    use distributary::prelude::*;
+    use distributary::web;
 
     // set up graph
     let mut g = distributary::Blender::new();
@@ -60,7 +61,12 @@ fn main() {
     println!("{}", g);
 
     // run the application
-    web::run(g).unwrap();
+    // serve until the process is killed
+    let server = web::run(g).unwrap();
+    println!("listening on {}", server.addr());
+    loop {
+        ::std::thread::park();
+    }
 }
 
 #[cfg(not(feature="web"))]
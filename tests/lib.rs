@@ -100,6 +100,849 @@ fn it_works_streaming() {
     assert_eq!(cq.recv(), Ok(vec![vec![id.clone(), 4.into()].into()]));
 }
 
+#[test]
+fn it_works_with_prepared_queries() {
+    use distributary::{Comparison, Operand, Predicate, PreparedQuery};
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b", "c"], distributary::Base::default());
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into(), "x".into()]);
+    muta.put(vec![1.into(), 5.into(), "y".into()]);
+    muta.put(vec![2.into(), 7.into(), "z".into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // only rows with b > 3 are returned, and only their c column
+    let q = PreparedQuery {
+        predicate: Some(Predicate::Compare(1, Comparison::Greater, Operand::Literal(3.into()))),
+        projection: Some(vec![2]),
+    };
+    let find = g.get_prepared_getter(a, q).unwrap();
+    assert_eq!(find(&1.into()), Ok(vec![vec!["y".into()]]));
+    assert_eq!(find(&2.into()), Ok(vec![vec!["z".into()]]));
+    assert_eq!(find(&3.into()), Ok(vec![]));
+
+    // an empty query is just a regular lookup
+    let find = g.get_prepared_getter(a, PreparedQuery::default()).unwrap();
+    assert_eq!(find(&2.into()), Ok(vec![vec![2.into(), 7.into(), "z".into()]]));
+}
+
+#[test]
+fn it_works_with_ordered_mutators() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::new(vec![0]));
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+
+    // writes for the same key keep their order even when spread over several queues
+    let muta = g.get_mutator(a).ordered_by(0, 4);
+    for k in 0..10 {
+        muta.put(vec![k.into(), 0.into()]);
+        for v in 1..5 {
+            muta.update(vec![k.into(), v.into()]);
+        }
+    }
+    drop(muta);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let cq = g.get_getter(a).unwrap();
+    for k in 0..10 {
+        assert_eq!(cq(&k.into()), Ok(vec![vec![k.into(), 4.into()]]));
+    }
+}
+
+#[test]
+fn it_works_with_secondary_indexes() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, by_id, by_author) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "author"], distributary::Base::default());
+        let by_id = mig.maintain(a, 0);
+        let by_author = mig.maintain_index(a, 1);
+        mig.commit();
+        (a, by_id, by_author)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), "x".into()]);
+    muta.put(vec![2.into(), "x".into()]);
+    muta.put(vec![3.into(), "y".into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    assert_eq!(by_id(&2.into()), Ok(vec![vec![2.into(), "x".into()]]));
+    let mut res = by_author(&"x".into()).unwrap();
+    res.sort();
+    assert_eq!(res, vec![vec![1.into(), "x".into()], vec![2.into(), "x".into()]]);
+
+    // getters can also be obtained for either column later
+    let find = g.get_index_getter(a, 1).unwrap();
+    assert_eq!(find(&"y".into()), Ok(vec![vec![3.into(), "y".into()]]));
+    assert!(g.get_index_getter(a, 0).is_some());
+}
+
+#[test]
+fn it_works_with_compound_keys() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, find) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["user", "device", "ts"], distributary::Base::default());
+        let latest = mig.add_ingredient("latest",
+                                        &["user", "device", "ts"],
+                                        distributary::Latest::new(a, vec![0, 1]));
+        let find = mig.maintain_compound(latest, &[1, 0]);
+        mig.commit();
+        (a, find)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), "phone".into(), 1.into()]);
+    muta.put(vec![1.into(), "laptop".into(), 2.into()]);
+    muta.put(vec![2.into(), "phone".into(), 3.into()]);
+    muta.put(vec![1.into(), "phone".into(), 4.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // each (user, device) pair has its own latest record
+    assert_eq!(find(&["phone".into(), 1.into()]),
+               Ok(vec![vec![1.into(), "phone".into(), 4.into()]]));
+    assert_eq!(find(&["laptop".into(), 1.into()]),
+               Ok(vec![vec![1.into(), "laptop".into(), 2.into()]]));
+    assert_eq!(find(&["phone".into(), 2.into()]),
+               Ok(vec![vec![2.into(), "phone".into(), 3.into()]]));
+    assert_eq!(find(&["laptop".into(), 2.into()]), Ok(vec![]));
+}
+
+#[test]
+fn it_reports_memory_usage() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, c) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "name"], distributary::Base::default());
+        let c = mig.add_ingredient("c",
+                                   &["name", "count"],
+                                   distributary::Aggregation::COUNT.over(a, 0, &[1]));
+        mig.maintain(a, 0);
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, c)
+    };
+
+    let empty = g.memory_usage();
+    assert_eq!(empty.total().rows, 0);
+
+    let muta = g.get_mutator(a);
+    for i in 0..10 {
+        muta.put(vec![i.into(), (if i % 2 == 0 { "even" } else { "odd" }).into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let memory = g.memory_usage();
+    // the reader for a holds every row, and c holds one row per name, both in its own state and
+    // in its reader
+    let by_node: Vec<_> = memory.largest().into_iter().map(|(_, m)| m.rows).collect();
+    assert!(by_node.contains(&10));
+    assert_eq!(by_node.iter().filter(|&&r| r == 2).count(), 2);
+    assert_eq!(memory.total().rows, by_node.iter().sum::<usize>());
+    assert!(memory.total().bytes > empty.total().bytes);
+
+    // the largest node comes first
+    let largest = memory.largest();
+    assert_eq!(largest[0].1.rows, 10);
+    assert!(largest.windows(2).all(|w| w[0].1.bytes >= w[1].1.bytes));
+
+    // every domain's total is the sum over its nodes
+    for d in memory.domains.values() {
+        assert_eq!(d.total.rows, d.nodes.values().map(|m| m.rows).sum::<usize>());
+    }
+    assert!(memory.node(c).is_some());
+}
+
+#[test]
+fn it_collects_metrics() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, c) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "name"], distributary::Base::default());
+        let c = mig.add_ingredient("c",
+                                   &["name", "count"],
+                                   distributary::Aggregation::COUNT.over(a, 0, &[1]));
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, c)
+    };
+
+    let muta = g.get_mutator(a);
+    for i in 0..10 {
+        muta.put(vec![i.into(), (if i % 2 == 0 { "even" } else { "odd" }).into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let getter = g.get_getter(c).unwrap();
+    assert_eq!(getter(&"even".into()), Ok(vec![vec!["even".into(), 5.into()]]));
+    assert_eq!(getter(&"odd".into()), Ok(vec![vec!["odd".into(), 5.into()]]));
+
+    // a collector keeps working from another thread
+    let collector = g.metrics_collector();
+    let metrics = thread::spawn(move || collector.collect()).join().unwrap();
+
+    let base = metrics.node(a).unwrap();
+    assert_eq!(base.records_in, 10);
+    assert_eq!(base.records_out, 10);
+    assert_eq!(base.process_time.count(), 10);
+
+    let agg = metrics.node(c).unwrap();
+    assert_eq!(agg.records_in, 10);
+    assert!(agg.records_out > 0);
+
+    let (hits, misses) = metrics.domains
+        .values()
+        .flat_map(|d| d.readers.values())
+        .fold((0, 0), |(h, m), r| (h + r.hits, m + r.misses));
+    assert_eq!((hits, misses), (2, 0));
+
+    // every domain has received the writes, and has seen its queue empty at some point
+    assert!(metrics.domains.values().all(|d| d.packets > 0 && d.queue_depth.count() > 0));
+
+    // the reader for c was populated by a replay when it was added
+    assert_eq!(metrics.replay_time.count(), g.get_statistics().replays.len() as u64);
+    assert_eq!(metrics.replay_bytes.count(), metrics.replay_time.count());
+}
+
+#[test]
+fn it_explains_reads() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, c, i) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "name"], distributary::Base::default());
+        let c = mig.add_ingredient("c",
+                                   &["name", "count"],
+                                   distributary::Aggregation::COUNT.over(a, 0, &[1]));
+        let i = mig.add_ingredient("i", &["id", "name"], distributary::Identity::new(a));
+        mig.maintain(c, 0);
+        mig.maintain(i, 1);
+        mig.commit();
+        (a, c, i)
+    };
+
+    let muta = g.get_mutator(a);
+    for i in 0..10 {
+        muta.put(vec![i.into(), (if i % 2 == 0 { "even" } else { "odd" }).into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // the reader answers the read, but the aggregation could also find the key in its own state
+    let e = g.explain(c, &"even".into()).unwrap();
+    assert!(e.hit);
+    assert!(!e.queries_ancestors());
+    assert_eq!(e.nodes.len(), 2);
+    assert_eq!(e.nodes[0].access, distributary::ReadAccess::Reader);
+    assert_eq!(e.nodes[0].rows, Some(1));
+    assert_eq!(e.nodes[1].node, c);
+    assert_eq!(e.nodes[1].access, distributary::ReadAccess::Index(vec![0]));
+    assert_eq!(e.nodes[1].rows, Some(1));
+    assert!(!e.nodes[1].consulted);
+    assert_eq!(e.consulted().len(), 1);
+
+    // the identity keeps no state, so its rows would have to come from the base node
+    let e = g.explain(i, &"odd".into()).unwrap();
+    assert_eq!(e.nodes[0].rows, Some(5));
+    assert_eq!(e.nodes[1].node, i);
+    assert_eq!(e.nodes[1].access, distributary::ReadAccess::Computed);
+    assert_eq!(e.nodes[1].columns, Some(vec![1]));
+    assert_eq!(e.nodes[2].node, a);
+    assert_eq!(e.nodes[2].columns, Some(vec![1]));
+    assert_eq!(e.nodes[2].depth, 2);
+    assert!(format!("{}", e).starts_with("read of "));
+
+    // views without readers cannot be explained
+    assert!(g.explain(a, &0.into()).is_none());
+}
+
+#[test]
+fn it_reads_own_writes() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, c) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "name"], distributary::Base::default());
+        let c = mig.add_ingredient("c",
+                                   &["name", "count"],
+                                   distributary::Aggregation::COUNT.over(a, 0, &[1]));
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, c)
+    };
+
+    let muta = g.get_mutator(a);
+    let getter = g.get_handle(c, |r| r.to_vec()).unwrap();
+    let timeout = time::Duration::from_secs(5);
+
+    let t1 = muta.put_with_token(vec![1.into(), "a".into()]);
+    let rs = getter.find_after(&"a".into(), t1, timeout).unwrap();
+    assert_eq!(*rs, vec![vec!["a".into(), 1.into()]]);
+
+    // later writes get later tokens, and reads wait for them too
+    let t2 = muta.put_with_token(vec![2.into(), "a".into()]);
+    assert!(t2 > t1);
+    let rs = getter.find_after(&"a".into(), t2, timeout).unwrap();
+    assert_eq!(*rs, vec![vec!["a".into(), 2.into()]]);
+
+    // a token for a write that has not happened yet is never reached
+    let later = distributary::WriteToken::from(t2.ts() + 100);
+    assert_eq!(getter.find_after(&"a".into(), later, time::Duration::from_millis(10)), Err(()));
+}
+
+#[test]
+fn it_reads_snapshots_of_several_views() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, c) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "name"], distributary::Base::default());
+        let c = mig.add_ingredient("c",
+                                   &["name", "count"],
+                                   distributary::Aggregation::COUNT.over(a, 0, &[1]));
+        mig.maintain(a, 0);
+        mig.maintain(c, 0);
+        mig.keep_versions(a, 4);
+        mig.keep_versions(c, 4);
+        mig.commit();
+        (a, c)
+    };
+
+    let muta = g.get_mutator(a);
+    let snapshot = g.get_snapshot_handle(&[a, c]).unwrap();
+    let timeout = time::Duration::from_secs(5);
+    let lookups = vec![(a, 2.into()), (c, "x".into())];
+
+    let t1 = muta.put_with_token(vec![1.into(), "x".into()]);
+    let t2 = muta.put_with_token(vec![2.into(), "x".into()]);
+
+    // the views agree with each other as of either write
+    let rs = snapshot.lookup(&lookups, t1, timeout).unwrap();
+    assert_eq!(rs, vec![vec![], vec![vec!["x".into(), 1.into()]]]);
+    let rs = snapshot.lookup(&lookups, t2, timeout).unwrap();
+    assert_eq!(rs,
+               vec![vec![vec![2.into(), "x".into()]], vec![vec!["x".into(), 2.into()]]]);
+
+    // and still do as of the first one after more writes
+    muta.put_with_token(vec![3.into(), "x".into()]);
+    let rs = snapshot.lookup(&lookups, t1, timeout).unwrap();
+    assert_eq!(rs, vec![vec![], vec![vec!["x".into(), 1.into()]]]);
+}
+
+#[test]
+fn it_updates_conditionally() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["id", "balance"], distributary::Base::new(vec![0]));
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+
+    let muta = g.get_mutator(a);
+    let aq = g.get_getter(a).unwrap();
+    let id: distributary::DataType = 1.into();
+
+    muta.put(vec![id.clone(), 10.into()]);
+
+    // the row holds the expected value, so the update goes through
+    assert_eq!(muta.update_if(vec![id.clone(), 15i64.into()], 1, 10), Ok(()));
+
+    // the row no longer holds the value, so the update is refused, and the current row returned
+    assert_eq!(muta.update_if(vec![id.clone(), 20.into()], 1, 10),
+               Err(distributary::ConditionFailed { current: Some(vec![id.clone(), 15.into()]) }));
+
+    // there is nothing to update for a key the base does not hold
+    assert_eq!(muta.update_if(vec![2.into(), 20.into()], 1, 10),
+               Err(distributary::ConditionFailed { current: None }));
+
+    // give it some time to propagate
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    assert_eq!(aq(&id), Ok(vec![vec![id.clone(), 15.into()]]));
+
+    // concurrent increments that retry from the current row never lose an update
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let muta = muta.clone();
+            let id = id.clone();
+            thread::spawn(move || for _ in 0..25 {
+                let mut balance: i64 = 15;
+                loop {
+                    let next = vec![id.clone(), (balance + 1).into()];
+                    match muta.update_if(next, 1, balance) {
+                        Ok(()) => break,
+                        Err(e) => balance = e.current.unwrap()[1].clone().into(),
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(aq(&id), Ok(vec![vec![id.clone(), 115.into()]]));
+}
+
+#[test]
+#[cfg(feature="netsoup")]
+fn it_serves_rpcs() {
+    use distributary::srv;
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let mut r = distributary::Recipe::from_str("CREATE TABLE a (id int, name varchar(255), \
+                                                PRIMARY KEY(id));")
+        .unwrap();
+    {
+        let mut mig = g.start_migration();
+        r.activate(&mut mig).unwrap();
+        mig.commit();
+    }
+
+    let addr = "127.0.0.1:7781".parse::<std::net::SocketAddr>().unwrap();
+    let _srv = srv::run_with_recipe(g, r, addr, 1);
+    let mut c = srv::Client::connect(addr).unwrap();
+
+    let a = c.view("a").unwrap().unwrap();
+    assert_eq!(c.columns(a).unwrap().len(), 2);
+    c.insert(a, vec![1.into(), "x".into()]).unwrap();
+    c.insert(a, vec![2.into(), "y".into()]).unwrap();
+    c.delete(a, vec![2.into()]).unwrap();
+
+    // add a view over the base through the server, and read from it
+    let added = c.extend_recipe("byname: SELECT a.id, a.name FROM a WHERE a.name = ?;")
+        .unwrap();
+    let q = added["byname"];
+    assert_eq!(c.view("byname").unwrap(), Some(q));
+
+    // give it some time to propagate
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    assert_eq!(c.query(q, "x".into()).unwrap(), vec![vec![1.into(), "x".into()]]);
+    assert!(c.query(q, "y".into()).unwrap().is_empty());
+
+    // requests for views that do not exist are refused
+    match c.insert(q + 100, vec![3.into(), "z".into()]) {
+        Err(distributary::srv::Error::App(srv::RpcError::NoSuchView(_))) => {}
+        r => panic!("unexpected response {:?}", r),
+    }
+
+    // as are rows that do not match the base's columns
+    match c.insert(a, vec![3.into()]) {
+        Err(distributary::srv::Error::App(srv::RpcError::BadRequest(_))) => {}
+        r => panic!("unexpected response {:?}", r),
+    }
+}
+
+#[test]
+#[cfg(feature="netsoup")]
+fn it_streams_subscriptions_over_rpc() {
+    use distributary::srv;
+    use distributary::StreamUpdate::*;
+    use std::sync::Arc;
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let addr = "127.0.0.1:7782".parse::<std::net::SocketAddr>().unwrap();
+    let _srv = srv::run(g, addr, 1);
+    let mut c = srv::Client::connect(addr).unwrap();
+
+    // the subscription starts out with everything written so far
+    let sub = c.subscribe(a.into()).unwrap();
+    assert_eq!(sub.snapshot, vec![vec![1.into(), 2.into()]]);
+
+    // and then receives everything written after it
+    muta.put(vec![3.into(), 4.into()]);
+    assert_eq!(c.updates(&sub).unwrap(),
+               vec![AddRow(Arc::new(vec![3.into(), 4.into()]))]);
+
+    // updates that arrive between calls are all returned by the next one
+    muta.put(vec![5.into(), 6.into()]);
+    muta.put(vec![7.into(), 8.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(c.updates(&sub).unwrap(),
+               vec![AddRow(Arc::new(vec![5.into(), 6.into()])),
+                    AddRow(Arc::new(vec![7.into(), 8.into()]))]);
+
+    // subscriptions end when unsubscribed from
+    c.unsubscribe(sub.clone()).unwrap();
+    match c.updates(&sub) {
+        Err(srv::Error::App(srv::RpcError::NoSuchSubscription(_))) => {}
+        r => panic!("unexpected response {:?}", r),
+    }
+}
+
+#[test]
+fn it_traces_writes() {
+    use distributary::TraceKind;
+    use std::sync::Arc;
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, b, c) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Base::default());
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 1]);
+        emits.insert(b, vec![0, 1]);
+        let u = distributary::Union::new(emits);
+        let c = mig.add_ingredient("c", &["a", "b"], u);
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, b, c)
+    };
+
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    let (tx, rx) = mpsc::channel();
+    muta.put(vec![1.into(), 2.into()]);
+    mutb.put_traced(vec![3.into(), 4.into()], distributary::Tracer::new(42, tx));
+    muta.put(vec![5.into(), 6.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let events: Vec<_> = rx.try_iter().collect();
+    assert!(events.iter().all(|e| e.id == 42));
+    // only the traced write is reported, from the base node to the reader
+    assert_eq!(events[0].node, b);
+    assert_eq!(events[0].kind, TraceKind::Base);
+    assert!(events.iter().all(|e| e.node != a));
+    assert!(events.iter().any(|e| e.node == c && e.kind == TraceKind::Operator));
+    let last = events.last().unwrap();
+    assert_eq!(last.kind, TraceKind::Reader);
+    assert_eq!(last.records,
+               vec![distributary::StreamUpdate::AddRow(Arc::new(vec![3.into(), 4.into()]))]);
+}
+
+#[test]
+fn it_works_with_range_lookups() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, range) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["score", "title"], distributary::Base::default());
+        let range = mig.maintain_ordered(a, 0);
+        mig.commit();
+        (a, range)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![7.into(), "x".into()]);
+    muta.put(vec![3.into(), "y".into()]);
+    muta.put(vec![5.into(), "z".into()]);
+    muta.put(vec![10.into(), "w".into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // keys in the range are returned in order
+    assert_eq!(range(&4.into(), &8.into()),
+               Ok(vec![vec![5.into(), "z".into()], vec![7.into(), "x".into()]]));
+    assert_eq!(range(&11.into(), &20.into()), Ok(vec![]));
+
+    // and single keys can still be read
+    let find = g.get_getter(a).unwrap();
+    assert_eq!(find(&3.into()), Ok(vec![vec![3.into(), "y".into()]]));
+    assert!(g.get_range_getter(a).is_some());
+}
+
+#[test]
+fn it_works_with_bounded_readers() {
+    use distributary::{Budget, Eviction, EvictionPolicy};
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let cq = mig.maintain(a, 0);
+        mig.set_eviction_policy(a,
+                                EvictionPolicy {
+                                    budget: Budget::Rows(2),
+                                    evict: Eviction::LeastRecentlyUsed,
+                                });
+        mig.commit();
+        (a, cq)
+    };
+
+    let muta = g.get_mutator(a);
+    for i in 0..3 {
+        muta.put(vec![i.into(), 0.into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // one key had to be evicted to stay within budget, and reading it fails
+    let results: Vec<_> = (0..3).map(|i| cq(&i.into())).collect();
+    assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+}
+
+#[test]
+fn it_tracks_column_types() {
+    use distributary::{Aggregation, Base, ColumnSpec, ColumnType};
+
+    let mut g = distributary::Blender::new();
+    let (a, c) = {
+        let mut mig = g.start_migration();
+        let columns = vec![ColumnSpec {
+                               ty: Some(ColumnType::Text),
+                               ..ColumnSpec::default()
+                           },
+                           ColumnSpec {
+                               ty: Some(ColumnType::Real),
+                               ..ColumnSpec::default()
+                           }];
+        let a = mig.add_ingredient("a", &["a", "b"], Base::default().with_columns(columns));
+        let c = mig.add_ingredient("c", &["a", "avg"], Aggregation::AVG.over(a, 1, &[0]));
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, c)
+    };
+
+    assert_eq!(g.column_types(a),
+               vec![Some(ColumnType::Text), Some(ColumnType::Real)]);
+    assert_eq!(g.column_types(c),
+               vec![Some(ColumnType::Text), Some(ColumnType::Real)]);
+}
+
+#[test]
+fn it_rejects_invalid_joins() {
+    use distributary::{Base, ColumnSpec, ColumnType, JoinBuilder};
+
+    let mut g = distributary::Blender::new();
+    let mut mig = g.start_migration();
+    let text = ColumnSpec {
+        ty: Some(ColumnType::Text),
+        ..ColumnSpec::default()
+    };
+    let a = mig.add_ingredient("a", &["id", "title"], Base::default());
+    let b = mig.add_ingredient("b",
+                               &["id", "author"],
+                               Base::default().with_columns(vec![ColumnSpec::default(), text]));
+
+    // b only has two columns, so the join is not added
+    let j = JoinBuilder::new(vec![(a, 0), (b, 2)]).from(a, vec![1, 0]).join(b, vec![1, 0]);
+    assert!(j.fields(&mig).is_err());
+    let e = mig.try_add_ingredient("j", &["id", "author"], j).unwrap_err();
+    assert!(e.contains("which only has 2 columns"), e);
+
+    // while a valid join has the names and types of the columns it emits
+    let j = JoinBuilder::new(vec![(a, 0), (b, 1)]).from(a, vec![1, 0]).join(b, vec![1, 0]);
+    assert_eq!(j.fields(&mig), Ok(vec!["id".to_owned(), "author".to_owned()]));
+    assert_eq!(j.column_types(&mig), Ok(vec![None, Some(ColumnType::Text)]));
+    assert!(mig.try_add_ingredient("j", &["id", "author"], j).is_ok());
+    mig.commit();
+}
+
+#[test]
+fn it_persists_base_nodes() {
+    use std::env;
+    use std::fs;
+    use distributary::PersistencePolicy;
+
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH).unwrap();
+    let policy = PersistencePolicy {
+        dir: env::temp_dir().join(format!("soup-persists-{}", now.subsec_nanos())),
+        snapshot_interval: time::Duration::from_millis(10),
+    };
+
+    let setup = || {
+        let mut g = distributary::Blender::new();
+        let (a, cq) = {
+            let mut mig = g.start_migration();
+            let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::new(vec![0]));
+            let cq = mig.maintain(a, 0);
+            mig.commit();
+            (a, cq)
+        };
+        (g, a, cq)
+    };
+
+    {
+        let (g, a, _) = setup();
+        assert_eq!(g.persist(a, policy.clone()).unwrap(), 0);
+
+        let muta = g.get_mutator(a);
+        muta.put(vec![1.into(), 2.into()]);
+        muta.put(vec![2.into(), 3.into()]);
+        muta.delete(vec![2.into()]);
+        assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+        // give the writes time to be logged
+        thread::sleep(time::Duration::from_millis(100));
+    }
+
+    // after a restart, the base node holds the same rows as before
+    let (g, a, cq) = setup();
+    assert_eq!(g.persist(a, policy.clone()).unwrap(), 1);
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+    assert_eq!(cq(&2.into()), Ok(vec![]));
+
+    fs::remove_dir_all(&policy.dir).unwrap();
+}
+
+#[test]
+fn it_traces_reads() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let cq = mig.maintain(a, 0);
+        mig.commit();
+        (a, cq)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![1.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // reads are not traced by default
+    assert_eq!(cq(&1.into()).unwrap().len(), 2);
+    assert_eq!(g.read_stats(a).unwrap().latency.count(), 0);
+
+    // but once sampling is enabled, getters obtained before and after are traced
+    g.trace_reads(a, 2);
+    let cq2 = g.get_getter(a).unwrap();
+    for _ in 0..2 {
+        cq(&1.into()).unwrap();
+        cq2(&2.into()).unwrap();
+    }
+
+    let stats = g.read_stats(a).unwrap();
+    assert_eq!(stats.latency.count(), 2);
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 0);
+    assert_eq!(stats.rows.max(), 2);
+    assert!(stats.bytes.max() > 0);
+}
+
+#[test]
+fn it_works_with_subscriptions() {
+    use std::sync::Arc;
+    use distributary::StreamUpdate::*;
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![2.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // the snapshot holds everything written so far
+    let sub = g.subscribe(a).unwrap();
+    let mut snapshot = sub.snapshot;
+    snapshot.sort();
+    assert_eq!(snapshot,
+               vec![vec![1.into(), 2.into()], vec![2.into(), 3.into()]]);
+
+    // and the stream everything written after it
+    muta.put(vec![3.into(), 4.into()]);
+    assert_eq!(sub.updates.recv(),
+               Ok(vec![AddRow(Arc::new(vec![3.into(), 4.into()]))]));
+    assert!(sub.updates.try_recv().is_err());
+
+    // nodes that are not maintained cannot be subscribed to
+    let b = {
+        let mut mig = g.start_migration();
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        b
+    };
+    assert!(g.subscribe(b).is_none());
+}
+
+#[test]
+fn it_works_with_sinks() {
+    use distributary::{Sink, SinkPolicy, StreamUpdate};
+    use std::sync::{Arc, Mutex};
+
+    // a sink that fails every other write
+    struct Flaky(Arc<Mutex<Vec<StreamUpdate>>>, bool);
+    impl Sink for Flaky {
+        fn write(&mut self, updates: &[StreamUpdate]) -> Result<(), String> {
+            self.1 = !self.1;
+            if self.1 {
+                return Err(String::from("try again"));
+            }
+            self.0.lock().unwrap().extend(updates.iter().cloned());
+            Ok(())
+        }
+    }
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let policy = SinkPolicy {
+            backoff: time::Duration::from_millis(1),
+            ..SinkPolicy::default()
+        };
+        mig.sink(a, Box::new(Flaky(written.clone(), false)), policy);
+        mig.commit();
+        a
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![1.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // the sink writes on its own thread, so give it a moment
+    let mut tries = 0;
+    while written.lock().unwrap().len() < 2 && tries < 100 {
+        thread::sleep(time::Duration::from_millis(10));
+        tries += 1;
+    }
+    assert_eq!(*written.lock().unwrap(),
+               vec![StreamUpdate::AddRow(Arc::new(vec![1.into(), 2.into()])),
+                    StreamUpdate::AddRow(Arc::new(vec![1.into(), 3.into()]))]);
+}
+
 #[test]
 fn shared_interdomain_ancestor() {
     // set up graph
@@ -193,53 +1036,370 @@ fn it_works_w_mat() {
     // give it some time to propagate
     thread::sleep(time::Duration::new(0, 10_000_000));
 
-    // check that value was updated again
-    let res = cq(&id).unwrap();
-    assert_eq!(res.len(), 6);
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 1.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 3.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 5.into()]));
-    assert!(res.iter().any(|r| r == &vec![id.clone(), 6.into()]));
+    // check that value was updated again
+    let res = cq(&id).unwrap();
+    assert_eq!(res.len(), 6);
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 1.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 3.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 5.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 6.into()]));
+}
+
+#[test]
+fn it_works_deletion() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, b, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![1]));
+        let b = mig.add_ingredient("b", &["_", "x", "y"], distributary::Base::new(vec![2]));
+
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 1]);
+        emits.insert(b, vec![1, 2]);
+        let u = distributary::Union::new(emits);
+        let c = mig.add_ingredient("c", &["x", "y"], u);
+        let cq = mig.stream(c);
+        mig.commit();
+        (a, b, cq)
+    };
+
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+
+    // send a value on a
+    muta.put(vec![1.into(), 2.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 2.into()].into()]));
+
+    // update value again
+    mutb.put(vec![0.into(), 1.into(), 4.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 4.into()].into()]));
+
+    // delete first value
+    use std::sync::Arc;
+    use distributary::StreamUpdate::*;
+    muta.delete(vec![2.into()]);
+    assert_eq!(cq.recv(),
+               Ok(vec![DeleteRow(Arc::new(vec![1.into(), 2.into()]))]));
+}
+
+#[test]
+#[should_panic(expected = "delete operations can only be applied to base nodes with key columns")]
+fn it_rejects_deletion_without_key() {
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::default());
+        mig.commit();
+        a
+    };
+
+    g.get_mutator(a).delete(vec![1.into()]);
+}
+
+#[test]
+fn it_works_without_sleeping() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, b, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::new(vec![0]));
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Base::new(vec![0]));
+
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 1]);
+        emits.insert(b, vec![0, 1]);
+        let u = distributary::Union::new(emits);
+        let c = mig.add_ingredient("c", &["a", "b"], u);
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, b, cq)
+    };
+
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    let id: distributary::DataType = 1.into();
+
+    muta.put(vec![id.clone(), 2.into()]);
+    mutb.put(vec![id.clone(), 4.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(10)));
+
+    let res = cq(&id).unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 2.into()]));
+    assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
+}
+
+#[test]
+fn it_works_truncation() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![0]));
+        let cq = mig.stream(a);
+        mig.commit();
+        (a, cq)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 2.into()].into()]));
+    muta.put(vec![3.into(), 4.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![3.into(), 4.into()].into()]));
+
+    // truncating should retract both rows
+    use std::sync::Arc;
+    use distributary::StreamUpdate::*;
+    g.truncate(a);
+    let mut deleted = cq.recv().unwrap();
+    deleted.sort_by_key(|u| match *u {
+        DeleteRow(ref r) => r[0].clone(),
+        AddRow(_) => unreachable!(),
+    });
+    assert_eq!(deleted,
+               vec![DeleteRow(Arc::new(vec![1.into(), 2.into()])),
+                    DeleteRow(Arc::new(vec![3.into(), 4.into()]))]);
+
+    // and the base should accept new writes afterwards
+    muta.put(vec![1.into(), 5.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 5.into()].into()]));
+}
+
+#[test]
+fn it_works_with_counting_readers() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, count) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![1]));
+        let count = mig.maintain_counts(a, 0);
+        mig.commit();
+        (a, count)
+    };
+    let contains = g.get_contains_getter(a).unwrap();
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 1.into()]);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![2.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    assert_eq!(count(&1.into()), Ok(2));
+    assert_eq!(count(&2.into()), Ok(1));
+    assert_eq!(contains(&2.into()), Ok(true));
+    assert_eq!(contains(&3.into()), Ok(false));
+
+    // deletions are reflected in the counts
+    muta.delete(vec![3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    assert_eq!(count(&2.into()), Ok(0));
+    assert_eq!(contains(&2.into()), Ok(false));
+}
+
+#[test]
+fn it_works_with_getter_handles() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::default());
+        mig.maintain(a, 0);
+        mig.commit();
+        a
+    };
+    let handle = g.get_handle(a, |r| {
+            let y: i64 = r[1].clone().into();
+            y
+        })
+        .unwrap()
+        .with_cache(16);
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![1.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // every thread gets its own handle
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let mut handle = handle.clone();
+            thread::spawn(move || {
+                (0..10)
+                    .map(|_| handle.lookup(&1.into()).unwrap().iter().sum::<i64>())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for r in readers {
+        assert_eq!(r.join().unwrap(), vec![5; 10]);
+    }
+
+    // cached rows are replaced once new writes are swapped in
+    let mut handle = handle;
+    assert_eq!(handle.lookup(&1.into()).unwrap().len(), 2);
+    muta.put(vec![1.into(), 4.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    assert_eq!(handle.lookup(&1.into()).unwrap().len(), 3);
+}
+
+#[test]
+fn it_isolates_domain_failures() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    g.isolate_domain_failures(true);
+    let (a, b, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![0]));
+        let b = mig.add_ingredient("b", &["x", "y"], distributary::Base::default());
+        let cq = mig.stream(b);
+        mig.commit();
+        (a, b, cq)
+    };
+    assert!(g.failed_domains().is_empty());
+
+    // a row without a value for the key column violates one of the base's invariants
+    let muta = g.get_mutator(a);
+    muta.put(Vec::<distributary::DataType>::new());
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    let failed = g.failed_domains();
+    assert_eq!(failed.len(), 1);
+    assert!(failed.values().next().unwrap().contains("index out of bounds"));
+
+    // writes to the failed domain are dropped
+    muta.put(vec![1.into(), 2.into()]);
+
+    // but the rest of the graph keeps working
+    let mutb = g.get_mutator(b);
+    mutb.put(vec![1.into(), 2.into()]);
+    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 2.into()].into()]));
+    assert_eq!(g.failed_domains().len(), 1);
+}
+
+#[test]
+fn it_reports_health() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    g.isolate_domain_failures(true);
+    let (a, b) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![0]));
+        let b = mig.add_ingredient("b", &["x", "y"], distributary::Base::new(vec![0]));
+        mig.maintain(a, 0);
+        mig.commit();
+        (a, b)
+    };
+
+    let health = g.health(time::Duration::from_secs(5));
+    assert!(health.is_healthy());
+    assert_eq!(health.domains.len(), 2);
+    assert!(health.domains.values().all(|d| *d == distributary::DomainHealth::Alive));
+
+    // the reader should have swapped in the write recently
+    g.get_mutator(a).put(vec![1.into(), 2.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    let health = g.health(time::Duration::from_secs(5));
+    assert_eq!(health.readers.len(), 1);
+    assert!(health.readers[&a].unwrap() < time::Duration::from_secs(5));
+
+    // a failed domain makes the graph unhealthy
+    g.get_mutator(b).put(Vec::<distributary::DataType>::new());
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    let health = g.health(time::Duration::from_secs(5));
+    assert!(!health.is_healthy());
+    assert_eq!(health.domains
+                   .values()
+                   .filter(|d| if let distributary::DomainHealth::Failed(_) = **d {
+                       true
+                   } else {
+                       false
+                   })
+                   .count(),
+               1);
+}
+
+#[test]
+fn it_reports_rejected_writes() {
+    use distributary::{Base, ColumnSpec, KeyConflict, RejectReason, Rejection};
+
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let columns = vec![ColumnSpec::default(),
+                           ColumnSpec {
+                               nullable: false,
+                               ..ColumnSpec::default()
+                           }];
+        let base = Base::new(vec![0]).with_key_conflict(KeyConflict::Reject).with_columns(columns);
+        let a = mig.add_ingredient("a", &["x", "y"], base);
+        mig.commit();
+        a
+    };
+
+    // the channel can be obtained after the base node has been moved into the graph
+    let rejected = g.rejections(a);
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![1.into(), 3.into()]);
+    muta.put(vec![2.into()]);
+    muta.delete(vec![3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // each rejection carries the rejected row, which ties it to the write that caused it
+    let rejected: Vec<_> = rejected.try_iter().collect();
+    assert_eq!(rejected.len(), 3);
+    assert!(rejected.contains(&Rejection {
+        row: vec![1.into(), 3.into()],
+        reason: RejectReason::DuplicateKey(vec![1.into()]),
+    }));
+    assert!(rejected.contains(&Rejection {
+        row: vec![2.into()],
+        reason: RejectReason::Null(1),
+    }));
+    assert!(rejected.contains(&Rejection {
+        row: vec![3.into()],
+        reason: RejectReason::NoSuchKey,
+    }));
 }
 
 #[test]
-fn it_works_deletion() {
+fn base_write_notifications() {
     // set up graph
     let mut g = distributary::Blender::new();
-    let (a, b, cq) = {
+    let (a, aw) = {
         let mut mig = g.start_migration();
-        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![1]));
-        let b = mig.add_ingredient("b", &["_", "x", "y"], distributary::Base::new(vec![2]));
-
-        let mut emits = HashMap::new();
-        emits.insert(a, vec![0, 1]);
-        emits.insert(b, vec![1, 2]);
-        let u = distributary::Union::new(emits);
-        let c = mig.add_ingredient("c", &["x", "y"], u);
-        let cq = mig.stream(c);
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::new(vec![0]));
+        let aw = mig.on_write(a);
         mig.commit();
-        (a, b, cq)
+        (a, aw)
     };
 
-    let muta = g.get_mutator(a);
-    let mutb = g.get_mutator(b);
+    // listeners can also be registered after the base has been committed
+    let aw2 = g.on_write(a);
 
-    // send a value on a
+    let muta = g.get_mutator(a);
     muta.put(vec![1.into(), 2.into()]);
-    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 2.into()].into()]));
-
-    // update value again
-    mutb.put(vec![0.into(), 1.into(), 4.into()]);
-    assert_eq!(cq.recv(), Ok(vec![vec![1.into(), 4.into()].into()]));
 
-    // delete first value
     use std::sync::Arc;
     use distributary::StreamUpdate::*;
-    muta.delete(vec![2.into()]);
-    assert_eq!(cq.recv(),
-               Ok(vec![DeleteRow(Arc::new(vec![1.into(), 2.into()]))]));
+    let w = aw.recv().unwrap();
+    assert_eq!(w.records, vec![AddRow(Arc::new(vec![1.into(), 2.into()]))]);
+    assert_eq!(w.ts, None);
+    assert_eq!(aw2.recv().unwrap(), w);
+
+    // deletions are reported as the rows they removed
+    muta.delete(vec![1.into()]);
+    let w = aw.recv().unwrap();
+    assert_eq!(w.records, vec![DeleteRow(Arc::new(vec![1.into(), 2.into()]))]);
+
+    // transactional writes carry their timestamp
+    let ts = muta.transactional_put(vec![3.into(), 4.into()], distributary::Token::empty())
+        .unwrap();
+    let w = aw.recv().unwrap();
+    assert_eq!(w.records, vec![AddRow(Arc::new(vec![3.into(), 4.into()]))]);
+    assert_eq!(w.ts, Some(ts));
 }
 
 #[test]
@@ -513,6 +1673,50 @@ fn empty_migration() {
     assert!(res.iter().any(|r| r == &vec![id.clone(), 4.into()]));
 }
 
+#[test]
+fn migration_summary_diff() {
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        a
+    };
+
+    let before = g.summary();
+    assert!(before.diff(&g.summary()).is_empty());
+
+    let mut mig = g.start_migration();
+    let mut emits = HashMap::new();
+    emits.insert(a, vec![0, 1]);
+    let b = mig.add_ingredient("b", &["a", "b"], distributary::Union::new(emits));
+    let diff = before.diff(&mig.summary());
+
+    // only the new node and the edge to it should show up
+    assert_eq!(diff.added_nodes.len(), 1);
+    assert_eq!(diff.added_nodes[0].0, b);
+    assert_eq!(diff.added_nodes[0].1.name, "b");
+    assert_eq!(diff.added_nodes[0].1.domain, None);
+    assert_eq!(diff.added_edges, vec![(a, b)]);
+    assert!(diff.removed_nodes.is_empty());
+    assert!(diff.changed_nodes.is_empty());
+    assert!(diff.removed_edges.is_empty());
+    assert!(diff.added_indexes.is_empty());
+    mig.commit();
+
+    // a migration that adds an aggregation also adds an index on its groups
+    let before = g.summary();
+    let mut mig = g.start_migration();
+    let c = mig.add_ingredient("c",
+                               &["a", "n"],
+                               distributary::Aggregation::COUNT.over(b, 1, &[0]));
+    let diff = before.diff(&mig.summary());
+    assert_eq!(diff.added_indexes, vec![(c, vec![0])]);
+    assert!(diff.removed_indexes.is_empty());
+    mig.commit();
+    assert!(g.summary().indexes.contains(&(c, vec![0])));
+}
+
 #[test]
 fn simple_migration() {
     let id: distributary::DataType = 1.into();
@@ -557,6 +1761,137 @@ fn simple_migration() {
     assert_eq!(bq(&id), Ok(vec![vec![1.into(), 4.into()]]));
 }
 
+#[test]
+fn two_phase_migration() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        a
+    };
+    let muta = g.get_mutator(a);
+    let ts = muta.transactional_put(vec![1.into(), 2.into()], distributary::Token::empty())
+        .unwrap();
+
+    let aq = {
+        let mut mig = g.start_migration();
+        let aq = mig.transactional_maintain(a, 0);
+        let prepared = mig.prepare();
+
+        // the new view has been populated, but only takes effect later
+        assert!(prepared.timestamp() > ts);
+        assert_eq!(aq(&1.into()).unwrap().0, vec![vec![1.into(), 2.into()]]);
+
+        prepared.commit();
+        aq
+    };
+
+    // writes after the commit should reach the new view
+    muta.transactional_put(vec![1.into(), 3.into()], distributary::Token::empty()).unwrap();
+
+    // give it some time to propagate
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    let res = aq(&1.into()).unwrap().0;
+    assert_eq!(res.len(), 2);
+    assert!(res.contains(&vec![1.into(), 3.into()]));
+}
+
+#[test]
+fn two_phase_migration_commits_on_drop() {
+    use std::panic;
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        a
+    };
+    let muta = g.get_mutator(a);
+
+    // the caller panics between the two phases, dropping the prepared migration
+    let aq = {
+        let mut mig = g.start_migration();
+        let aq = mig.transactional_maintain(a, 0);
+        let prepared = mig.prepare();
+        let hook = panic::catch_unwind(panic::AssertUnwindSafe(move || {
+            let _prepared = prepared;
+            panic!("rollout failed");
+        }));
+        assert!(hook.is_err());
+        aq
+    };
+
+    // transactional writes must not be held back forever
+    muta.transactional_put(vec![1.into(), 3.into()], distributary::Token::empty()).unwrap();
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(aq(&1.into()).unwrap().0, vec![vec![1.into(), 3.into()]]);
+}
+
+#[test]
+fn manual_reader_swaps() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, aq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let aq = mig.maintain(a, 0);
+        mig.set_swap_policy(a, distributary::SwapPolicy::Manual);
+        mig.commit();
+        (a, aq)
+    };
+    let muta = g.get_mutator(a);
+
+    // send a value on a
+    muta.put(vec![1.into(), 2.into()]);
+
+    // give it some time to propagate
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // the write should not be visible until we ask for it
+    assert_eq!(aq(&1.into()), Ok(vec![]));
+    g.swap_reader(a);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(aq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+}
+
+#[test]
+fn changed_reader_swap_policy() {
+    let mut g = distributary::Blender::new();
+    let (a, aq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let aq = mig.maintain(a, 0);
+        mig.set_swap_policy(a, distributary::SwapPolicy::Interval(time::Duration::from_millis(5)));
+        mig.commit();
+        (a, aq)
+    };
+    let muta = g.get_mutator(a);
+
+    // the write is swapped in by the periodic swaps
+    muta.put(vec![1.into(), 2.into()]);
+    thread::sleep(time::Duration::from_millis(50));
+    assert_eq!(aq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+
+    // changing the policy affects the existing reader, and stops the periodic swaps
+    {
+        let mut mig = g.start_migration();
+        mig.set_swap_policy(a, distributary::SwapPolicy::Manual);
+        mig.commit();
+    }
+    muta.put(vec![1.into(), 3.into()]);
+    thread::sleep(time::Duration::from_millis(50));
+    assert_eq!(aq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+
+    g.swap_reader(a);
+    thread::sleep(time::Duration::from_millis(10));
+    assert_eq!(aq(&1.into()).unwrap().len(), 2);
+}
+
 #[test]
 fn transactional_migration() {
     // set up graph
@@ -751,6 +2086,65 @@ fn domain_amend_migration() {
     assert_eq!(cq.recv(), Ok(vec![vec![id.clone(), 4.into()].into()]));
 }
 
+#[test]
+fn existing_view_materialization() {
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, i, domain) = {
+        let mut mig = g.start_migration();
+        let domain = mig.add_domain();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let i = mig.add_ingredient("i", &["a", "b"], distributary::Identity::new(a));
+        mig.assign_domain(a, domain);
+        mig.assign_domain(i, domain);
+        mig.commit();
+        (a, i, domain)
+    };
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 2.into()]);
+
+    // give it some time to propagate
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // join both a, which is materialized but not indexed by b, and i, which is not materialized
+    // at all, with a new base on b
+    let (c, aq, iq) = {
+        let mut mig = g.start_migration();
+        let c = mig.add_ingredient("c", &["c", "d"], distributary::Base::default());
+        mig.assign_domain(c, domain);
+
+        let mut joined = Vec::new();
+        for &(name, parent) in &[("ja", a), ("ji", i)] {
+            let j = distributary::JoinBuilder::new(vec![(parent, 0), (parent, 1), (c, 1)])
+                .from(parent, vec![0, 1])
+                .join(c, vec![1, 0]);
+            let j = mig.add_ingredient(name, &["a", "b", "d"], j);
+            mig.assign_domain(j, domain);
+            joined.push(mig.maintain(j, 0));
+        }
+        mig.commit();
+
+        let iq = joined.pop().unwrap();
+        let aq = joined.pop().unwrap();
+        (c, aq, iq)
+    };
+    let mutc = g.get_mutator(c);
+
+    // the joins must find the record that was in a before the migration
+    mutc.put(vec![2.into(), "x".into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    let expected = vec![vec![1.into(), 2.into(), "x".into()]];
+    assert_eq!(aq(&1.into()), Ok(expected.clone()));
+    assert_eq!(iq(&1.into()), Ok(expected));
+
+    // and both keep up with later records
+    muta.put(vec![3.into(), 2.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    let expected = vec![vec![3.into(), 2.into(), "x".into()]];
+    assert_eq!(aq(&3.into()), Ok(expected.clone()));
+    assert_eq!(iq(&3.into()), Ok(expected));
+}
+
 #[test]
 fn state_replay_migration_stream() {
     // we're going to set up a migration test that requires replaying existing state
@@ -827,39 +2221,183 @@ fn migration_depends_on_unchanged_domain() {
     // this is tricky, because the system must realize that n is materialized, even though it
     // normally wouldn't even look at that part of the data flow graph!
 
-    let mut g = distributary::Blender::new();
-    let left = {
+    let mut g = distributary::Blender::new();
+    let left = {
+        let mut mig = g.start_migration();
+
+        // base node, so will be materialized
+        let left = mig.add_ingredient("foo", &["a", "b"], distributary::Base::default());
+
+        // node in different domain that depends on foo causes egress to be added
+        mig.add_ingredient("bar", &["a", "b"], distributary::Identity::new(left));
+
+        // start processing
+        mig.commit();
+        left
+    };
+
+    let mut mig = g.start_migration();
+
+    // joins require their inputs to be materialized
+    // we need a new base as well so we can actually make a join
+    let tmp = mig.add_ingredient("tmp", &["a", "b"], distributary::Base::default());
+    let j = distributary::JoinBuilder::new(vec![(left, 0), (tmp, 1)])
+        .from(left, vec![1, 0])
+        .join(tmp, vec![1, 0]);
+    let j = mig.add_ingredient("join", &["a", "b"], j);
+
+    // we assign tmp and j to the same domain just to make the graph less complex
+    let d = mig.add_domain();
+    mig.assign_domain(tmp, d);
+    mig.assign_domain(j, d);
+
+    // start processing
+    mig.commit();
+    assert!(true);
+}
+
+#[test]
+fn removal_migration() {
+    let id: distributary::DataType = 1.into();
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, b, c, bq, cq, domain) = {
+        let mut mig = g.start_migration();
+        let domain = mig.add_domain();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Identity::new(a));
+        mig.assign_domain(b, domain);
+        let c = mig.add_ingredient("c", &["a", "b"], distributary::Identity::new(a));
+        let bq = mig.maintain(b, 0);
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, b, c, bq, cq, domain)
+    };
+    let muta = g.get_mutator(a);
+    muta.put(vec![id.clone(), 2.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(bq(&id), Ok(vec![vec![1.into(), 2.into()]]));
+
+    // remove b, and add a new node to the domain it was in
+    let dq = {
+        let mut mig = g.start_migration();
+        mig.remove_node(b);
+        let d = mig.add_ingredient("d", &["a", "b"], distributary::Identity::new(a));
+        mig.assign_domain(d, domain);
+        let dq = mig.maintain(d, 0);
+        mig.commit();
+        dq
+    };
+    assert!(g.outputs().iter().all(|&(n, _, _)| n != b));
+    assert!(g.outputs().iter().any(|&(n, _, _)| n == c));
+    assert!(g.get_getter(b).is_none());
+
+    // the rest of the graph keeps working
+    muta.put(vec![id.clone(), 4.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(cq(&id).unwrap().len(), 2);
+    assert!(dq(&id).unwrap().contains(&vec![id.clone(), 4.into()]));
+}
+
+#[test]
+#[should_panic]
+fn removal_of_depended_on_node() {
+    let mut g = distributary::Blender::new();
+    let b = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Identity::new(a));
+        mig.add_ingredient("c", &["a", "b"], distributary::Identity::new(b));
+        mig.commit();
+        b
+    };
+
+    let mut mig = g.start_migration();
+    mig.remove_node(b);
+    mig.commit();
+}
+
+#[test]
+fn column_addition_migration() {
+    let id: distributary::DataType = 1.into();
+
+    // set up graph
+    let mut g = distributary::Blender::new();
+    let (a, bq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::new(vec![0]));
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Permute::new(a, &[0, 1]));
+        let bq = mig.maintain(b, 0);
+        mig.commit();
+        (a, bq)
+    };
+    let muta = g.get_mutator(a);
+    muta.put(vec![id.clone(), 2.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // add a column, and a view that uses it
+    let cq = {
         let mut mig = g.start_migration();
+        assert_eq!(mig.add_column(a, "c", 3.into()), 2);
+        let c = mig.add_ingredient("c", &["a", "c"], distributary::Permute::new(a, &[0, 2]));
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        cq
+    };
+    assert_eq!(g.inputs()[0].1.fields().len(), 3);
 
-        // base node, so will be materialized
-        let left = mig.add_ingredient("foo", &["a", "b"], distributary::Base::default());
+    // existing rows are padded with the default, and so are writes that leave out the column
+    assert_eq!(cq(&id), Ok(vec![vec![1.into(), 3.into()]]));
+    muta.put(vec![2.into(), 4.into()]);
+    muta.put(vec![3.into(), 5.into(), 6.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(cq(&2.into()), Ok(vec![vec![2.into(), 3.into()]]));
+    assert_eq!(cq(&3.into()), Ok(vec![vec![3.into(), 6.into()]]));
 
-        // node in different domain that depends on foo causes egress to be added
-        mig.add_ingredient("bar", &["a", "b"], distributary::Identity::new(left));
+    // while the view that predates the column is unaffected
+    assert_eq!(bq(&id), Ok(vec![vec![1.into(), 2.into()]]));
+    assert_eq!(bq(&3.into()), Ok(vec![vec![3.into(), 5.into()]]));
 
-        // start processing
+    // the padded rows are also the ones that are deleted
+    muta.delete(vec![id.clone()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(bq(&id), Ok(vec![]));
+    assert_eq!(cq(&id), Ok(vec![]));
+}
+
+#[test]
+fn aborted_migration() {
+    let mut g = distributary::Blender::new();
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
         mig.commit();
-        left
+        a
     };
+    let before = g.summary();
 
-    let mut mig = g.start_migration();
-
-    // joins require their inputs to be materialized
-    // we need a new base as well so we can actually make a join
-    let tmp = mig.add_ingredient("tmp", &["a", "b"], distributary::Base::default());
-    let j = distributary::JoinBuilder::new(vec![(left, 0), (tmp, 1)])
-        .from(left, vec![1, 0])
-        .join(tmp, vec![1, 0]);
-    let j = mig.add_ingredient("join", &["a", "b"], j);
-
-    // we assign tmp and j to the same domain just to make the graph less complex
-    let d = mig.add_domain();
-    mig.assign_domain(tmp, d);
-    mig.assign_domain(j, d);
+    // nothing the aborted migration did remains
+    {
+        let mut mig = g.start_migration();
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Identity::new(a));
+        mig.maintain(b, 0);
+        mig.add_column(a, "c", 0.into());
+        mig.abort();
+    }
+    assert_eq!(g.summary(), before);
 
-    // start processing
-    mig.commit();
-    assert!(true);
+    // and later migrations work as usual
+    let bq = {
+        let mut mig = g.start_migration();
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Identity::new(a));
+        let bq = mig.maintain(b, 0);
+        mig.commit();
+        bq
+    };
+    g.get_mutator(a).put(vec![1.into(), 2.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(bq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
 }
 
 #[test]
@@ -1105,6 +2643,490 @@ fn state_replay_migration_query() {
 
     // there are (/should be) no records with x == 3
     assert!(out(&3.into()).unwrap().is_empty());
+
+    // the replay that populated the reader should have been recorded
+    let replays = g.get_statistics().replays;
+    assert!(!replays.is_empty());
+    assert!(replays.iter().any(|r| r.records > 0));
+    assert!(replays.iter().all(|r| (r.records == 0) == (r.bytes == 0)));
+}
+
+#[test]
+fn state_replay_from_reader() {
+    // the identity below is not materialized, but its reader is. when the count is added, it
+    // should be populated from the reader's state rather than from the base node.
+    let mut g = distributary::Blender::new();
+    g.prefer_replay_source(distributary::ReplaySource::PreferReader);
+    let (a, i) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["x", "y"], distributary::Base::default());
+        let i = mig.add_ingredient("i", &["x", "y"], distributary::Identity::new(a));
+        mig.maintain(i, 0);
+        mig.commit();
+        (a, i)
+    };
+
+    let muta = g.get_mutator(a);
+    muta.put(vec![1.into(), 1.into()]);
+    muta.put(vec![1.into(), 2.into()]);
+    muta.put(vec![2.into(), 3.into()]);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    let count = {
+        let mut mig = g.start_migration();
+        let c = mig.add_ingredient("c",
+                                   &["x", "n"],
+                                   distributary::Aggregation::COUNT.over(i, 1, &[0]));
+        let count = mig.maintain(c, 0);
+        mig.commit();
+        count
+    };
+
+    assert_eq!(count(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+    assert_eq!(count(&2.into()), Ok(vec![vec![2.into(), 1.into()]]));
+
+    let replays = g.get_statistics().replays;
+    assert!(replays.iter().any(|r| r.path[0] == i));
+    assert!(replays.iter().all(|r| r.path[0] != a));
+}
+
+#[test]
+fn join_replay_ancestor_choice() {
+    let mut g = distributary::Blender::new();
+    let (a, b, domain) = {
+        let mut mig = g.start_migration();
+        let domain = mig.add_domain();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "c"], distributary::Base::default());
+        mig.assign_domain(a, domain);
+        mig.assign_domain(b, domain);
+        mig.commit();
+        (a, b, domain)
+    };
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    for i in 0..3 {
+        muta.put(vec![1.into(), i.into()]);
+    }
+    mutb.put(vec![1.into(), "x".into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // b holds fewer records than a, so an inner join is replayed through b, unless told otherwise
+    let (j, jq, k, kq) = {
+        let mut mig = g.start_migration();
+        let mut joins = Vec::new();
+        for name in &["j", "k"] {
+            let j = distributary::JoinBuilder::new(vec![(a, 0), (a, 1), (b, 1)])
+                .from(a, vec![1, 0])
+                .join(b, vec![1, 0]);
+            let j = mig.add_ingredient(*name, &["a", "b", "c"], j);
+            mig.assign_domain(j, domain);
+            joins.push((j, mig.maintain(j, 0)));
+        }
+        let (k, kq) = joins.pop().unwrap();
+        let (j, jq) = joins.pop().unwrap();
+        mig.replay_through(k, a);
+        mig.commit();
+        (j, jq, k, kq)
+    };
+
+    assert_eq!(jq(&1.into()).unwrap().len(), 3);
+    assert_eq!(kq(&1.into()).unwrap().len(), 3);
+
+    let replays = g.get_statistics().replays;
+    assert!(replays.iter().any(|r| r.path[0] == b && r.path.contains(&j)));
+    assert!(replays.iter().all(|r| r.path[0] != a || !r.path.contains(&j)));
+    assert!(replays.iter().any(|r| r.path[0] == a && r.path.contains(&k)));
+    assert!(replays.iter().all(|r| r.path[0] != b || !r.path.contains(&k)));
+}
+
+#[test]
+fn concurrent_union_replays() {
+    let mut g = distributary::Blender::new();
+    let (a, b) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        (a, b)
+    };
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    for i in 0..10 {
+        muta.put(vec![1.into(), i.into()]);
+        mutb.put(vec![1.into(), (i + 10).into()]);
+    }
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // the union has to be filled from both its ancestors
+    let (u, uq) = {
+        let mut mig = g.start_migration();
+        let mut emits = HashMap::new();
+        emits.insert(a, vec![0, 1]);
+        emits.insert(b, vec![0, 1]);
+        let u = mig.add_ingredient("u", &["a", "b"], distributary::Union::new(emits));
+        let uq = mig.maintain(u, 0);
+        mig.commit();
+        (u, uq)
+    };
+
+    // writes made after the replays are applied exactly once
+    muta.put(vec![1.into(), 20.into()]);
+    mutb.put(vec![1.into(), 21.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    let mut res = uq(&1.into()).unwrap();
+    res.sort();
+    let expected: Vec<Vec<distributary::DataType>> =
+        (0..22).map(|i| vec![1.into(), i.into()]).collect();
+    assert_eq!(res, expected);
+
+    let replays = g.get_statistics().replays;
+    assert!(replays.iter().any(|r| r.path[0] == a && r.path.contains(&u)));
+    assert!(replays.iter().any(|r| r.path[0] == b && r.path.contains(&u)));
+}
+
+#[test]
+fn paced_replay() {
+    let mut g = distributary::Blender::new();
+    g.pace_replays(distributary::ReplayPacing {
+        batch_size: 7,
+        interleave: distributary::ReplayInterleave::Pause(time::Duration::new(0, 1_000_000)),
+    });
+    let a = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        a
+    };
+    let muta = g.get_mutator(a);
+    for i in 0..100 {
+        muta.put(vec![(i % 3).into(), i.into()]);
+    }
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    // the reader is filled from a's state in many small batches
+    let aq = {
+        let mut mig = g.start_migration();
+        let aq = mig.maintain(a, 0);
+        mig.commit();
+        aq
+    };
+    muta.put(vec![0.into(), 100.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    assert_eq!(aq(&0.into()).unwrap().len(), 35);
+    assert_eq!(aq(&1.into()).unwrap().len(), 33);
+    assert_eq!(aq(&2.into()).unwrap().len(), 33);
+}
+
+#[test]
+fn migration_plan() {
+    let mut g = distributary::Blender::new();
+    let (a, b) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "b"], distributary::Base::default());
+        mig.commit();
+        (a, b)
+    };
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    for i in 0..3 {
+        muta.put(vec![1.into(), i.into()]);
+    }
+    mutb.put(vec![1.into(), 3.into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+
+    let mut mig = g.start_migration();
+    let mut emits = HashMap::new();
+    emits.insert(a, vec![0, 1]);
+    emits.insert(b, vec![0, 1]);
+    let u = mig.add_ingredient("u", &["a", "b"], distributary::Union::new(emits));
+    let uq = mig.maintain(u, 0);
+
+    // the union's reader is filled from both bases, and u ends up in a new domain
+    let plan = mig.plan();
+    assert_eq!(plan.replays.len(), 2);
+    assert_eq!(plan.rows(), 4);
+    for r in &plan.replays {
+        assert!(r.path[0] == a || r.path[0] == b);
+        assert!(r.path.contains(&u));
+        assert_eq!(r.domains.len(), 2);
+        assert!(r.domains[0].is_some());
+        assert_eq!(r.domains[1], None);
+    }
+
+    // planning does not change what the migration does
+    mig.commit();
+    assert_eq!(uq(&1.into()).unwrap().len(), 4);
+    assert_eq!(g.get_statistics().replays.len(), 2);
+}
+
+#[test]
+fn domain_placement() {
+    let mut g = distributary::Blender::new();
+    g.place_with(Box::new(distributary::ParentDomain));
+    let (a, b, j, c, ia, ib) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], distributary::Base::default());
+        let b = mig.add_ingredient("b", &["a", "c"], distributary::Base::default());
+        let j = distributary::JoinBuilder::new(vec![(a, 0), (a, 1), (b, 1)])
+            .from(a, vec![1, 0])
+            .join(b, vec![1, 0]);
+        let j = mig.add_ingredient("j", &["a", "b", "c"], j);
+        let c = mig.add_ingredient("c",
+                                   &["a", "count"],
+                                   distributary::Aggregation::COUNT.over(j, 1, &[0]));
+        let ia = mig.add_ingredient("ia", &["a", "b"], distributary::Identity::new(a));
+        let ib = mig.add_ingredient("ib", &["a", "c"], distributary::Identity::new(b));
+        mig.isolate(c);
+        mig.colocate(&[ia, ib]);
+        mig.maintain(c, 0);
+        mig.commit();
+        (a, b, j, c, ia, ib)
+    };
+
+    let summary = g.summary();
+    let domain = |n| summary.nodes[&n].domain.unwrap();
+    assert!(domain(a) != domain(b));
+    assert_eq!(domain(j), domain(a));
+    assert!(domain(c) != domain(a) && domain(c) != domain(b));
+    assert_eq!(domain(ia), domain(ib));
+    assert!(domain(ia) == domain(a) || domain(ia) == domain(b));
+}
+
+#[test]
+fn move_nodes_between_domains() {
+    use distributary::{Base, JoinBuilder, Aggregation};
+
+    let mut g = distributary::Blender::new();
+    let (a, b, j, c, jq, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], Base::default());
+        let b = mig.add_ingredient("b", &["a", "c"], Base::default());
+        let d = mig.add_domain();
+        let j = JoinBuilder::new(vec![(a, 0), (a, 1), (b, 1)])
+            .from(a, vec![1, 0])
+            .join(b, vec![1, 0]);
+        let j = mig.add_ingredient("j", &["a", "b", "c"], j);
+        let c = mig.add_ingredient("c", &["a", "count"], Aggregation::COUNT.over(j, 1, &[0]));
+        mig.assign_domain(j, d);
+        mig.assign_domain(c, d);
+        let jq = mig.maintain(j, 0);
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, b, j, c, jq, cq)
+    };
+    let muta = g.get_mutator(a);
+    let mutb = g.get_mutator(b);
+    muta.put(vec![1.into(), "a".into()]);
+    muta.put(vec![2.into(), "b".into()]);
+    mutb.put(vec![1.into(), "x".into()]);
+    mutb.put(vec![1.into(), "y".into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+
+    // split the aggregation off into a domain of its own
+    {
+        let mut mig = g.start_migration();
+        let d = mig.add_domain();
+        mig.move_nodes(&[c], d);
+        mig.commit();
+    }
+    let summary = g.summary();
+    let domain = |summary: &distributary::GraphSummary, n| summary.nodes[&n].domain.unwrap();
+    assert!(domain(&summary, c) != domain(&summary, j));
+
+    // the moved node keeps its state, and sees later writes exactly once
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 2.into()]]));
+    mutb.put(vec![1.into(), "z".into()]);
+    mutb.put(vec![2.into(), "z".into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 3.into()]]));
+    assert_eq!(cq(&2.into()), Ok(vec![vec![2.into(), 1.into()]]));
+
+    // merge the join into the aggregation's domain, which it feeds
+    {
+        let (from, into) = (domain(&summary, j).into(), domain(&summary, c).into());
+        let mut mig = g.start_migration();
+        mig.merge_domains(from, into);
+        mig.commit();
+    }
+    let summary = g.summary();
+    assert_eq!(domain(&summary, j), domain(&summary, c));
+
+    mutb.put(vec![1.into(), "w".into()]);
+    muta.put(vec![2.into(), "c".into()]);
+    thread::sleep(time::Duration::new(0, 10_000_000));
+    assert_eq!(jq(&1.into()).unwrap().len(), 4);
+    assert_eq!(jq(&2.into()).unwrap().len(), 2);
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 4.into()]]));
+    assert_eq!(cq(&2.into()), Ok(vec![vec![2.into(), 2.into()]]));
+}
+
+#[test]
+fn sharded_base_and_operators() {
+    use std::collections::HashSet;
+    use distributary::{Base, JoinBuilder, Aggregation, Sharded};
+
+    let mut g = distributary::Blender::new();
+    let (article, vote, vq, cq, jq) = {
+        let mut mig = g.start_migration();
+        let article = mig.add_ingredient("article", &["id", "title"], Base::default());
+        let vote = mig.add_sharded_base("vote", &["user", "id"], Base::default(), 0, 4);
+
+        // counting votes per article needs the votes split up by article instead of by user
+        let vc = mig.add_sharded("vc", &["id", "votes"], &[(&vote, 1)], 0, |ps| {
+            Aggregation::COUNT.over(ps[0], 0, &[1])
+        });
+
+        // the articles are not sharded, so each shard of the join gets the ones it needs
+        let articles = Sharded::unsharded(article);
+        let fields = &["id", "title", "votes"];
+        let awv = mig.add_sharded("awv", fields, &[(&vc, 0), (&articles, 0)], 0, |ps| {
+            JoinBuilder::new(vec![(ps[0], 0), (ps[1], 1), (ps[0], 1)])
+                .from(ps[0], vec![1, 0])
+                .join(ps[1], vec![1, 0])
+        });
+
+        let vq = mig.maintain_sharded(&vote, 1);
+        let cq = mig.maintain_sharded(&vc, 0);
+        let jq = mig.maintain_sharded(&awv, 0);
+        mig.commit();
+        (article, vote, vq, cq, jq)
+    };
+
+    // every base shard is in a domain of its own
+    let summary = g.summary();
+    let domains: HashSet<_> = vote.shards()
+        .iter()
+        .map(|s| summary.nodes[s].domain.unwrap())
+        .collect();
+    assert_eq!(domains.len(), 4);
+
+    let muta = g.get_mutator(article);
+    let mutv = g.get_sharded_mutator(&vote);
+    muta.put(vec![1.into(), "a".into()]);
+    muta.put(vec![2.into(), "b".into()]);
+    for user in 0..10 {
+        mutv.put(vec![user.into(), 1.into()]);
+    }
+    for user in 0..3 {
+        mutv.put(vec![user.into(), 2.into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+
+    // reading by a column other than the shard key asks every shard
+    assert_eq!(vq(&1.into()).unwrap().len(), 10);
+    assert_eq!(vq(&2.into()).unwrap().len(), 3);
+
+    // reading by the shard key only asks the shard that has the key
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 10.into()]]));
+    assert_eq!(cq(&2.into()), Ok(vec![vec![2.into(), 3.into()]]));
+    assert_eq!(jq(&1.into()), Ok(vec![vec![1.into(), "a".into(), 10.into()]]));
+    assert_eq!(jq(&2.into()), Ok(vec![vec![2.into(), "b".into(), 3.into()]]));
+}
+
+#[test]
+fn remote_writes() {
+    use distributary::{Base, Aggregation};
+
+    // both processes set up the same graph
+    let setup = |g: &mut distributary::Blender| {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], Base::new(vec![0]));
+        let c = mig.add_ingredient("c", &["b", "count"], Aggregation::COUNT.over(a, 0, &[1]));
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, cq)
+    };
+    let mut local = distributary::Blender::new();
+    let mut remote = distributary::Blender::new();
+    let (a, lq) = setup(&mut local);
+    let (_, rq) = setup(&mut remote);
+
+    let addr = remote.listen("127.0.0.1:0").unwrap();
+    let muta = local.get_remote_mutator(addr, a).unwrap();
+    muta.put(vec![1.into(), "x".into()]);
+    muta.put(vec![2.into(), "x".into()]);
+    muta.put(vec![3.into(), "y".into()]);
+    muta.delete(vec![2.into()]);
+    thread::sleep(time::Duration::from_millis(100));
+
+    // the writes only reach the process they were sent to
+    assert_eq!(rq(&"x".into()), Ok(vec![vec!["x".into(), 1.into()]]));
+    assert_eq!(rq(&"y".into()), Ok(vec![vec!["y".into(), 1.into()]]));
+    assert_eq!(lq(&"x".into()), Ok(vec![]));
+}
+
+#[test]
+fn rejecting_backpressure() {
+    use distributary::{Backpressure, Base, Aggregation};
+
+    let mut g = distributary::Blender::new();
+    g.set_channel_capacity(1);
+    let (a, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], Base::default());
+        let c = mig.add_ingredient("c", &["b", "count"], Aggregation::COUNT.over(a, 0, &[1]));
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, cq)
+    };
+
+    // writes the base domain has no room for are refused rather than queued up
+    let muta = g.get_mutator(a).with_backpressure(Backpressure::Reject);
+    let mut accepted: i64 = 0;
+    for i in 0..10_000 {
+        if muta.try_put(vec![i.into(), 1.into()]).is_ok() {
+            accepted += 1;
+        }
+    }
+    assert!(accepted > 0);
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), accepted.into()]]));
+
+    // writes through a blocking mutator are never refused
+    let muta = g.get_mutator(a);
+    assert_eq!(muta.try_put(vec![0.into(), 2.into()]), Ok(()));
+}
+
+#[test]
+fn batched_updates_between_domains() {
+    use distributary::{Base, Aggregation, Batching};
+
+    let mut g = distributary::Blender::new();
+    g.batch_updates(Batching {
+        max_records: 1_000,
+        max_delay: time::Duration::from_secs(60),
+    });
+    let (a, cq) = {
+        let mut mig = g.start_migration();
+        let a = mig.add_ingredient("a", &["a", "b"], Base::default());
+        let c = mig.add_ingredient("c", &["b", "count"], Aggregation::COUNT.over(a, 0, &[1]));
+        let d = mig.add_domain();
+        mig.assign_domain(c, d);
+        let cq = mig.maintain(c, 0);
+        mig.commit();
+        (a, cq)
+    };
+
+    // updates are not held back once the base domain runs out of work, even if the batch is
+    // neither full nor old
+    let muta = g.get_mutator(a);
+    for i in 0..10 {
+        muta.put(vec![i.into(), 1.into()]);
+    }
+    thread::sleep(time::Duration::from_millis(100));
+    assert_eq!(cq(&1.into()), Ok(vec![vec![1.into(), 10.into()]]));
+
+    // a full batch is sent on right away
+    for i in 0..2_500 {
+        muta.put(vec![i.into(), 2.into()]);
+    }
+    assert!(g.wait_until_quiescent(time::Duration::from_secs(5)));
+    assert_eq!(cq(&2.into()), Ok(vec![vec![2.into(), 2_500.into()]]));
 }
 
 #[test]